@@ -42,6 +42,19 @@ impl CapeNullifierSet {
     pub fn insert(&mut self, n: Nullifier, value: bool) {
         self.0.insert(n, value);
     }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The nullifiers in the set, and whether each one is spent.
+    pub fn iter(&self) -> impl Iterator<Item = (Nullifier, bool)> + '_ {
+        self.0.iter().map(|(n, spent)| (*n, *spent))
+    }
 }
 
 impl NullifierSet for CapeNullifierSet {
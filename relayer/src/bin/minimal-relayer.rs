@@ -15,7 +15,8 @@ use ethers::prelude::{
 };
 use relayer::{
     init_web_server, submit_empty_block_loop, NonceCountRule, WebState, DEFAULT_RELAYER_EXTRA_GAS,
-    DEFAULT_RELAYER_MAX_RETRIES, DEFAULT_RELAYER_PORT, DEFAULT_RELAYER_RETRY_INTERVAL_MS,
+    DEFAULT_RELAYER_MAX_RETRIES, DEFAULT_RELAYER_MIN_FEE, DEFAULT_RELAYER_PORT,
+    DEFAULT_RELAYER_RETRY_INTERVAL_MS,
 };
 use std::{num::NonZeroU64, sync::Arc, time::Duration};
 use structopt::StructOpt;
@@ -89,6 +90,10 @@ struct MinimalRelayerOptions {
     /// Amount of time to sleep (in ms) before retrying after a nonce error.
     #[structopt(long, env = "CAPE_RELAYER_RETRY_INTERVAL_MS", default_value = DEFAULT_RELAYER_RETRY_INTERVAL_MS)]
     retry_interval: u64,
+
+    /// Minimum fee, in native asset units, advertised to wallets at `/feerate`.
+    #[structopt(long, env = "CAPE_RELAYER_MIN_FEE", default_value = DEFAULT_RELAYER_MIN_FEE)]
+    min_fee: u64,
 }
 
 #[async_std::main]
@@ -124,6 +129,7 @@ async fn main() -> std::io::Result<()> {
         opt.extra_gas.into(),
         opt.max_retries,
         Duration::from_millis(opt.retry_interval),
+        opt.min_fee,
     );
     // Start serving CAPE transaction submissions.
     let periodic_block_submission = async_std::task::spawn(submit_empty_block_loop(
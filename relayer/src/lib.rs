@@ -21,7 +21,12 @@ use jf_cap::{keys::UserPubKey, structs::ReceiverMemo, Signature};
 use net::server::{add_error_body, request_body, response};
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
-use std::{str::FromStr, time::Duration};
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 use tide::{
     http::headers::HeaderValue,
     security::{CorsMiddleware, Origin},
@@ -38,6 +43,12 @@ pub const DEFAULT_RELAYER_EXTRA_GAS: &str = "10000000"; // 10M
 pub const DEFAULT_RELAYER_RETRY_INTERVAL_MS: &str = "500";
 pub const DEFAULT_RELAYER_MAX_RETRIES: &str = "2";
 
+/// By default, the relayer advertises that it accepts transactions paying any fee.
+pub const DEFAULT_RELAYER_MIN_FEE: &str = "0";
+
+/// The period over which `/congestion` counts accepted transactions.
+pub const CONGESTION_WINDOW: Duration = Duration::from_secs(60);
+
 #[derive(Clone, Debug, Snafu, Serialize, Deserialize)]
 pub enum Error {
     #[snafu(display("failed to deserialize request body: {}", msg))]
@@ -95,7 +106,12 @@ pub struct WebState {
     extra_gas: u64,
     max_retries: u64,
     retry_interval: Duration,
+    min_fee: u64,
     block_submission_mutex: Arc<Mutex<()>>,
+    /// Transactions received by `/submit` which have not yet been submitted to the contract.
+    pending: Arc<AtomicU64>,
+    /// Times at which transactions were submitted to the contract within [CONGESTION_WINDOW].
+    accepted: Arc<Mutex<VecDeque<Instant>>>,
 }
 
 impl WebState {
@@ -105,6 +121,7 @@ impl WebState {
         extra_gas: u64,
         max_retries: u64,
         retry_interval: Duration,
+        min_fee: u64,
     ) -> Self {
         Self {
             contract,
@@ -112,7 +129,10 @@ impl WebState {
             extra_gas,
             max_retries,
             retry_interval,
+            min_fee,
             block_submission_mutex: Arc::new(Mutex::new(())),
+            pending: Default::default(),
+            accepted: Default::default(),
         }
     }
 }
@@ -124,6 +144,24 @@ pub struct SubmitBody {
     pub signature: Signature,
 }
 
+/// The fee policy advertised by the relayer at `/feerate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeRate {
+    /// The smallest fee, in native asset units, the relayer currently accepts.
+    pub min_fee: u64,
+}
+
+/// The backlog advertised by the relayer at `/congestion`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Congestion {
+    /// Transactions received which have not yet been submitted to the contract.
+    pub pending: u64,
+    /// Transactions submitted to the contract in the last `window_secs` seconds.
+    pub accepted: u64,
+    /// The period over which `accepted` is counted, in seconds.
+    pub window_secs: u64,
+}
+
 /// Determines how transaction nonces should be calculated.
 #[derive(Clone, Debug, Copy, Serialize, Deserialize)]
 pub enum NonceCountRule {
@@ -169,6 +207,36 @@ async fn healthcheck(_req: tide::Request<WebState>) -> Result<tide::Response, ti
         .build())
 }
 
+async fn feerate(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    response(
+        &req,
+        FeeRate {
+            min_fee: req.state().min_fee,
+        },
+    )
+}
+
+async fn congestion(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let state = req.state();
+    let mut accepted = state.accepted.lock().await;
+    prune_accepted(&mut accepted);
+    response(
+        &req,
+        Congestion {
+            pending: state.pending.load(Ordering::SeqCst),
+            accepted: accepted.len() as u64,
+            window_secs: CONGESTION_WINDOW.as_secs(),
+        },
+    )
+}
+
+/// Forget transactions accepted before the start of the current [CONGESTION_WINDOW].
+fn prune_accepted(accepted: &mut VecDeque<Instant>) {
+    while matches!(accepted.front(), Some(time) if time.elapsed() > CONGESTION_WINDOW) {
+        accepted.pop_front();
+    }
+}
+
 async fn submit_endpoint(mut req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
     let SubmitBody {
         transaction,
@@ -184,6 +252,26 @@ async fn submit_endpoint(mut req: tide::Request<WebState>) -> Result<tide::Respo
         .map_err(server_error)?;
     response(&req, ret)
 }
+
+async fn submit_batch_endpoint(
+    mut req: tide::Request<WebState>,
+) -> Result<tide::Response, tide::Error> {
+    let batch: Vec<SubmitBody> = request_body(&mut req).await.map_err(|err| {
+        server_error(Error::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+    if batch.is_empty() {
+        return Err(server_error(Error::BadBlock {
+            msg: String::from("the batch is empty"),
+        }));
+    }
+    let ret = relay_batch(req.state(), batch)
+        .await
+        .map_err(server_error)?;
+    response(&req, ret)
+}
+
 /// This function implements the core logic of the relayer
 ///
 /// * `contract` -  CAPE contract instance to submit the block information to
@@ -199,14 +287,33 @@ async fn relay(
     memos: Vec<ReceiverMemo>,
     sig: Signature,
 ) -> Result<H256, Error> {
+    relay_batch(
+        web_state,
+        vec![SubmitBody {
+            transaction,
+            memos,
+            signature: sig,
+        }],
+    )
+    .await
+}
+
+/// Submit several transactions in a single block, so that either all of them or none of them are
+/// committed.
+async fn relay_batch(web_state: &WebState, batch: Vec<SubmitBody>) -> Result<H256, Error> {
     let miner = UserPubKey::default();
+    let count = batch.len() as u64;
+    let (transactions, memos): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|body| (body.transaction, (body.memos, body.signature)))
+        .unzip();
     let block = BlockWithMemos {
-        block: CapeBlock::from_cape_transactions(vec![transaction], miner.address()).map_err(
-            |err| Error::BadBlock {
+        block: CapeBlock::from_cape_transactions(transactions, miner.address()).map_err(|err| {
+            Error::BadBlock {
                 msg: err.to_string(),
-            },
-        )?,
-        memos: vec![(memos, sig)],
+            }
+        })?,
+        memos,
     };
     // These log statements show what's being submitted to Ethereum blockchain,
     // except for the memos.
@@ -216,7 +323,16 @@ async fn relay(
         "Submitting CAPE block: {:?}",
         cap_rust_sandbox::types::CapeBlock::from(block.block.clone())
     );
-    submit_block(web_state, block).await
+    web_state.pending.fetch_add(count, Ordering::SeqCst);
+    let res = submit_block(web_state, block).await;
+    web_state.pending.fetch_sub(count, Ordering::SeqCst);
+    if res.is_ok() {
+        let mut accepted = web_state.accepted.lock().await;
+        prune_accepted(&mut accepted);
+        let now = Instant::now();
+        accepted.extend((0..count).map(|_| now));
+    }
+    res
 }
 
 async fn submit_empty_block(web_state: &WebState) -> Result<H256, Error> {
@@ -331,10 +447,13 @@ pub fn init_web_server(
             .allow_credentials(true),
     );
     web_server.at("/healthcheck").get(healthcheck);
+    web_server.at("/feerate").get(feerate);
+    web_server.at("/congestion").get(congestion);
     web_server
         .with(add_error_body::<_, Error>)
         .at("/submit")
         .post(submit_endpoint);
+    web_server.at("/submitbatch").post(submit_batch_endpoint);
     let addr = format!("0.0.0.0:{}", port);
     async_std::task::spawn(web_server.listen(addr))
 }
@@ -364,6 +483,7 @@ pub mod testing {
                 DEFAULT_RELAYER_EXTRA_GAS.parse().unwrap(),
                 DEFAULT_RELAYER_MAX_RETRIES.parse().unwrap(),
                 Duration::from_millis(DEFAULT_RELAYER_RETRY_INTERVAL_MS.parse().unwrap()),
+                DEFAULT_RELAYER_MIN_FEE.parse().unwrap(),
             )
         }
     }
@@ -559,6 +679,7 @@ mod test {
             DEFAULT_RELAYER_EXTRA_GAS.parse().unwrap(),
             DEFAULT_RELAYER_MAX_RETRIES.parse().unwrap(),
             Duration::from_millis(DEFAULT_RELAYER_RETRY_INTERVAL_MS.parse().unwrap()),
+            DEFAULT_RELAYER_MIN_FEE.parse().unwrap(),
         );
 
         // Submit a transaction and verify that the 2 output commitments get added to the contract's
@@ -631,6 +752,20 @@ mod test {
             err => panic!("expected Submission error, got {:?}", err),
         };
 
+        // An empty batch is not a block.
+        match Error::from_client_error(
+            client
+                .post("/submitbatch")
+                .body_json(&Vec::<SubmitBody>::new())
+                .unwrap()
+                .send()
+                .await
+                .expect_err("expected submission of empty batch to fail"),
+        ) {
+            Error::BadBlock { .. } => {}
+            err => panic!("expected BadBlock error, got {:?}", err),
+        };
+
         // Deploy a new contract (without faucet setup) and submit the same
         // transaction to trigger a "Root not found" error.
         let contract = {
@@ -671,6 +806,7 @@ mod test {
             DEFAULT_RELAYER_EXTRA_GAS.parse().unwrap(),
             DEFAULT_RELAYER_MAX_RETRIES.parse().unwrap(),
             Duration::from_millis(DEFAULT_RELAYER_RETRY_INTERVAL_MS.parse().unwrap()),
+            DEFAULT_RELAYER_MIN_FEE.parse().unwrap(),
         );
         init_web_server(web_state, port);
         wait_for_server(port).await;
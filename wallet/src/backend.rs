@@ -6,7 +6,7 @@
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 //! An implementation of [seahorse::WalletBackend] for CAPE.
-use crate::{loader::CapeMetadata, CapeWalletBackend, CapeWalletError};
+use crate::{loader::CapeMetadata, CapeWalletBackend, CapeWalletError, EventLog};
 use address_book::InsertPubKey;
 use async_std::{
     sync::{Arc, Mutex, MutexGuard},
@@ -25,7 +25,7 @@ use ethers::{
     core::k256::ecdsa::SigningKey,
     prelude::{
         coins_bip39::English, Address, Http, LocalWallet as LocalEthWallet, MnemonicBuilder,
-        Provider, SignerMiddleware, Wallet as EthWallet,
+        Provider, SignerMiddleware, TxHash, Wallet as EthWallet, U256,
     },
     providers::Middleware,
     signers::Signer,
@@ -43,20 +43,22 @@ use jf_cap::{
 use key_set::ProverKeySet;
 use net::client::{parse_error_body, response_body};
 use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
-use reef::Ledger;
-use relayer::SubmitBody;
+use reef::{traits::Transaction as _, Ledger};
+use relayer::{Congestion, FeeRate, SubmitBody};
 use seahorse::{
     events::{EventIndex, EventSource, LedgerEvent},
     hd,
     loader::WalletLoader,
     persistence::AtomicWalletStorage,
-    txn_builder::{RecordDatabase, TransactionInfo, TransactionState},
+    txn_builder::{RecordDatabase, TransactionInfo, TransactionState, TransactionUID},
     WalletBackend, WalletState,
 };
 use serde::de::DeserializeOwned;
 use std::cmp::min;
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::pin::Pin;
+use std::sync::RwLock;
 use std::time::{Duration, Instant};
 use surf::{StatusCode, Url};
 
@@ -70,6 +72,60 @@ pub struct CapeBackendConfig {
     pub min_polling_delay: Duration,
 }
 
+/// A shared, adjustable interval at which a [CapeBackend] polls the EQS for new events.
+///
+/// By default, a [CapeBackend] backs off while the EQS has no new events for it. A backend given a
+/// [ScanInterval] with [CapeBackend::set_scan_interval] instead polls at a fixed rate, and its event
+/// streams pick up changes to the interval on their next poll, so polling can be tuned without
+/// reopening the wallet. An interval of `None` disables polling entirely.
+///
+/// Scanning can also be paused for a bounded time, after which it resumes on its own. A pause only
+/// holds back the backend's open-ended event streams, which the wallet scans the ledger with;
+/// bounded streams replaying past events are unaffected. Streams resume from where they left off.
+#[derive(Clone, Debug)]
+pub struct ScanInterval {
+    interval: Arc<RwLock<Option<Duration>>>,
+    paused_until: Arc<RwLock<Option<Instant>>>,
+}
+
+impl ScanInterval {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval: Arc::new(RwLock::new(interval)),
+            paused_until: Default::default(),
+        }
+    }
+
+    pub fn get(&self) -> Option<Duration> {
+        *self.interval.read().unwrap()
+    }
+
+    pub fn set(&self, interval: Option<Duration>) {
+        *self.interval.write().unwrap() = interval;
+    }
+
+    /// Pause scanning until [resume](Self::resume) is called or `timeout` elapses.
+    pub fn pause(&self, timeout: Duration) {
+        *self.paused_until.write().unwrap() = Some(Instant::now() + timeout);
+    }
+
+    pub fn resume(&self) {
+        *self.paused_until.write().unwrap() = None;
+    }
+
+    /// The time at which scanning resumes on its own, if it is paused.
+    pub fn paused_until(&self) -> Option<Instant> {
+        self.paused_until
+            .read()
+            .unwrap()
+            .filter(|until| *until > Instant::now())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_until().is_some()
+    }
+}
+
 struct EthRpc {
     url: Url,
     contract: CAPE<EthMiddleware>,
@@ -126,7 +182,12 @@ pub struct CapeBackend<'a> {
     storage: Arc<Mutex<AtomicWalletStorage<'a, CapeLedger, CapeMetadata>>>,
     key_stream: hd::KeyTree,
     min_polling_delay: Duration,
+    scan_interval: Option<ScanInterval>,
     eth: Option<EthRpc>,
+    /// Transactions from other wallets to submit in the same block as one of ours; see
+    /// [CapeWalletBackend::bundle].
+    bundles: HashMap<TransactionUID<CapeLedger>, SubmitBody>,
+    event_log: EventLog,
 }
 
 impl<'a> CapeBackend<'a> {
@@ -177,9 +238,20 @@ impl<'a> CapeBackend<'a> {
             storage: Arc::new(Mutex::new(storage)),
             key_stream,
             min_polling_delay: config.min_polling_delay,
+            scan_interval: None,
             eth,
+            bundles: Default::default(),
+            event_log: Default::default(),
         })
     }
+
+    /// Poll the EQS at the rate given by `interval`, instead of backing off while idle.
+    ///
+    /// This only affects event streams created after it is called, so it should be called before
+    /// the backend is used to create a wallet.
+    pub fn set_scan_interval(&mut self, interval: ScanInterval) {
+        self.scan_interval = Some(interval);
+    }
 }
 
 impl<'a> CapeBackend<'a> {
@@ -250,15 +322,21 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
         txn: CapeTransition,
         info: TransactionInfo<CapeLedger>,
     ) -> Result<(), CapeWalletError> {
+        let bundle = self.bundles.remove(&TransactionUID(txn.hash()));
         match &txn {
-            CapeTransition::Transaction(txn) => self
-                .relayer
-                .post("submit")
-                .body_json(&SubmitBody {
+            CapeTransition::Transaction(txn) => {
+                let submission = SubmitBody {
                     transaction: txn.clone(),
                     memos: info.memos.clone().into_iter().flatten().collect(),
                     signature: info.sig.clone(),
-                })
+                };
+                match bundle {
+                    Some(partner) => self
+                        .relayer
+                        .post("submitbatch")
+                        .body_json(&vec![submission, partner]),
+                    None => self.relayer.post("submit").body_json(&submission),
+                }
                 .map_err(|err| CapeWalletError::Failed {
                     msg: err.to_string(),
                 })?
@@ -270,7 +348,8 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
                 // Ignore the response, which contains a hash of the submitted Ethereum transaction.
                 // The EQS will track this transaction for us and send us an event if/when it gets
                 // mined.
-                .map(|_| ()),
+                .map(|_| ())
+            }
             CapeTransition::Wrap { .. } => Err(CapeWalletError::Failed {
                 msg: String::from(
                     "invalid transaction type: wraps must be submitted using `wrap()`, not \
@@ -345,6 +424,7 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
             backoff: Duration,
             min_backoff: Duration,
             max_backoff: Duration,
+            scan_interval: Option<ScanInterval>,
         }
         let state = StreamState {
             from: from.index(EventSource::QueryService),
@@ -353,6 +433,7 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
             backoff: self.min_polling_delay,
             min_backoff: self.min_polling_delay,
             max_backoff,
+            scan_interval: self.scan_interval.clone(),
         };
 
         // Create a stream from a function which polls the EQS. The polling function itself returns
@@ -361,6 +442,32 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
         // is always QueryService) as required by the WalletBackend API.
         Box::pin(
             stream::unfold(state, |mut state| async move {
+                // While scanning is paused, the open-ended stream the wallet scans with stops
+                // polling. Check again later in case it has been resumed.
+                let paused = state
+                    .scan_interval
+                    .as_ref()
+                    .map(ScanInterval::is_paused)
+                    .unwrap_or(false);
+                if paused && state.to.is_none() {
+                    sleep(state.min_backoff).await;
+                    return Some((stream::iter(vec![]), state));
+                }
+
+                // If we have a fixed scan interval, read it each time we poll, in case it has
+                // changed.
+                let interval = match &state.scan_interval {
+                    Some(scan_interval) => match scan_interval.get() {
+                        Some(interval) => Some(interval),
+                        None => {
+                            // Polling is disabled. Check again later in case it is re-enabled.
+                            sleep(state.min_backoff).await;
+                            return Some((stream::iter(vec![]), state));
+                        }
+                    },
+                    None => None,
+                };
+
                 let req = if let Some(to) = state.to {
                     if state.from >= to {
                         // Returning `None` terminates the stream.
@@ -391,7 +498,15 @@ impl<'a> WalletBackend<'a, CapeLedger> for CapeBackend<'a> {
                         return Some((stream::iter(vec![]), state));
                     }
                 };
-                if events.is_empty() {
+                if let Some(interval) = interval {
+                    // With a fixed scan interval, we don't back off while idle, and while we are
+                    // catching up we poll again right away rather than waiting a whole interval
+                    // for each batch of events.
+                    state.backoff = interval;
+                    if events.is_empty() {
+                        sleep(interval).await;
+                    }
+                } else if events.is_empty() {
                     // If there were no new events, increase the backoff before retrying.
                     sleep(state.backoff).await;
                     state.backoff = min(state.backoff * 2, state.max_backoff);
@@ -580,7 +695,7 @@ impl<'a> CapeWalletBackend<'a> for CapeBackend<'a> {
         erc20_code: Erc20Code,
         src_addr: EthereumAddr,
         ro: RecordOpening,
-    ) -> Result<(), CapeWalletError> {
+    ) -> Result<TxHash, CapeWalletError> {
         let eth = match &self.eth {
             Some(eth) => eth,
             None => {
@@ -608,20 +723,160 @@ impl<'a> CapeWalletBackend<'a> for CapeBackend<'a> {
             })?;
 
         // Wraps don't go through the relayer, they go directly to the contract.
-        eth.contract
+        let deposit = eth
+            .contract
             .deposit_erc_20(ro.clone().into(), erc20_code.clone().into())
-            .from(Address::from(src_addr.clone()))
+            .from(Address::from(src_addr.clone()));
+        let pending = deposit
             .send()
             .await
             .map_err(|err| CapeWalletError::Failed {
                 msg: format!("error building CAPE::depositErc20 transaction: {}", err),
-            })?
+            })?;
+        let hash = *pending;
+        pending
             .await
             .map_err(|err| CapeWalletError::Failed {
                 msg: format!("error submitting CAPE::depositErc20 transaction: {}", err),
             })
             // Ignore the status code
-            .map(|_| ())
+            .map(|_| hash)
+    }
+
+    async fn estimate_wrap_gas(
+        &self,
+        erc20_code: Erc20Code,
+        amount: U256,
+    ) -> Result<U256, CapeWalletError> {
+        let eth = match &self.eth {
+            Some(eth) => eth,
+            None => {
+                return Err(CapeWalletError::Failed {
+                    msg: "cannot estimate gas without JSON-RPC connection".into(),
+                })
+            }
+        };
+        let erc20 = ERC20::new(erc20_code, eth.client());
+
+        // A wrap consists of an ERC20 approval, followed by a deposit in which the contract pulls
+        // the approved tokens from our account. We can't simulate the deposit itself until the
+        // approval is mined, so we estimate the token movement with an equivalent direct transfer.
+        let approve = erc20
+            .approve(eth.contract.address(), amount)
+            .from(eth.wallet.address())
+            .estimate_gas()
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error estimating gas for ERC20::approve: {}", err),
+            })?;
+        let deposit = erc20
+            .transfer(eth.contract.address(), amount)
+            .from(eth.wallet.address())
+            .estimate_gas()
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error estimating gas for CAPE::depositErc20: {}", err),
+            })?;
+        Ok(approve + deposit)
+    }
+
+    async fn estimate_unwrap_gas(
+        &self,
+        erc20_code: Erc20Code,
+        dst: Option<EthereumAddr>,
+        amount: U256,
+    ) -> Result<U256, CapeWalletError> {
+        let eth = match &self.eth {
+            Some(eth) => eth,
+            None => {
+                return Err(CapeWalletError::Failed {
+                    msg: "cannot estimate gas without JSON-RPC connection".into(),
+                })
+            }
+        };
+
+        // Burns are submitted by the relayer; the Ethereum cost of an unwrap which we can simulate
+        // is the transfer of the unlocked tokens from the contract to the destination account. The
+        // cost of verifying the block is shared with the rest of the block, and is not included.
+        let dst = dst
+            .map(Address::from)
+            .unwrap_or_else(|| eth.wallet.address());
+        ERC20::new(erc20_code, eth.client())
+            .transfer(dst, amount)
+            .from(eth.contract.address())
+            .estimate_gas()
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error estimating gas for ERC20::transfer: {}", err),
+            })
+    }
+
+    async fn gas_price(&self) -> Result<U256, CapeWalletError> {
+        self.eth_client()?
+            .get_gas_price()
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error getting gas price: {}", err),
+            })
+    }
+
+    async fn min_fee(&self) -> Result<u64, CapeWalletError> {
+        let mut res =
+            self.relayer
+                .get("feerate")
+                .send()
+                .await
+                .map_err(|err| CapeWalletError::Failed {
+                    msg: format!("relayer error: {}", err),
+                })?;
+        response_body::<FeeRate>(&mut res)
+            .await
+            .map(|rate| rate.min_fee)
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error deserializing relayer response: {}", err),
+            })
+    }
+
+    async fn congestion(&self) -> Result<Congestion, CapeWalletError> {
+        let mut res =
+            self.relayer
+                .get("congestion")
+                .send()
+                .await
+                .map_err(|err| CapeWalletError::Failed {
+                    msg: format!("relayer error: {}", err),
+                })?;
+        response_body::<Congestion>(&mut res)
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error deserializing relayer response: {}", err),
+            })
+    }
+
+    async fn erc20_deposited(&self, erc20_code: Erc20Code) -> Result<U256, CapeWalletError> {
+        let eth = match &self.eth {
+            Some(eth) => eth,
+            None => {
+                return Err(CapeWalletError::Failed {
+                    msg: "cannot query the contract without JSON-RPC connection".into(),
+                })
+            }
+        };
+        ERC20::new(erc20_code, eth.client())
+            .balance_of(eth.contract.address())
+            .call()
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error calling ERC20::balanceOf: {}", err),
+            })
+    }
+
+    fn bundle(&mut self, uid: TransactionUID<CapeLedger>, partner: SubmitBody) {
+        self.bundles.insert(uid, partner);
+    }
+
+    fn unbundle(&mut self, uid: &TransactionUID<CapeLedger>) {
+        self.bundles.remove(uid);
     }
 
     fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError> {
@@ -671,6 +926,10 @@ impl<'a> CapeWalletBackend<'a> for CapeBackend<'a> {
         let address: Address = self.get_eqs("get_cape_contract_address").await?;
         Ok(address.into())
     }
+
+    fn event_log(&self) -> EventLog {
+        self.event_log.clone()
+    }
 }
 
 pub fn gen_proving_keys(srs: &UniversalParam) -> ProverKeySet<key_set::OrderByOutputs> {
@@ -28,7 +28,7 @@ struct Options {
 
 fn main() -> std::io::Result<()> {
     let options = Options::from_args();
-    let api = load_messages(&options.api_path.unwrap_or_else(default_api_path));
+    let (api, _) = load_messages(&options.api_path.unwrap_or_else(default_api_path), None)?;
     let help = compose_help(&api);
 
     fs::create_dir_all(&options.dir.join("public/css"))?;
@@ -196,8 +196,13 @@ async fn cli_wrap<'a, C: CLI<'a, Ledger = CapeLedger>>(
     C::Backend: CapeWalletBackend<'a> + Sync + 'a,
 {
     match wallet.wrap(from, asset_def.clone(), to.0, amount).await {
-        Ok(()) => {
-            cli_writeln!(io, "\nAsset wrapped: {}", asset_def.code);
+        Ok(hash) => {
+            cli_writeln!(
+                io,
+                "\nAsset wrapped: {} (Ethereum transaction {:#x})",
+                asset_def.code,
+                hash
+            );
         }
         Err(err) => {
             cli_writeln!(io, "{}\nAsset was not wrapped.", err);
@@ -8,64 +8,219 @@
 //! Configurable API loading.
 
 #![allow(clippy::format_push_string)]
-use std::fs::read_to_string;
+use crate::ui::ApiIntegrity;
+use jf_cap::{keys::UserPubKey, Signature};
+use sha3::{Digest, Sha3_256};
+use std::fs::{self, read_to_string};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 use strum_macros::{AsRefStr, EnumIter, EnumString};
 use tide::http::Method;
 
+/// The path of the detached signature of the message catalog at `path`.
+///
+/// The signature is stored next to the catalog, with `.sig` appended to its file name.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
+
 /// Loads the message catalog or panics
-pub fn load_messages(path: &Path) -> toml::Value {
+///
+/// If `signer` is given, the catalog must have a detached signature by `signer`, a JSON
+/// [Signature] of the bytes of the catalog stored at [signature_path]. If the signature is missing
+/// or does not match, this returns an error instead of the catalog, so that a server does not start
+/// with routes which may have been tampered with.
+pub fn load_messages(
+    path: &Path,
+    signer: Option<&UserPubKey>,
+) -> io::Result<(toml::Value, ApiIntegrity)> {
     let messages = read_to_string(&path).unwrap_or_else(|_| panic!("Unable to read {:?}.", &path));
+    let mut integrity = ApiIntegrity {
+        hash: hex::encode(Sha3_256::digest(messages.as_bytes())),
+        signer: signer.cloned(),
+        verified: false,
+    };
+    if let Some(signer) = signer {
+        let sig_path = signature_path(path);
+        let sig = fs::read(&sig_path)
+            .and_then(|bytes| {
+                serde_json::from_slice::<Signature>(&bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            })
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("unable to read signature {:?}: {}", sig_path, err),
+                )
+            })?;
+        if signer.verify_sig(messages.as_bytes(), &sig).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "signature {:?} does not match {:?} for {}",
+                    sig_path, path, signer
+                ),
+            ));
+        }
+        integrity.verified = true;
+    }
+
     let api: toml::Value =
         toml::from_str(&messages).unwrap_or_else(|_| panic!("Unable to parse {:?}.", &path));
     if let Err(err) = check_api(api.clone()) {
         panic!("{}", err);
     }
-    api
+    Ok((api, integrity))
 }
 
 /// Index entries for documentation fragments
 #[allow(non_camel_case_types)]
 #[derive(AsRefStr, Copy, Clone, Debug, EnumIter, EnumString, strum_macros::Display)]
 pub enum ApiRouteKey {
+    accounts,
+    alerts,
+    anomalies,
+    apiconfig,
+    asset,
+    assetmetadata,
+    assets,
+    audit,
+    auditable,
+    auth,
+    autoconsolidate,
+    backup,
+    backups,
+    balanceindex,
+    balanceproof,
+    batch,
     buildsponsor,
     buildwrap,
+    cache,
+    cansend,
+    changepreview,
+    checkrecipient,
+    checkwallet,
+    clock,
     closewallet,
+    coinselection,
+    comparepolicy,
+    confirmations,
+    congestion,
+    contacts,
+    conversions,
+    diff,
+    erc20,
+    errordetail,
+    export,
     exportasset,
+    exportmetadata,
+    exporttransaction,
+    failed,
+    features,
+    feerate,
+    fees,
+    feetopup,
+    freezable,
     freeze,
+    frontier,
+    fsck,
+    gaplimit,
+    gasestimate,
     getaddress,
     getaccount,
     getaccounts,
+    getasset,
     getbalance,
     getinfo,
     getmnemonic,
     getprivatekey,
     getrecords,
+    import,
     importasset,
     healthcheck,
     importkey,
+    importmetadata,
+    keys,
     lastusedkeystore,
+    linkability,
+    linketh,
     listkeystores,
+    lock,
+    memstats,
+    mergewallet,
+    minanonymity,
     mint,
     newasset,
+    newassets,
     newkey,
     newwallet,
+    notifyrecord,
+    nullifiers,
     openwallet,
+    params,
+    paymentrequest,
+    pendingmemos,
+    pendingspends,
+    portfolio,
+    previewimportkey,
+    proofconfig,
+    proofofreserves,
+    quarantine,
+    receipt,
+    reconcile,
+    reconnect,
+    record,
+    recordages,
+    recordmemo,
     recordopening,
     recoverkey,
+    recoveryestimate,
+    report,
     resetpassword,
+    routes,
+    scan,
+    scanconfig,
+    scangaps,
+    schedule,
+    schedules,
+    seedfingerprint,
     send,
+    simulate,
+    skippedevents,
+    snapshot,
+    spendlimit,
+    statedigest,
+    stealthaddress,
+    subaccount,
+    subaccounts,
+    submission,
     submitsponsor,
     submitwrap,
+    subscriptions,
+    swap,
+    sweep,
+    syncstatus,
+    testvectors,
+    totalcost,
     transaction,
     transactionhistory,
+    transactions,
     unfreeze,
+    unlock,
     unwrap,
     updateasset,
+    validatemnemonic,
+    verifiers,
+    verifyasset,
+    verifymnemonic,
     view,
+    viewingcapability,
     waitforsponsor,
+    webhook,
 }
 
 /// Check consistency of `api.toml`
@@ -78,16 +233,22 @@ pub fn check_api(api: toml::Value) -> Result<(), String> {
             .get(key.as_ref())
             .ok_or_else(|| format!("Missing API definition for [route.{}]", key))?;
         if let Some(method) = route.get("METHOD") {
-            // If specified, METHOD must be an HTTP method.
-            let method = method
-                .as_str()
-                .ok_or_else(|| format!("Malformed METHOD for [route.{}] (expected string)", key))?;
-            Method::from_str(method).map_err(|_| {
-                format!(
-                    "METHOD {} for [route.{}] is not an HTTP method",
-                    method, key
-                )
-            })?;
+            // If specified, METHOD must be an HTTP method, or an array of HTTP methods.
+            let methods = match method.as_array() {
+                Some(methods) => methods.clone(),
+                None => vec![method.clone()],
+            };
+            for method in methods {
+                let method = method.as_str().ok_or_else(|| {
+                    format!("Malformed METHOD for [route.{}] (expected string)", key)
+                })?;
+                Method::from_str(method).map_err(|_| {
+                    format!(
+                        "METHOD {} for [route.{}] is not an HTTP method",
+                        method, key
+                    )
+                })?;
+            }
         }
         let paths = route["PATH"]
             .as_array()
@@ -7,17 +7,18 @@
 
 //! Tools for creating, loading, and verifying CAPE wallets.
 
-use crate::CapeWalletError;
+use crate::{ui::UnopenableReason, CapeWalletError};
 use cap_rust_sandbox::{ledger::CapeLedger, model::Erc20Code};
-use eqs::errors::EQSNetError;
+use eqs::{errors::EQSNetError, routes::CapState};
 use ethers::prelude::Address;
 use net::client::{parse_error_body, response_body};
 use seahorse::{
-    hd::KeyTree,
+    hd::{KeyTree, Mnemonic},
     loader::{Loader, LoaderMetadata, WalletLoader},
+    persistence::AtomicWalletStorage,
     reader::Reader,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use surf::Url;
 
@@ -60,30 +61,104 @@ impl CapeLoader {
     }
 
     pub async fn latest_contract(eqs: Url) -> Result<Erc20Code, CapeWalletError> {
-        let eqs: surf::Client = surf::Config::default()
-            .set_base_url(eqs)
-            .try_into()
-            .expect("Failed to configure EQS client");
-        let eqs = eqs.with(parse_error_body::<EQSNetError>);
-        let mut res = eqs
-            .get("get_cape_contract_address")
-            .send()
-            .await
-            .map_err(|err| CapeWalletError::Failed {
-                msg: format!("EQS error: {}", err),
-            })?;
-        let address: Address =
-            response_body(&mut res)
-                .await
-                .map_err(|err| CapeWalletError::Failed {
-                    msg: format!("Error parsing EQS response: {}", err),
-                })?;
+        let address: Address = eqs_get(eqs, "get_cape_contract_address").await?;
         Ok(address.into())
     }
 
+    /// The size of the ledger, as `(num_events, num_records)`.
+    pub async fn ledger_size(eqs: Url) -> Result<(u64, u64), CapeWalletError> {
+        let state: CapState = eqs_get(eqs, "get_cap_state").await?;
+        Ok((
+            state.num_events,
+            state.ledger.record_merkle_commitment.num_leaves,
+        ))
+    }
+
+    /// The number of blocks committed to the ledger.
+    pub async fn block_height(eqs: Url) -> Result<u64, CapeWalletError> {
+        let state: CapState = eqs_get(eqs, "get_cap_state").await?;
+        Ok(state.ledger.state_number)
+    }
+
     pub fn path(&self) -> &Path {
         self.inner.path()
     }
+
+    /// Check whether the keystore at `dir` could be opened with `mnemonic`, without opening it.
+    ///
+    /// Only the keystore metadata is decrypted. The keystore is never created or modified, and no
+    /// wallet is loaded, so nothing is scanned. Returns the reason the keystore cannot be opened,
+    /// if any.
+    pub fn check(
+        mnemonic: String,
+        dir: PathBuf,
+        contract: Erc20Code,
+    ) -> Result<(), UnopenableReason> {
+        if !dir.exists() {
+            return Err(UnopenableReason::MissingStore);
+        }
+        if Mnemonic::from_phrase(&mnemonic).is_err() {
+            return Err(UnopenableReason::WrongMnemonic);
+        }
+        let mut loader = CheckLoader {
+            // Recovery checks the mnemonic against the metadata and then re-encrypts the metadata
+            // with a new password, but the check loader discards the re-encrypted metadata, so the
+            // password is never used.
+            inner: Self::recovery(mnemonic, String::new(), dir, contract),
+            stage: CheckStage::Unread,
+        };
+        match AtomicWalletStorage::<CapeLedger, CapeMetadata>::new(&mut loader, 1024) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(match loader.stage {
+                CheckStage::Unread => UnopenableReason::CorruptedStore,
+                CheckStage::Missing => UnopenableReason::MissingStore,
+                CheckStage::WrongContract => UnopenableReason::WrongContract,
+                CheckStage::Loaded => UnopenableReason::WrongMnemonic,
+            }),
+        }
+    }
+}
+
+/// How far [CapeLoader::check] got before the keystore failed to open.
+enum CheckStage {
+    /// The metadata could not be read.
+    Unread,
+    /// There is no metadata.
+    Missing,
+    /// The metadata belongs to a different CAPE contract.
+    WrongContract,
+    /// The metadata was read, and was handed to the inner loader to decrypt.
+    Loaded,
+}
+
+/// A loader which never creates a keystore and never changes the metadata of an existing one.
+struct CheckLoader {
+    inner: CapeLoader,
+    stage: CheckStage,
+}
+
+impl WalletLoader<CapeLedger> for CheckLoader {
+    type Meta = CapeMetadata;
+
+    fn location(&self) -> PathBuf {
+        WalletLoader::<CapeLedger>::location(&self.inner)
+    }
+
+    fn create(&mut self) -> Result<(CapeMetadata, KeyTree), CapeWalletError> {
+        self.stage = CheckStage::Missing;
+        Err(CapeWalletError::Failed {
+            msg: String::from("keystore does not exist"),
+        })
+    }
+
+    fn load(&mut self, meta: &mut CapeMetadata) -> Result<KeyTree, CapeWalletError> {
+        self.stage = if meta.contract != self.inner.contract {
+            CheckStage::WrongContract
+        } else {
+            CheckStage::Loaded
+        };
+        self.inner.load(&mut meta.clone())
+    }
 }
 
 impl WalletLoader<CapeLedger> for CapeLoader {
@@ -115,3 +190,23 @@ impl WalletLoader<CapeLedger> for CapeLoader {
         self.inner.load(&mut meta.load)
     }
 }
+
+async fn eqs_get<T: DeserializeOwned>(eqs: Url, route: &str) -> Result<T, CapeWalletError> {
+    let eqs: surf::Client = surf::Config::default()
+        .set_base_url(eqs)
+        .try_into()
+        .expect("Failed to configure EQS client");
+    let eqs = eqs.with(parse_error_body::<EQSNetError>);
+    let mut res = eqs
+        .get(route)
+        .send()
+        .await
+        .map_err(|err| CapeWalletError::Failed {
+            msg: format!("EQS error: {}", err),
+        })?;
+    response_body(&mut res)
+        .await
+        .map_err(|err| CapeWalletError::Failed {
+            msg: format!("Error parsing EQS response: {}", err),
+        })
+}
@@ -7,7 +7,8 @@
 
 //! Test-only implementation of the [reef] ledger abstraction for CAPE.
 
-use crate::wallet::{CapeWalletBackend, CapeWalletError};
+use crate::backend::ScanInterval;
+use crate::wallet::{CapeWalletBackend, CapeWalletError, EventLog};
 use async_std::{
     sync::{Mutex, MutexGuard},
     task::sleep,
@@ -17,6 +18,10 @@ use cap_rust_sandbox::{
     deploy::EthMiddleware, ledger::*, model::*, universal_param::UNIVERSAL_PARAM,
 };
 use commit::Committable;
+use ethers::{
+    prelude::{TxHash, U256},
+    utils::keccak256,
+};
 use futures::stream::{iter, pending, Stream, StreamExt};
 use itertools::izip;
 use jf_cap::{
@@ -47,11 +52,23 @@ use std::cmp::min;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::time::{Duration, Instant};
 use tempdir::TempDir;
 use testing::{MockEventSource, MockLedger, MockNetwork, SystemUnderTest};
 
+/// Gas reported by [MockCapeBackend] for any wrap.
+pub const MOCK_WRAP_GAS: u64 = 100_000;
+/// Gas reported by [MockCapeBackend] for any unwrap.
+pub const MOCK_UNWRAP_GAS: u64 = 50_000;
+/// Gas price, in wei, reported by [MockCapeBackend].
+pub const MOCK_GAS_PRICE: u64 = 2_000_000_000;
+/// Minimum fee reported by [MockCapeBackend].
+pub const MOCK_MIN_FEE: u64 = 1;
+
 pub fn test_asset_signing_key() -> KeyPair {
     KeyPair::generate(&mut ChaChaRng::from_seed([3; 32]))
 }
@@ -86,6 +103,16 @@ pub struct MockCapeNetwork {
     events: MockEventSource<CapeLedger>,
     txns: HashMap<(u64, u64), CommittedTransaction>,
     address_map: HashMap<UserAddress, UserPubKey>,
+    // If set, the next block submitted is rejected without being validated.
+    reject_next_block: bool,
+    // If set, the memos for the next block committed are withheld instead of being broadcast.
+    withhold_next_memos: bool,
+    withheld_memos: Vec<(ReceiverMemo, RecordCommitment, u64)>,
+    // If set, the next memos event delivered to any subscriber is delivered without its outputs, as
+    // if the subscriber had failed to process it. Shared with the subscribers' event streams.
+    skip_next_memos: Arc<AtomicBool>,
+    // The backlog reported for the relayer, or `None` if the relayer cannot be reached.
+    congestion: Option<relayer::Congestion>,
 }
 
 impl MockCapeNetwork {
@@ -103,6 +130,11 @@ impl MockCapeNetwork {
             events: MockEventSource::new(EventSource::QueryService),
             txns: Default::default(),
             address_map: Default::default(),
+            reject_next_block: false,
+            withhold_next_memos: false,
+            withheld_memos: Default::default(),
+            skip_next_memos: Default::default(),
+            congestion: Some(Default::default()),
         };
 
         // Broadcast receiver memos for the records which are included in the tree from the start,
@@ -133,6 +165,68 @@ impl MockCapeNetwork {
         ledger
     }
 
+    /// Reject the next block submitted, as the contract would if its Merkle root were stale.
+    pub fn reject_next_block(&mut self) {
+        self.reject_next_block = true;
+    }
+
+    /// Withhold the memos for the next block committed, as if they were never posted.
+    pub fn withhold_next_memos(&mut self) {
+        self.withhold_next_memos = true;
+    }
+
+    /// Make the next subscriber to receive a memos event skip it, as if processing it had failed.
+    ///
+    /// The memos are still posted, so the event is seen by subscribers which replay it later.
+    pub fn skip_next_memos(&mut self) {
+        self.skip_next_memos.store(true, Ordering::SeqCst);
+    }
+
+    /// Report `congestion` as the relayer's backlog, or fail to reach the relayer if it is `None`.
+    pub fn set_congestion(&mut self, congestion: Option<relayer::Congestion>) {
+        self.congestion = congestion;
+    }
+
+    /// Commit a record which is already in the ledger again, at a new uid, as a faulty or
+    /// malicious query service might.
+    ///
+    /// The record is committed in a block of its own, with the opening published so that its owner
+    /// finds it without memos. The replay happens only in the query service, so the contract does
+    /// not know about it, and later blocks built against the new Merkle root will be rejected.
+    pub fn replay_record(&mut self, ro: RecordOpening) {
+        let txn = CapeTransition::Faucet { ro: Box::new(ro) };
+        let mut uids = Vec::new();
+        for comm in txn.output_commitments() {
+            uids.push(self.records.num_leaves());
+            self.records.push(comm.to_field_element());
+        }
+        self.txns.insert(
+            (self.block_height, 0),
+            CommittedTransaction {
+                txn: txn.clone(),
+                uids,
+                memos: None,
+            },
+        );
+        self.generate_event(LedgerEvent::Commit {
+            block: CapeBlock::new(vec![txn]),
+            block_id: self.block_height,
+            state_comm: self.block_height + 1,
+        });
+        self.block_height += 1;
+    }
+
+    /// The memos withheld so far, with the commitment, uid and current Merkle path of each record.
+    pub fn withheld_memos(&self) -> Vec<(ReceiverMemo, RecordCommitment, u64, MerklePath)> {
+        self.withheld_memos
+            .iter()
+            .map(|(memo, comm, uid)| {
+                let path = self.records.get_leaf(*uid).expect_ok().unwrap().1.path;
+                (memo.clone(), *comm, *uid, path)
+            })
+            .collect()
+    }
+
     pub fn register_erc20(
         &mut self,
         asset_def: AssetDefinition,
@@ -241,7 +335,33 @@ impl MockCapeNetwork {
         from: EventIndex,
         to: Option<EventIndex>,
     ) -> Pin<Box<dyn Stream<Item = (LedgerEvent<CapeLedger>, EventSource)> + Send>> {
-        self.events.subscribe(from, to)
+        let skip_next_memos = self.skip_next_memos.clone();
+        Box::pin(
+            self.events
+                .subscribe(from, to)
+                .map(move |(event, source)| match event {
+                    LedgerEvent::Memos {
+                        outputs,
+                        transaction,
+                    } if !outputs.is_empty() => {
+                        // Replace a skipped event rather than dropping it, so the indices of later
+                        // events are unchanged.
+                        let outputs = if skip_next_memos.swap(false, Ordering::SeqCst) {
+                            vec![]
+                        } else {
+                            outputs
+                        };
+                        (
+                            LedgerEvent::Memos {
+                                outputs,
+                                transaction,
+                            },
+                            source,
+                        )
+                    }
+                    event => (event, source),
+                }),
+        )
     }
 
     pub fn get_public_key(&self, address: &UserAddress) -> Result<UserPubKey, CapeWalletError> {
@@ -254,6 +374,11 @@ impl MockCapeNetwork {
             .clone())
     }
 
+    /// The number of blocks committed so far.
+    pub fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
     pub fn nullifier_spent(&self, nullifier: Nullifier) -> bool {
         self.contract.nullifiers.contains(&nullifier)
     }
@@ -375,11 +500,21 @@ impl MockCapeNetwork {
 
                 // The memos for this block should have already been posted in the calldata, so we
                 // can now generate the corresponding Memos events.
+                let withhold = std::mem::take(&mut self.withhold_next_memos);
                 for (txn_id, txn) in block.into_iter().enumerate() {
                     if let Some((memos, sig)) = self.call_data.remove(&TransactionUID(txn.commit()))
                     {
-                        self.post_memos(self.block_height, txn_id as u64, memos, sig)
-                            .unwrap();
+                        if withhold {
+                            let uids = self.txns[&(self.block_height, txn_id as u64)].uids.clone();
+                            self.withheld_memos.extend(izip!(
+                                memos,
+                                txn.output_commitments(),
+                                uids
+                            ));
+                        } else {
+                            self.post_memos(self.block_height, txn_id as u64, memos, sig)
+                                .unwrap();
+                        }
                     }
                 }
 
@@ -404,6 +539,14 @@ impl<'a> MockNetwork<'a, CapeLedger> for MockCapeNetwork {
     }
 
     fn submit(&mut self, block: Block<CapeLedger>) -> Result<(), WalletError<CapeLedger>> {
+        if std::mem::take(&mut self.reject_next_block) {
+            self.generate_event(LedgerEvent::Reject {
+                block,
+                error: CapeValidationError::BadMerkleRoot {},
+            });
+            return Ok(());
+        }
+
         // Convert the submitted transactions to CapeOperations.
         let ops = block
             .txns()
@@ -545,6 +688,9 @@ pub struct MockCapeBackend<'a, Meta: Serialize + DeserializeOwned> {
     storage: Arc<Mutex<AtomicWalletStorage<'a, CapeLedger, Meta>>>,
     pub(crate) ledger: Arc<Mutex<MockCapeLedger<'a>>>,
     key_stream: KeyTree,
+    scan_interval: Option<ScanInterval>,
+    bundles: HashMap<TransactionUID<CapeLedger>, relayer::SubmitBody>,
+    event_log: EventLog,
 }
 
 impl<'a, Meta: Serialize + DeserializeOwned + Send + Clone + PartialEq> MockCapeBackend<'a, Meta> {
@@ -557,6 +703,9 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send + Clone + PartialEq> MockCape
             key_stream: storage.key_stream(),
             storage: Arc::new(Mutex::new(storage)),
             ledger,
+            scan_interval: None,
+            bundles: Default::default(),
+            event_log: Default::default(),
         })
     }
 
@@ -569,8 +718,24 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send + Clone + PartialEq> MockCape
             key_stream,
             storage,
             ledger,
+            scan_interval: None,
+            bundles: Default::default(),
+            event_log: Default::default(),
         })
     }
+
+    /// The mock ledger this backend submits transactions to.
+    pub fn ledger(&self) -> &Arc<Mutex<MockCapeLedger<'a>>> {
+        &self.ledger
+    }
+
+    /// Control scanning with `interval`, as [crate::backend::CapeBackend::set_scan_interval] does.
+    ///
+    /// The mock ledger pushes events to its subscribers rather than being polled, so only pauses
+    /// apply: while `interval` is paused, open-ended event streams hold back their events.
+    pub fn set_scan_interval(&mut self, interval: ScanInterval) {
+        self.scan_interval = Some(interval);
+    }
 }
 
 #[async_trait]
@@ -597,7 +762,22 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> WalletBackend<'a, CapeLedger
     }
 
     async fn subscribe(&self, from: EventIndex, to: Option<EventIndex>) -> Self::EventStream {
-        self.ledger.lock().await.network().subscribe(from, to)
+        let events = self.ledger.lock().await.network().subscribe(from, to);
+        match (&self.scan_interval, to) {
+            (Some(scan_interval), None) => {
+                let scan_interval = scan_interval.clone();
+                Box::pin(events.then(move |event| {
+                    let scan_interval = scan_interval.clone();
+                    async move {
+                        while scan_interval.is_paused() {
+                            sleep(Duration::from_millis(10)).await;
+                        }
+                        event
+                    }
+                }))
+            }
+            _ => events,
+        }
     }
 
     async fn get_public_key(
@@ -662,7 +842,22 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> WalletBackend<'a, CapeLedger
             info.memos.into_iter().flatten().collect(),
             info.sig,
         );
-        ledger.submit(txn)
+        match self.bundles.remove(&TransactionUID(txn.hash())) {
+            // Submit a bundle straight to the network, which validates the whole block at once, as
+            // the contract would.
+            Some(partner) => {
+                let partner_txn = CapeTransition::Transaction(partner.transaction);
+                ledger.network().store_call_data(
+                    TransactionUID(partner_txn.hash()),
+                    partner.memos,
+                    partner.signature,
+                );
+                ledger
+                    .network()
+                    .submit(CapeBlock::new(vec![txn, partner_txn]))
+            }
+            None => ledger.submit(txn),
+        }
     }
 }
 
@@ -717,13 +912,74 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeWalletBackend<'a>
         erc20_code: Erc20Code,
         src_addr: EthereumAddr,
         ro: RecordOpening,
-    ) -> Result<(), WalletError<CapeLedger>> {
+    ) -> Result<TxHash, WalletError<CapeLedger>> {
+        // There is no real Ethereum transaction, so make up a hash which is unique to this wrap.
+        let hash = TxHash::from(keccak256(bincode::serialize(&ro)?));
         self.ledger
             .lock()
             .await
             .network()
             .wrap_erc20(erc20_code, src_addr, ro)
-            .map_err(cape_to_wallet_err)
+            .map_err(cape_to_wallet_err)?;
+        Ok(hash)
+    }
+
+    async fn estimate_wrap_gas(
+        &self,
+        _erc20_code: Erc20Code,
+        _amount: U256,
+    ) -> Result<U256, CapeWalletError> {
+        Ok(MOCK_WRAP_GAS.into())
+    }
+
+    async fn estimate_unwrap_gas(
+        &self,
+        _erc20_code: Erc20Code,
+        _dst: Option<EthereumAddr>,
+        _amount: U256,
+    ) -> Result<U256, CapeWalletError> {
+        Ok(MOCK_UNWRAP_GAS.into())
+    }
+
+    async fn gas_price(&self) -> Result<U256, CapeWalletError> {
+        Ok(MOCK_GAS_PRICE.into())
+    }
+
+    async fn min_fee(&self) -> Result<u64, CapeWalletError> {
+        Ok(MOCK_MIN_FEE)
+    }
+
+    async fn congestion(&self) -> Result<relayer::Congestion, CapeWalletError> {
+        self.ledger
+            .lock()
+            .await
+            .network()
+            .congestion
+            .ok_or_else(|| CapeWalletError::Failed {
+                msg: String::from("relayer error: relayer unreachable"),
+            })
+    }
+
+    async fn erc20_deposited(&self, erc20_code: Erc20Code) -> Result<U256, CapeWalletError> {
+        Ok(self
+            .ledger
+            .lock()
+            .await
+            .network()
+            .contract
+            .erc20_deposited
+            .get(&erc20_code)
+            .copied()
+            .unwrap_or(0)
+            .into())
+    }
+
+    fn bundle(&mut self, uid: TransactionUID<CapeLedger>, partner: relayer::SubmitBody) {
+        self.bundles.insert(uid, partner);
+    }
+
+    fn unbundle(&mut self, uid: &TransactionUID<CapeLedger>) {
+        self.bundles.remove(uid);
     }
 
     fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError> {
@@ -754,6 +1010,10 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeWalletBackend<'a>
         // This just has to match `contract_address`, so that the contract appears up to date.
         Ok(Erc20Code::default())
     }
+
+    fn event_log(&self) -> EventLog {
+        self.event_log.clone()
+    }
 }
 
 fn cape_to_wallet_err(err: CapeValidationError) -> WalletError<CapeLedger> {
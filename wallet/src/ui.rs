@@ -7,32 +7,43 @@
 
 //! Type definitions for UI-focused API responses.
 
-use crate::wallet::{CapeWallet, CapeWalletBackend, CapeWalletExt};
+use crate::wallet::{
+    tagged_bincode_string, transaction_hash_string, CapeWallet, CapeWalletBackend, CapeWalletError,
+    CapeWalletExt, TracedRecord, TracedTransaction, ViewedRecord,
+};
 use cap_rust_sandbox::ledger::{CapeLedger, CapeTransactionKind};
-use cap_rust_sandbox::model::Erc20Code;
+use cap_rust_sandbox::model::{Erc20Code, CAPE_MERKLE_HEIGHT, CAPE_NUM_ROOTS};
+use cap_rust_sandbox::types::GenericInto;
+use cap_rust_sandbox::universal_param::{SUPPORTED_FREEZE_SIZES, SUPPORTED_TRANSFER_SIZES};
 use espresso_macros::ser_test;
-use ethers::prelude::{Address, U256};
+use ethers::prelude::{Address, TxHash, U256};
 use futures::stream::{iter, StreamExt};
 use jf_cap::{
     keys::{AuditorKeyPair, AuditorPubKey, FreezerKeyPair, FreezerPubKey, UserKeyPair, UserPubKey},
-    structs::{AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy as JfAssetPolicy},
+    structs::{
+        AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy as JfAssetPolicy, FreezeFlag,
+        ReceiverMemo, RecordCommitment, RecordOpening as JfRecordOpening,
+    },
+    MerkleCommitment, MerkleFrontier, MerklePath, Signature,
 };
 use net::UserAddress;
-use reef::cap;
+use reef::{cap, traits::Transaction as _};
 use seahorse::{
     accounts::{AccountInfo, KeyPair},
     asset_library::Icon,
-    events::EventIndex,
-    txn_builder::RecordInfo,
+    events::{EventIndex, EventSource},
+    txn_builder::{RecordInfo, TransactionReceipt},
     MintInfo,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
 use std::io::Cursor;
 use std::iter::empty;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use tagged_base64::TaggedBase64;
 
 /// UI-friendly asset definition.
@@ -243,6 +254,9 @@ pub struct AssetInfo {
     pub icon: Option<String>,
     /// The ERC-20 token address that this asset wraps, if this is a wrapped asset.
     pub wrapped_erc20: Option<String>,
+    /// Local off-chain display metadata, if any has been recorded for this asset.
+    #[serde(default)]
+    pub metadata: Option<AssetMetadata>,
 }
 
 impl AssetInfo {
@@ -261,9 +275,15 @@ impl AssetInfo {
             description: info.description,
             icon,
             wrapped_erc20: wrapped_erc20.map(|code| format!("{:#x}", Address::from(code))),
+            metadata: None,
         }
     }
 
+    pub fn with_metadata(mut self, metadata: Option<AssetMetadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
     pub async fn from_code<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
         wallet: &CapeWallet<'a, Backend>,
         code: AssetCode,
@@ -286,6 +306,116 @@ impl AssetInfo {
     }
 }
 
+/// Maximum length of a URL in [AssetMetadata].
+pub const MAX_ASSET_METADATA_URL_LEN: usize = 2048;
+
+/// Off-chain display metadata for an asset.
+///
+/// This is recorded locally by the wallet server. It is not part of the asset definition, and it is
+/// not shared with other wallets.
+#[ser_test(ark(false))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetMetadata {
+    /// URL of an icon for the asset.
+    pub icon_url: Option<String>,
+    /// URL of the project website.
+    pub website: Option<String>,
+    /// Number of decimal places to use when displaying amounts of the asset.
+    pub decimals: Option<u8>,
+}
+
+impl AssetMetadata {
+    /// Check that URLs are well-formed HTTP(S) URLs of reasonable length, and that `decimals` does
+    /// not exceed the number of digits in the largest record amount.
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, url) in [("icon_url", &self.icon_url), ("website", &self.website)] {
+            if let Some(url) = url {
+                if url.len() > MAX_ASSET_METADATA_URL_LEN {
+                    return Err(format!(
+                        "{} must be at most {} characters",
+                        field, MAX_ASSET_METADATA_URL_LEN
+                    ));
+                }
+                match surf::Url::parse(url) {
+                    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                    Ok(url) => {
+                        return Err(format!(
+                            "{} must be an http or https URL, got scheme {}",
+                            field,
+                            url.scheme()
+                        ))
+                    }
+                    Err(err) => return Err(format!("{} is not a valid URL: {}", field, err)),
+                }
+            }
+        }
+        if let Some(decimals) = self.decimals {
+            // Record amounts are u128s, which have at most 39 decimal digits.
+            if decimals > 38 {
+                return Err(format!("decimals must be at most 38, got {}", decimals));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An asset to define with `newassets`, with the parameters of `newasset`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NewAsset {
+    pub symbol: String,
+    pub description: String,
+    pub freezing_key: Option<FreezerPubKey>,
+    pub viewing_key: Option<AuditorPubKey>,
+    pub view_amount: bool,
+    pub view_address: bool,
+    pub viewing_threshold: Option<u128>,
+}
+
+impl NewAsset {
+    /// Build the policy of the asset, or describe why it is invalid.
+    ///
+    /// As with `newasset`, the blinding factor is revealed if a viewing key is given, and amounts,
+    /// addresses and a viewing threshold can only be revealed to a viewing key.
+    pub fn policy(&self) -> Result<JfAssetPolicy, String> {
+        let mut policy = JfAssetPolicy::default();
+        if let Some(freezing_key) = &self.freezing_key {
+            policy = policy.set_freezer_pub_key(freezing_key.clone());
+        }
+        match &self.viewing_key {
+            Some(viewing_key) => {
+                policy = policy
+                    .set_auditor_pub_key(viewing_key.clone())
+                    .reveal_blinding_factor()
+                    .map_err(|err| err.to_string())?;
+                if self.view_amount {
+                    policy = policy.reveal_amount().map_err(|err| err.to_string())?;
+                }
+                if self.view_address {
+                    policy = policy
+                        .reveal_user_address()
+                        .map_err(|err| err.to_string())?;
+                }
+                if let Some(threshold) = self.viewing_threshold {
+                    policy = policy.set_reveal_threshold(threshold.into());
+                }
+            }
+            None => {
+                for (field, set) in [
+                    ("view_amount", self.view_amount),
+                    ("view_address", self.view_address),
+                    ("viewing_threshold", self.viewing_threshold.is_some()),
+                ] {
+                    if set {
+                        return Err(format!("{} requires a viewing_key", field));
+                    }
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
 impl From<AssetInfo> for seahorse::AssetInfo {
     fn from(info: AssetInfo) -> Self {
         let icon = info.icon.map(|b64| {
@@ -318,6 +448,88 @@ impl Display for AssetInfo {
     }
 }
 
+/// The policy an asset is advertised to have, as checked by `comparepolicy`.
+///
+/// The fields are the policy fields of [AssetDefinition].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedPolicy {
+    pub freezing_key: Option<FreezerPubKey>,
+    pub viewing_key: Option<AuditorPubKey>,
+    pub address_viewable: bool,
+    pub amount_viewable: bool,
+    pub blind_viewable: bool,
+    pub viewing_threshold: String,
+}
+
+/// A policy field whose actual value is not the expected one.
+///
+/// Values are formatted as strings, and an unset key is `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDifference {
+    pub field: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// The result of checking an asset's policy against an [ExpectedPolicy].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyComparison {
+    pub asset: AssetCode,
+    pub matches: bool,
+    pub differences: Vec<PolicyDifference>,
+}
+
+impl PolicyComparison {
+    pub fn new(expected: &ExpectedPolicy, actual: &AssetDefinition) -> Self {
+        let fields = [
+            (
+                "freezing_key",
+                expected.freezing_key.as_ref().map(ToString::to_string),
+                actual.freezing_key.as_ref().map(ToString::to_string),
+            ),
+            (
+                "viewing_key",
+                expected.viewing_key.as_ref().map(ToString::to_string),
+                actual.viewing_key.as_ref().map(ToString::to_string),
+            ),
+            (
+                "address_viewable",
+                Some(expected.address_viewable.to_string()),
+                Some(actual.address_viewable.to_string()),
+            ),
+            (
+                "amount_viewable",
+                Some(expected.amount_viewable.to_string()),
+                Some(actual.amount_viewable.to_string()),
+            ),
+            (
+                "blind_viewable",
+                Some(expected.blind_viewable.to_string()),
+                Some(actual.blind_viewable.to_string()),
+            ),
+            (
+                "viewing_threshold",
+                Some(expected.viewing_threshold.clone()),
+                Some(actual.viewing_threshold.clone()),
+            ),
+        ];
+        let differences = fields
+            .into_iter()
+            .filter(|(_, expected, actual)| expected != actual)
+            .map(|(field, expected, actual)| PolicyDifference {
+                field: field.to_string(),
+                expected,
+                actual,
+            })
+            .collect::<Vec<_>>();
+        Self {
+            asset: actual.code,
+            matches: differences.is_empty(),
+            differences,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UpdateAsset {
     pub symbol: Option<String>,
@@ -441,6 +653,96 @@ impl From<RecordInfo> for Record {
     }
 }
 
+/// The number and total amount of records of an asset a wallet is expected to hold, as given to
+/// `reconcile`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedHoldings {
+    pub asset: AssetCode,
+    pub count: usize,
+    pub total: U256,
+}
+
+/// An asset whose records do not match the expected holdings, as reported by `reconcile`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HoldingsDiscrepancy {
+    pub asset: AssetCode,
+    pub expected_count: usize,
+    pub actual_count: usize,
+    pub expected_total: U256,
+    pub actual_total: U256,
+    /// `actual_count - expected_count`.
+    pub count_delta: i64,
+    /// `actual_total - expected_total`, as a signed decimal string.
+    pub total_delta: String,
+}
+
+impl HoldingsDiscrepancy {
+    /// The discrepancy between `expected` and `actual`, or `None` if they match.
+    pub fn new(
+        expected: &ExpectedHoldings,
+        actual_count: usize,
+        actual_total: U256,
+    ) -> Option<Self> {
+        if expected.count == actual_count && expected.total == actual_total {
+            return None;
+        }
+        let total_delta = if actual_total >= expected.total {
+            (actual_total - expected.total).to_string()
+        } else {
+            format!("-{}", expected.total - actual_total)
+        };
+        Some(Self {
+            asset: expected.asset,
+            expected_count: expected.count,
+            actual_count,
+            expected_total: expected.total,
+            actual_total,
+            count_delta: actual_count as i64 - expected.count as i64,
+            total_delta,
+        })
+    }
+}
+
+/// A wallet's balance of a wrapped asset compared with the CAPE contract's view, as reported by
+/// `reconcile/onchain/:asset`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnChainReconciliation {
+    pub asset: AssetCode,
+    /// The ERC-20 token the asset wraps.
+    pub erc20_code: Address,
+    /// The total amount of the wallet's unspent records of the asset.
+    pub wallet_balance: U256,
+    /// The amount of the ERC-20 token held by the contract, including deposits it has not yet
+    /// finalized.
+    pub onchain_total: U256,
+    /// `onchain_total - wallet_balance`, as a signed decimal string.
+    pub delta: String,
+    pub consistent: bool,
+}
+
+impl OnChainReconciliation {
+    pub fn new(
+        asset: AssetCode,
+        erc20_code: Address,
+        wallet_balance: U256,
+        onchain_total: U256,
+    ) -> Self {
+        let delta = if onchain_total >= wallet_balance {
+            (onchain_total - wallet_balance).to_string()
+        } else {
+            format!("-{}", wallet_balance - onchain_total)
+        };
+        Self {
+            asset,
+            erc20_code,
+            wallet_balance,
+            onchain_total,
+            delta,
+            consistent: onchain_total == wallet_balance,
+        }
+    }
+}
+
 pub trait Ui {
     type UiAdaptor: Display;
     fn ui(self) -> Self::UiAdaptor;
@@ -530,6 +832,102 @@ impl Account {
     }
 }
 
+/// Account-centric summary of an address owned by the wallet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub address: UserAddress,
+    pub pub_key: UserPubKey,
+    pub description: String,
+    pub balances: HashMap<AssetCode, U256>,
+    /// Assets held by this account which the wallet can view using one of its viewing keys.
+    pub viewable_assets: Vec<AssetCode>,
+    /// Assets held by this account which the wallet can freeze using one of its freezing keys.
+    pub freezable_assets: Vec<AssetCode>,
+}
+
+impl AccountSummary {
+    pub async fn new<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
+        wallet: &CapeWallet<'a, Backend>,
+        pub_key: UserPubKey,
+    ) -> Result<Self, CapeWalletError> {
+        let info = wallet.sending_account(&pub_key.address()).await?;
+        let viewing_keys = wallet.auditor_pub_keys().await;
+        let freezing_keys = wallet.freezer_pub_keys().await;
+
+        let mut viewable_assets = vec![];
+        let mut freezable_assets = vec![];
+        for asset in &info.assets {
+            let policy = asset.definition.policy_ref();
+            if policy.is_auditor_pub_key_set() && viewing_keys.contains(policy.auditor_pub_key()) {
+                viewable_assets.push(asset.definition.code);
+            }
+            if policy.is_freezer_pub_key_set() && freezing_keys.contains(policy.freezer_pub_key()) {
+                freezable_assets.push(asset.definition.code);
+            }
+        }
+
+        Ok(Self {
+            address: pub_key.address().into(),
+            pub_key,
+            description: info.description,
+            balances: info.balances,
+            viewable_assets,
+            freezable_assets,
+        })
+    }
+}
+
+/// The keys derived at one index from a mnemonic, as reported by `testvectors`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyTestVector {
+    pub index: u64,
+    pub sending_key: UserPubKey,
+    pub address: UserAddress,
+    pub viewing_key: AuditorPubKey,
+    pub freezing_key: FreezerPubKey,
+}
+
+/// A sub-account derived from the wallet's key tree, as reported by `subaccounts`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubAccount {
+    pub index: u64,
+    pub address: UserAddress,
+    pub pub_key: UserPubKey,
+    /// Whether the wallet has generated this key, and so is tracking its balances.
+    pub tracked: bool,
+    /// The balance of each asset held by this sub-account, if it is tracked.
+    pub balances: HashMap<AssetCode, U256>,
+}
+
+impl SubAccount {
+    pub async fn new<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
+        wallet: &CapeWallet<'a, Backend>,
+        index: u64,
+    ) -> Result<Self, CapeWalletError> {
+        let pub_key = wallet.subaccount_key(index).await.pub_key();
+        let tracked = wallet.pub_keys().await.contains(&pub_key);
+        let balances = if tracked {
+            wallet.sending_account(&pub_key.address()).await?.balances
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            index,
+            address: pub_key.address().into(),
+            pub_key,
+            tracked,
+            balances,
+        })
+    }
+}
+
+/// A one-time receiving address, as returned by `stealthaddress`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StealthAddress {
+    pub address: UserAddress,
+    pub pub_key: UserPubKey,
+}
+
 #[ser_test(ark(false))]
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionHistoryEntry {
@@ -578,6 +976,21 @@ pub struct TransactionHistoryEntry {
     pub status: String,
 }
 
+/// The name of a kind of transaction, as reported in transaction history.
+pub fn transaction_kind_string(kind: &CapeTransactionKind) -> String {
+    match kind {
+        CapeTransactionKind::CAP(cap::TransactionKind::Send) => "send".to_string(),
+        CapeTransactionKind::CAP(cap::TransactionKind::Receive) => "receive".to_string(),
+        CapeTransactionKind::CAP(cap::TransactionKind::Mint) => "mint".to_string(),
+        CapeTransactionKind::CAP(cap::TransactionKind::Freeze) => "freeze".to_string(),
+        CapeTransactionKind::CAP(cap::TransactionKind::Unfreeze) => "unfreeze".to_string(),
+        CapeTransactionKind::CAP(cap::TransactionKind::Unknown) => "unknown".to_string(),
+        CapeTransactionKind::Burn => "unwrap".to_string(),
+        CapeTransactionKind::Wrap => "wrap".to_string(),
+        CapeTransactionKind::Faucet => "faucet".to_string(),
+    }
+}
+
 impl TransactionHistoryEntry {
     pub async fn from_wallet<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
         wallet: &CapeWallet<'a, Backend>,
@@ -586,26 +999,8 @@ impl TransactionHistoryEntry {
         Self {
             time: entry.time.to_string(),
             asset: entry.asset,
-            kind: match entry.kind {
-                CapeTransactionKind::CAP(cap::TransactionKind::Send) => "send".to_string(),
-                CapeTransactionKind::CAP(cap::TransactionKind::Receive) => "receive".to_string(),
-                CapeTransactionKind::CAP(cap::TransactionKind::Mint) => "mint".to_string(),
-                CapeTransactionKind::CAP(cap::TransactionKind::Freeze) => "freeze".to_string(),
-                CapeTransactionKind::CAP(cap::TransactionKind::Unfreeze) => "unfreeze".to_string(),
-                CapeTransactionKind::CAP(cap::TransactionKind::Unknown) => "unknown".to_string(),
-                CapeTransactionKind::Burn => "unwrap".to_string(),
-                CapeTransactionKind::Wrap => "wrap".to_string(),
-                CapeTransactionKind::Faucet => "faucet".to_string(),
-            },
-            hash: {
-                entry.hash.and_then(|hash| {
-                    bincode::serialize(&hash).ok().and_then(|bytes| {
-                        TaggedBase64::new("HASH", &bytes)
-                            .ok()
-                            .map(|tb| tb.to_string())
-                    })
-                })
-            },
+            kind: transaction_kind_string(&entry.kind),
+            hash: entry.hash.and_then(|hash| transaction_hash_string(&hash)),
             senders: entry.senders.into_iter().map(UserAddress::from).collect(),
             receivers: entry
                 .receivers
@@ -625,6 +1020,24 @@ impl TransactionHistoryEntry {
     }
 }
 
+/// The size of the open wallet's transaction history, as reported by `transactions/count`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionCount {
+    /// The number of transactions reported by `transactionhistory`.
+    pub transactions: usize,
+    /// The number of transactions which have been pruned.
+    pub pruned: usize,
+}
+
+/// The transactions removed by `transactions/prune/before/:block`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrunedHistory {
+    /// Hashes of the transactions pruned by this request, in history order.
+    pub pruned: Vec<String>,
+    /// The number of transactions still reported by `transactionhistory`.
+    pub remaining: usize,
+}
+
 #[ser_test(ark(false))]
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyStoreLocation {
@@ -659,6 +1072,2604 @@ impl From<&Path> for KeyStoreLocation {
     }
 }
 
+/// The balance of one account in a [ReservesAttestation], signed by that account's sending key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccountAttestation {
+    pub pub_key: UserPubKey,
+    pub balance: U256,
+    pub signature: Signature,
+}
+
+/// A signed statement of the total balance of an asset held by a wallet.
+///
+/// Each account owned by the wallet signs its own balance, so a third party can check the claimed
+/// total against the wallet's public keys without learning any secret keys or record openings.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReservesAttestation {
+    pub asset: AssetCode,
+    /// The sum of the balances of all accounts in `accounts`.
+    pub total: U256,
+    /// The time (as an event index) at which the balances were observed.
+    pub block_height: usize,
+    pub accounts: Vec<AccountAttestation>,
+}
+
+impl ReservesAttestation {
+    /// The message signed by an account attesting to `balance` of `asset` at `block_height`.
+    pub fn message(
+        asset: &AssetCode,
+        block_height: usize,
+        pub_key: &UserPubKey,
+        balance: &U256,
+    ) -> Vec<u8> {
+        bincode::serialize(&(asset, block_height, pub_key, balance))
+            .expect("failed to serialize attestation message")
+    }
+
+    /// Check each account signature, and that `total` is the sum of the attested balances.
+    pub fn verify(&self) -> bool {
+        let mut total = U256::zero();
+        for account in &self.accounts {
+            let message = Self::message(
+                &self.asset,
+                self.block_height,
+                &account.pub_key,
+                &account.balance,
+            );
+            if account
+                .pub_key
+                .verify_sig(&message, &account.signature)
+                .is_err()
+            {
+                return false;
+            }
+            total += account.balance;
+        }
+        total == self.total
+    }
+}
+
+/// The result of checking `api.toml` against its signature at startup, as reported by
+/// `apiconfig/integrity`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApiIntegrity {
+    /// Hex-encoded SHA3-256 hash of the loaded `api.toml`.
+    pub hash: String,
+    /// The key `api.toml` must be signed by, if the server was configured with one.
+    pub signer: Option<UserPubKey>,
+    /// Whether the signature of `api.toml` was verified.
+    ///
+    /// The server refuses to start if verification fails, so this is `true` whenever `signer` is
+    /// set.
+    pub verified: bool,
+}
+
+/// A transfer sent through this server which was rejected by the network, as reported by
+/// `failed`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FailedTransfer {
+    /// String representation of the TaggedBase64 encoded hash of the rejected transaction.
+    pub hash: String,
+    /// The sending address given in the original request, if any.
+    pub sender: Option<UserAddress>,
+    pub asset: AssetCode,
+    pub recipient: UserAddress,
+    pub amount: String,
+    pub fee: String,
+    /// Why the network rejected the transaction.
+    pub reason: String,
+}
+
+/// A record held out of the wallet's balance by its quarantine policy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedRecord {
+    pub uid: u64,
+    pub address: UserAddress,
+    pub asset: AssetCode,
+    pub amount: U256,
+    /// Why the record is quarantined: `dust` or `flagged`.
+    pub reason: String,
+}
+
+/// A wallet's quarantine policy and the records it holds, as reported by `quarantine`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantineReport {
+    /// Dust thresholds, by asset.
+    pub thresholds: HashMap<AssetCode, U256>,
+    pub flagged: Vec<AssetCode>,
+    /// Quarantined records which have been neither released nor rejected, by uid.
+    pub records: Vec<QuarantinedRecord>,
+}
+
+/// How long one phase of an operation took.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub duration_ns: u64,
+}
+
+/// A timing breakdown of an operation, in the order its phases ran.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationTrace {
+    pub phases: Vec<PhaseTiming>,
+}
+
+/// The result of an operation requested with `?trace=true`, with its timing breakdown.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traced<T> {
+    pub result: T,
+    pub trace: OperationTrace,
+}
+
+/// The relayer's fee policy, as reported by `feerate`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeRate {
+    /// The smallest fee, in native asset units, the relayer accepts.
+    pub min_fee: u64,
+    /// How long ago `min_fee` was fetched from the relayer, in milliseconds.
+    pub age_ms: u64,
+    /// Whether the relayer could not be reached, so `min_fee` is the last value it reported.
+    pub stale: bool,
+}
+
+/// The relayer's backlog of transactions, as reported by `congestion`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Congestion {
+    /// Transactions the relayer has received but not yet submitted to the contract.
+    pub pending: u64,
+    /// Transactions the relayer submitted to the contract in the last `window_secs` seconds.
+    pub accepted: u64,
+    /// The period over which `accepted` is counted, in seconds.
+    pub window_secs: u64,
+    /// How long ago the backlog was fetched from the relayer, in milliseconds.
+    pub age_ms: u64,
+    /// Whether the relayer could not be reached, so this is the last backlog it reported.
+    pub stale: bool,
+}
+
+/// How recently an asset was used, as reported by `assets/activity`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AssetActivity {
+    pub asset: AssetCode,
+    /// The time of the most recent transaction in the wallet's history involving the asset, in
+    /// seconds since the Unix epoch, or `None` if there is no such transaction.
+    pub last_activity: Option<i64>,
+    /// Whether the wallet owns any unfrozen records of the asset.
+    pub has_balance: bool,
+}
+
+/// The issuer of the native asset.
+pub const ISSUER_NATIVE: &str = "native";
+/// The issuer of domestic assets defined by the open wallet.
+pub const ISSUER_WALLET: &str = "wallet";
+/// The issuer of assets whose issuer cannot be determined.
+pub const ISSUER_UNKNOWN: &str = "unknown";
+
+/// The assets issued by one party, as reported by `assets/byissuer`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IssuerAssets {
+    /// The hex-encoded Ethereum address which sponsored the assets, if they are wrapped assets, or
+    /// one of [ISSUER_NATIVE], [ISSUER_WALLET] or [ISSUER_UNKNOWN].
+    pub issuer: String,
+    pub assets: Vec<AssetCode>,
+}
+
+/// The transactions assigned to one category, as reported by `report/bycategory`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CategoryReport {
+    pub category: String,
+    /// How many transactions in the category fall in the reported range.
+    pub transactions: usize,
+    /// The total amount those transactions sent to their receivers, by asset.
+    pub totals: HashMap<AssetCode, U256>,
+}
+
+/// The fees the open wallet has paid, as reported by `fees/total`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeTotal {
+    /// How many committed transactions built by the wallet fall in the reported range.
+    pub transactions: usize,
+    /// The total fees those transactions paid, by fee asset.
+    pub totals: HashMap<AssetCode, U256>,
+}
+
+/// The records importing a viewing key would reveal, as reported by `previewimportkey`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ViewingKeyPreview {
+    /// How many records the key would reveal.
+    pub records: usize,
+    /// The total amount of the revealed records, by asset.
+    ///
+    /// This only includes records whose asset policy reveals amounts to the key.
+    pub totals: HashMap<AssetCode, U256>,
+    /// How many of the revealed records have amounts hidden from the key.
+    pub hidden_amounts: usize,
+}
+
+impl ViewingKeyPreview {
+    pub fn new(records: impl IntoIterator<Item = ViewedRecord>) -> Self {
+        let mut preview = Self {
+            records: 0,
+            totals: HashMap::new(),
+            hidden_amounts: 0,
+        };
+        for record in records {
+            preview.records += 1;
+            match record.amount {
+                Some(amount) => {
+                    *preview.totals.entry(record.asset).or_default() += U256::from(amount);
+                }
+                None => preview.hidden_amounts += 1,
+            }
+        }
+        preview
+    }
+}
+
+/// The records a candidate viewing key would reveal, as reported by `keys/coverage`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyCoverage {
+    pub key: AuditorPubKey,
+    /// How many records of assets known to the wallet the key would make visible.
+    pub records: usize,
+    /// Whether the wallet already holds the key, in which case its records are already visible and
+    /// `records` is 0.
+    pub imported: bool,
+}
+
+/// A record spent or created by a transaction in an [AuditGraph], as revealed to the viewing key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEdge {
+    /// The party which owned the record, or `None` if the asset's policy hides addresses.
+    pub party: Option<UserAddress>,
+    /// The amount of the record, or `None` if the asset's policy hides amounts.
+    pub amount: Option<U256>,
+}
+
+impl From<TracedRecord> for AuditEdge {
+    fn from(record: TracedRecord) -> Self {
+        Self {
+            party: record.owner.map(UserAddress::from),
+            amount: record.amount.map(U256::from),
+        }
+    }
+}
+
+/// A transaction in an [AuditGraph].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditedTransaction {
+    /// Hash of the transaction, formatted as in transaction history.
+    pub hash: Option<String>,
+    /// Kind of the transaction, as in transaction history.
+    pub kind: String,
+    pub block_id: u64,
+    pub txn_id: u64,
+    /// Edges from the owners of the records of the asset the transaction spent.
+    pub inputs: Vec<AuditEdge>,
+    /// Edges to the owners of the records of the asset the transaction created.
+    pub outputs: Vec<AuditEdge>,
+}
+
+impl From<TracedTransaction> for AuditedTransaction {
+    fn from(traced: TracedTransaction) -> Self {
+        Self {
+            hash: transaction_hash_string(&traced.transaction.hash()),
+            kind: transaction_kind_string(&traced.transaction.kind()),
+            block_id: traced.block_id,
+            txn_id: traced.txn_id,
+            inputs: traced.inputs.into_iter().map(AuditEdge::from).collect(),
+            outputs: traced.outputs.into_iter().map(AuditEdge::from).collect(),
+        }
+    }
+}
+
+/// The transaction graph of an asset traced with a viewing key, as reported by `audit/graph/:asset`.
+///
+/// The nodes of the graph are the parties and the transactions. Each input of a transaction is an
+/// edge from the party which owned the spent record, and each output an edge to the party which
+/// received the new record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditGraph {
+    pub asset: AssetCode,
+    /// Whether the asset's policy reveals the parties of its records to the viewing key.
+    pub addresses_revealed: bool,
+    /// Whether the asset's policy reveals the amounts of its records to the viewing key.
+    pub amounts_revealed: bool,
+    /// The revealed parties, in order of first appearance.
+    pub parties: Vec<UserAddress>,
+    /// The traced transactions, in the order they were committed.
+    pub transactions: Vec<AuditedTransaction>,
+}
+
+impl AuditGraph {
+    pub fn new(asset: &JfAssetDefinition, transactions: Vec<AuditedTransaction>) -> Self {
+        let policy = asset.policy_ref();
+        Self {
+            asset: asset.code,
+            addresses_revealed: policy.is_user_address_revealed(),
+            amounts_revealed: policy.is_amount_revealed(),
+            parties: audit_parties(&transactions),
+            transactions,
+        }
+    }
+
+    /// Hide the parts of the graph which `scope` does not reveal.
+    pub fn restrict(self, scope: &ViewingScope) -> Self {
+        let transactions = self
+            .transactions
+            .into_iter()
+            .filter(|txn| scope.includes_block(txn.block_id))
+            .map(|mut txn| {
+                for edge in txn.inputs.iter_mut().chain(&mut txn.outputs) {
+                    if !scope.addresses {
+                        edge.party = None;
+                    }
+                    if !scope.amounts {
+                        edge.amount = None;
+                    }
+                }
+                txn
+            })
+            .collect::<Vec<_>>();
+        Self {
+            asset: self.asset,
+            addresses_revealed: self.addresses_revealed && scope.addresses,
+            amounts_revealed: self.amounts_revealed && scope.amounts,
+            parties: audit_parties(&transactions),
+            transactions,
+        }
+    }
+}
+
+/// The parties revealed by `transactions`, in order of first appearance.
+fn audit_parties(transactions: &[AuditedTransaction]) -> Vec<UserAddress> {
+    let mut parties = vec![];
+    let mut seen = HashSet::new();
+    for edge in transactions
+        .iter()
+        .flat_map(|txn| txn.inputs.iter().chain(&txn.outputs))
+    {
+        if let Some(party) = &edge.party {
+            if seen.insert(party.clone()) {
+                parties.push(party.clone());
+            }
+        }
+    }
+    parties
+}
+
+/// What a viewing capability reveals of an asset's [AuditGraph].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewingScope {
+    pub asset: AssetCode,
+    /// Whether the amounts of records are revealed.
+    #[serde(default)]
+    pub amounts: bool,
+    /// Whether the owners of records are revealed.
+    #[serde(default)]
+    pub addresses: bool,
+    /// The first block whose transactions are revealed, if not the start of the ledger.
+    #[serde(default)]
+    pub from_block: Option<u64>,
+    /// The last block whose transactions are revealed, if not the end of the ledger.
+    #[serde(default)]
+    pub to_block: Option<u64>,
+}
+
+impl ViewingScope {
+    pub fn includes_block(&self, block_id: u64) -> bool {
+        self.from_block.map_or(true, |from| block_id >= from)
+            && self.to_block.map_or(true, |to| block_id <= to)
+    }
+}
+
+/// The body of a `POST viewingcapability` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewingCapabilityRequest {
+    #[serde(flatten)]
+    pub scope: ViewingScope,
+    /// How long the capability lasts, if it should expire.
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// A scoped grant to view an asset, as reported by `viewingcapability`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewingCapability {
+    pub id: String,
+    /// The token to present to `audit/graph/:asset/capability/:token`.
+    pub token: String,
+    pub scope: ViewingScope,
+    /// When the capability was created, in milliseconds since the Unix epoch.
+    pub created: u64,
+    /// When the capability expires, in milliseconds since the Unix epoch, if it does.
+    pub expires: Option<u64>,
+    pub revoked: bool,
+}
+
+/// An exact non-negative decimal number, such as an exchange rate.
+///
+/// The value is `digits / 10^scale`. Arithmetic is done on integers, so values are never rounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Decimal {
+    digits: U256,
+    scale: u32,
+}
+
+impl Decimal {
+    /// The most decimal places a [Decimal] parsed from a string may have.
+    pub const MAX_SCALE: u32 = 38;
+
+    /// The amount of `units` of an asset with `decimals` decimal places in display units.
+    pub fn from_units(units: U256, decimals: u32) -> Self {
+        Self {
+            digits: units,
+            scale: decimals,
+        }
+    }
+
+    /// Multiply by an integer, or return `None` on overflow.
+    pub fn checked_mul(self, n: U256) -> Option<Self> {
+        Some(Self {
+            digits: self.digits.checked_mul(n)?,
+            scale: self.scale,
+        })
+    }
+
+    /// Add exactly, or return `None` on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = std::cmp::max(self.scale, other.scale);
+        let rescale = |n: Self| {
+            n.digits
+                .checked_mul(U256::exp10((scale - n.scale) as usize))
+        };
+        Some(Self {
+            digits: rescale(self)?.checked_add(rescale(other)?)?,
+            scale,
+        })
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(format!("{} is not a non-negative decimal number", s));
+        }
+        if frac.len() > Self::MAX_SCALE as usize {
+            return Err(format!(
+                "{} has more than {} decimal places",
+                s,
+                Self::MAX_SCALE
+            ));
+        }
+        let digits = U256::from_dec_str(&format!("{}{}", int, frac))
+            .map_err(|_| format!("{} is too large", s))?;
+        Ok(Self {
+            digits,
+            scale: frac.len() as u32,
+        })
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", self.digits.to_string(), width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            write!(f, "{}", int)
+        } else {
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+/// The wallet's balances valued in a reference unit, as reported by `portfolio/value`.
+///
+/// Values are exact decimal strings.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PortfolioValue {
+    /// The total value of the assets which have a rate.
+    pub total: String,
+    /// The value of the balance of each asset which has a rate.
+    pub values: HashMap<AssetCode, String>,
+    /// Assets with a nonzero balance which have no rate, and so are not included in `total`.
+    pub unvalued: Vec<AssetCode>,
+}
+
+impl PortfolioValue {
+    /// Value `balances` at `rates`, which give the value of one unit of each asset.
+    ///
+    /// Fails if a rate is not a valid [Decimal] or the total overflows.
+    pub fn new(
+        balances: HashMap<AssetCode, U256>,
+        rates: &HashMap<AssetCode, String>,
+    ) -> Result<Self, String> {
+        let rates = rates
+            .iter()
+            .map(|(asset, rate)| Ok((*asset, rate.parse::<Decimal>()?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let mut total = Decimal::default();
+        let mut values = HashMap::new();
+        let mut unvalued = vec![];
+        for (asset, balance) in balances {
+            if balance.is_zero() {
+                continue;
+            }
+            let rate = match rates.get(&asset) {
+                Some(rate) => *rate,
+                None => {
+                    unvalued.push(asset);
+                    continue;
+                }
+            };
+            let value = rate
+                .checked_mul(balance)
+                .ok_or_else(|| format!("value of {} overflows", asset))?;
+            total = total
+                .checked_add(value)
+                .ok_or_else(|| String::from("portfolio value overflows"))?;
+            values.insert(asset, value.to_string());
+        }
+        unvalued.sort_by_key(|asset| asset.to_string());
+        Ok(Self {
+            total: total.to_string(),
+            values,
+            unvalued,
+        })
+    }
+}
+
+/// Estimated Ethereum cost of a wrap or unwrap.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GasEstimate {
+    pub gas: U256,
+    /// Current gas price, in wei.
+    pub gas_price: U256,
+    /// Total estimated cost, in wei.
+    pub total: U256,
+}
+
+impl GasEstimate {
+    pub fn new(gas: U256, gas_price: U256) -> Self {
+        Self {
+            gas,
+            gas_price,
+            total: gas * gas_price,
+        }
+    }
+}
+
+/// A copy of the records and accounts in a wallet at some point in time.
+///
+/// Snapshots are kept by the server and compared to the current state of the wallet to produce a
+/// [WalletDiff].
+#[derive(Clone, Debug, Default)]
+pub struct WalletSnapshot {
+    pub records: Vec<RecordInfo>,
+    pub addresses: Vec<UserAddress>,
+}
+
+impl WalletSnapshot {
+    pub async fn from_wallet<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
+        wallet: &CapeWallet<'a, Backend>,
+    ) -> Self {
+        Self {
+            records: wallet.records().await.collect(),
+            addresses: wallet
+                .pub_keys()
+                .await
+                .into_iter()
+                .map(|key| key.address().into())
+                .collect(),
+        }
+    }
+
+    /// The total amount of each asset held in the records of this snapshot.
+    pub fn balances(&self) -> HashMap<AssetCode, U256> {
+        let mut balances = HashMap::<AssetCode, U256>::new();
+        for record in &self.records {
+            *balances.entry(record.ro.asset_def.code).or_default() +=
+                record.ro.amount.generic_into::<u128>().into();
+        }
+        balances
+    }
+}
+
+/// The change in the total balance of an asset between two [WalletSnapshot]s.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceDelta {
+    pub before: U256,
+    pub after: U256,
+}
+
+/// Ledger events coalesced into one frame by a debounced `events` subscription.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventBatch {
+    /// Index of the first event in the batch.
+    pub first: usize,
+    /// Number of events in the batch.
+    pub events: usize,
+    pub commits: usize,
+    pub memos: usize,
+    pub rejects: usize,
+    /// The net change in the wallet's total balance of each asset whose balance changed since the
+    /// previous frame.
+    pub balances: HashMap<AssetCode, BalanceDelta>,
+}
+
+/// Changes to a wallet between an earlier and a later [WalletSnapshot].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalletDiff {
+    /// Records in the later snapshot which were not in the earlier one.
+    pub new_records: Vec<RecordInfo>,
+    /// Records in the earlier snapshot which are no longer in the later one.
+    pub spent_records: Vec<RecordInfo>,
+    /// Addresses in the later snapshot which were not in the earlier one.
+    pub new_addresses: Vec<UserAddress>,
+    /// Balances which changed, by asset. Assets whose balance did not change are omitted.
+    pub balances: HashMap<AssetCode, BalanceDelta>,
+}
+
+impl WalletDiff {
+    pub fn new(before: &WalletSnapshot, after: &WalletSnapshot) -> Self {
+        let has_record = |snapshot: &WalletSnapshot, uid: u64| {
+            snapshot.records.iter().any(|record| record.uid == uid)
+        };
+        let before_balances = before.balances();
+        let after_balances = after.balances();
+        let balances = before_balances
+            .keys()
+            .chain(after_balances.keys())
+            .filter_map(|asset| {
+                let delta = BalanceDelta {
+                    before: before_balances.get(asset).cloned().unwrap_or_default(),
+                    after: after_balances.get(asset).cloned().unwrap_or_default(),
+                };
+                if delta.before == delta.after {
+                    None
+                } else {
+                    Some((*asset, delta))
+                }
+            })
+            .collect();
+        Self {
+            new_records: after
+                .records
+                .iter()
+                .filter(|record| !has_record(before, record.uid))
+                .cloned()
+                .collect(),
+            spent_records: before
+                .records
+                .iter()
+                .filter(|record| !has_record(after, record.uid))
+                .cloned()
+                .collect(),
+            new_addresses: after
+                .addresses
+                .iter()
+                .filter(|address| !before.addresses.contains(address))
+                .cloned()
+                .collect(),
+            balances,
+        }
+    }
+}
+
+/// Result of checking a recovery phrase with `validatemnemonic`.
+#[ser_test(ark(false))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MnemonicValidation {
+    pub valid: bool,
+    pub word_count: usize,
+}
+
+/// The outcome of one read in a `batch` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// The path of the read, as given in the request.
+    pub path: String,
+    /// The HTTP status the read would have had as a request of its own.
+    pub status: u16,
+    /// The response body of the read, if it succeeded.
+    pub result: Option<serde_json::Value>,
+    /// The error message of the read, if it failed.
+    pub error: Option<String>,
+}
+
+/// Why `checkwallet` found that a keystore cannot be opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnopenableReason {
+    /// There is no keystore at the given path.
+    MissingStore,
+    /// The mnemonic does not belong to the keystore.
+    WrongMnemonic,
+    /// The keystore was created for a different CAPE contract.
+    WrongContract,
+    /// The keystore metadata could not be read.
+    CorruptedStore,
+}
+
+/// Result of checking whether a keystore can be opened with `checkwallet`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletCheck {
+    pub openable: bool,
+    pub reason: Option<UnopenableReason>,
+}
+
+/// Result of checking a receiving key with `checkrecipient`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipientCheck {
+    /// Whether the key can be used as the recipient of a transfer.
+    pub ok: bool,
+    /// The address of the key, if it is usable.
+    pub address: Option<UserAddress>,
+    /// Why the key is not usable, if it is not.
+    pub error: Option<String>,
+}
+
+/// Approximate time to replay one ledger event during recovery, in microseconds.
+pub const RECOVERY_EVENT_COST_US: u64 = 500;
+
+/// Approximate time to check one record against one recovered key, in microseconds.
+pub const RECOVERY_RECORD_KEY_COST_US: u64 = 50;
+
+/// Estimated cost of recovering a wallet from a mnemonic, as reported by `recoveryestimate`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryEstimate {
+    /// Number of ledger events which will be replayed.
+    pub events: u64,
+    /// Number of records which will be checked for ownership.
+    pub records: u64,
+    /// Number of sending keys which will be derived and checked against each record.
+    pub keys: u64,
+    /// Approximate duration of the scan, in milliseconds.
+    pub estimated_ms: u64,
+}
+
+impl RecoveryEstimate {
+    pub fn new(events: u64, records: u64, keys: u64) -> Self {
+        let cost_us = events
+            .saturating_mul(RECOVERY_EVENT_COST_US)
+            .saturating_add(
+                records
+                    .saturating_mul(keys)
+                    .saturating_mul(RECOVERY_RECORD_KEY_COST_US),
+            );
+        Self {
+            events,
+            records,
+            keys,
+            estimated_ms: cost_us / 1000,
+        }
+    }
+}
+
+/// An inconsistency in a wallet store found by `fsck`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FsckAnomaly {
+    /// The balance reported for an account does not match the sum of its unfrozen records.
+    BalanceMismatch {
+        address: UserAddress,
+        asset: AssetCode,
+        records_total: U256,
+        balance: U256,
+    },
+    /// A record is owned by a key which is not in the wallet.
+    OrphanedRecord { uid: u64, owner: UserAddress },
+    /// More than one record has the same uid.
+    DuplicateRecord { uid: u64 },
+    /// A record has an asset type which is not in the wallet's asset library.
+    UnknownAsset { asset: AssetCode },
+    /// The balance index used by `getbalance` does not match the balance reported by the wallet.
+    StaleBalanceIndex {
+        address: UserAddress,
+        asset: AssetCode,
+        indexed: U256,
+        balance: U256,
+    },
+}
+
+impl FsckAnomaly {
+    /// Whether `fsck/repair` can safely fix this anomaly.
+    ///
+    /// Only missing asset definitions, which are repaired by importing the definition from the
+    /// record that uses it, and a stale balance index, which is rebuilt, can be repaired. Anything
+    /// else requires manual intervention.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Self::UnknownAsset { .. } | Self::StaleBalanceIndex { .. }
+        )
+    }
+}
+
+/// Result of a wallet store integrity check.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsckReport {
+    /// Anomalies which are still present.
+    pub anomalies: Vec<FsckAnomaly>,
+    /// Anomalies which were found and fixed, if a repair was requested.
+    pub repaired: Vec<FsckAnomaly>,
+}
+
+impl FsckReport {
+    /// Check the records of a wallet against its addresses, known assets, and reported balances.
+    pub fn check(
+        records: &[RecordInfo],
+        addresses: &[UserAddress],
+        assets: &HashSet<AssetCode>,
+        balances: &HashMap<(UserAddress, AssetCode), U256>,
+    ) -> Self {
+        let mut anomalies = vec![];
+
+        let mut uids = HashSet::new();
+        let mut unknown_assets = HashSet::new();
+        let mut records_totals = HashMap::<(UserAddress, AssetCode), U256>::new();
+        for record in records {
+            let owner = UserAddress::from(record.ro.pub_key.address());
+            let asset = record.ro.asset_def.code;
+            if !uids.insert(record.uid) {
+                anomalies.push(FsckAnomaly::DuplicateRecord { uid: record.uid });
+            }
+            if !addresses.contains(&owner) {
+                anomalies.push(FsckAnomaly::OrphanedRecord {
+                    uid: record.uid,
+                    owner: owner.clone(),
+                });
+            }
+            if !assets.contains(&asset) && unknown_assets.insert(asset) {
+                anomalies.push(FsckAnomaly::UnknownAsset { asset });
+            }
+            if record.ro.freeze_flag == FreezeFlag::Unfrozen {
+                *records_totals.entry((owner, asset)).or_default() +=
+                    record.ro.amount.generic_into::<u128>().into();
+            }
+        }
+
+        let accounts = balances
+            .keys()
+            .chain(records_totals.keys())
+            .cloned()
+            .collect::<HashSet<_>>();
+        for (address, asset) in accounts {
+            let key = (address, asset);
+            let records_total = records_totals.get(&key).cloned().unwrap_or_default();
+            let balance = balances.get(&key).cloned().unwrap_or_default();
+            if records_total != balance {
+                anomalies.push(FsckAnomaly::BalanceMismatch {
+                    address: key.0,
+                    asset,
+                    records_total,
+                    balance,
+                });
+            }
+        }
+
+        Self {
+            anomalies,
+            repaired: vec![],
+        }
+    }
+}
+
+/// An open streaming subscription.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionInfo {
+    pub id: u64,
+    /// The route which was subscribed to.
+    pub route: String,
+    /// How long the subscription has been open, in seconds.
+    pub open_secs: u64,
+    /// How many events can be waiting to be sent to the subscriber.
+    pub buffer: usize,
+    /// What happens when the buffer is full.
+    pub overflow: OverflowPolicy,
+    /// How many events have been dropped because the subscriber could not keep up.
+    pub dropped: u64,
+}
+
+/// What a subscription does with a new event when the subscriber is too slow to keep up and its
+/// buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered event to make room for the new one.
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Discard the buffer and end the stream.
+    Disconnect,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        Self::Disconnect
+    }
+}
+
+/// Statistics about the response cache, as reported by `cache/stats`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Number of cached responses.
+    pub size: usize,
+    /// Total size of the cached responses, in bytes.
+    pub bytes: usize,
+    /// Requests answered from the cache since it was last cleared.
+    pub hits: u64,
+    /// Requests to cached routes which were not in the cache since it was last cleared.
+    pub misses: u64,
+    /// The most bytes of responses the cache keeps, if limited by `--cache-budget`.
+    pub budget: Option<usize>,
+    /// Responses evicted to stay within the budget since the cache was last cleared.
+    pub evictions: u64,
+    pub entries: Vec<CacheEntryInfo>,
+}
+
+/// A cached response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntryInfo {
+    /// The path and query of the cached request.
+    pub url: String,
+    /// The number of events the wallet had processed when the response was cached.
+    pub event_index: usize,
+    /// Size of the response, in bytes.
+    pub bytes: usize,
+    /// Number of times the response has been served from the cache.
+    pub hits: u64,
+}
+
+/// Statistics about the balance index, as reported by `balanceindex`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceIndexStats {
+    /// Number of unfrozen records counted in the index.
+    pub records: usize,
+    /// Number of accounts and assets with a non-zero balance.
+    pub accounts: usize,
+    /// The number of events the wallet had processed when the index was last updated.
+    pub event_index: Option<usize>,
+    /// Number of times the index has been brought up to date with the wallet.
+    pub updates: u64,
+    /// Number of those updates which rebuilt the index from scratch.
+    pub rebuilds: u64,
+}
+
+/// A record the ledger presented again after the wallet already held it, as reported by
+/// `anomalies/duplicates`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayedRecord {
+    /// The uid the record was presented again at.
+    pub uid: u64,
+    /// The uid of the record the wallet already held, which has the same commitment.
+    pub original_uid: u64,
+    pub commitment: RecordCommitment,
+    pub address: UserAddress,
+    pub asset: AssetCode,
+    pub amount: U256,
+}
+
+/// Approximate memory used by the server's major in-memory structures, as reported by `memstats`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemStats {
+    /// The records of the open wallet.
+    pub records: MemUsage,
+    /// The transaction history of the open wallet.
+    pub transactions: MemUsage,
+    /// Cached responses to read-only routes, as reported by `cache/stats`.
+    pub response_cache: MemUsage,
+    /// The balance index, as reported by `balanceindex`.
+    pub balance_index: MemUsage,
+    /// The most bytes of cached responses to keep, if limited by `--cache-budget`.
+    pub cache_budget: Option<usize>,
+}
+
+/// The approximate memory used by one structure.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemUsage {
+    /// Number of entries in the structure.
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// The open wallet's local nullifier cache, as reported by `nullifiers/stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NullifierStats {
+    /// The number of cached nullifiers and their approximate size.
+    pub usage: MemUsage,
+    /// The number of cached nullifiers which are known to be spent.
+    pub spent: usize,
+    /// The number of cached nullifiers which do not belong to a record the wallet owns, and so
+    /// could be pruned.
+    pub prunable: usize,
+}
+
+/// An Ethereum address whose ownership has been proven with `linketh`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedEthAddress {
+    pub address: Address,
+    /// When ownership was proven, in seconds since the Unix epoch.
+    pub linked_at: u64,
+}
+
+/// An automatic consolidation policy, as reported by `autoconsolidate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoConsolidatePolicy {
+    pub asset: AssetCode,
+    /// Consolidate once the wallet holds more than this many records of `asset`.
+    pub threshold: usize,
+    /// How many consolidating transfers have been made under this policy.
+    pub consolidations: u64,
+    /// Why the last attempted consolidation failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// An automatic fee top-up policy, as reported by `feetopup`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTopUpPolicy {
+    /// Top up once the wallet holds fewer than this many fee records.
+    pub threshold: usize,
+    /// The smallest native record which counts as a fee record, and the size of new fee records.
+    pub amount: U256,
+    /// How many fee records have been split off under this policy.
+    pub top_ups: u64,
+    /// Why the last attempted top-up failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// Projected balances after a hypothetical transfer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferSimulation {
+    /// The balances of all accounts in the wallet, as if the transfer had been made.
+    pub balances: BalanceInfo,
+    /// The wallet does not own enough of the transferred asset to cover the amount.
+    pub insufficient_balance: bool,
+    /// The wallet does not own enough of the native asset to cover the fee.
+    pub insufficient_fee: bool,
+}
+
+impl TransferSimulation {
+    /// Project the effect of transferring `amount` of `asset` to `recipient` and paying `fee`.
+    ///
+    /// Which records a transfer spends is not known until it is built, so the amount and fee are
+    /// debited from the accounts with the largest balances first. If the recipient is one of the
+    /// accounts in `by_account`, it is credited with the amount of the transfer. If the wallet cannot
+    /// cover the amount or the fee, the affected balances are drained to 0 and the shortfall is
+    /// reported in `insufficient_balance` or `insufficient_fee`.
+    pub fn new(
+        mut by_account: HashMap<UserAddress, HashMap<AssetCode, U256>>,
+        assets: HashMap<AssetCode, AssetInfo>,
+        asset: AssetCode,
+        amount: U256,
+        fee: U256,
+        recipient: &UserAddress,
+    ) -> Self {
+        let debit = |by_account: &mut HashMap<UserAddress, HashMap<AssetCode, U256>>,
+                     asset: AssetCode,
+                     amount: U256| {
+            let mut balances = by_account
+                .values_mut()
+                .filter_map(|by_asset| by_asset.get_mut(&asset))
+                .collect::<Vec<_>>();
+            balances.sort_by(|a, b| b.cmp(a));
+            let mut remaining = amount;
+            for balance in balances {
+                let debit = std::cmp::min(*balance, remaining);
+                *balance -= debit;
+                remaining -= debit;
+            }
+            amount - remaining
+        };
+
+        let transferred = debit(&mut by_account, asset, amount);
+        let fee_paid = debit(&mut by_account, AssetCode::native(), fee);
+        if let Some(by_asset) = by_account.get_mut(recipient) {
+            *by_asset.entry(asset).or_default() += transferred;
+        }
+
+        let mut aggregate = HashMap::new();
+        for (asset, balance) in by_account.values().flat_map(|by_asset| by_asset.iter()) {
+            *aggregate.entry(*asset).or_default() += *balance;
+        }
+        Self {
+            balances: BalanceInfo {
+                balances: Balances::All {
+                    by_account,
+                    aggregate,
+                },
+                assets,
+            },
+            insufficient_balance: transferred < amount,
+            insufficient_fee: fee_paid < fee,
+        }
+    }
+}
+
+/// A reason a transfer cannot be built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferBlocker {
+    /// The wallet does not own enough of the asset, even counting records it cannot spend.
+    InsufficientBalance,
+    /// The wallet owns enough of the asset, but some of it is in frozen records.
+    FrozenRecords,
+    /// The wallet owns enough of the asset, but some of it is on hold for pending transactions.
+    RecordsOnHold,
+    /// No single spendable native record is large enough to pay the fee.
+    NoFeeRecord,
+    /// The amount is spread over more records than a single transfer can spend.
+    Fragmented,
+}
+
+impl TransferBlocker {
+    /// A stable, machine-readable identifier for this reason.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InsufficientBalance => "insufficient_balance",
+            Self::FrozenRecords => "frozen_records",
+            Self::RecordsOnHold => "records_on_hold",
+            Self::NoFeeRecord => "no_fee_record",
+            Self::Fragmented => "fragmented",
+        }
+    }
+}
+
+impl Display for TransferBlocker {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::InsufficientBalance => "insufficient balance",
+            Self::FrozenRecords => "some of the balance is in frozen records",
+            Self::RecordsOnHold => "some of the balance is on hold for pending transactions",
+            Self::NoFeeRecord => {
+                "no single record of the native asset is large enough to pay the fee"
+            }
+            Self::Fragmented => {
+                "the balance is spread over too many records to spend in one transfer"
+            }
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// The minimum anonymity set of the records `send` spends, as reported by `minanonymity`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinAnonymityStatus {
+    /// The minimum anonymity set, or 0 if there is none.
+    pub threshold: u64,
+    /// The number of records in the ledger the wallet has scanned.
+    pub ledger_size: u64,
+    /// The uids of the wallet's records whose anonymity set is below the minimum, in order.
+    pub restricted: Vec<u64>,
+}
+
+/// How the records a transfer spends are chosen, as configured by `coinselection`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionStrategy {
+    /// Spend the largest records first, so the transfer has as few inputs as possible.
+    LargestFirst,
+    /// Spend the smallest records first, consolidating dust into the change.
+    SmallestFirst,
+    /// Spend the single record which covers the amount with the least change, if there is one, so
+    /// the transfer links as few records as possible. Otherwise, spend the largest records first.
+    Privacy,
+}
+
+impl Default for SelectionStrategy {
+    fn default() -> Self {
+        Self::LargestFirst
+    }
+}
+
+impl Display for SelectionStrategy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Self::LargestFirst => "largest",
+            Self::SmallestFirst => "smallest",
+            Self::Privacy => "privacy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "largest" => Ok(Self::LargestFirst),
+            "smallest" => Ok(Self::SmallestFirst),
+            "privacy" => Ok(Self::Privacy),
+            _ => Err(format!(
+                "{} is not a selection strategy (largest, smallest or privacy)",
+                s
+            )),
+        }
+    }
+}
+
+/// How much diagnostic detail error responses include, as configured by `errordetail`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorDetail {
+    /// Respond with just the error and its message.
+    Terse,
+    /// Respond with a [VerboseError], which also explains how the request was routed and what
+    /// caused the error.
+    Verbose,
+}
+
+impl Default for ErrorDetail {
+    fn default() -> Self {
+        Self::Terse
+    }
+}
+
+impl Display for ErrorDetail {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Terse => "terse",
+            Self::Verbose => "verbose",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ErrorDetail {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terse" => Ok(Self::Terse),
+            "verbose" => Ok(Self::Verbose),
+            _ => Err(format!(
+                "{} is not an error detail level (terse or verbose)",
+                s
+            )),
+        }
+    }
+}
+
+/// The body of an error response with [ErrorDetail::Verbose].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerboseError {
+    /// The error, exactly as it appears in a terse error response.
+    pub error: serde_json::Value,
+    /// The error's message.
+    pub message: String,
+    /// The messages of the errors which caused `error`, outermost first.
+    pub causes: Vec<String>,
+    /// How the request path was matched against the API's route patterns, or `None` if the request
+    /// was not for a route in the API. Values of secret parameters, such as passwords, are redacted.
+    pub route_doc: Option<String>,
+}
+
+/// Whether a transfer can be built, as reported by `cansend`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanSend {
+    pub ok: bool,
+    /// Why the transfer cannot be built, if it can't.
+    pub reason: Option<String>,
+    /// Machine-readable code for `reason`; see [TransferBlocker::code].
+    pub code: Option<String>,
+}
+
+impl CanSend {
+    /// Check whether the wallet owning `records` can transfer `amount` of `asset` and pay `fee`.
+    ///
+    /// See [ChangePreview::select] for how records are selected.
+    pub fn check(
+        records: impl IntoIterator<Item = RecordInfo>,
+        asset: AssetCode,
+        amount: U256,
+        fee: U256,
+        max_inputs: usize,
+    ) -> Self {
+        match ChangePreview::select(records, asset, amount, fee, max_inputs) {
+            Ok(_) => Self {
+                ok: true,
+                reason: None,
+                code: None,
+            },
+            Err(blocker) => Self::blocked(blocker),
+        }
+    }
+
+    fn blocked(blocker: TransferBlocker) -> Self {
+        Self {
+            ok: false,
+            reason: Some(blocker.to_string()),
+            code: Some(blocker.code().to_string()),
+        }
+    }
+}
+
+/// The records a transfer would spend and the change it would return, as reported by
+/// `changepreview`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangePreview {
+    /// Uid of the native record which would pay the fee.
+    pub fee_input: u64,
+    /// Uids of the records of the asset which would pay the amount, in the order they were
+    /// selected.
+    ///
+    /// When transferring the native asset, this does not include `fee_input`.
+    pub inputs: Vec<u64>,
+    /// The change in the transferred asset: the total of `inputs` minus the amount.
+    pub change: U256,
+    /// The native change from the fee record: the value of `fee_input` minus the fee.
+    pub fee_change: U256,
+}
+
+impl ChangePreview {
+    /// Select the records the wallet owning `records` would spend to transfer `amount` of `asset`
+    /// and pay `fee`.
+    ///
+    /// This mirrors the way a transfer selects records: the fee is paid from the smallest native
+    /// record which covers it, and the amount from the largest records of `asset`, using at most
+    /// `max_inputs` records in total including the fee record. Checks are made in order of
+    /// severity, and the first one which fails is reported.
+    pub fn select(
+        records: impl IntoIterator<Item = RecordInfo>,
+        asset: AssetCode,
+        amount: U256,
+        fee: U256,
+        max_inputs: usize,
+    ) -> Result<Self, TransferBlocker> {
+        Self::select_with(
+            records,
+            asset,
+            amount,
+            fee,
+            max_inputs,
+            SelectionStrategy::LargestFirst,
+        )
+    }
+
+    /// Select records like [ChangePreview::select], but pay the amount from the records chosen by
+    /// `strategy`.
+    pub fn select_with(
+        records: impl IntoIterator<Item = RecordInfo>,
+        asset: AssetCode,
+        amount: U256,
+        fee: U256,
+        max_inputs: usize,
+        strategy: SelectionStrategy,
+    ) -> Result<Self, TransferBlocker> {
+        let mut spendable = vec![];
+        let mut spendable_fee = vec![];
+        let mut frozen = U256::zero();
+        let mut on_hold = U256::zero();
+        for record in records {
+            let record_amount: U256 = record.ro.amount.generic_into::<u128>().into();
+            let code = record.ro.asset_def.code;
+            if code != asset && code != AssetCode::native() {
+                continue;
+            }
+            if record.ro.freeze_flag != FreezeFlag::Unfrozen {
+                if code == asset {
+                    frozen += record_amount;
+                }
+            } else if record.hold_until.is_some() {
+                if code == asset {
+                    on_hold += record_amount;
+                }
+            } else {
+                if code == asset {
+                    spendable.push((record_amount, record.uid));
+                }
+                if code == AssetCode::native() {
+                    spendable_fee.push((record_amount, record.uid));
+                }
+            }
+        }
+
+        // If we are transferring the native asset, the fee comes out of the same balance.
+        let required = if asset == AssetCode::native() {
+            amount + fee
+        } else {
+            amount
+        };
+        let total = spendable
+            .iter()
+            .fold(U256::zero(), |total, (amount, _)| total + amount);
+        if total < required {
+            return Err(if total + frozen + on_hold < required {
+                TransferBlocker::InsufficientBalance
+            } else if total + on_hold < required {
+                TransferBlocker::FrozenRecords
+            } else {
+                TransferBlocker::RecordsOnHold
+            });
+        }
+
+        // The fee is paid from the smallest native record which covers it.
+        spendable_fee.sort();
+        let (fee_record, fee_input) = match spendable_fee.iter().find(|(amount, _)| *amount >= fee)
+        {
+            Some(record) => *record,
+            None => return Err(TransferBlocker::NoFeeRecord),
+        };
+        if asset == AssetCode::native() {
+            spendable.retain(|(_, uid)| *uid != fee_input);
+        }
+
+        // Order the records by `strategy`, and see how many it takes to cover the amount.
+        match strategy {
+            SelectionStrategy::LargestFirst => spendable.sort_by(|a, b| b.cmp(a)),
+            SelectionStrategy::SmallestFirst => spendable.sort(),
+            SelectionStrategy::Privacy => {
+                spendable.sort_by(|a, b| b.cmp(a));
+                // The last record which covers the amount is the one with the least change.
+                if let Some(i) = spendable
+                    .iter()
+                    .rposition(|(record_amount, _)| *record_amount >= amount)
+                {
+                    let record = spendable.remove(i);
+                    spendable.insert(0, record);
+                }
+            }
+        }
+        let mut covered = U256::zero();
+        let mut inputs = vec![];
+        for (record_amount, uid) in spendable {
+            if covered >= amount {
+                break;
+            }
+            covered += record_amount;
+            inputs.push(uid);
+        }
+        if covered < amount {
+            // Only possible if the fee record was needed to cover the amount as well.
+            return Err(TransferBlocker::InsufficientBalance);
+        }
+        if inputs.len() + 1 > max_inputs {
+            return Err(TransferBlocker::Fragmented);
+        }
+
+        Ok(Self {
+            fee_input,
+            inputs,
+            change: covered - amount,
+            fee_change: fee_record - fee,
+        })
+    }
+}
+
+/// One of the transfers planned with `totalcost`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedTransfer {
+    pub asset: AssetCode,
+    pub recipient: UserAddress,
+    pub amount: U256,
+}
+
+/// What a set of planned transfers would cost, as reported by `totalcost`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TotalCost {
+    /// The number of transactions it takes to make the transfers.
+    pub transactions: usize,
+    /// The fee paid by each transaction.
+    pub fee: U256,
+    /// The fees paid by all of the transactions.
+    pub fees: U256,
+    /// The total amount of each asset transferred, not counting fees.
+    pub amounts: HashMap<AssetCode, U256>,
+    /// The total amount of each asset the wallet would spend: `amounts`, plus `fees` of the native
+    /// asset.
+    pub totals: HashMap<AssetCode, U256>,
+    /// Whether the wallet's spendable balances cover `totals`.
+    pub affordable: bool,
+}
+
+impl TotalCost {
+    /// The cost of making `transfers` from the wallet owning `records`, paying `fee` per
+    /// transaction.
+    ///
+    /// Transfers of the same asset are batched into as few transactions as possible. Each
+    /// transaction has at most `max_outputs` outputs, including the fee change and, except when
+    /// transferring the native asset, where the change and fee change are the same output, the
+    /// change.
+    pub fn new(
+        records: impl IntoIterator<Item = RecordInfo>,
+        transfers: &[PlannedTransfer],
+        fee: U256,
+        max_outputs: usize,
+    ) -> Self {
+        let mut amounts = HashMap::<AssetCode, U256>::new();
+        let mut receivers = HashMap::<AssetCode, usize>::new();
+        for transfer in transfers {
+            *amounts.entry(transfer.asset).or_default() += transfer.amount;
+            *receivers.entry(transfer.asset).or_default() += 1;
+        }
+        let transactions = receivers
+            .iter()
+            .map(|(asset, receivers)| {
+                let change_outputs = if *asset == AssetCode::native() { 1 } else { 2 };
+                let per_transaction = max_outputs.saturating_sub(change_outputs).max(1);
+                (receivers + per_transaction - 1) / per_transaction
+            })
+            .sum::<usize>();
+        let fees = fee * transactions;
+
+        let mut totals = amounts.clone();
+        if !fees.is_zero() {
+            *totals.entry(AssetCode::native()).or_default() += fees;
+        }
+        let mut spendable = HashMap::<AssetCode, U256>::new();
+        for record in records {
+            if record.ro.freeze_flag == FreezeFlag::Unfrozen && record.hold_until.is_none() {
+                *spendable.entry(record.ro.asset_def.code).or_default() +=
+                    U256::from(record.ro.amount.generic_into::<u128>());
+            }
+        }
+        let affordable = totals
+            .iter()
+            .all(|(asset, total)| spendable.get(asset).copied().unwrap_or_default() >= *total);
+
+        Self {
+            transactions,
+            fee,
+            fees,
+            amounts,
+            totals,
+            affordable,
+        }
+    }
+}
+
+/// Why a planned transfer would link to prior transactions, as reported by `linkability`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkabilityReason {
+    /// Machine-readable reason, like `reused_address`.
+    pub code: String,
+    pub detail: String,
+    /// How much this reason adds to the risk score.
+    pub weight: u32,
+}
+
+/// How easily a planned transfer could be linked to the wallet's prior transactions, as reported
+/// by `linkability`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Linkability {
+    /// The risk score, from 0 (no known linkage) to [Linkability::MAX_SCORE].
+    pub score: u32,
+    /// The uids of the records the transfer would spend, as reported by `changepreview`.
+    pub inputs: Vec<u64>,
+    /// The address the change would return to, if there is any change.
+    pub change_address: Option<UserAddress>,
+    pub reasons: Vec<LinkabilityReason>,
+}
+
+impl Linkability {
+    pub const MAX_SCORE: u32 = 100;
+
+    /// Analyze the transfer previewed by `preview`.
+    ///
+    /// `records` are the records of the wallet, including the inputs of `preview`, and
+    /// `used_addresses` are the addresses which appear as a sender or receiver in the wallet's
+    /// transaction history. Change returns to the owner of the first input.
+    pub fn analyze(
+        preview: &ChangePreview,
+        records: &[RecordInfo],
+        used_addresses: &HashSet<UserAddress>,
+    ) -> Self {
+        let inputs = preview
+            .inputs
+            .iter()
+            .filter_map(|uid| records.iter().find(|record| record.uid == *uid))
+            .collect::<Vec<_>>();
+        let owners = inputs
+            .iter()
+            .map(|record| UserAddress::from(record.ro.pub_key.address()))
+            .collect::<HashSet<_>>();
+        let change_address = if preview.change > U256::zero() {
+            inputs
+                .first()
+                .map(|record| UserAddress::from(record.ro.pub_key.address()))
+        } else {
+            None
+        };
+
+        let mut reasons = vec![];
+        if let Some(address) = &change_address {
+            if used_addresses.contains(address) {
+                reasons.push(LinkabilityReason {
+                    code: String::from("reused_address"),
+                    detail: format!(
+                        "change returns to {}, which appears in prior transactions",
+                        address
+                    ),
+                    weight: 40,
+                });
+            }
+        }
+        if owners.len() > 1 {
+            reasons.push(LinkabilityReason {
+                code: String::from("multiple_addresses"),
+                detail: format!(
+                    "inputs are owned by {} different addresses, which the transfer links together",
+                    owners.len()
+                ),
+                weight: 30,
+            });
+        }
+        if inputs.len() > 1 {
+            reasons.push(LinkabilityReason {
+                code: String::from("multiple_inputs"),
+                detail: format!(
+                    "spends {} records, linking the transactions which created them",
+                    inputs.len()
+                ),
+                weight: std::cmp::min(10 * (inputs.len() as u32 - 1), 20),
+            });
+        }
+        let viewable = inputs
+            .iter()
+            .filter(|record| record.ro.asset_def.policy_ref().is_user_address_revealed())
+            .count();
+        if viewable > 0 {
+            reasons.push(LinkabilityReason {
+                code: String::from("viewable_input"),
+                detail: format!(
+                    "{} of the inputs belong to an asset whose viewer sees their owner",
+                    viewable
+                ),
+                weight: 20,
+            });
+        }
+
+        Self {
+            score: std::cmp::min(
+                reasons.iter().map(|reason| reason.weight).sum(),
+                Self::MAX_SCORE,
+            ),
+            inputs: preview.inputs.clone(),
+            change_address,
+            reasons,
+        }
+    }
+}
+
+/// The records of an asset created in a range of ages, as reported by `recordages`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordAgeBucket {
+    /// The youngest age in the bucket, in blocks.
+    pub min_age: u64,
+    /// The oldest age in the bucket, in blocks.
+    pub max_age: u64,
+    /// The number of records in the bucket.
+    pub records: usize,
+    /// The total amount of the records in the bucket.
+    pub amount: U256,
+}
+
+/// How old the open wallet's records of an asset are, as reported by `recordages`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordAges {
+    pub asset: AssetCode,
+    /// The number of blocks committed as of the wallet's current sync time.
+    pub height: u64,
+    /// The non-empty buckets, youngest first.
+    pub buckets: Vec<RecordAgeBucket>,
+}
+
+impl RecordAges {
+    /// Bucket records by age, given the number of blocks committed so far and the amount and
+    /// creating block of each record.
+    ///
+    /// A record created in the latest block has age 0. Each bucket covers `width` ages.
+    pub fn new(
+        asset: AssetCode,
+        height: u64,
+        records: impl IntoIterator<Item = (U256, u64)>,
+        width: u64,
+    ) -> Self {
+        let mut buckets = BTreeMap::<u64, RecordAgeBucket>::new();
+        for (amount, block) in records {
+            let age = height.saturating_sub(block + 1);
+            let min_age = age - age % width;
+            let bucket = buckets.entry(min_age).or_insert(RecordAgeBucket {
+                min_age,
+                max_age: min_age + width - 1,
+                records: 0,
+                amount: U256::zero(),
+            });
+            bucket.records += 1;
+            bucket.amount += amount;
+        }
+        Self {
+            asset,
+            height,
+            buckets: buckets.into_values().collect(),
+        }
+    }
+}
+
+/// An asset verification authority trusted by the open wallet, as reported by `verifiers`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedVerifier {
+    pub name: String,
+    /// The key which signs the verifier's asset libraries.
+    pub key: String,
+    /// The assets in libraries the verifier signed, which the wallet considers verified.
+    pub assets: Vec<AssetCode>,
+}
+
+/// Whether the server requires an admin token, and how old the current token is.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthStatus {
+    pub enabled: bool,
+    /// Seconds since the current admin token was created, if authentication is enabled.
+    pub token_age_secs: Option<u64>,
+}
+
+/// The client certificate a connection was authenticated with, as reported by `auth/peer`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCertificate {
+    /// The distinguished name of the subject, like `CN=client`.
+    pub subject: String,
+    /// The distinguished name of the CA which issued the certificate.
+    pub issuer: String,
+}
+
+/// What `mergewallet` merged into the open wallet.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// Assets which were only known to the other wallet.
+    pub added_assets: Vec<AssetCode>,
+    /// Assets known to both wallets whose annotations were updated from the other wallet.
+    pub updated_assets: Vec<AssetCode>,
+    /// Assets with conflicting annotations in the two wallets.
+    pub conflicts: Vec<AssetCode>,
+    /// Contacts which were only in the address book of the server which created the other wallet.
+    pub added_contacts: Vec<String>,
+    /// Contacts whose address was replaced by the one in the other address book.
+    pub updated_contacts: Vec<String>,
+    /// Contacts with a different address in the two address books.
+    pub contact_conflicts: Vec<String>,
+    /// Sending keys which only the other wallet had generated.
+    pub added_sending_keys: Vec<UserPubKey>,
+    /// Viewing keys which only the other wallet had generated.
+    pub added_viewing_keys: Vec<AuditorPubKey>,
+    /// Freezing keys which only the other wallet had generated.
+    pub added_freezing_keys: Vec<FreezerPubKey>,
+    /// Hashes of the transactions which were only in the other wallet's history.
+    pub added_transactions: Vec<String>,
+}
+
+/// The funds `sweep` moved from a one-time key into the open wallet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SweepReport {
+    /// The address of the wallet the funds were sent to.
+    pub address: UserAddress,
+    /// The amount of each asset sent to `address`, after fees. Empty if the key had no funds.
+    pub swept: HashMap<AssetCode, U256>,
+    /// Hashes of the transfers which moved the funds, in the order they were submitted.
+    pub transactions: Vec<String>,
+}
+
+/// Version of the [MetadataBundle] format produced by `exportmetadata`.
+pub const METADATA_BUNDLE_VERSION: u32 = 1;
+
+/// Local, non-secret wallet metadata, as exported by `exportmetadata`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetadataBundle {
+    /// Must be [METADATA_BUNDLE_VERSION] to be imported.
+    pub version: u32,
+    pub contacts: Vec<Contact>,
+    /// Assets in the wallet's library, with their annotations and local display metadata, but
+    /// without mint info.
+    pub assets: Vec<AssetInfo>,
+}
+
+/// A wallet's automatic backup policy, as reported by `backup/schedule`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupPolicy {
+    /// The directory backups are written to.
+    pub dir: PathBuf,
+    /// How often to back up the wallet, in seconds, or 0 to only back up with `backup/now`.
+    pub interval_secs: u64,
+    /// How many backups to keep. Older backups are deleted after each new one is written.
+    pub keep: usize,
+    /// When the last backup was written, in milliseconds since the Unix epoch.
+    pub last_backup: Option<u64>,
+    /// Why the last scheduled backup failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// A backup of wallet metadata, as listed by `backups`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    /// The name of the backup file, which identifies it to `backup/restore`.
+    pub name: String,
+    /// When the backup was written, in milliseconds since the Unix epoch.
+    pub created: u64,
+    /// The size of the backup file, in bytes.
+    pub size: u64,
+}
+
+/// What `importmetadata` imported.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataImportReport {
+    /// The outcome of importing each contact in the bundle, in order.
+    pub contacts: Vec<ContactImportResult>,
+    /// Assets added to or updated in the wallet's library.
+    pub assets: MergeReport,
+    /// Assets whose local display metadata was imported.
+    pub metadata: Vec<AssetCode>,
+}
+
+/// A named address in the address book.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    pub name: String,
+    pub address: UserAddress,
+}
+
+/// An entry in a `contacts/import` request.
+///
+/// The address is not parsed until the entry is imported, so that one malformed address does not
+/// cause the whole request to be rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactEntry {
+    pub name: String,
+    pub address: String,
+}
+
+/// What happened to one entry in a `contacts/import` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContactImportStatus {
+    /// The contact was added.
+    Added,
+    /// A contact with the same name already existed and was replaced.
+    Overwritten,
+    /// A contact with the same name already existed and was kept.
+    Skipped,
+    /// The entry was not imported because it was malformed.
+    Invalid { msg: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactImportResult {
+    pub name: String,
+    pub status: ContactImportStatus,
+}
+
+/// A transaction built by `exporttransaction`, ready to be submitted by an external service.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedTransaction {
+    /// Base64 encoding of the bincode-serialized relayer submission (transaction, memos, and
+    /// signature).
+    pub transaction: String,
+    /// The hash the transaction will have in the wallet's transaction history.
+    pub hash: Option<String>,
+    /// The records spent by the transaction, which are on hold until it is committed or expires.
+    pub inputs: Vec<Record>,
+}
+
+/// The terms of an atomic swap proposed with `swap/propose`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapTerms {
+    /// The address which sends the offered asset and receives the requested asset.
+    pub proposer: UserAddress,
+    /// The address which sends the requested asset and receives the offered asset.
+    pub counterparty: UserAddress,
+    pub offered_asset: AssetCode,
+    pub offered_amount: U256,
+    pub requested_asset: AssetCode,
+    pub requested_amount: U256,
+}
+
+/// A swap proposed with `swap/propose`, to be completed by the counterparty with `swap/accept`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapProposal {
+    pub terms: SwapTerms,
+    /// Base64 encoding of the bincode-serialized proposer's half of the swap: the terms, the relayer
+    /// submission of the proposer's transfer, and the opening of the record it sends the
+    /// counterparty.
+    pub proposal: String,
+    /// The hash the proposer's transfer will have in the wallet's transaction history.
+    pub hash: Option<String>,
+}
+
+/// Parameters and limits of the proof system used to build CAPE transactions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofParams {
+    /// Height of the record Merkle tree.
+    pub merkle_height: u8,
+    /// Number of recent Merkle roots accepted by the contract.
+    pub num_roots: usize,
+    /// Maximum number of inputs to a transfer, including the fee input.
+    pub max_transfer_inputs: usize,
+    /// Maximum number of outputs of a transfer, including the fee change output.
+    pub max_transfer_outputs: usize,
+    /// The (inputs, outputs) sizes of transfers for which proofs can be generated.
+    pub transfer_sizes: Vec<(usize, usize)>,
+    /// The numbers of inputs of freezes for which proofs can be generated.
+    pub freeze_sizes: Vec<usize>,
+}
+
+impl ProofParams {
+    /// The parameters this wallet was compiled with.
+    pub fn cape() -> Self {
+        Self {
+            merkle_height: CAPE_MERKLE_HEIGHT,
+            num_roots: CAPE_NUM_ROOTS,
+            max_transfer_inputs: SUPPORTED_TRANSFER_SIZES
+                .iter()
+                .map(|(inputs, _)| *inputs)
+                .max()
+                .unwrap_or(0),
+            max_transfer_outputs: SUPPORTED_TRANSFER_SIZES
+                .iter()
+                .map(|(_, outputs)| *outputs)
+                .max()
+                .unwrap_or(0),
+            transfer_sizes: SUPPORTED_TRANSFER_SIZES.to_vec(),
+            freeze_sizes: SUPPORTED_FREEZE_SIZES.to_vec(),
+        }
+    }
+}
+
+/// The record Merkle tree as scanned by the wallet, as reported by `frontier`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordFrontier {
+    /// Root of the tree, as a `ROOT~...` TaggedBase64 string.
+    pub root: String,
+    pub height: u8,
+    /// Number of records in the tree, which is the position of the next record to be appended.
+    pub num_leaves: u64,
+    /// The frontier of the tree, as a `FRONTIER~...` TaggedBase64 string.
+    pub frontier: String,
+    /// Index of the last event the wallet incorporated into the tree.
+    pub sync_time: usize,
+}
+
+impl RecordFrontier {
+    /// Returns `None` if the root or frontier cannot be serialized.
+    pub fn new(
+        commitment: &MerkleCommitment,
+        frontier: &MerkleFrontier,
+        sync_time: EventIndex,
+    ) -> Option<Self> {
+        Some(Self {
+            root: tagged_bincode_string("ROOT", &commitment.root_value)?,
+            height: commitment.height,
+            num_leaves: commitment.num_leaves,
+            frontier: tagged_bincode_string("FRONTIER", frontier)?,
+            sync_time: sync_time.index(EventSource::QueryService),
+        })
+    }
+}
+
+/// A record owner memo which an indexer decrypted on the wallet's behalf, as accepted by
+/// `notifyrecord`.
+///
+/// The fields are those of an output in a ledger `Memos` event, except that the Merkle path must be
+/// valid against the record Merkle root the wallet has currently scanned, as reported by `frontier`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordNotification {
+    pub memo: ReceiverMemo,
+    pub commitment: RecordCommitment,
+    pub uid: u64,
+    pub merkle_path: MerklePath,
+}
+
+/// An unspent record with its Merkle path, as exported by `export/records`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedRecord {
+    pub ro: JfRecordOpening,
+    pub uid: u64,
+    pub merkle_path: MerklePath,
+}
+
+/// The unspent records of an asset, as exported by `export/records` and imported by
+/// `import/records`.
+///
+/// Every Merkle path is valid against `commitment`, the record Merkle tree the exporting wallet had
+/// scanned at the time, so a wallet which has scanned the same tree can spend the records without
+/// scanning for them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordBundle {
+    pub asset: AssetCode,
+    pub commitment: MerkleCommitment,
+    pub records: Vec<ExportedRecord>,
+}
+
+/// An event the wallet skipped, with the records it would have added to the wallet, as reported by
+/// `skippedevents`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedEvent {
+    /// Index of the event in the query service event stream.
+    pub index: usize,
+    pub records: Vec<Record>,
+}
+
+impl From<crate::wallet::SkippedEvent> for SkippedEvent {
+    fn from(event: crate::wallet::SkippedEvent) -> Self {
+        Self {
+            index: event.index,
+            records: event
+                .records
+                .into_iter()
+                .map(|record| record.info.into())
+                .collect(),
+        }
+    }
+}
+
+/// A run of consecutive events the wallet skipped, as reported by `scangaps`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanGap {
+    /// Index of the first skipped event.
+    pub from: usize,
+    /// Index of the first event after the gap.
+    pub to: usize,
+    /// How many of the wallet's records were posted in the gap.
+    pub records: usize,
+}
+
+impl ScanGap {
+    /// Merge skipped `events`, in order of event index, into gaps.
+    pub fn from_events(events: &[SkippedEvent]) -> Vec<Self> {
+        let mut gaps: Vec<Self> = vec![];
+        for event in events {
+            match gaps.last_mut() {
+                Some(gap) if gap.to == event.index => {
+                    gap.to += 1;
+                    gap.records += event.records.len();
+                }
+                _ => gaps.push(Self {
+                    from: event.index,
+                    to: event.index + 1,
+                    records: event.records.len(),
+                }),
+            }
+        }
+        gaps
+    }
+}
+
+/// The gaps in the open wallet's scan, and how they are retried, as reported by `scangaps`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanGaps {
+    pub gaps: Vec<ScanGap>,
+    /// Whether the server retries the skipped events automatically.
+    pub auto_retry: bool,
+    /// How many automatic retries have been attempted.
+    pub retries: u64,
+    /// How many skipped events automatic retries have reprocessed.
+    pub closed: u64,
+    /// Why the last automatic retry failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// A human-readable receipt for a transaction in the open wallet's history, as reported by
+/// `receipt`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub hash: String,
+    pub time: String,
+    pub kind: String,
+    /// The sending keys which submitted the transaction, if the wallet knows them.
+    pub from: Vec<UserAddress>,
+    /// The receivers the asset policy reveals to the wallet.
+    pub to: Vec<UserAddress>,
+    pub asset: AssetCode,
+    /// The asset's symbol, if it has one.
+    pub symbol: Option<String>,
+    /// The total amount received by `to`, in base units.
+    pub amount: String,
+    /// `amount` in display units, using the asset's `decimals` metadata if it is set.
+    pub display_amount: String,
+    /// The fee paid, in base units of the native asset, if the wallet submitted the transaction.
+    pub fee: Option<String>,
+    /// The status, as reported by `transactionhistory`.
+    pub status: String,
+    /// How many confirmations the transaction has, or `None` if it is not included yet.
+    pub confirmations: Option<usize>,
+    /// How many confirmations the transaction needs to be final.
+    pub required_confirmations: usize,
+}
+
+/// A compact summary of the state of a wallet, as reported by `statedigest`.
+///
+/// Each component is a hex-encoded SHA3-256 hash of a canonical, sorted encoding of part of the
+/// wallet's state, so two wallets in the same state have the same digest no matter what order they
+/// learned about it in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDigest {
+    /// Hash of the other components.
+    pub digest: String,
+    /// Hash of the balance of each asset held by each address.
+    pub balances: String,
+    /// Hash of the wallet's sending, viewing and freezing keys.
+    pub keys: String,
+    /// Hash of the codes of the assets known to the wallet.
+    pub assets: String,
+    /// Hash of the root and size of the record Merkle tree as scanned by the wallet.
+    pub frontier: String,
+}
+
+impl StateDigest {
+    /// Returns `None` if the record Merkle root cannot be serialized.
+    pub async fn from_wallet<'a, Backend: CapeWalletBackend<'a> + Sync + 'a>(
+        wallet: &CapeWallet<'a, Backend>,
+    ) -> Option<Self> {
+        let mut balances = HashMap::<(String, AssetCode), U256>::new();
+        for record in wallet.records().await {
+            *balances
+                .entry((
+                    UserAddress::from(record.ro.pub_key.address()).to_string(),
+                    record.ro.asset_def.code,
+                ))
+                .or_default() += record.ro.amount.generic_into::<u128>().into();
+        }
+        let balances = balances
+            .into_iter()
+            .map(|((address, asset), amount)| format!("{} {} {}", address, asset, amount))
+            .collect();
+
+        let keys = wallet
+            .pub_keys()
+            .await
+            .into_iter()
+            .map(|key| format!("sending {}", UserAddress::from(key.address())))
+            .chain(
+                wallet
+                    .auditor_pub_keys()
+                    .await
+                    .into_iter()
+                    .map(|key| format!("viewing {}", key)),
+            )
+            .chain(
+                wallet
+                    .freezer_pub_keys()
+                    .await
+                    .into_iter()
+                    .map(|key| format!("freezing {}", key)),
+            )
+            .collect();
+
+        let assets = wallet
+            .assets()
+            .await
+            .into_iter()
+            .map(|asset| asset.definition.code.to_string())
+            .collect();
+
+        let (commitment, _, _) = wallet.record_merkle_frontier().await;
+        let frontier = vec![format!(
+            "{} {}",
+            tagged_bincode_string("ROOT", &commitment.root_value)?,
+            commitment.num_leaves
+        )];
+
+        let balances = digest_lines(balances);
+        let keys = digest_lines(keys);
+        let assets = digest_lines(assets);
+        let frontier = digest_lines(frontier);
+        Some(Self {
+            digest: digest_lines(vec![
+                balances.clone(),
+                keys.clone(),
+                assets.clone(),
+                frontier.clone(),
+            ]),
+            balances,
+            keys,
+            assets,
+            frontier,
+        })
+    }
+}
+
+/// Hash `lines` in sorted order.
+fn digest_lines(mut lines: Vec<String>) -> String {
+    lines.sort();
+    let mut hasher = Sha3_256::new();
+    for line in lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// How often open wallets poll for new records, as reported by `scanconfig`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanConfig {
+    pub polling: bool,
+    /// Time between polls, in milliseconds, or `None` if polling is disabled.
+    pub interval_ms: Option<u64>,
+}
+
+impl From<Option<Duration>> for ScanConfig {
+    fn from(interval: Option<Duration>) -> Self {
+        Self {
+            polling: interval.is_some(),
+            interval_ms: interval.map(|interval| interval.as_millis() as u64),
+        }
+    }
+}
+
+/// How far the open wallet has scanned the ledger, as reported by `syncstatus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncStatus {
+    /// The index of the last event the wallet processed, as `sync_time` in `getinfo`.
+    pub synced_to: usize,
+    /// The index of the latest event according to the EQS, as `real_time` in `getinfo`.
+    pub real_time: usize,
+    /// Whether scanning is paused.
+    pub paused: bool,
+    /// Milliseconds until a pause expires and scanning resumes on its own, or `None` if scanning is
+    /// not paused.
+    pub resumes_in_ms: Option<u64>,
+}
+
+/// How `reconnect` brought the open wallet back in sync with the ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    /// The wallet processed only the events it missed, starting from its scan cursor.
+    Incremental,
+    /// The ledger no longer extends the history the wallet has scanned, so the wallet must be
+    /// rescanned from the start.
+    FullResync,
+}
+
+/// The outcome of `reconnect`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reconnection {
+    pub catch_up: CatchUp,
+    /// The wallet's scan cursor when it reconnected, as `synced_to` in `syncstatus`.
+    pub cursor: usize,
+    /// The index of the last event the wallet has processed, as `synced_to` in `syncstatus`.
+    pub synced_to: usize,
+    /// The index of the latest event according to the EQS.
+    pub real_time: usize,
+    /// The number of missed events the wallet processed.
+    pub events: usize,
+    /// The number of blocks committed by those events.
+    pub blocks: u64,
+    /// Why a full resync is required, if it is.
+    pub reason: Option<String>,
+}
+
+/// The server's time and the ledger's height, as reported by `clock`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClockStatus {
+    /// The server's current time, in milliseconds since the Unix epoch.
+    pub now_ms: u64,
+    /// The number of blocks committed to the ledger.
+    pub block_height: u64,
+    /// The rate at which blocks have been committed recently, or `None` if it has not been observed
+    /// yet.
+    pub blocks_per_second: Option<f64>,
+}
+
+/// How many threads transaction proofs are generated with, as reported by `proofconfig`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofConfig {
+    pub threads: usize,
+    /// The number of CPUs available to the server.
+    pub available_threads: usize,
+    /// The number of threads the most recently built transaction's proofs were generated with, or
+    /// `None` if no transaction has been built since the server started.
+    pub last_build_threads: Option<usize>,
+}
+
+/// Call statistics of one route since the server started, as reported by `routes/stats`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteStats {
+    /// The `api.toml` pattern of the route.
+    pub pattern: String,
+    pub calls: u64,
+    /// How many calls failed.
+    pub errors: u64,
+    /// Latency percentiles over the most recent calls, in microseconds.
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+}
+
+/// The body of a `POST webhook` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookRegistration {
+    pub url: String,
+    /// The key to sign requests to `url` with, if they should be signed.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// The webhook of a wallet, as reported by `webhook`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookInfo {
+    pub url: String,
+    /// Whether requests to `url` are signed.
+    pub signed: bool,
+    /// How many settlements have been delivered.
+    pub delivered: u64,
+    /// How many settlements were dropped after every delivery attempt failed.
+    pub failed: u64,
+    /// Why the last dropped settlement could not be delivered.
+    pub last_error: Option<String>,
+}
+
+/// The body of a request to a webhook, notifying it that a transaction has settled.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettlementEvent {
+    pub hash: String,
+    /// The transaction, whose `status` is its final status.
+    pub transaction: TransactionHistoryEntry,
+}
+
+/// A threshold above which transactions of an asset raise an alert, as reported by `alerts`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertThreshold {
+    pub asset: AssetCode,
+    pub amount: U256,
+}
+
+/// An alert raised by a transaction which moved more of an asset than the wallet's threshold for
+/// it, as streamed by `subscribe/alerts` and delivered to webhooks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LargeTransaction {
+    pub hash: String,
+    pub asset: AssetCode,
+    /// Total amount the transaction transferred to its receivers.
+    pub amount: U256,
+    pub threshold: U256,
+    pub transaction: TransactionHistoryEntry,
+}
+
+/// Whether a conversion moved assets into CAPE or out of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConversionDirection {
+    Wrap,
+    Unwrap,
+}
+
+/// The CAPE side of a conversion.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ConversionReceipt {
+    /// Commitment to the record created by a wrap.
+    Wrap(RecordCommitment),
+    /// Receipt of the burn transaction submitted by an unwrap.
+    Unwrap(TransactionReceipt<CapeLedger>),
+}
+
+/// A wrap or unwrap submitted by this wallet, as reported by `conversions`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conversion {
+    pub direction: ConversionDirection,
+    pub asset: AssetCode,
+    pub amount: U256,
+    /// The CAPE address which received the wrapped record or submitted the burn.
+    pub address: UserAddress,
+    /// The Ethereum address which deposited the ERC-20 tokens or receives the unwrapped tokens.
+    pub eth_address: Address,
+    pub cape_receipt: ConversionReceipt,
+    /// Hash of the Ethereum deposit transaction, for wraps.
+    pub eth_tx_hash: Option<TxHash>,
+    pub status: String,
+}
+
+/// How a record owned by the wallet was created, as reported by `record/:uid/provenance`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordProvenance {
+    pub record: Record,
+    /// Hash of the creating transaction, formatted as in transaction history.
+    pub transaction: Option<String>,
+    /// Kind of the creating transaction, as in transaction history.
+    pub kind: String,
+    /// Index of the block which created the record.
+    pub block_id: u64,
+    /// Index of the creating transaction within its block.
+    pub txn_id: u64,
+    /// Senders of the creating transaction, if known.
+    pub senders: Vec<UserAddress>,
+    /// The Ethereum address which deposited the ERC-20 tokens, if the record was created by a wrap.
+    pub eth_sender: Option<Address>,
+    /// The owner memo of the record, if one has been posted.
+    pub memo: Option<ReceiverMemo>,
+}
+
+/// A reason a record cannot be spent, as reported by `record/:uid/spendable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnspendableReason {
+    /// The record has been frozen by the freezer of its asset.
+    Frozen,
+    /// The record is on hold as an input of a pending transaction.
+    OnHold,
+    /// The record's anonymity set is below the wallet's minimum.
+    BelowMinAnonymity,
+    /// The wallet has not yet scanned the block which commits the record.
+    Unconfirmed,
+    /// The wallet has no Merkle path for the record, so it cannot prove the record exists.
+    MissingMerkleProof,
+}
+
+impl Display for UnspendableReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::Frozen => "the record is frozen",
+            Self::OnHold => "the record is on hold for a pending transaction",
+            Self::BelowMinAnonymity => {
+                "the record's anonymity set is below the wallet's minimum anonymity set"
+            }
+            Self::Unconfirmed => {
+                "the wallet has not yet scanned the block which commits the record"
+            }
+            Self::MissingMerkleProof => "the wallet has no Merkle proof for the record",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Whether a record can be spent, as reported by `record/:uid/spendable`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordSpendability {
+    pub uid: u64,
+    pub spendable: bool,
+    /// The record, if the wallet has scanned it.
+    pub record: Option<Record>,
+    /// The anonymity set of the record, as in `minanonymity`.
+    pub anonymity_set: u64,
+    /// The wallet's minimum anonymity set, or 0 if it has none.
+    pub min_anonymity: u64,
+    /// Every reason the record cannot be spent, empty if it can be.
+    pub reasons: Vec<UnspendableReason>,
+    /// The reasons, explained in one sentence, or `None` if the record can be spent.
+    pub explanation: Option<String>,
+}
+
+impl RecordSpendability {
+    pub fn new(
+        uid: u64,
+        record: Option<Record>,
+        anonymity_set: u64,
+        min_anonymity: u64,
+        reasons: Vec<UnspendableReason>,
+    ) -> Self {
+        let explanation = if reasons.is_empty() {
+            None
+        } else {
+            let reasons = reasons
+                .iter()
+                .map(UnspendableReason::to_string)
+                .collect::<Vec<_>>();
+            Some(format!(
+                "record {} cannot be spent: {}",
+                uid,
+                reasons.join("; ")
+            ))
+        };
+        Self {
+            uid,
+            spendable: reasons.is_empty(),
+            record,
+            anonymity_set,
+            min_anonymity,
+            reasons,
+            explanation,
+        }
+    }
+}
+
+/// A spending limit, as reported by `spendlimit`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendLimitInfo {
+    pub asset: AssetCode,
+    /// Maximum amount which can be spent in any window of `period_secs` seconds.
+    pub limit: U256,
+    pub period_secs: u64,
+    /// Amount spent in the current window.
+    pub spent: U256,
+    /// Amount which can still be spent in the current window.
+    pub remaining: U256,
+}
+
+/// What happened when a scheduled transfer fired.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ScheduledTransferOutcome {
+    /// The transfer was submitted.
+    Sent {
+        receipt: TransactionReceipt<CapeLedger>,
+    },
+    /// The transfer was not attempted, for example because the balance was insufficient.
+    Skipped {
+        reason: String,
+        /// Machine-readable code for `reason`; see [TransferBlocker::code].
+        code: Option<String>,
+    },
+    /// The transfer was attempted but the wallet failed to build or submit it.
+    Failed { msg: String },
+}
+
+/// One firing of a scheduled transfer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledTransferFiring {
+    /// When the transfer fired, in milliseconds since the Unix epoch.
+    pub time_ms: u64,
+    pub outcome: ScheduledTransferOutcome,
+}
+
+/// Whether transaction submission is paused, as reported by `submission`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmissionStatus {
+    pub paused: bool,
+}
+
+/// Which routes this server serves, as reported by `features`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Features {
+    /// The routes which are enabled, in alphabetical order.
+    pub enabled: Vec<String>,
+    /// The routes disabled with `--disable-route`, in alphabetical order.
+    pub disabled: Vec<String>,
+}
+
+/// Whether the open wallet is locked in read-only mode, as reported by `lock` and `unlock`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockStatus {
+    pub locked: bool,
+    /// Whether the lock can be released with a PIN, as well as with the mnemonic.
+    pub pin: bool,
+}
+
+/// A recurring transfer, as reported by `schedules`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledTransfer {
+    pub id: u64,
+    pub asset: AssetCode,
+    pub recipient: UserAddress,
+    pub amount: U256,
+    pub fee: U256,
+    pub interval_secs: u64,
+    /// When the transfer will next fire, in milliseconds since the Unix epoch, or `None` if it has
+    /// been cancelled.
+    pub next_firing_ms: Option<u64>,
+    /// The most recent firings, oldest first.
+    pub firings: Vec<ScheduledTransferFiring>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestStatus {
+    Pending,
+    Paid,
+    Expired,
+}
+
+/// A request for payment, as reported by `paymentrequest`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentRequest {
+    pub id: u64,
+    /// Random hex string identifying the request, which is also the description of its address.
+    pub nonce: String,
+    /// A `cape:` URI giving the payer the address, asset, amount and nonce.
+    pub uri: String,
+    /// The address, used only for this request, at which the payment is to be received.
+    pub address: UserAddress,
+    pub asset: AssetCode,
+    pub amount: U256,
+    /// When the request expires, in milliseconds since the Unix epoch.
+    pub expires_ms: u64,
+    /// The amount received at `address` as of the last time the request was checked.
+    pub received: U256,
+    pub status: PaymentRequestStatus,
+    /// When the request was found to be paid, in milliseconds since the Unix epoch.
+    pub paid_ms: Option<u64>,
+}
+
 /// Solidity types, serialized as JSON in a MetaMask-compatible format.
 pub mod sol {
     use super::*;
@@ -0,0 +1,110 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-wallet alerts for large transactions.
+//!
+//! A wallet can set, for each asset, a threshold above which a transaction of that asset raises a
+//! [LargeTransaction] alert. Alerts are streamed by `subscribe/alerts` and delivered to the
+//! wallet's webhook, if it has one. Thresholds are stored in the `alerts` file in the storage
+//! directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::{AlertThreshold, LargeTransaction, TransactionHistoryEntry};
+use jf_cap::structs::AssetCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Threshold {
+    asset: AssetCode,
+    amount: u128,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Alerts {
+    thresholds: HashMap<PathBuf, Vec<Threshold>>,
+}
+
+impl Persisted for Alerts {}
+
+impl Alerts {
+    /// The thresholds of the wallet at `wallet_path`, in the order they were first set.
+    pub fn list(&self, wallet_path: &Path) -> Vec<AlertThreshold> {
+        self.thresholds
+            .get(wallet_path)
+            .into_iter()
+            .flatten()
+            .map(|threshold| AlertThreshold {
+                asset: threshold.asset,
+                amount: threshold.amount.into(),
+            })
+            .collect()
+    }
+
+    /// Alert on transactions of more than `amount` of `asset` in the wallet at `wallet_path`.
+    ///
+    /// Replaces any existing threshold for `asset`.
+    pub fn set(&mut self, wallet_path: &Path, asset: AssetCode, amount: u128) {
+        let thresholds = self
+            .thresholds
+            .entry(wallet_path.to_path_buf())
+            .or_default();
+        match thresholds
+            .iter_mut()
+            .find(|threshold| threshold.asset == asset)
+        {
+            Some(threshold) => threshold.amount = amount,
+            None => thresholds.push(Threshold { asset, amount }),
+        }
+    }
+
+    /// Stop alerting on transactions of `asset`. Returns whether `asset` had a threshold.
+    pub fn remove(&mut self, wallet_path: &Path, asset: &AssetCode) -> bool {
+        match self.thresholds.get_mut(wallet_path) {
+            Some(thresholds) => {
+                let len = thresholds.len();
+                thresholds.retain(|threshold| threshold.asset != *asset);
+                thresholds.len() < len
+            }
+            None => false,
+        }
+    }
+
+    /// The alert raised by `transaction`, with hash `hash`, in the wallet at `wallet_path`, if any.
+    ///
+    /// A transaction raises an alert if the total amount it transfers to its receivers exceeds the
+    /// wallet's threshold for its asset.
+    pub fn check(
+        &self,
+        wallet_path: &Path,
+        hash: &str,
+        transaction: &TransactionHistoryEntry,
+    ) -> Option<LargeTransaction> {
+        let threshold = self
+            .thresholds
+            .get(wallet_path)?
+            .iter()
+            .find(|threshold| threshold.asset == transaction.asset)?;
+        let amount = transaction
+            .receivers
+            .iter()
+            .filter_map(|(_, amount)| amount.parse::<u128>().ok())
+            .fold(0u128, |total, amount| total.saturating_add(amount));
+        if amount > threshold.amount {
+            Some(LargeTransaction {
+                hash: hash.to_string(),
+                asset: transaction.asset,
+                amount: amount.into(),
+                threshold: threshold.amount.into(),
+                transaction: transaction.clone(),
+            })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Local storage for off-chain asset metadata.
+//!
+//! Metadata is stored in the `asset_metadata` file in the storage directory, separately from any
+//! wallet, so it survives closing and reopening wallets.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::AssetMetadata;
+use jf_cap::structs::AssetCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AssetMetadataStore {
+    metadata: HashMap<AssetCode, AssetMetadata>,
+}
+
+impl Persisted for AssetMetadataStore {}
+
+impl AssetMetadataStore {
+    pub fn get(&self, asset: &AssetCode) -> Option<AssetMetadata> {
+        self.metadata.get(asset).cloned()
+    }
+
+    pub fn set(&mut self, asset: AssetCode, metadata: AssetMetadata) {
+        self.metadata.insert(asset, metadata);
+    }
+}
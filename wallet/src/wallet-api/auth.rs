@@ -0,0 +1,89 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Admin token management.
+//!
+//! The admin token is a bearer token which clients present in an `Authorization` header. The server
+//! only ever stores a hash of the token, in the `admin_token` file in the storage directory, so that
+//! the token cannot be recovered from the server's configuration.
+
+use crate::persisted::write_atomic;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tagged_base64::TaggedBase64;
+
+/// The hash of an admin token, and when the token was created.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminToken {
+    hash: String,
+    created: u64,
+}
+
+impl AdminToken {
+    /// Register `token` as the admin token.
+    pub fn new(token: &str) -> Self {
+        Self {
+            hash: hash(token),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+
+    /// Generate a new random admin token.
+    ///
+    /// Returns the plaintext token, which is not stored anywhere and must be given to the client,
+    /// and its [AdminToken].
+    pub fn generate(rng: &mut impl RngCore) -> (String, Self) {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        let token = TaggedBase64::new("ADMIN", &bytes).unwrap().to_string();
+        let admin_token = Self::new(&token);
+        (token, admin_token)
+    }
+
+    /// Whether `token` is this admin token.
+    pub fn verify(&self, token: &str) -> bool {
+        hash(token) == self.hash
+    }
+
+    /// How long ago this token was created.
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(self.created))
+            .unwrap_or_default()
+    }
+
+    /// Load the admin token stored at `path`, if there is one.
+    ///
+    /// Unlike other [Persisted](crate::persisted::Persisted) state, a corrupt admin token is an
+    /// error, since ignoring it would disable authentication.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Store this admin token at `path`, replacing any previous token.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, &serde_json::to_vec(self)?)
+    }
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha3_256::digest(token.as_bytes()))
+}
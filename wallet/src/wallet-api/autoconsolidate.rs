@@ -0,0 +1,238 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic consolidation of fragmented balances.
+//!
+//! A policy asks for an asset to be consolidated once the wallet holds more than a threshold number
+//! of its records. Policies belong to the wallet they were set for, and are stored in the
+//! `autoconsolidate` file in the storage directory, keyed by wallet path. A background task checks
+//! the policies of the open wallet whenever no request has been made for a while, and merges the
+//! largest records of an asset by transferring them back to their owner with no fee.
+//!
+//! The task never competes with user transfers: it only runs while the server is idle, and it does
+//! nothing while any of the wallet's records are on hold, since that means a transfer is still
+//! pending.
+
+use crate::cache::ResponseCache;
+use crate::idle::Activity;
+use crate::lock::WalletLock;
+use crate::persisted::Persisted;
+use crate::routes::{read_last_path, Wallet};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cap_rust_sandbox::types::GenericInto;
+use cape_wallet::ui::{AutoConsolidatePolicy, ProofParams};
+use jf_cap::structs::{AssetCode, FreezeFlag};
+use net::UserAddress;
+use seahorse::txn_builder::RecordInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// How often the background task checks whether consolidation is needed.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How long the server must go without a request before the background task consolidates.
+const IDLE: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AutoConsolidate {
+    policies: HashMap<PathBuf, Vec<AutoConsolidatePolicy>>,
+}
+
+impl Persisted for AutoConsolidate {}
+
+impl AutoConsolidate {
+    /// The policies of the wallet at `wallet_path`.
+    pub fn list(&self, wallet_path: &Path) -> Vec<AutoConsolidatePolicy> {
+        self.policies.get(wallet_path).cloned().unwrap_or_default()
+    }
+
+    /// Consolidate `asset` in the wallet at `wallet_path` once it has more than `threshold` records.
+    ///
+    /// A threshold of 0 removes the policy for `asset`.
+    pub fn set(&mut self, wallet_path: &Path, asset: AssetCode, threshold: usize) {
+        let policies = self.policies.entry(wallet_path.to_path_buf()).or_default();
+        policies.retain(|policy| policy.asset != asset);
+        if threshold > 0 {
+            policies.push(AutoConsolidatePolicy {
+                asset,
+                threshold,
+                consolidations: 0,
+                last_error: None,
+            });
+        }
+    }
+}
+
+/// Consolidate assets in the open wallet according to its policies while the server is idle.
+///
+/// The task exits once the server which owns `policies` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    policies: Arc<Mutex<AutoConsolidate>>,
+    activity: Arc<Mutex<Activity>>,
+    submission_paused: Arc<Mutex<bool>>,
+    lock: Arc<Mutex<Option<WalletLock>>>,
+    cache: Arc<Mutex<ResponseCache>>,
+) {
+    while Arc::strong_count(&policies) > 1 {
+        sleep(TICK).await;
+
+        // Take the wallet lock first, so that a request in progress finishes (and updates the
+        // activity time) before we decide whether the server is idle.
+        let mut wallet = wallet.lock().await;
+        let wallet = match wallet.as_mut() {
+            Some(wallet) => wallet,
+            None => continue,
+        };
+        if activity.lock().await.idle() < IDLE
+            || *submission_paused.lock().await
+            || lock.lock().await.is_some()
+        {
+            continue;
+        }
+        let wallet_path = match read_last_path(&options).await {
+            Ok(Some(path)) => path,
+            _ => continue,
+        };
+        let mut policies = policies.lock().await;
+        let wallet_policies = match policies.policies.get_mut(&wallet_path) {
+            Some(wallet_policies) if !wallet_policies.is_empty() => wallet_policies,
+            _ => continue,
+        };
+
+        let mut changed = false;
+        for policy in wallet_policies.iter_mut() {
+            let records = wallet.records().await.collect::<Vec<_>>();
+            if records.iter().any(|record| record.hold_until.is_some()) {
+                // A transfer is pending. Leave its records alone until it settles.
+                break;
+            }
+            let count = records
+                .iter()
+                .filter(|record| record.ro.asset_def.code == policy.asset)
+                .count();
+            if count <= policy.threshold {
+                continue;
+            }
+            let (address, amount) = match plan(
+                records,
+                policy.asset,
+                ProofParams::cape().max_transfer_inputs,
+            ) {
+                Some(plan) => plan,
+                None => continue,
+            };
+            changed = true;
+            match wallet
+                .transfer(
+                    Some(&address.clone().into()),
+                    &policy.asset,
+                    &[(address.into(), amount)],
+                    0,
+                )
+                .await
+            {
+                Ok(_) => {
+                    policy.consolidations += 1;
+                    policy.last_error = None;
+                }
+                Err(err) => {
+                    event!(
+                        Level::WARN,
+                        "failed to consolidate {}: {}",
+                        policy.asset,
+                        err
+                    );
+                    policy.last_error = Some(err.to_string());
+                }
+            }
+        }
+        if !changed {
+            continue;
+        }
+        // Consolidating changes the wallet behind the back of the request handlers.
+        cache.lock().await.invalidate();
+        if let Err(err) = policies.save(&options.autoconsolidate_path()) {
+            event!(
+                Level::ERROR,
+                "failed to save consolidation policies: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Choose a transfer which merges records of `asset`.
+///
+/// Returns the address to transfer from and back to, and the amount, or `None` if no address has
+/// enough spendable records to merge. The amount is the total of the largest records at the address
+/// which fit in one transfer alongside the fee record, so a transfer selecting the largest records
+/// first spends exactly those records.
+fn plan(
+    records: impl IntoIterator<Item = RecordInfo>,
+    asset: AssetCode,
+    max_inputs: usize,
+) -> Option<(UserAddress, u128)> {
+    let mut owners: Vec<(UserAddress, Vec<u128>, bool)> = vec![];
+    for record in records {
+        let code = record.ro.asset_def.code;
+        if (code != asset && code != AssetCode::native())
+            || record.ro.freeze_flag != FreezeFlag::Unfrozen
+            || record.hold_until.is_some()
+        {
+            continue;
+        }
+        let address = UserAddress::from(record.ro.pub_key.address());
+        let i = match owners.iter().position(|(owner, _, _)| *owner == address) {
+            Some(i) => i,
+            None => {
+                owners.push((address, vec![], false));
+                owners.len() - 1
+            }
+        };
+        let (_, amounts, has_fee_record) = &mut owners[i];
+        if code == asset {
+            amounts.push(record.ro.amount.generic_into::<u128>());
+        }
+        if code == AssetCode::native() {
+            *has_fee_record = true;
+        }
+    }
+
+    owners
+        .into_iter()
+        .filter_map(|(address, mut amounts, has_fee_record)| {
+            if !has_fee_record {
+                return None;
+            }
+            amounts.sort_unstable();
+            if asset == AssetCode::native() {
+                // The smallest record pays the (zero) fee, so it is not available to merge.
+                amounts.remove(0);
+            }
+            let merged = amounts
+                .iter()
+                .rev()
+                .take(max_inputs - 1)
+                .copied()
+                .collect::<Vec<_>>();
+            if merged.len() < 2 {
+                return None;
+            }
+            Some((address, merged.len(), merged.into_iter().sum::<u128>()))
+        })
+        .max_by_key(|(_, merged, _)| *merged)
+        .map(|(address, _, amount)| (address, amount))
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic retry of the events a wallet skipped.
+//!
+//! If processing an event fails, the wallet carries on scanning without the event's records,
+//! leaving a gap in its scan (see `skippedevents`). With auto-retry enabled, a background task
+//! finds the gaps in the scan of the open wallet and reprocesses the skipped events, until every
+//! gap is closed. Policies belong to the wallet they were set for, and are stored in the
+//! `autoretry` file in the storage directory, keyed by wallet path.
+//!
+//! Finding gaps means replaying the event log, and gaps only appear when the wallet processes new
+//! events, so the task only looks for them after the wallet's sync time advances, or while the
+//! last retry has failed.
+
+use crate::cache::ResponseCache;
+use crate::persisted::Persisted;
+use crate::routes::{read_last_path, Wallet};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cape_wallet::{ui::ScanGaps, wallet::CapeWalletExt};
+use seahorse::events::EventSource;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// How often the background task checks for gaps.
+const TICK: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Policy {
+    retries: u64,
+    closed: u64,
+    last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AutoRetry {
+    /// The policy of each wallet with auto-retry enabled.
+    policies: HashMap<PathBuf, Policy>,
+}
+
+impl Persisted for AutoRetry {}
+
+impl AutoRetry {
+    /// The policy of the wallet at `wallet_path`, without its gaps.
+    pub fn get(&self, wallet_path: &Path) -> ScanGaps {
+        match self.policies.get(wallet_path) {
+            Some(policy) => ScanGaps {
+                gaps: vec![],
+                auto_retry: true,
+                retries: policy.retries,
+                closed: policy.closed,
+                last_error: policy.last_error.clone(),
+            },
+            None => ScanGaps::default(),
+        }
+    }
+
+    /// Enable or disable auto-retry for the wallet at `wallet_path`.
+    ///
+    /// Enabling auto-retry for a wallet which already has it enabled keeps its counts.
+    pub fn set(&mut self, wallet_path: &Path, enabled: bool) {
+        if enabled {
+            self.policies.entry(wallet_path.to_path_buf()).or_default();
+        } else {
+            self.policies.remove(wallet_path);
+        }
+    }
+}
+
+/// Reprocess the events the open wallet skipped, if its policy says so.
+///
+/// The task exits once the server which owns `policies` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    policies: Arc<Mutex<AutoRetry>>,
+    cache: Arc<Mutex<ResponseCache>>,
+) {
+    // The wallet and sync time at which no gaps were left, so there is nothing to do until the
+    // wallet processes more events.
+    let mut checked: Option<(PathBuf, usize)> = None;
+    while Arc::strong_count(&policies) > 1 {
+        sleep(TICK).await;
+
+        let mut wallet = wallet.lock().await;
+        let wallet = match wallet.as_mut() {
+            Some(wallet) => wallet,
+            None => continue,
+        };
+        let wallet_path = match read_last_path(&options).await {
+            Ok(Some(path)) => path,
+            _ => continue,
+        };
+        let now = wallet.now().await.index(EventSource::QueryService);
+        let mut policies = policies.lock().await;
+        let policy = match policies.policies.get_mut(&wallet_path) {
+            Some(policy) => policy,
+            None => continue,
+        };
+        if checked.as_ref() == Some(&(wallet_path.clone(), now)) {
+            continue;
+        }
+
+        match wallet.skipped_events().await {
+            Ok(skipped) if skipped.is_empty() => {
+                checked = Some((wallet_path, now));
+                continue;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                event!(Level::WARN, "failed to look for skipped events: {}", err);
+                continue;
+            }
+        }
+        policy.retries += 1;
+        match wallet.replay_skipped_events().await {
+            Ok(replayed) => {
+                policy.closed += replayed.len() as u64;
+                policy.last_error = None;
+                checked = Some((wallet_path, now));
+            }
+            Err(err) => {
+                event!(Level::WARN, "failed to reprocess skipped events: {}", err);
+                policy.last_error = Some(err.to_string());
+            }
+        }
+        // Reprocessing events changes the wallet behind the back of the request handlers.
+        cache.lock().await.invalidate();
+        if let Err(err) = policies.save(&options.autoretry_path()) {
+            event!(Level::ERROR, "failed to save auto-retry policies: {}", err);
+        }
+    }
+}
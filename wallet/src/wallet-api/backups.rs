@@ -0,0 +1,286 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic backups of wallet metadata.
+//!
+//! A backup is the bundle exported by `exportmetadata`, encrypted with a key derived from the
+//! wallet's mnemonic, so a backup file never contains plaintext metadata, and the bundle itself
+//! contains no keys or mint info. Backup policies name the directory each wallet is backed up to,
+//! and are stored in the `backups` file in the storage directory, keyed by wallet path. A background
+//! task backs up the open wallet whenever its interval elapses.
+//!
+//! Backup files are named after the wallet's seed fingerprint and the time they were written, so
+//! several wallets can share a directory. Each file is written under a temporary name and then
+//! renamed, so a backup is never seen half written.
+
+use crate::asset_metadata::AssetMetadataStore;
+use crate::contacts::Contacts;
+use crate::persisted::Persisted;
+use crate::routes::{metadata_bundle, read_last_path, Wallet};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cape_wallet::{
+    ui::{BackupInfo, BackupPolicy, MetadataBundle},
+    wallet::CapeWalletExt,
+};
+use seahorse::encryption::CipherText;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{event, Level};
+
+/// How often the background task checks for backups which are due.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How many backups to keep if the policy does not say.
+pub const DEFAULT_KEEP: usize = 5;
+
+/// The version of the backup file format.
+const BACKUP_VERSION: u32 = 1;
+
+/// The contents of a backup file.
+#[derive(Serialize, Deserialize)]
+struct BackupFile {
+    version: u32,
+    /// The bundle, serialized as JSON and encrypted.
+    ciphertext: CipherText,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Backups {
+    policies: HashMap<PathBuf, BackupPolicy>,
+    /// When each wallet was last backed up or failed to be, so a failing backup is retried once per
+    /// interval rather than on every tick.
+    #[serde(skip)]
+    attempts: HashMap<PathBuf, u64>,
+}
+
+impl Persisted for Backups {}
+
+impl Backups {
+    /// The policy of the wallet at `wallet_path`, if it has one.
+    pub fn get(&self, wallet_path: &Path) -> Option<&BackupPolicy> {
+        self.policies.get(wallet_path)
+    }
+
+    /// Back up the wallet at `wallet_path` to `dir` every `interval_secs` seconds, keeping the newest
+    /// `keep` backups.
+    ///
+    /// Replaces any existing policy for the wallet. An interval of 0 disables scheduled backups, but
+    /// still sets the directory for `backup/now`.
+    pub fn set(
+        &mut self,
+        wallet_path: &Path,
+        dir: PathBuf,
+        interval_secs: u64,
+        keep: usize,
+    ) -> BackupPolicy {
+        let last_backup = self
+            .policies
+            .get(wallet_path)
+            .and_then(|policy| policy.last_backup);
+        let policy = BackupPolicy {
+            dir,
+            interval_secs,
+            keep,
+            last_backup,
+            last_error: None,
+        };
+        self.policies
+            .insert(wallet_path.to_path_buf(), policy.clone());
+        policy
+    }
+
+    /// Record the outcome of backing up the wallet at `wallet_path` at time `now`.
+    pub fn backed_up(&mut self, wallet_path: &Path, now: u64, result: Result<u64, String>) {
+        self.attempts.insert(wallet_path.to_path_buf(), now);
+        if let Some(policy) = self.policies.get_mut(wallet_path) {
+            match result {
+                Ok(created) => {
+                    policy.last_backup = Some(created);
+                    policy.last_error = None;
+                }
+                Err(err) => policy.last_error = Some(err),
+            }
+        }
+    }
+
+    /// The policy of the wallet at `wallet_path`, if a scheduled backup of it is due at time `now`.
+    fn due(&self, wallet_path: &Path, now: u64) -> Option<BackupPolicy> {
+        let policy = self.policies.get(wallet_path)?;
+        if policy.interval_secs == 0 {
+            return None;
+        }
+        let last = policy
+            .last_backup
+            .max(self.attempts.get(wallet_path).copied());
+        match last {
+            Some(last) if now < last.saturating_add(policy.interval_secs.saturating_mul(1000)) => {
+                None
+            }
+            _ => Some(policy.clone()),
+        }
+    }
+}
+
+/// The prefix of the names of backups of the wallet with seed fingerprint `fingerprint`.
+fn prefix(fingerprint: &str) -> String {
+    format!("cape-{}-", &fingerprint[..fingerprint.len().min(16)])
+}
+
+/// The backups of the wallet with seed fingerprint `fingerprint` in `dir`, oldest first.
+pub fn list(dir: &Path, fingerprint: &str) -> io::Result<Vec<BackupInfo>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let prefix = prefix(fingerprint);
+    let mut backups = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let created = match name
+            .strip_prefix(&prefix)
+            .and_then(|name| name.strip_suffix(".backup"))
+            .and_then(|created| created.parse().ok())
+        {
+            Some(created) => created,
+            None => continue,
+        };
+        backups.push(BackupInfo {
+            name,
+            created,
+            size: entry.metadata()?.len(),
+        });
+    }
+    backups.sort_by_key(|backup| backup.created);
+    Ok(backups)
+}
+
+/// Back up `bundle`, the metadata of `wallet`, to `dir`, keeping only the newest `keep` backups.
+pub async fn backup(
+    wallet: &Wallet,
+    bundle: &MetadataBundle,
+    dir: &Path,
+    keep: usize,
+) -> io::Result<BackupInfo> {
+    let ciphertext = wallet
+        .encrypt_backup(&serde_json::to_vec(bundle)?)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let bytes = serde_json::to_vec(&BackupFile {
+        version: BACKUP_VERSION,
+        ciphertext,
+    })?;
+
+    fs::create_dir_all(dir)?;
+    let fingerprint = wallet.seed_fingerprint().await;
+    let existing = list(dir, &fingerprint)?;
+    // Backup names must be unique, even if two backups are made in the same millisecond.
+    let created = match existing.last() {
+        Some(last) => now().max(last.created + 1),
+        None => now(),
+    };
+    let name = format!("{}{:020}.backup", prefix(&fingerprint), created);
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(&name))?;
+
+    // Rotate out the oldest backups.
+    let backups = list(dir, &fingerprint)?;
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_file(dir.join(&old.name))?;
+    }
+    Ok(BackupInfo {
+        name,
+        created,
+        size: bytes.len() as u64,
+    })
+}
+
+/// Read the backup named `name` from `dir`.
+///
+/// `name` must be one of the backups of `wallet` listed by [list]. Fails if the backup was not
+/// written by a wallet with the same mnemonic as `wallet`.
+pub async fn read(wallet: &Wallet, dir: &Path, name: &str) -> io::Result<MetadataBundle> {
+    let file: BackupFile = serde_json::from_slice(&fs::read(dir.join(name))?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if file.version != BACKUP_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported backup version {}", file.version),
+        ));
+    }
+    let plaintext = wallet
+        .decrypt_backup(&file.ciphertext)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    serde_json::from_slice(&plaintext)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Back up the open wallet whenever its policy's interval elapses.
+///
+/// The task exits once the server which owns `backups` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    backups: Arc<Mutex<Backups>>,
+    contacts: Arc<Mutex<Contacts>>,
+    asset_metadata: Arc<Mutex<AssetMetadataStore>>,
+) {
+    while Arc::strong_count(&backups) > 1 {
+        sleep(TICK).await;
+
+        let wallet = wallet.lock().await;
+        let wallet = match wallet.as_ref() {
+            Some(wallet) => wallet,
+            None => continue,
+        };
+        let wallet_path = match read_last_path(&options).await {
+            Ok(Some(path)) => path,
+            _ => continue,
+        };
+        let mut backups = backups.lock().await;
+        let now = now();
+        let policy = match backups.due(&wallet_path, now) {
+            Some(policy) => policy,
+            None => continue,
+        };
+        let bundle = metadata_bundle(&contacts, &asset_metadata, wallet).await;
+        let result = backup(wallet, &bundle, &policy.dir, policy.keep)
+            .await
+            .map(|backup| backup.created)
+            .map_err(|err| {
+                event!(Level::ERROR, "failed to back up wallet metadata: {}", err);
+                err.to_string()
+            });
+        backups.backed_up(&wallet_path, now, result);
+        if let Err(err) = backups.save(&options.backups_path()) {
+            event!(Level::ERROR, "failed to save backup policies: {}", err);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
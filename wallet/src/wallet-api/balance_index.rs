@@ -0,0 +1,209 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Index of the balances of the open wallet.
+//!
+//! The wallet computes the balance of an account by summing its records, so answering
+//! `getbalance/all` by asking the wallet for each account and asset took time proportional to the
+//! number of records times the number of accounts and assets, all while holding the wallet lock.
+//! The index keeps the total of the unfrozen records of each account and asset, along with the
+//! records that make up the totals, so a balance is a map lookup.
+//!
+//! The index is brought up to date before it is used whenever the wallet has processed a ledger
+//! event or the response cache has been invalidated (meaning a request or background task may have
+//! modified the wallet) since the last update. An update adds the records which appeared since the
+//! last update and subtracts those which disappeared. The index is rebuilt from scratch when a
+//! different wallet is opened, and when `fsck/repair` finds it inconsistent with the wallet.
+//!
+//! A record with the same commitment as a record already counted is a sign of a bug or an attack
+//! in the ledger, which has presented the same record twice. Such a record is not counted, but is
+//! logged and kept as a [ReplayedRecord] until it disappears from the wallet.
+
+use crate::routes::Wallet;
+use cap_rust_sandbox::types::GenericInto;
+use cape_wallet::ui::{BalanceIndexStats, MemUsage, ReplayedRecord};
+use ethers::prelude::U256;
+use jf_cap::structs::{AssetCode, FreezeFlag, RecordCommitment};
+use net::UserAddress;
+use seahorse::events::EventSource;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+type Account = (UserAddress, AssetCode);
+
+/// The state of the wallet an index is up to date with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Version {
+    wallet_path: PathBuf,
+    event_index: usize,
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct BalanceIndex {
+    version: Option<Version>,
+    /// The account, amount and commitment of each record counted in `balances`, by uid.
+    records: HashMap<u64, (Account, U256, RecordCommitment)>,
+    /// The uid of the record counted with each commitment.
+    commitments: HashMap<RecordCommitment, u64>,
+    /// Records which were not counted because they have the same commitment as one which was.
+    replayed: BTreeMap<u64, ReplayedRecord>,
+    balances: HashMap<Account, U256>,
+    updates: u64,
+    rebuilds: u64,
+}
+
+impl BalanceIndex {
+    /// Bring the index up to date with `wallet`, which was opened from `wallet_path`.
+    ///
+    /// `generation` is the current generation of the response cache.
+    pub async fn update(&mut self, wallet: &Wallet, wallet_path: &Path, generation: u64) {
+        let version = Version {
+            wallet_path: wallet_path.to_path_buf(),
+            event_index: wallet.now().await.index(EventSource::QueryService),
+            generation,
+        };
+        match &self.version {
+            Some(current) if *current == version => return,
+            Some(current) if current.wallet_path == version.wallet_path => {}
+            _ => {
+                self.records.clear();
+                self.commitments.clear();
+                self.replayed.clear();
+                self.balances.clear();
+                self.rebuilds += 1;
+            }
+        }
+
+        let records = wallet
+            .records()
+            .await
+            .filter(|record| record.ro.freeze_flag == FreezeFlag::Unfrozen)
+            .map(|record| (record.uid, record))
+            .collect::<HashMap<_, _>>();
+        let removed = self
+            .records
+            .keys()
+            .filter(|uid| !records.contains_key(uid))
+            .copied()
+            .collect::<Vec<_>>();
+        for uid in removed {
+            let (account, amount, commitment) = self.records.remove(&uid).unwrap();
+            self.commitments.remove(&commitment);
+            if let Entry::Occupied(mut balance) = self.balances.entry(account) {
+                *balance.get_mut() -= amount;
+                if balance.get().is_zero() {
+                    balance.remove();
+                }
+            }
+        }
+        self.replayed.retain(|uid, _| records.contains_key(uid));
+
+        // Add new records in order of uid, so that if the ledger presents a record twice, the
+        // first copy is the one which is counted.
+        let mut added = records
+            .into_iter()
+            .filter(|(uid, _)| !self.records.contains_key(uid) && !self.replayed.contains_key(uid))
+            .collect::<Vec<_>>();
+        added.sort_by_key(|(uid, _)| *uid);
+        for (uid, record) in added {
+            let account = (
+                UserAddress::from(record.ro.pub_key.address()),
+                record.ro.asset_def.code,
+            );
+            let amount = U256::from(record.ro.amount.generic_into::<u128>());
+            let commitment = RecordCommitment::from(&record.ro);
+            match self.commitments.entry(commitment) {
+                Entry::Occupied(original) => {
+                    event!(
+                        Level::WARN,
+                        "record {} has the same commitment as record {}, which the wallet already \
+                         holds; it will not be counted in balances",
+                        uid,
+                        original.get()
+                    );
+                    self.replayed.insert(
+                        uid,
+                        ReplayedRecord {
+                            uid,
+                            original_uid: *original.get(),
+                            commitment,
+                            address: account.0,
+                            asset: account.1,
+                            amount,
+                        },
+                    );
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(uid);
+                    *self.balances.entry(account.clone()).or_default() += amount;
+                    self.records.insert(uid, (account, amount, commitment));
+                }
+            }
+        }
+
+        self.version = Some(version);
+        self.updates += 1;
+    }
+
+    /// The balance of `asset` owned by `address`, as of the last update.
+    pub fn balance(&self, address: &UserAddress, asset: &AssetCode) -> U256 {
+        self.balances
+            .get(&(address.clone(), *asset))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Records which were not counted because the wallet already held a record with the same
+    /// commitment, in order of uid, as of the last update.
+    pub fn replayed(&self) -> Vec<ReplayedRecord> {
+        self.replayed.values().cloned().collect()
+    }
+
+    /// The total amount of `asset` owned by `address` in records which were not counted because
+    /// they were replayed, as of the last update.
+    pub fn replayed_balance(&self, address: &UserAddress, asset: &AssetCode) -> U256 {
+        self.replayed
+            .values()
+            .filter(|record| record.address == *address && record.asset == *asset)
+            .fold(U256::zero(), |total, record| total + record.amount)
+    }
+
+    /// The non-zero balances of each account and asset, as of the last update.
+    pub fn balances(&self) -> &HashMap<Account, U256> {
+        &self.balances
+    }
+
+    /// Discard the index, so that it is rebuilt from scratch by the next update.
+    pub fn reset(&mut self) {
+        self.version = None;
+    }
+
+    /// The approximate memory used by the index, counting each indexed record as an entry.
+    pub fn memory(&self) -> MemUsage {
+        MemUsage {
+            count: self.records.len(),
+            bytes: self.records.len() * size_of::<(u64, (Account, U256, RecordCommitment))>()
+                + self.commitments.len() * size_of::<(RecordCommitment, u64)>()
+                + self.replayed.len() * size_of::<(u64, ReplayedRecord)>()
+                + self.balances.len() * size_of::<(Account, U256)>(),
+        }
+    }
+
+    pub fn stats(&self) -> BalanceIndexStats {
+        BalanceIndexStats {
+            records: self.records.len(),
+            accounts: self.balances.len(),
+            event_index: self.version.as_ref().map(|version| version.event_index),
+            updates: self.updates,
+            rebuilds: self.rebuilds,
+        }
+    }
+}
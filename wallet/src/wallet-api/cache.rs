@@ -0,0 +1,239 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Cache of responses to read-only routes.
+//!
+//! Clients tend to poll the same read-only routes (`getinfo`, `getbalance`, ...) many times between
+//! changes to the wallet, and some of these routes, especially with `asof`, are expensive. Responses
+//! to these routes are cached under a key made of the request URL, the event index the open wallet
+//! has processed, and a generation which is bumped by every request that might modify the wallet.
+//! Either a new ledger event or a modifying request therefore makes old entries unreachable, and
+//! they are dropped the next time a response is cached. The `cache` route lets operators inspect the
+//! cache and clear it when debugging reports of stale data.
+//!
+//! Within one generation and event index, a server answering many different URLs can accumulate a
+//! large cache. With a budget (`--cache-budget`), caching a response evicts the least recently used
+//! responses until the total size of the cache is within the budget. Cached responses are only ever
+//! derived from the wallet, so evicting one just means the next request for it is computed again.
+
+use cape_wallet::ui::{CacheEntryInfo, CacheStats};
+use std::collections::HashMap;
+use tide::http::{Method, Mime};
+
+/// Routes whose responses are cached.
+///
+/// Each of these is a function of the state of the open wallet, and does not modify it.
+const CACHED_ROUTES: &[&str] = &[
+    "getaccount",
+    "getaccounts",
+    "getbalance",
+    "getinfo",
+    "getrecords",
+    "frontier",
+    "transactionhistory",
+];
+
+/// Whether a `method` request to the route matching `pattern` can be answered from the cache.
+pub fn cacheable(method: Method, pattern: &str) -> bool {
+    let route = pattern.split('/').next().unwrap_or(pattern);
+    method == Method::Get && CACHED_ROUTES.contains(&route)
+}
+
+/// Whether a `method` request to the route matching `pattern` might modify the wallet.
+pub fn modifies_wallet(method: Method, pattern: &str) -> bool {
+    // Routes which modify the wallet use POST, except for `fsck/repair`, which shares its route
+    // (and so its method) with the read-only `fsck`, and a few routes whose API requires GET.
+    // `batch` uses POST to take a body, but only reads.
+    (method != Method::Get && pattern != "batch")
+        || matches!(
+            pattern,
+            "fsck/repair"
+                | "failed/:receipt/resend"
+                | "quarantine/:uid/release"
+                | "quarantine/:uid/reject"
+                | "skippedevents/replay"
+                | "stealthaddress"
+                | "transactions/prune/before/:block"
+        )
+}
+
+/// The identity of a cached response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// The path and query of the request.
+    pub url: String,
+    /// The `Accept` header of the request, which determines the format of the response.
+    pub accept: Option<String>,
+    /// The number of events processed by the open wallet.
+    pub event_index: usize,
+}
+
+/// A cached response body.
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub content_type: Option<Mime>,
+    pub body: Vec<u8>,
+}
+
+struct Entry {
+    generation: u64,
+    response: CachedResponse,
+    hits: u64,
+    /// The value of [ResponseCache::clock] when the entry was last inserted or hit.
+    last_used: u64,
+}
+
+/// The response cache, with hit and miss counts.
+#[derive(Default)]
+pub struct ResponseCache {
+    generation: u64,
+    entries: HashMap<CacheKey, Entry>,
+    /// The most bytes of responses to keep, if limited.
+    budget: Option<usize>,
+    /// Counts cache accesses, to order entries by recency of use.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl ResponseCache {
+    /// An empty cache which keeps at most `budget` bytes of responses, if given.
+    pub fn with_budget(budget: Option<usize>) -> Self {
+        Self {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    /// Look up a response, counting a hit or a miss.
+    pub fn get(&mut self, key: &CacheKey) -> Option<CachedResponse> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.generation == self.generation => {
+                entry.hits += 1;
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.response.clone())
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// The current generation, which should be read before computing a response to cache.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Cache a response computed in `generation`, dropping any entries which can no longer be hit.
+    ///
+    /// If the cache has been invalidated since `generation`, the wallet may have changed while the
+    /// response was being computed, so it is not cached. If the cache has a budget, the least
+    /// recently used entries are evicted to make room for the response, and a response larger than
+    /// the whole budget is not cached.
+    pub fn insert(&mut self, key: CacheKey, generation: u64, response: CachedResponse) {
+        if generation != self.generation {
+            return;
+        }
+        if matches!(self.budget, Some(budget) if response.body.len() > budget) {
+            return;
+        }
+        let event_index = key.event_index;
+        self.entries
+            .retain(|key, entry| entry.generation == generation && key.event_index == event_index);
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                generation,
+                response,
+                hits: 0,
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+    }
+
+    /// Evict the least recently used entries until the cache is within its budget.
+    fn evict(&mut self) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let mut bytes = self.bytes();
+        if bytes <= budget {
+            return;
+        }
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(last_used, _)| *last_used);
+        for (_, key) in entries {
+            if bytes <= budget {
+                break;
+            }
+            let entry = self.entries.remove(&key).unwrap();
+            bytes -= entry.response.body.len();
+            self.evictions += 1;
+        }
+    }
+
+    /// The total size of the cached responses, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.entries
+            .values()
+            .map(|entry| entry.response.body.len())
+            .sum()
+    }
+
+    /// The most bytes of responses the cache keeps, if limited.
+    pub fn budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    /// Make all cached responses unreachable, because the wallet may have changed.
+    pub fn invalidate(&mut self) {
+        self.generation += 1;
+    }
+
+    /// Remove all entries and reset the hit, miss and eviction counts.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+        self.misses = 0;
+        self.evictions = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.generation == self.generation)
+            .map(|(key, entry)| CacheEntryInfo {
+                url: key.url.clone(),
+                event_index: key.event_index,
+                bytes: entry.response.body.len(),
+                hits: entry.hits,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| (&a.url, a.event_index).cmp(&(&b.url, b.event_index)));
+        CacheStats {
+            size: entries.len(),
+            bytes: entries.iter().map(|entry| entry.bytes).sum(),
+            hits: self.hits,
+            misses: self.misses,
+            budget: self.budget,
+            evictions: self.evictions,
+            entries,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! User-assigned transaction categories, for reporting.
+//!
+//! Categories are local metadata: they are never sent to the network. They belong to the wallet
+//! they were assigned in, and are stored in the `categories` file in the storage directory, keyed
+//! by wallet path and then by transaction hash.
+
+use crate::persisted::Persisted;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Categories {
+    categories: HashMap<PathBuf, HashMap<String, String>>,
+}
+
+impl Persisted for Categories {}
+
+impl Categories {
+    /// The category of the transaction with hash `hash` in the wallet at `wallet_path`, if any.
+    pub fn get(&self, wallet_path: &Path, hash: &str) -> Option<&str> {
+        self.categories
+            .get(wallet_path)
+            .and_then(|categories| categories.get(hash))
+            .map(String::as_str)
+    }
+
+    /// Assign `category` to the transaction with hash `hash` in the wallet at `wallet_path`,
+    /// replacing any previous category.
+    pub fn set(&mut self, wallet_path: &Path, hash: String, category: String) {
+        self.categories
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .insert(hash, category);
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The relationship between wall-clock time and ledger height, as observed by `clock`.
+//!
+//! Each call to `clock` records the current time and the ledger's block height. The rate at which
+//! the ledger grows is estimated from the oldest and newest of the most recent observations. The
+//! observations are only kept in memory.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of observations the block rate is estimated from.
+const MAX_OBSERVATIONS: usize = 32;
+
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    /// `(milliseconds since the Unix epoch, block height)`, oldest first.
+    observations: VecDeque<(u64, u64)>,
+}
+
+impl Clock {
+    /// Record that the block height is `height` now, returning the current time in milliseconds
+    /// since the Unix epoch.
+    pub fn observe(&mut self, height: u64) -> u64 {
+        let now = now();
+        if self.observations.len() == MAX_OBSERVATIONS {
+            self.observations.pop_front();
+        }
+        self.observations.push_back((now, height));
+        now
+    }
+
+    /// The observed rate at which blocks are committed to the ledger.
+    ///
+    /// Returns `None` until there are two observations at different times.
+    pub fn blocks_per_second(&self) -> Option<f64> {
+        let (first_time, first_block) = self.observations.front()?;
+        let (last_time, last_block) = self.observations.back()?;
+        if last_time <= first_time {
+            return None;
+        }
+        Some(
+            last_block.saturating_sub(*first_block) as f64 * 1000.
+                / (last_time - first_time) as f64,
+        )
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,38 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-wallet record selection strategies.
+//!
+//! Strategies are stored in the `coin_selection` file in the storage directory, keyed by wallet
+//! path. Wallets without a configured strategy spend their largest records first.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::SelectionStrategy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CoinSelection {
+    strategies: HashMap<PathBuf, SelectionStrategy>,
+}
+
+impl Persisted for CoinSelection {}
+
+impl CoinSelection {
+    /// The strategy of the wallet at `wallet_path`.
+    pub fn get(&self, wallet_path: &Path) -> SelectionStrategy {
+        self.strategies
+            .get(wallet_path)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set(&mut self, wallet_path: &Path, strategy: SelectionStrategy) {
+        self.strategies.insert(wallet_path.to_path_buf(), strategy);
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The number of confirmations a transaction needs before it is reported as final.
+//!
+//! A transaction in the wallet's history is `accepted` as soon as it is included in the ledger.
+//! Integrations with a lower tolerance for reorganizations can configure a confirmation depth for a
+//! wallet, after which an included transaction is reported as `pending` by `transactionhistory`,
+//! and its settlement is withheld from the wallet's webhook, until it has that many confirmations.
+//!
+//! Confirmations are counted in ledger events, like the wallet's sync time. A transaction has one
+//! confirmation at the event index where the server first observes it included, and one more for
+//! each event the wallet processes after that. Inclusion is observed whenever the history is read,
+//! so the count may lag behind the true depth, but never exceeds it. Transactions which were already
+//! included when a depth was first configured for a wallet are final. Depths and observations are
+//! stored in the `confirmations` file in the storage directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use crate::routes::Wallet;
+use crate::web::NodeOpt;
+use async_std::sync::Mutex;
+use cape_wallet::ui::TransactionHistoryEntry;
+use seahorse::events::EventSource;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The confirmation depth of a wallet which has not configured one: final as soon as included.
+pub const DEFAULT_DEPTH: usize = 1;
+
+/// The status of a transaction which is included in the ledger.
+const ACCEPTED: &str = "accepted";
+
+/// The status reported for an included transaction without enough confirmations.
+pub const PENDING: &str = "pending";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Depth {
+    depth: usize,
+    /// The event index where each included transaction was first observed, by hash.
+    included: HashMap<String, usize>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Confirmations {
+    depths: HashMap<PathBuf, Depth>,
+}
+
+impl Persisted for Confirmations {}
+
+impl Confirmations {
+    /// The confirmation depth of the wallet at `wallet_path`.
+    pub fn depth(&self, wallet_path: &Path) -> usize {
+        self.depths
+            .get(wallet_path)
+            .map(|depth| depth.depth)
+            .unwrap_or(DEFAULT_DEPTH)
+    }
+
+    /// Set the confirmation depth of the wallet at `wallet_path`.
+    ///
+    /// `included` are the hashes of the transactions in the wallet's history which are currently
+    /// included. If the wallet did not have a depth yet, they are final regardless of `depth`.
+    pub fn set_depth(
+        &mut self,
+        wallet_path: &Path,
+        depth: usize,
+        included: impl IntoIterator<Item = String>,
+    ) {
+        self.depths
+            .entry(wallet_path.to_path_buf())
+            .or_insert_with(|| Depth {
+                depth,
+                included: included.into_iter().map(|hash| (hash, 0)).collect(),
+            })
+            .depth = depth;
+    }
+
+    /// Report each transaction in `history` which is included but does not have enough
+    /// confirmations as pending.
+    ///
+    /// `history` belongs to the wallet at `wallet_path`, whose current event index is `now`.
+    /// Returns whether any transaction was observed included for the first time.
+    pub fn apply(
+        &mut self,
+        wallet_path: &Path,
+        now: usize,
+        history: &mut [TransactionHistoryEntry],
+    ) -> bool {
+        let depth = match self.depths.get_mut(wallet_path) {
+            Some(depth) => depth,
+            None => return false,
+        };
+        let mut observed = false;
+        for entry in history {
+            let hash = match &entry.hash {
+                Some(hash) if entry.status == ACCEPTED => hash,
+                _ => continue,
+            };
+            let included = *depth.included.entry(hash.clone()).or_insert_with(|| {
+                observed = true;
+                now
+            });
+            if now.saturating_sub(included) + 1 < depth.depth {
+                entry.status = String::from(PENDING);
+            }
+        }
+        observed
+    }
+}
+
+/// Apply the confirmation depth of the open wallet, at `wallet_path`, to entries of its `history`.
+///
+/// See [Confirmations::apply]. New observations are saved.
+pub async fn apply(
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
+    wallet: &Wallet,
+    wallet_path: &Path,
+    history: &mut [TransactionHistoryEntry],
+) -> io::Result<()> {
+    let now = wallet.now().await.index(EventSource::QueryService);
+    let mut confirmations = confirmations.lock().await;
+    if confirmations.apply(wallet_path, now, history) {
+        confirmations.save(&options.confirmations_path())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Address book of named contacts.
+//!
+//! The address book belongs to the server rather than to a particular wallet, and is stored in the
+//! `contacts` file in the storage directory.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::{Contact, ContactEntry, ContactImportResult, ContactImportStatus};
+use net::{TaggedBlob, UserAddress};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tagged_base64::TaggedBase64;
+
+/// What to do when importing a contact whose name is already in the address book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the existing contact.
+    Skip,
+    /// Replace the existing contact.
+    Overwrite,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Contacts {
+    contacts: BTreeMap<String, UserAddress>,
+}
+
+impl Persisted for Contacts {}
+
+impl Contacts {
+    /// All contacts, sorted by name.
+    pub fn list(&self) -> Vec<Contact> {
+        self.contacts
+            .iter()
+            .map(|(name, address)| Contact {
+                name: name.clone(),
+                address: address.clone(),
+            })
+            .collect()
+    }
+
+    /// Add each of `entries` to the address book.
+    ///
+    /// Every entry is attempted, even if some are invalid, and the outcome of each entry is
+    /// reported in the same order as `entries`.
+    pub fn import(
+        &mut self,
+        entries: Vec<ContactEntry>,
+        policy: DuplicatePolicy,
+    ) -> Vec<ContactImportResult> {
+        entries
+            .into_iter()
+            .map(|entry| {
+                let status = match parse_entry(&entry) {
+                    Ok(address) => match (self.contacts.contains_key(&entry.name), policy) {
+                        (false, _) => {
+                            self.contacts.insert(entry.name.clone(), address);
+                            ContactImportStatus::Added
+                        }
+                        (true, DuplicatePolicy::Overwrite) => {
+                            self.contacts.insert(entry.name.clone(), address);
+                            ContactImportStatus::Overwritten
+                        }
+                        (true, DuplicatePolicy::Skip) => ContactImportStatus::Skipped,
+                    },
+                    Err(msg) => ContactImportStatus::Invalid { msg },
+                };
+                ContactImportResult {
+                    name: entry.name,
+                    status,
+                }
+            })
+            .collect()
+    }
+}
+
+fn parse_entry(entry: &ContactEntry) -> Result<UserAddress, String> {
+    if entry.name.is_empty() {
+        return Err(String::from("contact name must not be empty"));
+    }
+    let tb64 = TaggedBase64::parse(&entry.address).map_err(|err| err.to_string())?;
+    UserAddress::from_tagged_blob(&tb64).map_err(|err| err.to_string())
+}
@@ -0,0 +1,33 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Local history of wraps and unwraps.
+//!
+//! Conversions are recorded in the `conversions` file in the storage directory when they are
+//! submitted through this server, since neither the wallet's transaction history nor the ledger
+//! links the CAPE side of a conversion to the Ethereum side.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::Conversion;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Conversions {
+    conversions: Vec<Conversion>,
+}
+
+impl Persisted for Conversions {}
+
+impl Conversions {
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Conversion> {
+        self.conversions.iter_mut()
+    }
+
+    pub fn push(&mut self, conversion: Conversion) {
+        self.conversions.push(conversion);
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Verbose error responses.
+//!
+//! By default, an error response contains just the [CapeAPIError], as added by
+//! [net::server::add_error_body]. The [error_detail] middleware replaces it with a [VerboseError]
+//! when the `verbose` query parameter is `true`, or when the parameter is absent and the server's
+//! [ErrorDetail] level is verbose. It must run inside `add_error_body`, so that it sees the error
+//! before `add_error_body` turns it into a terse body.
+
+use crate::routes::{server_error, CapeAPIError};
+use crate::web::{explain_route, WebState};
+use cape_wallet::ui::{ErrorDetail, VerboseError};
+use std::future::Future;
+use std::pin::Pin;
+use tide::{Body, Next, Request, Response};
+
+/// Whether the error response to `req` should be verbose.
+///
+/// This is controlled by the `verbose` query parameter, which defaults to the server's
+/// [ErrorDetail] level.
+async fn verbose(req: &Request<WebState>) -> Result<bool, tide::Error> {
+    match req.url().query_pairs().find(|(key, _)| key == "verbose") {
+        Some((_, value)) => value.parse().map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("true or false"),
+                actual: value.to_string(),
+            })
+        }),
+        None => Ok(*req.state().error_detail.lock().await == ErrorDetail::Verbose),
+    }
+}
+
+/// Middleware which adds diagnostic context to error responses, if verbose errors were requested.
+pub fn error_detail<'a>(
+    req: Request<WebState>,
+    next: Next<'a, WebState>,
+) -> Pin<Box<dyn Future<Output = tide::Result> + Send + 'a>> {
+    Box::pin(async move {
+        if !verbose(&req).await? {
+            return Ok(next.run(req).await);
+        }
+        let state = req.state().clone();
+        let segments = req
+            .url()
+            .path_segments()
+            .map(|segments| segments.map(String::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let mut res = next.run(req).await;
+        let err = match res.take_error() {
+            Some(err) => err,
+            None => return Ok(res),
+        };
+        let error = match err.downcast_ref::<CapeAPIError>() {
+            Some(err) => serde_json::to_value(err)?,
+            None => serde_json::to_value(CapeAPIError::Internal {
+                msg: err.to_string(),
+            })?,
+        };
+        let message = err.to_string();
+        let status = err.status();
+        let causes = err
+            .into_inner()
+            .chain()
+            .skip(1)
+            .map(|cause| cause.to_string())
+            .collect();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        let route_doc = explain_route(&state.api, &segments)
+            .ok()
+            .map(|(_, arg_doc)| arg_doc);
+
+        Ok(Response::builder(status)
+            .body(Body::from_json(&VerboseError {
+                error,
+                message,
+                causes,
+                route_doc,
+            })?)
+            .build())
+    })
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Proofs of ownership of Ethereum addresses.
+//!
+//! A client proves that it controls an Ethereum address by signing a challenge issued by the server
+//! with the address's key. Once verified, the address is linked, and if the server was started with
+//! `--require-eth-link`, only linked addresses can be wrapped from. Links are stored in the
+//! `eth_links` file in the storage directory. Challenges are only kept in memory, so restarting the
+//! server invalidates any outstanding challenges.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::LinkedEthAddress;
+use ethers::prelude::{Address, Signature};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Linked addresses, and outstanding challenges.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EthLinks {
+    links: Vec<LinkedEthAddress>,
+    #[serde(skip)]
+    challenges: HashMap<Address, String>,
+}
+
+impl Persisted for EthLinks {}
+
+impl EthLinks {
+    pub fn list(&self) -> Vec<LinkedEthAddress> {
+        self.links.clone()
+    }
+
+    pub fn is_linked(&self, address: &Address) -> bool {
+        self.links.iter().any(|link| link.address == *address)
+    }
+
+    /// Issue a new challenge for `address` to sign.
+    ///
+    /// The challenge replaces any earlier challenge for the same address.
+    pub fn challenge(&mut self, address: Address, rng: &mut impl RngCore) -> String {
+        let mut nonce = [0u8; 16];
+        rng.fill_bytes(&mut nonce);
+        let challenge = format!(
+            "Link Ethereum address {:?} to CAPE wallet. Nonce: {}",
+            address,
+            hex::encode(nonce)
+        );
+        self.challenges.insert(address, challenge.clone());
+        challenge
+    }
+
+    /// Link `address` if `signature` is its signature of the outstanding challenge.
+    ///
+    /// The signature must be an Ethereum signed message (as produced by `eth_sign` or
+    /// `personal_sign`) of the exact challenge string. On success, the challenge is used up. On
+    /// failure, returns a description of the problem, and the challenge can still be signed.
+    pub fn link(
+        &mut self,
+        address: Address,
+        signature: &Signature,
+    ) -> Result<LinkedEthAddress, String> {
+        let challenge = self
+            .challenges
+            .get(&address)
+            .ok_or_else(|| format!("no challenge has been issued for {:?}", address))?;
+        signature
+            .verify(challenge.as_str(), address)
+            .map_err(|err| format!("invalid signature for {:?}: {}", address, err))?;
+        self.challenges.remove(&address);
+
+        let link = LinkedEthAddress {
+            address,
+            linked_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        match self.links.iter_mut().find(|link| link.address == address) {
+            Some(existing) => *existing = link.clone(),
+            None => self.links.push(link.clone()),
+        }
+        Ok(link)
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic top-ups of native fee records.
+//!
+//! Every transaction spends a native record to pay its fee, so a wallet which holds all of its
+//! native balance in a few records can only build a few transactions at a time, no matter how much
+//! of other assets it holds. A policy asks for the wallet to keep at least a threshold number of
+//! fee records, which are native records of at least a given amount. Policies belong to the wallet
+//! they were set for, and are stored in the `feetopup` file in the storage directory, keyed by
+//! wallet path. A background task checks the policy of the open wallet whenever no request has been
+//! made for a while, and when the wallet has too few fee records, splits a fee record off its
+//! largest native record by transferring part of it back to its owner with no fee.
+//!
+//! Like automatic consolidation, the task only runs while the server is idle, and it does nothing
+//! while any of the wallet's records are on hold, since that means a transfer is still pending.
+
+use crate::cache::ResponseCache;
+use crate::idle::Activity;
+use crate::lock::WalletLock;
+use crate::persisted::Persisted;
+use crate::routes::{read_last_path, Wallet};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cap_rust_sandbox::types::GenericInto;
+use cape_wallet::ui::FeeTopUpPolicy;
+use jf_cap::structs::{AssetCode, FreezeFlag};
+use net::UserAddress;
+use seahorse::txn_builder::RecordInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// How often the background task checks whether a top-up is needed.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How long the server must go without a request before the background task tops up.
+const IDLE: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FeeTopUp {
+    policies: HashMap<PathBuf, FeeTopUpPolicy>,
+}
+
+impl Persisted for FeeTopUp {}
+
+impl FeeTopUp {
+    /// The policy of the wallet at `wallet_path`, if it has one.
+    pub fn get(&self, wallet_path: &Path) -> Option<FeeTopUpPolicy> {
+        self.policies.get(wallet_path).cloned()
+    }
+
+    /// Keep at least `threshold` native records of at least `amount` in the wallet at
+    /// `wallet_path`.
+    ///
+    /// A threshold of 0 removes the policy.
+    pub fn set(&mut self, wallet_path: &Path, threshold: usize, amount: u128) {
+        if threshold > 0 {
+            self.policies.insert(
+                wallet_path.to_path_buf(),
+                FeeTopUpPolicy {
+                    threshold,
+                    amount: amount.into(),
+                    top_ups: 0,
+                    last_error: None,
+                },
+            );
+        } else {
+            self.policies.remove(wallet_path);
+        }
+    }
+}
+
+/// Top up fee records in the open wallet according to its policy while the server is idle.
+///
+/// The task exits once the server which owns `policies` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    policies: Arc<Mutex<FeeTopUp>>,
+    activity: Arc<Mutex<Activity>>,
+    submission_paused: Arc<Mutex<bool>>,
+    lock: Arc<Mutex<Option<WalletLock>>>,
+    cache: Arc<Mutex<ResponseCache>>,
+) {
+    while Arc::strong_count(&policies) > 1 {
+        sleep(TICK).await;
+
+        // Take the wallet lock first, so that a request in progress finishes (and updates the
+        // activity time) before we decide whether the server is idle.
+        let mut wallet = wallet.lock().await;
+        let wallet = match wallet.as_mut() {
+            Some(wallet) => wallet,
+            None => continue,
+        };
+        if activity.lock().await.idle() < IDLE
+            || *submission_paused.lock().await
+            || lock.lock().await.is_some()
+        {
+            continue;
+        }
+        let wallet_path = match read_last_path(&options).await {
+            Ok(Some(path)) => path,
+            _ => continue,
+        };
+        let mut policies = policies.lock().await;
+        let policy = match policies.policies.get_mut(&wallet_path) {
+            Some(policy) => policy,
+            None => continue,
+        };
+
+        let records = wallet.records().await.collect::<Vec<_>>();
+        if records.iter().any(|record| record.hold_until.is_some()) {
+            // A transfer is pending. Leave its records alone until it settles.
+            continue;
+        }
+        let amount = policy.amount.as_u128();
+        if fee_records(&records, amount) >= policy.threshold {
+            continue;
+        }
+        match plan(records, amount) {
+            Some((address, transfer_amount)) => {
+                match wallet
+                    .transfer(
+                        Some(&address.clone().into()),
+                        &AssetCode::native(),
+                        &[(address.into(), transfer_amount)],
+                        0,
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        policy.top_ups += 1;
+                        policy.last_error = None;
+                    }
+                    Err(err) => {
+                        event!(Level::WARN, "failed to top up fee records: {}", err);
+                        policy.last_error = Some(err.to_string());
+                    }
+                }
+                // Topping up changes the wallet behind the back of the request handlers.
+                cache.lock().await.invalidate();
+            }
+            None => {
+                let err = format!("no native record is large enough to split off {}", amount);
+                if policy.last_error.as_ref() == Some(&err) {
+                    continue;
+                }
+                policy.last_error = Some(err);
+            }
+        }
+        if let Err(err) = policies.save(&options.feetopup_path()) {
+            event!(Level::ERROR, "failed to save fee top-up policies: {}", err);
+        }
+    }
+}
+
+/// Whether `record` is a native record the wallet can spend right now.
+fn spendable_native(record: &RecordInfo) -> bool {
+    record.ro.asset_def.code == AssetCode::native()
+        && record.ro.freeze_flag == FreezeFlag::Unfrozen
+        && record.hold_until.is_none()
+}
+
+/// The number of spendable native records of at least `amount`.
+fn fee_records(records: &[RecordInfo], amount: u128) -> usize {
+    records
+        .iter()
+        .filter(|record| {
+            spendable_native(record) && record.ro.amount.generic_into::<u128>() >= amount
+        })
+        .count()
+}
+
+/// Choose a transfer which splits a fee record of `amount` off the largest native record.
+///
+/// Returns the address to transfer from and back to, and the amount, or `None` if no native record
+/// is at least twice `amount`. The transfer sends all but `amount` of the largest record at the
+/// address back to the address, so a transfer selecting the largest records first spends only that
+/// record and leaves a change record of exactly `amount`. Addresses which already hold a record of
+/// the amount to transfer are skipped, since a transfer may spend a record of exactly the right
+/// amount instead of splitting a larger one.
+fn plan(
+    records: impl IntoIterator<Item = RecordInfo>,
+    amount: u128,
+) -> Option<(UserAddress, u128)> {
+    let mut owners: HashMap<UserAddress, Vec<u128>> = HashMap::new();
+    for record in records {
+        if spendable_native(&record) {
+            owners
+                .entry(record.ro.pub_key.address().into())
+                .or_default()
+                .push(record.ro.amount.generic_into::<u128>());
+        }
+    }
+
+    owners
+        .into_iter()
+        .filter_map(|(address, amounts)| {
+            let largest = amounts.iter().copied().max()?;
+            if largest / 2 < amount || amounts.contains(&(largest - amount)) {
+                return None;
+            }
+            Some((address, largest))
+        })
+        .max_by_key(|(_, largest)| *largest)
+        .map(|(address, largest)| (address, largest - amount))
+}
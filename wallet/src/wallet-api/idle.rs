@@ -0,0 +1,96 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic closing of idle wallets.
+//!
+//! If the server is started with `--idle-timeout`, a watchdog task closes the open wallet once no
+//! request has been made for that long, so that its secrets are not held in memory indefinitely.
+
+use crate::cache::ResponseCache;
+use crate::routes::{has_pending_spends, Wallet};
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use std::time::{Duration, Instant};
+use tracing::{event, Level};
+
+/// When the last request was handled, and whether the wallet was closed for being idle since.
+#[derive(Clone, Copy, Debug)]
+pub struct Activity {
+    last_request: Instant,
+    timed_out: bool,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            last_request: Instant::now(),
+            timed_out: false,
+        }
+    }
+}
+
+impl Activity {
+    /// Record that a request is being handled now.
+    pub fn touch(&mut self) {
+        self.last_request = Instant::now();
+    }
+
+    /// How long it has been since the last request.
+    pub fn idle(&self) -> Duration {
+        self.last_request.elapsed()
+    }
+
+    /// Whether the wallet was closed by the watchdog and has not been reopened since.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Record that a wallet is open, clearing any previous timeout.
+    pub fn reopened(&mut self) {
+        self.timed_out = false;
+    }
+}
+
+/// Close `wallet` whenever no request has been handled for `timeout`.
+///
+/// Closing the wallet clears `cache`, whose responses describe the wallet. The task exits once the server which owns `wallet` has been dropped.
+pub async fn watchdog(
+    timeout: Duration,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    activity: Arc<Mutex<Activity>>,
+    cache: Arc<Mutex<ResponseCache>>,
+) {
+    let period = std::cmp::min(timeout, Duration::from_secs(1));
+    while Arc::strong_count(&wallet) > 1 {
+        sleep(period).await;
+
+        // Take the wallet lock first, so that a request in progress finishes (and updates the
+        // activity time) before we decide whether the wallet is idle.
+        let mut wallet = wallet.lock().await;
+        let mut activity = activity.lock().await;
+        if activity.last_request.elapsed() < timeout {
+            continue;
+        }
+        // Like `closewallet`, leave the wallet open until its pending transactions settle.
+        let idle = match wallet.as_ref() {
+            Some(open) => !has_pending_spends(open).await,
+            None => false,
+        };
+        if idle {
+            event!(
+                Level::INFO,
+                "closing wallet after {:?} of inactivity",
+                timeout
+            );
+            *wallet = None;
+            activity.timed_out = true;
+            cache.lock().await.invalidate();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The read-only mode the open wallet can be locked in.
+//!
+//! While the wallet is locked, it keeps scanning and answering reads, but requests which would
+//! modify it or reveal its private keys are rejected until it is unlocked with its mnemonic or the
+//! PIN it was locked with, and background tasks which would spend from it are paused. Only a salted
+//! PBKDF2 hash of the PIN is kept, and only in memory, so a lock does not outlive the server. After a
+//! few failed attempts to unlock the wallet, each further attempt has to wait twice as long as the
+//! last, so that a short PIN cannot be guessed by brute force.
+
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use rand::RngCore;
+use sha3::Sha3_256;
+use std::time::{Duration, Instant};
+
+/// The number of PBKDF2 rounds a PIN is hashed with.
+const PIN_HASH_ROUNDS: u32 = 100_000;
+/// How many attempts to unlock the wallet may fail before further attempts are throttled.
+const FREE_UNLOCK_ATTEMPTS: u32 = 3;
+/// How long to wait after the last free attempt fails. The wait doubles with each further failure.
+pub const UNLOCK_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest wait between attempts to unlock the wallet.
+const MAX_UNLOCK_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+struct PinHash {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl PinHash {
+    fn new(pin: &str, rng: &mut impl RngCore) -> Self {
+        let mut salt = [0u8; 16];
+        rng.fill_bytes(&mut salt);
+        Self {
+            hash: hash(pin, &salt),
+            salt,
+        }
+    }
+
+    fn matches(&self, pin: &str) -> bool {
+        hash(pin, &self.salt) == self.hash
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WalletLock {
+    pin_hash: Option<PinHash>,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl WalletLock {
+    /// A lock which can be released with `pin`, as well as with the wallet's mnemonic.
+    pub fn new(pin: Option<&str>, rng: &mut impl RngCore) -> Self {
+        Self {
+            pin_hash: pin.map(|pin| PinHash::new(pin, rng)),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the lock has a PIN.
+    pub fn has_pin(&self) -> bool {
+        self.pin_hash.is_some()
+    }
+
+    /// Whether `pin` releases the lock.
+    pub fn check_pin(&self, pin: &str) -> bool {
+        self.pin_hash
+            .as_ref()
+            .map_or(false, |pin_hash| pin_hash.matches(pin))
+    }
+
+    /// How long to wait before the next attempt to unlock the wallet, if it has to wait at all.
+    pub fn throttled(&self) -> Option<Duration> {
+        let wait = self.retry_at?.saturating_duration_since(Instant::now());
+        if wait.is_zero() {
+            None
+        } else {
+            Some(wait)
+        }
+    }
+
+    /// Record a failed attempt to unlock the wallet.
+    pub fn failed(&mut self) {
+        self.failures += 1;
+        if self.failures >= FREE_UNLOCK_ATTEMPTS {
+            let doublings = (self.failures - FREE_UNLOCK_ATTEMPTS).min(16);
+            let wait = std::cmp::min(UNLOCK_BACKOFF * (1 << doublings), MAX_UNLOCK_BACKOFF);
+            self.retry_at = Some(Instant::now() + wait);
+        }
+    }
+}
+
+fn hash(pin: &str, salt: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    pbkdf2::<Hmac<Sha3_256>>(pin.as_bytes(), salt, PIN_HASH_ROUNDS, &mut hash);
+    hash
+}
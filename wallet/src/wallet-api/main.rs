@@ -39,8 +39,48 @@
 //! the web server. Most of the functionality, such as API interpretation, request parsing, and
 //! route handling, is defined in the [cape_wallet] crate.
 
+mod alerts;
+mod asset_metadata;
+mod auth;
+mod autoconsolidate;
+mod autoretry;
+mod backups;
+mod balance_index;
+mod cache;
+mod categories;
+mod clock;
+mod coin_selection;
+mod confirmations;
+mod contacts;
+mod conversions;
+mod error_detail;
+mod eth_links;
+mod feetopup;
+mod idle;
+mod lock;
+mod min_anonymity;
+mod notified;
+mod payment_requests;
+mod persisted;
+mod proof_threads;
+mod pruned;
+mod quarantine;
+mod ranges;
+mod route_stats;
 mod routes;
+mod schedules;
+mod sent;
+mod spend_limits;
+mod sponsors;
+mod subscriptions;
+mod telemetry;
+#[cfg(any(test, feature = "test-state"))]
+mod teststate;
+mod tls;
+mod verifiers;
+mod viewing_capabilities;
 mod web;
+mod webhook;
 
 use crate::web::{init_server, NodeOpt};
 use cap_rust_sandbox::universal_param::UNIVERSAL_PARAM;
@@ -49,41 +89,59 @@ use structopt::StructOpt;
 
 #[async_std::main]
 async fn main() -> Result<(), std::io::Error> {
-    tracing_subscriber::fmt()
-        .pretty()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    let options = NodeOpt::from_args();
+    telemetry::init_tracing(&options)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
 
     // It can take a little while to unpack the universal params. Start doing this in the background
     // while the server is starting and the user is going through the login process.
     async_std::task::spawn(async { &UNIVERSAL_PARAM });
 
-    init_server(ChaChaRng::from_entropy(), &NodeOpt::from_args())?.await?;
-    Ok(())
+    let res = init_server(ChaChaRng::from_entropy(), &options)?.await;
+    telemetry::shutdown_tracing();
+    res
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        routes::CapeAPIError,
+        contacts::{Contacts, DuplicatePolicy},
+        persisted::Persisted,
+        routes::{mock_ledger, CapeAPIError},
         web::{
-            DEFAULT_ETH_ADDR, DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR,
+            build_server, DEFAULT_ETH_ADDR, DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR,
             DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR, DEFAULT_WRAPPED_AMT,
         },
     };
     use ark_serialize::CanonicalDeserialize;
     use async_std::fs;
-    use cap_rust_sandbox::{ledger::CapeLedger, model::EthereumAddr};
+    use cap_rust_sandbox::{
+        ledger::CapeLedger,
+        model::{EthereumAddr, CAPE_MERKLE_HEIGHT},
+        types::GenericInto,
+        universal_param::SUPPORTED_TRANSFER_SIZES,
+    };
     use cape_wallet::{
-        mocks::test_asset_signing_key,
+        disco::ApiRouteKey,
+        mocks::{
+            test_asset_signing_key, MOCK_GAS_PRICE, MOCK_MIN_FEE, MOCK_UNWRAP_GAS, MOCK_WRAP_GAS,
+        },
         testing::{port, retry},
         ui::*,
+        wallet::transaction_hash_string,
     };
     use ethers::prelude::{Address, U256};
+    use ethers::signers::{LocalWallet, Signer};
     use jf_cap::{
-        keys::{AuditorKeyPair, AuditorPubKey, FreezerKeyPair, FreezerPubKey, UserKeyPair},
-        structs::{AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy},
+        keys::{
+            AuditorKeyPair, AuditorPubKey, FreezerKeyPair, FreezerPubKey, UserKeyPair, UserPubKey,
+        },
+        structs::{
+            AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy, FreezeFlag,
+            RecordCommitment, RecordOpening,
+        },
+        KeyPair,
     };
     use net::{client, UserAddress};
     use seahorse::{
@@ -99,7 +157,9 @@ mod tests {
     use std::iter::once;
     use std::path::{Path, PathBuf};
     use std::str::FromStr;
+    use std::time::Duration;
     use surf::Url;
+    use tagged_base64::TaggedBase64;
     use tempdir::TempDir;
     use tracing_test::traced_test;
 
@@ -136,6 +196,10 @@ mod tests {
 
     impl TestServer {
         async fn new() -> Self {
+            Self::with_options(|_| {}).await
+        }
+
+        async fn with_options(configure: impl FnOnce(&mut NodeOpt)) -> Self {
             let port = port().await;
 
             // Run a server in the background that is unique to this test. Note that the server task
@@ -144,7 +208,8 @@ mod tests {
             // ends. This is ok, since each test's server task should be idle once
             // the test is over.
             let temp_dir = TempDir::new("test_wallet_api_storage").unwrap();
-            let options = NodeOpt::for_test(port as u16, temp_dir.path().to_path_buf());
+            let mut options = NodeOpt::for_test(port as u16, temp_dir.path().to_path_buf());
+            configure(&mut options);
             init_server(ChaChaRng::from_seed([42; 32]), &options).unwrap();
             Self::wait(port).await;
 
@@ -438,6 +503,121 @@ mod tests {
         server.post::<()>("closewallet").await.unwrap();
     }
 
+    /// Open a new wallet containing the faucet record, and return the faucet address.
+    async fn open_faucet_wallet(server: &TestServer) -> UserAddress {
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    faucet_addr,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR.into())
+        })
+        .await;
+        faucet_addr
+    }
+
+    fn send_native(sender: &UserAddress) -> String {
+        let mut rng = ChaChaRng::from_seed([46u8; 32]);
+        let recipient = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        format!(
+            "send/sender/{}/asset/{}/recipient/{}/amount/10/fee/1",
+            sender,
+            AssetCode::native(),
+            recipient
+        )
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_closewallet_before_transfer() {
+        let server = TestServer::new().await;
+        let faucet_addr = open_faucet_wallet(&server).await;
+
+        // With no transfers pending, the wallet closes right away, and a transfer made after that
+        // fails without a wallet to make it.
+        async_std::future::timeout(Duration::from_secs(1), server.post::<()>("closewallet"))
+            .await
+            .expect("closewallet waited with no transfers pending")
+            .unwrap();
+        server
+            .requires_wallet_post::<TransactionReceipt<CapeLedger>>(&send_native(&faucet_addr))
+            .await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_closewallet_during_transfer() {
+        let server = TestServer::new().await;
+        let faucet_addr = open_faucet_wallet(&server).await;
+
+        // Make the mock ledger hold submitted transactions until we flush it, so the transfer stays
+        // pending for as long as we want.
+        let ledger = mock_ledger(&server.options);
+        ledger.lock().await.set_block_size(2).unwrap();
+        let receipt = server
+            .post::<TransactionReceipt<CapeLedger>>(&send_native(&faucet_addr))
+            .await
+            .unwrap();
+        assert_ne!(
+            server.get::<Vec<Record>>("pendingspends").await.unwrap(),
+            vec![]
+        );
+
+        // `closewallet` must wait for the transfer to settle, without keeping other requests from
+        // using the wallet in the meantime.
+        let mut close = Box::pin(server.post::<()>("closewallet"));
+        async_std::future::timeout(Duration::from_secs(1), &mut close)
+            .await
+            .expect_err("closewallet did not wait for the pending transfer");
+        async_std::future::timeout(
+            Duration::from_secs(1),
+            server.get::<WalletSummary>("getinfo"),
+        )
+        .await
+        .expect("closewallet kept other requests from using the wallet")
+        .unwrap();
+        assert_ne!(
+            server.get::<Vec<Record>>("pendingspends").await.unwrap(),
+            vec![]
+        );
+        assert!(!ledger
+            .lock()
+            .await
+            .network()
+            .nullifier_spent(receipt.fee_nullifier));
+
+        // Once the transfer is committed, the wallet closes.
+        ledger.lock().await.flush().unwrap();
+        close.await.unwrap();
+
+        // The transfer was committed before the wallet closed.
+        server.requires_wallet::<WalletSummary>("getinfo").await;
+        assert!(ledger
+            .lock()
+            .await
+            .network()
+            .nullifier_spent(receipt.fee_nullifier));
+    }
+
     #[async_std::test]
     #[traced_test]
     async fn test_getinfo() {
@@ -1015,6 +1195,93 @@ mod tests {
         assert_eq!(asset.symbol, Some("my-defined-asset".into()));
     }
 
+    #[async_std::test]
+    #[traced_test]
+    async fn test_newassets() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let viewing_key = match server.post::<PubKey>("newkey/viewing").await.unwrap() {
+            PubKey::Viewing(key) => key,
+            key => panic!("expected PubKey::Viewing, got {:?}", key),
+        };
+        let num_assets = || async {
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .assets
+                .len()
+        };
+        let assets_before = num_assets().await;
+
+        // The second asset reveals amounts without a viewing key, so none of the assets are
+        // defined.
+        let mut assets = vec![
+            NewAsset {
+                symbol: String::from("ONE"),
+                ..Default::default()
+            },
+            NewAsset {
+                symbol: String::from("TWO"),
+                view_amount: true,
+                ..Default::default()
+            },
+            NewAsset {
+                symbol: String::from("THREE"),
+                description: String::from("the third asset"),
+                ..Default::default()
+            },
+        ];
+        let err = server
+            .client
+            .post("newassets")
+            .body_json(&assets)
+            .unwrap()
+            .send()
+            .await
+            .expect_err("defined an asset with an invalid policy");
+        assert!(
+            err.to_string()
+                .contains("asset 1: view_amount requires a viewing_key"),
+            "{}",
+            err
+        );
+        assert!(!err.to_string().contains("asset 0"), "{}", err);
+        assert_eq!(num_assets().await, assets_before);
+
+        // With a viewing key, all three are defined.
+        assets[1].viewing_key = Some(viewing_key.clone());
+        let mut res = server
+            .client
+            .post("newassets")
+            .body_json(&assets)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let definitions = client::response_body::<Vec<AssetDefinition>>(&mut res)
+            .await
+            .unwrap();
+        assert_eq!(definitions.len(), 3);
+        assert_eq!(num_assets().await, assets_before + 3);
+        assert_eq!(definitions[1].viewing_key, Some(viewing_key));
+        assert!(definitions[1].amount_viewable);
+        assert_eq!(definitions[0].viewing_key, None);
+        let info = server
+            .get::<AssetInfo>(&format!("getasset/{}", definitions[2].code))
+            .await
+            .unwrap();
+        assert_eq!(info.symbol, Some(String::from("THREE")));
+    }
+
     #[async_std::test]
     #[traced_test]
     async fn test_sponsor() {
@@ -1307,6 +1574,158 @@ mod tests {
             .unwrap();
     }
 
+    #[async_std::test]
+    #[traced_test]
+    async fn test_linketh() {
+        let server = TestServer::with_options(|options| {
+            options.require_eth_link = true;
+        })
+        .await;
+        let eth_wallet = LocalWallet::new(&mut ChaChaRng::from_seed([7u8; 32]));
+        let eth_address = eth_wallet.address();
+        let other_eth_wallet = LocalWallet::new(&mut ChaChaRng::from_seed([8u8; 32]));
+        assert_eq!(
+            server
+                .get::<Vec<LinkedEthAddress>>("linketh")
+                .await
+                .unwrap(),
+            vec![]
+        );
+        let link_with = |signature: String| {
+            let server = &server;
+            async move {
+                server
+                    .post::<LinkedEthAddress>(&format!(
+                        "linketh/address/{:#x}/signature/{}",
+                        eth_address, signature
+                    ))
+                    .await
+            }
+        };
+
+        // Linking fails if no challenge has been issued.
+        let signature = eth_wallet.sign_message("not a challenge").await.unwrap();
+        let err = link_with(signature.to_string())
+            .await
+            .expect_err("linked an address without a challenge");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+
+        // Linking fails with a signature of the wrong message, or by the wrong key.
+        let challenge = server
+            .get::<String>(&format!("linketh/address/{:#x}/challenge", eth_address))
+            .await
+            .unwrap();
+        for signature in [
+            eth_wallet.sign_message("not the challenge").await.unwrap(),
+            other_eth_wallet.sign_message(&challenge).await.unwrap(),
+        ] {
+            let err = link_with(signature.to_string())
+                .await
+                .expect_err("linked an address with an invalid signature");
+            assert_eq!(err.status(), surf::StatusCode::Forbidden);
+        }
+        let err = link_with(String::from("0xnotasignature"))
+            .await
+            .expect_err("linked an address with a malformed signature");
+        assert_eq!(err.status(), surf::StatusCode::BadRequest);
+        assert_eq!(
+            server
+                .get::<Vec<LinkedEthAddress>>("linketh")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // A signature of the challenge links the address.
+        let signature = eth_wallet.sign_message(&challenge).await.unwrap();
+        let link = link_with(signature.to_string()).await.unwrap();
+        assert_eq!(link.address, eth_address);
+        assert_eq!(
+            server
+                .get::<Vec<LinkedEthAddress>>("linketh")
+                .await
+                .unwrap(),
+            vec![link]
+        );
+
+        // The challenge cannot be reused.
+        let err = link_with(signature.to_string())
+            .await
+            .expect_err("reused a challenge");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+
+        // Only the linked address can be wrapped from.
+        let erc20_code = Address::from([1u8; 20]);
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let (asset, info) = server
+            .post::<(sol::AssetDefinition, String)>(&format!(
+                "buildsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, eth_address
+            ))
+            .await
+            .unwrap();
+        server
+            .client
+            .post("importasset")
+            .body_json(&info)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        server
+            .client
+            .post(&format!(
+                "submitsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, eth_address
+            ))
+            .body_json(&asset)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let asset: JfAssetDefinition = asset.into();
+        let destination: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let ro = server
+            .post::<sol::RecordOpening>(&format!(
+                "buildwrap/destination/{}/asset/{}/amount/{}",
+                destination, asset.code, 10
+            ))
+            .await
+            .unwrap();
+        let err = server
+            .client
+            .post(&format!(
+                "submitwrap/ethaddress/{:#x}",
+                other_eth_wallet.address()
+            ))
+            .body_json(&ro)
+            .unwrap()
+            .send()
+            .await
+            .expect_err("wrapped from an unlinked address");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+        server
+            .client
+            .post(&format!("submitwrap/ethaddress/{:#x}", eth_address))
+            .body_json(&ro)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+    }
+
     #[async_std::test]
     #[traced_test]
     async fn test_mint() {
@@ -2255,36 +2674,123 @@ mod tests {
 
     #[async_std::test]
     #[traced_test]
-    async fn test_verified_assets() {
+    async fn test_checkwallet() {
         let server = TestServer::new().await;
-        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let password = base64("my-password".as_bytes());
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let other_mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let missing_path = fmt_path(
+            &[
+                server.temp_dir.path(),
+                Path::new("keystores/missing_wallet"),
+            ]
+            .iter()
+            .collect::<PathBuf>(),
+        );
+        let check = |mnemonic: String, path: String| {
+            let server = &server;
+            async move {
+                server
+                    .get::<WalletCheck>(&format!("checkwallet/{}/path/{}", mnemonic, path))
+                    .await
+                    .unwrap()
+            }
+        };
+        let unopenable = |reason| WalletCheck {
+            openable: false,
+            reason: Some(reason),
+        };
 
-        let (code, _) = AssetCode::random(&mut rng);
-        let new_asset = JfAssetDefinition::new(code, AssetPolicy::default()).unwrap();
-        let assets = VerifiedAssetLibrary::new(
-            vec![AssetInfo::native().into(), new_asset.clone().into()],
-            &test_asset_signing_key(),
+        // A keystore which does not exist cannot be opened, and checking it does not create it.
+        assert_eq!(
+            check(mnemonic.clone(), server.path()).await,
+            unopenable(UnopenableReason::MissingStore)
         );
-        let path = server.options().assets_path();
-        fs::write(&path, &bincode::serialize(&assets).unwrap())
+        assert_eq!(
+            check(mnemonic.clone(), missing_path.clone()).await,
+            unopenable(UnopenableReason::MissingStore)
+        );
+        server
+            .post::<()>(&format!("openwallet/{}/path/{}", password, server.path()))
             .await
-            .unwrap();
+            .expect_err("checkwallet created a keystore");
 
+        // Create a wallet and close it, so that the keystore itself is checked.
         server
             .post::<()>(&format!(
                 "newwallet/{}/{}/path/{}",
-                server.get::<String>("getmnemonic").await.unwrap(),
-                base64("my-password".as_bytes()),
-                server.path(),
+                mnemonic,
+                password,
+                server.path()
             ))
             .await
             .unwrap();
+        server.post::<()>("closewallet").await.unwrap();
+        assert_eq!(
+            check(mnemonic.clone(), server.path()).await,
+            WalletCheck {
+                openable: true,
+                reason: None,
+            }
+        );
+        assert_eq!(
+            check(other_mnemonic.clone(), server.path()).await,
+            unopenable(UnopenableReason::WrongMnemonic)
+        );
+        assert_eq!(
+            check(String::from("not-a-mnemonic"), server.path()).await,
+            unopenable(UnopenableReason::WrongMnemonic)
+        );
+        assert_eq!(
+            check(mnemonic.clone(), missing_path).await,
+            unopenable(UnopenableReason::MissingStore)
+        );
 
-        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
-        let native_info = info
-            .assets
-            .iter()
-            .find(|asset| asset.definition == AssetDefinition::native())
+        // Checking did not change the keystore, so it still opens with its password, and the open
+        // wallet can be checked too.
+        server
+            .post::<()>(&format!("openwallet/{}/path/{}", password, server.path()))
+            .await
+            .unwrap();
+        assert!(check(mnemonic, server.path()).await.openable);
+        assert_eq!(
+            check(other_mnemonic, server.path()).await,
+            unopenable(UnopenableReason::WrongMnemonic)
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_verified_assets() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+
+        let (code, _) = AssetCode::random(&mut rng);
+        let new_asset = JfAssetDefinition::new(code, AssetPolicy::default()).unwrap();
+        let assets = VerifiedAssetLibrary::new(
+            vec![AssetInfo::native().into(), new_asset.clone().into()],
+            &test_asset_signing_key(),
+        );
+        let path = server.options().assets_path();
+        fs::write(&path, &bincode::serialize(&assets).unwrap())
+            .await
+            .unwrap();
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path(),
+            ))
+            .await
+            .unwrap();
+
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let native_info = info
+            .assets
+            .iter()
+            .find(|asset| asset.definition == AssetDefinition::native())
             .unwrap();
         assert!(native_info.verified);
         let asset_info = info
@@ -2295,6 +2801,124 @@ mod tests {
         assert!(asset_info.verified);
     }
 
+    #[async_std::test]
+    #[traced_test]
+    async fn test_verifiers() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([2; 32]);
+        server
+            .requires_wallet::<Vec<TrustedVerifier>>("verifiers")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<Vec<TrustedVerifier>>("verifiers")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // Sign a library containing a new asset with a key the wallet does not trust yet.
+        let key_pair = KeyPair::generate(&mut rng);
+        let (code, _) = AssetCode::random(&mut rng);
+        let new_asset = JfAssetDefinition::new(code, AssetPolicy::default()).unwrap();
+        let library = VerifiedAssetLibrary::new(vec![new_asset.clone().into()], &key_pair);
+        let library = base64::encode(&bincode::serialize(&library).unwrap());
+        let verifyasset = || async {
+            let mut res = server
+                .client
+                .post("verifyasset")
+                .body_json(&library)
+                .unwrap()
+                .send()
+                .await
+                .unwrap();
+            client::response_body::<Vec<AssetInfo>>(&mut res).await
+        };
+        verifyasset()
+            .await
+            .expect_err("imported a library signed by an untrusted key");
+
+        // Trust the key and import the library.
+        let mut res = server
+            .client
+            .post("verifiers/acme")
+            .body_json(&key_pair.ver_key().to_string())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let verifiers = client::response_body::<Vec<TrustedVerifier>>(&mut res)
+            .await
+            .unwrap();
+        assert_eq!(verifiers.len(), 1);
+        assert_eq!(verifiers[0].name, "acme");
+        assert_eq!(verifiers[0].key, key_pair.ver_key().to_string());
+        let assets = verifyasset().await.unwrap();
+        assert_eq!(assets.len(), 1);
+        assert_eq!(
+            assets[0].definition,
+            AssetDefinition::from(new_asset.clone())
+        );
+        assert!(assets[0].verified);
+        assert!(
+            server
+                .get::<AssetInfo>(&format!("getasset/{}", code))
+                .await
+                .unwrap()
+                .verified
+        );
+        let verified = || async {
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .assets
+                .into_iter()
+                .find(|asset| asset.definition.code == code)
+                .unwrap()
+                .verified
+        };
+        assert!(verified().await);
+        assert_eq!(
+            server
+                .get::<Vec<TrustedVerifier>>("verifiers")
+                .await
+                .unwrap()[0]
+                .assets,
+            vec![code]
+        );
+
+        // Once the verifier is removed, the asset is still known but no longer verified.
+        let mut res = server.client.delete("verifiers/acme").send().await.unwrap();
+        assert_eq!(
+            client::response_body::<Vec<TrustedVerifier>>(&mut res)
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert!(!verified().await);
+        assert!(
+            !server
+                .get::<AssetInfo>(&format!("getasset/{}", code))
+                .await
+                .unwrap()
+                .verified
+        );
+        let mut res = server.client.delete("verifiers/acme").send().await.unwrap();
+        client::response_body::<Vec<TrustedVerifier>>(&mut res)
+            .await
+            .expect_err("removed a verifier which is not trusted");
+    }
+
     #[async_std::test]
     #[traced_test]
     async fn test_export_import_asset() {
@@ -2816,4 +3440,9879 @@ mod tests {
             expected_balance
         );
     }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_recordmemo() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<sol::RecordOpening>("recordmemo/0")
+            .await;
+
+        // Now open a wallet and populate it with some records.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // The decrypted memo for each record we own should be exactly that record's opening.
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+        assert!(!records.is_empty());
+        for record in &records {
+            let memo = server
+                .get::<sol::RecordOpening>(&format!("recordmemo/{}", record.uid))
+                .await
+                .unwrap();
+            assert_eq!(memo, sol::RecordOpening::from(record.ro.clone()));
+        }
+
+        // Should fail for a record we don't own.
+        let unknown_uid = records.iter().map(|record| record.uid).max().unwrap() + 1000;
+        server
+            .get::<sol::RecordOpening>(&format!("recordmemo/{}", unknown_uid))
+            .await
+            .expect_err("recordmemo succeeded with an unowned uid");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_record_provenance() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<RecordProvenance>("record/0/provenance")
+            .await;
+
+        // Now open a wallet and populate it with some records.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+
+        // Receive a record at a new address via a transfer.
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+        let received = records
+            .iter()
+            .find(|record| UserAddress::from(record.ro.pub_key.address()) == dst_address)
+            .unwrap();
+        let (history, _) = server
+            .get::<(Vec<TransactionHistoryEntry>, HashMap<AssetCode, AssetInfo>)>(
+                "transactionhistory",
+            )
+            .await
+            .unwrap();
+        let transfer = history.last().unwrap();
+        assert_eq!(transfer.kind, "send");
+
+        // The provenance of the received record should point back to the transfer.
+        let provenance = server
+            .get::<RecordProvenance>(&format!("record/{}/provenance", received.uid))
+            .await
+            .unwrap();
+        assert_eq!(provenance.record.uid, received.uid);
+        assert_eq!(provenance.record.address, dst_address);
+        assert_eq!(provenance.record.amount, "100");
+        assert_eq!(provenance.transaction, transfer.hash);
+        assert_eq!(provenance.kind, "send");
+        assert_eq!(provenance.senders, vec![src_address]);
+        assert_eq!(provenance.eth_sender, None);
+        assert!(provenance.memo.is_some());
+
+        // Should fail for a record we don't own.
+        let unknown_uid = records.iter().map(|record| record.uid).max().unwrap() + 1000;
+        server
+            .get::<RecordProvenance>(&format!("record/{}/provenance", unknown_uid))
+            .await
+            .expect_err("record provenance succeeded with an unowned uid");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_record_spendable() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<RecordSpendability>("record/0/spendable")
+            .await;
+
+        // Now open a wallet with some native tokens, and keys to define a freezable asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet: UserAddress = receipt.submitters[0].clone().into();
+        server.post::<PubKey>("newkey/viewing").await.unwrap();
+        server.post::<PubKey>("newkey/freezing").await.unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let owner: UserAddress = info.sending_keys[0].address().into();
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/freezing_key/{}/viewing_key/{}/view_amount/true/view_address/true",
+                info.freezing_keys[0], info.viewing_keys[0]
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // Mint a record of the asset to ourselves.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, faucet, owner
+            ))
+            .await
+            .unwrap();
+        let record_of = |frozen: FreezeFlag| {
+            let server = &server;
+            async move {
+                server
+                    .get::<Vec<RecordInfo>>("getrecords")
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .find(|record| {
+                        record.ro.asset_def.code == asset && record.ro.freeze_flag == frozen
+                    })
+            }
+        };
+        retry(|| async { record_of(FreezeFlag::Unfrozen).await.is_some() }).await;
+        let minted = record_of(FreezeFlag::Unfrozen).await.unwrap();
+        let spendable = server
+            .get::<RecordSpendability>(&format!("record/{}/spendable", minted.uid))
+            .await
+            .unwrap();
+        assert!(spendable.spendable);
+        assert!(spendable.reasons.is_empty());
+        assert_eq!(spendable.explanation, None);
+        assert_eq!(spendable.record.unwrap().uid, minted.uid);
+
+        // Freeze the record. It is replaced by a frozen record, which cannot be spent.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "freeze/{}/{}/fee/{}/1",
+                owner, asset, faucet
+            ))
+            .await
+            .unwrap();
+        retry(|| async { record_of(FreezeFlag::Frozen).await.is_some() }).await;
+        let frozen = record_of(FreezeFlag::Frozen).await.unwrap();
+        let spendable = server
+            .get::<RecordSpendability>(&format!("record/{}/spendable", frozen.uid))
+            .await
+            .unwrap();
+        assert!(!spendable.spendable);
+        assert_eq!(spendable.reasons, vec![UnspendableReason::Frozen]);
+        assert!(spendable.explanation.unwrap().contains("frozen"));
+
+        // The record which was frozen has been spent, so it is no longer reported.
+        server
+            .get::<RecordSpendability>(&format!("record/{}/spendable", minted.uid))
+            .await
+            .expect_err("reported the spendability of a spent record");
+
+        // Unfreeze the record, and it can be spent again.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "unfreeze/{}/{}/fee/{}/1",
+                owner, asset, faucet
+            ))
+            .await
+            .unwrap();
+        retry(|| async { record_of(FreezeFlag::Unfrozen).await.is_some() }).await;
+        let unfrozen = record_of(FreezeFlag::Unfrozen).await.unwrap();
+        let spendable = server
+            .get::<RecordSpendability>(&format!("record/{}/spendable", unfrozen.uid))
+            .await
+            .unwrap();
+        assert!(spendable.spendable);
+        assert!(spendable.reasons.is_empty());
+
+        // A record the wallet has not scanned yet is unconfirmed.
+        let ledger_size = server
+            .get::<MinAnonymityStatus>("minanonymity")
+            .await
+            .unwrap()
+            .ledger_size;
+        let spendable = server
+            .get::<RecordSpendability>(&format!("record/{}/spendable", ledger_size + 100))
+            .await
+            .unwrap();
+        assert!(!spendable.spendable);
+        assert_eq!(spendable.record, None);
+        assert_eq!(spendable.reasons, vec![UnspendableReason::Unconfirmed]);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_proofofreserves() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<ReservesAttestation>(&format!(
+                "proofofreserves/{}",
+                AssetCode::native()
+            ))
+            .await;
+
+        // Now open a wallet and populate it with some native balance.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        let mut attestation = server
+            .get::<ReservesAttestation>(&format!("proofofreserves/{}", AssetCode::native()))
+            .await
+            .unwrap();
+        assert!(attestation.verify());
+
+        // The attested total should match the wallet's actual balance.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(attestation.block_height, info.sync_time);
+        let balances = server.get::<BalanceInfo>("getbalance/all").await.unwrap();
+        match balances.balances {
+            Balances::All { aggregate, .. } => {
+                assert_eq!(attestation.total, aggregate[&AssetCode::native()]);
+            }
+            balances => panic!("Expected Balances::All, found {:?}", balances),
+        }
+        assert_eq!(
+            attestation.total,
+            U256::from(DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR + DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR)
+        );
+
+        // An inflated claim should not verify.
+        attestation.total += U256::from(1u64);
+        assert!(!attestation.verify());
+    }
+
+    #[test]
+    fn test_request_span() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id},
+            Subscriber,
+        };
+        use tracing_subscriber::{
+            layer::{Context, SubscriberExt},
+            Layer,
+        };
+
+        // A layer which records the name and `route` field of every span that is created. This
+        // sees the same spans that would be exported to an OpenTelemetry collector.
+        #[derive(Clone, Default)]
+        struct SpanRecorder(Arc<Mutex<Vec<(String, Option<String>)>>>);
+
+        struct RouteVisitor(Option<String>);
+
+        impl Visit for RouteVisitor {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                if field.name() == "route" {
+                    self.0 = Some(format!("{:?}", value));
+                }
+            }
+        }
+
+        impl<S: Subscriber> Layer<S> for SpanRecorder {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+                let mut visitor = RouteVisitor(None);
+                attrs.record(&mut visitor);
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((attrs.metadata().name().to_string(), visitor.0));
+            }
+        }
+
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        let temp_dir = TempDir::new("test_wallet_api_storage").unwrap();
+        let options = NodeOpt::for_test(0, temp_dir.path().to_path_buf());
+        let server = build_server(ChaChaRng::from_seed([42; 32]), &options).unwrap();
+
+        // Handle the request on this thread, so that the spans it creates are seen by `recorder`.
+        tracing::subscriber::with_default(subscriber, || {
+            async_std::task::block_on(async {
+                let req = tide::http::Request::new(
+                    tide::http::Method::Get,
+                    Url::parse("http://localhost/getmnemonic").unwrap(),
+                );
+                let res: tide::http::Response = server.respond(req).await.unwrap();
+                assert!(res.status().is_success());
+            })
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        assert!(
+            spans
+                .iter()
+                .any(|(name, route)| name == "request" && route.as_deref() == Some("getmnemonic")),
+            "no request span for getmnemonic in {:?}",
+            *spans
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_gasestimate() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<GasEstimate>(&format!(
+                "gasestimate/wrap/{}/100",
+                AssetCode::native()
+            ))
+            .await;
+
+        // Now open a wallet and populate it with a wrapped asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let wrapped_asset = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.ro.asset_def.code)
+            .find(|code| *code != AssetCode::native())
+            .unwrap();
+
+        // The mock backend reports a fixed estimate for each operation.
+        let estimate = server
+            .get::<GasEstimate>(&format!("gasestimate/wrap/{}/100", wrapped_asset))
+            .await
+            .unwrap();
+        assert_eq!(estimate.gas, MOCK_WRAP_GAS.into());
+        assert_eq!(estimate.gas_price, MOCK_GAS_PRICE.into());
+        assert_eq!(
+            estimate.total,
+            U256::from(MOCK_WRAP_GAS) * U256::from(MOCK_GAS_PRICE)
+        );
+        let estimate = server
+            .get::<GasEstimate>(&format!("gasestimate/unwrap/{}/100", wrapped_asset))
+            .await
+            .unwrap();
+        assert_eq!(estimate.gas, MOCK_UNWRAP_GAS.into());
+        assert_eq!(estimate.gas_price, MOCK_GAS_PRICE.into());
+        assert_eq!(
+            estimate.total,
+            U256::from(MOCK_UNWRAP_GAS) * U256::from(MOCK_GAS_PRICE)
+        );
+        let estimate = server
+            .get::<GasEstimate>(&format!(
+                "gasestimate/unwrap/{}/100/to/{:#x}",
+                wrapped_asset,
+                Address::from([3u8; 20])
+            ))
+            .await
+            .unwrap();
+        assert_eq!(estimate.gas, MOCK_UNWRAP_GAS.into());
+
+        // Domestic assets cannot be wrapped or unwrapped.
+        server
+            .get::<GasEstimate>(&format!("gasestimate/wrap/{}/100", AssetCode::native()))
+            .await
+            .expect_err("gasestimate succeeded for a domestic asset");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_pendingmemos() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<Vec<TransactionHistoryEntry>>("pendingmemos")
+            .await;
+
+        // Now open a wallet and populate it with some native balance.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // Once `populatefortest` returns, all of its transactions have been confirmed.
+        assert_eq!(
+            server
+                .get::<Vec<TransactionHistoryEntry>>("pendingmemos")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // Send a transfer to a new account.
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        let is_our_transfer = |entry: &TransactionHistoryEntry| {
+            entry.kind == "send"
+                && entry
+                    .receivers
+                    .contains(&(dst_address.clone(), "100".into()))
+        };
+
+        // The mock ledger confirms the memos as soon as the wallet processes the block, so the
+        // transfer may already have cleared by the time we check. If anything is listed, it must be
+        // this transfer.
+        let pending = server
+            .get::<Vec<TransactionHistoryEntry>>("pendingmemos")
+            .await
+            .unwrap();
+        assert!(pending.len() <= 1);
+        for entry in &pending {
+            assert!(
+                is_our_transfer(entry),
+                "unexpected pending entry {:?}",
+                entry
+            );
+        }
+
+        // Once the recipient sees the funds, the memos have been confirmed and the entry clears.
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+        retry(|| async {
+            server
+                .get::<Vec<TransactionHistoryEntry>>("pendingmemos")
+                .await
+                .unwrap()
+                .is_empty()
+        })
+        .await;
+        assert!(server
+            .get::<(
+                Vec<TransactionHistoryEntry>,
+                HashMap<AssetCode, Option<AssetInfo>>
+            )>("transactionhistory")
+            .await
+            .unwrap()
+            .0
+            .iter()
+            .any(is_our_transfer));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_snapshot_diff() {
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server.requires_wallet::<String>("snapshot").await;
+
+        // Now open a wallet and populate it with some native balance.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+
+        // A diff against a fresh snapshot should be empty.
+        let snapshot = server.get::<String>("snapshot").await.unwrap();
+        let diff = server
+            .get::<WalletDiff>(&format!("diff/{}", snapshot))
+            .await
+            .unwrap();
+        assert!(diff.new_records.is_empty());
+        assert!(diff.spent_records.is_empty());
+        assert!(diff.new_addresses.is_empty());
+        assert!(diff.balances.is_empty());
+
+        // Send some of the native asset to a new account.
+        let dst_address = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<WalletDiff>(&format!("diff/{}", snapshot))
+                .await
+                .unwrap()
+                .new_records
+                .len()
+                == 2
+        })
+        .await;
+
+        // The diff should report the spent input, the received record and the change, and the fee
+        // deducted from the native balance.
+        let diff = server
+            .get::<WalletDiff>(&format!("diff/{}", snapshot))
+            .await
+            .unwrap();
+        assert_eq!(diff.new_addresses, vec![dst_address.clone()]);
+        let spent_input = records
+            .iter()
+            .find(|record| {
+                UserAddress::from(record.ro.pub_key.address()) == src_address
+                    && record.ro.asset_def.code == AssetCode::native()
+            })
+            .unwrap();
+        assert_eq!(
+            diff.spent_records
+                .iter()
+                .map(|record| record.uid)
+                .collect::<Vec<_>>(),
+            vec![spent_input.uid]
+        );
+        let received = diff
+            .new_records
+            .iter()
+            .find(|record| UserAddress::from(record.ro.pub_key.address()) == dst_address)
+            .unwrap();
+        assert_eq!(received.ro.asset_def.code, AssetCode::native());
+        assert_eq!(received.ro.amount, 100u128.into());
+        let native_total = DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR + DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR;
+        assert_eq!(
+            diff.balances,
+            once((
+                AssetCode::native(),
+                BalanceDelta {
+                    before: native_total.into(),
+                    after: (native_total - 1).into(),
+                }
+            ))
+            .collect()
+        );
+
+        // Unknown and malformed tokens are rejected.
+        server
+            .get::<WalletDiff>(&format!(
+                "diff/{}",
+                TaggedBase64::new("SNAPSHOT", &[0; 8]).unwrap()
+            ))
+            .await
+            .expect_err("diff succeeded with an unknown snapshot");
+        server
+            .get::<WalletDiff>(&format!("diff/{}", AssetCode::native()))
+            .await
+            .expect_err("diff succeeded with a malformed snapshot");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_fsck() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<FsckReport>("fsck").await;
+
+        // A freshly populated wallet should be consistent.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        assert_eq!(
+            server.get::<FsckReport>("fsck").await.unwrap(),
+            FsckReport::default()
+        );
+
+        // Repair is disabled unless the server is started with `--enable-fsck-repair`.
+        server
+            .get::<FsckReport>("fsck/repair")
+            .await
+            .expect_err("fsck/repair succeeded without --enable-fsck-repair");
+    }
+
+    #[test]
+    fn test_fsck_detects_inconsistencies() {
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let key_pair = UserKeyPair::generate(&mut rng);
+        let address = UserAddress::from(key_pair.address());
+        let ro = RecordOpening::new(
+            &mut rng,
+            100u64.into(),
+            JfAssetDefinition::native(),
+            key_pair.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        let record = RecordInfo::new(ro, 0, &key_pair);
+        let records = vec![record.clone()];
+        let addresses = vec![address.clone()];
+        let assets = once(AssetCode::native()).collect::<HashSet<_>>();
+        let mut balances = HashMap::new();
+        balances.insert((address.clone(), AssetCode::native()), U256::from(100));
+        assert_eq!(
+            FsckReport::check(&records, &addresses, &assets, &balances),
+            FsckReport::default()
+        );
+
+        // Inject a mismatch between the reported balance and the records.
+        let mut bad_balances = balances.clone();
+        bad_balances.insert((address.clone(), AssetCode::native()), U256::from(90));
+        assert_eq!(
+            FsckReport::check(&records, &addresses, &assets, &bad_balances).anomalies,
+            vec![FsckAnomaly::BalanceMismatch {
+                address: address.clone(),
+                asset: AssetCode::native(),
+                records_total: U256::from(100),
+                balance: U256::from(90),
+            }]
+        );
+
+        // Inject a record owned by an address the wallet doesn't know about.
+        assert_eq!(
+            FsckReport::check(&records, &[], &assets, &balances).anomalies,
+            vec![FsckAnomaly::OrphanedRecord {
+                uid: 0,
+                owner: address.clone(),
+            }]
+        );
+
+        // Inject a duplicate record. This also makes the records total disagree with the balance.
+        let anomalies =
+            FsckReport::check(&[record.clone(), record], &addresses, &assets, &balances).anomalies;
+        assert!(anomalies.contains(&FsckAnomaly::DuplicateRecord { uid: 0 }));
+        assert!(anomalies.contains(&FsckAnomaly::BalanceMismatch {
+            address,
+            asset: AssetCode::native(),
+            records_total: U256::from(200),
+            balance: U256::from(100),
+        }));
+
+        // Remove the record's asset type from the asset library. This is the only repairable
+        // anomaly.
+        let anomalies =
+            FsckReport::check(&records, &addresses, &HashSet::new(), &balances).anomalies;
+        assert_eq!(
+            anomalies,
+            vec![FsckAnomaly::UnknownAsset {
+                asset: AssetCode::native()
+            }]
+        );
+        assert!(anomalies[0].is_repairable());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_subscriptions() {
+        let server = TestServer::new().await;
+        assert_eq!(
+            server
+                .get::<Vec<SubscriptionInfo>>("subscriptions")
+                .await
+                .unwrap(),
+            vec![]
+        );
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Open a subscription. The subscription handler runs in the background, so we may have to
+        // wait for it to be registered.
+        let mut events = server.client.get("events").send().await.unwrap();
+        retry(|| async {
+            server
+                .get::<Vec<SubscriptionInfo>>("subscriptions")
+                .await
+                .unwrap()
+                .len()
+                == 1
+        })
+        .await;
+        let subscriptions = server
+            .get::<Vec<SubscriptionInfo>>("subscriptions")
+            .await
+            .unwrap();
+        assert_eq!(subscriptions[0].route, "events");
+
+        // Close it and check that the stream terminates.
+        assert_eq!(
+            server
+                .get::<Vec<SubscriptionInfo>>(&format!(
+                    "subscriptions/{}/close",
+                    subscriptions[0].id
+                ))
+                .await
+                .unwrap(),
+            vec![]
+        );
+        async_std::future::timeout(std::time::Duration::from_secs(10), events.body_string())
+            .await
+            .expect("stream did not terminate after the subscription was closed")
+            .unwrap();
+
+        // Closing a subscription which is not open is an error.
+        server
+            .get::<Vec<SubscriptionInfo>>(&format!("subscriptions/{}/close", subscriptions[0].id))
+            .await
+            .expect_err("closed a subscription twice");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_cache() {
+        let server = TestServer::new().await;
+        let empty = CacheStats {
+            size: 0,
+            bytes: 0,
+            hits: 0,
+            misses: 0,
+            budget: None,
+            evictions: 0,
+            entries: vec![],
+        };
+        assert_eq!(
+            server.get::<CacheStats>("cache/stats").await.unwrap(),
+            empty
+        );
+
+        // Nothing is cached without an open wallet.
+        server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .expect_err("getinfo succeeded without an open wallet");
+        assert_eq!(
+            server.get::<CacheStats>("cache/stats").await.unwrap(),
+            empty
+        );
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // Repeated reads are answered from the cache. Retry in case the wallet processes a new event
+        // between reads, which would cause a miss.
+        retry(|| async {
+            server.get::<WalletSummary>("getinfo").await.unwrap();
+            server.get::<CacheStats>("cache/stats").await.unwrap().hits > 0
+        })
+        .await;
+        let stats = server.get::<CacheStats>("cache/stats").await.unwrap();
+        assert!(stats.misses > 0);
+        assert!(stats.size > 0);
+        assert!(stats.bytes > 0);
+        let entry = stats
+            .entries
+            .iter()
+            .find(|entry| entry.url == "/getinfo")
+            .unwrap();
+        assert!(entry.hits > 0);
+        assert_eq!(stats.hits, entry.hits);
+
+        // A request which modifies the wallet makes cached responses unreachable, so we don't see
+        // stale data.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        server.post::<PubKey>("newkey/sending").await.unwrap();
+        assert_eq!(
+            server.get::<CacheStats>("cache/stats").await.unwrap().size,
+            0
+        );
+        assert_eq!(
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .sending_keys
+                .len(),
+            info.sending_keys.len() + 1
+        );
+
+        // Clearing the cache resets the stats.
+        server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert!(server.get::<CacheStats>("cache/stats").await.unwrap().size > 0);
+        assert_eq!(
+            server.get::<CacheStats>("cache/clear").await.unwrap(),
+            empty
+        );
+        assert_eq!(
+            server.get::<CacheStats>("cache/stats").await.unwrap(),
+            empty
+        );
+    }
+
+    /// Read the next server-sent event from `lines` as a `(name, data)` pair.
+    async fn next_event(
+        lines: &mut (impl futures::Stream<Item = std::io::Result<String>> + Unpin),
+    ) -> (String, String) {
+        use futures::StreamExt;
+
+        let mut name = String::new();
+        let mut data = String::new();
+        while let Some(line) = lines.next().await {
+            let line = line.unwrap();
+            if let Some(value) = line.strip_prefix("event: ") {
+                name = value.to_string();
+            } else if let Some(value) = line.strip_prefix("data: ") {
+                data += value;
+            } else if line.is_empty() && !name.is_empty() {
+                break;
+            }
+        }
+        (name, data)
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_subscribe_assets() {
+        use async_std::io::prelude::BufReadExt;
+
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+
+        let mut res = server.client.get("subscribe/assets").send().await.unwrap();
+        let mut lines = res.take_body().lines();
+
+        // The first event is the current asset library.
+        let (name, data) = next_event(&mut lines).await;
+        assert_eq!(name, "assets");
+        let initial = serde_json::from_str::<Vec<AssetInfo>>(&data).unwrap();
+        assert!(initial
+            .iter()
+            .any(|asset| asset.definition.code == AssetCode::native()));
+
+        // Receive a record of a new asset, and check that the new asset is streamed.
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        assert!(!initial.iter().any(|info| info.definition.code == asset));
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .unwrap();
+        async_std::future::timeout(std::time::Duration::from_secs(60), async {
+            loop {
+                let (name, data) = next_event(&mut lines).await;
+                assert_eq!(name, "asset");
+                let info = serde_json::from_str::<AssetInfo>(&data).unwrap();
+                if info.definition.code == asset {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("new asset was not streamed");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_events_debounced() {
+        use async_std::io::prelude::BufReadExt;
+
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let native_balance = || async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    minter,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+        };
+        retry(|| async {
+            native_balance().await == Balances::One(DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR.into())
+        })
+        .await;
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        let total_native = match server
+            .get::<BalanceInfo>("getbalance/all")
+            .await
+            .unwrap()
+            .balances
+        {
+            Balances::All { aggregate, .. } => aggregate[&AssetCode::native()],
+            balances => panic!("expected Balances::All, got {:?}", balances),
+        };
+        let mut res = server
+            .client
+            .get("events?debounce_ms=10000")
+            .send()
+            .await
+            .unwrap();
+        let mut lines = res.take_body().lines();
+        retry(|| async {
+            server
+                .get::<Vec<SubscriptionInfo>>("subscriptions")
+                .await
+                .unwrap()
+                .len()
+                == 1
+        })
+        .await;
+
+        // Mint to ourselves several times in quick succession. Each mint generates several ledger
+        // events, but they all fall in the same window.
+        for i in 1..=3 {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                    asset, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async {
+                native_balance().await
+                    == Balances::One((DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR - i).into())
+            })
+            .await;
+        }
+
+        // The subscriber gets one frame, with the net effect of all 3 mints.
+        let (name, data) =
+            async_std::future::timeout(Duration::from_secs(60), next_event(&mut lines))
+                .await
+                .expect("no batch was streamed");
+        assert_eq!(name, "batch");
+        let batch = serde_json::from_str::<EventBatch>(&data).unwrap();
+        assert_eq!(batch.commits, 3);
+        assert_eq!(batch.events, batch.commits + batch.memos + batch.rejects);
+        assert_eq!(
+            batch.balances,
+            vec![
+                (
+                    asset,
+                    BalanceDelta {
+                        before: 0.into(),
+                        after: 30.into()
+                    }
+                ),
+                (
+                    AssetCode::native(),
+                    BalanceDelta {
+                        before: total_native,
+                        after: total_native - 3
+                    }
+                ),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_events_filter() {
+        use async_std::io::prelude::BufReadExt;
+
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // An unknown event kind is rejected before the stream starts.
+        let err = server
+            .client
+            .get("events?events=transaction,bogus")
+            .send()
+            .await
+            .expect_err("subscribed to an unknown event kind");
+        assert!(err.to_string().contains("bogus"), "{}", err);
+
+        // Subscribe to transaction events only, and to every event for comparison.
+        let mut filtered = server
+            .client
+            .get("events?events=transaction")
+            .send()
+            .await
+            .unwrap();
+        let mut filtered = filtered.take_body().lines();
+        let mut all = server.client.get("events").send().await.unwrap();
+        let mut all = all.take_body().lines();
+        retry(|| async {
+            server
+                .get::<Vec<SubscriptionInfo>>("subscriptions")
+                .await
+                .unwrap()
+                .len()
+                == 2
+        })
+        .await;
+        let mint = || async {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                    asset, minter, minter
+                ))
+                .await
+                .unwrap();
+        };
+
+        // A mint is committed, and then its memos are posted, which is not a transaction event.
+        mint().await;
+        let memos = async_std::future::timeout(Duration::from_secs(60), async {
+            loop {
+                let (name, data) = next_event(&mut all).await;
+                if name == "memos" {
+                    break data;
+                }
+            }
+        })
+        .await
+        .expect("no memos event was streamed");
+        let memos = memos.parse::<usize>().unwrap();
+
+        // The filtered stream skips the memos event and delivers the commit of the next mint.
+        mint().await;
+        async_std::future::timeout(Duration::from_secs(60), async {
+            loop {
+                let (name, data) = next_event(&mut filtered).await;
+                assert_eq!(name, "commit");
+                let index = data.parse::<usize>().unwrap();
+                assert_ne!(index, memos);
+                if index > memos {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("no transaction event was streamed");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_subscription_buffer() {
+        use crate::subscriptions::{BufferPolicy, Outbox, StreamEvent};
+        use futures::StreamExt;
+
+        let event = |i: usize| StreamEvent {
+            name: String::from("commit"),
+            data: i.to_string(),
+            id: None,
+        };
+        let dropped = |total: u64| StreamEvent {
+            name: String::from("dropped"),
+            data: total.to_string(),
+            id: None,
+        };
+        let outbox = |overflow| {
+            Outbox::new(BufferPolicy {
+                capacity: 2,
+                overflow,
+            })
+        };
+
+        // In each case, a slow subscriber takes only one event while 4 are sent to a buffer of 2, so
+        // one event overflows. With drop-oldest, the subscriber misses the oldest buffered event.
+        let drop_oldest = outbox(OverflowPolicy::DropOldest);
+        let mut events = Box::pin(drop_oldest.stream());
+        assert!(drop_oldest.send("commit", "0", None));
+        assert!(drop_oldest.send("commit", "1", None));
+        assert_eq!(events.next().await, Some(event(0)));
+        assert!(drop_oldest.send("commit", "2", None));
+        assert!(drop_oldest.send("commit", "3", None));
+        drop(drop_oldest);
+        assert_eq!(
+            events.collect::<Vec<_>>().await,
+            vec![dropped(1), event(2), event(3)]
+        );
+
+        // With drop-newest, the subscriber misses the new event.
+        let drop_newest = outbox(OverflowPolicy::DropNewest);
+        let mut events = Box::pin(drop_newest.stream());
+        assert!(drop_newest.send("commit", "0", None));
+        assert!(drop_newest.send("commit", "1", None));
+        assert_eq!(events.next().await, Some(event(0)));
+        assert!(drop_newest.send("commit", "2", None));
+        assert!(drop_newest.send("commit", "3", None));
+        drop(drop_newest);
+        assert_eq!(
+            events.collect::<Vec<_>>().await,
+            vec![dropped(1), event(1), event(2)]
+        );
+
+        // With disconnect, the buffer is discarded and the stream ends.
+        let disconnect = outbox(OverflowPolicy::Disconnect);
+        let mut events = Box::pin(disconnect.stream());
+        assert!(disconnect.send("commit", "0", None));
+        assert!(disconnect.send("commit", "1", None));
+        assert_eq!(events.next().await, Some(event(0)));
+        assert!(disconnect.send("commit", "2", None));
+        assert!(!disconnect.send("commit", "3", None));
+        assert!(!disconnect.send("commit", "4", None));
+        assert_eq!(events.collect::<Vec<_>>().await, vec![dropped(3)]);
+
+        // The buffer policy of a subscription is set with query parameters.
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        for query in ["buffer=0", "buffer=many", "overflow=bogus"] {
+            server
+                .client
+                .get(&format!("events?{}", query))
+                .send()
+                .await
+                .expect_err(&format!("subscribed with {}", query));
+        }
+        let _events = server
+            .client
+            .get("events?buffer=4&overflow=drop-oldest")
+            .send()
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<Vec<SubscriptionInfo>>("subscriptions")
+                .await
+                .unwrap()
+                .len()
+                == 1
+        })
+        .await;
+        let subscription = server
+            .get::<Vec<SubscriptionInfo>>("subscriptions")
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(subscription.buffer, 4);
+        assert_eq!(subscription.overflow, OverflowPolicy::DropOldest);
+        assert_eq!(subscription.dropped, 0);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_simulate_transfer() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let recipient = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        server
+            .requires_wallet::<TransferSimulation>(&format!(
+                "simulate/transfer/{}/100/{}",
+                AssetCode::native(),
+                recipient
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let aggregate = |info: &BalanceInfo| match &info.balances {
+            Balances::All { aggregate, .. } => aggregate.clone(),
+            balances => panic!("expected Balances::All, got {:?}", balances),
+        };
+        let balance = aggregate(&server.get::<BalanceInfo>("getbalance/all").await.unwrap())
+            [&AssetCode::native()];
+
+        // Simulate a transfer to a remote address. The projected balance is the current balance,
+        // minus the amount, minus the fee.
+        let simulation = server
+            .get::<TransferSimulation>(&format!(
+                "simulate/transfer/{}/100/{}/fee/1",
+                AssetCode::native(),
+                recipient
+            ))
+            .await
+            .unwrap();
+        assert!(!simulation.insufficient_balance);
+        assert!(!simulation.insufficient_fee);
+        assert_eq!(
+            aggregate(&simulation.balances)[&AssetCode::native()],
+            balance - 101
+        );
+
+        // Simulating doesn't change the actual balance.
+        assert_eq!(
+            aggregate(&server.get::<BalanceInfo>("getbalance/all").await.unwrap())
+                [&AssetCode::native()],
+            balance
+        );
+
+        // Simulating a transfer of more than the wallet owns reports the shortfall instead of
+        // failing.
+        let simulation = server
+            .get::<TransferSimulation>(&format!(
+                "simulate/transfer/{}/{}/{}/fee/1",
+                AssetCode::native(),
+                balance,
+                recipient
+            ))
+            .await
+            .unwrap();
+        assert!(!simulation.insufficient_balance);
+        assert!(simulation.insufficient_fee);
+        assert_eq!(
+            aggregate(&simulation.balances)[&AssetCode::native()],
+            U256::zero()
+        );
+        let simulation = server
+            .get::<TransferSimulation>(&format!(
+                "simulate/transfer/{}/{}/{}",
+                AssetCode::native(),
+                balance + 1,
+                recipient
+            ))
+            .await
+            .unwrap();
+        assert!(simulation.insufficient_balance);
+        assert!(!simulation.insufficient_fee);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_auth() {
+        // Without an admin token, authentication is disabled and there is no token to rotate.
+        let server = TestServer::new().await;
+        assert_eq!(
+            server.get::<AuthStatus>("auth/status").await.unwrap(),
+            AuthStatus {
+                enabled: false,
+                token_age_secs: None
+            }
+        );
+        server
+            .post::<String>("auth/rotate")
+            .await
+            .expect_err("rotated admin token with authentication disabled");
+
+        let server = TestServer::with_options(|options| {
+            options.admin_token = Some(String::from("initial-token"));
+        })
+        .await;
+        let get_with_token = |path: &'static str, token: String| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .get(path)
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await?;
+                client::response_body::<AuthStatus>(&mut res).await
+            }
+        };
+        let rotate_with_token = |token: String| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post("auth/rotate")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await?;
+                client::response_body::<String>(&mut res).await
+            }
+        };
+
+        // The status requires the admin token.
+        server
+            .get::<AuthStatus>("auth/status")
+            .await
+            .expect_err("got auth status without a token");
+        get_with_token("auth/status", "wrong-token".into())
+            .await
+            .expect_err("got auth status with the wrong token");
+        let status = get_with_token("auth/status", "initial-token".into())
+            .await
+            .unwrap();
+        assert!(status.enabled);
+        assert!(status.token_age_secs.is_some());
+
+        // The token is stored hashed, not in plaintext.
+        let stored = fs::read_to_string(server.options.admin_token_path())
+            .await
+            .unwrap();
+        assert!(!stored.contains("initial-token"));
+
+        // Rotate the token. The old token is rejected and the new one works.
+        rotate_with_token("wrong-token".into())
+            .await
+            .expect_err("rotated admin token with the wrong token");
+        let token = rotate_with_token("initial-token".into()).await.unwrap();
+        get_with_token("auth/status", "initial-token".into())
+            .await
+            .expect_err("old admin token still accepted after rotation");
+        assert!(
+            get_with_token("auth/status", token.clone())
+                .await
+                .unwrap()
+                .enabled
+        );
+        rotate_with_token("initial-token".into())
+            .await
+            .expect_err("rotated admin token with the old token");
+
+        // The new token is also stored hashed.
+        let stored = fs::read_to_string(server.options.admin_token_path())
+            .await
+            .unwrap();
+        assert!(!stored.contains(&token));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_import_contacts() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let alice = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        let bob = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        let carol = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![]
+        );
+
+        let import = |path: &'static str, entries: Vec<ContactEntry>| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post(path)
+                    .body_json(&entries)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap();
+                client::response_body::<Vec<ContactImportResult>>(&mut res)
+                    .await
+                    .unwrap()
+            }
+        };
+        let entry = |name: &str, address: String| ContactEntry {
+            name: name.to_string(),
+            address,
+        };
+        let result = |name: &str, status| ContactImportResult {
+            name: name.to_string(),
+            status,
+        };
+
+        // Import a list with one invalid address. The valid entries are added and the invalid one
+        // is reported.
+        let results = import(
+            "contacts/import",
+            vec![
+                entry("alice", alice.to_string()),
+                entry("bob", String::from("not-an-address")),
+                entry("carol", carol.to_string()),
+            ],
+        )
+        .await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], result("alice", ContactImportStatus::Added));
+        assert!(matches!(
+            results[1].status,
+            ContactImportStatus::Invalid { .. }
+        ));
+        assert_eq!(results[2], result("carol", ContactImportStatus::Added));
+        let contact = |name: &str, address: &UserAddress| Contact {
+            name: name.to_string(),
+            address: address.clone(),
+        };
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![contact("alice", &alice), contact("carol", &carol)]
+        );
+
+        // By default, duplicate names are skipped.
+        assert_eq!(
+            import("contacts/import", vec![entry("alice", bob.to_string())]).await,
+            vec![result("alice", ContactImportStatus::Skipped)]
+        );
+        assert_eq!(
+            import(
+                "contacts/import/skip",
+                vec![entry("alice", bob.to_string())]
+            )
+            .await,
+            vec![result("alice", ContactImportStatus::Skipped)]
+        );
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![contact("alice", &alice), contact("carol", &carol)]
+        );
+
+        // With the overwrite policy, they replace the existing contact.
+        assert_eq!(
+            import(
+                "contacts/import/overwrite",
+                vec![entry("alice", bob.to_string())]
+            )
+            .await,
+            vec![result("alice", ContactImportStatus::Overwritten)]
+        );
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![contact("alice", &bob), contact("carol", &carol)]
+        );
+
+        // Importing requires a POST.
+        server
+            .get::<Vec<ContactImportResult>>("contacts/import")
+            .await
+            .expect_err("imported contacts with a GET request");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_corrupt_state() {
+        // A corrupt state file is set aside, rather than preventing the server from starting.
+        let server = TestServer::with_options(|options| {
+            std::fs::write(options.contacts_path(), "not json").unwrap();
+        })
+        .await;
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![]
+        );
+        let mut corrupt = server.options().contacts_path().into_os_string();
+        corrupt.push(".corrupt");
+        assert_eq!(std::fs::read(corrupt).unwrap(), b"not json");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_exporttransaction() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let dst_address = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+
+        // Should fail if a wallet is not already open.
+        server.requires_wallet::<Vec<Record>>("pendingspends").await;
+        server
+            .requires_wallet_post::<ExportedTransaction>(&format!(
+                "exporttransaction/asset/{}/recipient/{}/amount/100/fee/1",
+                &AssetCode::native(),
+                dst_address,
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        assert_eq!(
+            server.get::<Vec<Record>>("pendingspends").await.unwrap(),
+            vec![]
+        );
+
+        // Export a transfer.
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let exported = server
+            .post::<ExportedTransaction>(&format!(
+                "exporttransaction/sender/{}/asset/{}/recipient/{}/amount/100/fee/1",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+            ))
+            .await
+            .unwrap();
+        assert!(exported.hash.is_some());
+        let submission: relayer::SubmitBody =
+            bincode::deserialize(&::base64::decode(&exported.transaction).unwrap()).unwrap();
+        assert!(!submission.memos.is_empty());
+
+        // The inputs of the exported transaction are reserved, even though it was never submitted.
+        assert!(!exported.inputs.is_empty());
+        let pending = server.get::<Vec<Record>>("pendingspends").await.unwrap();
+        for input in &exported.inputs {
+            assert_eq!(input.address, src_address);
+            assert!(pending.contains(input));
+        }
+
+        // Exporting doesn't submit anything, so the transfer does not appear in the history.
+        let history = server
+            .get::<Vec<TransactionHistoryEntry>>("transactionhistory")
+            .await
+            .unwrap();
+        assert!(!history.iter().any(|entry| entry.hash == exported.hash));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_swap() {
+        let server = TestServer::new().await;
+        let password = base64("my-password".as_bytes());
+        let proposer_path = server.path();
+        let counterparty_path = fmt_path(
+            &[server.temp_dir.path(), Path::new("keystores/counterparty")]
+                .iter()
+                .collect::<PathBuf>(),
+        );
+        // Switch to another wallet on the same ledger.
+        let switch = |path: &str| {
+            let server = &server;
+            let password = &password;
+            let path = path.to_string();
+            async move {
+                server.post::<()>("shareledgerfortest").await.unwrap();
+                server.post::<()>("closewallet").await.unwrap();
+                server
+                    .post::<()>(&format!("openwallet/{}/path/{}", password, path))
+                    .await
+                    .unwrap();
+            }
+        };
+        let balance = |address: &UserAddress, asset: AssetCode| {
+            let server = &server;
+            let address = address.clone();
+            async move {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", address, asset))
+                    .await
+                    .unwrap()
+                    .balances
+            }
+        };
+        let accept = |proposal: &SwapProposal| {
+            let server = &server;
+            let proposal = proposal.clone();
+            async move {
+                server
+                    .client
+                    .post("swap/accept/fee/1")
+                    .body_json(&proposal)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // The proposer has the native asset and 10 units of a new asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                password,
+                proposer_path
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let proposer: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, proposer, proposer
+            ))
+            .await
+            .unwrap();
+        retry(|| async { balance(&proposer, asset).await == Balances::One(10u64.into()) }).await;
+
+        // The counterparty is a different wallet on the same ledger, with 50 of the native asset.
+        server.post::<()>("shareledgerfortest").await.unwrap();
+        server.post::<()>("closewallet").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                password,
+                counterparty_path
+            ))
+            .await
+            .unwrap();
+        let counterparty = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        switch(&proposer_path).await;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/50/fee/1",
+                proposer,
+                AssetCode::native(),
+                counterparty
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            balance(&proposer, AssetCode::native()).await
+                == Balances::One((DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR - 52).into())
+        })
+        .await;
+
+        // Offer 5 of the new asset for 20 of the native asset.
+        let proposal = server
+            .post::<SwapProposal>(&format!(
+                "swap/propose/sender/{}/offer/{}/offer_amount/5/request/{}/request_amount/20/counterparty/{}/fee/1",
+                proposer,
+                asset,
+                AssetCode::native(),
+                counterparty
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            proposal.terms,
+            SwapTerms {
+                proposer: proposer.clone(),
+                counterparty: counterparty.clone(),
+                offered_asset: asset,
+                offered_amount: 5u64.into(),
+                requested_asset: AssetCode::native(),
+                requested_amount: 20u64.into(),
+            }
+        );
+        // Only the counterparty can accept.
+        assert_eq!(
+            accept(&proposal).await.status(),
+            surf::StatusCode::BadRequest
+        );
+
+        // The counterparty rejects a proposal whose terms have been changed.
+        switch(&counterparty_path).await;
+        retry(|| async {
+            balance(&counterparty, AssetCode::native()).await == Balances::One(50u64.into())
+        })
+        .await;
+        let mut tampered = proposal.clone();
+        tampered.terms.offered_amount = 10u64.into();
+        assert_eq!(
+            accept(&tampered).await.status(),
+            surf::StatusCode::BadRequest
+        );
+        assert_eq!(
+            balance(&counterparty, AssetCode::native()).await,
+            Balances::One(50u64.into())
+        );
+
+        // Accepting the proposal moves both assets in the same block.
+        let mut res = accept(&proposal).await;
+        client::response_body::<TransactionReceipt<CapeLedger>>(&mut res)
+            .await
+            .unwrap();
+        retry(|| async { balance(&counterparty, asset).await == Balances::One(5u64.into()) }).await;
+        assert_eq!(
+            balance(&counterparty, AssetCode::native()).await,
+            Balances::One(29u64.into())
+        );
+
+        // The proposer sees the other side of the swap.
+        switch(&proposer_path).await;
+        retry(|| async { balance(&proposer, asset).await == Balances::One(5u64.into()) }).await;
+        assert_eq!(
+            balance(&proposer, AssetCode::native()).await,
+            Balances::One((DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR - 53 + 20).into())
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_params() {
+        let server = TestServer::new().await;
+
+        // No wallet is required.
+        let params = server.get::<ProofParams>("params").await.unwrap();
+        assert_eq!(params, ProofParams::cape());
+        assert_eq!(params.merkle_height, CAPE_MERKLE_HEIGHT);
+        assert_eq!(params.transfer_sizes, SUPPORTED_TRANSFER_SIZES);
+        assert_eq!(
+            params.max_transfer_inputs,
+            SUPPORTED_TRANSFER_SIZES
+                .iter()
+                .map(|(inputs, _)| *inputs)
+                .max()
+                .unwrap()
+        );
+        assert!(params
+            .transfer_sizes
+            .iter()
+            .all(|(inputs, outputs)| *inputs <= params.max_transfer_inputs
+                && *outputs <= params.max_transfer_outputs));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_assetmetadata() {
+        let server = TestServer::new().await;
+        let password = base64("my-password".as_bytes());
+        server
+            .requires_wallet::<AssetInfo>(&format!("getasset/{}", AssetCode::native()))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                password,
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let description = base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD);
+        let asset = server
+            .post::<AssetInfo>(&format!("newasset/description/{}", description))
+            .await
+            .unwrap();
+        let code = asset.definition.code;
+        assert_eq!(asset.metadata, None);
+
+        let set_metadata = |metadata: AssetMetadata| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post(format!("assetmetadata/{}", code))
+                    .body_json(&metadata)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<AssetInfo>(&mut res).await
+            }
+        };
+
+        // Invalid metadata is rejected.
+        set_metadata(AssetMetadata {
+            icon_url: Some(String::from("not a url")),
+            ..Default::default()
+        })
+        .await
+        .expect_err("set metadata with an invalid URL");
+        set_metadata(AssetMetadata {
+            website: Some(String::from("ftp://example.com")),
+            ..Default::default()
+        })
+        .await
+        .expect_err("set metadata with a non-HTTP URL");
+        set_metadata(AssetMetadata {
+            website: Some(format!(
+                "https://example.com/{}",
+                "a".repeat(MAX_ASSET_METADATA_URL_LEN)
+            )),
+            ..Default::default()
+        })
+        .await
+        .expect_err("set metadata with an overlong URL");
+        set_metadata(AssetMetadata {
+            decimals: Some(39),
+            ..Default::default()
+        })
+        .await
+        .expect_err("set metadata with too many decimals");
+        assert_eq!(
+            server
+                .get::<AssetInfo>(&format!("getasset/{}", code))
+                .await
+                .unwrap()
+                .metadata,
+            None
+        );
+
+        // Set valid metadata.
+        let metadata = AssetMetadata {
+            icon_url: Some(String::from("https://example.com/icon.png")),
+            website: Some(String::from("https://example.com")),
+            decimals: Some(6),
+        };
+        let info = set_metadata(metadata.clone()).await.unwrap();
+        assert_eq!(info.definition.code, code);
+        assert_eq!(info.metadata, Some(metadata.clone()));
+
+        // Reopen the wallet and check that the metadata persists.
+        server.post::<()>("closewallet").await.unwrap();
+        server
+            .post::<()>(&format!("openwallet/{}/path/{}", password, server.path()))
+            .await
+            .unwrap();
+        let info = server
+            .get::<AssetInfo>(&format!("getasset/{}", code))
+            .await
+            .unwrap();
+        assert_eq!(info.metadata, Some(metadata));
+
+        // Metadata can only be set for assets in the library.
+        let unknown = AssetCode::random(&mut ChaChaRng::from_seed([7u8; 32])).0;
+        let res = server
+            .client
+            .post(format!("assetmetadata/{}", unknown))
+            .body_json(&AssetMetadata::default())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert!(!res.status().is_success());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_metadata_bundle() {
+        let post_json = |server: &TestServer, path: String, body: serde_json::Value| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client.post(path).body_json(&body).unwrap().send().await?;
+                client::response_body::<serde_json::Value>(&mut res).await
+            }
+        };
+
+        // Set up some metadata in one wallet.
+        let src = TestServer::new().await;
+        src.requires_wallet::<MetadataBundle>("exportmetadata")
+            .await;
+        src.post::<()>(&format!(
+            "newwallet/{}/{}/path/{}",
+            src.get::<String>("getmnemonic").await.unwrap(),
+            base64("my-password".as_bytes()),
+            src.path()
+        ))
+        .await
+        .unwrap();
+        let alice =
+            UserAddress::from(UserKeyPair::generate(&mut ChaChaRng::from_seed([1; 32])).address());
+        post_json(
+            &src,
+            String::from("contacts/import"),
+            serde_json::json!([{ "name": "alice", "address": alice.to_string() }]),
+        )
+        .await
+        .unwrap();
+        let asset = src
+            .post::<AssetInfo>(&format!(
+                "newasset/symbol/{}/description/{}",
+                base64::encode_config("TOKEN".as_bytes(), base64::URL_SAFE_NO_PAD),
+                base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD),
+            ))
+            .await
+            .unwrap();
+        let code = asset.definition.code;
+        let metadata = AssetMetadata {
+            website: Some(String::from("https://example.com")),
+            decimals: Some(2),
+            ..Default::default()
+        };
+        post_json(
+            &src,
+            format!("assetmetadata/{}", code),
+            serde_json::to_value(&metadata).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        // The exported bundle has the contacts and annotations, but no mint info.
+        let bundle = src.get::<MetadataBundle>("exportmetadata").await.unwrap();
+        assert_eq!(bundle.version, METADATA_BUNDLE_VERSION);
+        assert_eq!(
+            bundle.contacts,
+            vec![Contact {
+                name: String::from("alice"),
+                address: alice.clone(),
+            }]
+        );
+        let exported = bundle
+            .assets
+            .iter()
+            .find(|info| info.definition.code == code)
+            .unwrap();
+        assert_eq!(exported.symbol, Some(String::from("TOKEN")));
+        assert_eq!(exported.metadata, Some(metadata.clone()));
+        assert!(bundle.assets.iter().all(|info| info.mint_info.is_none()));
+
+        // Import the bundle into a different wallet on a different server.
+        let dst = TestServer::new().await;
+        dst.post::<()>(&format!(
+            "newwallet/{}/{}/path/{}",
+            dst.get::<String>("getmnemonic").await.unwrap(),
+            base64("my-password".as_bytes()),
+            dst.path()
+        ))
+        .await
+        .unwrap();
+
+        // A bundle with the wrong version is rejected without importing anything.
+        let mut incompatible = serde_json::to_value(&bundle).unwrap();
+        incompatible["version"] = serde_json::json!(METADATA_BUNDLE_VERSION + 1);
+        post_json(&dst, String::from("importmetadata"), incompatible)
+            .await
+            .expect_err("imported an incompatible metadata bundle");
+        assert_eq!(dst.get::<Vec<Contact>>("contacts").await.unwrap(), vec![]);
+
+        let report: MetadataImportReport = serde_json::from_value(
+            post_json(
+                &dst,
+                String::from("importmetadata"),
+                serde_json::to_value(&bundle).unwrap(),
+            )
+            .await
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            report.contacts,
+            vec![ContactImportResult {
+                name: String::from("alice"),
+                status: ContactImportStatus::Added,
+            }]
+        );
+        assert!(report.assets.added_assets.contains(&code));
+        assert_eq!(report.metadata, vec![code]);
+
+        // The destination now has the same metadata, so exporting it gives back the same entries.
+        let reexported = dst.get::<MetadataBundle>("exportmetadata").await.unwrap();
+        assert_eq!(reexported.contacts, bundle.contacts);
+        assert!(reexported.assets.contains(exported));
+        let imported = dst
+            .get::<AssetInfo>(&format!("getasset/{}", code))
+            .await
+            .unwrap();
+        assert_eq!(imported.symbol, Some(String::from("TOKEN")));
+        assert_eq!(imported.metadata, Some(metadata));
+        assert_eq!(imported.mint_info, None);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_backups() {
+        let src = TestServer::new().await;
+        src.requires_wallet::<Vec<BackupInfo>>("backups").await;
+        let mnemonic = src.get::<String>("getmnemonic").await.unwrap();
+        src.post::<()>(&format!(
+            "newwallet/{}/{}/path/{}",
+            mnemonic,
+            base64("my-password".as_bytes()),
+            src.path()
+        ))
+        .await
+        .unwrap();
+
+        // Backing up requires a directory.
+        src.get::<BackupInfo>("backup/now")
+            .await
+            .expect_err("backed up without a backup directory");
+
+        // Set up some metadata to back up.
+        let alice =
+            UserAddress::from(UserKeyPair::generate(&mut ChaChaRng::from_seed([1; 32])).address());
+        let mut res = src
+            .client
+            .post("contacts/import")
+            .body_json(&serde_json::json!([{ "name": "alice", "address": alice.to_string() }]))
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        client::response_body::<Vec<ContactImportResult>>(&mut res)
+            .await
+            .unwrap();
+        let code = src
+            .post::<AssetInfo>(&format!(
+                "newasset/symbol/{}/description/{}",
+                base64("TOKEN".as_bytes()),
+                base64(&[3u8; 32]),
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let bundle = src.get::<MetadataBundle>("exportmetadata").await.unwrap();
+
+        // Back up immediately, keeping two backups.
+        let dir = src.temp_dir.path().join("backups");
+        let policy = src
+            .post::<BackupPolicy>(&format!("backup/schedule/0/{}/keep/2", fmt_path(&dir)))
+            .await
+            .unwrap();
+        assert_eq!(policy.dir, dir);
+        assert_eq!(policy.keep, 2);
+        let first = src.get::<BackupInfo>("backup/now").await.unwrap();
+        let contents = fs::read(dir.join(&first.name)).await.unwrap();
+        assert_eq!(contents.len() as u64, first.size);
+        // The backup is encrypted.
+        let contents = String::from_utf8_lossy(&contents);
+        assert!(!contents.contains("alice"));
+        assert!(!contents.contains("TOKEN"));
+        assert_eq!(
+            src.get::<Vec<BackupInfo>>("backups").await.unwrap(),
+            vec![first.clone()]
+        );
+
+        // Only the newest backups are kept.
+        let second = src.get::<BackupInfo>("backup/now").await.unwrap();
+        let third = src.get::<BackupInfo>("backup/now").await.unwrap();
+        assert!(first.created < second.created && second.created < third.created);
+        assert_eq!(
+            src.get::<Vec<BackupInfo>>("backups").await.unwrap(),
+            vec![second, third.clone()]
+        );
+        assert!(!dir.join(&first.name).exists());
+
+        // A wallet with a different mnemonic on another server can't see or restore the backups.
+        let dst = TestServer::new().await;
+        let other_path = dst.temp_dir.path().join("keystores/other");
+        dst.post::<()>(&format!(
+            "newwallet/{}/{}/path/{}",
+            dst.get::<String>("getmnemonic").await.unwrap(),
+            base64("my-password".as_bytes()),
+            fmt_path(&other_path)
+        ))
+        .await
+        .unwrap();
+        dst.post::<BackupPolicy>(&format!("backup/schedule/0/{}", fmt_path(&dir)))
+            .await
+            .unwrap();
+        assert_eq!(dst.get::<Vec<BackupInfo>>("backups").await.unwrap(), vec![]);
+        dst.post::<MetadataImportReport>(&format!("backup/restore/{}", third.name))
+            .await
+            .expect_err("restored a backup of another wallet");
+
+        // Restoring into a wallet with the same mnemonic reproduces the metadata.
+        dst.post::<()>("closewallet").await.unwrap();
+        dst.post::<()>(&format!(
+            "newwallet/{}/{}/path/{}",
+            mnemonic,
+            base64("my-password".as_bytes()),
+            dst.path()
+        ))
+        .await
+        .unwrap();
+        dst.post::<BackupPolicy>(&format!("backup/schedule/0/{}", fmt_path(&dir)))
+            .await
+            .unwrap();
+        let report = dst
+            .post::<MetadataImportReport>(&format!("backup/restore/{}", third.name))
+            .await
+            .unwrap();
+        assert!(report.assets.added_assets.contains(&code));
+        let restored = dst.get::<MetadataBundle>("exportmetadata").await.unwrap();
+        assert_eq!(restored.contacts, bundle.contacts);
+        for info in &bundle.assets {
+            assert!(restored.assets.contains(info));
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_accounts() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<AccountSummary>>("accounts")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let recipient: UserAddress = info.sending_keys[0].address().into();
+
+        // Define an asset which this wallet can view and freeze, and mint some to `recipient`.
+        let description = base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD);
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/description/{}/freezing_key/{}/viewing_key/{}",
+                description, info.freezing_keys[0], info.viewing_keys[0]
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, minter, recipient
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<Vec<AccountSummary>>("accounts")
+                .await
+                .unwrap()
+                .into_iter()
+                .any(|account| {
+                    account.address == recipient
+                        && account.balances.get(&asset) == Some(&U256::from(10))
+                })
+        })
+        .await;
+
+        // There is one account for each address, and each has the same balances as `getbalance`.
+        let accounts = server.get::<Vec<AccountSummary>>("accounts").await.unwrap();
+        assert_eq!(accounts.len(), info.addresses.len());
+        for account in &accounts {
+            assert!(info.addresses.contains(&account.address));
+            assert_eq!(
+                UserAddress::from(account.pub_key.address()),
+                account.address
+            );
+            for (code, balance) in &account.balances {
+                assert_eq!(
+                    server
+                        .get::<BalanceInfo>(&format!(
+                            "getbalance/address/{}/asset/{}",
+                            account.address, code
+                        ))
+                        .await
+                        .unwrap()
+                        .balances,
+                    Balances::One(*balance)
+                );
+            }
+        }
+
+        // The new asset is associated with the recipient, which can view and freeze it.
+        let recipient_account = accounts
+            .iter()
+            .find(|account| account.address == recipient)
+            .unwrap();
+        assert!(recipient_account.viewable_assets.contains(&asset));
+        assert!(recipient_account.freezable_assets.contains(&asset));
+        for account in &accounts {
+            if account.address != recipient {
+                assert!(account
+                    .balances
+                    .get(&asset)
+                    .map_or(true, |balance| balance.is_zero()));
+            }
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_scanconfig() {
+        let server = TestServer::new().await;
+
+        // The scan configuration does not require a wallet, and defaults to polling every
+        // `min_polling_delay`.
+        assert_eq!(
+            server.get::<ScanConfig>("scanconfig").await.unwrap(),
+            ScanConfig {
+                polling: true,
+                interval_ms: Some(500)
+            }
+        );
+
+        // Changing the configuration requires a POST, and the interval must be positive.
+        server
+            .get::<ScanConfig>("scanconfig/interval/100")
+            .await
+            .expect_err("scanconfig changed with a GET request");
+        server
+            .post::<ScanConfig>("scanconfig/interval/0")
+            .await
+            .expect_err("scan interval set to 0");
+        assert_eq!(
+            server.get::<ScanConfig>("scanconfig").await.unwrap(),
+            ScanConfig {
+                polling: true,
+                interval_ms: Some(500)
+            }
+        );
+
+        // Polling can be disabled and re-enabled.
+        assert_eq!(
+            server
+                .post::<ScanConfig>("scanconfig/disable")
+                .await
+                .unwrap(),
+            ScanConfig {
+                polling: false,
+                interval_ms: None
+            }
+        );
+        assert_eq!(
+            server.get::<ScanConfig>("scanconfig").await.unwrap(),
+            ScanConfig {
+                polling: false,
+                interval_ms: None
+            }
+        );
+        let interval = Duration::from_millis(100);
+        assert_eq!(
+            server
+                .post::<ScanConfig>(&format!("scanconfig/interval/{}", interval.as_millis()))
+                .await
+                .unwrap(),
+            ScanConfig {
+                polling: true,
+                interval_ms: Some(100)
+            }
+        );
+
+        // Open a wallet after setting the interval, and check that a new record is discovered
+        // promptly. Allow some slack on top of the interval for the ledger to process the block.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .unwrap();
+        async_std::future::timeout(interval + Duration::from_secs(5), async {
+            while !server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .iter()
+                .any(|record| record.ro.asset_def.code == asset)
+            {
+                async_std::task::sleep(interval).await;
+            }
+        })
+        .await
+        .expect("new record was not discovered");
+
+        // The initial configuration can be set on the command line, where 0 disables polling.
+        let server = TestServer::with_options(|options| options.scan_interval_ms = Some(0)).await;
+        assert_eq!(
+            server.get::<ScanConfig>("scanconfig").await.unwrap(),
+            ScanConfig {
+                polling: false,
+                interval_ms: None
+            }
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_subaccounts() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<SubAccount>>("subaccounts")
+            .await;
+        server
+            .requires_wallet::<UserAddress>("subaccount/0/address")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Derivation is deterministic, and different indices yield different addresses.
+        let address0 = server
+            .get::<UserAddress>("subaccount/0/address")
+            .await
+            .unwrap();
+        assert_eq!(
+            address0,
+            server
+                .get::<UserAddress>("subaccount/0/address")
+                .await
+                .unwrap()
+        );
+        let address1 = server
+            .get::<UserAddress>("subaccount/1/address")
+            .await
+            .unwrap();
+        assert_ne!(address0, address1);
+
+        // `subaccounts` derives the same addresses.
+        let subaccounts = server
+            .get::<Vec<SubAccount>>("subaccounts/2")
+            .await
+            .unwrap();
+        assert_eq!(subaccounts.len(), 2);
+        assert_eq!(subaccounts[0].index, 0);
+        assert_eq!(subaccounts[0].address, address0);
+        assert_eq!(subaccounts[1].index, 1);
+        assert_eq!(subaccounts[1].address, address1);
+        for subaccount in &subaccounts {
+            assert_eq!(
+                subaccount.address,
+                UserAddress::from(subaccount.pub_key.address())
+            );
+            if !subaccount.tracked {
+                assert!(subaccount.balances.is_empty());
+            }
+        }
+
+        // Too many sub-accounts is an error.
+        server
+            .get::<Vec<SubAccount>>("subaccounts/1001")
+            .await
+            .expect_err("derived more than the maximum number of sub-accounts");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_cansend() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([43u8; 32]);
+        let recipient = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        server
+            .requires_wallet::<CanSend>(&format!("cansend/{}/1/{}", AssetCode::native(), recipient))
+            .await;
+
+        // An empty wallet cannot send anything.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let res = server
+            .get::<CanSend>(&format!("cansend/{}/1/{}", AssetCode::native(), recipient))
+            .await
+            .unwrap();
+        assert!(!res.ok);
+        assert_eq!(res.code.unwrap(), "insufficient_balance");
+        assert!(res.reason.is_some());
+
+        // Mint 3 separate records of a new asset to ourselves.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let asset_records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == asset)
+                .count()
+        };
+        for i in 1..=3 {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/1/fee/1/minter/{}/recipient/{}",
+                    asset, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { asset_records().await == i }).await;
+        }
+
+        // We can send 2 units, using 2 records plus a fee record, but 3 units would take too many
+        // records.
+        let res = server
+            .get::<CanSend>(&format!("cansend/{}/2/{}/fee/1", asset, recipient))
+            .await
+            .unwrap();
+        assert_eq!(
+            res,
+            CanSend {
+                ok: true,
+                reason: None,
+                code: None
+            }
+        );
+        let res = server
+            .get::<CanSend>(&format!("cansend/{}/3/{}/fee/1", asset, recipient))
+            .await
+            .unwrap();
+        assert!(!res.ok);
+        assert_eq!(res.code.unwrap(), "fragmented");
+
+        // A fee larger than any native record cannot be paid.
+        let largest_native = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.ro.asset_def.code == AssetCode::native())
+            .map(|record| record.ro.amount.generic_into::<u128>())
+            .max()
+            .unwrap();
+        let res = server
+            .get::<CanSend>(&format!(
+                "cansend/{}/1/{}/fee/{}",
+                asset,
+                recipient,
+                largest_native + 1
+            ))
+            .await
+            .unwrap();
+        assert!(!res.ok);
+        assert_eq!(res.code.unwrap(), "no_fee_record");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_mergewallet() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let password = base64("my-password".as_bytes());
+        // The other wallet belongs to another server, with its own storage directory.
+        let other_storage = server.temp_dir.path().join("other");
+        std::fs::create_dir_all(other_storage.join("keystores")).unwrap();
+        let other_path = fmt_path(&other_storage.join("keystores/other_wallet"));
+        server
+            .requires_wallet_post::<MergeReport>(&format!(
+                "mergewallet/{}/{}/path/{}",
+                mnemonic, password, other_path
+            ))
+            .await;
+
+        let annotate = |asset: AssetCode, symbol: &'static str| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post(format!("updateasset/{}", asset))
+                    .body_json(&UpdateAsset {
+                        symbol: Some(symbol.to_string()),
+                        ..Default::default()
+                    })
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap();
+                client::response_body::<AssetInfo>(&mut res).await.unwrap()
+            }
+        };
+
+        // Create two wallets from the same mnemonic, each with a different annotated asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic, password, other_path
+            ))
+            .await
+            .unwrap();
+        let theirs = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        annotate(theirs, "THEIRS").await;
+
+        // The other wallet has also generated keys and submitted transactions which the open wallet
+        // has not, and the other server has an address book.
+        let history = || async {
+            server
+                .get::<(Vec<TransactionHistoryEntry>, HashMap<AssetCode, AssetInfo>)>(
+                    "transactionhistory",
+                )
+                .await
+                .unwrap()
+                .0
+                .into_iter()
+                .filter_map(|entry| entry.hash)
+                .collect::<HashSet<_>>()
+        };
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let their_info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let their_history = history().await;
+        assert!(!their_history.is_empty());
+        let alice = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        let mut their_contacts = Contacts::default();
+        their_contacts.import(
+            vec![ContactEntry {
+                name: String::from("alice"),
+                address: alice.to_string(),
+            }],
+            DuplicatePolicy::Skip,
+        );
+        their_contacts
+            .save(&other_storage.join("contacts"))
+            .unwrap();
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                password,
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let ours = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        annotate(ours, "OURS").await;
+        server
+            .get::<AssetInfo>(&format!("getasset/{}", theirs))
+            .await
+            .expect_err("asset from other wallet known before merge");
+
+        // A wallet cannot be merged with a different mnemonic, or into itself.
+        server
+            .post::<MergeReport>(&format!(
+                "mergewallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                password,
+                other_path
+            ))
+            .await
+            .expect_err("merged wallet with a different mnemonic");
+        server
+            .post::<MergeReport>(&format!(
+                "mergewallet/{}/{}/path/{}",
+                mnemonic,
+                password,
+                server.path()
+            ))
+            .await
+            .expect_err("merged wallet into itself");
+
+        // After merging, the open wallet has both assets, with their annotations.
+        let report = server
+            .post::<MergeReport>(&format!(
+                "mergewallet/{}/{}/path/{}",
+                mnemonic, password, other_path
+            ))
+            .await
+            .unwrap();
+        assert!(report.added_assets.contains(&theirs));
+        assert!(!report.added_assets.contains(&ours));
+        assert_eq!(report.conflicts, vec![]);
+        assert_eq!(
+            server
+                .get::<AssetInfo>(&format!("getasset/{}", theirs))
+                .await
+                .unwrap()
+                .symbol,
+            Some(String::from("THEIRS"))
+        );
+        assert_eq!(
+            server
+                .get::<AssetInfo>(&format!("getasset/{}", ours))
+                .await
+                .unwrap()
+                .symbol,
+            Some(String::from("OURS"))
+        );
+
+        // It also has the other server's contacts, the keys the other wallet generated, and the
+        // other wallet's transactions.
+        assert_eq!(report.added_contacts, vec![String::from("alice")]);
+        assert_eq!(
+            server.get::<Vec<Contact>>("contacts").await.unwrap(),
+            vec![Contact {
+                name: String::from("alice"),
+                address: alice,
+            }]
+        );
+        assert!(!report.added_sending_keys.is_empty());
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        for key in their_info.sending_keys {
+            // `populatefortest` imported the faucet key, which cannot be derived from the mnemonic.
+            if key.address() != receipt.submitters[0] {
+                assert!(info.sending_keys.contains(&key));
+            }
+        }
+        for key in their_info.viewing_keys {
+            assert!(info.viewing_keys.contains(&key));
+        }
+        for key in their_info.freezing_keys {
+            assert!(info.freezing_keys.contains(&key));
+        }
+        assert!(history().await.is_superset(&their_history));
+
+        // Merging again is a no-op.
+        assert_eq!(
+            server
+                .post::<MergeReport>(&format!(
+                    "mergewallet/{}/{}/path/{}",
+                    mnemonic, password, other_path
+                ))
+                .await
+                .unwrap(),
+            MergeReport::default()
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_pretty() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let get_string = |path: &'static str| {
+            let client = server.client.clone();
+            async move {
+                client
+                    .get(path)
+                    .send()
+                    .await
+                    .unwrap()
+                    .body_string()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Responses are compact by default.
+        let compact = get_string("getinfo").await;
+        assert!(!compact.contains('\n'));
+
+        // With `pretty=true`, the response is indented, but still a valid `WalletSummary`.
+        let pretty = get_string("getinfo?pretty=true").await;
+        assert!(pretty.contains("\n  "));
+        assert_eq!(
+            serde_json::from_str::<WalletSummary>(&pretty).unwrap(),
+            serde_json::from_str::<WalletSummary>(&compact).unwrap()
+        );
+
+        // Errors are not affected.
+        let error = get_string("getbalance/address/invalid?pretty=true").await;
+        assert!(!error.contains('\n'));
+
+        // The default can be changed on the command line, and overridden per request.
+        let server = TestServer::with_options(|options| options.pretty_json = true).await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let get_string = |path: &'static str| {
+            let client = server.client.clone();
+            async move {
+                client
+                    .get(path)
+                    .send()
+                    .await
+                    .unwrap()
+                    .body_string()
+                    .await
+                    .unwrap()
+            }
+        };
+        assert!(get_string("getinfo").await.contains("\n  "));
+        assert!(!get_string("getinfo?pretty=false").await.contains('\n'));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_error_detail() {
+        let server = TestServer::new().await;
+        let password = base64("my-password".as_bytes());
+        let url = format!("openwallet/{}/path/{}", password, server.path());
+
+        // Send requests without the client's error parsing middleware, so we can see the raw body
+        // of each error response.
+        let client: surf::Client = surf::Config::new()
+            .set_base_url(Url::parse(&format!("http://localhost:{}", server.options.port)).unwrap())
+            .set_timeout(None)
+            .try_into()
+            .unwrap();
+        let post_error = |url: String| {
+            let client = client.clone();
+            async move {
+                let mut res = client.post(url).send().await.unwrap();
+                assert!(!res.status().is_success());
+                (res.status(), res.body_string().await.unwrap())
+            }
+        };
+
+        // By default, opening a wallet which doesn't exist fails with just the error.
+        let (status, terse) = post_error(url.clone()).await;
+        let error = serde_json::from_str::<serde_json::Value>(&terse).unwrap();
+        serde_json::from_value::<CapeAPIError>(error.clone()).unwrap();
+        assert!(serde_json::from_str::<VerboseError>(&terse).is_err());
+
+        // With `verbose=true`, the same error is explained, without revealing the password.
+        let (verbose_status, verbose) = post_error(format!("{}?verbose=true", url)).await;
+        assert_eq!(verbose_status, status);
+        assert!(!verbose.contains(&password));
+        let verbose = serde_json::from_str::<VerboseError>(&verbose).unwrap();
+        assert_eq!(verbose.error, error);
+        let route_doc = verbose.route_doc.unwrap();
+        assert!(route_doc.contains("Route matches request: openwallet/:password/path/:path"));
+        assert!(route_doc.contains("<redacted>"));
+
+        // The `verbose` parameter must be a boolean.
+        let (status, body) = post_error(format!("{}?verbose=maybe", url)).await;
+        assert_eq!(status, surf::StatusCode::BadRequest);
+        serde_json::from_str::<CapeAPIError>(&body).unwrap();
+
+        // The default level can be changed at run time, and overridden per request.
+        assert_eq!(
+            server.get::<ErrorDetail>("errordetail").await.unwrap(),
+            ErrorDetail::Terse
+        );
+        server
+            .get::<ErrorDetail>("errordetail/verbose")
+            .await
+            .expect_err("errordetail set the level with a GET request");
+        server
+            .post::<ErrorDetail>("errordetail/invalid")
+            .await
+            .expect_err("errordetail set an invalid level");
+        assert_eq!(
+            server
+                .post::<ErrorDetail>("errordetail/verbose")
+                .await
+                .unwrap(),
+            ErrorDetail::Verbose
+        );
+        let (_, body) = post_error(url.clone()).await;
+        serde_json::from_str::<VerboseError>(&body).unwrap();
+        let (_, body) = post_error(format!("{}?verbose=false", url)).await;
+        assert_eq!(body, terse);
+
+        // The initial level can be set on the command line.
+        let server =
+            TestServer::with_options(|options| options.error_detail = ErrorDetail::Verbose).await;
+        assert_eq!(
+            server.get::<ErrorDetail>("errordetail").await.unwrap(),
+            ErrorDetail::Verbose
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_conversions() {
+        let erc20_code = Address::from([1u8; 20]);
+        let sponsor_addr = Address::from([2u8; 20]);
+
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<Conversion>>("conversions")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        assert!(server
+            .get::<Vec<Conversion>>("conversions")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Sponsor an asset and wrap some of it.
+        let (asset, info) = server
+            .post::<(sol::AssetDefinition, String)>(&format!(
+                "buildsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, sponsor_addr
+            ))
+            .await
+            .unwrap();
+        server
+            .client
+            .post("importasset")
+            .body_json(&info)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        server
+            .client
+            .post(&format!(
+                "submitsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, sponsor_addr
+            ))
+            .body_json(&asset)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let asset: JfAssetDefinition = asset.into();
+        server.post::<PubKey>("newkey/sending").await.unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let destination: UserAddress = info.sending_keys[0].address().into();
+        let ro = server
+            .post::<sol::RecordOpening>(&format!(
+                "buildwrap/destination/{}/asset/{}/amount/{}",
+                destination, asset.code, 10
+            ))
+            .await
+            .unwrap();
+        server
+            .client
+            .post(&format!("submitwrap/ethaddress/{:#x}", sponsor_addr))
+            .body_json(&ro)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        // The wrap is listed with both its CAPE and Ethereum receipts.
+        let conversions = server.get::<Vec<Conversion>>("conversions").await.unwrap();
+        assert_eq!(conversions.len(), 1);
+        let conversion = &conversions[0];
+        assert_eq!(conversion.direction, ConversionDirection::Wrap);
+        assert_eq!(conversion.asset, asset.code);
+        assert_eq!(conversion.amount, U256::from(10));
+        assert_eq!(conversion.address, destination);
+        assert_eq!(conversion.eth_address, sponsor_addr);
+        let ro = RecordOpening::from(ro);
+        match &conversion.cape_receipt {
+            ConversionReceipt::Wrap(comm) => assert_eq!(*comm, RecordCommitment::from(&ro)),
+            receipt => panic!("expected wrap receipt, got {:?}", receipt),
+        }
+        assert!(conversion.eth_tx_hash.is_some());
+        assert_eq!(conversion.status, "pending");
+
+        // Conversions made by other wallets are not listed.
+        let path = [server.temp_dir.path(), Path::new("keystores/other_wallet")]
+            .iter()
+            .collect::<PathBuf>();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                fmt_path(&path)
+            ))
+            .await
+            .unwrap();
+        assert!(server
+            .get::<Vec<Conversion>>("conversions")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_spendlimit() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([44u8; 32]);
+        let recipient = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        assert_eq!(
+            server
+                .get::<Vec<SpendLimitInfo>>("spendlimit")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // Setting a limit requires a POST and a valid period.
+        server
+            .get::<Vec<SpendLimitInfo>>(&format!("spendlimit/{}/3/day", asset))
+            .await
+            .expect_err("spendlimit set with a GET request");
+        for period in ["0", "month"] {
+            server
+                .post::<Vec<SpendLimitInfo>>(&format!("spendlimit/{}/3/{}", asset, period))
+                .await
+                .expect_err(&format!("spendlimit set with invalid period {}", period));
+        }
+        assert_eq!(
+            server
+                .post::<Vec<SpendLimitInfo>>(&format!("spendlimit/{}/3/day", asset))
+                .await
+                .unwrap(),
+            vec![SpendLimitInfo {
+                asset,
+                limit: 3.into(),
+                period_secs: 24 * 60 * 60,
+                spent: 0.into(),
+                remaining: 3.into(),
+            }]
+        );
+
+        // Spend up to the limit.
+        let asset_balance = || async {
+            server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                .await
+                .unwrap()
+                .balances
+        };
+        for (amount, balance) in [(2u64, 2u64), (1, 3)] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { asset_balance().await == Balances::One(balance.into()) }).await;
+        }
+        let limits = server
+            .get::<Vec<SpendLimitInfo>>("spendlimit")
+            .await
+            .unwrap();
+        assert_eq!(limits[0].spent, 3.into());
+        assert_eq!(limits[0].remaining, 0.into());
+
+        // Minting, transferring and exporting more are all rejected.
+        let err = server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/1/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .expect_err("mint exceeded the spending limit");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+        let err = server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/asset/{}/recipient/{}/amount/1/fee/1",
+                asset, recipient
+            ))
+            .await
+            .expect_err("send exceeded the spending limit");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+        // Exporting a transaction for someone else to submit is spending too.
+        let err = server
+            .post::<ExportedTransaction>(&format!(
+                "exporttransaction/asset/{}/recipient/{}/amount/1/fee/1",
+                asset, recipient
+            ))
+            .await
+            .expect_err("exporttransaction exceeded the spending limit");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+
+        // Shrink the window to 1 second and wait for the spending to roll out of it.
+        server
+            .post::<Vec<SpendLimitInfo>>(&format!("spendlimit/{}/3/1", asset))
+            .await
+            .unwrap();
+        async_std::task::sleep(Duration::from_secs(1)).await;
+        let limits = server
+            .get::<Vec<SpendLimitInfo>>("spendlimit")
+            .await
+            .unwrap();
+        assert_eq!(limits[0].spent, 0.into());
+        assert_eq!(limits[0].remaining, 3.into());
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/asset/{}/recipient/{}/amount/1/fee/1",
+                asset, recipient
+            ))
+            .await
+            .unwrap();
+        let limits = server
+            .get::<Vec<SpendLimitInfo>>("spendlimit")
+            .await
+            .unwrap();
+        assert_eq!(limits[0].spent, 1.into());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_frontier() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<RecordFrontier>("frontier").await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // The frontier is stable while no new records are added.
+        let initial = server.get::<RecordFrontier>("frontier").await.unwrap();
+        assert!(initial.root.starts_with("ROOT~"));
+        assert!(initial.frontier.starts_with("FRONTIER~"));
+        assert_eq!(initial.height, CAPE_MERKLE_HEIGHT);
+        assert_eq!(
+            server.get::<RecordFrontier>("frontier").await.unwrap(),
+            initial
+        );
+
+        // Once the wallet incorporates new records, the root and position change.
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        retry(|| async {
+            let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+            info.sync_time == info.real_time
+                && server
+                    .get::<RecordFrontier>("frontier")
+                    .await
+                    .unwrap()
+                    .num_leaves
+                    > initial.num_leaves
+        })
+        .await;
+        let updated = server.get::<RecordFrontier>("frontier").await.unwrap();
+        assert_ne!(updated.root, initial.root);
+        assert_ne!(updated.frontier, initial.frontier);
+        assert!(updated.sync_time > initial.sync_time);
+        assert_eq!(
+            updated.sync_time,
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .sync_time
+        );
+        assert_eq!(
+            server.get::<RecordFrontier>("frontier").await.unwrap(),
+            updated
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_idle_timeout() {
+        let server = TestServer::with_options(|options| options.idle_timeout_secs = Some(1)).await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Requests keep the wallet open.
+        for _ in 0..3 {
+            async_std::task::sleep(Duration::from_millis(500)).await;
+            server.get::<WalletSummary>("getinfo").await.unwrap();
+        }
+
+        // Once the wallet is idle for longer than the timeout, it is closed.
+        async_std::task::sleep(Duration::from_secs(3)).await;
+        let err = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .expect_err("wallet was not closed after idle timeout");
+        assert!(err.to_string().contains("idle"), "{}", err);
+
+        // Reopening the wallet clears the timeout.
+        server
+            .post::<()>(&format!(
+                "openwallet/{}/path/{}",
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server.get::<WalletSummary>("getinfo").await.unwrap();
+        server.post::<()>("closewallet").await.unwrap();
+        let err = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .expect_err("getinfo succeeded after closewallet");
+        assert!(!err.to_string().contains("idle"), "{}", err);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_schedule() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([45u8; 32]);
+        let recipient = UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        let native = AssetCode::native();
+        server
+            .requires_wallet_post::<ScheduledTransfer>(&format!(
+                "schedule/transfer/asset/{}/recipient/{}/amount/1/fee/1/interval/1",
+                native, recipient
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // Schedule a transfer we can afford, and one we can't.
+        let affordable = server
+            .post::<ScheduledTransfer>(&format!(
+                "schedule/transfer/asset/{}/recipient/{}/amount/1/fee/1/interval/1",
+                native, recipient
+            ))
+            .await
+            .unwrap();
+        assert!(affordable.next_firing_ms.is_some());
+        assert!(affordable.firings.is_empty());
+        let unaffordable = server
+            .post::<ScheduledTransfer>(&format!(
+                "schedule/transfer/asset/{}/recipient/{}/amount/{}/fee/1/interval/1",
+                native,
+                recipient,
+                u64::MAX
+            ))
+            .await
+            .unwrap();
+        assert_ne!(affordable.id, unaffordable.id);
+
+        // Wait for each to fire at least once.
+        let schedule = |id| async move {
+            server
+                .get::<Vec<ScheduledTransfer>>("schedules")
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|schedule| schedule.id == id)
+                .unwrap()
+        };
+        retry(|| async {
+            !schedule(affordable.id).await.firings.is_empty()
+                && !schedule(unaffordable.id).await.firings.is_empty()
+        })
+        .await;
+        match &schedule(affordable.id).await.firings[0].outcome {
+            ScheduledTransferOutcome::Sent { receipt } => {
+                assert_eq!(receipt.submitters.len(), 1);
+            }
+            outcome => panic!("expected transfer to be sent, got {:?}", outcome),
+        }
+        match &schedule(unaffordable.id).await.firings[0].outcome {
+            ScheduledTransferOutcome::Skipped { code, .. } => {
+                assert_eq!(code.as_deref(), Some("insufficient_balance"));
+            }
+            outcome => panic!("expected transfer to be skipped, got {:?}", outcome),
+        }
+
+        // Cancelled schedules stop firing.
+        for id in [affordable.id, unaffordable.id] {
+            let cancelled = server
+                .get::<ScheduledTransfer>(&format!("schedule/{}/cancel", id))
+                .await
+                .unwrap();
+            assert_eq!(cancelled.next_firing_ms, None);
+        }
+        let firings = schedule(affordable.id).await.firings.len();
+        async_std::task::sleep(Duration::from_secs(2)).await;
+        assert_eq!(schedule(affordable.id).await.firings.len(), firings);
+        server
+            .get::<ScheduledTransfer>("schedule/100/cancel")
+            .await
+            .expect_err("cancelled a schedule which does not exist");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_recoveryestimate() {
+        let server = TestServer::new().await;
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        server
+            .get::<RecoveryEstimate>("recoveryestimate/not-a-mnemonic")
+            .await
+            .expect_err("recoveryestimate accepted an invalid mnemonic");
+
+        // Without an open wallet, the estimate is for the mock ledger a new wallet would get, which
+        // contains just the faucet record.
+        let empty = server
+            .get::<RecoveryEstimate>(&format!("recoveryestimate/{}", mnemonic))
+            .await
+            .unwrap();
+        assert_eq!(empty, RecoveryEstimate::new(0, 1, 1));
+
+        // The estimate grows with the ledger.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let before = server
+            .get::<RecoveryEstimate>(&format!("recoveryestimate/{}", mnemonic))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let after = server
+            .get::<RecoveryEstimate>(&format!("recoveryestimate/{}", mnemonic))
+            .await
+            .unwrap();
+        assert!(after.events > before.events);
+        assert!(after.records > before.records);
+        assert_eq!(after.keys, 1);
+        assert!(after.estimated_ms > before.estimated_ms);
+        assert_eq!(
+            after,
+            RecoveryEstimate::new(after.events, after.records, after.keys)
+        );
+
+        // The estimate grows with the gap limit.
+        server.post::<usize>("gaplimit/20").await.unwrap();
+        let wide = server
+            .get::<RecoveryEstimate>(&format!("recoveryestimate/{}", mnemonic))
+            .await
+            .unwrap();
+        assert_eq!(wide.keys, 20);
+        assert_eq!(wide.records, after.records);
+        assert!(wide.estimated_ms > after.estimated_ms);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_gaplimit() {
+        let server = TestServer::new().await;
+
+        // The gap limit does not require a wallet, and defaults to 0.
+        assert_eq!(server.get::<usize>("gaplimit").await.unwrap(), 0);
+        assert_eq!(server.post::<usize>("gaplimit").await.unwrap(), 0);
+
+        // With a gap limit of 0, creating a wallet does not generate any keys.
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(info.sending_keys, vec![]);
+
+        // Changing the gap limit requires a POST.
+        server
+            .get::<usize>("gaplimit/3")
+            .await
+            .expect_err("gaplimit changed with a GET request");
+        assert_eq!(server.get::<usize>("gaplimit").await.unwrap(), 0);
+        assert_eq!(server.post::<usize>("gaplimit/3").await.unwrap(), 3);
+        assert_eq!(server.get::<usize>("gaplimit").await.unwrap(), 3);
+
+        // Recovering the same mnemonic with a gap limit of 3 scans ahead. None of the keys have
+        // been used on this ledger, so the wallet should stop after exactly 3 keys.
+        let path = [
+            server.temp_dir.path(),
+            Path::new("keystores/recovered_wallet"),
+        ]
+        .iter()
+        .collect::<PathBuf>();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                fmt_path(&path)
+            ))
+            .await
+            .unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(info.sending_keys.len(), 3);
+
+        // A new key in the recovered wallet comes after the scanned keys.
+        let key = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key,
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        assert!(!info.sending_keys.contains(&key));
+
+        // `recoverkey` also scans ahead after recovering the requested key, so it adds the recovered
+        // key plus 3 more unused keys.
+        server.post::<PubKey>("recoverkey/sending").await.unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(info.sending_keys.len(), 8);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_asof() {
+        let server = TestServer::new().await;
+
+        // Open a wallet and populate it with some native balance.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let balance_path = format!(
+            "getbalance/address/{}/asset/{}",
+            src_address,
+            AssetCode::native()
+        );
+
+        // Record the current state of the wallet.
+        let then = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .unwrap()
+            .sync_time;
+        let balance_then = server.get::<BalanceInfo>(&balance_path).await.unwrap();
+        assert_eq!(
+            balance_then.balances,
+            Balances::One(DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR.into())
+        );
+        let records_then = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.uid)
+            .collect::<HashSet<_>>();
+        let history_then = server
+            .get::<(
+                Vec<TransactionHistoryEntry>,
+                HashMap<AssetCode, Option<AssetInfo>>,
+            )>("transactionhistory")
+            .await
+            .unwrap()
+            .0;
+
+        // Advance the ledger by making a transfer.
+        let dst_address = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&balance_path)
+                .await
+                .unwrap()
+                .balances
+                == Balances::One((DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR - 101).into())
+        })
+        .await;
+
+        // Queries as of the earlier time should return the earlier state.
+        assert_eq!(
+            server
+                .get::<BalanceInfo>(&format!("{}?asof={}", balance_path, then))
+                .await
+                .unwrap()
+                .balances,
+            balance_then.balances
+        );
+        assert_eq!(
+            server
+                .get::<Vec<RecordInfo>>(&format!("getrecords?asof={}", then))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|record| record.uid)
+                .collect::<HashSet<_>>(),
+            records_then
+        );
+        let history = server
+            .get::<(
+                Vec<TransactionHistoryEntry>,
+                HashMap<AssetCode, Option<AssetInfo>>,
+            )>(&format!("transactionhistory?asof={}", then))
+            .await
+            .unwrap()
+            .0;
+        assert!(history.len() <= history_then.len());
+        assert!(!history.iter().any(|entry| entry
+            .receivers
+            .contains(&(dst_address.clone(), "100".into()))));
+
+        // `asof` cannot be in the future.
+        let now = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .unwrap()
+            .sync_time;
+        server
+            .get::<BalanceInfo>(&format!("{}?asof={}", balance_path, now + 1))
+            .await
+            .expect_err("getbalance succeeded with asof in the future");
+        server
+            .get::<BalanceInfo>(&format!("{}?asof=not-an-index", balance_path))
+            .await
+            .expect_err("getbalance succeeded with a malformed asof");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_validatemnemonic() {
+        let server = TestServer::new().await;
+
+        // A freshly generated mnemonic is valid, and no wallet is needed to check it.
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        assert_eq!(
+            server
+                .get::<MnemonicValidation>(&format!("validatemnemonic/{}", mnemonic))
+                .await
+                .unwrap(),
+            MnemonicValidation {
+                valid: true,
+                word_count: mnemonic.split('-').count(),
+            }
+        );
+
+        // The standard BIP39 test vector: 11 repetitions of "abandon" followed by "about".
+        let words = vec!["abandon"; 11];
+        let valid = [words.clone(), vec!["about"]].concat().join("-");
+        assert_eq!(
+            server
+                .get::<MnemonicValidation>(&format!("validatemnemonic/{}", valid))
+                .await
+                .unwrap(),
+            MnemonicValidation {
+                valid: true,
+                word_count: 12,
+            }
+        );
+
+        // Every word is in the word list, but the checksum is wrong.
+        let bad_checksum = [words.clone(), vec!["abandon"]].concat().join("-");
+        assert_eq!(
+            server
+                .get::<MnemonicValidation>(&format!("validatemnemonic/{}", bad_checksum))
+                .await
+                .unwrap(),
+            MnemonicValidation {
+                valid: false,
+                word_count: 12,
+            }
+        );
+
+        // One of the words is not in the word list.
+        let bad_word = [words, vec!["notaword"]].concat().join("-");
+        assert_eq!(
+            server
+                .get::<MnemonicValidation>(&format!("validatemnemonic/{}", bad_word))
+                .await
+                .unwrap(),
+            MnemonicValidation {
+                valid: false,
+                word_count: 12,
+            }
+        );
+
+        // Validation does not create any keystores.
+        assert_eq!(
+            server.get::<Vec<String>>("listkeystores").await.unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_getinfo_fields() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+
+        // Request only the addresses.
+        let sparse = server
+            .get::<serde_json::Value>("getinfo?fields=addresses")
+            .await
+            .unwrap();
+        let sparse = sparse.as_object().unwrap();
+        assert_eq!(
+            sparse.keys().cloned().collect::<Vec<_>>(),
+            vec![String::from("addresses")]
+        );
+        assert_eq!(
+            serde_json::from_value::<Vec<UserAddress>>(sparse["addresses"].clone()).unwrap(),
+            info.addresses
+        );
+
+        // Request more than one field.
+        let sparse = server
+            .get::<serde_json::Value>("getinfo?fields=addresses,assets")
+            .await
+            .unwrap();
+        let mut keys = sparse
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![String::from("addresses"), String::from("assets")]
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_getinfo_unknown_field() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        let err = server
+            .get::<serde_json::Value>("getinfo?fields=addresses,bogus")
+            .await
+            .expect_err("getinfo succeeded with an unknown field");
+        assert_eq!(err.status(), surf::StatusCode::BadRequest);
+        assert!(err.to_string().contains("bogus"), "{}", err);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_public_range_requests() {
+        let web_dir = TempDir::new("test_wallet_api_public").unwrap();
+        let contents = (0..=255u8).collect::<Vec<_>>();
+        fs::write(web_dir.path().join("data.bin"), &contents)
+            .await
+            .unwrap();
+        let server = TestServer::with_options(|options| {
+            options.web_path = Some(web_dir.path().to_path_buf());
+        })
+        .await;
+        let url = format!("http://localhost:{}/public/data.bin", server.options().port);
+        let get_range = |range: Option<&'static str>| {
+            let url = url.clone();
+            async move {
+                let mut req = surf::get(url);
+                if let Some(range) = range {
+                    req = req.header("Range", range);
+                }
+                let mut res = req.send().await.unwrap();
+                let body = res.body_bytes().await.unwrap();
+                (res, body)
+            }
+        };
+
+        // Without a range, we get the whole file, and are told that ranges are supported.
+        let (res, body) = get_range(None).await;
+        assert_eq!(res.status(), surf::StatusCode::Ok);
+        assert_eq!(res["Accept-Ranges"], "bytes");
+        assert_eq!(body, contents);
+
+        // A byte range.
+        let (res, body) = get_range(Some("bytes=10-19")).await;
+        assert_eq!(res.status(), surf::StatusCode::PartialContent);
+        assert_eq!(res["Content-Range"], "bytes 10-19/256");
+        assert_eq!(body, &contents[10..20]);
+
+        // Open-ended and suffix ranges.
+        let (res, body) = get_range(Some("bytes=250-")).await;
+        assert_eq!(res.status(), surf::StatusCode::PartialContent);
+        assert_eq!(res["Content-Range"], "bytes 250-255/256");
+        assert_eq!(body, &contents[250..]);
+        let (res, body) = get_range(Some("bytes=-4")).await;
+        assert_eq!(res.status(), surf::StatusCode::PartialContent);
+        assert_eq!(res["Content-Range"], "bytes 252-255/256");
+        assert_eq!(body, &contents[252..]);
+
+        // A range which extends past the end of the file is truncated.
+        let (res, body) = get_range(Some("bytes=200-1000")).await;
+        assert_eq!(res.status(), surf::StatusCode::PartialContent);
+        assert_eq!(res["Content-Range"], "bytes 200-255/256");
+        assert_eq!(body, &contents[200..]);
+
+        // A range which starts past the end of the file is unsatisfiable.
+        let (res, _) = get_range(Some("bytes=256-300")).await;
+        assert_eq!(res.status(), surf::StatusCode::RequestedRangeNotSatisfiable);
+        assert_eq!(res["Content-Range"], "bytes */256");
+
+        // Multiple ranges are not supported, so we get the whole file.
+        let (res, body) = get_range(Some("bytes=0-1,4-5")).await;
+        assert_eq!(res.status(), surf::StatusCode::Ok);
+        assert_eq!(body, contents);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_autoconsolidate() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<AutoConsolidatePolicy>>("autoconsolidate")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<Vec<AutoConsolidatePolicy>>("autoconsolidate")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // Mint 3 separate records of a new asset to ourselves.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let asset_records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == asset)
+                .map(|record| record.ro.amount.generic_into::<u128>())
+                .collect::<Vec<_>>()
+        };
+        for i in 1..=3 {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, i, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { asset_records().await.len() == i }).await;
+        }
+
+        // Setting a policy requires POST.
+        server
+            .get::<Vec<AutoConsolidatePolicy>>(&format!("autoconsolidate/{}/2", asset))
+            .await
+            .expect_err("set a consolidation policy with GET");
+        let policies = server
+            .post::<Vec<AutoConsolidatePolicy>>(&format!("autoconsolidate/{}/2", asset))
+            .await
+            .unwrap();
+        assert_eq!(
+            policies,
+            vec![AutoConsolidatePolicy {
+                asset,
+                threshold: 2,
+                consolidations: 0,
+                last_error: None,
+            }]
+        );
+
+        // Without any further requests, the 2 largest records are merged, leaving 2 records and
+        // the same balance.
+        retry(|| async {
+            let mut records = asset_records().await;
+            records.sort_unstable();
+            records == vec![1, 5]
+        })
+        .await;
+        let policies = server
+            .get::<Vec<AutoConsolidatePolicy>>("autoconsolidate")
+            .await
+            .unwrap();
+        assert_eq!(policies[0].consolidations, 1);
+        assert_eq!(policies[0].last_error, None);
+
+        // A threshold of 0 removes the policy.
+        assert_eq!(
+            server
+                .post::<Vec<AutoConsolidatePolicy>>(&format!("autoconsolidate/{}/0", asset))
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_changepreview() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<ChangePreview>(&format!(
+                "changepreview/transfer/{}/1",
+                AssetCode::native()
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Mint records of 5, 3 and 2 units of a new asset to ourselves.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|record| {
+                    (
+                        record.uid,
+                        (
+                            record.ro.asset_def.code,
+                            U256::from(record.ro.amount.generic_into::<u128>()),
+                        ),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        for (i, amount) in [5, 3, 2].into_iter().enumerate() {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async {
+                records()
+                    .await
+                    .values()
+                    .filter(|(code, _)| *code == asset)
+                    .count()
+                    == i + 1
+            })
+            .await;
+        }
+        let records = records().await;
+        let value = |uid: &u64| records[uid].1;
+
+        // Sending 6 units takes the 5 and the 3, leaving 2 in change.
+        let preview = server
+            .get::<ChangePreview>(&format!("changepreview/transfer/{}/6/fee/1", asset))
+            .await
+            .unwrap();
+        assert_eq!(
+            preview.inputs.iter().map(value).collect::<Vec<_>>(),
+            vec![U256::from(5), U256::from(3)]
+        );
+        assert_eq!(preview.change, U256::from(2));
+        assert_eq!(records[&preview.fee_input].0, AssetCode::native());
+        assert_eq!(preview.fee_change, value(&preview.fee_input) - 1);
+
+        // For the native asset, the change is everything spent minus the amount and the fee.
+        let preview = server
+            .get::<ChangePreview>(&format!(
+                "changepreview/transfer/{}/1/fee/1",
+                AssetCode::native()
+            ))
+            .await
+            .unwrap();
+        assert!(!preview.inputs.contains(&preview.fee_input));
+        let spent = preview
+            .inputs
+            .iter()
+            .chain([&preview.fee_input])
+            .map(value)
+            .fold(U256::zero(), |total, amount| total + amount);
+        assert_eq!(preview.change + preview.fee_change, spent - 2);
+
+        // A transfer which cannot be built has no preview.
+        server
+            .get::<ChangePreview>(&format!("changepreview/transfer/{}/10/fee/1", asset))
+            .await
+            .expect_err("previewed a transfer exceeding the balance");
+        server
+            .get::<ChangePreview>(&format!("changepreview/transfer/{}/9/fee/1", asset))
+            .await
+            .expect_err("previewed a transfer of too many records");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_totalcost() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let wrapped = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|record| record.ro.asset_def.code != AssetCode::native())
+            .unwrap()
+            .ro
+            .asset_def
+            .code;
+        let mut rng = ChaChaRng::from_seed([42u8; 32]);
+        let mut recipient = || UserAddress::from(UserKeyPair::generate(&mut rng).address());
+        let totalcost = |path: &'static str, transfers: Vec<PlannedTransfer>| async move {
+            client::response_body::<TotalCost>(
+                &mut server
+                    .client
+                    .post(path)
+                    .body_json(&transfers)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+        };
+
+        // Plan to pay 3 invoices: two in the native asset, which fit in one transaction, and one
+        // in the wrapped asset, which needs a transaction of its own.
+        let mut transfers = vec![
+            PlannedTransfer {
+                asset: AssetCode::native(),
+                recipient: recipient(),
+                amount: 10.into(),
+            },
+            PlannedTransfer {
+                asset: AssetCode::native(),
+                recipient: recipient(),
+                amount: 20.into(),
+            },
+            PlannedTransfer {
+                asset: wrapped,
+                recipient: recipient(),
+                amount: 5.into(),
+            },
+        ];
+        let cost = totalcost("totalcost/fee/2", transfers.clone()).await;
+        assert_eq!(
+            cost,
+            TotalCost {
+                transactions: 2,
+                fee: 2.into(),
+                fees: 4.into(),
+                amounts: vec![(AssetCode::native(), 30.into()), (wrapped, 5.into())]
+                    .into_iter()
+                    .collect(),
+                totals: vec![(AssetCode::native(), 34.into()), (wrapped, 5.into())]
+                    .into_iter()
+                    .collect(),
+                affordable: true,
+            }
+        );
+
+        // Without a fee, each transaction pays the minimum fee.
+        let cost = totalcost("totalcost", transfers.clone()).await;
+        assert_eq!(cost.fee, MOCK_MIN_FEE.into());
+        assert_eq!(cost.fees, (2 * MOCK_MIN_FEE).into());
+        assert_eq!(
+            cost.totals[&AssetCode::native()],
+            (30 + 2 * MOCK_MIN_FEE).into()
+        );
+
+        // A third native transfer does not fit in the native transaction.
+        transfers.push(PlannedTransfer {
+            asset: AssetCode::native(),
+            recipient: recipient(),
+            amount: 30.into(),
+        });
+        let cost = totalcost("totalcost/fee/2", transfers.clone()).await;
+        assert_eq!(cost.transactions, 3);
+        assert_eq!(cost.totals[&AssetCode::native()], 66.into());
+        assert!(cost.affordable);
+
+        // Paying more of the wrapped asset than the wallet owns is not affordable.
+        transfers.push(PlannedTransfer {
+            asset: wrapped,
+            recipient: recipient(),
+            amount: DEFAULT_WRAPPED_AMT.into(),
+        });
+        assert!(!totalcost("totalcost/fee/2", transfers).await.affordable);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_recordages() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<RecordAges>(&format!("recordages/{}", AssetCode::native()))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Mint records of 5, 3 and 2 units of a new asset, each in a later block than the last.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == asset)
+                .collect::<Vec<_>>()
+        };
+        for (i, amount) in [5, 3, 2].into_iter().enumerate() {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { records().await.len() == i + 1 }).await;
+        }
+
+        // Find the block which created each record.
+        let mut blocks = vec![];
+        for record in records().await {
+            let provenance = server
+                .get::<RecordProvenance>(&format!("record/{}/provenance", record.uid))
+                .await
+                .unwrap();
+            blocks.push((
+                provenance.block_id,
+                U256::from(record.ro.amount.generic_into::<u128>()),
+            ));
+        }
+        blocks.sort();
+        assert!(blocks[0].0 < blocks[1].0 && blocks[1].0 < blocks[2].0);
+        assert_eq!(
+            blocks.iter().map(|(_, amount)| *amount).collect::<Vec<_>>(),
+            vec![U256::from(5), U256::from(3), U256::from(2)]
+        );
+
+        // With buckets of 1 block, each record has a bucket of its own, and the youngest record is
+        // in the first bucket.
+        let ages = server
+            .get::<RecordAges>(&format!("recordages/{}/bucket/1", asset))
+            .await
+            .unwrap();
+        assert_eq!(ages.asset, asset);
+        assert!(ages.height > blocks[2].0);
+        assert_eq!(
+            ages.buckets,
+            blocks
+                .iter()
+                .rev()
+                .map(|(block, amount)| {
+                    let age = ages.height - block - 1;
+                    RecordAgeBucket {
+                        min_age: age,
+                        max_age: age,
+                        records: 1,
+                        amount: *amount,
+                    }
+                })
+                .collect::<Vec<_>>()
+        );
+
+        // With the default bucket width, all the records are in the youngest bucket.
+        let ages = server
+            .get::<RecordAges>(&format!("recordages/{}", asset))
+            .await
+            .unwrap();
+        assert_eq!(
+            ages.buckets,
+            vec![RecordAgeBucket {
+                min_age: 0,
+                max_age: 99,
+                records: 3,
+                amount: U256::from(10),
+            }]
+        );
+
+        server
+            .get::<RecordAges>(&format!("recordages/{}/bucket/0", asset))
+            .await
+            .expect_err("bucketed records with a width of 0");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_linkability() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Linkability>(&format!(
+                "linkability/transfer/{}/1",
+                AssetCode::native()
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Mint records of 5 and 3 units of a new asset to the faucet address, so that it appears in
+        // the wallet's history.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        for (i, amount) in [5, 3].into_iter().enumerate() {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async {
+                server
+                    .get::<Vec<RecordInfo>>("getrecords")
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|record| record.ro.asset_def.code == asset)
+                    .count()
+                    == i + 1
+            })
+            .await;
+        }
+
+        // Sending 1 unit spends the 5, and the change returns to the address which received it.
+        let linkability = server
+            .get::<Linkability>(&format!("linkability/transfer/{}/1/fee/1", asset))
+            .await
+            .unwrap();
+        assert_eq!(linkability.inputs.len(), 1);
+        assert_eq!(linkability.change_address, Some(minter.clone()));
+        let codes = |linkability: &Linkability| {
+            linkability
+                .reasons
+                .iter()
+                .map(|reason| reason.code.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(codes(&linkability), vec!["reused_address"]);
+        assert_eq!(linkability.score, 40);
+
+        // Sending 7 units spends both records as well.
+        let linkability = server
+            .get::<Linkability>(&format!("linkability/transfer/{}/7/fee/1", asset))
+            .await
+            .unwrap();
+        assert_eq!(linkability.inputs.len(), 2);
+        assert_eq!(
+            codes(&linkability),
+            vec!["reused_address", "multiple_inputs"]
+        );
+        assert_eq!(linkability.score, 50);
+
+        // A transfer which cannot be built cannot be analyzed.
+        server
+            .get::<Linkability>(&format!("linkability/transfer/{}/9/fee/1", asset))
+            .await
+            .expect_err("analyzed a transfer exceeding the balance");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_auditable_freezable() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([47u8; 32]);
+        server.requires_wallet::<Vec<AssetCode>>("auditable").await;
+        server.requires_wallet::<Vec<AssetCode>>("freezable").await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server.post::<PubKey>("newkey/viewing").await.unwrap();
+        server.post::<PubKey>("newkey/freezing").await.unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let viewing_key = &info.viewing_keys[0];
+        let freezing_key = &info.freezing_keys[0];
+        let foreign_viewing_key = AuditorKeyPair::generate(&mut rng).pub_key();
+        let foreign_freezing_key = FreezerKeyPair::generate(&mut rng).pub_key();
+
+        // Define an asset we can view, one we can freeze, and one someone else can view and freeze.
+        let server = &server;
+        let define = |path: String| async move {
+            server
+                .post::<AssetInfo>(&path)
+                .await
+                .unwrap()
+                .definition
+                .code
+        };
+        let viewable = define(format!("newasset/viewing_key/{}", viewing_key)).await;
+        let freezable = define(format!("newasset/freezing_key/{}", freezing_key)).await;
+        define(format!(
+            "newasset/freezing_key/{}/viewing_key/{}",
+            foreign_freezing_key, foreign_viewing_key
+        ))
+        .await;
+
+        assert_eq!(
+            server.get::<Vec<AssetCode>>("auditable").await.unwrap(),
+            vec![viewable]
+        );
+        assert_eq!(
+            server.get::<Vec<AssetCode>>("freezable").await.unwrap(),
+            vec![freezable]
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_audit_graph() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<AuditGraph>(&format!("audit/graph/{}", AssetCode::native()))
+            .await;
+
+        // Open a wallet with some native tokens, and define an asset which reveals everything to a
+        // viewing key we own.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let alice: UserAddress = receipt.submitters[0].clone().into();
+        let new_address = || async {
+            match server.post::<PubKey>("newkey/sending").await.unwrap() {
+                PubKey::Sending(key) => UserAddress::from(key.address()),
+                key => panic!("Expected PubKey::Sending, found {:?}", key),
+            }
+        };
+        let bob = new_address().await;
+        let carol = new_address().await;
+        let viewing_key = match server.post::<PubKey>("newkey/viewing").await.unwrap() {
+            PubKey::Viewing(key) => key,
+            key => panic!("Expected PubKey::Viewing, found {:?}", key),
+        };
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/viewing_key/{}/view_amount/true/view_address/true",
+                viewing_key
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let balance = |address: UserAddress, asset: AssetCode| {
+            let server = &server;
+            async move {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", address, asset))
+                    .await
+                    .unwrap()
+                    .balances
+            }
+        };
+        let send = |sender: UserAddress, asset: AssetCode, recipient: UserAddress, amount: u64| {
+            let server = &server;
+            async move {
+                server
+                    .post::<TransactionReceipt<CapeLedger>>(&format!(
+                        "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/1",
+                        sender, asset, recipient, amount
+                    ))
+                    .await
+                    .unwrap();
+            }
+        };
+
+        // Mint to Alice, who sends some to Bob, who sends some on to Carol. Bob needs native tokens
+        // to pay the fee, but native transfers are not part of the asset's graph.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/100/fee/1/minter/{}/recipient/{}",
+                asset, alice, alice
+            ))
+            .await
+            .unwrap();
+        retry(|| async { balance(alice.clone(), asset).await == Balances::One(100u64.into()) })
+            .await;
+        send(alice.clone(), AssetCode::native(), bob.clone(), 5).await;
+        retry(|| async {
+            balance(bob.clone(), AssetCode::native()).await == Balances::One(5u64.into())
+        })
+        .await;
+        send(alice.clone(), asset, bob.clone(), 30).await;
+        retry(|| async { balance(bob.clone(), asset).await == Balances::One(30u64.into()) }).await;
+        send(bob.clone(), asset, carol.clone(), 10).await;
+        retry(|| async { balance(carol.clone(), asset).await == Balances::One(10u64.into()) })
+            .await;
+
+        let graph = server
+            .get::<AuditGraph>(&format!("audit/graph/{}", asset))
+            .await
+            .unwrap();
+        assert_eq!(graph.asset, asset);
+        assert!(graph.addresses_revealed);
+        assert!(graph.amounts_revealed);
+        assert_eq!(
+            graph.parties,
+            vec![alice.clone(), bob.clone(), carol.clone()]
+        );
+        let kinds = graph
+            .transactions
+            .iter()
+            .map(|txn| txn.kind.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec!["mint", "send", "send"]);
+        let edge = |party: &UserAddress, amount: u64| AuditEdge {
+            party: Some(party.clone()),
+            amount: Some(amount.into()),
+        };
+        let (mint, first, second) = (
+            &graph.transactions[0],
+            &graph.transactions[1],
+            &graph.transactions[2],
+        );
+        assert_eq!(mint.outputs, vec![edge(&alice, 100)]);
+        assert_eq!(first.inputs, vec![edge(&alice, 100)]);
+        assert_eq!(first.outputs.len(), 2);
+        assert!(first.outputs.contains(&edge(&bob, 30)));
+        assert!(first.outputs.contains(&edge(&alice, 70)));
+        assert_eq!(second.inputs, vec![edge(&bob, 30)]);
+        assert_eq!(second.outputs.len(), 2);
+        assert!(second.outputs.contains(&edge(&carol, 10)));
+        assert!(second.outputs.contains(&edge(&bob, 20)));
+
+        // We cannot trace an asset whose viewing key we do not own, or one we do not know.
+        server
+            .get::<AuditGraph>(&format!("audit/graph/{}", AssetCode::native()))
+            .await
+            .expect_err("traced an asset without its viewing key");
+        server
+            .get::<AuditGraph>(&format!("audit/graph/{}", AssetCode::dummy()))
+            .await
+            .expect_err("traced an unknown asset");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_viewing_capability() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let alice: UserAddress = receipt.submitters[0].clone().into();
+        let bob = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let viewing_key = match server.post::<PubKey>("newkey/viewing").await.unwrap() {
+            PubKey::Viewing(key) => key,
+            key => panic!("Expected PubKey::Viewing, found {:?}", key),
+        };
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/viewing_key/{}/view_amount/true/view_address/true",
+                viewing_key
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // Mint to Alice, who sends some to Bob.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/100/fee/1/minter/{}/recipient/{}",
+                asset, alice, alice
+            ))
+            .await
+            .unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/30/fee/1",
+                alice, asset, bob
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<AuditGraph>(&format!("audit/graph/{}", asset))
+                .await
+                .unwrap()
+                .transactions
+                .len()
+                == 2
+        })
+        .await;
+
+        let grant = |scope: ViewingScope| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post("viewingcapability")
+                    .body_json(&ViewingCapabilityRequest {
+                        scope,
+                        expires_in_secs: None,
+                    })
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<ViewingCapability>(&mut res).await
+            }
+        };
+        let amounts_only = |asset: AssetCode| ViewingScope {
+            asset,
+            amounts: true,
+            addresses: false,
+            from_block: None,
+            to_block: None,
+        };
+
+        // We cannot grant a capability for an asset we cannot view.
+        grant(amounts_only(AssetCode::native()))
+            .await
+            .expect_err("granted a capability without the viewing key");
+
+        // An amount-only capability reveals the amounts of the traced transactions, but no parties.
+        let capability = grant(amounts_only(asset)).await.unwrap();
+        assert_eq!(capability.scope, amounts_only(asset));
+        assert!(!capability.revoked);
+        assert_eq!(capability.expires, None);
+        let view = format!("audit/graph/{}/capability/{}", asset, capability.token);
+        let graph = server.get::<AuditGraph>(&view).await.unwrap();
+        assert!(graph.amounts_revealed);
+        assert!(!graph.addresses_revealed);
+        assert!(graph.parties.is_empty());
+        let kinds = graph
+            .transactions
+            .iter()
+            .map(|txn| txn.kind.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec!["mint", "send"]);
+        let edge = |amount: u64| AuditEdge {
+            party: None,
+            amount: Some(amount.into()),
+        };
+        assert_eq!(graph.transactions[0].outputs, vec![edge(100)]);
+        assert_eq!(graph.transactions[1].inputs, vec![edge(100)]);
+        assert_eq!(graph.transactions[1].outputs.len(), 2);
+        assert!(graph.transactions[1].outputs.contains(&edge(30)));
+        assert!(graph.transactions[1].outputs.contains(&edge(70)));
+
+        // Once revoked, the capability is rejected.
+        let revoked = server
+            .post::<ViewingCapability>(&format!("viewingcapability/{}/revoke", capability.id))
+            .await
+            .unwrap();
+        assert!(revoked.revoked);
+        let err = server
+            .get::<AuditGraph>(&view)
+            .await
+            .expect_err("viewed an asset with a revoked capability");
+        assert_eq!(err.status(), surf::StatusCode::Unauthorized);
+        server
+            .post::<ViewingCapability>("viewingcapability/unknown/revoke")
+            .await
+            .expect_err("revoked an unknown capability");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_statedigest() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<StateDigest>("statedigest").await;
+
+        // Create a wallet with a key.
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server.post::<PubKey>("newkey/sending").await.unwrap();
+        let digest = server.get::<StateDigest>("statedigest").await.unwrap();
+        assert_eq!(
+            digest,
+            server.get::<StateDigest>("statedigest").await.unwrap()
+        );
+
+        // A second instance of the same wallet, with the same key, has the same digest.
+        let other_dir = TempDir::new("test_statedigest_path2").unwrap();
+        server.post::<()>("closewallet").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                fmt_path(other_dir.path())
+            ))
+            .await
+            .unwrap();
+        server.post::<PubKey>("recoverkey/sending").await.unwrap();
+        retry(|| async { server.get::<StateDigest>("statedigest").await.unwrap() == digest }).await;
+
+        // Once it receives records, the instances diverge.
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<StateDigest>("statedigest")
+                .await
+                .unwrap()
+                .balances
+                != digest.balances
+        })
+        .await;
+        assert_ne!(
+            server
+                .get::<StateDigest>("statedigest")
+                .await
+                .unwrap()
+                .digest,
+            digest.digest
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_feerate() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<FeeRate>("feerate").await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.get::<FeeRate>("feerate").await.unwrap(),
+            FeeRate {
+                min_fee: MOCK_MIN_FEE,
+                age_ms: 0,
+                stale: false,
+            }
+        );
+
+        // Within the cache window, the same value is reported, and it is still fresh.
+        let rate = server.get::<FeeRate>("feerate").await.unwrap();
+        assert_eq!(rate.min_fee, MOCK_MIN_FEE);
+        assert!(!rate.stale);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_congestion() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<Congestion>("congestion").await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let congestion = server.get::<Congestion>("congestion").await.unwrap();
+        assert_eq!(congestion.pending, 0);
+        assert_eq!(congestion.accepted, 0);
+        assert!(!congestion.stale);
+
+        // The relayer's backlog is reported as it changes.
+        server.post::<()>("congestionfortest/42/7").await.unwrap();
+        let congestion = server.get::<Congestion>("congestion").await.unwrap();
+        assert_eq!(congestion.pending, 42);
+        assert_eq!(congestion.accepted, 7);
+        assert_eq!(congestion.window_secs, 60);
+        assert!(!congestion.stale);
+
+        // If the relayer becomes unreachable, the last backlog it reported is marked stale.
+        server.post::<()>("relayerdownfortest").await.unwrap();
+        let congestion = server.get::<Congestion>("congestion").await.unwrap();
+        assert_eq!(congestion.pending, 42);
+        assert_eq!(congestion.accepted, 7);
+        assert!(congestion.stale);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_transaction_categories() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<CategoryReport>>("report/bycategory")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+
+        // Make two transfers, waiting for each to complete.
+        let mut received = 0u64;
+        for amount in [100u64, 50] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                    src_address,
+                    &AssetCode::native(),
+                    dst_address,
+                    amount,
+                    1
+                ))
+                .await
+                .unwrap();
+            received += amount;
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!(
+                        "getbalance/address/{}/asset/{}",
+                        dst_address,
+                        AssetCode::native()
+                    ))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One(received.into())
+            })
+            .await;
+        }
+        let (history, _) = server
+            .get::<(Vec<TransactionHistoryEntry>, HashMap<AssetCode, AssetInfo>)>(
+                "transactionhistory",
+            )
+            .await
+            .unwrap();
+        let transfers = &history[history.len() - 2..];
+        assert!(transfers.iter().all(|entry| entry.kind == "send"));
+
+        // Nothing is categorized yet.
+        assert_eq!(
+            server
+                .get::<Vec<CategoryReport>>("report/bycategory")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // Only transactions in the wallet's history can be categorized, and only with POST.
+        let unknown = TaggedBase64::new("HASH", &[0; 32]).unwrap();
+        server
+            .post::<()>(&format!("transaction/category/{}/rent", unknown))
+            .await
+            .expect_err("categorized an unknown transaction");
+        let hash = transfers[0].hash.clone().unwrap();
+        server
+            .get::<()>(&format!("transaction/category/{}/rent", hash))
+            .await
+            .expect_err("categorized a transaction with GET");
+
+        // Categorize both transfers, changing the category of the first one along the way.
+        server
+            .post::<()>(&format!("transaction/category/{}/food", hash))
+            .await
+            .unwrap();
+        for entry in transfers {
+            server
+                .post::<()>(&format!(
+                    "transaction/category/{}/rent",
+                    entry.hash.clone().unwrap()
+                ))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            server
+                .get::<Vec<CategoryReport>>("report/bycategory")
+                .await
+                .unwrap(),
+            vec![CategoryReport {
+                category: String::from("rent"),
+                transactions: 2,
+                totals: once((AssetCode::native(), U256::from(150u64))).collect(),
+            }]
+        );
+
+        // Transactions outside the date range are left out.
+        assert_eq!(
+            server
+                .get::<Vec<CategoryReport>>("report/bycategory/from/0/to/1")
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_fees_total() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<FeeTotal>("fees/total").await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let before = server.get::<FeeTotal>("fees/total").await.unwrap();
+
+        // Make three transfers with different fees, waiting for each to complete.
+        let mut received = 0u64;
+        for fee in [1u64, 2, 3] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                    src_address,
+                    &AssetCode::native(),
+                    dst_address,
+                    10,
+                    fee
+                ))
+                .await
+                .unwrap();
+            received += 10;
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!(
+                        "getbalance/address/{}/asset/{}",
+                        dst_address,
+                        AssetCode::native()
+                    ))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One(received.into())
+            })
+            .await;
+        }
+
+        // The total grows by the sum of the fees, all paid in the native asset.
+        let after = server.get::<FeeTotal>("fees/total").await.unwrap();
+        assert_eq!(after.transactions, before.transactions + 3);
+        assert_eq!(after.totals.len(), 1);
+        let native_total = |total: &FeeTotal| {
+            total
+                .totals
+                .get(&AssetCode::native())
+                .copied()
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            native_total(&after),
+            native_total(&before) + U256::from(6u64)
+        );
+
+        // Transactions outside the date range are left out.
+        assert_eq!(
+            server
+                .get::<FeeTotal>("fees/total/from/0/to/1")
+                .await
+                .unwrap(),
+            FeeTotal {
+                transactions: 0,
+                totals: HashMap::new(),
+            }
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_previewimportkey() {
+        let server = TestServer::new().await;
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let foreign_key = AuditorKeyPair::generate(&mut rng);
+        server
+            .requires_wallet::<ViewingKeyPreview>(&format!(
+                "previewimportkey/trace/{}",
+                foreign_key
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let viewing_key = match server.post::<PubKey>("newkey/viewing").await.unwrap() {
+            PubKey::Viewing(key) => key,
+            key => panic!("Expected PubKey::Viewing, found {:?}", key),
+        };
+        let key = match server
+            .get::<PrivateKey>(&format!("getprivatekey/{}", viewing_key))
+            .await
+            .unwrap()
+        {
+            PrivateKey::Viewing(key) => key,
+            key => panic!("Expected PrivateKey::Viewing, found {:?}", key),
+        };
+
+        // Define an asset whose records the key can view, and mint some traceable records.
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/viewing_key/{}/view_amount/true/view_address/true",
+                viewing_key
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let mut minted = 0u64;
+        for amount in [10u64, 20] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/{}/minter/{}/recipient/{}",
+                    asset, amount, 1, minter, minter
+                ))
+                .await
+                .unwrap();
+            minted += amount;
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One(minted.into())
+            })
+            .await;
+        }
+
+        // The preview should match the traceable records which were actually created.
+        let records = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.ro.asset_def.code == asset)
+            .collect::<Vec<_>>();
+        let preview = server
+            .get::<ViewingKeyPreview>(&format!("previewimportkey/trace/{}", key))
+            .await
+            .unwrap();
+        assert_eq!(preview.records, records.len());
+        assert_eq!(
+            preview,
+            ViewingKeyPreview {
+                records: 2,
+                totals: once((asset, U256::from(minted))).collect(),
+                hidden_amounts: 0,
+            }
+        );
+
+        // A key which no asset names reveals nothing.
+        assert_eq!(
+            server
+                .get::<ViewingKeyPreview>(&format!("previewimportkey/trace/{}", foreign_key))
+                .await
+                .unwrap(),
+            ViewingKeyPreview {
+                records: 0,
+                totals: HashMap::new(),
+                hidden_amounts: 0,
+            }
+        );
+
+        // Previewing does not import the key.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(info.viewing_keys, vec![viewing_key]);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_key_coverage() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet_post::<Vec<KeyCoverage>>("keys/coverage")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+
+        // Two viewing keys the wallet doesn't hold, one which no asset names, and one it does hold.
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let key_a = AuditorKeyPair::generate(&mut rng);
+        let key_b = AuditorKeyPair::generate(&mut rng);
+        let unused_key = AuditorKeyPair::generate(&mut rng);
+        let own_key = match server.post::<PubKey>("newkey/viewing").await.unwrap() {
+            PubKey::Viewing(key) => key,
+            key => panic!("Expected PubKey::Viewing, found {:?}", key),
+        };
+        let own_key = match server
+            .get::<PrivateKey>(&format!("getprivatekey/{}", own_key))
+            .await
+            .unwrap()
+        {
+            PrivateKey::Viewing(key) => key,
+            key => panic!("Expected PrivateKey::Viewing, found {:?}", key),
+        };
+
+        // Mint two records viewable by `key_a`, one viewable by `key_b`, and one viewable by the
+        // wallet's own key.
+        let mut mints = vec![];
+        for (key, count) in [(&key_a, 2), (&key_b, 1), (&own_key, 1)] {
+            let asset = server
+                .post::<AssetInfo>(&format!(
+                    "newasset/viewing_key/{}/view_amount/true/view_address/true",
+                    key.pub_key()
+                ))
+                .await
+                .unwrap()
+                .definition
+                .code;
+            for _ in 0..count {
+                mints.push(asset);
+            }
+        }
+        let mut minted = HashMap::<AssetCode, u64>::new();
+        for asset in mints {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/{}/minter/{}/recipient/{}",
+                    asset, 10, 1, minter, minter
+                ))
+                .await
+                .unwrap();
+            let minted = *minted
+                .entry(asset)
+                .and_modify(|amount| *amount += 10)
+                .or_insert(10);
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One(minted.into())
+            })
+            .await;
+        }
+
+        // Each candidate is credited with the records it alone can view, in the order given.
+        let candidates = vec![
+            key_b.clone(),
+            key_a.clone(),
+            unused_key.clone(),
+            own_key.clone(),
+        ];
+        let mut res = server
+            .client
+            .post("keys/coverage")
+            .body_json(&candidates)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let coverage = client::response_body::<Vec<KeyCoverage>>(&mut res)
+            .await
+            .unwrap();
+        assert_eq!(
+            coverage,
+            vec![
+                KeyCoverage {
+                    key: key_b.pub_key(),
+                    records: 1,
+                    imported: false,
+                },
+                KeyCoverage {
+                    key: key_a.pub_key(),
+                    records: 2,
+                    imported: false,
+                },
+                KeyCoverage {
+                    key: unused_key.pub_key(),
+                    records: 0,
+                    imported: false,
+                },
+                // The wallet can already view the records of its own key.
+                KeyCoverage {
+                    key: own_key.pub_key(),
+                    records: 0,
+                    imported: true,
+                },
+            ]
+        );
+
+        // The candidates are not imported.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert_eq!(info.viewing_keys, vec![own_key.pub_key()]);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_portfolio_value() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet_post::<PortfolioValue>("portfolio/value")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        // This gives us a native balance and a balance of a wrapped asset.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+
+        // Mint a third asset.
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .unwrap();
+        let balances = || async {
+            match server
+                .get::<BalanceInfo>("getbalance/all")
+                .await
+                .unwrap()
+                .balances
+            {
+                Balances::All { aggregate, .. } => aggregate,
+                balances => panic!("expected Balances::All, got {:?}", balances),
+            }
+        };
+        retry(|| async {
+            let balances = balances().await;
+            balances.get(&asset) == Some(&10u64.into())
+                && balances
+                    .values()
+                    .filter(|balance| !balance.is_zero())
+                    .count()
+                    == 3
+        })
+        .await;
+        let balances = balances().await;
+        let wrapped = *balances
+            .keys()
+            .find(|code| **code != asset && **code != AssetCode::native())
+            .unwrap();
+        let native = balances[&AssetCode::native()];
+
+        let value = |rates: HashMap<AssetCode, String>| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post("portfolio/value")
+                    .body_json(&rates)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<PortfolioValue>(&mut res).await
+            }
+        };
+
+        // Value two of the three assets. The third is reported as unvalued.
+        let rates = vec![
+            (AssetCode::native(), String::from("2")),
+            (asset, String::from("1.25")),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        assert_eq!(
+            value(rates).await.unwrap(),
+            PortfolioValue {
+                total: format!("{}.5", native * 2 + 12),
+                values: vec![
+                    (AssetCode::native(), (native * 2).to_string()),
+                    (asset, String::from("12.5")),
+                ]
+                .into_iter()
+                .collect(),
+                unvalued: vec![wrapped],
+            }
+        );
+
+        // Rates must be non-negative decimal numbers.
+        for rate in ["-1", "1e3", "0.5.5", ""] {
+            value(once((asset, String::from(rate))).collect())
+                .await
+                .expect_err(&format!("valued an asset at {:?}", rate));
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_api_integrity() {
+        use cape_wallet::disco::{default_api_path, signature_path};
+        use sha3::{Digest, Sha3_256};
+
+        // Without a signer, the configuration is loaded unverified.
+        let config = fs::read(default_api_path()).await.unwrap();
+        let hash = hex::encode(Sha3_256::digest(&config));
+        let server = TestServer::new().await;
+        assert_eq!(
+            server
+                .get::<ApiIntegrity>("apiconfig/integrity")
+                .await
+                .unwrap(),
+            ApiIntegrity {
+                hash: hash.clone(),
+                signer: None,
+                verified: false,
+            }
+        );
+
+        // Sign a copy of the configuration.
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let signer = UserKeyPair::generate(&mut rng);
+        let dir = TempDir::new("test_api_integrity").unwrap();
+        let api_path = dir.path().join("api.toml");
+        fs::write(&api_path, &config).await.unwrap();
+        fs::write(
+            signature_path(&api_path),
+            serde_json::to_vec(&signer.sign(&config)).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        // A correctly signed configuration is verified at startup.
+        let server = TestServer::with_options(|options| {
+            options.api_path = Some(api_path.clone());
+            options.api_signer = Some(signer.pub_key());
+        })
+        .await;
+        assert_eq!(
+            server
+                .get::<ApiIntegrity>("apiconfig/integrity")
+                .await
+                .unwrap(),
+            ApiIntegrity {
+                hash,
+                signer: Some(signer.pub_key()),
+                verified: true,
+            }
+        );
+
+        // The server refuses to start if the configuration does not match the signature.
+        let start = |signer: UserPubKey| {
+            let options = NodeOpt {
+                api_path: Some(api_path.clone()),
+                api_signer: Some(signer),
+                ..NodeOpt::for_test(0, dir.path().join("storage"))
+            };
+            build_server(ChaChaRng::from_seed([42; 32]), &options)
+                .err()
+                .expect("server started with a bad signature")
+        };
+        let other = UserKeyPair::generate(&mut rng);
+        let err = start(other.pub_key());
+        assert!(err.to_string().contains("signature"), "{}", err);
+
+        let mut tampered = config.clone();
+        tampered.extend_from_slice(b"\n# tampered\n");
+        fs::write(&api_path, &tampered).await.unwrap();
+        let err = start(signer.pub_key());
+        assert!(err.to_string().contains("signature"), "{}", err);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_failed_transfers() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<FailedTransfer>>("failed")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        assert_eq!(
+            server.get::<Vec<FailedTransfer>>("failed").await.unwrap(),
+            vec![]
+        );
+
+        // Make the mock network reject the next transfer.
+        server.post::<()>("rejectnextfortest").await.unwrap();
+        let receipt = server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        let hash = transaction_hash_string(&receipt.uid.0).unwrap();
+        retry(|| async {
+            !server
+                .get::<Vec<FailedTransfer>>("failed")
+                .await
+                .unwrap()
+                .is_empty()
+        })
+        .await;
+        let failed = server.get::<Vec<FailedTransfer>>("failed").await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert!(
+            failed[0].reason.contains("BadMerkleRoot"),
+            "{}",
+            failed[0].reason
+        );
+        assert_eq!(
+            failed[0],
+            FailedTransfer {
+                hash: hash.clone(),
+                sender: Some(src_address.clone()),
+                asset: AssetCode::native(),
+                recipient: dst_address.clone(),
+                amount: String::from("100"),
+                fee: String::from("1"),
+                reason: failed[0].reason.clone(),
+            }
+        );
+
+        // Only failed transfers can be resent.
+        let unknown = TaggedBase64::new("HASH", &[0; 32]).unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>(&format!("failed/{}/resend", unknown))
+            .await
+            .expect_err("resent an unknown transaction");
+
+        // Resending the transfer submits a new transaction, which is accepted this time.
+        let resent = server
+            .get::<TransactionReceipt<CapeLedger>>(&format!("failed/{}/resend", hash))
+            .await
+            .unwrap();
+        assert_ne!(transaction_hash_string(&resent.uid.0).unwrap(), hash);
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+
+        // The resent transfer is no longer listed, and cannot be resent again.
+        assert_eq!(
+            server.get::<Vec<FailedTransfer>>("failed").await.unwrap(),
+            vec![]
+        );
+        server
+            .get::<TransactionReceipt<CapeLedger>>(&format!("failed/{}/resend", hash))
+            .await
+            .expect_err("resent a transfer twice");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_seedfingerprint() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<String>("seedfingerprint").await;
+
+        let password = base64("my-password".as_bytes());
+        let fingerprint = |mnemonic: String, name: &'static str| {
+            let server = &server;
+            let password = &password;
+            async move {
+                let path = fmt_path(
+                    &[
+                        server.temp_dir.path(),
+                        Path::new("keystores"),
+                        Path::new(name),
+                    ]
+                    .iter()
+                    .collect::<PathBuf>(),
+                );
+                server
+                    .post::<()>(&format!(
+                        "newwallet/{}/{}/path/{}",
+                        mnemonic, password, path
+                    ))
+                    .await
+                    .unwrap();
+                server.get::<String>("seedfingerprint").await.unwrap()
+            }
+        };
+
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let original = fingerprint(mnemonic.clone(), "original").await;
+        assert_eq!(hex::decode(&original).unwrap().len(), 32);
+
+        // A wallet from the same mnemonic has the same fingerprint.
+        assert_eq!(fingerprint(mnemonic, "copy").await, original);
+
+        // A wallet from a different mnemonic has a different fingerprint.
+        let other = server.get::<String>("getmnemonic").await.unwrap();
+        assert_ne!(fingerprint(other, "other").await, original);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_coinselection() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<SelectionStrategy>("coinselection")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<SelectionStrategy>("coinselection")
+                .await
+                .unwrap(),
+            SelectionStrategy::LargestFirst
+        );
+
+        // Mint records of 5, 3 and 2 units of a new asset to ourselves.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == asset)
+                .map(|record| {
+                    (
+                        record.uid,
+                        U256::from(record.ro.amount.generic_into::<u128>()),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        for (i, amount) in [5, 3, 2].into_iter().enumerate() {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { records().await.len() == i + 1 }).await;
+        }
+        let records = records().await;
+        let preview = |strategy: &'static str, amount: u64| {
+            let server = &server;
+            let records = &records;
+            async move {
+                assert_eq!(
+                    server
+                        .post::<SelectionStrategy>(&format!("coinselection/{}", strategy))
+                        .await
+                        .unwrap(),
+                    strategy.parse().unwrap()
+                );
+                let preview = server
+                    .get::<ChangePreview>(&format!(
+                        "changepreview/transfer/{}/{}/fee/1",
+                        asset, amount
+                    ))
+                    .await
+                    .unwrap();
+                (
+                    preview
+                        .inputs
+                        .iter()
+                        .map(|uid| records[uid])
+                        .collect::<Vec<_>>(),
+                    preview.change,
+                )
+            }
+        };
+
+        // The same transfer spends different records depending on the strategy.
+        assert_eq!(
+            preview("smallest", 4).await,
+            (vec![U256::from(2), U256::from(3)], U256::from(1))
+        );
+        assert_eq!(
+            preview("largest", 4).await,
+            (vec![U256::from(5)], U256::from(1))
+        );
+        assert_eq!(
+            preview("largest", 3).await,
+            (vec![U256::from(5)], U256::from(2))
+        );
+        assert_eq!(
+            preview("privacy", 3).await,
+            (vec![U256::from(3)], U256::zero())
+        );
+        assert_eq!(
+            server
+                .get::<SelectionStrategy>("coinselection")
+                .await
+                .unwrap(),
+            SelectionStrategy::Privacy
+        );
+
+        // Strategies can only be set with POST, and must be known.
+        server
+            .get::<SelectionStrategy>("coinselection/smallest")
+            .await
+            .expect_err("set a selection strategy with GET");
+        server
+            .post::<SelectionStrategy>("coinselection/random")
+            .await
+            .expect_err("set an unknown selection strategy");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_minanonymity() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<MinAnonymityStatus>("minanonymity")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src: UserAddress = receipt.submitters[0].clone().into();
+        let dst = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let send = |sender: &UserAddress, recipient: &UserAddress, amount: u64| {
+            format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/1",
+                sender,
+                AssetCode::native(),
+                recipient,
+                amount
+            )
+        };
+        let balance = |address: UserAddress| {
+            let server = &server;
+            async move {
+                server
+                    .get::<BalanceInfo>(&format!(
+                        "getbalance/address/{}/asset/{}",
+                        address,
+                        AssetCode::native()
+                    ))
+                    .await
+                    .unwrap()
+                    .balances
+            }
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send(&src, &dst, 10))
+            .await
+            .unwrap();
+        retry(|| async { balance(dst.clone()).await == Balances::One(10.into()) }).await;
+
+        // Without a minimum, no records are restricted.
+        let status = server
+            .get::<MinAnonymityStatus>("minanonymity")
+            .await
+            .unwrap();
+        assert_eq!(status.threshold, 0);
+        assert_eq!(status.restricted, vec![]);
+
+        // Require one more record than has been committed since the record we just received.
+        let uid = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|record| UserAddress::from(record.ro.pub_key.address()) == dst)
+            .unwrap()
+            .uid;
+        let threshold = status.ledger_size - uid + 1;
+        let status = server
+            .post::<MinAnonymityStatus>(&format!("minanonymity/{}", threshold))
+            .await
+            .unwrap();
+        assert_eq!(status.threshold, threshold);
+        assert!(status.restricted.contains(&uid));
+
+        // The record cannot be spent yet.
+        let err = server
+            .post::<TransactionReceipt<CapeLedger>>(&send(&dst, &src, 1))
+            .await
+            .expect_err("spent a record with too small an anonymity set");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+
+        // Grow the ledger with an unrestricted transfer.
+        server
+            .post::<MinAnonymityStatus>("minanonymity/0")
+            .await
+            .unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send(&src, &src, 1))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<MinAnonymityStatus>("minanonymity")
+                .await
+                .unwrap()
+                .ledger_size
+                > status.ledger_size
+        })
+        .await;
+
+        // Now the record's anonymity set is large enough to spend it.
+        let status = server
+            .post::<MinAnonymityStatus>(&format!("minanonymity/{}", threshold))
+            .await
+            .unwrap();
+        assert!(!status.restricted.contains(&uid));
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send(&dst, &src, 1))
+            .await
+            .unwrap();
+        retry(|| async { balance(dst.clone()).await == Balances::One(8.into()) }).await;
+
+        // Minimums can only be set with POST.
+        server
+            .get::<MinAnonymityStatus>("minanonymity/1")
+            .await
+            .expect_err("set a minimum anonymity set with GET");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_quarantine() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<QuarantineReport>("quarantine")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        assert_eq!(
+            server.get::<QuarantineReport>("quarantine").await.unwrap(),
+            QuarantineReport::default()
+        );
+
+        // Records of the asset below 10 units are dust. The policy can only be changed with POST.
+        server
+            .get::<QuarantineReport>(&format!("quarantine/threshold/{}/10", asset))
+            .await
+            .expect_err("changed the quarantine policy with GET");
+        let report = server
+            .post::<QuarantineReport>(&format!("quarantine/threshold/{}/10", asset))
+            .await
+            .unwrap();
+        assert_eq!(report.thresholds[&asset], U256::from(10));
+
+        let balance = || async {
+            match server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                .await
+                .unwrap()
+                .balances
+            {
+                Balances::One(balance) => balance,
+                balances => panic!("expected Balances::One, got {:?}", balances),
+            }
+        };
+        let mint = |amount: u64| {
+            let server = &server;
+            let minter = &minter;
+            async move {
+                server
+                    .post::<TransactionReceipt<CapeLedger>>(&format!(
+                        "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                        asset, amount, minter, minter
+                    ))
+                    .await
+                    .unwrap();
+            }
+        };
+        let quarantined = || async {
+            server
+                .get::<QuarantineReport>("quarantine")
+                .await
+                .unwrap()
+                .records
+        };
+
+        // Receive a record of dust. It is quarantined, and does not count toward the balance.
+        mint(5).await;
+        retry(|| async { !quarantined().await.is_empty() }).await;
+        let records = quarantined().await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].asset, asset);
+        assert_eq!(records[0].address, minter);
+        assert_eq!(records[0].amount, U256::from(5));
+        assert_eq!(records[0].reason, "dust");
+        assert_eq!(balance().await, U256::zero());
+
+        // Once it is released, it counts.
+        let uid = records[0].uid;
+        let report = server
+            .get::<QuarantineReport>(&format!("quarantine/{}/release", uid))
+            .await
+            .unwrap();
+        assert_eq!(report.records, vec![]);
+        assert_eq!(balance().await, U256::from(5));
+        server
+            .get::<QuarantineReport>(&format!("quarantine/{}/release", uid))
+            .await
+            .expect_err("released a record which is not quarantined");
+
+        // A rejected record is no longer listed, and still does not count.
+        mint(3).await;
+        retry(|| async { !quarantined().await.is_empty() }).await;
+        let uid = quarantined().await[0].uid;
+        let report = server
+            .get::<QuarantineReport>(&format!("quarantine/{}/reject", uid))
+            .await
+            .unwrap();
+        assert_eq!(report.records, vec![]);
+        assert_eq!(balance().await, U256::from(5));
+
+        // Flagging the asset does not quarantine the record which was already released.
+        let report = server
+            .post::<QuarantineReport>(&format!("quarantine/flag/{}", asset))
+            .await
+            .unwrap();
+        assert_eq!(report.flagged, vec![asset]);
+        assert_eq!(report.records, vec![]);
+        assert_eq!(balance().await, U256::from(5));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_send_trace() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let send = format!(
+            "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+            src_address,
+            &AssetCode::native(),
+            dst_address,
+            100,
+            1
+        );
+
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!("{}?trace=maybe", send))
+            .await
+            .expect_err("sent with an invalid trace flag");
+
+        // A traced transfer reports each phase, up to confirmation.
+        let traced = server
+            .post::<Traced<TransactionReceipt<CapeLedger>>>(&format!("{}?trace=true", send))
+            .await
+            .unwrap();
+        assert_eq!(
+            traced
+                .trace
+                .phases
+                .iter()
+                .map(|phase| phase.phase.as_str())
+                .collect::<Vec<_>>(),
+            vec!["select", "prove", "submit", "confirm"]
+        );
+        for phase in &traced.trace.phases {
+            assert!(phase.duration_ns > 0, "{:?}", phase);
+        }
+        let submitter: UserAddress = traced.result.submitters[0].clone().into();
+        assert_eq!(submitter, src_address);
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+
+        // Without the flag, the response is just the receipt.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!("{}?trace=false", send))
+            .await
+            .unwrap();
+
+        // Other mutating endpoints can be traced too.
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/description/{}",
+                base64::encode_config(&[4u8; 32], base64::URL_SAFE_NO_PAD)
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let traced = server
+            .post::<Traced<TransactionReceipt<CapeLedger>>>(&format!(
+                "mint/asset/{}/amount/10/fee/1/minter/{}/recipient/{}?trace=true",
+                asset, src_address, dst_address
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            traced
+                .trace
+                .phases
+                .iter()
+                .map(|phase| phase.phase.as_str())
+                .collect::<Vec<_>>(),
+            vec!["select", "prove", "submit", "confirm"]
+        );
+        // An exported transaction is not submitted, so there is nothing to wait for.
+        let traced = server
+            .post::<Traced<ExportedTransaction>>(&format!(
+                "exporttransaction/sender/{}/asset/{}/recipient/{}/amount/100/fee/1?trace=true",
+                src_address,
+                AssetCode::native(),
+                dst_address
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            traced
+                .trace
+                .phases
+                .iter()
+                .map(|phase| phase.phase.as_str())
+                .collect::<Vec<_>>(),
+            vec!["select", "prove"]
+        );
+        assert!(!traced.result.inputs.is_empty());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_notifyrecord() {
+        let server = TestServer::with_options(|options| {
+            options.admin_token = Some(String::from("admin-token"));
+        })
+        .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+        };
+        let notify = |notification: RecordNotification, token: &'static str| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post("notifyrecord")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body_json(&notification)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<Record>(&mut res).await
+            }
+        };
+
+        // Send to `dst_address` without posting the memos, so the wallet does not see the record.
+        server.post::<()>("withholdmemosfortest").await.unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            !server
+                .get::<Vec<RecordNotification>>("withholdmemosfortest")
+                .await
+                .unwrap()
+                .is_empty()
+        })
+        .await;
+        let notifications = server
+            .get::<Vec<RecordNotification>>("withholdmemosfortest")
+            .await
+            .unwrap();
+        // Wait for the wallet to scan the block, so the Merkle paths are valid against its frontier.
+        let num_leaves = notifications.iter().map(|n| n.uid).max().unwrap() + 1;
+        retry(|| async {
+            server
+                .get::<RecordFrontier>("frontier")
+                .await
+                .unwrap()
+                .num_leaves
+                >= num_leaves
+        })
+        .await;
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Notifications are authenticated.
+        notify(notifications[0].clone(), "wrong-token")
+            .await
+            .expect_err("notified record with the wrong token");
+
+        // A notification whose Merkle path does not prove the record is in the ledger, or whose
+        // memo does not open the record, is rejected and does not affect the balance.
+        for notification in &notifications {
+            let invalid = RecordNotification {
+                uid: notification.uid + 1,
+                ..notification.clone()
+            };
+            notify(invalid, "admin-token")
+                .await
+                .expect_err("notified record with an invalid Merkle path");
+        }
+        if notifications.len() > 1 {
+            let invalid = RecordNotification {
+                memo: notifications[1].memo.clone(),
+                ..notifications[0].clone()
+            };
+            notify(invalid, "admin-token")
+                .await
+                .expect_err("notified record with the memo of a different record");
+        }
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Exactly one of the notifications is for the record sent to `dst_address`; the others are
+        // for the sender's change.
+        let mut records = vec![];
+        for notification in notifications {
+            records.push(notify(notification, "admin-token").await.unwrap());
+        }
+        let received = records
+            .iter()
+            .filter(|record| record.address == dst_address)
+            .collect::<Vec<_>>();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].amount, "100");
+        assert_eq!(balance().await, Balances::One(100u64.into()));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_export_records() {
+        let server = TestServer::new().await;
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let password = base64("my-password".as_bytes());
+        let cold_path = fmt_path(
+            &[server.temp_dir.path(), Path::new("keystores/cold_wallet")]
+                .iter()
+                .collect::<PathBuf>(),
+        );
+        server
+            .requires_wallet::<RecordBundle>(&format!("export/records/{}", AssetCode::native()))
+            .await;
+
+        // Create a wallet with records of a wrapped asset and of the native asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                password,
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let wrapped = info
+            .assets
+            .iter()
+            .find(|asset| asset.definition.code != AssetCode::native())
+            .unwrap()
+            .definition
+            .code;
+        let mut address = None;
+        for candidate in info.addresses {
+            let balance = server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    candidate, wrapped
+                ))
+                .await
+                .unwrap()
+                .balances;
+            if balance == Balances::One(DEFAULT_WRAPPED_AMT.into()) {
+                address = Some(candidate);
+                break;
+            }
+        }
+        let address = address.unwrap();
+
+        // Export the records of both assets owned by the address with the wrapped asset. Every
+        // path is valid against the exported root.
+        let export = |asset: AssetCode| {
+            let server = &server;
+            let address = &address;
+            async move {
+                server
+                    .get::<RecordBundle>(&format!("export/records/{}/address/{}", asset, address))
+                    .await
+                    .unwrap()
+            }
+        };
+        let bundles = vec![export(wrapped).await, export(AssetCode::native()).await];
+        for (bundle, amount) in bundles
+            .iter()
+            .zip([DEFAULT_WRAPPED_AMT, DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR])
+        {
+            assert_eq!(
+                bundle
+                    .records
+                    .iter()
+                    .map(|record| record.ro.amount.generic_into::<u128>())
+                    .sum::<u128>(),
+                amount
+            );
+            for record in &bundle.records {
+                assert_eq!(UserAddress::from(record.ro.pub_key.address()), address);
+                jf_cap::MerkleTree::check_proof(
+                    bundle.commitment.root_value,
+                    record.uid,
+                    &jf_cap::MerkleLeafProof::new(
+                        RecordCommitment::from(&record.ro).to_field_element(),
+                        record.merkle_path.clone(),
+                    ),
+                )
+                .unwrap();
+            }
+        }
+
+        // Create a second wallet on the same ledger, from the same mnemonic, so that it has the
+        // same sending keys but none of the records.
+        server.post::<()>("shareledgerfortest").await.unwrap();
+        server.post::<()>("closewallet").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic, password, cold_path
+            ))
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            server.post::<PubKey>("newkey/sending").await.unwrap();
+        }
+        let balance = |asset: AssetCode| {
+            let server = &server;
+            let address = &address;
+            async move {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", address, asset))
+                    .await
+                    .unwrap()
+                    .balances
+            }
+        };
+        assert_eq!(balance(wrapped).await, Balances::One(0u64.into()));
+
+        // Import the records and check that the second wallet has the balances of the first.
+        for bundle in &bundles {
+            let mut res = server
+                .client
+                .post("import/records")
+                .body_json(bundle)
+                .unwrap()
+                .send()
+                .await
+                .unwrap();
+            let records = client::response_body::<Vec<Record>>(&mut res)
+                .await
+                .unwrap();
+            assert_eq!(records.len(), bundle.records.len());
+        }
+        assert_eq!(
+            balance(wrapped).await,
+            Balances::One(DEFAULT_WRAPPED_AMT.into())
+        );
+        assert_eq!(
+            balance(AssetCode::native()).await,
+            Balances::One(DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR.into())
+        );
+
+        // The imported records can be spent.
+        let recipient = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => UserAddress::from(key.address()),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/1/fee/1",
+                address, wrapped, recipient
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    recipient, wrapped
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(1u64.into())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_comparepolicy() {
+        let server = TestServer::new().await;
+        let compare = |asset: AssetCode, expected: ExpectedPolicy| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post(&format!("comparepolicy/{}", asset))
+                    .body_json(&expected)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<PolicyComparison>(&mut res).await
+            }
+        };
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server.post::<PubKey>("newkey/viewing").await.unwrap();
+        server.post::<PubKey>("newkey/freezing").await.unwrap();
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let viewing_key = info.viewing_keys[0].clone();
+        let freezing_key = info.freezing_keys[0].clone();
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/description/{}/freezing_key/{}/viewing_key/{}/view_amount/true/view_address/false/viewing_threshold/10",
+                base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD),
+                freezing_key,
+                viewing_key
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // The policy as advertised matches.
+        let advertised = ExpectedPolicy {
+            freezing_key: Some(freezing_key),
+            viewing_key: None,
+            address_viewable: false,
+            amount_viewable: true,
+            blind_viewable: false,
+            viewing_threshold: String::from("10"),
+        };
+        let expected = ExpectedPolicy {
+            viewing_key: Some(viewing_key.clone()),
+            ..advertised.clone()
+        };
+        assert_eq!(
+            compare(asset, expected.clone()).await.unwrap(),
+            PolicyComparison {
+                asset,
+                matches: true,
+                differences: vec![],
+            }
+        );
+
+        // An asset advertised without a viewing key, which actually has one, is caught.
+        assert_eq!(
+            compare(asset, advertised).await.unwrap(),
+            PolicyComparison {
+                asset,
+                matches: false,
+                differences: vec![PolicyDifference {
+                    field: String::from("viewing_key"),
+                    expected: None,
+                    actual: Some(viewing_key.to_string()),
+                }],
+            }
+        );
+
+        // The request fails for an invalid threshold or an unknown asset.
+        compare(
+            asset,
+            ExpectedPolicy {
+                viewing_threshold: String::from("ten"),
+                ..expected.clone()
+            },
+        )
+        .await
+        .expect_err("compared policy with an invalid threshold");
+        compare(
+            AssetCode::random(&mut ChaChaRng::from_seed([7; 32])).0,
+            expected,
+        )
+        .await
+        .expect_err("compared policy of an unknown asset");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_paymentrequest() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet_post::<PaymentRequest>(&format!(
+                "paymentrequest/asset/{}/amount/100/expiry/3600",
+                AssetCode::native()
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+
+        // Requests are created with POST, and must have a positive expiry.
+        server
+            .get::<PaymentRequest>(&format!(
+                "paymentrequest/asset/{}/amount/100/expiry/3600",
+                AssetCode::native()
+            ))
+            .await
+            .expect_err("created payment request with GET");
+        server
+            .post::<PaymentRequest>(&format!(
+                "paymentrequest/asset/{}/amount/100/expiry/0",
+                AssetCode::native()
+            ))
+            .await
+            .expect_err("created payment request with no expiry");
+        server
+            .get::<PaymentRequest>("paymentrequest/0")
+            .await
+            .expect_err("checked unknown payment request");
+
+        let request = server
+            .post::<PaymentRequest>(&format!(
+                "paymentrequest/asset/{}/amount/100/expiry/3600",
+                AssetCode::native()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(request.status, PaymentRequestStatus::Pending);
+        assert_eq!(
+            request.uri,
+            format!(
+                "cape:{}?asset={}&amount=100&nonce={}",
+                request.address,
+                AssetCode::native(),
+                request.nonce
+            )
+        );
+        assert_eq!(
+            server
+                .get::<PaymentRequest>(&format!("paymentrequest/{}", request.id))
+                .await
+                .unwrap()
+                .status,
+            PaymentRequestStatus::Pending
+        );
+
+        // Pay the request, and wait for it to be marked paid.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                request.address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<PaymentRequest>(&format!("paymentrequest/{}", request.id))
+                .await
+                .unwrap()
+                .status
+                == PaymentRequestStatus::Paid
+        })
+        .await;
+        let paid = server
+            .get::<PaymentRequest>(&format!("paymentrequest/{}", request.id))
+            .await
+            .unwrap();
+        assert_eq!(paid.received, 100u64.into());
+        assert!(paid.paid_ms.unwrap() <= paid.expires_ms);
+
+        // A request which is not paid in time expires.
+        let request = server
+            .post::<PaymentRequest>(&format!(
+                "paymentrequest/asset/{}/amount/100/expiry/1",
+                AssetCode::native()
+            ))
+            .await
+            .unwrap();
+        async_std::task::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            server
+                .get::<PaymentRequest>(&format!("paymentrequest/{}", request.id))
+                .await
+                .unwrap()
+                .status,
+            PaymentRequestStatus::Expired
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_submission_pause() {
+        let server = TestServer::with_options(|options| {
+            options.admin_token = Some(String::from("admin-token"));
+        })
+        .await;
+        let submission = |action: &'static str, token: &'static str| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .get(&format!("submission/{}", action))
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await?;
+                client::response_body::<SubmissionStatus>(&mut res).await
+            }
+        };
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let send = format!(
+            "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+            src_address,
+            &AssetCode::native(),
+            dst_address,
+            100,
+            1
+        );
+        let balance = format!(
+            "getbalance/address/{}/asset/{}",
+            dst_address,
+            AssetCode::native()
+        );
+
+        // Pausing requires the admin token.
+        submission("pause", "wrong-token")
+            .await
+            .expect_err("paused submission with the wrong token");
+        assert_eq!(
+            submission("pause", "admin-token").await.unwrap(),
+            SubmissionStatus { paused: true }
+        );
+
+        // While paused, transfers are rejected, but reads still work.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send)
+            .await
+            .expect_err("sent a transfer while submission is paused");
+        assert_eq!(
+            server.get::<BalanceInfo>(&balance).await.unwrap().balances,
+            Balances::One(0u64.into())
+        );
+
+        // Once resumed, the transfer goes through.
+        assert_eq!(
+            submission("resume", "admin-token").await.unwrap(),
+            SubmissionStatus { paused: false }
+        );
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send)
+            .await
+            .unwrap();
+        retry(|| async {
+            server.get::<BalanceInfo>(&balance).await.unwrap().balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_lock() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<LockStatus>("lock").await;
+
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let send = format!(
+            "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+            src_address,
+            &AssetCode::native(),
+            dst_address,
+            100,
+            1
+        );
+        let balance = format!(
+            "getbalance/address/{}/asset/{}",
+            dst_address,
+            AssetCode::native()
+        );
+        assert_eq!(
+            server.get::<LockStatus>("lock/status").await.unwrap(),
+            LockStatus {
+                locked: false,
+                pin: false
+            }
+        );
+
+        // Lock the wallet with a PIN. Locking it again does not replace the PIN.
+        let locked = LockStatus {
+            locked: true,
+            pin: true,
+        };
+        assert_eq!(
+            server.get::<LockStatus>("lock/pin/1234").await.unwrap(),
+            locked
+        );
+        assert_eq!(server.get::<LockStatus>("lock").await.unwrap(), locked);
+
+        // While locked, transfers are rejected, but reads still work.
+        let err = server
+            .client
+            .post(&send)
+            .send()
+            .await
+            .expect_err("sent a transfer while the wallet is locked");
+        assert!(err.to_string().contains("locked"), "{}", err);
+        assert_eq!(
+            server.get::<BalanceInfo>(&balance).await.unwrap().balances,
+            Balances::One(0u64.into())
+        );
+        let err = server
+            .post::<()>("closewallet")
+            .await
+            .expect_err("closed the wallet while it is locked");
+        assert_eq!(err.status(), surf::StatusCode::Locked);
+        server
+            .post::<LockStatus>("unlock/pin/4321")
+            .await
+            .expect_err("unlocked the wallet with the wrong PIN");
+        server
+            .post::<LockStatus>("unlock/mnemonic/not-the-mnemonic")
+            .await
+            .expect_err("unlocked the wallet with the wrong mnemonic");
+        assert_eq!(
+            server.get::<LockStatus>("lock/status").await.unwrap(),
+            locked
+        );
+
+        // Once unlocked with the mnemonic, the transfer goes through.
+        assert!(
+            !server
+                .post::<LockStatus>(&format!("unlock/mnemonic/{}", mnemonic))
+                .await
+                .unwrap()
+                .locked
+        );
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&send)
+            .await
+            .unwrap();
+        retry(|| async {
+            server.get::<BalanceInfo>(&balance).await.unwrap().balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+
+        // The PIN releases a lock as well, and private keys cannot be read while locked.
+        server.get::<LockStatus>("lock/pin/1234").await.unwrap();
+        server
+            .get::<PrivateKey>(&format!("getprivatekey/{}", src_address))
+            .await
+            .expect_err("read a private key while the wallet is locked");
+        assert!(
+            !server
+                .post::<LockStatus>("unlock/pin/1234")
+                .await
+                .unwrap()
+                .locked
+        );
+        server
+            .get::<PrivateKey>(&format!("getprivatekey/{}", src_address))
+            .await
+            .unwrap();
+
+        // After a few wrong guesses, even the right PIN has to wait out the backoff.
+        server.get::<LockStatus>("lock/pin/1234").await.unwrap();
+        for _ in 0..3 {
+            server
+                .post::<LockStatus>("unlock/pin/4321")
+                .await
+                .expect_err("unlocked the wallet with the wrong PIN");
+        }
+        let err = server
+            .post::<LockStatus>("unlock/pin/1234")
+            .await
+            .expect_err("unlocked the wallet while throttled");
+        assert_eq!(err.status(), surf::StatusCode::TooManyRequests);
+        async_std::task::sleep(crate::lock::UNLOCK_BACKOFF).await;
+        assert!(
+            !server
+                .post::<LockStatus>("unlock/pin/1234")
+                .await
+                .unwrap()
+                .locked
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_verifymnemonic() {
+        let server = TestServer::new().await;
+        let verify = |mnemonic: String| {
+            let server = &server;
+            async move {
+                let mut res = server
+                    .client
+                    .post("verifymnemonic")
+                    .body_json(&mnemonic)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap();
+                client::response_body::<bool>(&mut res).await
+            }
+        };
+
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        let other = server.get::<String>("getmnemonic").await.unwrap();
+        verify(mnemonic.clone())
+            .await
+            .expect_err("verified a mnemonic without an open wallet");
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // The wallet's mnemonic matches, with words separated either way.
+        assert!(verify(mnemonic.clone()).await.unwrap());
+        assert!(verify(mnemonic.replace('-', " ")).await.unwrap());
+
+        // A different mnemonic, or a phrase which is not a mnemonic, does not.
+        assert!(!verify(other).await.unwrap());
+        assert!(!verify(String::from("not a mnemonic")).await.unwrap());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_batch() {
+        let server = TestServer::new().await;
+        let batch = |paths: Vec<String>| {
+            let server = &server;
+            async move {
+                let mut res = server
+                    .client
+                    .post("batch")
+                    .body_json(&paths)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap();
+                client::response_body::<Vec<BatchResult>>(&mut res).await
+            }
+        };
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // Both reads are answered in one response, as they would be on their own.
+        let results = batch(vec![
+            String::from("getinfo"),
+            String::from("getbalance/all"),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].path, "getinfo");
+        assert_eq!(results[0].status, 200);
+        assert_eq!(
+            serde_json::from_value::<WalletSummary>(results[0].result.clone().unwrap()).unwrap(),
+            server.get::<WalletSummary>("getinfo").await.unwrap()
+        );
+        assert_eq!(results[1].path, "getbalance/all");
+        assert_eq!(results[1].status, 200);
+        assert_eq!(
+            serde_json::from_value::<BalanceInfo>(results[1].result.clone().unwrap()).unwrap(),
+            server.get::<BalanceInfo>("getbalance/all").await.unwrap()
+        );
+
+        // A read which fails does not affect the others.
+        let results = batch(vec![
+            format!("getaccount/{}", AssetCode::native()),
+            String::from("getaddress"),
+        ])
+        .await
+        .unwrap();
+        assert_ne!(results[0].status, 200);
+        assert_eq!(results[0].result, None);
+        assert!(results[0].error.is_some());
+        assert_eq!(
+            serde_json::from_value::<Vec<UserAddress>>(results[1].result.clone().unwrap()).unwrap(),
+            server.get::<Vec<UserAddress>>("getaddress").await.unwrap()
+        );
+
+        // A batch with a route which is not a read, or is not a route at all, is rejected, and
+        // nothing in it is done.
+        let keys = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .unwrap()
+            .sending_keys;
+        batch(vec![
+            String::from("getinfo"),
+            String::from("newkey/sending"),
+        ])
+        .await
+        .expect_err("batched a mutating route");
+        batch(vec![String::from("getinfo"), String::from("notaroute")])
+            .await
+            .expect_err("batched an unknown route");
+        assert_eq!(
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .sending_keys,
+            keys
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_prune_transactions() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<TransactionCount>("transactions/count")
+            .await;
+        server
+            .requires_wallet::<PrunedHistory>("transactions/prune/before/0")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Mint three records of a new asset, each in a later block than the last.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == asset)
+                .collect::<Vec<_>>()
+        };
+        for i in 0..3 {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset,
+                    i + 1,
+                    minter,
+                    minter
+                ))
+                .await
+                .unwrap();
+            retry(|| async { records().await.len() == i + 1 }).await;
+        }
+
+        // Find the block and transaction which created each mint, oldest first.
+        let mut mints = vec![];
+        for record in records().await {
+            let provenance = server
+                .get::<RecordProvenance>(&format!("record/{}/provenance", record.uid))
+                .await
+                .unwrap();
+            mints.push((provenance.block_id, provenance.transaction.unwrap()));
+        }
+        mints.sort();
+        assert!(mints[0].0 < mints[1].0 && mints[1].0 < mints[2].0);
+
+        let history = || async {
+            server
+                .get::<(
+                    Vec<TransactionHistoryEntry>,
+                    HashMap<AssetCode, Option<AssetInfo>>,
+                )>("transactionhistory")
+                .await
+                .unwrap()
+                .0
+                .into_iter()
+                .filter_map(|entry| entry.hash)
+                .collect::<Vec<_>>()
+        };
+        let before = history().await;
+        let count = server
+            .get::<TransactionCount>("transactions/count")
+            .await
+            .unwrap();
+        assert_eq!(count.pruned, 0);
+        let balances = server.get::<BalanceInfo>("getbalance/all").await.unwrap();
+
+        // Categorize the first mint, so that it survives pruning.
+        server
+            .post::<()>(&format!("transaction/category/{}/minting", mints[0].1))
+            .await
+            .unwrap();
+
+        // Prune everything committed before the last mint. The first mint is kept for its
+        // category, the second is pruned, and the last is too new.
+        let pruned = server
+            .get::<PrunedHistory>(&format!("transactions/prune/before/{}", mints[2].0))
+            .await
+            .unwrap();
+        assert!(pruned.pruned.contains(&mints[1].1));
+        assert!(!pruned.pruned.contains(&mints[0].1));
+        assert!(!pruned.pruned.contains(&mints[2].1));
+        let after = history().await;
+        assert_eq!(pruned.remaining, after.len());
+        assert_eq!(
+            after,
+            before
+                .iter()
+                .filter(|hash| !pruned.pruned.contains(hash))
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert!(after.contains(&mints[0].1));
+        assert!(after.contains(&mints[2].1));
+        assert_eq!(
+            server
+                .get::<TransactionCount>("transactions/count")
+                .await
+                .unwrap(),
+            TransactionCount {
+                transactions: count.transactions - pruned.pruned.len(),
+                pruned: pruned.pruned.len(),
+            }
+        );
+
+        // Pruning does not touch records.
+        assert_eq!(
+            server.get::<BalanceInfo>("getbalance/all").await.unwrap(),
+            balances
+        );
+
+        // Pruning again with the same cutoff finds nothing new.
+        let again = server
+            .get::<PrunedHistory>(&format!("transactions/prune/before/{}", mints[2].0))
+            .await
+            .unwrap();
+        assert!(again.pruned.is_empty());
+        assert_eq!(again.remaining, after.len());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_erc20_mapping() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<String>(&format!("erc20/byasset/{}", AssetCode::native()))
+            .await;
+        server
+            .requires_wallet::<Vec<AssetCode>>(&format!("asset/byerc20/{:#x}", Address::zero()))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // `populatefortest` sponsors a wrapped asset.
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let wrapped = server
+            .get::<WalletSummary>("getinfo")
+            .await
+            .unwrap()
+            .assets
+            .into_iter()
+            .find(|asset| asset.wrapped_erc20.is_some())
+            .unwrap();
+        let erc20 = wrapped.wrapped_erc20.unwrap();
+        let asset = wrapped.definition.code;
+
+        // The mapping resolves in both directions.
+        assert_eq!(
+            server
+                .get::<String>(&format!("erc20/byasset/{}", asset))
+                .await
+                .unwrap(),
+            erc20
+        );
+        assert_eq!(
+            server
+                .get::<Vec<AssetCode>>(&format!("asset/byerc20/{}", erc20))
+                .await
+                .unwrap(),
+            vec![asset]
+        );
+
+        // Domestic assets and unknown contracts are not found.
+        server
+            .get::<String>(&format!("erc20/byasset/{}", AssetCode::native()))
+            .await
+            .expect_err("found an ERC-20 contract for the native asset");
+        server
+            .get::<Vec<AssetCode>>(&format!("asset/byerc20/{:#x}", Address::zero()))
+            .await
+            .expect_err("found an asset for an unknown ERC-20 contract");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_stealthaddress() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<StealthAddress>("stealthaddress")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let total = || async {
+            match server
+                .get::<BalanceInfo>("getbalance/all")
+                .await
+                .unwrap()
+                .balances
+            {
+                Balances::All { aggregate, .. } => aggregate[&AssetCode::native()],
+                balances => panic!("expected Balances::All, got {:?}", balances),
+            }
+        };
+        let initial_total = total().await;
+
+        // Each request gives a new address, which belongs to the wallet.
+        let stealth = server
+            .get::<StealthAddress>("stealthaddress")
+            .await
+            .unwrap();
+        assert_eq!(
+            UserAddress::from(stealth.pub_key.address()),
+            stealth.address
+        );
+        assert_ne!(
+            server
+                .get::<StealthAddress>("stealthaddress")
+                .await
+                .unwrap()
+                .address,
+            stealth.address
+        );
+        assert!(server
+            .get::<Vec<UserAddress>>("getaddress")
+            .await
+            .unwrap()
+            .contains(&stealth.address));
+
+        // A payment to the address is attributed to it, and counts toward the wallet's total.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                stealth.address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    stealth.address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(100u64.into())
+        })
+        .await;
+        // The payment came from the wallet itself, so the total only goes down by the fee.
+        retry(|| async { total().await == initial_total - 1 }).await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_reconcile() {
+        let server = TestServer::new().await;
+        let reconcile = |expected: Vec<ExpectedHoldings>| {
+            let client = server.client.clone();
+            async move {
+                let mut res = client
+                    .post("reconcile")
+                    .body_json(&expected)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<Vec<HoldingsDiscrepancy>>(&mut res).await
+            }
+        };
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+
+        // Build the expectation from the wallet's own records, so that it matches.
+        let mut holdings = HashMap::<AssetCode, (usize, U256)>::new();
+        for record in server.get::<Vec<RecordInfo>>("getrecords").await.unwrap() {
+            let (count, total) = holdings.entry(record.ro.asset_def.code).or_default();
+            *count += 1;
+            *total += U256::from(record.ro.amount.generic_into::<u128>());
+        }
+        assert_eq!(holdings.len(), 2);
+        let mut expected = holdings
+            .iter()
+            .map(|(asset, (count, total))| ExpectedHoldings {
+                asset: *asset,
+                count: *count,
+                total: *total,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reconcile(expected.clone()).await.unwrap(), vec![]);
+
+        // Overstate the total of one asset. Only that asset is flagged, with the right delta.
+        let (count, total) = holdings[&AssetCode::native()];
+        for holdings in &mut expected {
+            if holdings.asset == AssetCode::native() {
+                holdings.total += U256::from(5);
+            }
+        }
+        assert_eq!(
+            reconcile(expected.clone()).await.unwrap(),
+            vec![HoldingsDiscrepancy {
+                asset: AssetCode::native(),
+                expected_count: count,
+                actual_count: count,
+                expected_total: total + U256::from(5),
+                actual_total: total,
+                count_delta: 0,
+                total_delta: String::from("-5"),
+            }]
+        );
+
+        // An asset which is held but not listed is flagged too.
+        let unlisted = expected
+            .into_iter()
+            .filter(|holdings| holdings.asset != AssetCode::native())
+            .collect::<Vec<_>>();
+        let discrepancies = reconcile(unlisted).await.unwrap();
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(discrepancies[0].asset, AssetCode::native());
+        assert_eq!(discrepancies[0].expected_count, 0);
+        assert_eq!(discrepancies[0].count_delta, count as i64);
+        assert_eq!(discrepancies[0].total_delta, total.to_string());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_reconcile_onchain() {
+        let erc20_code = Address::from([1u8; 20]);
+        let sponsor_addr = Address::from([2u8; 20]);
+        let server = TestServer::new().await;
+
+        // Should fail if a wallet is not already open.
+        server
+            .requires_wallet::<OnChainReconciliation>(&format!(
+                "reconcile/onchain/{}",
+                AssetCode::native()
+            ))
+            .await;
+
+        // Open a wallet with some native tokens, and sponsor a wrapped asset.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let address: UserAddress = receipt.submitters[0].clone().into();
+        let (asset, info) = server
+            .post::<(sol::AssetDefinition, String)>(&format!(
+                "buildsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, sponsor_addr
+            ))
+            .await
+            .unwrap();
+        server
+            .client
+            .post("importasset")
+            .body_json(&info)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        server
+            .client
+            .post(&format!(
+                "submitsponsor/erc20/{:#x}/sponsor/{:#x}",
+                erc20_code, sponsor_addr
+            ))
+            .body_json(&asset)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let asset = JfAssetDefinition::from(asset).code;
+        let reconcile = || async {
+            server
+                .get::<OnChainReconciliation>(&format!("reconcile/onchain/{}", asset))
+                .await
+        };
+
+        // Nothing has been wrapped yet, so the balances match.
+        let reconciliation = reconcile().await.unwrap();
+        assert_eq!(reconciliation.erc20_code, erc20_code);
+        assert_eq!(reconciliation.wallet_balance, U256::zero());
+        assert_eq!(reconciliation.onchain_total, U256::zero());
+        assert_eq!(reconciliation.delta, "0");
+        assert!(reconciliation.consistent);
+
+        // Only wrapped assets are backed by the contract.
+        server
+            .get::<OnChainReconciliation>(&format!("reconcile/onchain/{}", AssetCode::native()))
+            .await
+            .expect_err("reconciled an asset which is not wrapped");
+
+        // Deposit some tokens. The contract holds them as soon as they are deposited, but the
+        // wrapped record is not created until the deposit is finalized in the next block.
+        let ro = server
+            .post::<sol::RecordOpening>(&format!(
+                "buildwrap/destination/{}/asset/{}/amount/{}",
+                address, asset, 10
+            ))
+            .await
+            .unwrap();
+        server
+            .client
+            .post(&format!("submitwrap/ethaddress/{:#x}", sponsor_addr))
+            .body_json(&ro)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let reconciliation = reconcile().await.unwrap();
+        assert_eq!(reconciliation.wallet_balance, U256::zero());
+        assert_eq!(reconciliation.onchain_total, U256::from(10));
+        assert_eq!(reconciliation.delta, "10");
+        assert!(!reconciliation.consistent);
+
+        // Commit a block to finalize the deposit, and the balances match again.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                address,
+                AssetCode::native(),
+                address,
+                1,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async { reconcile().await.unwrap().consistent }).await;
+        let reconciliation = reconcile().await.unwrap();
+        assert_eq!(reconciliation.wallet_balance, U256::from(10));
+        assert_eq!(reconciliation.delta, "0");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_skippedevents() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<SkippedEvent>>("skippedevents")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+        };
+        let skipped = || async {
+            server
+                .get::<Vec<SkippedEvent>>("skippedevents")
+                .await
+                .unwrap()
+        };
+        assert_eq!(skipped().await, vec![]);
+
+        // Make the wallet fail to process the memos of a transfer to `dst_address`.
+        server.post::<()>("skipmemosfortest").await.unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async { !skipped().await.is_empty() }).await;
+        let events = skipped().await;
+        assert_eq!(events.len(), 1);
+        let received = events[0]
+            .records
+            .iter()
+            .filter(|record| record.address == dst_address)
+            .collect::<Vec<_>>();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].asset, AssetCode::native());
+        assert_eq!(received[0].amount, "100");
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Replaying the event adds its records to the wallet, after which nothing is skipped.
+        assert_eq!(
+            server
+                .get::<Vec<SkippedEvent>>("skippedevents/replay")
+                .await
+                .unwrap(),
+            events
+        );
+        assert_eq!(balance().await, Balances::One(100u64.into()));
+        assert_eq!(skipped().await, vec![]);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_feetopup() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Option<FeeTopUpPolicy>>("feetopup")
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<Option<FeeTopUpPolicy>>("feetopup")
+                .await
+                .unwrap(),
+            None
+        );
+
+        // After populating, the wallet has two native records large enough to be fee records: one
+        // at the faucet address and one at the address holding the wrapped asset.
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+        let wrapper_address: UserAddress = records
+            .iter()
+            .find(|record| {
+                record.ro.asset_def.code == AssetCode::native()
+                    && record.ro.amount == DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR.into()
+            })
+            .unwrap()
+            .ro
+            .pub_key
+            .address()
+            .into();
+        let wrapped_asset = records
+            .iter()
+            .find(|record| record.ro.asset_def.code != AssetCode::native())
+            .unwrap()
+            .ro
+            .asset_def
+            .code;
+        let native_records = || async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|record| record.ro.asset_def.code == AssetCode::native())
+                .map(|record| record.ro.amount.generic_into::<u128>())
+                .collect::<Vec<_>>()
+        };
+
+        // Setting a policy requires POST and a positive amount.
+        server
+            .get::<Option<FeeTopUpPolicy>>("feetopup/2/100")
+            .await
+            .expect_err("set a fee top-up policy with GET");
+        server
+            .post::<Option<FeeTopUpPolicy>>("feetopup/2/0")
+            .await
+            .expect_err("set a fee top-up policy with a zero amount");
+        assert_eq!(
+            server
+                .post::<Option<FeeTopUpPolicy>>("feetopup/2/100")
+                .await
+                .unwrap(),
+            Some(FeeTopUpPolicy {
+                threshold: 2,
+                amount: 100u64.into(),
+                top_ups: 0,
+                last_error: None,
+            })
+        );
+
+        // Spend most of the fee record at the wrapper address on the fee of a transfer, leaving
+        // change too small to be a fee record.
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/1/fee/{}",
+                wrapper_address,
+                wrapped_asset,
+                wrapper_address,
+                DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR - 50
+            ))
+            .await
+            .unwrap();
+
+        // Without any further requests, a new fee record of 100 is split off the largest native
+        // record, restoring the number of fee records.
+        retry(|| async {
+            let records = native_records().await;
+            records.contains(&50)
+                && records.contains(&100)
+                && records.iter().filter(|amount| **amount >= 100).count() >= 2
+        })
+        .await;
+        let policy = server
+            .get::<Option<FeeTopUpPolicy>>("feetopup")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(policy.top_ups, 1);
+        assert_eq!(policy.last_error, None);
+
+        // A threshold of 0 removes the policy.
+        assert_eq!(
+            server
+                .post::<Option<FeeTopUpPolicy>>("feetopup/0/100")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_balanceproof() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<ReservesAttestation>(&format!(
+                "balanceproof/{}/0",
+                AssetCode::native()
+            ))
+            .await;
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+
+        // Record the current balance, then advance the ledger by paying a fee.
+        let before = server
+            .get::<ReservesAttestation>(&format!("proofofreserves/{}", AssetCode::native()))
+            .await
+            .unwrap();
+        assert_eq!(
+            before.total,
+            U256::from(DEFAULT_NATIVE_AMT_IN_FAUCET_ADDR + DEFAULT_NATIVE_AMT_IN_WRAPPER_ADDR)
+        );
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<ReservesAttestation>(&format!("proofofreserves/{}", AssetCode::native()))
+                .await
+                .unwrap()
+                .total
+                == before.total - 1
+        })
+        .await;
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert!(info.sync_time > before.block_height);
+
+        // A proof at the earlier block attests to the earlier balance, and verifies.
+        let mut proof = server
+            .get::<ReservesAttestation>(&format!(
+                "balanceproof/{}/{}",
+                AssetCode::native(),
+                before.block_height
+            ))
+            .await
+            .unwrap();
+        assert!(proof.verify());
+        assert_eq!(proof.block_height, before.block_height);
+        assert_eq!(proof.total, before.total);
+
+        // A proof at the tip attests to the current balance.
+        let proof_now = server
+            .get::<ReservesAttestation>(&format!(
+                "balanceproof/{}/{}",
+                AssetCode::native(),
+                info.sync_time
+            ))
+            .await
+            .unwrap();
+        assert!(proof_now.verify());
+        assert_eq!(proof_now.total, before.total - 1);
+
+        // Blocks beyond the synced tip are rejected.
+        server
+            .get::<ReservesAttestation>(&format!(
+                "balanceproof/{}/{}",
+                AssetCode::native(),
+                info.sync_time + 100
+            ))
+            .await
+            .expect_err("balance proof beyond the synced tip");
+
+        // The signatures cover the block, so a proof cannot be moved to a different one.
+        proof.block_height = info.sync_time;
+        assert!(!proof.verify());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_proofconfig() {
+        let server = TestServer::with_options(|options| {
+            options.proof_threads = Some(2);
+        })
+        .await;
+        let config = server.get::<ProofConfig>("proofconfig").await.unwrap();
+        assert_eq!(config.threads, 2);
+        assert!(config.available_threads >= 1);
+        assert_eq!(config.last_build_threads, None);
+
+        // Changing the configuration requires POST and a positive number of threads.
+        server
+            .get::<ProofConfig>("proofconfig/threads/1")
+            .await
+            .expect_err("changed the proof configuration with GET");
+        server
+            .post::<ProofConfig>("proofconfig/threads/0")
+            .await
+            .expect_err("changed the proof configuration to 0 threads");
+        assert_eq!(
+            server.get::<ProofConfig>("proofconfig").await.unwrap(),
+            config
+        );
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+
+        // Transfers succeed with a single thread and with several, and their proofs are generated
+        // with the configured number of threads.
+        for (i, threads) in [1, 3].into_iter().enumerate() {
+            assert_eq!(
+                server
+                    .post::<ProofConfig>(&format!("proofconfig/threads/{}", threads))
+                    .await
+                    .unwrap()
+                    .threads,
+                threads
+            );
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                    src_address,
+                    &AssetCode::native(),
+                    dst_address,
+                    10,
+                    1
+                ))
+                .await
+                .unwrap();
+            assert_eq!(
+                server
+                    .get::<ProofConfig>("proofconfig")
+                    .await
+                    .unwrap()
+                    .last_build_threads,
+                Some(threads)
+            );
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!(
+                        "getbalance/address/{}/asset/{}",
+                        dst_address,
+                        AssetCode::native()
+                    ))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One((10 * (i as u64 + 1)).into())
+            })
+            .await;
+        }
+
+        // So are the proofs of exported transactions.
+        server
+            .post::<ProofConfig>("proofconfig/threads/2")
+            .await
+            .unwrap();
+        server
+            .post::<ExportedTransaction>(&format!(
+                "exporttransaction/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                10,
+                1
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<ProofConfig>("proofconfig")
+                .await
+                .unwrap()
+                .last_build_threads,
+            Some(2)
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_routes_stats() {
+        let server = TestServer::new().await;
+        assert_eq!(
+            server.get::<Vec<RouteStats>>("routes/stats").await.unwrap(),
+            vec![]
+        );
+
+        // Two successful calls to one route, and three failing calls to another route through
+        // different URLs matching the same pattern.
+        for _ in 0..2 {
+            server.get::<String>("getmnemonic").await.unwrap();
+        }
+        for asset in [AssetCode::native(), AssetCode::dummy(), AssetCode::native()] {
+            server
+                .get::<WalletSummary>(&format!("getinfo/asset/{}", asset))
+                .await
+                .expect_err("getinfo succeeded without a wallet");
+        }
+
+        let stats = server.get::<Vec<RouteStats>>("routes/stats").await.unwrap();
+        let patterns = stats
+            .iter()
+            .map(|stats| stats.pattern.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            patterns,
+            vec!["getinfo/asset/:asset", "getmnemonic", "routes/stats"]
+        );
+        let stats = stats
+            .into_iter()
+            .map(|stats| (stats.pattern.clone(), stats))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(stats["getmnemonic"].calls, 2);
+        assert_eq!(stats["getmnemonic"].errors, 0);
+        assert_eq!(stats["getinfo/asset/:asset"].calls, 3);
+        assert_eq!(stats["getinfo/asset/:asset"].errors, 3);
+        assert_eq!(stats["routes/stats"].calls, 1);
+        assert_eq!(stats["routes/stats"].errors, 0);
+        for stats in stats.values() {
+            assert!(stats.p50_us <= stats.p90_us);
+            assert!(stats.p90_us <= stats.p99_us);
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_webhook() {
+        use crate::webhook::{sign, SIGNATURE_HEADER};
+        use async_std::sync::{Arc, Mutex};
+
+        // A receiver which records the signature and body of each request it gets, and fails the
+        // first one, so that the server has to retry.
+        type Received = Arc<Mutex<Vec<(Option<String>, Vec<u8>)>>>;
+        async fn receive(mut req: tide::Request<Received>) -> tide::Result<tide::StatusCode> {
+            let signature = req
+                .header(SIGNATURE_HEADER)
+                .map(|values| values.last().to_string());
+            let body = req.body_bytes().await?;
+            let mut received = req.state().lock().await;
+            received.push((signature, body));
+            Ok(if received.len() == 1 {
+                tide::StatusCode::InternalServerError
+            } else {
+                tide::StatusCode::Ok
+            })
+        }
+        let received = Received::default();
+        let receiver_port = port().await;
+        let mut receiver = tide::with_state(received.clone());
+        receiver.at("settled").post(receive);
+        async_std::task::spawn(receiver.listen(format!("127.0.0.1:{}", receiver_port)));
+        TestServer::wait(receiver_port).await;
+
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Option<WebhookInfo>>("webhook")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        assert_eq!(
+            server.get::<Option<WebhookInfo>>("webhook").await.unwrap(),
+            None
+        );
+
+        let register = |url: String, secret: Option<&str>| {
+            let client = server.client.clone();
+            let registration = WebhookRegistration {
+                url,
+                secret: secret.map(String::from),
+            };
+            async move {
+                let mut res = client
+                    .post("webhook")
+                    .body_json(&registration)
+                    .unwrap()
+                    .send()
+                    .await?;
+                client::response_body::<Option<WebhookInfo>>(&mut res).await
+            }
+        };
+
+        // Only http and https URLs can be registered.
+        register("ftp://localhost/settled".to_string(), None)
+            .await
+            .expect_err("registered a webhook with an ftp URL");
+        let url = format!("http://127.0.0.1:{}/settled", receiver_port);
+        let info = register(url.clone(), Some("my-secret")).await.unwrap();
+        assert_eq!(
+            info,
+            Some(WebhookInfo {
+                url: url.clone(),
+                signed: true,
+                delivered: 0,
+                failed: 0,
+                last_error: None,
+            })
+        );
+        assert_eq!(
+            server.get::<Option<WebhookInfo>>("webhook").await.unwrap(),
+            info
+        );
+
+        // Make a transfer and wait for its settlement to be delivered, after one failed attempt.
+        // Transactions which settled before the webhook was registered are not delivered.
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let transfer = server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                src_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        let hash = transaction_hash_string(&transfer.uid.0).unwrap();
+        retry(|| async {
+            server
+                .get::<Option<WebhookInfo>>("webhook")
+                .await
+                .unwrap()
+                .unwrap()
+                .delivered
+                == 1
+        })
+        .await;
+        let received = received.lock().await.clone();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], received[1]);
+        let (signature, body) = &received[1];
+        assert_eq!(signature.as_ref().unwrap(), &sign("my-secret", body));
+        assert_ne!(signature.as_ref().unwrap(), &sign("other-secret", body));
+        let settlement: SettlementEvent = serde_json::from_slice(body).unwrap();
+        assert_eq!(settlement.hash, hash);
+        assert_eq!(settlement.transaction.hash, Some(hash));
+        assert_eq!(settlement.transaction.kind, "send");
+        assert_eq!(settlement.transaction.status, "accepted");
+
+        // Remove the webhook.
+        let mut res = server.client.delete("webhook").send().await.unwrap();
+        assert_eq!(
+            client::response_body::<Option<WebhookInfo>>(&mut res)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            server.get::<Option<WebhookInfo>>("webhook").await.unwrap(),
+            None
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_checkrecipient() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let pub_key = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key,
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let check = |key: String| {
+            let server = &server;
+            async move {
+                server
+                    .get::<RecipientCheck>(&format!("checkrecipient/{}", key))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // A fresh receiving key is usable.
+        assert_eq!(
+            check(pub_key.to_string()).await,
+            RecipientCheck {
+                ok: true,
+                address: Some(pub_key.address().into()),
+                error: None,
+            }
+        );
+
+        // A truncated key is caught by the TaggedBase64 checksum.
+        let key = pub_key.to_string();
+        let truncated = check(key[..key.len() - 4].to_string()).await;
+        assert!(!truncated.ok);
+        assert_eq!(truncated.address, None);
+        assert!(truncated.error.unwrap().contains("truncated or corrupted"));
+
+        // A key with a character changed is also caught.
+        let mut garbled = key.into_bytes();
+        let i = garbled.len() / 2;
+        garbled[i] = if garbled[i] == b'A' { b'B' } else { b'A' };
+        let garbled = check(String::from_utf8(garbled).unwrap()).await;
+        assert!(!garbled.ok);
+        assert!(garbled.error.unwrap().contains("truncated or corrupted"));
+
+        // Well-formed values which are not receiving keys are rejected with an explanation.
+        let address = check(UserAddress::from(pub_key.address()).to_string()).await;
+        assert!(!address.ok);
+        assert!(address.error.unwrap().contains("this is an address"));
+        let asset = check(AssetCode::native().to_string()).await;
+        assert!(!asset.ok);
+        assert!(asset
+            .error
+            .unwrap()
+            .contains("expected a receiving key with tag USERPUBKEY"));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_balanceindex() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let stats = server
+            .get::<BalanceIndexStats>("balanceindex")
+            .await
+            .unwrap();
+        assert_eq!(stats.event_index, None);
+        assert_eq!(stats.updates, 0);
+
+        // Split the faucet balance into many records by making a series of small transfers.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let transfers = 8u64;
+        for i in 1..=transfers {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/1/fee/1",
+                    src_address,
+                    &AssetCode::native(),
+                    dst_address,
+                ))
+                .await
+                .unwrap();
+            retry(|| async {
+                server
+                    .get::<BalanceInfo>(&format!(
+                        "getbalance/address/{}/asset/{}",
+                        dst_address,
+                        AssetCode::native()
+                    ))
+                    .await
+                    .unwrap()
+                    .balances
+                    == Balances::One(i.into())
+            })
+            .await;
+        }
+
+        // Compute the balances by brute force from the records, and compare them to the indexed
+        // balances reported by `getbalance/all`.
+        let records = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.ro.freeze_flag == FreezeFlag::Unfrozen)
+            .collect::<Vec<_>>();
+        assert!(records.len() as u64 > transfers);
+        let mut totals = HashMap::<(UserAddress, AssetCode), U256>::new();
+        for record in &records {
+            *totals
+                .entry((record.ro.pub_key.address().into(), record.ro.asset_def.code))
+                .or_default() += record.ro.amount.generic_into::<u128>().into();
+        }
+        let by_account = match server
+            .get::<BalanceInfo>("getbalance/all")
+            .await
+            .unwrap()
+            .balances
+        {
+            Balances::All { by_account, .. } => by_account,
+            balances => panic!("Expected Balances::All, found {:?}", balances),
+        };
+        for (address, balances) in &by_account {
+            for (asset, balance) in balances {
+                assert_eq!(
+                    *balance,
+                    totals
+                        .get(&(address.clone(), *asset))
+                        .copied()
+                        .unwrap_or_default()
+                );
+            }
+        }
+        for ((address, asset), total) in &totals {
+            assert_eq!(by_account[address][asset], *total);
+        }
+        assert_eq!(
+            by_account[&dst_address][&AssetCode::native()],
+            transfers.into()
+        );
+
+        // The index holds every record, and is not updated again by lookups while the wallet does
+        // not change.
+        let stats = server
+            .get::<BalanceIndexStats>("balanceindex")
+            .await
+            .unwrap();
+        assert_eq!(stats.records, records.len());
+        assert_eq!(stats.accounts, totals.len());
+        assert!(stats.event_index.is_some());
+        assert_eq!(stats.rebuilds, 1);
+        server
+            .get::<BalanceInfo>(&format!("getbalance/address/{}", src_address))
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<BalanceIndexStats>("balanceindex")
+                .await
+                .unwrap()
+                .updates,
+            stats.updates
+        );
+
+        // `fsck` agrees that the index is consistent with the wallet.
+        assert_eq!(
+            server.get::<FsckReport>("fsck").await.unwrap(),
+            FsckReport::default()
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_anomalies() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<ReplayedRecord>>("anomalies/duplicates")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<Vec<ReplayedRecord>>("anomalies/duplicates")
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+        let record = records
+            .iter()
+            .find(|record| record.ro.asset_def.code != AssetCode::native())
+            .unwrap();
+        let address: UserAddress = record.ro.pub_key.address().into();
+        let asset = record.ro.asset_def.code;
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", address, asset))
+                .await
+                .unwrap()
+                .balances
+        };
+        assert_eq!(balance().await, Balances::One(DEFAULT_WRAPPED_AMT.into()));
+
+        // Have the ledger present the wrapped record again, and wait for the wallet to pick up
+        // the second copy.
+        server
+            .post::<()>(&format!("replayrecordfortest/{}", record.uid))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<Vec<RecordInfo>>("getrecords")
+                .await
+                .unwrap()
+                .len()
+                == records.len() + 1
+        })
+        .await;
+
+        // The copy is reported, but not counted in the balance.
+        let replayed = server
+            .get::<Vec<ReplayedRecord>>("anomalies/duplicates")
+            .await
+            .unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_ne!(replayed[0].uid, record.uid);
+        assert_eq!(replayed[0].original_uid, record.uid);
+        assert_eq!(replayed[0].commitment, RecordCommitment::from(&record.ro));
+        assert_eq!(replayed[0].address, address);
+        assert_eq!(replayed[0].asset, asset);
+        assert_eq!(replayed[0].amount, DEFAULT_WRAPPED_AMT.into());
+        assert_eq!(balance().await, Balances::One(DEFAULT_WRAPPED_AMT.into()));
+
+        // The index still agrees with the wallet, once the replayed record is accounted for.
+        assert!(!server
+            .get::<FsckReport>("fsck")
+            .await
+            .unwrap()
+            .anomalies
+            .iter()
+            .any(|anomaly| matches!(anomaly, FsckAnomaly::StaleBalanceIndex { .. })));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_client_certificates() {
+        use async_std::net::TcpStream;
+        use futures::{AsyncReadExt, AsyncWriteExt};
+        use futures_rustls::{
+            rustls::{self, ClientConfig, RootCertStore, ServerName},
+            TlsConnector,
+        };
+        use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+        use std::sync::Arc;
+
+        // A CA which issues the server's certificate and a client certificate.
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let ca = Certificate::from_params(params).unwrap();
+        let server_cert =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+                .unwrap();
+        let mut params = CertificateParams::new(vec![]);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test-client");
+        let client_cert = Certificate::from_params(params).unwrap();
+
+        // Start a server which requires client certificates issued by the CA.
+        let temp_dir = TempDir::new("test_wallet_api_storage").unwrap();
+        let write = |name: &str, contents: String| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        let port = port().await;
+        let mut options = NodeOpt::for_test(port, temp_dir.path().to_path_buf());
+        options.tls_cert = Some(write(
+            "server.pem",
+            server_cert.serialize_pem_with_signer(&ca).unwrap(),
+        ));
+        options.tls_key = Some(write("server.key", server_cert.serialize_private_key_pem()));
+        options.client_ca = Some(write("ca.pem", ca.serialize_pem().unwrap()));
+        init_server(ChaChaRng::from_seed([42; 32]), &options).unwrap();
+        retry(|| async move { TcpStream::connect(("localhost", port)).await.is_ok() }).await;
+
+        // Request `auth/peer` over a TLS connection, authenticating with `client_auth` if given.
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+            .unwrap();
+        let get_peer = |client_auth: Option<(rustls::Certificate, rustls::PrivateKey)>| {
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots.clone());
+            let config = match client_auth {
+                Some((cert, key)) => builder.with_single_cert(vec![cert], key).unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            async move {
+                let tcp = TcpStream::connect(("localhost", port)).await?;
+                let mut tls = TlsConnector::from(Arc::new(config))
+                    .connect(ServerName::try_from("localhost").unwrap(), tcp)
+                    .await?;
+                tls.write_all(
+                    b"GET /auth/peer HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\
+                      Connection: close\r\n\r\n",
+                )
+                .await?;
+                tls.flush().await?;
+                let mut response = vec![];
+                tls.read_to_end(&mut response).await?;
+                Ok::<_, std::io::Error>(String::from_utf8(response).unwrap())
+            }
+        };
+
+        // A client with a certificate issued by the CA is served, and its certificate is reported.
+        let response = get_peer(Some((
+            rustls::Certificate(client_cert.serialize_der_with_signer(&ca).unwrap()),
+            rustls::PrivateKey(client_cert.serialize_private_key_der()),
+        )))
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            serde_json::from_str::<Option<PeerCertificate>>(body).unwrap(),
+            Some(PeerCertificate {
+                subject: "CN=test-client".to_string(),
+                issuer: "CN=Test CA".to_string(),
+            })
+        );
+
+        // Clients without a certificate, or with a certificate the CA did not issue, are rejected
+        // during the handshake. Depending on the TLS version, the client may only find out when it
+        // uses the connection.
+        let mut params = CertificateParams::new(vec![]);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test-client");
+        let self_signed = Certificate::from_params(params).unwrap();
+        for client_auth in [
+            None,
+            Some((
+                rustls::Certificate(self_signed.serialize_der().unwrap()),
+                rustls::PrivateKey(self_signed.serialize_private_key_der()),
+            )),
+        ] {
+            if let Ok(response) = get_peer(client_auth).await {
+                assert!(!response.starts_with("HTTP/1.1 200"), "{}", response);
+            }
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_teststate() {
+        use crate::teststate::TestState;
+
+        let server = TestServer::new().await;
+        let password = base64("my-password".as_bytes());
+        server
+            .requires_wallet::<TestState>("teststate/snapshot")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                password,
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Build some state and save it.
+        server.post::<PubKey>("newkey/sending").await.unwrap();
+        server.post::<PubKey>("newkey/viewing").await.unwrap();
+        server.post::<PubKey>("newkey/freezing").await.unwrap();
+        let before = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let snapshot = server.get::<TestState>("teststate/snapshot").await.unwrap();
+        assert!(!snapshot.files.is_empty());
+
+        // Change the wallet.
+        let key = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key,
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+        server.post::<PubKey>("newkey/viewing").await.unwrap();
+        let after = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert!(after.sending_keys.contains(&key));
+        assert_eq!(after.viewing_keys.len(), before.viewing_keys.len() + 1);
+
+        // Restoring the snapshot undoes the change.
+        server
+            .client
+            .post(&format!("teststate/restore/{}", password))
+            .body_json(&snapshot)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        let restored = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert!(!restored.sending_keys.contains(&key));
+        assert_eq!(restored.sending_keys, before.sending_keys);
+        assert_eq!(restored.viewing_keys, before.viewing_keys);
+        assert_eq!(restored.freezing_keys, before.freezing_keys);
+
+        // The snapshot can be restored more than once.
+        server.post::<PubKey>("newkey/sending").await.unwrap();
+        server
+            .client
+            .post(&format!("teststate/restore/{}", password))
+            .body_json(&snapshot)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            server
+                .get::<WalletSummary>("getinfo")
+                .await
+                .unwrap()
+                .sending_keys,
+            before.sending_keys
+        );
+
+        // Restoring requires the wallet's password.
+        server
+            .client
+            .post(&format!(
+                "teststate/restore/{}",
+                base64("wrong-password".as_bytes())
+            ))
+            .body_json(&snapshot)
+            .unwrap()
+            .send()
+            .await
+            .unwrap_err();
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_asset_activity() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let now = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        };
+
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<AssetActivity>>("assets/activity")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        let wrapped = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|record| record.ro.asset_def.code != AssetCode::native())
+            .unwrap();
+        let wrapped_asset = wrapped.ro.asset_def.code;
+        let wrapped_addr: UserAddress = wrapped.ro.pub_key.address().into();
+        let dst_addr: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+
+        // Transact in the native asset, and later in the wrapped asset. Wait between transactions
+        // so they get distinct timestamps.
+        let send = |sender: UserAddress, asset: AssetCode| {
+            let server = &server;
+            let dst_addr = dst_addr.clone();
+            async move {
+                server
+                    .post::<TransactionReceipt<CapeLedger>>(&format!(
+                        "send/sender/{}/asset/{}/recipient/{}/amount/1/fee/1",
+                        sender, asset, dst_addr,
+                    ))
+                    .await
+                    .unwrap();
+                retry(|| async {
+                    server
+                        .get::<BalanceInfo>(&format!(
+                            "getbalance/address/{}/asset/{}",
+                            dst_addr, asset
+                        ))
+                        .await
+                        .unwrap()
+                        .balances
+                        != Balances::One(0u64.into())
+                })
+                .await;
+            }
+        };
+        async_std::task::sleep(Duration::from_secs(2)).await;
+        let native_start = now();
+        send(faucet_addr, AssetCode::native()).await;
+        let native_end = now();
+        async_std::task::sleep(Duration::from_secs(2)).await;
+        let wrapped_start = now();
+        send(wrapped_addr, wrapped_asset).await;
+        let wrapped_end = now();
+
+        let activity = server
+            .get::<Vec<AssetActivity>>("assets/activity")
+            .await
+            .unwrap();
+        assert_eq!(activity.len(), 2, "{:?}", activity);
+        assert_eq!(activity[0].asset, wrapped_asset);
+        assert_eq!(activity[1].asset, AssetCode::native());
+        let wrapped_time = activity[0].last_activity.unwrap();
+        let native_time = activity[1].last_activity.unwrap();
+        assert!((wrapped_start..=wrapped_end).contains(&wrapped_time));
+        assert!((native_start..=native_end).contains(&native_time));
+        assert!(activity.iter().all(|asset| asset.has_balance));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_testvectors() {
+        // Test vectors are only available in dev mode.
+        let server = TestServer::new().await;
+        let mnemonic = server.get::<String>("getmnemonic").await.unwrap();
+        server
+            .get::<Vec<KeyTestVector>>(&format!("testvectors/{}", mnemonic))
+            .await
+            .unwrap_err();
+
+        let server = TestServer::with_options(|options| {
+            options.dev_mode = true;
+        })
+        .await;
+        server
+            .get::<Vec<KeyTestVector>>("testvectors/not-a-mnemonic")
+            .await
+            .unwrap_err();
+        server
+            .get::<Vec<KeyTestVector>>(&format!("testvectors/{}/count/101", mnemonic))
+            .await
+            .unwrap_err();
+
+        // The vectors are deterministic.
+        let vectors = server
+            .get::<Vec<KeyTestVector>>(&format!("testvectors/{}", mnemonic))
+            .await
+            .unwrap();
+        assert_eq!(vectors.len(), 5);
+        assert_eq!(
+            vectors,
+            server
+                .get::<Vec<KeyTestVector>>(&format!("testvectors/{}", mnemonic))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vectors[..2],
+            server
+                .get::<Vec<KeyTestVector>>(&format!("testvectors/{}/count/2", mnemonic))
+                .await
+                .unwrap()
+        );
+        for (i, vector) in vectors.iter().enumerate() {
+            assert_eq!(vector.index, i as u64);
+            assert_eq!(
+                vector.address,
+                UserAddress::from(vector.sending_key.address())
+            );
+        }
+        assert_ne!(vectors[0].sending_key, vectors[1].sending_key);
+
+        // They match the keys a wallet created from the same mnemonic generates.
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                mnemonic,
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        for vector in &vectors[..2] {
+            assert_eq!(
+                server.post::<PubKey>("newkey/sending").await.unwrap(),
+                PubKey::Sending(vector.sending_key.clone())
+            );
+            assert_eq!(
+                server.post::<PubKey>("newkey/viewing").await.unwrap(),
+                PubKey::Viewing(vector.viewing_key.clone())
+            );
+            assert_eq!(
+                server.post::<PubKey>("newkey/freezing").await.unwrap(),
+                PubKey::Freezing(vector.freezing_key.clone())
+            );
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_features() {
+        let server = TestServer::with_options(|options| {
+            options.disabled_routes = vec![ApiRouteKey::newasset];
+        })
+        .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // The disabled route fails, even with valid parameters.
+        let description = base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD);
+        let err = server
+            .post::<AssetInfo>(&format!("newasset/description/{}", description))
+            .await
+            .expect_err("created an asset with newasset disabled");
+        assert_eq!(err.status(), surf::StatusCode::Forbidden);
+        assert!(err.to_string().contains("disabled"), "{}", err);
+
+        // Other routes still work.
+        server.get::<WalletSummary>("getinfo").await.unwrap();
+
+        let features = server.get::<Features>("features").await.unwrap();
+        assert_eq!(features.disabled, vec![String::from("newasset")]);
+        assert!(features.enabled.contains(&String::from("getinfo")));
+        assert!(features.enabled.contains(&String::from("features")));
+        assert!(!features.enabled.contains(&String::from("newasset")));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_alerts() {
+        use async_std::io::prelude::BufReadExt;
+
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<AlertThreshold>>("alerts")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        // Alert on transactions of more than 10 units of the asset.
+        assert_eq!(
+            server.get::<Vec<AlertThreshold>>("alerts").await.unwrap(),
+            vec![]
+        );
+        let expected = vec![AlertThreshold {
+            asset,
+            amount: U256::from(10),
+        }];
+        assert_eq!(
+            server
+                .post::<Vec<AlertThreshold>>(&format!("alerts/threshold/{}/10", asset))
+                .await
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            server.get::<Vec<AlertThreshold>>("alerts").await.unwrap(),
+            expected
+        );
+
+        let mut res = server.client.get("subscribe/alerts").send().await.unwrap();
+        let mut lines = res.take_body().lines();
+
+        // Mint 5 units, below the threshold, and then 20, above it.
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                .await
+                .unwrap()
+                .balances
+        };
+        let mut minted = 0u64;
+        let mut hashes = vec![];
+        for amount in [5u64, 20] {
+            let receipt = server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            hashes.push(transaction_hash_string(&receipt.uid.0).unwrap());
+            minted += amount;
+            retry(|| async { balance().await == Balances::One(minted.into()) }).await;
+        }
+
+        // Only the second mint raises an alert, so it is the first event streamed.
+        let (name, data) =
+            async_std::future::timeout(std::time::Duration::from_secs(60), next_event(&mut lines))
+                .await
+                .expect("alert was not streamed");
+        assert_eq!(name, "large_transaction");
+        let alert = serde_json::from_str::<LargeTransaction>(&data).unwrap();
+        assert_eq!(alert.hash, hashes[1]);
+        assert_eq!(alert.asset, asset);
+        assert_eq!(alert.amount, U256::from(20));
+        assert_eq!(alert.threshold, U256::from(10));
+        assert_eq!(alert.transaction.kind, "mint");
+
+        // Remove the threshold.
+        let remove = || async {
+            let mut res = server
+                .client
+                .delete(&format!("alerts/threshold/{}", asset))
+                .send()
+                .await
+                .unwrap();
+            client::response_body::<Vec<AlertThreshold>>(&mut res).await
+        };
+        assert_eq!(remove().await.unwrap(), vec![]);
+        remove()
+            .await
+            .expect_err("removed a threshold which was not set");
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_scan_pause() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<SyncStatus>("syncstatus").await;
+        server.requires_wallet::<SyncStatus>("scan/pause").await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                .await
+                .unwrap()
+                .balances
+        };
+        let status = || async { server.get::<SyncStatus>("syncstatus").await.unwrap() };
+        assert!(!status().await.paused);
+        assert_eq!(status().await.resumes_in_ms, None);
+
+        // Pause scanning and mint a record. The ledger moves on, but the wallet does not.
+        let paused = server.get::<SyncStatus>("scan/pause").await.unwrap();
+        assert!(paused.paused);
+        assert!(paused.resumes_in_ms.unwrap() <= 600_000);
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/5/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .unwrap();
+        retry(|| async { status().await.real_time > paused.synced_to }).await;
+        async_std::task::sleep(Duration::from_millis(500)).await;
+        let stalled = status().await;
+        assert!(stalled.paused);
+        assert_eq!(stalled.synced_to, paused.synced_to);
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Once resumed, the wallet catches up from where it stopped.
+        assert!(
+            !server
+                .get::<SyncStatus>("scan/resume")
+                .await
+                .unwrap()
+                .paused
+        );
+        retry(|| async { status().await.synced_to >= stalled.real_time }).await;
+        retry(|| async { balance().await == Balances::One(5u64.into()) }).await;
+
+        // A pause expires on its own.
+        assert!(
+            server
+                .get::<SyncStatus>("scan/pause/1")
+                .await
+                .unwrap()
+                .paused
+        );
+        retry(|| async { !status().await.paused }).await;
+
+        // Pauses must be between 1 second and the safety timeout.
+        server
+            .get::<SyncStatus>("scan/pause/0")
+            .await
+            .expect_err("paused scanning for 0 seconds");
+        server
+            .get::<SyncStatus>("scan/pause/601")
+            .await
+            .expect_err("paused scanning beyond the safety timeout");
+        assert!(!status().await.paused);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_reconnect() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<Reconnection>("reconnect").await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", minter, asset))
+                .await
+                .unwrap()
+                .balances
+        };
+        let status = || async { server.get::<SyncStatus>("syncstatus").await.unwrap() };
+
+        // Lose the connection to the ledger, and let the ledger move on without the wallet.
+        let disconnected = server.get::<SyncStatus>("scan/pause").await.unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/5/fee/1/minter/{}/recipient/{}",
+                asset, minter, minter
+            ))
+            .await
+            .unwrap();
+        retry(|| async { status().await.real_time > disconnected.synced_to }).await;
+        async_std::task::sleep(Duration::from_millis(500)).await;
+        let stalled = status().await;
+        assert_eq!(stalled.synced_to, disconnected.synced_to);
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Reconnecting processes only the events missed since the cursor.
+        let reconnection = server.get::<Reconnection>("reconnect").await.unwrap();
+        assert_eq!(reconnection.catch_up, CatchUp::Incremental);
+        assert_eq!(reconnection.reason, None);
+        assert_eq!(reconnection.cursor, disconnected.synced_to);
+        assert!(reconnection.synced_to >= stalled.real_time);
+        assert_eq!(
+            reconnection.events,
+            reconnection.synced_to - disconnected.synced_to
+        );
+        assert_eq!(reconnection.blocks, 1);
+        assert!(!status().await.paused);
+        retry(|| async { balance().await == Balances::One(5u64.into()) }).await;
+
+        // With nothing missed, there is nothing to catch up.
+        let reconnection = server.get::<Reconnection>("reconnect").await.unwrap();
+        assert_eq!(reconnection.catch_up, CatchUp::Incremental);
+        assert_eq!(reconnection.events, 0);
+        assert_eq!(reconnection.blocks, 0);
+        assert_eq!(balance().await, Balances::One(5u64.into()));
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_clock() {
+        let server = TestServer::new().await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // A new ledger has no blocks, and the block rate cannot be estimated from one observation.
+        let first = server.get::<ClockStatus>("clock").await.unwrap();
+        assert_eq!(first.block_height, 0);
+        assert_eq!(first.blocks_per_second, None);
+
+        // Once the wallet has scanned the whole ledger, the block height is the number of blocks
+        // the ledger has committed.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        retry(|| async {
+            let status = server.get::<SyncStatus>("syncstatus").await.unwrap();
+            status.synced_to == status.real_time
+        })
+        .await;
+        let committed = mock_ledger(&server.options)
+            .lock()
+            .await
+            .network()
+            .block_height();
+        assert!(committed > 0);
+        let second = server.get::<ClockStatus>("clock").await.unwrap();
+        assert_eq!(second.block_height, committed);
+        assert!(second.now_ms >= first.now_ms);
+        assert!(second.blocks_per_second.unwrap() >= 0.);
+
+        // Each transfer is committed in a block of its own.
+        let sender: UserAddress = receipt.submitters[0].clone().into();
+        let recipient = UserAddress::from(
+            UserKeyPair::generate(&mut ChaChaRng::from_seed([47u8; 32])).address(),
+        );
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/10/fee/1",
+                sender,
+                AssetCode::native(),
+                recipient
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<ClockStatus>("clock")
+                .await
+                .unwrap()
+                .block_height
+                == committed + 1
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_nullifier_stats() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<NullifierStats>("nullifiers/stats")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Mint records of 5 and 3 units of a new asset to ourselves.
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let minter: UserAddress = receipt.submitters[0].clone().into();
+        let dst: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+        let asset = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let balance = |address: &UserAddress| {
+            let path = format!("getbalance/address/{}/asset/{}", address, asset);
+            let server = &server;
+            async move { server.get::<BalanceInfo>(&path).await.unwrap().balances }
+        };
+        let mut minted = 0u64;
+        for amount in [5u64, 3] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "mint/asset/{}/amount/{}/fee/1/minter/{}/recipient/{}",
+                    asset, amount, minter, minter
+                ))
+                .await
+                .unwrap();
+            minted += amount;
+            retry(|| async { balance(&minter).await == Balances::One(minted.into()) }).await;
+        }
+        let before = server
+            .get::<NullifierStats>("nullifiers/stats")
+            .await
+            .unwrap();
+
+        // Send each record in full. Each transfer spends the record and a native record for the
+        // fee.
+        let mut sent = 0u64;
+        for amount in [5u64, 3] {
+            server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/1",
+                    minter, asset, dst, amount
+                ))
+                .await
+                .unwrap();
+            sent += amount;
+            retry(|| async { balance(&dst).await == Balances::One(sent.into()) }).await;
+        }
+        let spends = 4;
+
+        let after = server
+            .get::<NullifierStats>("nullifiers/stats")
+            .await
+            .unwrap();
+        assert_eq!(after.usage.count, before.usage.count + spends);
+        assert!(after.usage.bytes > before.usage.bytes);
+        assert_eq!(after.spent, before.spent + spends);
+        // Spent nullifiers never belong to records the wallet still owns.
+        assert!(after.prunable >= after.spent);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_memstats() {
+        let budget = 1024;
+        let server = TestServer::with_options(|options| {
+            options.cache_budget = Some(budget);
+        })
+        .await;
+        let stats = server.get::<MemStats>("memstats").await.unwrap();
+        assert_eq!(stats.records, MemUsage::default());
+        assert_eq!(stats.transactions, MemUsage::default());
+        assert_eq!(stats.cache_budget, Some(budget));
+
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        let balance_url = format!(
+            "getbalance/address/{}/asset/{}",
+            faucet_addr,
+            AssetCode::native()
+        );
+        let balance = server.get::<BalanceInfo>(&balance_url).await.unwrap();
+
+        // Fill the cache past its budget with balances of unrelated addresses. Retry in case the
+        // wallet processes an event while we are filling the cache, which would drop the entries
+        // rather than evicting them.
+        let mut rng = ChaChaRng::from_seed([42; 32]);
+        let urls = (0..64)
+            .map(|_| {
+                format!(
+                    "getbalance/address/{}/asset/{}",
+                    UserAddress::from(UserKeyPair::generate(&mut rng).address()),
+                    AssetCode::native()
+                )
+            })
+            .collect::<Vec<_>>();
+        retry(|| async {
+            for url in &urls {
+                server.get::<BalanceInfo>(url).await.unwrap();
+            }
+            let stats = server.get::<CacheStats>("cache/stats").await.unwrap();
+            let cached = |url: &str| {
+                stats
+                    .entries
+                    .iter()
+                    .any(|entry| entry.url == format!("/{}", url))
+            };
+            stats.evictions > 0 && cached(&urls[urls.len() - 1]) && !cached(&urls[0])
+        })
+        .await;
+        let stats = server.get::<CacheStats>("cache/stats").await.unwrap();
+        assert!(stats.bytes <= budget, "{:?}", stats);
+        assert_eq!(stats.budget, Some(budget));
+
+        // Evicting cached responses does not affect the wallet.
+        assert_eq!(
+            server.get::<BalanceInfo>(&balance_url).await.unwrap(),
+            balance
+        );
+        let records = server.get::<Vec<RecordInfo>>("getrecords").await.unwrap();
+        let stats = server.get::<MemStats>("memstats").await.unwrap();
+        assert_eq!(stats.records.count, records.len());
+        assert!(stats.records.bytes > 0);
+        assert!(stats.transactions.count > 0);
+        assert!(stats.transactions.bytes > 0);
+        assert!(stats.response_cache.count > 0);
+        assert!(stats.response_cache.bytes <= budget);
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_sweep() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let key = UserKeyPair::generate(&mut rng);
+        let server = TestServer::new().await;
+        server
+            .requires_wallet_post::<SweepReport>(&format!("sweep/{}/fee/1", key))
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        let wrapped = server
+            .get::<Vec<RecordInfo>>("getrecords")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|record| record.ro.asset_def.code != AssetCode::native())
+            .unwrap();
+        let wrapped_asset = wrapped.ro.asset_def.code;
+        let wrapped_addr: UserAddress = wrapped.ro.pub_key.address().into();
+        let dst_addr: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+        let balance = |address: UserAddress, asset: AssetCode| {
+            let server = &server;
+            async move {
+                server
+                    .get::<BalanceInfo>(&format!("getbalance/address/{}/asset/{}", address, asset))
+                    .await
+                    .unwrap()
+                    .balances
+            }
+        };
+
+        // A key which owns nothing is swept gracefully.
+        let report = server
+            .post::<SweepReport>(&format!("sweep/{}/fee/1/to/{}", key, dst_addr))
+            .await
+            .unwrap();
+        assert_eq!(report.address, dst_addr);
+        assert!(report.swept.is_empty());
+        assert!(report.transactions.is_empty());
+
+        // Funds can only be swept into the open wallet.
+        server
+            .post::<SweepReport>(&format!(
+                "sweep/{}/fee/1/to/{}",
+                key,
+                UserAddress::from(key.address())
+            ))
+            .await
+            .expect_err("swept into an address which does not belong to the wallet");
+
+        // Fund the one-time key with the native asset and the wrapped asset. Wait for the change
+        // from each transfer to come back, so we know the transfers have been committed.
+        let faucet_balance = balance(faucet_addr.clone(), AssetCode::native()).await;
+        let wrapped_balance = balance(wrapped_addr.clone(), wrapped_asset).await;
+        let less = |balance: Balances, amount: u64| match balance {
+            Balances::One(balance) => Balances::One(balance - amount),
+            balance => panic!("expected Balances::One, got {:?}", balance),
+        };
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/100/fee/1",
+                faucet_addr,
+                AssetCode::native(),
+                UserAddress::from(key.address()),
+            ))
+            .await
+            .unwrap();
+        let expected = less(faucet_balance, 101);
+        retry(|| async { balance(faucet_addr.clone(), AssetCode::native()).await == expected })
+            .await;
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/10/fee/1",
+                wrapped_addr,
+                wrapped_asset,
+                UserAddress::from(key.address()),
+            ))
+            .await
+            .unwrap();
+        let expected = less(wrapped_balance, 10);
+        retry(|| async { balance(wrapped_addr.clone(), wrapped_asset).await == expected }).await;
+
+        // Sweep the key. The wrapped asset and the rest of the native asset each pay a fee.
+        let report = server
+            .post::<SweepReport>(&format!("sweep/{}/fee/1/to/{}", key, dst_addr))
+            .await
+            .unwrap();
+        assert_eq!(report.address, dst_addr);
+        assert_eq!(report.swept.len(), 2);
+        assert_eq!(report.swept[&AssetCode::native()], 98u64.into());
+        assert_eq!(report.swept[&wrapped_asset], 10u64.into());
+        assert_eq!(report.transactions.len(), 2);
+        retry(|| async {
+            balance(dst_addr.clone(), AssetCode::native()).await == Balances::One(98u64.into())
+                && balance(dst_addr.clone(), wrapped_asset).await == Balances::One(10u64.into())
+        })
+        .await;
+
+        // The key was not added to the wallet, and it has nothing left to sweep.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        assert!(!info.sending_keys.contains(&key.pub_key()));
+        assert!(!info.addresses.contains(&key.address().into()));
+        let report = server
+            .post::<SweepReport>(&format!("sweep/{}/fee/1", key))
+            .await
+            .unwrap();
+        assert!(report.swept.is_empty());
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_confirmations() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<usize>("confirmations").await;
+        server
+            .requires_wallet_post::<usize>("confirmations/3")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        let dst_addr: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+        let status = |hash: String| {
+            let server = &server;
+            async move {
+                server
+                    .get::<(Vec<TransactionHistoryEntry>, HashMap<AssetCode, AssetInfo>)>(
+                        "transactionhistory",
+                    )
+                    .await
+                    .unwrap()
+                    .0
+                    .into_iter()
+                    .find(|entry| entry.hash.as_ref() == Some(&hash))
+                    .unwrap()
+                    .status
+            }
+        };
+        let send = || async {
+            let receipt = server
+                .post::<TransactionReceipt<CapeLedger>>(&format!(
+                    "send/sender/{}/asset/{}/recipient/{}/amount/100/fee/1",
+                    faucet_addr,
+                    AssetCode::native(),
+                    dst_addr,
+                ))
+                .await
+                .unwrap();
+            transaction_hash_string(&receipt.uid.0).unwrap()
+        };
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_addr,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+        };
+
+        // The depth defaults to 1, and can only be changed to a positive depth with a POST.
+        assert_eq!(server.get::<usize>("confirmations").await.unwrap(), 1);
+        server
+            .get::<usize>("confirmations/3")
+            .await
+            .expect_err("confirmation depth changed with a GET request");
+        server
+            .post::<usize>("confirmations/0")
+            .await
+            .expect_err("confirmation depth set to 0");
+        assert_eq!(server.post::<usize>("confirmations/3").await.unwrap(), 3);
+        assert_eq!(server.get::<usize>("confirmations").await.unwrap(), 3);
+
+        // Transactions which were included before the depth was set are still final.
+        let (history, _) = server
+            .get::<(Vec<TransactionHistoryEntry>, HashMap<AssetCode, AssetInfo>)>(
+                "transactionhistory",
+            )
+            .await
+            .unwrap();
+        assert!(!history.is_empty());
+        for entry in &history {
+            assert_eq!(entry.status, "accepted");
+        }
+
+        // A transaction which has just been included has 1 confirmation, so it is still pending.
+        let first = send().await;
+        retry(|| async { balance().await == Balances::One(100u64.into()) }).await;
+        assert_eq!(status(first.clone()).await, "pending");
+
+        // Once the ledger advances past it, it has 3 confirmations and becomes final.
+        send().await;
+        retry(|| async { balance().await == Balances::One(200u64.into()) }).await;
+        retry(|| async { status(first.clone()).await == "accepted" }).await;
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_assets_by_issuer() {
+        let server = TestServer::new().await;
+        server
+            .requires_wallet::<Vec<IssuerAssets>>("assets/byissuer")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+
+        // Build an asset wrapping `erc20_code`, and import it into the wallet. If `submit`, also
+        // sponsor it.
+        let sponsor = |erc20_code: Address, sponsor_addr: Address, submit: bool| {
+            let server = &server;
+            async move {
+                let (asset, info) = server
+                    .post::<(sol::AssetDefinition, String)>(&format!(
+                        "buildsponsor/erc20/{:#x}/sponsor/{:#x}",
+                        erc20_code, sponsor_addr
+                    ))
+                    .await
+                    .unwrap();
+                server
+                    .client
+                    .post("importasset")
+                    .body_json(&info)
+                    .unwrap()
+                    .send()
+                    .await
+                    .unwrap();
+                if submit {
+                    server
+                        .client
+                        .post(&format!(
+                            "submitsponsor/erc20/{:#x}/sponsor/{:#x}",
+                            erc20_code, sponsor_addr
+                        ))
+                        .body_json(&asset)
+                        .unwrap()
+                        .send()
+                        .await
+                        .unwrap();
+                }
+                JfAssetDefinition::from(asset).code
+            }
+        };
+        let sponsor1 = Address::from([1u8; 20]);
+        let sponsor2 = Address::from([2u8; 20]);
+        let mut sponsored1 = vec![
+            sponsor(Address::from([11u8; 20]), sponsor1, true).await,
+            sponsor(Address::from([12u8; 20]), sponsor1, true).await,
+        ];
+        sponsored1.sort_by_key(|asset| asset.to_string());
+        let sponsored2 = sponsor(Address::from([13u8; 20]), sponsor2, true).await;
+        // An asset which was never sponsored is not a wrapped asset, and we did not define it.
+        let unsponsored = sponsor(Address::from([14u8; 20]), sponsor2, false).await;
+        let defined = server
+            .post::<AssetInfo>("newasset")
+            .await
+            .unwrap()
+            .definition
+            .code;
+
+        let groups = server
+            .get::<Vec<IssuerAssets>>("assets/byissuer")
+            .await
+            .unwrap();
+        let by_issuer = groups
+            .iter()
+            .map(|group| (group.issuer.as_str(), group.assets.clone()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(by_issuer.len(), groups.len());
+        assert_eq!(by_issuer[format!("{:#x}", sponsor1).as_str()], sponsored1);
+        assert_eq!(
+            by_issuer[format!("{:#x}", sponsor2).as_str()],
+            vec![sponsored2]
+        );
+        assert_eq!(by_issuer[ISSUER_WALLET], vec![defined]);
+        assert_eq!(by_issuer[ISSUER_UNKNOWN], vec![unsponsored]);
+        assert_eq!(groups.last().unwrap().issuer, ISSUER_UNKNOWN);
+
+        // Every asset in the wallet is in exactly one group.
+        let info = server.get::<WalletSummary>("getinfo").await.unwrap();
+        let grouped = groups
+            .iter()
+            .flat_map(|group| group.assets.iter().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(grouped.len(), info.assets.len());
+        for asset in &info.assets {
+            assert!(grouped.contains(&asset.definition.code));
+        }
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_scangaps() {
+        let server = TestServer::new().await;
+        server.requires_wallet::<ScanGaps>("scangaps").await;
+        server
+            .requires_wallet_post::<ScanGaps>("scangaps/autoretry/true")
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let src_address: UserAddress = receipt.submitters[0].clone().into();
+        let dst_address: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap()
+        {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("Expected PubKey::Sending, found {:?}", key),
+        };
+        let balance = || async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    dst_address,
+                    AssetCode::native()
+                ))
+                .await
+                .unwrap()
+                .balances
+        };
+        let gaps = || async { server.get::<ScanGaps>("scangaps").await.unwrap() };
+        assert_eq!(gaps().await, ScanGaps::default());
+
+        // Make the wallet fail to process the memos of a transfer to `dst_address`, leaving a gap.
+        server.post::<()>("skipmemosfortest").await.unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/{}/fee/{}",
+                src_address,
+                &AssetCode::native(),
+                dst_address,
+                100,
+                1
+            ))
+            .await
+            .unwrap();
+        retry(|| async { !gaps().await.gaps.is_empty() }).await;
+        let skipped = server
+            .get::<Vec<SkippedEvent>>("skippedevents")
+            .await
+            .unwrap();
+        assert_eq!(skipped.len(), 1);
+        let info = gaps().await;
+        assert_eq!(
+            info.gaps,
+            vec![ScanGap {
+                from: skipped[0].index,
+                to: skipped[0].index + 1,
+                records: skipped[0].records.len(),
+            }]
+        );
+        assert!(!info.auto_retry);
+        assert_eq!(balance().await, Balances::One(0u64.into()));
+
+        // Changing the policy requires a POST.
+        server
+            .get::<ScanGaps>("scangaps/autoretry/true")
+            .await
+            .expect_err("auto-retry enabled with a GET request");
+        assert!(!gaps().await.auto_retry);
+
+        // With auto-retry enabled, the gap is closed and the missed records appear.
+        let info = server
+            .post::<ScanGaps>("scangaps/autoretry/true")
+            .await
+            .unwrap();
+        assert!(info.auto_retry);
+        retry(|| async { gaps().await.gaps.is_empty() }).await;
+        retry(|| async { balance().await == Balances::One(100u64.into()) }).await;
+        let info = gaps().await;
+        assert!(info.auto_retry);
+        assert_eq!(info.retries, 1);
+        assert_eq!(info.closed, 1);
+        assert_eq!(info.last_error, None);
+
+        // Disabling auto-retry resets the policy.
+        assert_eq!(
+            server
+                .post::<ScanGaps>("scangaps/autoretry/false")
+                .await
+                .unwrap(),
+            ScanGaps::default()
+        );
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn test_receipt() {
+        let server = TestServer::new().await;
+        let unknown = TaggedBase64::new("HASH", &[0; 32]).unwrap();
+        server
+            .requires_wallet::<Receipt>(&format!("receipt/{}", unknown))
+            .await;
+        server
+            .post::<()>(&format!(
+                "newwallet/{}/{}/path/{}",
+                server.get::<String>("getmnemonic").await.unwrap(),
+                base64("my-password".as_bytes()),
+                server.path()
+            ))
+            .await
+            .unwrap();
+        let receipt = server
+            .get::<TransactionReceipt<CapeLedger>>("populatefortest")
+            .await
+            .unwrap();
+        let faucet_addr: UserAddress = receipt.submitters[0].clone().into();
+        let dst_addr: UserAddress = match server.post::<PubKey>("newkey/sending").await.unwrap() {
+            PubKey::Sending(key) => key.address().into(),
+            key => panic!("expected PubKey::Sending, got {:?}", key),
+        };
+
+        // Define an asset displayed with 2 decimal places, and mint some for the faucet address.
+        let asset = server
+            .post::<AssetInfo>(&format!(
+                "newasset/symbol/{}/description/{}",
+                base64::encode_config("TOKEN".as_bytes(), base64::URL_SAFE_NO_PAD),
+                base64::encode_config(&[3u8; 32], base64::URL_SAFE_NO_PAD),
+            ))
+            .await
+            .unwrap()
+            .definition
+            .code;
+        let mut res = server
+            .client
+            .post(format!("assetmetadata/{}", asset))
+            .body_json(&AssetMetadata {
+                decimals: Some(2),
+                ..Default::default()
+            })
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        client::response_body::<AssetInfo>(&mut res).await.unwrap();
+        server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "mint/asset/{}/amount/1000/fee/1/minter/{}/recipient/{}",
+                asset, faucet_addr, faucet_addr
+            ))
+            .await
+            .unwrap();
+        retry(|| async {
+            server
+                .get::<BalanceInfo>(&format!(
+                    "getbalance/address/{}/asset/{}",
+                    faucet_addr, asset
+                ))
+                .await
+                .unwrap()
+                .balances
+                == Balances::One(1000u64.into())
+        })
+        .await;
+
+        // Transfer some of the asset and get a receipt for the transfer once it is accepted.
+        let receipt = server
+            .post::<TransactionReceipt<CapeLedger>>(&format!(
+                "send/sender/{}/asset/{}/recipient/{}/amount/250/fee/2",
+                faucet_addr, asset, dst_addr,
+            ))
+            .await
+            .unwrap();
+        let hash = transaction_hash_string(&receipt.uid.0).unwrap();
+        retry(|| async {
+            server
+                .get::<Receipt>(&format!("receipt/{}", hash))
+                .await
+                .unwrap()
+                .status
+                == "accepted"
+        })
+        .await;
+        let receipt = server
+            .get::<Receipt>(&format!("receipt/{}", hash))
+            .await
+            .unwrap();
+        assert_eq!(receipt.hash, hash);
+        assert_eq!(receipt.kind, "send");
+        assert_eq!(receipt.from, vec![faucet_addr]);
+        assert_eq!(receipt.to, vec![dst_addr]);
+        assert_eq!(receipt.asset, asset);
+        assert_eq!(receipt.symbol, Some(String::from("TOKEN")));
+        assert_eq!(receipt.amount, "250");
+        assert_eq!(receipt.display_amount, "2.5");
+        assert_eq!(receipt.fee, Some(String::from("2")));
+        assert!(receipt.confirmations.unwrap() >= 1);
+        assert_eq!(receipt.required_confirmations, 1);
+
+        // Transactions which are not in the wallet's history have no receipt.
+        server
+            .get::<Receipt>(&format!("receipt/{}", unknown))
+            .await
+            .expect_err("got a receipt for an unknown transaction");
+    }
 }
@@ -0,0 +1,121 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-wallet minimum anonymity sets.
+//!
+//! Spending a record reveals nothing about which record it is, but an observer who knows when a
+//! record was created can guess that a transaction spends one of the records created shortly
+//! before it. The anonymity set of a record is the number of records committed to the ledger since
+//! it, including itself, which grows as the ledger does. A wallet can require records to have an
+//! anonymity set of at least some size before spending them. The check is made against the inputs
+//! of the transaction the wallet actually builds, before it is submitted or exported. Thresholds are
+//! stored in the `min_anonymity` file in the storage directory, keyed by wallet path.
+//!
+//! Building a transaction puts its inputs on hold, so a transaction which is refused after it is
+//! built leaves its inputs unspendable until the hold expires. To make that rare, the inputs are
+//! also predicted before the transaction is built, and a transfer whose predicted inputs are too
+//! young is refused without building it.
+
+use crate::persisted::Persisted;
+use crate::routes::Wallet;
+use cap_rust_sandbox::model::CapeModelTxn;
+use cape_wallet::ui::{ChangePreview, ProofParams};
+use ethers::prelude::U256;
+use jf_cap::{keys::UserAddress, structs::AssetCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+use std::path::{Path, PathBuf};
+
+/// The anonymity set of the record with `uid`, in a ledger of `ledger_size` records.
+pub fn anonymity_set(uid: u64, ledger_size: u64) -> u64 {
+    ledger_size.saturating_sub(uid)
+}
+
+/// The first input of `txn` whose anonymity set is smaller than `threshold`, as its UID and the size
+/// of its anonymity set.
+///
+/// The inputs are found among the records of `wallet` by nullifier, the way `exporttransaction`
+/// lists them, so they are the records the transaction builder selected.
+pub async fn too_small_input(
+    wallet: &Wallet,
+    txn: &CapeModelTxn,
+    threshold: u64,
+) -> Option<(u64, u64)> {
+    if threshold == 0 {
+        return None;
+    }
+    let (frontier, _, _) = wallet.record_merkle_frontier().await;
+    let nullifiers = txn.nullifiers().into_iter().collect::<HashSet<_>>();
+    wallet
+        .records()
+        .await
+        .filter(|record| nullifiers.contains(&record.nullifier))
+        .map(|record| (record.uid, anonymity_set(record.uid, frontier.num_leaves)))
+        .find(|(_, size)| *size < threshold)
+}
+
+/// The first record a transfer is predicted to spend whose anonymity set is smaller than
+/// `threshold`, as its UID and the size of its anonymity set.
+///
+/// The transfer sends `amount` of `asset` from `sender`, or from any address of `wallet` if there is
+/// no sender, and pays `fee`. Its inputs are predicted the way `changepreview` predicts them, which
+/// may not be the records the transaction builder selects, so the built transaction must still be
+/// checked with [too_small_input].
+pub async fn too_small_predicted_input(
+    wallet: &Wallet,
+    sender: Option<&UserAddress>,
+    asset: AssetCode,
+    amount: u128,
+    fee: u128,
+    threshold: u64,
+) -> Option<(u64, u64)> {
+    if threshold == 0 {
+        return None;
+    }
+    let (frontier, _, _) = wallet.record_merkle_frontier().await;
+    let records = wallet.records().await.filter(|record| match sender {
+        Some(sender) => record.ro.pub_key.address() == *sender,
+        None => true,
+    });
+    // If no inputs can be selected, building the transfer will fail with a more specific error.
+    let preview = ChangePreview::select(
+        records,
+        asset,
+        U256::from(amount),
+        U256::from(fee),
+        ProofParams::cape().max_transfer_inputs,
+    )
+    .ok()?;
+    once(preview.fee_input)
+        .chain(preview.inputs)
+        .map(|uid| (uid, anonymity_set(uid, frontier.num_leaves)))
+        .find(|(_, size)| *size < threshold)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MinAnonymity {
+    thresholds: HashMap<PathBuf, u64>,
+}
+
+impl Persisted for MinAnonymity {}
+
+impl MinAnonymity {
+    /// The minimum anonymity set of the wallet at `wallet_path`, or 0 if it has none.
+    pub fn get(&self, wallet_path: &Path) -> u64 {
+        self.thresholds.get(wallet_path).copied().unwrap_or(0)
+    }
+
+    /// Set the minimum anonymity set of the wallet at `wallet_path`. A threshold of 0 removes it.
+    pub fn set(&mut self, wallet_path: &Path, threshold: u64) {
+        if threshold == 0 {
+            self.thresholds.remove(wallet_path);
+        } else {
+            self.thresholds.insert(wallet_path.to_path_buf(), threshold);
+        }
+    }
+}
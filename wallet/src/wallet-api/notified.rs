@@ -0,0 +1,45 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Records an external indexer notified the wallet about, ahead of their memos.
+//!
+//! The wallet only learns about a record it receives when the record's owner memo is posted to the
+//! ledger. An indexer which sees the memo first, or which has the memo when it is never posted, can
+//! notify the wallet of the record, and once the notification has been validated the record counts
+//! towards the wallet's balance until the wallet receives the memo itself. Notified records are
+//! stored in the `notified` file in the storage directory, keyed by wallet path and then by uid.
+
+use crate::persisted::Persisted;
+use jf_cap::structs::RecordOpening;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NotifiedRecords {
+    records: HashMap<PathBuf, BTreeMap<u64, RecordOpening>>,
+}
+
+impl Persisted for NotifiedRecords {}
+
+impl NotifiedRecords {
+    /// The records notified to the wallet at `wallet_path`, with their uids, in order of uid.
+    pub fn get(&self, wallet_path: &Path) -> impl Iterator<Item = (u64, &RecordOpening)> {
+        self.records
+            .get(wallet_path)
+            .into_iter()
+            .flat_map(|records| records.iter().map(|(uid, ro)| (*uid, ro)))
+    }
+
+    /// Record that the wallet at `wallet_path` was notified of the record `ro` with uid `uid`.
+    pub fn insert(&mut self, wallet_path: &Path, uid: u64, ro: RecordOpening) {
+        self.records
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .insert(uid, ro);
+    }
+}
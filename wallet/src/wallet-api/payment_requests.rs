@@ -0,0 +1,113 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Payment requests which expire, for invoicing.
+//!
+//! CAP transfers cannot carry arbitrary data, so a payment cannot quote the nonce of the request it
+//! pays. Instead, each request gets a new sending key of its own, described by the request's nonce,
+//! and any record received at that key's address is a payment of the request. Requests are stored
+//! in the `payment_requests` file in the storage directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::{PaymentRequest, PaymentRequestStatus};
+use ethers::prelude::U256;
+use jf_cap::structs::AssetCode;
+use net::UserAddress;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaymentRequests {
+    next_id: u64,
+    requests: HashMap<PathBuf, Vec<PaymentRequest>>,
+}
+
+impl Persisted for PaymentRequests {}
+
+impl PaymentRequests {
+    /// Create a pending request for the wallet at `wallet_path` to be paid `amount` of `asset` at
+    /// `address`, which expires `expiry_secs` from now.
+    pub fn add(
+        &mut self,
+        wallet_path: &Path,
+        nonce: String,
+        address: UserAddress,
+        asset: AssetCode,
+        amount: u128,
+        expiry_secs: u64,
+    ) -> PaymentRequest {
+        let request = PaymentRequest {
+            id: self.next_id,
+            uri: format!(
+                "cape:{}?asset={}&amount={}&nonce={}",
+                address, asset, amount, nonce
+            ),
+            nonce,
+            address,
+            asset,
+            amount: amount.into(),
+            expires_ms: now() + expiry_secs * 1000,
+            received: U256::zero(),
+            status: PaymentRequestStatus::Pending,
+            paid_ms: None,
+        };
+        self.next_id += 1;
+        self.requests
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .push(request.clone());
+        request
+    }
+
+    /// Request `id` of the wallet at `wallet_path`, if there is one.
+    pub fn get(&self, wallet_path: &Path, id: u64) -> Option<&PaymentRequest> {
+        self.requests
+            .get(wallet_path)?
+            .iter()
+            .find(|request| request.id == id)
+    }
+
+    /// Update request `id` of the wallet at `wallet_path`, given that `received` has been received
+    /// at its address so far.
+    ///
+    /// A pending request becomes paid if the amount received covers it before it expires, and
+    /// expired if it is updated after it expires without having been paid. Once a request is paid
+    /// or expired it does not change again. Returns the updated request, or `None` if the wallet has
+    /// no request `id`.
+    pub fn update(
+        &mut self,
+        wallet_path: &Path,
+        id: u64,
+        received: U256,
+    ) -> Option<PaymentRequest> {
+        let request = self
+            .requests
+            .get_mut(wallet_path)?
+            .iter_mut()
+            .find(|request| request.id == id)?;
+        if request.status == PaymentRequestStatus::Pending {
+            let now = now();
+            request.received = received;
+            if now > request.expires_ms {
+                request.status = PaymentRequestStatus::Expired;
+            } else if received >= request.amount {
+                request.status = PaymentRequestStatus::Paid;
+                request.paid_ms = Some(now);
+            }
+        }
+        Some(request.clone())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
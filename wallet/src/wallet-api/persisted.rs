@@ -0,0 +1,69 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Server state stored as JSON files in the storage directory.
+//!
+//! Most of this state, such as policies and annotations, is keyed by wallet path, so that it follows
+//! whichever wallet is open. None of it is essential to a wallet, so a file which cannot be parsed
+//! is set aside, with a warning, rather than preventing the server from starting.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+
+pub trait Persisted: Default + Serialize + DeserializeOwned {
+    /// Load the state stored at `path`, or the default state if there is none.
+    ///
+    /// If the file at `path` is corrupt, it is renamed with a `.corrupt` extension, so that it can
+    /// be inspected and is not overwritten by the next save, and the default state is returned.
+    fn load(path: &Path) -> io::Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        match serde_json::from_slice(&bytes) {
+            Ok(state) => Ok(state),
+            Err(err) => {
+                let corrupt = with_extension(path, "corrupt");
+                event!(
+                    Level::WARN,
+                    "{} is corrupt ({}), moving it to {} and starting from scratch",
+                    path.display(),
+                    err,
+                    corrupt.display()
+                );
+                fs::rename(path, corrupt)?;
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Store this state at `path`.
+    ///
+    /// The state is written with [write_atomic], so a crash during a save leaves either the old or
+    /// the new state, never a truncated file.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, &serde_json::to_vec(self)?)
+    }
+}
+
+/// Replace the contents of `path` with `bytes`, atomically.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = with_extension(path, "tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(tmp, path)
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The thread pool which generates proofs for transactions built by requests.
+//!
+//! Proofs are generated in parallel on a rayon thread pool: the pool of the thread which starts the
+//! work, or rayon's global pool if that thread belongs to none. The global pool cannot be resized
+//! once it is running, so requests build their transactions with [build], on a pool of our own,
+//! which is replaced when the number of threads is changed. A build which is already running keeps
+//! the pool it started with.
+
+use crate::routes::{server_error, CapeAPIError, Wallet};
+use async_std::{
+    sync::Mutex,
+    task::{block_on, spawn_blocking},
+};
+use cape_wallet::ui::ProofConfig;
+use futures::future::BoxFuture;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::sync::Arc;
+use std::thread::available_parallelism;
+
+pub struct ProofThreads {
+    pool: Arc<ThreadPool>,
+    /// The number of threads the most recent [build] generated its proofs with.
+    last_build_threads: Option<usize>,
+}
+
+impl ProofThreads {
+    /// A pool with `threads` threads, or rayon's default number of threads if `threads` is `None`.
+    pub fn new(threads: Option<usize>) -> Result<Self, ThreadPoolBuildError> {
+        Ok(Self {
+            pool: Arc::new(build_pool(threads.unwrap_or(0))?),
+            last_build_threads: None,
+        })
+    }
+
+    /// The pool to generate proofs on.
+    pub fn pool(&self) -> Arc<ThreadPool> {
+        self.pool.clone()
+    }
+
+    /// Use a pool with `threads` threads for requests made from now on.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), ThreadPoolBuildError> {
+        self.pool = Arc::new(build_pool(threads)?);
+        Ok(())
+    }
+
+    pub fn config(&self) -> ProofConfig {
+        ProofConfig {
+            threads: self.pool.current_num_threads(),
+            available_threads: available_parallelism().map_or(1, |n| n.get()),
+            last_build_threads: self.last_build_threads,
+        }
+    }
+}
+
+/// Build a transaction with the open wallet, generating its proofs on the configured pool.
+///
+/// Only `build` runs on the pool, on a blocking thread, so the executor is free to serve other
+/// requests while the proofs are generated. The wallet is moved to that thread for the build and put
+/// back afterwards; if `build` panics, the wallet is closed.
+pub async fn build<T, F>(
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+    build: F,
+) -> Result<T, tide::Error>
+where
+    T: Send + 'static,
+    F: for<'a> FnOnce(&'a mut Wallet) -> BoxFuture<'a, Result<T, tide::Error>> + Send + 'static,
+{
+    let mut open = wallet
+        .take()
+        .ok_or_else(|| server_error(CapeAPIError::MissingWallet))?;
+    let pool = proof_threads.lock().await.pool();
+    let (open, threads, res) = spawn_blocking(move || {
+        pool.install(move || {
+            let res = block_on(build(&mut open));
+            (open, rayon::current_num_threads(), res)
+        })
+    })
+    .await;
+    *wallet = Some(open);
+    proof_threads.lock().await.last_build_threads = Some(threads);
+    res
+}
+
+fn build_pool(threads: usize) -> Result<ThreadPool, ThreadPoolBuildError> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("proof-{}", i))
+        .build()
+}
@@ -0,0 +1,44 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Transactions pruned from the history the server reports.
+//!
+//! The wallet's own transaction history is append-only, so pruning does not shrink the wallet's
+//! storage. Instead, the server remembers the hashes of pruned transactions and leaves them out of
+//! `transactionhistory` and `transactions/count`. Pruning never touches records, so balances are
+//! unaffected. Pruned hashes are stored in the `pruned` file in the storage directory, keyed by
+//! wallet path.
+
+use crate::persisted::Persisted;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PrunedTransactions {
+    pruned: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl Persisted for PrunedTransactions {}
+
+impl PrunedTransactions {
+    /// Whether the transaction with hash `hash` was pruned from the wallet at `wallet_path`.
+    pub fn contains(&self, wallet_path: &Path, hash: &str) -> bool {
+        self.pruned
+            .get(wallet_path)
+            .map(|pruned| pruned.contains(hash))
+            .unwrap_or(false)
+    }
+
+    /// Prune the transaction with hash `hash` from the wallet at `wallet_path`.
+    pub fn insert(&mut self, wallet_path: &Path, hash: String) {
+        self.pruned
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .insert(hash);
+    }
+}
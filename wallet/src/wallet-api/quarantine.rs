@@ -0,0 +1,118 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Quarantine for dust and records of flagged assets.
+//!
+//! A record is quarantined if its amount is below the dust threshold of its asset, or if its asset
+//! is flagged, until it is released or rejected. Quarantined records, including rejected ones, are
+//! left out of the balances reported by `getbalance`. The policy and the fate of each record are
+//! stored in the `quarantine` file in the storage directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use cap_rust_sandbox::types::GenericInto;
+use jf_cap::structs::AssetCode;
+use seahorse::txn_builder::RecordInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Why a record is quarantined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuarantineReason {
+    /// The record's amount is below its asset's dust threshold.
+    Dust,
+    /// The record's asset is flagged.
+    Flagged,
+}
+
+impl QuarantineReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dust => "dust",
+            Self::Flagged => "flagged",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WalletQuarantine {
+    /// Records of each asset with an amount below its threshold are quarantined.
+    pub thresholds: HashMap<AssetCode, u128>,
+    /// All records of these assets are quarantined.
+    pub flagged: HashSet<AssetCode>,
+    released: HashSet<u64>,
+    rejected: HashSet<u64>,
+}
+
+impl WalletQuarantine {
+    /// Why `record` is quarantined, if it is, and whether it has been rejected.
+    ///
+    /// Released records are never quarantined, even if the policy changes.
+    pub fn check(&self, record: &RecordInfo) -> Option<(QuarantineReason, bool)> {
+        if self.released.contains(&record.uid) {
+            return None;
+        }
+        let asset = record.ro.asset_def.code;
+        let reason = if self.flagged.contains(&asset) {
+            QuarantineReason::Flagged
+        } else if matches!(
+            self.thresholds.get(&asset),
+            Some(threshold) if record.ro.amount.generic_into::<u128>() < *threshold
+        ) {
+            QuarantineReason::Dust
+        } else {
+            return None;
+        };
+        Some((reason, self.rejected.contains(&record.uid)))
+    }
+
+    /// Set the dust threshold for `asset`. A threshold of 0 removes it.
+    pub fn set_threshold(&mut self, asset: AssetCode, threshold: u128) {
+        if threshold == 0 {
+            self.thresholds.remove(&asset);
+        } else {
+            self.thresholds.insert(asset, threshold);
+        }
+    }
+
+    pub fn flag(&mut self, asset: AssetCode, flagged: bool) {
+        if flagged {
+            self.flagged.insert(asset);
+        } else {
+            self.flagged.remove(&asset);
+        }
+    }
+
+    /// Accept the record `uid`, so that it is no longer quarantined.
+    pub fn release(&mut self, uid: u64) {
+        self.rejected.remove(&uid);
+        self.released.insert(uid);
+    }
+
+    /// Hide the record `uid`. It stays out of balances, but is no longer listed.
+    pub fn reject(&mut self, uid: u64) {
+        self.rejected.insert(uid);
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Quarantine {
+    wallets: HashMap<PathBuf, WalletQuarantine>,
+}
+
+impl Persisted for Quarantine {}
+
+impl Quarantine {
+    /// The quarantine of the wallet at `wallet_path`, if it has one.
+    pub fn get(&self, wallet_path: &Path) -> Option<&WalletQuarantine> {
+        self.wallets.get(wallet_path)
+    }
+
+    pub fn get_mut(&mut self, wallet_path: &Path) -> &mut WalletQuarantine {
+        self.wallets.entry(wallet_path.to_path_buf()).or_default()
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! HTTP range requests for static files.
+//!
+//! Tide's `serve_dir` always responds with the whole file. The [ranges] middleware adds support for
+//! single byte ranges on top of it, so that clients can resume interrupted downloads of large assets.
+//! As allowed by RFC 7233, requests for multiple ranges, or with a `Range` header we can't parse,
+//! get the whole file.
+
+use async_std::io::{self, ReadExt};
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
+use tide::{Body, Next, Request, Response, StatusCode};
+
+/// The part of a file to send in response to a `Range` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Send the whole file, with status 200.
+    Full,
+    /// Send just these bytes, with status 206.
+    Partial(Range<u64>),
+    /// None of the requested bytes exist, respond with status 416.
+    Unsatisfiable,
+}
+
+/// Interpret the value of a `Range` header for a file of `len` bytes.
+pub fn parse_range(header: &str, len: u64) -> ByteRange {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec,
+        _ => return ByteRange::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => return ByteRange::Full,
+    };
+
+    let range = if start.is_empty() {
+        // A suffix range, `bytes=-n`, requests the last `n` bytes.
+        match end.parse::<u64>() {
+            Ok(n) => len.saturating_sub(n)..len,
+            Err(_) => return ByteRange::Full,
+        }
+    } else {
+        let start = match start.parse::<u64>() {
+            Ok(start) => start,
+            Err(_) => return ByteRange::Full,
+        };
+        // The end of a range is inclusive, and may extend past the end of the file.
+        let end = if end.is_empty() {
+            len
+        } else {
+            match end.parse::<u64>() {
+                Ok(end) if end >= start => end.saturating_add(1).min(len),
+                _ => return ByteRange::Full,
+            }
+        };
+        start..end
+    };
+
+    if range.start >= range.end {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range)
+    }
+}
+
+/// Middleware which answers `Range` requests using the full responses of the wrapped endpoint.
+///
+/// Successful responses with a known length advertise `Accept-Ranges: bytes`. If the request has a
+/// satisfiable `Range` header, the body is trimmed to the requested bytes without buffering the
+/// rest of the file.
+pub fn ranges<'a, State: Clone + Send + Sync + 'static>(
+    req: Request<State>,
+    next: Next<'a, State>,
+) -> Pin<Box<dyn Future<Output = tide::Result> + Send + 'a>> {
+    Box::pin(async move {
+        let header = req
+            .header("Range")
+            .map(|values| values.last().as_str().to_string());
+        let mut res = next.run(req).await;
+        let len = match res.len() {
+            Some(len) if res.status() == StatusCode::Ok => len as u64,
+            _ => return Ok(res),
+        };
+        res.insert_header("Accept-Ranges", "bytes");
+
+        match header.map(|header| parse_range(&header, len)) {
+            None | Some(ByteRange::Full) => Ok(res),
+            Some(ByteRange::Unsatisfiable) => {
+                Ok(Response::builder(StatusCode::RequestedRangeNotSatisfiable)
+                    .header("Accept-Ranges", "bytes")
+                    .header("Content-Range", format!("bytes */{}", len))
+                    .build())
+            }
+            Some(ByteRange::Partial(range)) => {
+                let mut body = res.take_body();
+                let mime = body.mime().clone();
+                // Discard the bytes before the range as they are read, rather than loading the
+                // whole file into memory.
+                io::copy(&mut (&mut body).take(range.start), &mut io::sink()).await?;
+                let count = range.end - range.start;
+                let mut partial = Body::from_reader(body.take(count), Some(count as usize));
+                partial.set_mime(mime);
+
+                res.set_status(StatusCode::PartialContent);
+                res.insert_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, len),
+                );
+                res.set_body(partial);
+                Ok(res)
+            }
+        }
+    })
+}
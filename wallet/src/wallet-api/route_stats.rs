@@ -0,0 +1,74 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Call statistics for each route, as reported by `routes/stats`.
+//!
+//! Statistics are kept in memory from the time the server starts, keyed by the `api.toml` pattern
+//! each request matched rather than by its URL, so the number of entries is bounded by the size of
+//! the API. Latency percentiles are computed over the most recent calls to each route.
+
+use cape_wallet::ui::RouteStats;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// How many of the most recent latencies of each route are kept for computing percentiles.
+const LATENCY_SAMPLES: usize = 1000;
+
+#[derive(Clone, Debug, Default)]
+struct PatternStats {
+    calls: u64,
+    errors: u64,
+    latencies: VecDeque<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RouteStatistics {
+    patterns: HashMap<String, PatternStats>,
+}
+
+impl RouteStatistics {
+    /// Record a call to the route matching `pattern` which took `latency`, and failed if `error`.
+    pub fn record(&mut self, pattern: &str, latency: Duration, error: bool) {
+        let stats = self.patterns.entry(pattern.to_string()).or_default();
+        stats.calls += 1;
+        if error {
+            stats.errors += 1;
+        }
+        if stats.latencies.len() == LATENCY_SAMPLES {
+            stats.latencies.pop_front();
+        }
+        stats.latencies.push_back(latency);
+    }
+
+    /// The statistics of each route which has been called, in order of pattern.
+    pub fn summary(&self) -> Vec<RouteStats> {
+        let mut summary = self
+            .patterns
+            .iter()
+            .map(|(pattern, stats)| {
+                let mut latencies = stats.latencies.iter().copied().collect::<Vec<_>>();
+                latencies.sort_unstable();
+                RouteStats {
+                    pattern: pattern.clone(),
+                    calls: stats.calls,
+                    errors: stats.errors,
+                    p50_us: percentile(&latencies, 50),
+                    p90_us: percentile(&latencies, 90),
+                    p99_us: percentile(&latencies, 99),
+                }
+            })
+            .collect::<Vec<_>>();
+        summary.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+        summary
+    }
+}
+
+/// The `p`th percentile of the non-empty, sorted `latencies`, in microseconds, by nearest rank.
+fn percentile(latencies: &[Duration], p: usize) -> u64 {
+    let rank = (latencies.len() * p + 99) / 100;
+    latencies[rank.max(1) - 1].as_micros() as u64
+}
@@ -8,47 +8,101 @@
 //! Web server endpoint handlers.
 
 #![allow(clippy::let_unit_value)]
-use crate::web::{NodeOpt, WebState};
-use async_std::fs::{read_dir, File};
-use cap_rust_sandbox::{ledger::CapeLedger, model::Erc20Code};
+use crate::{
+    alerts::Alerts,
+    asset_metadata::AssetMetadataStore,
+    auth::AdminToken,
+    autoconsolidate::AutoConsolidate,
+    autoretry::AutoRetry,
+    backups::{self, Backups},
+    balance_index::BalanceIndex,
+    cache::{modifies_wallet, ResponseCache},
+    categories::Categories,
+    clock::Clock,
+    coin_selection::CoinSelection,
+    confirmations::{self, Confirmations},
+    contacts::{Contacts, DuplicatePolicy},
+    conversions::Conversions,
+    eth_links::EthLinks,
+    feetopup::FeeTopUp,
+    lock::WalletLock,
+    min_anonymity::{anonymity_set, too_small_input, too_small_predicted_input, MinAnonymity},
+    notified::NotifiedRecords,
+    payment_requests::PaymentRequests,
+    persisted::Persisted,
+    proof_threads::{self, ProofThreads},
+    pruned::PrunedTransactions,
+    quarantine::{Quarantine, QuarantineReason, WalletQuarantine},
+    schedules::Schedules,
+    sent::{SentTransfers, TransferParams},
+    spend_limits::{self, SpendError, SpendLimits},
+    sponsors::Sponsors,
+    subscriptions::Subscriptions,
+    verifiers::Verifiers,
+    viewing_capabilities::ViewingCapabilities,
+    web::{match_route, NodeOpt, WebState},
+    webhook::{settled_transactions, Webhooks},
+};
+use async_std::{
+    fs::{read_dir, File},
+    sync::Mutex,
+};
+use cap_rust_sandbox::{
+    ledger::{CapeLedger, CapeTransactionKind, CapeTransition},
+    model::{is_erc20_asset_def_valid, CapeModelTxn, Erc20Code},
+    types::GenericInto,
+};
 use cape_wallet::{
+    backend::ScanInterval,
     disco::{ApiRouteKey, UrlSegmentType},
     loader::CapeLoader,
     ui::*,
-    wallet::{CapeWalletError, CapeWalletExt},
+    wallet::{
+        derive_subaccount_key, transaction_hash_string, wallet_key_tree, CapeWalletError,
+        CapeWalletExt, HistoricalState,
+    },
 };
-use ethers::prelude::Address;
-use futures::{prelude::*, stream::iter};
+use ethers::prelude::{Address, Signature, U256};
+use futures::{future::BoxFuture, prelude::*, stream::iter};
 use jf_cap::{
-    keys::{AuditorPubKey, FreezerPubKey, UserKeyPair, UserPubKey},
+    keys::{AuditorKeyPair, AuditorPubKey, FreezerPubKey, UserKeyPair, UserPubKey},
     structs::{
-        AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy, FreezeFlag,
-        RecordOpening as JfRecordOpening,
+        AssetCode, AssetDefinition as JfAssetDefinition, AssetPolicy, FreezeFlag, Nullifier,
+        ReceiverMemo, RecordCommitment, RecordOpening as JfRecordOpening,
     },
+    MerkleLeafProof, MerkleTree, TransactionNote, VerKey,
 };
 use net::{
-    server::{request_body, response},
+    server::{self, request_body},
     TaggedBlob, UserAddress,
 };
+use rand::RngCore;
 use rand_chacha::ChaChaRng;
+use reef::cap;
+use reef::traits::Transaction as _;
+use relayer::SubmitBody;
 use seahorse::{
-    asset_library::Icon,
+    asset_library::{Icon, VerifiedAssetLibrary},
     events::{EventIndex, EventSource},
-    hd::KeyTree,
-    txn_builder::{RecordInfo, TransactionReceipt},
+    hd::{KeyTree, Mnemonic},
+    txn_builder::{RecordInfo, TransactionInfo, TransactionReceipt, TransactionStatus},
     WalletBackend, WalletStorage,
 };
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::Cursor;
+use std::mem::{size_of, size_of_val};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use strum::IntoEnumIterator;
 use tagged_base64::TaggedBase64;
-use tide::{Request, StatusCode};
+use tempdir::TempDir;
+use tide::{http::Method, Request, StatusCode};
+use tracing::{event, Instrument, Level};
 
 #[derive(Debug, Snafu, Serialize, Deserialize)]
 #[snafu(module(error))]
@@ -73,6 +127,58 @@ pub enum CapeAPIError {
 
     #[snafu(display("internal server error: {}", msg))]
     Internal { msg: String },
+
+    #[snafu(display("unknown field: {}", field))]
+    UnknownField { field: String },
+
+    #[snafu(display("unauthorized: {}", msg))]
+    Unauthorized { msg: String },
+
+    #[snafu(display(
+        "spending limit exceeded for {}: only {} can be spent until the window rolls",
+        asset,
+        remaining
+    ))]
+    SpendLimitExceeded { asset: AssetCode, remaining: u128 },
+
+    #[snafu(display("the wallet was closed after being idle; open it again to continue"))]
+    WalletTimedOut,
+
+    #[snafu(display("failed to verify ownership of Ethereum address: {}", msg))]
+    EthOwnershipFailed { msg: String },
+
+    #[snafu(display("the wallet has pending transactions; try again once they settle"))]
+    WalletBusy,
+
+    #[snafu(display("transaction submission is paused; try again once an operator resumes it"))]
+    SubmissionPaused,
+
+    #[snafu(display("the wallet is locked; unlock it to make changes"))]
+    WalletLocked,
+
+    #[snafu(display(
+        "too many failed attempts to unlock the wallet; try again in {} seconds",
+        retry_in_secs
+    ))]
+    UnlockThrottled { retry_in_secs: u64 },
+
+    #[snafu(display("not found: {}", msg))]
+    NotFound { msg: String },
+
+    #[snafu(display("invalid swap proposal: {}", msg))]
+    InvalidSwap { msg: String },
+
+    #[snafu(display(
+        "record {} has an anonymity set of {}, below the minimum of {}; wait for more records to \
+         be committed before spending it",
+        uid,
+        size,
+        threshold
+    ))]
+    AnonymitySetTooSmall { uid: u64, size: u64, threshold: u64 },
+
+    #[snafu(display("the {} route is disabled on this server", route))]
+    RouteDisabled { route: String },
 }
 
 impl net::Error for CapeAPIError {
@@ -85,7 +191,20 @@ impl net::Error for CapeAPIError {
             | Self::Tag { .. }
             | Self::Deserialize { .. }
             | Self::OpenWallet { .. }
-            | Self::MissingWallet => StatusCode::BadRequest,
+            | Self::MissingWallet
+            | Self::WalletTimedOut
+            | Self::UnknownField { .. }
+            | Self::InvalidSwap { .. } => StatusCode::BadRequest,
+            Self::Unauthorized { .. } => StatusCode::Unauthorized,
+            Self::NotFound { .. } => StatusCode::NotFound,
+            Self::SpendLimitExceeded { .. }
+            | Self::EthOwnershipFailed { .. }
+            | Self::AnonymitySetTooSmall { .. }
+            | Self::RouteDisabled { .. } => StatusCode::Forbidden,
+            Self::WalletBusy => StatusCode::Conflict,
+            Self::WalletLocked => StatusCode::Locked,
+            Self::UnlockThrottled { .. } => StatusCode::TooManyRequests,
+            Self::SubmissionPaused => StatusCode::ServiceUnavailable,
             Self::Wallet { .. } | Self::Internal { .. } => StatusCode::InternalServerError,
         }
     }
@@ -102,23 +221,63 @@ mod backend {
     use cap_rust_sandbox::universal_param::verifier_keys;
     use cape_wallet::{
         loader::CapeMetadata,
-        mocks::{MockCapeBackend, MockCapeNetwork},
+        mocks::{MockCapeBackend, MockCapeLedger, MockCapeNetwork},
     };
     use jf_cap::{
         structs::{FreezeFlag, ReceiverMemo, RecordCommitment, RecordOpening},
         MerkleTree,
     };
+    use lazy_static::lazy_static;
     use reef::traits::Ledger;
     use seahorse::testing::MockLedger;
 
     pub type Backend = MockCapeBackend<'static, CapeMetadata>;
 
+    type SharedLedger = Arc<Mutex<MockCapeLedger<'static>>>;
+
+    lazy_static! {
+        static ref LEDGERS: std::sync::Mutex<HashMap<PathBuf, SharedLedger>> = Default::default();
+        /// The ledger the next wallet opened with each storage directory will use, if it should
+        /// not get a new one. See [share_ledger].
+        static ref SHARED_LEDGERS: std::sync::Mutex<HashMap<PathBuf, SharedLedger>> =
+            Default::default();
+    }
+
+    /// The mock ledger of the wallet most recently opened by the server using `options`.
+    ///
+    /// The ledger commits each transaction as soon as it is submitted. Tests which need a
+    /// transaction to stay pending can increase the ledger's block size, and flush the ledger when
+    /// they are ready for the transaction to be committed.
+    pub fn mock_ledger(options: &NodeOpt) -> SharedLedger {
+        LEDGERS.lock().unwrap()[&options.storage()].clone()
+    }
+
+    /// Make the next wallet opened with `options` use `ledger`, instead of a new mock ledger.
+    pub fn share_ledger(options: &NodeOpt, ledger: SharedLedger) {
+        SHARED_LEDGERS
+            .lock()
+            .unwrap()
+            .insert(options.storage(), ledger);
+    }
+
     pub async fn new(
-        _options: &NodeOpt,
+        options: &NodeOpt,
+        scan_interval: &ScanInterval,
         rng: &mut ChaChaRng,
         faucet_pub_key: UserPubKey,
         loader: &mut CapeLoader,
     ) -> Result<Backend, CapeWalletError> {
+        let shared = SHARED_LEDGERS.lock().unwrap().remove(&options.storage());
+        if let Some(ledger) = shared {
+            LEDGERS
+                .lock()
+                .unwrap()
+                .insert(options.storage(), ledger.clone());
+            let mut backend = MockCapeBackend::new(ledger, loader)?;
+            backend.set_scan_interval(scan_interval.clone());
+            return Ok(backend);
+        }
+
         let verif_crs = verifier_keys();
 
         // Set up a faucet record.
@@ -139,7 +298,27 @@ mod backend {
         );
         ledger.set_block_size(1).unwrap();
 
-        MockCapeBackend::new(Arc::new(Mutex::new(ledger)), loader)
+        let ledger = Arc::new(Mutex::new(ledger));
+        LEDGERS
+            .lock()
+            .unwrap()
+            .insert(options.storage(), ledger.clone());
+        let mut backend = MockCapeBackend::new(ledger, loader)?;
+        backend.set_scan_interval(scan_interval.clone());
+        Ok(backend)
+    }
+
+    /// A backend for a temporary wallet which uses the same ledger as `wallet`.
+    pub async fn temporary(
+        _options: &NodeOpt,
+        _scan_interval: &ScanInterval,
+        _rng: &mut ChaChaRng,
+        _faucet_pub_key: UserPubKey,
+        wallet: &Wallet,
+        loader: &mut CapeLoader,
+    ) -> Result<Backend, CapeWalletError> {
+        let ledger = wallet.lock().await.backend().ledger().clone();
+        MockCapeBackend::new(ledger, loader)
     }
 
     pub async fn latest_contract(_options: &NodeOpt) -> Result<Erc20Code, CapeWalletError> {
@@ -148,6 +327,17 @@ mod backend {
         // doesn't matter what we return here.
         Ok(Erc20Code::default())
     }
+
+    pub async fn ledger_size(_options: &NodeOpt) -> Result<(u64, u64), CapeWalletError> {
+        // Each wallet gets its own mock ledger, which starts out with just the faucet record, so
+        // that is the ledger a newly recovered wallet would scan.
+        Ok((0, 1))
+    }
+
+    pub async fn block_height(_options: &NodeOpt) -> Result<u64, CapeWalletError> {
+        // Each wallet gets its own mock ledger, which starts out with no blocks.
+        Ok(0)
+    }
 }
 
 #[cfg(not(test))]
@@ -160,11 +350,12 @@ mod backend {
 
     pub async fn new(
         options: &NodeOpt,
+        scan_interval: &ScanInterval,
         _rng: &mut ChaChaRng,
         _faucet_pub_key: UserPubKey,
         loader: &mut CapeLoader,
     ) -> Result<Backend, CapeWalletError> {
-        CapeBackend::new(
+        let mut backend = CapeBackend::new(
             &UNIVERSAL_PARAM,
             CapeBackendConfig {
                 web3_provider: options.web3_provider(),
@@ -176,15 +367,40 @@ mod backend {
             },
             loader,
         )
-        .await
+        .await?;
+        backend.set_scan_interval(scan_interval.clone());
+        Ok(backend)
+    }
+
+    /// A backend for a temporary wallet which uses the same ledger as `wallet`.
+    pub async fn temporary(
+        options: &NodeOpt,
+        scan_interval: &ScanInterval,
+        rng: &mut ChaChaRng,
+        faucet_pub_key: UserPubKey,
+        _wallet: &Wallet,
+        loader: &mut CapeLoader,
+    ) -> Result<Backend, CapeWalletError> {
+        // Every backend connects to the CAPE network given by `options`.
+        new(options, scan_interval, rng, faucet_pub_key, loader).await
     }
 
     pub async fn latest_contract(options: &NodeOpt) -> Result<Erc20Code, CapeWalletError> {
         CapeLoader::latest_contract(options.eqs_url()).await
     }
+
+    pub async fn ledger_size(options: &NodeOpt) -> Result<(u64, u64), CapeWalletError> {
+        CapeLoader::ledger_size(options.eqs_url()).await
+    }
+
+    pub async fn block_height(options: &NodeOpt) -> Result<u64, CapeWalletError> {
+        CapeLoader::block_height(options.eqs_url()).await
+    }
 }
 
-pub use backend::Backend;
+pub use backend::{latest_contract, Backend};
+#[cfg(test)]
+pub use backend::{mock_ledger, share_ledger};
 pub type Wallet = seahorse::Wallet<'static, Backend, CapeLedger>;
 
 #[allow(dead_code)]
@@ -383,9 +599,17 @@ pub async fn read_last_path(options: &NodeOpt) -> Result<Option<PathBuf>, tide::
     Ok(Some(bincode::deserialize(&bytes)?))
 }
 
+/// The path of the last wallet opened, which per-wallet state is keyed by.
+pub async fn require_wallet_path(options: &NodeOpt) -> Result<PathBuf, tide::Error> {
+    read_last_path(options)
+        .await?
+        .ok_or_else(|| server_error(CapeAPIError::MissingWallet))
+}
+
 // Create a wallet (if !existing) or open an existing one.
 pub async fn init_wallet(
     options: &NodeOpt,
+    scan_interval: &ScanInterval,
     rng: &mut ChaChaRng,
     faucet_pub_key: UserPubKey,
     mut loader: CapeLoader,
@@ -394,7 +618,7 @@ pub async fn init_wallet(
     // Store the path so we can have a getlastkeystore endpoint
     write_path(options, loader.path()).await?;
 
-    let mut backend = backend::new(options, rng, faucet_pub_key, &mut loader)
+    let mut backend = backend::new(options, scan_interval, rng, faucet_pub_key, &mut loader)
         .map_err(wallet_error)
         .await?;
     if backend.storage().await.exists() != existing {
@@ -438,6 +662,305 @@ pub fn require_wallet(wallet: &mut Option<Wallet>) -> Result<&mut Wallet, tide::
         .ok_or_else(|| server_error(CapeAPIError::MissingWallet))
 }
 
+/// Whether the response to `req` should be pretty-printed.
+///
+/// This is controlled by the `pretty` query parameter, which defaults to [NodeOpt::pretty_json].
+fn pretty(req: &Request<WebState>) -> Result<bool, tide::Error> {
+    match req.url().query_pairs().find(|(key, _)| key == "pretty") {
+        Some((_, value)) => value.parse().map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("true or false"),
+                actual: value.to_string(),
+            })
+        }),
+        None => Ok(req.state().options.pretty_json),
+    }
+}
+
+/// Whether [server::response] will serialize the response to `req` as JSON.
+///
+/// We only pretty-print JSON. To be safe, if the client mentions a binary format at all, we leave
+/// content negotiation entirely to [server::response].
+fn accepts_json(req: &Request<WebState>) -> bool {
+    match req.header("Accept") {
+        Some(accept) => {
+            let accept = accept.as_str();
+            !accept.contains("application/octet-stream")
+                && (accept.contains("json") || accept.contains("*/*"))
+        }
+        None => true,
+    }
+}
+
+/// Serialize a successful response, pretty-printing it if requested.
+///
+/// Error responses are serialized by middleware, and are never pretty-printed, so their format does
+/// not depend on the request.
+pub(crate) fn response<T: Serialize>(
+    req: &Request<WebState>,
+    t: T,
+) -> Result<tide::Response, tide::Error> {
+    if pretty(req)? && accepts_json(req) {
+        Ok(tide::Response::builder(StatusCode::Ok)
+            .content_type(tide::http::mime::JSON)
+            .body(serde_json::to_string_pretty(&t)?)
+            .build())
+    } else {
+        server::response(req, t)
+    }
+}
+
+/// Parse the optional `asof` query parameter accepted by some read-only endpoints.
+fn asof(req: &Request<WebState>) -> Result<Option<usize>, tide::Error> {
+    match req.url().query_pairs().find(|(key, _)| key == "asof") {
+        Some((_, value)) => value.parse().map(Some).map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("event index"),
+                actual: value.to_string(),
+            })
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Parse the optional `trace` query parameter accepted by some mutating endpoints.
+fn trace(req: &Request<WebState>) -> Result<bool, tide::Error> {
+    match req.url().query_pairs().find(|(key, _)| key == "trace") {
+        Some((_, value)) => value.parse().map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("true or false"),
+                actual: value.to_string(),
+            })
+        }),
+        None => Ok(false),
+    }
+}
+
+/// Run one phase of a traced operation in its own span, recording how long it took in `trace`.
+async fn timed<F: Future>(trace: &mut OperationTrace, phase: &str, f: F) -> F::Output {
+    let start = Instant::now();
+    let output = f
+        .instrument(tracing::info_span!("phase", name = phase))
+        .await;
+    trace.phases.push(PhaseTiming {
+        phase: phase.to_string(),
+        duration_ns: start.elapsed().as_nanos() as u64,
+    });
+    output
+}
+
+/// How often a traced operation checks whether its transaction has been confirmed.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Select the records a transaction spending `amount` of `asset` and paying `fee` would spend.
+///
+/// This is the selection the transaction builder makes (see [ChangePreview::select]), made up front
+/// so that traced operations can time it separately from generating the proof.
+async fn select_inputs(
+    wallet: &Wallet,
+    asset: AssetCode,
+    amount: u128,
+    fee: u128,
+) -> Result<ChangePreview, tide::Error> {
+    ChangePreview::select(
+        wallet.records().await,
+        asset,
+        amount.into(),
+        fee.into(),
+        ProofParams::cape().max_transfer_inputs,
+    )
+    .map_err(|blocker| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("a transfer the wallet can build"),
+            actual: format!("{} ({})", blocker, blocker.code()),
+        })
+    })
+}
+
+/// Build a transaction with `build` and submit it, timing the `select`, `prove` and `submit` phases.
+///
+/// The transaction spends `amount` of `asset` and pays `fee`, which determines the records selected
+/// in the `select` phase. `build` generates the proofs on the configured pool, as for
+/// [proof_threads::build].
+async fn traced_submit<F>(
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+    asset: AssetCode,
+    amount: u128,
+    fee: u128,
+    build: F,
+) -> Result<(TransactionReceipt<CapeLedger>, OperationTrace), tide::Error>
+where
+    F: for<'a> FnOnce(
+            &'a mut Wallet,
+        ) -> BoxFuture<
+            'a,
+            Result<(CapeModelTxn, TransactionInfo<CapeLedger>), tide::Error>,
+        > + Send
+        + 'static,
+{
+    let mut trace = OperationTrace::default();
+    timed(
+        &mut trace,
+        "select",
+        select_inputs(require_wallet(wallet)?, asset, amount, fee),
+    )
+    .await?;
+    let (txn, info) = timed(
+        &mut trace,
+        "prove",
+        proof_threads::build(proof_threads, wallet, build),
+    )
+    .await?;
+    let receipt = timed(
+        &mut trace,
+        "submit",
+        require_wallet(wallet)?.submit(CapeTransition::Transaction(txn), info),
+    )
+    .await
+    .map_err(wallet_error)?;
+    Ok((receipt, trace))
+}
+
+/// Wait for the transaction with `receipt` to be confirmed or rejected, timing the wait as the
+/// `confirm` phase of `trace`.
+///
+/// Confirmation can take several blocks, so like `closewallet`, this takes the wallet lock only to
+/// check the status of the transaction, letting other requests use the wallet in the meantime.
+async fn confirm(
+    wallet: &Mutex<Option<Wallet>>,
+    receipt: &TransactionReceipt<CapeLedger>,
+    trace: &mut OperationTrace,
+) -> Result<(), tide::Error> {
+    timed(trace, "confirm", async {
+        loop {
+            let status = require_wallet(&mut *wallet.lock().await)?
+                .transaction_status(receipt)
+                .await
+                .map_err(wallet_error)?;
+            if !matches!(
+                status,
+                TransactionStatus::Pending | TransactionStatus::AwaitingMemos
+            ) {
+                return Ok(());
+            }
+            async_std::task::sleep(CONFIRM_POLL_INTERVAL).await;
+        }
+    })
+    .await
+}
+
+/// Respond with the result of an operation, along with its timing breakdown if it was traced.
+fn traced_response<T: Serialize>(
+    req: &Request<WebState>,
+    result: T,
+    trace: Option<OperationTrace>,
+) -> Result<tide::Response, tide::Error> {
+    match trace {
+        Some(trace) => response(req, Traced { result, trace }),
+        None => response(req, result),
+    }
+}
+
+/// Parse the optional `fields` query parameter, a comma-separated list of top-level field names.
+fn fields(req: &Request<WebState>) -> Option<Vec<String>> {
+    req.url()
+        .query_pairs()
+        .find(|(key, _)| key == "fields")
+        .map(|(_, value)| {
+            value
+                .split(',')
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect()
+        })
+}
+
+/// Restrict the serialized form of `value` to the top-level fields named in `fields`.
+fn select_fields<T: Serialize>(
+    value: &T,
+    fields: &[String],
+) -> Result<serde_json::Value, tide::Error> {
+    let mut object = match serde_json::to_value(value)? {
+        serde_json::Value::Object(object) => object,
+        _ => {
+            return Err(server_error(CapeAPIError::Internal {
+                msg: String::from("field selection is only supported for structs"),
+            }))
+        }
+    };
+    let mut selected = serde_json::Map::new();
+    for field in fields {
+        match object.remove(field) {
+            Some(value) => {
+                selected.insert(field.clone(), value);
+            }
+            None => {
+                return Err(server_error(CapeAPIError::UnknownField {
+                    field: field.clone(),
+                }))
+            }
+        }
+    }
+    Ok(serde_json::Value::Object(selected))
+}
+
+/// Reconstruct the state of `wallet` as of the event index `asof`.
+///
+/// Fails if `asof` is later than the wallet's current sync time.
+async fn historical_state(wallet: &Wallet, asof: usize) -> Result<HistoricalState, tide::Error> {
+    let now = wallet.now().await.index(EventSource::QueryService);
+    if asof > now {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!("event index no later than the current sync time ({})", now),
+            actual: asof.to_string(),
+        }));
+    }
+    Ok(wallet
+        .state_at(EventIndex::from_source(EventSource::QueryService, asof))
+        .await?)
+}
+
+/// Recover sending keys until `gap_limit` consecutive keys are found which have never been used.
+///
+/// Each key is scanned from the start of the ledger. A key counts as used if it owns any records
+/// or appears in the transaction history.
+async fn scan_ahead(wallet: &mut Wallet, gap_limit: usize) -> Result<(), tide::Error> {
+    let mut gap = 0;
+    while gap < gap_limit {
+        let key = wallet
+            .generate_user_key(
+                String::new(),
+                Some(EventIndex::from_source(EventSource::QueryService, 0)),
+            )
+            .await?;
+        let address = key.address();
+        wallet.await_key_scan(&address).await?;
+
+        let has_records = wallet
+            .records()
+            .await
+            .any(|record| record.ro.pub_key == key);
+        let has_history = wallet
+            .transaction_history()
+            .await?
+            .into_iter()
+            .any(|entry| {
+                entry.senders.contains(&address)
+                    || entry
+                        .receivers
+                        .iter()
+                        .any(|(receiver, _)| *receiver == address)
+            });
+        if has_records || has_history {
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+    }
+    Ok(())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Endpoints
 //
@@ -451,6 +974,298 @@ pub async fn getmnemonic(rng: &mut ChaChaRng) -> Result<String, tide::Error> {
     Ok(KeyTree::random(rng).1.to_string().replace(' ', "-"))
 }
 
+/// Minimum time taken by `validatemnemonic`.
+///
+/// Parsing a phrase can fail early (at the first word not in the word list) or late (at the
+/// checksum), so we pad every response to this duration to avoid revealing how close an invalid
+/// phrase came to being valid.
+const VALIDATE_MNEMONIC_TIME: Duration = Duration::from_millis(10);
+
+async fn validatemnemonic(
+    bindings: &HashMap<String, RouteBinding>,
+) -> Result<MnemonicValidation, tide::Error> {
+    let start = Instant::now();
+    let phrase = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+    let validation = MnemonicValidation {
+        valid: Mnemonic::from_phrase(&phrase).is_ok(),
+        word_count: phrase.split_whitespace().count(),
+    };
+    async_std::task::sleep(VALIDATE_MNEMONIC_TIME.saturating_sub(start.elapsed())).await;
+    Ok(validation)
+}
+
+/// The number of indices `testvectors` derives keys for when no count is given.
+const DEFAULT_TEST_VECTORS: u64 = 5;
+
+/// The most indices `testvectors` will derive keys for.
+const MAX_TEST_VECTORS: u64 = 100;
+
+fn testvectors(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+) -> Result<Vec<KeyTestVector>, tide::Error> {
+    if !options.dev_mode {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("server started with --dev-mode"),
+            actual: String::from("testvectors"),
+        }));
+    }
+    let phrase = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+    let mnemonic = Mnemonic::from_phrase(&phrase).map_err(|_| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("valid mnemonic"),
+            actual: String::from("invalid mnemonic"),
+        })
+    })?;
+    let count = match bindings.get(":count") {
+        Some(param) => param.value.as_u64()?,
+        None => DEFAULT_TEST_VECTORS,
+    };
+    if count > MAX_TEST_VECTORS {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!("at most {} test vectors", MAX_TEST_VECTORS),
+            actual: count.to_string(),
+        }));
+    }
+
+    // The same derivation the wallet uses, with a separate sub-tree for each kind of key.
+    let key_tree = wallet_key_tree(&mnemonic);
+    let viewing_keys = key_tree.derive_sub_tree("auditor".as_bytes());
+    let freezing_keys = key_tree.derive_sub_tree("freezer".as_bytes());
+    Ok((0..count)
+        .map(|index| {
+            let sending_key = derive_subaccount_key(&key_tree, index).pub_key();
+            KeyTestVector {
+                index,
+                address: sending_key.address().into(),
+                sending_key,
+                viewing_key: viewing_keys
+                    .derive_auditor_key_pair(&index.to_le_bytes())
+                    .pub_key(),
+                freezing_key: freezing_keys
+                    .derive_freezer_key_pair(&index.to_le_bytes())
+                    .pub_key(),
+            }
+        })
+        .collect())
+}
+
+fn checkrecipient(bindings: &HashMap<String, RouteBinding>) -> Result<RecipientCheck, tide::Error> {
+    let key = bindings[":receiving_key"].value.as_string()?;
+    let error = match TaggedBase64::parse(&key) {
+        Err(err) => format!(
+            "not a valid TaggedBase64 string ({}); the key may have been truncated or corrupted \
+             when it was copied",
+            err
+        ),
+        Ok(tb64) if tb64.tag() == "ADDR" => String::from(
+            "this is an address, not a receiving key; a transfer needs the recipient's receiving \
+             key (USERPUBKEY~...)",
+        ),
+        Ok(tb64) if tb64.tag() != "USERPUBKEY" => format!(
+            "expected a receiving key with tag USERPUBKEY, but the tag is {}",
+            tb64.tag()
+        ),
+        Ok(tb64) => match UserPubKey::from_tagged_blob(&tb64) {
+            Ok(pub_key) => {
+                return Ok(RecipientCheck {
+                    ok: true,
+                    address: Some(pub_key.address().into()),
+                    error: None,
+                })
+            }
+            Err(err) => format!("not a valid CAPE receiving key ({})", err),
+        },
+    };
+    Ok(RecipientCheck {
+        ok: false,
+        address: None,
+        error: Some(error),
+    })
+}
+
+async fn record(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<RecordProvenance, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let uid = bindings[":uid"].value.as_u64()?;
+    let record = wallet
+        .records()
+        .await
+        .find(|record| record.uid == uid)
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("uid of a record owned by this wallet"),
+                actual: uid.to_string(),
+            })
+        })?;
+    let origin = wallet
+        .record_origin(uid)
+        .await
+        .map_err(wallet_error)?
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Internal {
+                msg: format!("record {} is not in the event log", uid),
+            })
+        })?;
+
+    let transaction = transaction_hash_string(&origin.transaction.hash());
+    // The senders of a transaction are not part of the transaction itself, but we may know them
+    // from our own history, for example if we sent it.
+    let senders = match &transaction {
+        Some(hash) => wallet
+            .transaction_history()
+            .await
+            .map_err(wallet_error)?
+            .into_iter()
+            .find(|entry| {
+                entry
+                    .hash
+                    .as_ref()
+                    .and_then(transaction_hash_string)
+                    .as_ref()
+                    == Some(hash)
+            })
+            .map(|entry| entry.senders.into_iter().map(UserAddress::from).collect())
+            .unwrap_or_default(),
+        None => vec![],
+    };
+    let eth_sender = match &origin.transaction {
+        CapeTransition::Wrap { src_addr, .. } => Some(Address::from(src_addr.0)),
+        _ => None,
+    };
+    Ok(RecordProvenance {
+        record: Record::from(record),
+        transaction,
+        kind: transaction_kind_string(&origin.transaction.kind()),
+        block_id: origin.block_id,
+        txn_id: origin.txn_id,
+        senders,
+        eth_sender,
+        memo: origin.memo,
+    })
+}
+
+/// Explain whether the record `:uid` can be spent, combining every check which would stop a
+/// transfer from spending it.
+async fn recordspendable(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    min_anonymity: &Mutex<MinAnonymity>,
+    wallet: &mut Option<Wallet>,
+) -> Result<RecordSpendability, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let uid = bindings[":uid"].value.as_u64()?;
+    let threshold = min_anonymity.lock().await.get(&wallet_path);
+    let (frontier, _, _) = wallet.record_merkle_frontier().await;
+    let size = anonymity_set(uid, frontier.num_leaves);
+
+    let record = match wallet.records().await.find(|record| record.uid == uid) {
+        Some(record) => record,
+        // A uid beyond the records the wallet has scanned may be a record it will receive once it
+        // catches up. Any other uid is not a record the wallet can spend at all.
+        None if uid >= frontier.num_leaves => {
+            return Ok(RecordSpendability::new(
+                uid,
+                None,
+                size,
+                threshold,
+                vec![UnspendableReason::Unconfirmed],
+            ))
+        }
+        None => {
+            return Err(server_error(CapeAPIError::NotFound {
+                msg: format!(
+                    "record {} is not an unspent record owned by this wallet",
+                    uid
+                ),
+            }))
+        }
+    };
+
+    let mut reasons = vec![];
+    if record.ro.freeze_flag != FreezeFlag::Unfrozen {
+        reasons.push(UnspendableReason::Frozen);
+    }
+    if record.hold_until.is_some() {
+        reasons.push(UnspendableReason::OnHold);
+    }
+    if size < threshold {
+        reasons.push(UnspendableReason::BelowMinAnonymity);
+    }
+    let (_, paths) = wallet.record_merkle_paths(&[uid]).await;
+    if !paths.contains_key(&uid) {
+        reasons.push(UnspendableReason::MissingMerkleProof);
+    }
+    Ok(RecordSpendability::new(
+        uid,
+        Some(Record::from(record)),
+        size,
+        threshold,
+        reasons,
+    ))
+}
+
+async fn recoveryestimate(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    gap_limit: &Mutex<usize>,
+    wallet: &mut Option<Wallet>,
+) -> Result<RecoveryEstimate, tide::Error> {
+    let phrase = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+    if Mnemonic::from_phrase(&phrase).is_err() {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a valid mnemonic phrase"),
+            actual: format!("{} words", phrase.split_whitespace().count()),
+        }));
+    }
+
+    // An open wallet has already synced with the ledger a recovered wallet would scan, so use its
+    // view rather than querying the EQS.
+    let (events, records) = match wallet {
+        Some(wallet) => {
+            let (_, eqs_time) = wallet.scan_status().await.map_err(wallet_error)?;
+            let (commitment, _, _) = wallet.record_merkle_frontier().await;
+            (
+                eqs_time.index(EventSource::QueryService) as u64,
+                commitment.num_leaves,
+            )
+        }
+        None => backend::ledger_size(options).await.map_err(wallet_error)?,
+    };
+    // Recovery always checks at least one key, and with a gap limit it checks that many keys
+    // beyond the last one in use.
+    let keys = std::cmp::max(*gap_limit.lock().await, 1) as u64;
+    Ok(RecoveryEstimate::new(events, records, keys))
+}
+
+async fn clock(
+    options: &NodeOpt,
+    clock: &Mutex<Clock>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ClockStatus, tide::Error> {
+    // As for `recoveryestimate`, prefer the open wallet's view of the ledger to querying the EQS.
+    let block_height = match wallet {
+        Some(wallet) => {
+            wallet
+                .record_blocks(&HashSet::new())
+                .await
+                .map_err(wallet_error)?
+                .0
+        }
+        None => backend::block_height(options).await.map_err(wallet_error)?,
+    };
+    let mut clock = clock.lock().await;
+    let now_ms = clock.observe(block_height);
+    Ok(ClockStatus {
+        now_ms,
+        block_height,
+        blocks_per_second: clock.blocks_per_second(),
+    })
+}
+
 /// Return a JSON expression with status 200 indicating the server
 /// is up and running. The JSON expression is simply,
 ///    {"status": "available"}
@@ -466,9 +1281,11 @@ async fn healthcheck() -> Result<tide::Response, tide::Error> {
 
 pub async fn newwallet(
     options: &NodeOpt,
+    scan_interval: &ScanInterval,
     bindings: &HashMap<String, RouteBinding>,
     rng: &mut ChaChaRng,
     faucet_key_pair: &UserKeyPair,
+    gap_limit: usize,
     wallet: &mut Option<Wallet>,
 ) -> Result<(), tide::Error> {
     let path = match bindings.get(":path") {
@@ -491,12 +1308,25 @@ pub async fn newwallet(
     // with two wallets using the same file at the same time.
     *wallet = None;
 
-    *wallet = Some(init_wallet(options, rng, faucet_key_pair.pub_key(), loader, false).await?);
-    Ok(())
+    *wallet = Some(
+        init_wallet(
+            options,
+            scan_interval,
+            rng,
+            faucet_key_pair.pub_key(),
+            loader,
+            false,
+        )
+        .await?,
+    );
+
+    // The mnemonic may belong to a wallet which has been used before, so look for keys it used.
+    scan_ahead(require_wallet(wallet)?, gap_limit).await
 }
 
 pub async fn openwallet(
     options: &NodeOpt,
+    scan_interval: &ScanInterval,
     bindings: &HashMap<String, RouteBinding>,
     rng: &mut ChaChaRng,
     faucet_key_pair: &UserKeyPair,
@@ -521,12 +1351,48 @@ pub async fn openwallet(
     // with two wallets using the same file at the same time.
     *wallet = None;
 
-    *wallet = Some(init_wallet(options, rng, faucet_key_pair.pub_key(), loader, true).await?);
+    *wallet = Some(
+        init_wallet(
+            options,
+            scan_interval,
+            rng,
+            faucet_key_pair.pub_key(),
+            loader,
+            true,
+        )
+        .await?,
+    );
     Ok(())
 }
 
+async fn checkwallet(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    wallet: &mut Option<Wallet>,
+) -> Result<WalletCheck, tide::Error> {
+    let path = bindings[":path"].value.as_path()?;
+    let mnemonic = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+    let result = match wallet {
+        // The open wallet is using its keystore, so check the mnemonic against the wallet rather
+        // than reading the keystore a second time.
+        Some(wallet) if read_last_path(options).await?.as_ref() == Some(&path) => {
+            if is_wallet_mnemonic(wallet, &mnemonic).await {
+                Ok(())
+            } else {
+                Err(UnopenableReason::WrongMnemonic)
+            }
+        }
+        _ => CapeLoader::check(mnemonic, path, backend::latest_contract(options).await?),
+    };
+    Ok(WalletCheck {
+        openable: result.is_ok(),
+        reason: result.err(),
+    })
+}
+
 pub async fn resetpassword(
     options: &NodeOpt,
+    scan_interval: &ScanInterval,
     bindings: &HashMap<String, RouteBinding>,
     rng: &mut ChaChaRng,
     faucet_key_pair: &UserKeyPair,
@@ -552,31 +1418,358 @@ pub async fn resetpassword(
     // with two wallets using the same file at the same time.
     *wallet = None;
 
-    *wallet = Some(init_wallet(options, rng, faucet_key_pair.pub_key(), loader, true).await?);
-    Ok(())
-}
-
-async fn closewallet(wallet: &mut Option<Wallet>) -> Result<(), tide::Error> {
-    require_wallet(wallet)?;
-    *wallet = None;
+    *wallet = Some(
+        init_wallet(
+            options,
+            scan_interval,
+            rng,
+            faucet_key_pair.pub_key(),
+            loader,
+            true,
+        )
+        .await?,
+    );
     Ok(())
 }
 
-async fn listkeystores(options: &NodeOpt) -> Result<Vec<String>, tide::Error> {
-    let mut entries = read_dir(options.keystores_dir()).await?;
-    let mut keystores = vec![];
-    while let Some(entry) = entries.next().await {
-        let path: PathBuf = entry?.path().into();
-        if let Some(name) = KeyStoreLocation::from(path).name {
-            keystores.push(name);
+/// Merge the annotations a user has given an asset in another wallet into `ours`.
+///
+/// Annotations which are only set in `theirs` are always merged. Annotations which are set in both
+/// and differ are resolved by `policy`. Returns whether `ours` changed and whether there were any
+/// conflicts.
+fn merge_asset_annotations(
+    ours: &mut seahorse::AssetInfo,
+    theirs: seahorse::AssetInfo,
+    policy: DuplicatePolicy,
+) -> (bool, bool) {
+    let mut changed = false;
+    let mut conflict = false;
+    for (ours, theirs) in [
+        (&mut ours.name, theirs.name),
+        (&mut ours.description, theirs.description),
+    ] {
+        match (ours.as_ref(), theirs) {
+            (None, Some(theirs)) => {
+                *ours = Some(theirs);
+                changed = true;
+            }
+            (Some(mine), Some(theirs)) if *mine != theirs => {
+                conflict = true;
+                if policy == DuplicatePolicy::Overwrite {
+                    *ours = Some(theirs);
+                    changed = true;
+                }
+            }
+            _ => {}
         }
     }
-    Ok(keystores)
+    if ours.icon.is_none() && theirs.icon.is_some() {
+        ours.icon = theirs.icon;
+        changed = true;
+    }
+    (changed, conflict)
 }
 
-async fn getinfo(wallet: &mut Option<Wallet>) -> Result<WalletSummary, tide::Error> {
+/// How many keys of one kind a wallet with `ours` must generate to have each of `theirs` which is
+/// derived by `derive`.
+///
+/// A wallet generates the keys of each kind in order, so a wallet which has generated `n` keys has
+/// exactly the derived keys with indices below `n`.
+fn keys_to_generate<K: PartialEq>(ours: &[K], theirs: &[K], derive: impl Fn(u64) -> K) -> usize {
+    let generated = |keys: &[K]| {
+        (0..)
+            .take_while(|index| keys.contains(&derive(*index)))
+            .count()
+    };
+    generated(theirs).saturating_sub(generated(ours))
+}
+
+/// The storage directory of the server which created the keystore at `path`, if there is one.
+///
+/// A server keeps its keystores in the `keystores` directory of its storage directory (see
+/// [NodeOpt::keystore_path]), alongside the rest of its state.
+fn keystore_storage_dir(path: &Path) -> Option<&Path> {
+    let keystores = path.parent()?;
+    if keystores.file_name()? == "keystores" {
+        keystores.parent()
+    } else {
+        None
+    }
+}
+
+pub async fn mergewallet(
+    options: &NodeOpt,
+    scan_interval: &ScanInterval,
+    bindings: &HashMap<String, RouteBinding>,
+    contacts: &Mutex<Contacts>,
+    rng: &mut ChaChaRng,
+    faucet_key_pair: &UserKeyPair,
+    wallet: &mut Option<Wallet>,
+) -> Result<MergeReport, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let path = bindings[":path"].value.as_path()?;
+    let mnemonic = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+    let password = bindings[":password"].value.as_string()?;
+    let policy = duplicate_policy(bindings.get(":on_conflict"))?;
+
+    // Opening the same store twice would leave two wallets using the same files.
+    if read_last_path(options).await?.as_ref() == Some(&path) {
+        return Err(server_error(CapeAPIError::OpenWallet {
+            msg: String::from("cannot merge a wallet into itself"),
+        }));
+    }
+
+    // Both wallets must belong to `mnemonic`. We check this by comparing the first sub-account key
+    // derived from each, so that no key material is copied from one wallet to the other.
+    let key_tree = wallet_key_tree(&Mnemonic::from_phrase(&mnemonic).map_err(|_| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("valid mnemonic"),
+            actual: String::from("invalid mnemonic"),
+        })
+    })?);
+    let key = derive_subaccount_key(&key_tree, 0).pub_key();
+    if wallet.subaccount_key(0).await.pub_key() != key {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("mnemonic of the open wallet"),
+            actual: String::from("a different mnemonic"),
+        }));
+    }
+
+    let mut loader = CapeLoader::from_literal(
+        None,
+        password,
+        path.clone(),
+        backend::latest_contract(options).await?,
+    );
+    let mut backend = backend::new(
+        options,
+        scan_interval,
+        rng,
+        faucet_key_pair.pub_key(),
+        &mut loader,
+    )
+    .map_err(wallet_error)
+    .await?;
+    if !backend.storage().await.exists() {
+        return Err(server_error(CapeAPIError::OpenWallet {
+            msg: String::from("cannot merge wallet that does not exist"),
+        }));
+    }
+    let other = Wallet::new(backend).await.map_err(wallet_error)?;
+    if other.subaccount_key(0).await.pub_key() != key {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("mnemonic of the wallet to merge"),
+            actual: String::from("a different mnemonic"),
+        }));
+    }
+
+    let mut report = MergeReport::default();
+    for theirs in other.assets().await {
+        let code = theirs.definition.code;
+        match wallet.asset(code).await {
+            Some(mut ours) => {
+                let (changed, conflict) = merge_asset_annotations(&mut ours, theirs, policy);
+                if conflict {
+                    report.conflicts.push(code);
+                }
+                if changed {
+                    wallet.import_asset(ours).await.map_err(wallet_error)?;
+                    report.updated_assets.push(code);
+                }
+            }
+            None => {
+                wallet.import_asset(theirs).await.map_err(wallet_error)?;
+                report.added_assets.push(code);
+            }
+        }
+    }
+
+    // The address book belongs to the server, so the other wallet's contacts are those of the
+    // server which created it, if that is not this one.
+    if let Some(storage) = keystore_storage_dir(&path).filter(|dir| *dir != options.storage()) {
+        let theirs = Contacts::load(&storage.join("contacts"))?;
+        let mut contacts = contacts.lock().await;
+        let ours = contacts.list();
+        let entries = theirs
+            .list()
+            .into_iter()
+            .filter(|contact| !ours.contains(contact))
+            .map(|contact| ContactEntry {
+                name: contact.name,
+                address: contact.address.to_string(),
+            })
+            .collect();
+        for result in contacts.import(entries, policy) {
+            match result.status {
+                ContactImportStatus::Added => report.added_contacts.push(result.name),
+                ContactImportStatus::Overwritten => {
+                    report.contact_conflicts.push(result.name.clone());
+                    report.updated_contacts.push(result.name);
+                }
+                ContactImportStatus::Skipped => report.contact_conflicts.push(result.name),
+                ContactImportStatus::Invalid { .. } => {}
+            }
+        }
+        contacts.save(&options.contacts_path())?;
+    }
+
+    // Keys which only the other wallet has generated are the ones this wallet would generate next,
+    // so generating them here merges them without copying any key material. Keys the other wallet
+    // imported, rather than generated, cannot be derived from the mnemonic, and are not merged.
+    let sending_keys =
+        keys_to_generate(&wallet.pub_keys().await, &other.pub_keys().await, |index| {
+            derive_subaccount_key(&key_tree, index).pub_key()
+        });
+    for _ in 0..sending_keys {
+        // Scan the key from the start of the ledger, to discover its records and the transactions
+        // it received before the history is merged below.
+        let key = wallet
+            .generate_user_key(
+                String::new(),
+                Some(EventIndex::from_source(EventSource::QueryService, 0)),
+            )
+            .await
+            .map_err(wallet_error)?;
+        wallet
+            .await_key_scan(&key.address())
+            .await
+            .map_err(wallet_error)?;
+        report.added_sending_keys.push(key);
+    }
+    let viewing_tree = key_tree.derive_sub_tree("auditor".as_bytes());
+    let viewing_keys = keys_to_generate(
+        &wallet.auditor_pub_keys().await,
+        &other.auditor_pub_keys().await,
+        |index| {
+            viewing_tree
+                .derive_auditor_key_pair(&index.to_le_bytes())
+                .pub_key()
+        },
+    );
+    for _ in 0..viewing_keys {
+        let key = wallet
+            .generate_audit_key(String::new())
+            .await
+            .map_err(wallet_error)?;
+        report.added_viewing_keys.push(key);
+    }
+    let freezing_tree = key_tree.derive_sub_tree("freezer".as_bytes());
+    let freezing_keys = keys_to_generate(
+        &wallet.freezer_pub_keys().await,
+        &other.freezer_pub_keys().await,
+        |index| {
+            freezing_tree
+                .derive_freezer_key_pair(&index.to_le_bytes())
+                .pub_key()
+        },
+    );
+    for _ in 0..freezing_keys {
+        let key = wallet
+            .generate_freeze_key(String::new())
+            .await
+            .map_err(wallet_error)?;
+        report.added_freezing_keys.push(key);
+    }
+
+    // With the same keys, both wallets discover the same transactions from the ledger, but a
+    // transaction which a wallet submitted itself is only in its own history. Entries are matched
+    // by transaction hash, so an entry without one cannot be deduplicated, and is not merged.
+    let known = wallet
+        .transaction_history()
+        .await
+        .map_err(wallet_error)?
+        .iter()
+        .filter_map(|entry| entry.hash.as_ref().and_then(transaction_hash_string))
+        .collect::<HashSet<_>>();
+    for entry in other.transaction_history().await.map_err(wallet_error)? {
+        if let Some(hash) = entry.hash.as_ref().and_then(transaction_hash_string) {
+            if !known.contains(&hash) {
+                wallet
+                    .import_transaction(entry)
+                    .await
+                    .map_err(wallet_error)?;
+                report.added_transactions.push(hash);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// How long `closewallet` waits for pending transactions to settle before giving up.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `closewallet` checks whether pending transactions have settled.
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+async fn closewallet(
+    lock: &Mutex<Option<WalletLock>>,
+    wallet: &Mutex<Option<Wallet>>,
+) -> Result<(), tide::Error> {
+    // Requests hold the wallet lock for their whole duration, so once we have it, no other request
+    // is using the wallet. A transfer which has been submitted but not yet settled is still tracked
+    // by the open wallet, though, so wait for it to settle rather than dropping it on the floor.
+    // This can take a while, so release the lock between checks, to let other requests use the
+    // wallet in the meantime.
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        {
+            let mut wallet = wallet.lock().await;
+            let open = require_wallet(&mut wallet)?;
+            // The wallet may have been locked while we were waiting, so check on every attempt.
+            let mut lock = lock.lock().await;
+            if lock.is_some() {
+                return Err(server_error(CapeAPIError::WalletLocked));
+            }
+            if !has_pending_spends(open).await {
+                *wallet = None;
+                *lock = None;
+                return Ok(());
+            }
+        }
+        if start.elapsed() >= CLOSE_TIMEOUT {
+            return Err(server_error(CapeAPIError::WalletBusy));
+        }
+        if !waiting {
+            event!(
+                Level::INFO,
+                "waiting for pending transactions to settle before closing wallet"
+            );
+            waiting = true;
+        }
+        async_std::task::sleep(CLOSE_POLL_INTERVAL).await;
+    }
+}
+
+/// Whether any of the wallet's records are on hold for a pending transaction.
+pub async fn has_pending_spends(wallet: &Wallet) -> bool {
+    wallet
+        .records()
+        .await
+        .any(|record| record.hold_until.is_some())
+}
+
+async fn listkeystores(options: &NodeOpt) -> Result<Vec<String>, tide::Error> {
+    let mut entries = read_dir(options.keystores_dir()).await?;
+    let mut keystores = vec![];
+    while let Some(entry) = entries.next().await {
+        let path: PathBuf = entry?.path().into();
+        if let Some(name) = KeyStoreLocation::from(path).name {
+            keystores.push(name);
+        }
+    }
+    Ok(keystores)
+}
+
+async fn getinfo(
+    options: &NodeOpt,
+    verifiers: &Mutex<Verifiers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<WalletSummary, tide::Error> {
     let wallet = require_wallet(wallet)?;
     let (sync_time, real_time) = wallet.scan_status().await.map_err(wallet_error)?;
+    let mut assets = known_assets(wallet).await.into_values().collect::<Vec<_>>();
+    apply_verifiers(options, verifiers, &mut assets).await?;
     Ok(WalletSummary {
         addresses: wallet
             .pub_keys()
@@ -587,7 +1780,7 @@ async fn getinfo(wallet: &mut Option<Wallet>) -> Result<WalletSummary, tide::Err
         sending_keys: wallet.pub_keys().await,
         viewing_keys: wallet.auditor_pub_keys().await,
         freezing_keys: wallet.freezer_pub_keys().await,
-        assets: known_assets(wallet).await.into_values().collect(),
+        assets,
         sync_time: sync_time.index(EventSource::QueryService),
         real_time: real_time.index(EventSource::QueryService),
         wallet_contract: format!("{:#x}", Address::from(wallet.contract_address().await?)),
@@ -598,6 +1791,85 @@ async fn getinfo(wallet: &mut Option<Wallet>) -> Result<WalletSummary, tide::Err
     })
 }
 
+/// Freeze (if `freeze` is true) or unfreeze all of the records of `:asset` owned by `:address`
+/// which this wallet can see.
+async fn freeze(
+    bindings: &HashMap<String, RouteBinding>,
+    freeze: bool,
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
+    let owner = bindings[":address"].value.to::<UserAddress>()?.0;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let fee_address = match bindings.get(":fee_address") {
+        Some(param) => Some(param.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    let fee = bindings[":fee_amount"].value.as_u128()?;
+
+    let flag = if freeze {
+        FreezeFlag::Unfrozen
+    } else {
+        FreezeFlag::Frozen
+    };
+    let amount = require_wallet(wallet)?
+        .records()
+        .await
+        .filter(|record| {
+            record.ro.asset_def.code == asset
+                && record.ro.pub_key.address() == owner
+                && record.ro.freeze_flag == flag
+        })
+        .map(|record| record.ro.amount.generic_into::<u128>())
+        .sum::<u128>();
+    if amount == 0 {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!(
+                "an address with {} records of {}",
+                if freeze { "unfrozen" } else { "frozen" },
+                asset
+            ),
+            actual: UserAddress::from(owner).to_string(),
+        }));
+    }
+
+    proof_threads::build(proof_threads, wallet, move |wallet| {
+        async move {
+            let receipt = if freeze {
+                wallet
+                    .freeze(fee_address.as_ref(), fee, &asset, amount, owner)
+                    .await
+            } else {
+                wallet
+                    .unfreeze(fee_address.as_ref(), fee, &asset, amount, owner)
+                    .await
+            };
+            receipt.map_err(tide::Error::from)
+        }
+        .boxed()
+    })
+    .await
+}
+
+async fn frontier(wallet: &mut Option<Wallet>) -> Result<RecordFrontier, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let (commitment, frontier, sync_time) = wallet.record_merkle_frontier().await;
+    RecordFrontier::new(&commitment, &frontier, sync_time).ok_or_else(|| {
+        server_error(CapeAPIError::Internal {
+            msg: String::from("failed to serialize record Merkle frontier"),
+        })
+    })
+}
+
+async fn statedigest(wallet: &mut Option<Wallet>) -> Result<StateDigest, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    StateDigest::from_wallet(wallet).await.ok_or_else(|| {
+        server_error(CapeAPIError::Internal {
+            msg: String::from("failed to serialize record Merkle root"),
+        })
+    })
+}
+
 async fn getaddress(wallet: &mut Option<Wallet>) -> Result<Vec<UserAddress>, tide::Error> {
     let wallet = require_wallet(wallet)?;
     Ok(wallet
@@ -621,8 +1893,15 @@ async fn getaddress(wallet: &mut Option<Wallet>) -> Result<Vec<UserAddress>, tid
 //  * Balances::One, if address and asset code both given
 //  * Balances::Account, if address given
 //  * Balances::All, if neither given
+#[allow(clippy::too_many_arguments)]
 async fn getbalance(
     bindings: &HashMap<String, RouteBinding>,
+    asof: Option<usize>,
+    options: &NodeOpt,
+    cache: &Mutex<ResponseCache>,
+    balance_index: &Mutex<BalanceIndex>,
+    quarantine: &Mutex<Quarantine>,
+    notified: Option<&Mutex<NotifiedRecords>>,
     wallet: &mut Option<Wallet>,
 ) -> Result<BalanceInfo, tide::Error> {
     let wallet = &require_wallet(wallet)?;
@@ -643,8 +1922,72 @@ async fn getbalance(
         None => None,
     };
 
+    // If a historical balance was requested, compute it from the reconstructed records rather than
+    // the live wallet state.
+    let historical = match asof {
+        Some(asof) => Some(historical_state(wallet, asof).await?),
+        None => None,
+    };
+    let historical = &historical;
+
+    // Live balances are looked up in the balance index. Quarantined records are left out of them
+    // until they are released, and notified records are added to them until the wallet receives
+    // their memos.
+    let mut balance_index = balance_index.lock().await;
+    let mut held = HashMap::<(UserAddress, AssetCode), U256>::new();
+    let mut pending = HashMap::<(UserAddress, AssetCode), U256>::new();
+    if historical.is_none() {
+        let wallet_path = read_last_path(options).await?;
+        let generation = cache.lock().await.generation();
+        balance_index
+            .update(
+                wallet,
+                wallet_path.as_deref().unwrap_or(Path::new("")),
+                generation,
+            )
+            .await;
+        if let Some(wallet_path) = wallet_path {
+            for (record, _, _) in
+                quarantined_records(wallet, quarantine.lock().await.get(&wallet_path)).await
+            {
+                *held
+                    .entry((record.ro.pub_key.address().into(), record.ro.asset_def.code))
+                    .or_default() += U256::from(record.ro.amount.generic_into::<u128>());
+            }
+            if let Some(notified) = notified {
+                for ro in
+                    pending_notified_records(wallet, &*notified.lock().await, &wallet_path).await?
+                {
+                    *pending
+                        .entry((ro.pub_key.address().into(), ro.asset_def.code))
+                        .or_default() += U256::from(ro.amount.generic_into::<u128>());
+                }
+            }
+        }
+    }
+    let balance_index = &*balance_index;
+    let held = &held;
+    let pending = &pending;
+
     let one_balance = |address: UserAddress, asset| async move {
-        wallet.balance_breakdown(&address.into(), &asset).await
+        match historical {
+            Some(state) => state
+                .records
+                .iter()
+                .filter(|record| {
+                    UserAddress::from(record.ro.pub_key.address()) == address
+                        && record.ro.asset_def.code == asset
+                })
+                .fold(U256::zero(), |total, record| {
+                    total + U256::from(record.ro.amount.generic_into::<u128>())
+                }),
+            None => {
+                let balance = balance_index.balance(&address, &asset);
+                let key = (address, asset);
+                let balance = balance + pending.get(&key).copied().unwrap_or_default();
+                balance.saturating_sub(held.get(&key).copied().unwrap_or_default())
+            }
+        }
     };
     let account_balances = |address: UserAddress| async move {
         iter(wallet.assets().await)
@@ -694,6 +2037,306 @@ async fn getbalance(
     Ok(BalanceInfo { balances, assets })
 }
 
+/// The records notified to the wallet at `wallet_path` which it has not received the memos for
+/// itself yet.
+async fn pending_notified_records(
+    wallet: &Wallet,
+    notified: &NotifiedRecords,
+    wallet_path: &Path,
+) -> Result<Vec<JfRecordOpening>, tide::Error> {
+    let mut pending = vec![];
+    for (uid, ro) in notified.get(wallet_path) {
+        if wallet.records().await.any(|record| record.uid == uid) {
+            continue;
+        }
+        // Once the wallet has processed the memo for a record, the record is part of the wallet's
+        // own state, even if it has since been spent.
+        let origin = wallet.record_origin(uid).await.map_err(wallet_error)?;
+        if origin.and_then(|origin| origin.memo).is_none() {
+            pending.push(ro.clone());
+        }
+    }
+    Ok(pending)
+}
+
+async fn notifyrecord(
+    req: &mut Request<WebState>,
+    options: &NodeOpt,
+    admin_token: &Mutex<Option<AdminToken>>,
+    notified: &Mutex<NotifiedRecords>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Record, tide::Error> {
+    require_admin_token(req, &*admin_token.lock().await)?;
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let RecordNotification {
+        memo,
+        commitment,
+        uid,
+        merkle_path,
+    } = request_body(req).await?;
+
+    // The record must be in the ledger as the wallet has scanned it...
+    let (frontier, _, _) = wallet.record_merkle_frontier().await;
+    if MerkleTree::check_proof(
+        frontier.root_value,
+        uid,
+        &MerkleLeafProof::new(commitment.to_field_element(), merkle_path),
+    )
+    .is_err()
+    {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a Merkle path from the wallet's record Merkle root"),
+            actual: format!("an invalid path for record {}", uid),
+        }));
+    }
+
+    // ...and the memo must open it for one of the wallet's keys.
+    let mut record = None;
+    for pub_key in wallet.pub_keys().await {
+        let key_pair = wallet
+            .get_user_private_key(&pub_key.address())
+            .await
+            .map_err(wallet_error)?;
+        if let Ok(ro) = memo.decrypt(&key_pair, &commitment, &[]) {
+            if RecordCommitment::from(&ro) == commitment {
+                record = Some(RecordInfo::new(ro, uid, &key_pair));
+                break;
+            }
+        }
+    }
+    let record = record.ok_or_else(|| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("a memo for a record owned by this wallet"),
+            actual: format!("a memo which does not open record {}", uid),
+        })
+    })?;
+
+    let mut notified = notified.lock().await;
+    notified.insert(&wallet_path, uid, record.ro.clone());
+    notified.save(&options.notified_path())?;
+    Ok(Record::from(record))
+}
+
+async fn exportrecords(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<RecordBundle, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let owner = match bindings.get(":address") {
+        Some(address) => Some(address.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    // Frozen records cannot be spent, so there is no point moving them to another wallet.
+    let records = wallet
+        .records()
+        .await
+        .filter(|record| {
+            record.ro.asset_def.code == asset
+                && record.ro.freeze_flag == FreezeFlag::Unfrozen
+                && owner
+                    .as_ref()
+                    .map_or(true, |owner| record.ro.pub_key.address() == *owner)
+        })
+        .collect::<Vec<_>>();
+    let uids = records.iter().map(|record| record.uid).collect::<Vec<_>>();
+    let (commitment, mut paths) = wallet.record_merkle_paths(&uids).await;
+    let records = records
+        .into_iter()
+        .map(|record| {
+            let merkle_path = paths.remove(&record.uid).ok_or_else(|| {
+                server_error(CapeAPIError::Internal {
+                    msg: format!("missing Merkle path for record {}", record.uid),
+                })
+            })?;
+            Ok(ExportedRecord {
+                ro: record.ro,
+                uid: record.uid,
+                merkle_path,
+            })
+        })
+        .collect::<Result<_, tide::Error>>()?;
+    Ok(RecordBundle {
+        asset,
+        commitment,
+        records,
+    })
+}
+
+async fn importrecords(
+    req: &mut Request<WebState>,
+    rng: &mut ChaChaRng,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<Record>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let bundle = request_body::<RecordBundle, _>(req).await?;
+
+    // The paths must be valid against the tree the wallet has scanned, or the wallet could not use
+    // them to build transactions.
+    let (commitment, _, _) = wallet.record_merkle_frontier().await;
+    if bundle.commitment != commitment {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("records exported at the wallet's record Merkle root"),
+            actual: String::from("records exported at a different root"),
+        }));
+    }
+
+    // Check every record before importing any, so that a bad bundle is not partially imported.
+    let mut records = vec![];
+    for record in bundle.records {
+        let comm = RecordCommitment::from(&record.ro);
+        if record.ro.asset_def.code != bundle.asset
+            || MerkleTree::check_proof(
+                commitment.root_value,
+                record.uid,
+                &MerkleLeafProof::new(comm.to_field_element(), record.merkle_path.clone()),
+            )
+            .is_err()
+        {
+            return Err(server_error(CapeAPIError::Param {
+                expected: format!("a record of {} with a valid Merkle path", bundle.asset),
+                actual: format!("an invalid record {}", record.uid),
+            }));
+        }
+        let key_pair = wallet
+            .get_user_private_key(&record.ro.pub_key.address())
+            .await
+            .map_err(|_| {
+                server_error(CapeAPIError::Param {
+                    expected: String::from("a record owned by one of the wallet's sending keys"),
+                    actual: format!("record {} owned by {}", record.uid, record.ro.pub_key),
+                })
+            })?;
+        records.push((record, comm, key_pair));
+    }
+
+    let mut imported = vec![];
+    for (record, comm, key_pair) in records {
+        if wallet.records().await.any(|owned| owned.uid == record.uid) {
+            continue;
+        }
+        let memo = ReceiverMemo::from_ro(rng, &record.ro, &[]).map_err(|err| {
+            server_error(CapeAPIError::Internal {
+                msg: format!("failed to encrypt memo for record {}: {}", record.uid, err),
+            })
+        })?;
+        wallet
+            .import_memo(memo, comm, record.uid, record.merkle_path)
+            .await
+            .map_err(wallet_error)?;
+        imported.push(Record::from(RecordInfo::new(
+            record.ro, record.uid, &key_pair,
+        )));
+    }
+    Ok(imported)
+}
+
+/// The records of `wallet` quarantined by `quarantine`, with the reason each is quarantined and
+/// whether it has been rejected.
+async fn quarantined_records(
+    wallet: &Wallet,
+    quarantine: Option<&WalletQuarantine>,
+) -> Vec<(RecordInfo, QuarantineReason, bool)> {
+    match quarantine {
+        Some(quarantine) => wallet
+            .records()
+            .await
+            .filter_map(|record| {
+                let (reason, rejected) = quarantine.check(&record)?;
+                Some((record, reason, rejected))
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+async fn quarantine(
+    method: Method,
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    quarantine: &Mutex<Quarantine>,
+    wallet: &mut Option<Wallet>,
+) -> Result<QuarantineReport, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut quarantine = quarantine.lock().await;
+
+    match route_params {
+        [""] => {}
+        ["threshold" | "flag" | "unflag", ..] => {
+            if method != Method::Post {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("POST request to change the quarantine policy"),
+                    actual: method.to_string(),
+                }));
+            }
+            let asset = bindings[":asset"].value.to::<AssetCode>()?;
+            let policy = quarantine.get_mut(&wallet_path);
+            match route_params[0] {
+                "threshold" => policy.set_threshold(asset, bindings[":amount"].value.as_u128()?),
+                "flag" => policy.flag(asset, true),
+                _ => policy.flag(asset, false),
+            }
+            quarantine.save(&options.quarantine_path())?;
+        }
+        [_, action] => {
+            let uid = bindings[":uid"].value.as_u64()?;
+            if !quarantined_records(wallet, quarantine.get(&wallet_path))
+                .await
+                .iter()
+                .any(|(record, _, rejected)| record.uid == uid && !rejected)
+            {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("the uid of a quarantined record"),
+                    actual: uid.to_string(),
+                }));
+            }
+            let policy = quarantine.get_mut(&wallet_path);
+            if *action == "release" {
+                policy.release(uid);
+            } else {
+                policy.reject(uid);
+            }
+            quarantine.save(&options.quarantine_path())?;
+        }
+        _ => unreachable!(),
+    }
+
+    let policy = quarantine.get(&wallet_path);
+    let mut flagged = policy
+        .map(|policy| policy.flagged.iter().copied().collect::<Vec<_>>())
+        .unwrap_or_default();
+    flagged.sort_by_key(|asset| asset.to_string());
+    let mut records = quarantined_records(wallet, policy)
+        .await
+        .into_iter()
+        .filter(|(_, _, rejected)| !rejected)
+        .map(|(record, reason, _)| QuarantinedRecord {
+            uid: record.uid,
+            address: record.ro.pub_key.address().into(),
+            asset: record.ro.asset_def.code,
+            amount: U256::from(record.ro.amount.generic_into::<u128>()),
+            reason: reason.as_str().to_string(),
+        })
+        .collect::<Vec<_>>();
+    records.sort_by_key(|record| record.uid);
+    Ok(QuarantineReport {
+        thresholds: policy
+            .map(|policy| {
+                policy
+                    .thresholds
+                    .iter()
+                    .map(|(asset, threshold)| (*asset, U256::from(*threshold)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        flagged,
+        records,
+    })
+}
+
 async fn newkey(
     route_params: &[&str],
     bindings: &HashMap<String, RouteBinding>,
@@ -778,29 +2421,236 @@ async fn newasset(
     Ok(asset)
 }
 
-async fn buildsponsor(
-    bindings: &HashMap<String, RouteBinding>,
+async fn newassets(
+    req: &mut Request<WebState>,
     wallet: &mut Option<Wallet>,
-) -> Result<(sol::AssetDefinition, String), tide::Error> {
+) -> Result<Vec<AssetDefinition>, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let symbol = match bindings.get(":symbol") {
-        Some(param) => param.value.as_string()?,
-        None => String::new(),
-    };
-    let description = match bindings.get(":description") {
-        Some(param) => param.value.as_string()?,
-        None => String::new(),
-    };
+    let assets = request_body::<Vec<NewAsset>, _>(req).await?;
 
-    // Construct the asset policy.
-    let mut policy = AssetPolicy::default();
-    if let Some(freezing_key) = bindings.get(":freezing_key") {
-        policy = policy.set_freezer_pub_key(freezing_key.value.to::<FreezerPubKey>()?)
-    };
-    if let Some(viewing_key) = bindings.get(":viewing_key") {
-        // Always reveal blinding factor if a viewing key is given.
-        policy = policy
-            .set_auditor_pub_key(viewing_key.value.to::<AuditorPubKey>()?)
+    // Check every definition before defining any, so that an invalid one leaves the wallet
+    // unchanged.
+    let mut policies = vec![];
+    let mut errors = vec![];
+    for (i, asset) in assets.iter().enumerate() {
+        match asset.policy() {
+            Ok(policy) => policies.push(policy),
+            Err(err) => errors.push(format!("asset {}: {}", i, err)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("valid asset definitions"),
+            actual: errors.join("; "),
+        }));
+    }
+
+    let mut definitions = vec![];
+    for (asset, policy) in assets.into_iter().zip(policies) {
+        let definition = wallet
+            .define_asset(asset.symbol, asset.description.as_bytes(), policy)
+            .await?;
+        definitions.push(definition.into());
+    }
+    Ok(definitions)
+}
+
+// Check that a request carries the current admin token as a bearer token.
+fn require_admin_token(
+    req: &Request<WebState>,
+    admin_token: &Option<AdminToken>,
+) -> Result<(), tide::Error> {
+    let admin_token = admin_token.as_ref().ok_or_else(|| {
+        server_error(CapeAPIError::Unauthorized {
+            msg: String::from("authentication is not enabled"),
+        })
+    })?;
+    let token = req
+        .header("Authorization")
+        .and_then(|header| header.as_str().strip_prefix("Bearer "));
+    match token {
+        Some(token) if admin_token.verify(token) => Ok(()),
+        Some(_) => Err(server_error(CapeAPIError::Unauthorized {
+            msg: String::from("invalid admin token"),
+        })),
+        None => Err(server_error(CapeAPIError::Unauthorized {
+            msg: String::from("missing bearer token"),
+        })),
+    }
+}
+
+async fn submission(
+    req: &Request<WebState>,
+    route_params: &[&str],
+    admin_token: &Mutex<Option<AdminToken>>,
+    submission_paused: &Mutex<bool>,
+) -> Result<SubmissionStatus, tide::Error> {
+    require_admin_token(req, &*admin_token.lock().await)?;
+    let mut paused = submission_paused.lock().await;
+    *paused = route_params[0] == "pause";
+    Ok(SubmissionStatus { paused: *paused })
+}
+
+// Whether a request submits a transaction, and so must wait while submission is paused.
+fn submits_transaction(key: &ApiRouteKey, route_params: &[&str]) -> bool {
+    match key {
+        ApiRouteKey::freeze
+        | ApiRouteKey::mint
+        | ApiRouteKey::send
+        | ApiRouteKey::submitsponsor
+        | ApiRouteKey::submitwrap
+        | ApiRouteKey::sweep
+        | ApiRouteKey::unfreeze
+        | ApiRouteKey::unwrap => true,
+        // `swap/accept`
+        ApiRouteKey::swap => route_params[0] == "accept",
+        // `failed/:receipt/resend`
+        ApiRouteKey::failed => route_params.len() == 2,
+        _ => false,
+    }
+}
+
+async fn lock(
+    bindings: &HashMap<String, RouteBinding>,
+    route_params: &[&str],
+    lock: &Mutex<Option<WalletLock>>,
+    rng: &mut ChaChaRng,
+    wallet: &mut Option<Wallet>,
+) -> Result<LockStatus, tide::Error> {
+    require_wallet(wallet)?;
+    let mut lock = lock.lock().await;
+    // Locking an already locked wallet does nothing, so that the PIN cannot be replaced without
+    // unlocking the wallet first.
+    if lock.is_none() && route_params[0] != "status" {
+        let pin = match bindings.get(":pin") {
+            Some(pin) => Some(pin.value.as_string()?),
+            None => None,
+        };
+        *lock = Some(WalletLock::new(pin.as_deref(), rng));
+    }
+    Ok(LockStatus {
+        locked: lock.is_some(),
+        pin: lock.as_ref().map(WalletLock::has_pin).unwrap_or(false),
+    })
+}
+
+async fn unlock(
+    bindings: &HashMap<String, RouteBinding>,
+    lock: &Mutex<Option<WalletLock>>,
+    wallet: &mut Option<Wallet>,
+) -> Result<LockStatus, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mut lock = lock.lock().await;
+    if let Some(wallet_lock) = &mut *lock {
+        if let Some(wait) = wallet_lock.throttled() {
+            return Err(server_error(CapeAPIError::UnlockThrottled {
+                retry_in_secs: wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+            }));
+        }
+        let unlocked = if let Some(pin) = bindings.get(":pin") {
+            wallet_lock.check_pin(&pin.value.as_string()?)
+        } else {
+            let mnemonic = bindings[":mnemonic"].value.as_string()?.replace('-', " ");
+            is_wallet_mnemonic(wallet, &mnemonic).await
+        };
+        if !unlocked {
+            wallet_lock.failed();
+            return Err(server_error(CapeAPIError::Unauthorized {
+                msg: String::from("incorrect PIN or mnemonic"),
+            }));
+        }
+    }
+    *lock = None;
+    Ok(LockStatus {
+        locked: false,
+        pin: false,
+    })
+}
+
+// Whether `mnemonic` is the mnemonic of `wallet`.
+//
+// Compares the first sub-account key derived from the mnemonic with the wallet's, as `mergewallet`
+// does. A phrase which is not a valid mnemonic does not match.
+async fn is_wallet_mnemonic(wallet: &Wallet, mnemonic: &str) -> bool {
+    match Mnemonic::from_phrase(mnemonic) {
+        Ok(mnemonic) => {
+            derive_subaccount_key(&wallet_key_tree(&mnemonic), 0).pub_key()
+                == wallet.subaccount_key(0).await.pub_key()
+        }
+        Err(_) => false,
+    }
+}
+
+async fn verifymnemonic(
+    req: &mut Request<WebState>,
+    wallet: &mut Option<Wallet>,
+) -> Result<bool, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mnemonic = request_body::<String, _>(req).await?;
+    Ok(is_wallet_mnemonic(wallet, mnemonic.replace('-', " ").trim()).await)
+}
+
+async fn authstatus(
+    req: &Request<WebState>,
+    admin_token: &Mutex<Option<AdminToken>>,
+) -> Result<AuthStatus, tide::Error> {
+    let admin_token = admin_token.lock().await;
+    if admin_token.is_none() {
+        return Ok(AuthStatus {
+            enabled: false,
+            token_age_secs: None,
+        });
+    }
+    require_admin_token(req, &admin_token)?;
+    Ok(AuthStatus {
+        enabled: true,
+        token_age_secs: admin_token.as_ref().map(|token| token.age().as_secs()),
+    })
+}
+
+async fn authrotate(
+    req: &Request<WebState>,
+    rng: &mut ChaChaRng,
+    options: &NodeOpt,
+    admin_token: &Mutex<Option<AdminToken>>,
+) -> Result<String, tide::Error> {
+    if req.method() != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to rotate the admin token"),
+            actual: req.method().to_string(),
+        }));
+    }
+    let mut admin_token = admin_token.lock().await;
+    require_admin_token(req, &admin_token)?;
+    let (token, new_admin_token) = AdminToken::generate(rng);
+    new_admin_token.save(&options.admin_token_path())?;
+    *admin_token = Some(new_admin_token);
+    Ok(token)
+}
+
+async fn buildsponsor(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<(sol::AssetDefinition, String), tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let symbol = match bindings.get(":symbol") {
+        Some(param) => param.value.as_string()?,
+        None => String::new(),
+    };
+    let description = match bindings.get(":description") {
+        Some(param) => param.value.as_string()?,
+        None => String::new(),
+    };
+
+    // Construct the asset policy.
+    let mut policy = AssetPolicy::default();
+    if let Some(freezing_key) = bindings.get(":freezing_key") {
+        policy = policy.set_freezer_pub_key(freezing_key.value.to::<FreezerPubKey>()?)
+    };
+    if let Some(viewing_key) = bindings.get(":viewing_key") {
+        // Always reveal blinding factor if a viewing key is given.
+        policy = policy
+            .set_auditor_pub_key(viewing_key.value.to::<AuditorPubKey>()?)
             .reveal_blinding_factor()?;
 
         // Only if a viewing key is given, can amount and user address be revealed and viewing
@@ -846,6 +2696,8 @@ async fn buildsponsor(
 async fn submitsponsor(
     req: &mut Request<WebState>,
     bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    sponsors: &Mutex<Sponsors>,
     wallet: &mut Option<Wallet>,
 ) -> Result<AssetInfo, tide::Error> {
     let wallet = require_wallet(wallet)?;
@@ -864,10 +2716,50 @@ async fn submitsponsor(
         .submit_sponsor(erc20_code.into(), sponsor.into(), &asset)
         .await
         .map_err(wallet_error)?;
+    // The contract does not record the sponsor, so remember it for `assets/byissuer`.
+    let mut sponsors = sponsors.lock().await;
+    sponsors.set(asset.code, sponsor);
+    sponsors.save(&options.sponsors_path())?;
 
     Ok(AssetInfo::from_info(wallet, info).await)
 }
 
+async fn viewingcapability(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    rng: &mut ChaChaRng,
+    viewing_capabilities: &Mutex<ViewingCapabilities>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ViewingCapability, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut viewing_capabilities = viewing_capabilities.lock().await;
+    let capability = match bindings.get(":id") {
+        Some(id) => {
+            let id = id.value.as_string()?;
+            viewing_capabilities
+                .revoke(&wallet_path, &id)
+                .ok_or_else(|| {
+                    server_error(CapeAPIError::NotFound {
+                        msg: format!("viewing capability {} was not granted by this wallet", id),
+                    })
+                })?
+        }
+        None => {
+            let ViewingCapabilityRequest {
+                scope,
+                expires_in_secs,
+            } = request_body(req).await?;
+            // Only capabilities for assets we can view ourselves can be granted.
+            asset_viewing_key(wallet, scope.asset).await?;
+            viewing_capabilities.create(&wallet_path, scope, expires_in_secs, rng)
+        }
+    };
+    viewing_capabilities.save(&options.viewing_capabilities_path())?;
+    Ok(capability)
+}
+
 async fn waitforsponsor(
     req: &mut Request<WebState>,
     bindings: &HashMap<String, RouteBinding>,
@@ -910,413 +2802,4071 @@ async fn buildwrap(
 async fn submitwrap(
     req: &mut Request<WebState>,
     bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    conversions: &Mutex<Conversions>,
+    eth_links: &Mutex<EthLinks>,
     wallet: &mut Option<Wallet>,
 ) -> Result<(), tide::Error> {
     let wallet = require_wallet(wallet)?;
 
     let eth_address: Address = bindings[":eth_address"].value.as_string()?.parse()?;
+    if options.require_eth_link && !eth_links.lock().await.is_linked(&eth_address) {
+        return Err(server_error(CapeAPIError::EthOwnershipFailed {
+            msg: format!(
+                "{:?} has not been linked; prove ownership with linketh before wrapping",
+                eth_address
+            ),
+        }));
+    }
     let ro = JfRecordOpening::from(request_body::<sol::RecordOpening, _>(req).await?);
+    let conversion = Conversion {
+        direction: ConversionDirection::Wrap,
+        asset: ro.asset_def.code,
+        amount: ro.amount.generic_into::<u128>().into(),
+        address: ro.pub_key.address().into(),
+        eth_address,
+        cape_receipt: ConversionReceipt::Wrap(RecordCommitment::from(&ro)),
+        eth_tx_hash: None,
+        status: String::from("pending"),
+    };
 
-    Ok(wallet.submit_wrap(eth_address.into(), ro).await?)
+    let hash = wallet.submit_wrap(eth_address.into(), ro).await?;
+    let mut conversions = conversions.lock().await;
+    conversions.push(Conversion {
+        eth_tx_hash: Some(hash),
+        ..conversion
+    });
+    conversions.save(&options.conversions_path())?;
+    Ok(())
 }
 
-async fn mint(
-    bindings: &HashMap<String, RouteBinding>,
-    wallet: &mut Option<Wallet>,
-) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
-    let wallet = require_wallet(wallet)?;
-
-    let asset = bindings
-        .get(":asset")
-        .expect("mint must have ':asset' parameter")
-        .value
-        .to::<AssetCode>()?;
-    let amount = bindings
-        .get(":amount")
-        .expect("mint must have ':amount' parameter")
-        .value
-        .as_u128()?;
-    let fee = bindings
-        .get(":fee")
-        .expect("mint must have ':fee' parameter")
-        .value
-        .as_u128()?;
-    let minter = match bindings.get(":minter") {
-        Some(param) => Some(param.value.to::<UserAddress>()?.0),
-        None => None,
-    };
-    let recipient = bindings
-        .get(":recipient")
-        .expect("mint must have ':recipient' parameter")
-        .value
-        .to::<UserAddress>()?
-        .0;
-
-    Ok(wallet
-        .mint(minter.as_ref(), fee, &asset, amount, recipient)
-        .await?)
+async fn listethlinks(eth_links: &Mutex<EthLinks>) -> Result<Vec<LinkedEthAddress>, tide::Error> {
+    Ok(eth_links.lock().await.list())
 }
 
-async fn unwrap(
+async fn ethchallenge(
     bindings: &HashMap<String, RouteBinding>,
-    wallet: &mut Option<Wallet>,
-) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
-    let wallet = require_wallet(wallet)?;
+    rng: &mut ChaChaRng,
+    eth_links: &Mutex<EthLinks>,
+) -> Result<String, tide::Error> {
+    let eth_address: Address = bindings[":eth_address"].value.as_string()?.parse()?;
+    Ok(eth_links.lock().await.challenge(eth_address, rng))
+}
 
-    let source = match bindings.get(":source") {
-        Some(param) => Some(param.value.to::<UserAddress>()?.0),
-        None => None,
-    };
+async fn linketh(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    eth_links: &Mutex<EthLinks>,
+) -> Result<LinkedEthAddress, tide::Error> {
+    if method != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to link an Ethereum address"),
+            actual: method.to_string(),
+        }));
+    }
     let eth_address: Address = bindings[":eth_address"].value.as_string()?.parse()?;
-    let asset = bindings[":asset"].value.to::<AssetCode>()?;
-    let amount = bindings[":amount"].value.as_u128()?;
-    let fee = bindings[":fee"].value.as_u128()?;
+    let signature = bindings[":signature"].value.as_string()?;
+    let signature: Signature = signature.parse().map_err(|_| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("hex-encoded Ethereum signature"),
+            actual: signature.clone(),
+        })
+    })?;
 
-    Ok(wallet
-        .burn(source.as_ref(), eth_address.into(), &asset, amount, fee)
-        .await?)
+    let mut eth_links = eth_links.lock().await;
+    let link = eth_links
+        .link(eth_address, &signature)
+        .map_err(|msg| server_error(CapeAPIError::EthOwnershipFailed { msg }))?;
+    eth_links.save(&options.eth_links_path())?;
+    Ok(link)
 }
 
-async fn recoverkey(
+async fn fsck(
     route_params: &[&str],
-    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    cache: &Mutex<ResponseCache>,
+    balance_index: &Mutex<BalanceIndex>,
     wallet: &mut Option<Wallet>,
-) -> Result<PubKey, tide::Error> {
+) -> Result<FsckReport, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let description = match bindings.get(":description") {
-        Some(param) => param.value.as_string()?,
-        None => String::new(),
-    };
+    let repair = route_params[0] == "repair";
+    if repair && !options.enable_fsck_repair {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("server started with --enable-fsck-repair"),
+            actual: String::from("fsck/repair"),
+        }));
+    }
 
-    match route_params[0] {
-        "send" | "sending" => {
-            let scan_from = match bindings.get(":scan_from") {
-                Some(param) => param.value.as_usize()?,
-                None => 0,
-            };
-            Ok(PubKey::Sending(
+    let records = wallet.records().await.collect::<Vec<_>>();
+    let addresses = wallet
+        .pub_keys()
+        .await
+        .into_iter()
+        .map(|pub_key| UserAddress::from(pub_key.address()))
+        .collect::<Vec<_>>();
+    let assets = wallet
+        .assets()
+        .await
+        .into_iter()
+        .map(|asset| asset.definition.code)
+        .collect::<HashSet<_>>();
+    let mut balances = HashMap::new();
+    for address in &addresses {
+        for asset in assets
+            .iter()
+            .chain(records.iter().map(|record| &record.ro.asset_def.code))
+        {
+            balances.insert(
+                (address.clone(), *asset),
                 wallet
-                    .generate_user_key(
-                        description,
-                        Some(EventIndex::from_source(
-                            EventSource::QueryService,
-                            scan_from,
-                        )),
-                    )
-                    .await?,
-            ))
+                    .balance_breakdown(&address.clone().into(), asset)
+                    .await,
+            );
         }
-        "view" | "viewing" => Ok(PubKey::Viewing(
-            wallet.generate_audit_key(description).await?,
-        )),
-        "freeze" | "freezing" => Ok(PubKey::Freezing(
-            wallet.generate_freeze_key(description).await?,
-        )),
-        key_type => Err(server_error(CapeAPIError::Param {
-            expected: String::from("key type (sending, viewing or freezing)"),
-            actual: String::from(key_type),
-        })),
     }
-}
 
-pub async fn send(
-    bindings: &HashMap<String, RouteBinding>,
-    wallet: &mut Option<Wallet>,
-) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
-    let wallet = require_wallet(wallet)?;
+    let mut report = FsckReport::check(&records, &addresses, &assets, &balances);
 
-    let dst = bindings
-        .get(":recipient")
-        .expect("send must have ':recipient' parameter")
-        .value
-        .to::<UserAddress>()?;
-    let asset = bindings
-        .get(":asset")
-        .expect("send must have ':asset' parameter")
-        .value
-        .to::<AssetCode>()?;
-    let amount = bindings
-        .get(":amount")
-        .expect("send must have ':amount' parameter")
-        .value
-        .as_u128()?;
-    let fee = bindings
-        .get(":fee")
-        .expect("send must have ':fee' parameter")
-        .value
-        .as_u128()?;
+    // The balance index must agree with the balances reported by the wallet, except that the wallet
+    // counts replayed records, which the index deliberately leaves out.
+    let mut balance_index = balance_index.lock().await;
+    let wallet_path = read_last_path(options).await?.unwrap_or_default();
+    let generation = cache.lock().await.generation();
+    balance_index.update(wallet, &wallet_path, generation).await;
+    let accounts = balances
+        .keys()
+        .chain(balance_index.balances().keys())
+        .cloned()
+        .collect::<HashSet<_>>();
+    for (address, asset) in accounts {
+        let indexed = balance_index.balance(&address, &asset);
+        let balance = balances
+            .get(&(address.clone(), asset))
+            .cloned()
+            .unwrap_or_default()
+            .saturating_sub(balance_index.replayed_balance(&address, &asset));
+        if indexed != balance {
+            report.anomalies.push(FsckAnomaly::StaleBalanceIndex {
+                address,
+                asset,
+                indexed,
+                balance,
+            });
+        }
+    }
 
-    match bindings.get(":sender") {
-        Some(addr) => wallet
-            .transfer(
-                Some(&addr.value.to::<UserAddress>()?.into()),
-                &asset,
-                &[(dst.into(), amount)],
-                fee,
-            )
-            .await
-            .map_err(wallet_error),
-        None => wallet
-            .transfer(None, &asset, &[(dst.into(), amount)], fee)
-            .await
-            .map_err(wallet_error),
+    if repair {
+        let (repairable, anomalies) = report
+            .anomalies
+            .into_iter()
+            .partition::<Vec<_>, _>(FsckAnomaly::is_repairable);
+        report.anomalies = anomalies;
+        for anomaly in repairable {
+            if let FsckAnomaly::StaleBalanceIndex { .. } = &anomaly {
+                balance_index.reset();
+            }
+            if let FsckAnomaly::UnknownAsset { asset } = &anomaly {
+                // The record which references the unknown asset carries its full definition, so
+                // we can restore the missing library entry from there.
+                if let Some(record) = records
+                    .iter()
+                    .find(|record| record.ro.asset_def.code == *asset)
+                {
+                    wallet
+                        .import_asset(record.ro.asset_def.clone().into())
+                        .await
+                        .map_err(wallet_error)?;
+                }
+            }
+            report.repaired.push(anomaly);
+        }
     }
+    Ok(report)
 }
 
-pub async fn get_records(wallet: &mut Option<Wallet>) -> Result<Vec<RecordInfo>, tide::Error> {
-    let wallet = require_wallet(wallet)?;
-    Ok(wallet.records().await.collect::<Vec<_>>())
+async fn gaplimit(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    gap_limit: &Mutex<usize>,
+) -> Result<usize, tide::Error> {
+    let mut gap_limit = gap_limit.lock().await;
+    if let Some(n) = bindings.get(":n") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to change the gap limit"),
+                actual: method.to_string(),
+            }));
+        }
+        *gap_limit = n.value.as_usize()?;
+    }
+    Ok(*gap_limit)
 }
 
-pub async fn get_last_keystore(options: &NodeOpt) -> Result<Option<KeyStoreLocation>, tide::Error> {
-    Ok(read_last_path(options).await?.map(KeyStoreLocation::from))
+async fn features(options: &NodeOpt) -> Result<Features, tide::Error> {
+    let mut features = Features::default();
+    for route in ApiRouteKey::iter() {
+        if options.route_disabled(&route) {
+            features.disabled.push(route.to_string());
+        } else {
+            features.enabled.push(route.to_string());
+        }
+    }
+    Ok(features)
 }
 
-async fn getaccount(
+async fn errordetail(
+    method: Method,
     bindings: &HashMap<String, RouteBinding>,
+    error_detail: &Mutex<ErrorDetail>,
+) -> Result<ErrorDetail, tide::Error> {
+    let mut error_detail = error_detail.lock().await;
+    if let Some(level) = bindings.get(":level") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to change the error detail level"),
+                actual: method.to_string(),
+            }));
+        }
+        let level = level.value.as_string()?;
+        *error_detail = level.parse().map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("error detail level (terse or verbose)"),
+                actual: level,
+            })
+        })?;
+    }
+    Ok(*error_detail)
+}
+
+async fn confirmations(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
     wallet: &mut Option<Wallet>,
-) -> Result<Account, tide::Error> {
+) -> Result<usize, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let address = bindings[":address"].value.clone();
-    match address.as_identifier()?.tag().as_str() {
-        "ADDR" => Ok(Account::from_info(
-            wallet,
-            wallet
-                .sending_account(&address.to::<UserAddress>()?.0)
-                .await?,
+    let wallet_path = require_wallet_path(options).await?;
+    let n = match bindings.get(":n") {
+        Some(n) => n,
+        None => return Ok(confirmations.lock().await.depth(&wallet_path)),
+    };
+    if method != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to change the confirmation depth"),
+            actual: method.to_string(),
+        }));
+    }
+    let depth = n.value.as_usize()?;
+    if depth == 0 {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a positive confirmation depth"),
+            actual: depth.to_string(),
+        }));
+    }
+
+    // Transactions which are already included when a depth is first configured stay final.
+    let mut included = vec![];
+    for entry in wallet.transaction_history().await.map_err(wallet_error)? {
+        let hash = match entry.hash.as_ref().and_then(transaction_hash_string) {
+            Some(hash) => hash,
+            None => continue,
+        };
+        let retired = match &entry.receipt {
+            Some(receipt) => matches!(
+                wallet.transaction_status(receipt).await,
+                Ok(TransactionStatus::Retired)
+            ),
+            None => true,
+        };
+        if retired {
+            included.push(hash);
+        }
+    }
+    let mut confirmations = confirmations.lock().await;
+    confirmations.set_depth(&wallet_path, depth, included);
+    confirmations.save(&options.confirmations_path())?;
+    Ok(depth)
+}
+
+async fn scanconfig(
+    method: Method,
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    scan_interval: &ScanInterval,
+) -> Result<ScanConfig, tide::Error> {
+    if !route_params[0].is_empty() {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to change the scan configuration"),
+                actual: method.to_string(),
+            }));
+        }
+        match route_params[0] {
+            "interval" => {
+                let ms = bindings[":ms"].value.as_u64()?;
+                if ms == 0 {
+                    return Err(server_error(CapeAPIError::Param {
+                        expected: String::from("positive scan interval"),
+                        actual: ms.to_string(),
+                    }));
+                }
+                scan_interval.set(Some(Duration::from_millis(ms)));
+            }
+            "disable" => scan_interval.set(None),
+            param => {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("interval or disable"),
+                    actual: String::from(param),
+                }))
+            }
+        }
+    }
+    Ok(ScanConfig::from(scan_interval.get()))
+}
+
+/// How long scanning stays paused if it is not resumed, unless a shorter pause is requested.
+const MAX_SCAN_PAUSE: Duration = Duration::from_secs(600);
+
+async fn syncstatus(
+    scan_interval: &ScanInterval,
+    wallet: &mut Option<Wallet>,
+) -> Result<SyncStatus, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let (sync_time, real_time) = wallet.scan_status().await.map_err(wallet_error)?;
+    let resumes_in = scan_interval
+        .paused_until()
+        .map(|until| until.saturating_duration_since(Instant::now()));
+    Ok(SyncStatus {
+        synced_to: sync_time.index(EventSource::QueryService),
+        real_time: real_time.index(EventSource::QueryService),
+        paused: resumes_in.is_some(),
+        resumes_in_ms: resumes_in.map(|resumes_in| resumes_in.as_millis() as u64),
+    })
+}
+
+async fn scan(
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    scan_interval: &ScanInterval,
+    wallet: &mut Option<Wallet>,
+) -> Result<SyncStatus, tide::Error> {
+    require_wallet(wallet)?;
+    match route_params[0] {
+        "pause" => {
+            let timeout = match bindings.get(":seconds") {
+                Some(seconds) => {
+                    let seconds = seconds.value.as_u64()?;
+                    if seconds == 0 || seconds > MAX_SCAN_PAUSE.as_secs() {
+                        return Err(server_error(CapeAPIError::Param {
+                            expected: format!(
+                                "pause between 1 and {} seconds",
+                                MAX_SCAN_PAUSE.as_secs()
+                            ),
+                            actual: seconds.to_string(),
+                        }));
+                    }
+                    Duration::from_secs(seconds)
+                }
+                None => MAX_SCAN_PAUSE,
+            };
+            scan_interval.pause(timeout);
+        }
+        "resume" => scan_interval.resume(),
+        _ => unreachable!(),
+    }
+    syncstatus(scan_interval, wallet).await
+}
+
+/// How long `reconnect` waits for the wallet to process the events it missed.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often `reconnect` checks whether the wallet has caught up.
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+async fn reconnect(
+    scan_interval: &ScanInterval,
+    wallet: &mut Option<Wallet>,
+) -> Result<Reconnection, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    if scan_interval.get().is_none() {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("polling to be enabled with scanconfig"),
+            actual: String::from("polling disabled"),
+        }));
+    }
+    let resync = wallet.reconnect().await.map_err(wallet_error)?;
+    let (cursor, real_time) = wallet.scan_status().await.map_err(wallet_error)?;
+    let mut reconnection = Reconnection {
+        catch_up: CatchUp::Incremental,
+        cursor: cursor.index(EventSource::QueryService),
+        synced_to: cursor.index(EventSource::QueryService),
+        real_time: real_time.index(EventSource::QueryService),
+        events: 0,
+        blocks: 0,
+        reason: None,
+    };
+    if let Some(reason) = resync {
+        // Scanning on from the cursor would build on events the ledger no longer has.
+        reconnection.catch_up = CatchUp::FullResync;
+        reconnection.reason = Some(reason);
+        return Ok(reconnection);
+    }
+
+    // The wallet's event stream resumes from the cursor, so it only has to be allowed to run.
+    scan_interval.resume();
+    let start = Instant::now();
+    let mut synced_to = cursor;
+    while synced_to.index(EventSource::QueryService) < reconnection.real_time {
+        if start.elapsed() >= RECONNECT_TIMEOUT {
+            return Err(server_error(CapeAPIError::Internal {
+                msg: format!(
+                    "timed out catching up with the ledger: synced to event {} of {}",
+                    synced_to.index(EventSource::QueryService),
+                    reconnection.real_time
+                ),
+            }));
+        }
+        async_std::task::sleep(RECONNECT_POLL_INTERVAL).await;
+        synced_to = wallet.now().await;
+    }
+    reconnection.synced_to = synced_to.index(EventSource::QueryService);
+    reconnection.events = reconnection.synced_to - reconnection.cursor;
+    reconnection.blocks = wallet
+        .blocks_committed(cursor, synced_to)
+        .await
+        .map_err(wallet_error)?;
+    Ok(reconnection)
+}
+
+async fn scangaps(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    autoretry: &Mutex<AutoRetry>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ScanGaps, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut autoretry = autoretry.lock().await;
+    if let Some(enabled) = bindings.get(":enabled") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to change the auto-retry policy"),
+                actual: method.to_string(),
+            }));
+        }
+        autoretry.set(&wallet_path, enabled.value.as_boolean()?);
+        autoretry.save(&options.autoretry_path())?;
+    }
+    let skipped = wallet
+        .skipped_events()
+        .await
+        .map_err(wallet_error)?
+        .into_iter()
+        .map(SkippedEvent::from)
+        .collect::<Vec<_>>();
+    Ok(ScanGaps {
+        gaps: ScanGap::from_events(&skipped),
+        ..autoretry.get(&wallet_path)
+    })
+}
+
+async fn proofconfig(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    proof_threads: &Mutex<ProofThreads>,
+) -> Result<ProofConfig, tide::Error> {
+    let mut proof_threads = proof_threads.lock().await;
+    if let Some(n) = bindings.get(":n") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to change the proof configuration"),
+                actual: method.to_string(),
+            }));
+        }
+        let threads = n.value.as_usize()?;
+        if threads == 0 {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("positive number of threads"),
+                actual: threads.to_string(),
+            }));
+        }
+        proof_threads.set_threads(threads)?;
+    }
+    Ok(proof_threads.config())
+}
+
+/// Parse a spending limit window: `hour`, `day`, `week`, or a number of seconds.
+fn parse_period(period: &str) -> Result<Duration, tide::Error> {
+    let secs = match period {
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        secs => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => secs,
+            _ => {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("hour, day, week, or a positive number of seconds"),
+                    actual: String::from(period),
+                }))
+            }
+        },
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+async fn spendlimit(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+) -> Result<Vec<SpendLimitInfo>, tide::Error> {
+    let mut spend_limits = spend_limits.lock().await;
+    if let Some(asset) = bindings.get(":asset") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to set a spending limit"),
+                actual: method.to_string(),
+            }));
+        }
+        let wallet_path = require_wallet_path(options).await?;
+        let asset = asset.value.to::<AssetCode>()?;
+        let amount = bindings[":amount"].value.as_u128()?;
+        let period = parse_period(&bindings[":period"].value.as_string()?)?;
+        spend_limits.set(&wallet_path, asset, amount, period);
+        spend_limits.save(&options.spend_limits_path())?;
+    }
+    // Without a wallet, there are no limits to list.
+    Ok(match read_last_path(options).await? {
+        Some(wallet_path) => spend_limits.list(&wallet_path),
+        None => vec![],
+    })
+}
+
+async fn schedule(
+    method: Method,
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    schedules: &Mutex<Schedules>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ScheduledTransfer, tide::Error> {
+    let mut schedules = schedules.lock().await;
+    let schedule = match route_params[0] {
+        "transfer" => {
+            if method != Method::Post {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("POST request to schedule a transfer"),
+                    actual: method.to_string(),
+                }));
+            }
+            // Scheduled transfers are made from the open wallet, so require one now rather than
+            // discovering there isn't one when the transfer first fires.
+            let wallet = require_wallet(wallet)?;
+            let asset = bindings[":asset"].value.to::<AssetCode>()?;
+            if wallet.asset(asset).await.is_none() {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("an asset known to the wallet"),
+                    actual: asset.to_string(),
+                }));
+            }
+            let recipient = bindings[":recipient"].value.to::<UserAddress>()?;
+            let amount = bindings[":amount"].value.as_u128()?;
+            let fee = bindings[":fee"].value.as_u128()?;
+            let interval = bindings[":interval"].value.as_u64()?;
+            if interval == 0 {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("positive interval"),
+                    actual: interval.to_string(),
+                }));
+            }
+            schedules.add(asset, recipient, amount, fee, Duration::from_secs(interval))
+        }
+        _ => {
+            let id = bindings[":id"].value.as_u64()?;
+            schedules.cancel(id).ok_or_else(|| {
+                server_error(CapeAPIError::Param {
+                    expected: String::from("id of a scheduled transfer"),
+                    actual: id.to_string(),
+                })
+            })?
+        }
+    };
+    schedules.save(&options.schedules_path())?;
+    Ok(schedule)
+}
+
+async fn listschedules(
+    schedules: &Mutex<Schedules>,
+) -> Result<Vec<ScheduledTransfer>, tide::Error> {
+    Ok(schedules.lock().await.list())
+}
+
+async fn paymentrequest(
+    method: Method,
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    rng: &mut ChaChaRng,
+    options: &NodeOpt,
+    payment_requests: &Mutex<PaymentRequests>,
+    wallet: &mut Option<Wallet>,
+) -> Result<PaymentRequest, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut requests = payment_requests.lock().await;
+
+    let request = match route_params[0] {
+        "asset" => {
+            if method != Method::Post {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("POST request to create a payment request"),
+                    actual: method.to_string(),
+                }));
+            }
+            let asset = bindings[":asset"].value.to::<AssetCode>()?;
+            if wallet.asset(asset).await.is_none() {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("an asset known to the wallet"),
+                    actual: asset.to_string(),
+                }));
+            }
+            let amount = bindings[":amount"].value.as_u128()?;
+            let expiry = bindings[":expiry"].value.as_u64()?;
+            if expiry == 0 {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("positive expiry"),
+                    actual: expiry.to_string(),
+                }));
+            }
+
+            // Payments are matched to the request by the address they are received at, so each
+            // request gets a new key.
+            let mut nonce = [0u8; 16];
+            rng.fill_bytes(&mut nonce);
+            let nonce = hex::encode(nonce);
+            let key = wallet
+                .generate_user_key(format!("payment request {}", nonce), None)
+                .await
+                .map_err(wallet_error)?;
+            requests.add(
+                &wallet_path,
+                nonce,
+                key.address().into(),
+                asset,
+                amount,
+                expiry,
+            )
+        }
+        _ => {
+            let id = bindings[":id"].value.as_u64()?;
+            let (address, asset) = match requests.get(&wallet_path, id) {
+                Some(request) => (request.address.clone(), request.asset),
+                None => {
+                    return Err(server_error(CapeAPIError::Param {
+                        expected: String::from("id of a payment request"),
+                        actual: id.to_string(),
+                    }))
+                }
+            };
+            let received = wallet
+                .records()
+                .await
+                .filter(|record| {
+                    UserAddress::from(record.ro.pub_key.address()) == address
+                        && record.ro.asset_def.code == asset
+                })
+                .fold(U256::zero(), |total, record| {
+                    total + U256::from(record.ro.amount.generic_into::<u128>())
+                });
+            requests
+                .update(&wallet_path, id, received)
+                .expect("payment request disappeared while locked")
+        }
+    };
+    requests.save(&options.payment_requests_path())?;
+    Ok(request)
+}
+
+async fn autoconsolidate(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    policies: &Mutex<AutoConsolidate>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<AutoConsolidatePolicy>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut policies = policies.lock().await;
+    if bindings.contains_key(":asset") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to set a consolidation policy"),
+                actual: method.to_string(),
+            }));
+        }
+        let asset = bindings[":asset"].value.to::<AssetCode>()?;
+        if wallet.asset(asset).await.is_none() {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("an asset known to the wallet"),
+                actual: asset.to_string(),
+            }));
+        }
+        let threshold = bindings[":threshold"].value.as_u64()?;
+        policies.set(&wallet_path, asset, threshold as usize);
+        policies.save(&options.autoconsolidate_path())?;
+    }
+    Ok(policies.list(&wallet_path))
+}
+
+async fn feetopup(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    policies: &Mutex<FeeTopUp>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Option<FeeTopUpPolicy>, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut policies = policies.lock().await;
+    if bindings.contains_key(":threshold") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to set a fee top-up policy"),
+                actual: method.to_string(),
+            }));
+        }
+        let amount = bindings[":amount"].value.as_u128()?;
+        if amount == 0 {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("a positive fee record amount"),
+                actual: amount.to_string(),
+            }));
+        }
+        let threshold = bindings[":threshold"].value.as_u64()?;
+        policies.set(&wallet_path, threshold as usize, amount);
+        policies.save(&options.feetopup_path())?;
+    }
+    Ok(policies.get(&wallet_path))
+}
+
+async fn webhook(
+    req: &mut Request<WebState>,
+    options: &NodeOpt,
+    webhooks: &Mutex<Webhooks>,
+    confirmations: &Mutex<Confirmations>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Option<WebhookInfo>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    match req.method() {
+        Method::Post => {
+            let WebhookRegistration { url, secret } = request_body(req).await?;
+            match tide::http::Url::parse(&url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => {
+                    return Err(server_error(CapeAPIError::Param {
+                        expected: String::from("an http or https URL"),
+                        actual: url,
+                    }))
+                }
+            }
+            // Only notify the webhook of transactions which settle after it is registered.
+            let settled = settled_transactions(options, confirmations, wallet, &wallet_path)
+                .await
+                .map_err(wallet_error)?
+                .into_iter()
+                .map(|settlement| settlement.hash);
+            let mut webhooks = webhooks.lock().await;
+            let info = webhooks.set(&wallet_path, url, secret, settled);
+            webhooks.save(&options.webhooks_path())?;
+            Ok(Some(info))
+        }
+        Method::Delete => {
+            let mut webhooks = webhooks.lock().await;
+            if webhooks.remove(&wallet_path) {
+                webhooks.save(&options.webhooks_path())?;
+            }
+            Ok(None)
+        }
+        _ => Ok(webhooks.lock().await.get(&wallet_path)),
+    }
+}
+
+/// Run `spend`, which spends `amount` of `asset` from the open wallet, subject to the wallet's
+/// spending limit for `asset`.
+///
+/// Every route which spends the open wallet's assets, whether it submits a transaction or exports
+/// one, goes through this, so that a limit cannot be evaded by spending some other way.
+async fn limited_spend<T>(
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    asset: &AssetCode,
+    amount: u128,
+    spend: impl Future<Output = Result<T, tide::Error>>,
+) -> Result<T, tide::Error> {
+    let wallet_path = require_wallet_path(options).await?;
+    spend_limits::limited_spend(options, spend_limits, &wallet_path, asset, amount, spend)
+        .await
+        .map_err(|err| match err {
+            SpendError::LimitExceeded { asset, remaining } => {
+                server_error(CapeAPIError::SpendLimitExceeded { asset, remaining })
+            }
+            SpendError::Failed(err) => err,
+        })
+}
+
+/// How long `feerate` reuses the minimum fee last fetched from the relayer.
+const FEE_RATE_TTL: Duration = Duration::from_secs(10);
+
+async fn feerate(
+    fee_rate: &Mutex<Option<(Instant, u64)>>,
+    wallet: &mut Option<Wallet>,
+) -> Result<FeeRate, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mut fee_rate = fee_rate.lock().await;
+    if let Some((fetched, min_fee)) = *fee_rate {
+        if fetched.elapsed() < FEE_RATE_TTL {
+            return Ok(FeeRate {
+                min_fee,
+                age_ms: fetched.elapsed().as_millis() as u64,
+                stale: false,
+            });
+        }
+    }
+    match wallet.min_fee().await {
+        Ok(min_fee) => {
+            *fee_rate = Some((Instant::now(), min_fee));
+            Ok(FeeRate {
+                min_fee,
+                age_ms: 0,
+                stale: false,
+            })
+        }
+        // If the relayer is unreachable, fall back to the last value it gave us, if any.
+        Err(err) => match *fee_rate {
+            Some((fetched, min_fee)) => Ok(FeeRate {
+                min_fee,
+                age_ms: fetched.elapsed().as_millis() as u64,
+                stale: true,
+            }),
+            None => Err(wallet_error(err)),
+        },
+    }
+}
+
+async fn congestion(
+    congestion: &Mutex<Option<(Instant, relayer::Congestion)>>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Congestion, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mut congestion = congestion.lock().await;
+    // The backlog changes with every transaction, so unlike `feerate`, always try to refresh it.
+    let (fetched, backlog, stale) = match wallet.congestion().await {
+        Ok(backlog) => {
+            *congestion = Some((Instant::now(), backlog));
+            (Instant::now(), backlog, false)
+        }
+        // If the relayer is unreachable, fall back to the last backlog it reported, if any.
+        Err(err) => match *congestion {
+            Some((fetched, backlog)) => (fetched, backlog, true),
+            None => return Err(wallet_error(err)),
+        },
+    };
+    Ok(Congestion {
+        pending: backlog.pending,
+        accepted: backlog.accepted,
+        window_secs: backlog.window_secs,
+        age_ms: fetched.elapsed().as_millis() as u64,
+        stale,
+    })
+}
+
+async fn gasestimate(
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<GasEstimate, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let amount = U256::from(bindings[":amount"].value.as_u128()?);
+    let (gas, gas_price) = match route_params[0] {
+        "wrap" => wallet.estimate_wrap_gas(&asset, amount).await?,
+        "unwrap" => {
+            let dst = match bindings.get(":eth_address") {
+                Some(address) => Some(address.value.as_string()?.parse::<Address>()?.into()),
+                None => None,
+            };
+            wallet.estimate_unwrap_gas(&asset, dst, amount).await?
+        }
+        op => {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("operation (wrap or unwrap)"),
+                actual: String::from(op),
+            }))
+        }
+    };
+    Ok(GasEstimate::new(gas, gas_price))
+}
+
+async fn mint(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    proof_threads: &Mutex<ProofThreads>,
+    trace: bool,
+    wallet: &mut Option<Wallet>,
+) -> Result<(TransactionReceipt<CapeLedger>, Option<OperationTrace>), tide::Error> {
+    require_wallet(wallet)?;
+
+    let asset = bindings
+        .get(":asset")
+        .expect("mint must have ':asset' parameter")
+        .value
+        .to::<AssetCode>()?;
+    let amount = bindings
+        .get(":amount")
+        .expect("mint must have ':amount' parameter")
+        .value
+        .as_u128()?;
+    let fee = bindings
+        .get(":fee")
+        .expect("mint must have ':fee' parameter")
+        .value
+        .as_u128()?;
+    let minter = match bindings.get(":minter") {
+        Some(param) => Some(param.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    let recipient = bindings
+        .get(":recipient")
+        .expect("mint must have ':recipient' parameter")
+        .value
+        .to::<UserAddress>()?
+        .0;
+
+    limited_spend(options, spend_limits, &asset, amount, async {
+        if trace {
+            // Minting spends only a record to pay the fee.
+            traced_submit(
+                proof_threads,
+                wallet,
+                AssetCode::native(),
+                0,
+                fee,
+                move |wallet| {
+                    async move {
+                        wallet
+                            .export_mint(minter.as_ref(), fee, &asset, amount, recipient)
+                            .await
+                            .map_err(wallet_error)
+                    }
+                    .boxed()
+                },
+            )
+            .await
+            .map(|(receipt, trace)| (receipt, Some(trace)))
+        } else {
+            proof_threads::build(proof_threads, wallet, move |wallet| {
+                async move {
+                    wallet
+                        .mint(minter.as_ref(), fee, &asset, amount, recipient)
+                        .await
+                        .map_err(tide::Error::from)
+                }
+                .boxed()
+            })
+            .await
+            .map(|receipt| (receipt, None))
+        }
+    })
+    .await
+}
+
+async fn unwrap(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    conversions: &Mutex<Conversions>,
+    proof_threads: &Mutex<ProofThreads>,
+    trace: bool,
+    wallet: &mut Option<Wallet>,
+) -> Result<(TransactionReceipt<CapeLedger>, Option<OperationTrace>), tide::Error> {
+    require_wallet(wallet)?;
+
+    let source = match bindings.get(":source") {
+        Some(param) => Some(param.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    let eth_address: Address = bindings[":eth_address"].value.as_string()?.parse()?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let amount = bindings[":amount"].value.as_u128()?;
+    let fee = bindings[":fee"].value.as_u128()?;
+
+    let burner = source.clone();
+    let (receipt, trace) = limited_spend(options, spend_limits, &asset, amount, async {
+        if trace {
+            traced_submit(proof_threads, wallet, asset, amount, fee, move |wallet| {
+                async move {
+                    wallet
+                        .export_burn(burner.as_ref(), eth_address.into(), &asset, amount, fee)
+                        .await
+                        .map_err(wallet_error)
+                }
+                .boxed()
+            })
+            .await
+            .map(|(receipt, trace)| (receipt, Some(trace)))
+        } else {
+            proof_threads::build(proof_threads, wallet, move |wallet| {
+                async move {
+                    wallet
+                        .burn(burner.as_ref(), eth_address.into(), &asset, amount, fee)
+                        .await
+                        .map_err(tide::Error::from)
+                }
+                .boxed()
+            })
+            .await
+            .map(|receipt| (receipt, None))
+        }
+    })
+    .await?;
+    let mut conversions = conversions.lock().await;
+    conversions.push(Conversion {
+        direction: ConversionDirection::Unwrap,
+        asset,
+        amount: amount.into(),
+        address: source
+            .unwrap_or_else(|| receipt.submitters[0].clone())
+            .into(),
+        eth_address,
+        cape_receipt: ConversionReceipt::Unwrap(receipt.clone()),
+        eth_tx_hash: None,
+        status: String::from("pending"),
+    });
+    conversions.save(&options.conversions_path())?;
+    Ok((receipt, trace))
+}
+
+async fn conversions(
+    options: &NodeOpt,
+    conversions: &Mutex<Conversions>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<Conversion>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let addresses = wallet
+        .pub_keys()
+        .await
+        .into_iter()
+        .map(|pub_key| pub_key.address())
+        .collect::<HashSet<_>>();
+    let commitments = wallet
+        .records()
+        .await
+        .map(|record| RecordCommitment::from(&record.ro))
+        .collect::<HashSet<_>>();
+
+    let mut conversions = conversions.lock().await;
+    let mut changed = false;
+    let mut ours = vec![];
+    for conversion in conversions.iter_mut() {
+        if !addresses.contains(&conversion.address.0) {
+            continue;
+        }
+        match &conversion.cape_receipt {
+            // A wrapped record may be spent by the time we look for it, so once we have seen it
+            // we remember that the wrap completed.
+            ConversionReceipt::Wrap(comm) => {
+                if conversion.status != "completed" && commitments.contains(comm) {
+                    conversion.status = String::from("completed");
+                    changed = true;
+                }
+            }
+            ConversionReceipt::Unwrap(receipt) => {
+                conversion.status = match wallet.transaction_status(receipt).await {
+                    Ok(status) => status.to_string(),
+                    Err(_) => String::from("unknown"),
+                };
+            }
+        }
+        ours.push(conversion.clone());
+    }
+    if changed {
+        conversions.save(&options.conversions_path())?;
+    }
+    Ok(ours)
+}
+
+async fn recoverkey(
+    route_params: &[&str],
+    bindings: &HashMap<String, RouteBinding>,
+    gap_limit: usize,
+    wallet: &mut Option<Wallet>,
+) -> Result<PubKey, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let description = match bindings.get(":description") {
+        Some(param) => param.value.as_string()?,
+        None => String::new(),
+    };
+
+    match route_params[0] {
+        "send" | "sending" => {
+            let scan_from = match bindings.get(":scan_from") {
+                Some(param) => param.value.as_usize()?,
+                None => 0,
+            };
+            let key = wallet
+                .generate_user_key(
+                    description,
+                    Some(EventIndex::from_source(
+                        EventSource::QueryService,
+                        scan_from,
+                    )),
+                )
+                .await?;
+            if gap_limit > 0 {
+                // Keep looking for any keys which were used after this one.
+                wallet.await_key_scan(&key.address()).await?;
+                scan_ahead(wallet, gap_limit).await?;
+            }
+            Ok(PubKey::Sending(key))
+        }
+        "view" | "viewing" => Ok(PubKey::Viewing(
+            wallet.generate_audit_key(description).await?,
+        )),
+        "freeze" | "freezing" => Ok(PubKey::Freezing(
+            wallet.generate_freeze_key(description).await?,
+        )),
+        key_type => Err(server_error(CapeAPIError::Param {
+            expected: String::from("key type (sending, viewing or freezing)"),
+            actual: String::from(key_type),
+        })),
+    }
+}
+
+pub async fn send(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    sent: &Mutex<SentTransfers>,
+    min_anonymity: &Mutex<MinAnonymity>,
+    proof_threads: &Mutex<ProofThreads>,
+    trace: bool,
+    wallet: &mut Option<Wallet>,
+) -> Result<(TransactionReceipt<CapeLedger>, Option<OperationTrace>), tide::Error> {
+    require_wallet(wallet)?;
+
+    let params = TransferParams {
+        sender: bindings
+            .get(":sender")
+            .map(|addr| addr.value.to::<UserAddress>())
+            .transpose()?,
+        recipient: bindings
+            .get(":recipient")
+            .expect("send must have ':recipient' parameter")
+            .value
+            .to::<UserAddress>()?,
+        asset: bindings
+            .get(":asset")
+            .expect("send must have ':asset' parameter")
+            .value
+            .to::<AssetCode>()?,
+        amount: bindings
+            .get(":amount")
+            .expect("send must have ':amount' parameter")
+            .value
+            .as_u128()?,
+        fee: bindings
+            .get(":fee")
+            .expect("send must have ':fee' parameter")
+            .value
+            .as_u128()?,
+    };
+
+    let wallet_path = require_wallet_path(options).await?;
+    let threshold = min_anonymity.lock().await.get(&wallet_path);
+    let (receipt, trace) =
+        limited_spend(options, spend_limits, &params.asset, params.amount, async {
+            if trace {
+                traced_transfer(proof_threads, wallet, &params, threshold)
+                    .await
+                    .map(|(receipt, trace)| (receipt, Some(trace)))
+            } else {
+                transfer(proof_threads, wallet, &params, threshold)
+                    .await
+                    .map(|receipt| (receipt, None))
+            }
+        })
+        .await?;
+
+    // Remember the parameters of the transfer, in case it fails and has to be resent.
+    if let Some(hash) = transaction_hash_string(&receipt.uid.0) {
+        let mut sent = sent.lock().await;
+        sent.insert(&wallet_path, hash, params);
+        sent.save(&options.sent_path())?;
+    }
+    Ok((receipt, trace))
+}
+
+/// Check that the records `txn` spends have anonymity sets of at least `threshold`.
+async fn check_min_anonymity(
+    wallet: &Wallet,
+    txn: &CapeModelTxn,
+    threshold: u64,
+) -> Result<(), tide::Error> {
+    anonymity_set_too_small(too_small_input(wallet, txn, threshold).await, threshold)
+}
+
+/// Check that the records a transfer of `amount` of `asset` from `sender` paying `fee` is predicted
+/// to spend have anonymity sets of at least `threshold`.
+///
+/// This refuses most transfers which [check_min_anonymity] would refuse before they are built, and
+/// so before their inputs are put on hold.
+async fn check_predicted_min_anonymity(
+    wallet: &Wallet,
+    sender: Option<&jf_cap::keys::UserAddress>,
+    asset: AssetCode,
+    amount: u128,
+    fee: u128,
+    threshold: u64,
+) -> Result<(), tide::Error> {
+    anonymity_set_too_small(
+        too_small_predicted_input(wallet, sender, asset, amount, fee, threshold).await,
+        threshold,
+    )
+}
+
+fn anonymity_set_too_small(
+    too_small: Option<(u64, u64)>,
+    threshold: u64,
+) -> Result<(), tide::Error> {
+    match too_small {
+        Some((uid, size)) => Err(server_error(CapeAPIError::AnonymitySetTooSmall {
+            uid,
+            size,
+            threshold,
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Build a transfer with the given parameters, selecting inputs from the current state of
+/// `wallet`, unless it would spend a record whose anonymity set is smaller than `min_anonymity`.
+async fn build_transfer(
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+    params: &TransferParams,
+    min_anonymity: u64,
+) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), tide::Error> {
+    let sender: Option<jf_cap::keys::UserAddress> = params.sender.clone().map(Into::into);
+    check_predicted_min_anonymity(
+        require_wallet(wallet)?,
+        sender.as_ref(),
+        params.asset,
+        params.amount,
+        params.fee,
+        min_anonymity,
+    )
+    .await?;
+    let params = params.clone();
+    let (txn, info) = proof_threads::build(proof_threads, wallet, move |wallet| {
+        async move {
+            let sender: Option<jf_cap::keys::UserAddress> = params.sender.map(Into::into);
+            wallet
+                .export_transfer(
+                    sender.as_ref(),
+                    &params.asset,
+                    &[(params.recipient.0, params.amount.into())],
+                    params.fee,
+                )
+                .await
+                .map_err(wallet_error)
+        }
+        .boxed()
+    })
+    .await?;
+    check_min_anonymity(require_wallet(wallet)?, &txn, min_anonymity).await?;
+    Ok((txn, info))
+}
+
+/// Build and submit a transfer with the given parameters, unless it would spend a record whose
+/// anonymity set is smaller than `min_anonymity`.
+async fn transfer(
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+    params: &TransferParams,
+    min_anonymity: u64,
+) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
+    let (txn, info) = build_transfer(proof_threads, wallet, params, min_anonymity).await?;
+    require_wallet(wallet)?
+        .submit(CapeTransition::Transaction(txn), info)
+        .await
+        .map_err(wallet_error)
+}
+
+/// Build and submit a transfer like [transfer], timing the `select`, `prove` and `submit` phases.
+async fn traced_transfer(
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+    params: &TransferParams,
+    min_anonymity: u64,
+) -> Result<(TransactionReceipt<CapeLedger>, OperationTrace), tide::Error> {
+    let mut trace = OperationTrace::default();
+    timed(
+        &mut trace,
+        "select",
+        select_inputs(
+            require_wallet(wallet)?,
+            params.asset,
+            params.amount,
+            params.fee,
+        ),
+    )
+    .await?;
+    let (txn, info) = timed(
+        &mut trace,
+        "prove",
+        build_transfer(proof_threads, wallet, params, min_anonymity),
+    )
+    .await?;
+    let receipt = timed(
+        &mut trace,
+        "submit",
+        require_wallet(wallet)?.submit(CapeTransition::Transaction(txn), info),
+    )
+    .await
+    .map_err(wallet_error)?;
+    Ok((receipt, trace))
+}
+
+/// Move all the funds owned by a one-time sending key into the open wallet.
+///
+/// The key is only added to a temporary wallet in a temporary directory, which are both discarded
+/// once the funds have moved, so the key is never stored with the open wallet. Each asset is sent in
+/// its own transfer, paying `:fee` from the key's native balance, and the native balance left after
+/// all the fees is sent last.
+async fn sweep(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    scan_interval: &ScanInterval,
+    rng: &mut ChaChaRng,
+    faucet_key_pair: &UserKeyPair,
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+) -> Result<SweepReport, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let key = bindings[":key"].value.to::<UserKeyPair>()?;
+    let fee = bindings[":fee"].value.as_u128()?;
+    let address = match bindings.get(":address") {
+        Some(param) => {
+            let address = param.value.to::<UserAddress>()?;
+            if !wallet
+                .pub_keys()
+                .await
+                .iter()
+                .any(|pub_key| UserAddress::from(pub_key.address()) == address)
+            {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("an address of the open wallet"),
+                    actual: address.to_string(),
+                }));
+            }
+            address
+        }
+        None => match wallet.pub_keys().await.first() {
+            Some(pub_key) => pub_key.address().into(),
+            None => wallet
+                .generate_user_key(String::from("sweep"), None)
+                .await
+                .map_err(wallet_error)?
+                .address()
+                .into(),
+        },
+    };
+
+    // Declared before the temporary wallet, so the wallet is dropped before its files are deleted.
+    let dir = TempDir::new("cape_wallet_sweep")?;
+    let mut password = [0u8; 32];
+    rng.fill_bytes(&mut password);
+    let mut loader = CapeLoader::from_literal(
+        Some(KeyTree::random(rng).1.to_string()),
+        hex::encode(password),
+        dir.path().join("keystore"),
+        backend::latest_contract(options).await?,
+    );
+    let backend = backend::temporary(
+        options,
+        scan_interval,
+        rng,
+        faucet_key_pair.pub_key(),
+        wallet,
+        &mut loader,
+    )
+    .map_err(wallet_error)
+    .await?;
+    let mut sweeper = Wallet::new(backend).await.map_err(wallet_error)?;
+    sweeper
+        .add_user_key(key.clone(), String::from("sweep"), Default::default())
+        .await
+        .map_err(wallet_error)?;
+    sweeper
+        .await_key_scan(&key.address())
+        .await
+        .map_err(wallet_error)?;
+
+    let mut balances = HashMap::<AssetCode, u128>::new();
+    for record in sweeper.records().await {
+        if record.ro.freeze_flag == FreezeFlag::Unfrozen {
+            *balances.entry(record.ro.asset_def.code).or_default() +=
+                record.ro.amount.generic_into::<u128>();
+        }
+    }
+    let native = balances.remove(&AssetCode::native()).unwrap_or(0);
+    let mut transfers = balances.into_iter().collect::<Vec<_>>();
+    transfers.sort_by_key(|(asset, _)| asset.to_string());
+    let fees = fee * transfers.len() as u128;
+    if native < fees {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!("at least {} of the native asset to pay the fees", fees),
+            actual: native.to_string(),
+        }));
+    }
+    if native - fees > fee {
+        transfers.push((AssetCode::native(), native - fees - fee));
+    }
+
+    let mut report = SweepReport {
+        address: address.clone(),
+        swept: HashMap::new(),
+        transactions: vec![],
+    };
+    let mut sweeper = Some(sweeper);
+    for (asset, amount) in transfers {
+        let sender = key.address();
+        let recipient = address.clone();
+        let receipt = proof_threads::build(proof_threads, &mut sweeper, move |sweeper| {
+            async move {
+                sweeper
+                    .transfer(Some(&sender), &asset, &[(recipient.into(), amount)], fee)
+                    .await
+                    .map_err(wallet_error)
+            }
+            .boxed()
+        })
+        .await?;
+        let status = require_wallet(&mut sweeper)?
+            .await_transaction(&receipt)
+            .await
+            .map_err(wallet_error)?;
+        if status != TransactionStatus::Retired {
+            return Err(server_error(CapeAPIError::Internal {
+                msg: format!(
+                    "transfer of {} from the swept key ended as {:?}",
+                    asset, status
+                ),
+            }));
+        }
+        report.swept.insert(asset, amount.into());
+        report
+            .transactions
+            .extend(transaction_hash_string(&receipt.uid.0));
+    }
+    Ok(report)
+}
+
+/// The hashes and parameters of transfers sent through this server which were rejected, in the
+/// order they appear in the transaction history.
+async fn rejected_transfers(
+    wallet: &mut Wallet,
+    wallet_path: &Path,
+    sent: &SentTransfers,
+) -> Result<Vec<(String, TransferParams)>, tide::Error> {
+    let history = wallet.transaction_history().await.map_err(wallet_error)?;
+    let mut rejected = vec![];
+    for entry in history {
+        let (receipt, hash) = match (
+            &entry.receipt,
+            entry.hash.as_ref().and_then(transaction_hash_string),
+        ) {
+            (Some(receipt), Some(hash)) => (receipt, hash),
+            _ => continue,
+        };
+        if let Some(params) = sent.get(wallet_path, &hash) {
+            if let Ok(TransactionStatus::Rejected) = wallet.transaction_status(receipt).await {
+                rejected.push((hash, params.clone()));
+            }
+        }
+    }
+    Ok(rejected)
+}
+
+async fn failed(
+    options: &NodeOpt,
+    sent: &Mutex<SentTransfers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<FailedTransfer>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let rejected = rejected_transfers(wallet, &wallet_path, &*sent.lock().await).await?;
+    if rejected.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let rejections = wallet.rejections().await.map_err(wallet_error)?;
+    Ok(rejected
+        .into_iter()
+        .map(|(hash, params)| FailedTransfer {
+            reason: rejections
+                .get(&hash)
+                .cloned()
+                .unwrap_or_else(|| String::from("unknown")),
+            hash,
+            sender: params.sender,
+            asset: params.asset,
+            recipient: params.recipient,
+            amount: params.amount.to_string(),
+            fee: params.fee.to_string(),
+        })
+        .collect())
+}
+
+async fn resend(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    sent: &Mutex<SentTransfers>,
+    min_anonymity: &Mutex<MinAnonymity>,
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let hash = bindings[":receipt"].value.as_identifier()?;
+    if hash.tag() != "HASH" {
+        return Err(server_error(CapeAPIError::Tag {
+            expected: String::from("HASH"),
+            actual: hash.tag(),
+        }));
+    }
+    let hash = hash.to_string();
+
+    let mut sent = sent.lock().await;
+    let params = rejected_transfers(require_wallet(wallet)?, &wallet_path, &sent)
+        .await?
+        .into_iter()
+        .find_map(|(rejected, params)| if rejected == hash { Some(params) } else { None })
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("a failed transfer sent through this server"),
+                actual: hash.clone(),
+            })
+        })?;
+
+    // The inputs of the original transaction may be spent or have stale Merkle paths, so the
+    // transfer is rebuilt from scratch. It was already counted against the asset's spending limit
+    // when it was first sent, so it is not counted again.
+    let threshold = min_anonymity.lock().await.get(&wallet_path);
+    let receipt = transfer(proof_threads, wallet, &params, threshold).await?;
+    if let Some(resent_as) = transaction_hash_string(&receipt.uid.0) {
+        sent.resend(&wallet_path, &hash, resent_as);
+        sent.save(&options.sent_path())?;
+    }
+    Ok(receipt)
+}
+
+pub async fn get_records(
+    asof: Option<usize>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<RecordInfo>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    match asof {
+        Some(asof) => Ok(historical_state(wallet, asof).await?.records),
+        None => Ok(wallet.records().await.collect::<Vec<_>>()),
+    }
+}
+
+pub async fn get_last_keystore(options: &NodeOpt) -> Result<Option<KeyStoreLocation>, tide::Error> {
+    Ok(read_last_path(options).await?.map(KeyStoreLocation::from))
+}
+
+async fn accounts(wallet: &mut Option<Wallet>) -> Result<Vec<AccountSummary>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mut accounts = vec![];
+    for pub_key in wallet.pub_keys().await {
+        accounts.push(
+            AccountSummary::new(wallet, pub_key)
+                .await
+                .map_err(wallet_error)?,
+        );
+    }
+    Ok(accounts)
+}
+
+/// The most sub-accounts `subaccounts` will derive in one request.
+const MAX_SUBACCOUNTS: u64 = 1000;
+
+async fn subaccounts(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<SubAccount>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let count = match bindings.get(":count") {
+        Some(count) => count.value.as_u64()?,
+        None => wallet.pub_keys().await.len() as u64,
+    };
+    if count > MAX_SUBACCOUNTS {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!("at most {} sub-accounts", MAX_SUBACCOUNTS),
+            actual: count.to_string(),
+        }));
+    }
+    let mut subaccounts = vec![];
+    for index in 0..count {
+        subaccounts.push(SubAccount::new(wallet, index).await.map_err(wallet_error)?);
+    }
+    Ok(subaccounts)
+}
+
+async fn subaccount_address(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<UserAddress, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let index = bindings[":index"].value.as_u64()?;
+    Ok(wallet.subaccount_key(index).await.address().into())
+}
+
+async fn stealthaddress(wallet: &mut Option<Wallet>) -> Result<StealthAddress, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    // A fresh key has never been used, so there is nothing to scan for.
+    let pub_key = wallet
+        .generate_user_key(String::from("one-time address"), None)
+        .await
+        .map_err(wallet_error)?;
+    Ok(StealthAddress {
+        address: pub_key.address().into(),
+        pub_key,
+    })
+}
+
+async fn reconcile(
+    req: &mut Request<WebState>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<HoldingsDiscrepancy>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    if req.method() != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request with the expected holdings"),
+            actual: req.method().to_string(),
+        }));
+    }
+    let expected: Vec<ExpectedHoldings> = request_body(req).await?;
+
+    let mut actual = HashMap::<AssetCode, (usize, U256)>::new();
+    for record in wallet.records().await {
+        let (count, total) = actual.entry(record.ro.asset_def.code).or_default();
+        *count += 1;
+        *total += U256::from(record.ro.amount.generic_into::<u128>());
+    }
+
+    // Assets the wallet holds but which were not listed are expected to have no records.
+    let mut expected = expected
+        .into_iter()
+        .map(|holdings| (holdings.asset, holdings))
+        .collect::<HashMap<_, _>>();
+    for asset in actual.keys() {
+        expected.entry(*asset).or_insert(ExpectedHoldings {
+            asset: *asset,
+            count: 0,
+            total: U256::zero(),
+        });
+    }
+
+    let mut discrepancies = expected
+        .values()
+        .filter_map(|holdings| {
+            let (count, total) = actual.get(&holdings.asset).copied().unwrap_or_default();
+            HoldingsDiscrepancy::new(holdings, count, total)
+        })
+        .collect::<Vec<_>>();
+    discrepancies.sort_by_key(|discrepancy| discrepancy.asset.to_string());
+    Ok(discrepancies)
+}
+
+/// Compare the wallet's balance of the wrapped asset `:asset` with the amount of the ERC-20 token
+/// held by the CAPE contract.
+async fn reconcileonchain(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<OnChainReconciliation, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let not_wrapped = || {
+        server_error(CapeAPIError::Param {
+            expected: String::from("a wrapped ERC-20 asset"),
+            actual: asset.to_string(),
+        })
+    };
+    let erc20_code = wallet.wrapped_erc20(asset).await.ok_or_else(not_wrapped)?;
+    let onchain_total = wallet
+        .erc20_deposited(asset)
+        .await
+        .map_err(wallet_error)?
+        .ok_or_else(not_wrapped)?;
+    // Records which are frozen or on hold are still backed by the contract, so they all count.
+    let wallet_balance = wallet
+        .records()
+        .await
+        .filter(|record| record.ro.asset_def.code == asset)
+        .fold(U256::zero(), |total, record| {
+            total + U256::from(record.ro.amount.generic_into::<u128>())
+        });
+    Ok(OnChainReconciliation::new(
+        asset,
+        Address::from(erc20_code),
+        wallet_balance,
+        onchain_total,
+    ))
+}
+
+async fn skippedevents(
+    route_params: &[&str],
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<SkippedEvent>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let events = if route_params[0] == "replay" {
+        wallet.replay_skipped_events().await
+    } else {
+        wallet.skipped_events().await
+    }
+    .map_err(wallet_error)?;
+    Ok(events.into_iter().map(SkippedEvent::from).collect())
+}
+
+async fn seedfingerprint(wallet: &mut Option<Wallet>) -> Result<String, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    Ok(wallet.seed_fingerprint().await)
+}
+
+async fn getaccount(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Account, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let address = bindings[":address"].value.clone();
+    match address.as_identifier()?.tag().as_str() {
+        "ADDR" => Ok(Account::from_info(
+            wallet,
+            wallet
+                .sending_account(&address.to::<UserAddress>()?.0)
+                .await?,
         )
         .await),
         "USERPUBKEY" => Ok(Account::from_info(
             wallet,
             wallet
-                .sending_account(&address.to::<UserPubKey>()?.address())
-                .await?,
-        )
-        .await),
-        "AUDPUBKEY" => {
-            Ok(Account::from_info(wallet, wallet.viewing_account(&address.to()?).await?).await)
+                .sending_account(&address.to::<UserPubKey>()?.address())
+                .await?,
+        )
+        .await),
+        "AUDPUBKEY" => {
+            Ok(Account::from_info(wallet, wallet.viewing_account(&address.to()?).await?).await)
+        }
+        "FREEZEPUBKEY" => {
+            Ok(Account::from_info(wallet, wallet.freezing_account(&address.to()?).await?).await)
+        }
+        tag => Err(server_error(CapeAPIError::Tag {
+            expected: String::from("ADDR | USERPUBKEY | AUDPUBKEY | FREEZEPUBKEY"),
+            actual: String::from(tag),
+        })),
+    }
+}
+
+async fn getaccounts(
+    route_params: &[&str],
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<Account>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let selection = route_params[0];
+    let mut accounts = Vec::new();
+
+    if selection == "sending" || selection == "all" {
+        for key in wallet.pub_keys().await {
+            accounts.push(
+                Account::from_info(
+                    wallet,
+                    wallet
+                        .sending_account(&key.address())
+                        .await
+                        .map_err(wallet_error)?,
+                )
+                .await,
+            );
+        }
+    }
+    if selection == "viewing" || selection == "all" {
+        for key in wallet.auditor_pub_keys().await {
+            accounts.push(
+                Account::from_info(
+                    wallet,
+                    wallet.viewing_account(&key).await.map_err(wallet_error)?,
+                )
+                .await,
+            );
+        }
+    }
+    if selection == "freezing" || selection == "all" {
+        for key in wallet.freezer_pub_keys().await {
+            accounts.push(
+                Account::from_info(
+                    wallet,
+                    wallet.freezing_account(&key).await.map_err(wallet_error)?,
+                )
+                .await,
+            );
+        }
+    }
+
+    Ok(accounts)
+}
+
+async fn comparepolicy(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<PolicyComparison, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let asset = wallet
+        .asset(code)
+        .await
+        .ok_or_else(|| wallet_error(CapeWalletError::UndefinedAsset { asset: code }))?;
+
+    let mut expected: ExpectedPolicy = request_body(req).await?;
+    // Compare thresholds as numbers, so that equivalent formats match.
+    expected.viewing_threshold = expected
+        .viewing_threshold
+        .parse::<u128>()
+        .map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("a decimal viewing threshold"),
+                actual: expected.viewing_threshold.clone(),
+            })
+        })?
+        .to_string();
+    Ok(PolicyComparison::new(
+        &expected,
+        &AssetDefinition::from(asset.definition),
+    ))
+}
+
+async fn updateasset(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<AssetInfo, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+
+    // Get the existing asset information.
+    let mut asset = wallet
+        .asset(code)
+        .await
+        .ok_or_else(|| wallet_error(CapeWalletError::UndefinedAsset { asset: code }))?;
+
+    // Update based on request parameters.
+    let params: UpdateAsset = request_body(req).await?;
+    if let Some(symbol) = params.symbol {
+        asset = asset.with_name(symbol);
+    }
+    if let Some(description) = params.description {
+        asset = asset.with_description(description);
+    }
+    if let Some(icon) = params.icon {
+        let bytes = base64::decode(&icon)?;
+        let icon = Icon::load_png(Cursor::new(bytes))?;
+        asset = asset.with_icon(icon);
+    }
+
+    // Update the asset info in the wallet.
+    wallet.import_asset(asset).await.map_err(wallet_error)?;
+
+    // Get the final asset info, which may be different than what we imported if, say, the asset is
+    // a verified asset that cannot be overridden.
+    Ok(AssetInfo::from_info(
+        wallet,
+        wallet
+            .asset(code)
+            .await
+            .expect("Updated asset not in the wallet's asset storage"),
+    )
+    .await)
+}
+
+pub async fn listcontacts(contacts: &Mutex<Contacts>) -> Result<Vec<Contact>, tide::Error> {
+    Ok(contacts.lock().await.list())
+}
+
+/// Parse an optional `skip` or `overwrite` parameter, defaulting to `skip`.
+fn duplicate_policy(binding: Option<&RouteBinding>) -> Result<DuplicatePolicy, tide::Error> {
+    match binding {
+        Some(policy) => match policy.value.as_string()?.as_str() {
+            "skip" => Ok(DuplicatePolicy::Skip),
+            "overwrite" => Ok(DuplicatePolicy::Overwrite),
+            policy => Err(server_error(CapeAPIError::Param {
+                expected: String::from("skip or overwrite"),
+                actual: policy.to_string(),
+            })),
+        },
+        None => Ok(DuplicatePolicy::Skip),
+    }
+}
+
+async fn importcontacts(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    contacts: &Mutex<Contacts>,
+) -> Result<Vec<ContactImportResult>, tide::Error> {
+    if req.method() != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to import contacts"),
+            actual: req.method().to_string(),
+        }));
+    }
+    let policy = duplicate_policy(bindings.get(":on_duplicate"))?;
+    let entries = request_body::<Vec<ContactEntry>, _>(req).await?;
+
+    let mut contacts = contacts.lock().await;
+    let results = contacts.import(entries, policy);
+    contacts.save(&options.contacts_path())?;
+    Ok(results)
+}
+
+async fn portfoliovalue(
+    req: &mut Request<WebState>,
+    wallet: &mut Option<Wallet>,
+) -> Result<PortfolioValue, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let rates = request_body::<HashMap<AssetCode, String>, _>(req).await?;
+    let balances = WalletSnapshot::from_wallet(wallet).await.balances();
+    PortfolioValue::new(balances, &rates).map_err(|msg| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("exchange rates as non-negative decimal strings"),
+            actual: msg,
+        })
+    })
+}
+
+async fn previewimportkey(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ViewingKeyPreview, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let key = bindings[":key"].value.to::<AuditorKeyPair>()?;
+    let records = wallet.viewed_records(&key).await.map_err(wallet_error)?;
+    Ok(ViewingKeyPreview::new(records))
+}
+
+async fn keycoverage(
+    req: &mut Request<WebState>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<KeyCoverage>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let candidates = request_body::<Vec<AuditorKeyPair>, _>(req).await?;
+    // Records viewable by a key the wallet already holds are visible already, so only scan for the
+    // others.
+    let imported = wallet.auditor_pub_keys().await;
+    let unimported = candidates
+        .iter()
+        .filter(|key| !imported.contains(&key.pub_key()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let records = wallet
+        .viewed_records_by_key(&unimported)
+        .await
+        .map_err(wallet_error)?;
+    Ok(candidates
+        .into_iter()
+        .map(|key| {
+            let key = key.pub_key();
+            KeyCoverage {
+                records: records.get(&key).map(Vec::len).unwrap_or(0),
+                imported: imported.contains(&key),
+                key,
+            }
+        })
+        .collect())
+}
+
+/// The definition of the asset `code`, and the viewing key named by its policy.
+///
+/// Fails if the asset is not known to `wallet`, or `wallet` does not own its viewing key.
+async fn asset_viewing_key(
+    wallet: &Wallet,
+    code: AssetCode,
+) -> Result<(JfAssetDefinition, AuditorKeyPair), tide::Error> {
+    let asset = wallet
+        .asset(code)
+        .await
+        .ok_or_else(|| {
+            server_error(CapeAPIError::NotFound {
+                msg: format!("asset {} is not known to this wallet", code),
+            })
+        })?
+        .definition;
+    let policy = asset.policy_ref();
+    let key = if policy.is_auditor_pub_key_set() {
+        wallet
+            .get_auditor_private_key(policy.auditor_pub_key())
+            .await
+            .ok()
+    } else {
+        None
+    };
+    let key = key.ok_or_else(|| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("an asset whose viewing key is owned by this wallet"),
+            actual: code.to_string(),
+        })
+    })?;
+    Ok((asset, key))
+}
+
+/// The transaction graph of `:asset` revealed by the wallet's viewing key for it, or by the viewing
+/// capability `:token`.
+async fn audit(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    viewing_capabilities: &Mutex<ViewingCapabilities>,
+    wallet: &mut Option<Wallet>,
+) -> Result<AuditGraph, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let scope = match bindings.get(":token") {
+        Some(token) => {
+            let wallet_path = require_wallet_path(options).await?;
+            let scope = viewing_capabilities
+                .lock()
+                .await
+                .check(&wallet_path, &token.value.as_identifier()?)
+                .map_err(|msg| server_error(CapeAPIError::Unauthorized { msg }))?;
+            if scope.asset != code {
+                return Err(server_error(CapeAPIError::Unauthorized {
+                    msg: format!("the viewing capability does not grant access to {}", code),
+                }));
+            }
+            Some(scope)
+        }
+        None => None,
+    };
+    let (asset, key) = asset_viewing_key(wallet, code).await?;
+    let transactions = wallet
+        .trace_asset(&asset, &key)
+        .await
+        .map_err(wallet_error)?
+        .into_iter()
+        .map(AuditedTransaction::from)
+        .collect();
+    let graph = AuditGraph::new(&asset, transactions);
+    Ok(match scope {
+        Some(scope) => graph.restrict(&scope),
+        None => graph,
+    })
+}
+
+/// Assets known to the wallet whose viewing key the wallet owns.
+async fn auditable(wallet: &mut Option<Wallet>) -> Result<Vec<AssetCode>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let viewing_keys = wallet.auditor_pub_keys().await;
+    Ok(wallet
+        .assets()
+        .await
+        .into_iter()
+        .filter(|asset| {
+            let policy = asset.definition.policy_ref();
+            policy.is_auditor_pub_key_set() && viewing_keys.contains(policy.auditor_pub_key())
+        })
+        .map(|asset| asset.definition.code)
+        .collect())
+}
+
+/// Assets known to the wallet whose freezing key the wallet owns.
+async fn freezable(wallet: &mut Option<Wallet>) -> Result<Vec<AssetCode>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let freezing_keys = wallet.freezer_pub_keys().await;
+    Ok(wallet
+        .assets()
+        .await
+        .into_iter()
+        .filter(|asset| {
+            let policy = asset.definition.policy_ref();
+            policy.is_freezer_pub_key_set() && freezing_keys.contains(policy.freezer_pub_key())
+        })
+        .map(|asset| asset.definition.code)
+        .collect())
+}
+
+async fn exportmetadata(
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Option<Wallet>,
+) -> Result<MetadataBundle, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    Ok(metadata_bundle(contacts, asset_metadata, wallet).await)
+}
+
+/// The local, non-secret metadata of `wallet`, as exported by `exportmetadata` and backed up by
+/// `backup`.
+pub async fn metadata_bundle(
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &Wallet,
+) -> MetadataBundle {
+    let asset_metadata = asset_metadata.lock().await;
+    let mut assets = vec![];
+    for asset in wallet.assets().await {
+        let code = asset.definition.code;
+        if code == AssetCode::native() {
+            continue;
+        }
+        let mut info = AssetInfo::from_info(wallet, asset)
+            .await
+            .with_metadata(asset_metadata.get(&code));
+        // Mint info is a secret: it lets the holder of the minting key mint more of the asset.
+        info.mint_info = None;
+        assets.push(info);
+    }
+    MetadataBundle {
+        version: METADATA_BUNDLE_VERSION,
+        contacts: contacts.lock().await.list(),
+        assets,
+    }
+}
+
+async fn importmetadata(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Option<Wallet>,
+) -> Result<MetadataImportReport, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let policy = duplicate_policy(bindings.get(":on_duplicate"))?;
+    let bundle = request_body::<MetadataBundle, _>(req).await?;
+    import_bundle(bundle, policy, options, contacts, asset_metadata, wallet).await
+}
+
+/// Import a metadata bundle, from `importmetadata` or a backup, into `wallet` and the server.
+async fn import_bundle(
+    bundle: MetadataBundle,
+    policy: DuplicatePolicy,
+    options: &NodeOpt,
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Wallet,
+) -> Result<MetadataImportReport, tide::Error> {
+    if bundle.version != METADATA_BUNDLE_VERSION {
+        return Err(server_error(CapeAPIError::Param {
+            expected: format!("metadata bundle version {}", METADATA_BUNDLE_VERSION),
+            actual: format!("version {}", bundle.version),
+        }));
+    }
+    // Check the whole bundle before importing anything, so that a bad bundle is not half imported.
+    for info in &bundle.assets {
+        if let Some(metadata) = &info.metadata {
+            metadata.validate().map_err(|msg| {
+                server_error(CapeAPIError::Param {
+                    expected: String::from("valid asset metadata"),
+                    actual: msg,
+                })
+            })?;
+        }
+        if let Some(icon) = &info.icon {
+            base64::decode(icon)
+                .ok()
+                .and_then(|bytes| Icon::load_png(Cursor::new(bytes)).ok())
+                .ok_or_else(|| {
+                    server_error(CapeAPIError::Param {
+                        expected: String::from("base64-encoded PNG icon"),
+                        actual: format!("invalid icon for asset {}", info.definition.code),
+                    })
+                })?;
+        }
+    }
+
+    let mut contacts = contacts.lock().await;
+    let contact_results = contacts.import(
+        bundle
+            .contacts
+            .into_iter()
+            .map(|contact| ContactEntry {
+                name: contact.name,
+                address: contact.address.to_string(),
+            })
+            .collect(),
+        policy,
+    );
+    contacts.save(&options.contacts_path())?;
+
+    let mut asset_metadata = asset_metadata.lock().await;
+    let mut assets = MergeReport::default();
+    let mut metadata = vec![];
+    for mut info in bundle.assets {
+        let code = info.definition.code;
+        if let Some(theirs) = info.metadata.take() {
+            if asset_metadata.get(&code).is_none() || policy == DuplicatePolicy::Overwrite {
+                asset_metadata.set(code, theirs);
+                metadata.push(code);
+            }
+        }
+        // Verified assets come from the official asset library, which the wallet already has, and
+        // their annotations cannot be changed.
+        if info.verified {
+            continue;
+        }
+        info.mint_info = None;
+        let theirs = seahorse::AssetInfo::from(info);
+        match wallet.asset(code).await {
+            Some(mut ours) => {
+                let (changed, conflict) = merge_asset_annotations(&mut ours, theirs, policy);
+                if conflict {
+                    assets.conflicts.push(code);
+                }
+                if changed {
+                    wallet.import_asset(ours).await.map_err(wallet_error)?;
+                    assets.updated_assets.push(code);
+                }
+            }
+            None => {
+                wallet.import_asset(theirs).await.map_err(wallet_error)?;
+                assets.added_assets.push(code);
+            }
+        }
+    }
+    asset_metadata.save(&options.asset_metadata_path())?;
+
+    Ok(MetadataImportReport {
+        contacts: contact_results,
+        assets,
+        metadata,
+    })
+}
+
+async fn backupschedule(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    backups: &Mutex<Backups>,
+    wallet: &mut Option<Wallet>,
+) -> Result<BackupPolicy, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    if method != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to set a backup policy"),
+            actual: method.to_string(),
+        }));
+    }
+    let interval = bindings[":interval"].value.as_u64()?;
+    let dir = bindings[":dir"].value.as_path()?;
+    let keep = match bindings.get(":keep") {
+        Some(keep) => keep.value.as_usize()?,
+        None => backups::DEFAULT_KEEP,
+    };
+    if keep == 0 {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a positive number of backups to keep"),
+            actual: keep.to_string(),
+        }));
+    }
+    let mut backups = backups.lock().await;
+    let policy = backups.set(&wallet_path, dir, interval, keep);
+    backups.save(&options.backups_path())?;
+    Ok(policy)
+}
+
+// The backup policy of the open wallet, which says where its backups are.
+async fn backup_policy(
+    options: &NodeOpt,
+    backups: &Mutex<Backups>,
+) -> Result<BackupPolicy, tide::Error> {
+    let wallet_path = require_wallet_path(options).await?;
+    backups
+        .lock()
+        .await
+        .get(&wallet_path)
+        .cloned()
+        .ok_or_else(|| {
+            server_error(CapeAPIError::NotFound {
+                msg: String::from("no backup directory is set; set one with backup/schedule"),
+            })
+        })
+}
+
+async fn backupnow(
+    options: &NodeOpt,
+    backups: &Mutex<Backups>,
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Option<Wallet>,
+) -> Result<BackupInfo, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let policy = backup_policy(options, backups).await?;
+    let bundle = metadata_bundle(contacts, asset_metadata, wallet).await;
+    Ok(backups::backup(wallet, &bundle, &policy.dir, policy.keep).await?)
+}
+
+async fn listbackups(
+    options: &NodeOpt,
+    backups: &Mutex<Backups>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<BackupInfo>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let policy = backup_policy(options, backups).await?;
+    Ok(backups::list(
+        &policy.dir,
+        &wallet.seed_fingerprint().await,
+    )?)
+}
+
+async fn backuprestore(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    backups: &Mutex<Backups>,
+    contacts: &Mutex<Contacts>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Option<Wallet>,
+) -> Result<MetadataImportReport, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let name = bindings[":name"].value.as_string()?;
+    let policy = duplicate_policy(bindings.get(":on_duplicate"))?;
+    let dir = backup_policy(options, backups).await?.dir;
+    // Only read backups of this wallet, so that `:name` cannot refer to any other file.
+    if !backups::list(&dir, &wallet.seed_fingerprint().await)?
+        .iter()
+        .any(|backup| backup.name == name)
+    {
+        return Err(server_error(CapeAPIError::NotFound {
+            msg: format!("no backup named {}", name),
+        }));
+    }
+    let bundle = backups::read(wallet, &dir, &name).await?;
+    import_bundle(bundle, policy, options, contacts, asset_metadata, wallet).await
+}
+
+async fn exporttransaction(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    min_anonymity: &Mutex<MinAnonymity>,
+    proof_threads: &Mutex<ProofThreads>,
+    trace: bool,
+    wallet: &mut Option<Wallet>,
+) -> Result<(ExportedTransaction, Option<OperationTrace>), tide::Error> {
+    require_wallet(wallet)?;
+
+    let sender = match bindings.get(":sender") {
+        Some(param) => Some(param.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let recipient = bindings[":recipient"].value.to::<UserAddress>()?;
+    let amount = bindings[":amount"].value.as_u128()?;
+    let fee = bindings[":fee"].value.as_u128()?;
+
+    let threshold = min_anonymity
+        .lock()
+        .await
+        .get(&require_wallet_path(options).await?);
+    check_predicted_min_anonymity(
+        require_wallet(wallet)?,
+        sender.as_ref(),
+        asset,
+        amount,
+        fee,
+        threshold,
+    )
+    .await?;
+    // The exported transaction can be submitted by anyone, so it counts as spent once exported.
+    let mut trace = if trace {
+        Some(OperationTrace::default())
+    } else {
+        None
+    };
+    let (txn, info) = limited_spend(options, spend_limits, &asset, amount, async {
+        if let Some(trace) = &mut trace {
+            timed(
+                trace,
+                "select",
+                select_inputs(require_wallet(wallet)?, asset, amount, fee),
+            )
+            .await?;
+        }
+        let export = proof_threads::build(proof_threads, wallet, move |wallet| {
+            async move {
+                wallet
+                    .export_transfer(
+                        sender.as_ref(),
+                        &asset,
+                        &[(recipient.0, amount.into())],
+                        fee,
+                    )
+                    .await
+                    .map_err(wallet_error)
+            }
+            .boxed()
+        });
+        let (txn, info) = match &mut trace {
+            Some(trace) => timed(trace, "prove", export).await?,
+            None => export.await?,
+        };
+        check_min_anonymity(require_wallet(wallet)?, &txn, threshold)
+            .await
+            .map(|()| (txn, info))
+    })
+    .await?;
+    let wallet = require_wallet(wallet)?;
+    let transition = CapeTransition::Transaction(txn.clone());
+    let nullifiers = transition
+        .input_nullifiers()
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let inputs = wallet
+        .records()
+        .await
+        .filter(|record| nullifiers.contains(&record.nullifier))
+        .map(Record::from)
+        .collect();
+    let submission = SubmitBody {
+        transaction: txn,
+        memos: info.memos.into_iter().flatten().collect(),
+        signature: info.sig,
+    };
+    let exported = ExportedTransaction {
+        transaction: base64::encode(bincode::serialize(&submission)?),
+        hash: transaction_hash_string(&transition.hash()),
+        inputs,
+    };
+    Ok((exported, trace))
+}
+
+/// The proposer's half of a swap, as encoded in [SwapProposal::proposal].
+#[derive(Serialize, Deserialize)]
+struct SwapHalf {
+    terms: SwapTerms,
+    submission: SubmitBody,
+    /// The record the proposer's transfer sends to the counterparty.
+    output: JfRecordOpening,
+    /// The key of the proposer, which signs the terms and the proposer's transfer, since the
+    /// transfer itself does not reveal who sent it.
+    proposer: UserPubKey,
+    signature: jf_cap::Signature,
+}
+
+impl SwapHalf {
+    /// The message signed by the proposer of a swap with `terms` to vouch for `transaction`.
+    fn message(terms: &SwapTerms, transaction: &CapeModelTxn) -> Vec<u8> {
+        bincode::serialize(&(terms, transaction)).expect("failed to serialize swap message")
+    }
+}
+
+/// Build the proposer's half of a swap: a transfer of the offered asset to the counterparty.
+///
+/// The transfer is not submitted. Like `exporttransaction`, its inputs are put on hold until it is
+/// committed, as part of the swap, or expires.
+async fn swappropose(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    min_anonymity: &Mutex<MinAnonymity>,
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+) -> Result<SwapProposal, tide::Error> {
+    let offered_amount = bindings[":offer_amount"].value.as_u128()?;
+    let terms = SwapTerms {
+        proposer: bindings[":sender"].value.to::<UserAddress>()?,
+        counterparty: bindings[":counterparty"].value.to::<UserAddress>()?,
+        offered_asset: bindings[":offer"].value.to::<AssetCode>()?,
+        offered_amount: offered_amount.into(),
+        requested_asset: bindings[":request"].value.to::<AssetCode>()?,
+        requested_amount: bindings[":request_amount"].value.as_u128()?.into(),
+    };
+    let fee = bindings[":fee"].value.as_u128()?;
+    let key_pair = require_wallet(wallet)?
+        .get_user_private_key(&terms.proposer.0)
+        .await
+        .map_err(wallet_error)?;
+
+    let threshold = min_anonymity
+        .lock()
+        .await
+        .get(&require_wallet_path(options).await?);
+    check_predicted_min_anonymity(
+        require_wallet(wallet)?,
+        Some(&terms.proposer.0),
+        terms.offered_asset,
+        offered_amount,
+        fee,
+        threshold,
+    )
+    .await?;
+    let (txn, info) = limited_spend(
+        options,
+        spend_limits,
+        &terms.offered_asset,
+        offered_amount,
+        async {
+            let build_terms = terms.clone();
+            let (txn, info) = proof_threads::build(proof_threads, wallet, move |wallet| {
+                async move {
+                    wallet
+                        .export_transfer(
+                            Some(&build_terms.proposer.0),
+                            &build_terms.offered_asset,
+                            &[(build_terms.counterparty.0, offered_amount.into())],
+                            fee,
+                        )
+                        .await
+                        .map_err(wallet_error)
+                }
+                .boxed()
+            })
+            .await?;
+            check_min_anonymity(require_wallet(wallet)?, &txn, threshold)
+                .await
+                .map(|()| (txn, info))
+        },
+    )
+    .await?;
+    let output = info
+        .outputs
+        .iter()
+        .find(|output| {
+            output.asset_def.code == terms.offered_asset
+                && output.amount.generic_into::<u128>() == offered_amount
+                && UserAddress::from(output.pub_key.address()) == terms.counterparty
+        })
+        .cloned()
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Internal {
+                msg: String::from("the proposer's transfer has no output for the counterparty"),
+            })
+        })?;
+    let hash = transaction_hash_string(&CapeTransition::Transaction(txn.clone()).hash());
+    let signature = key_pair.sign(&SwapHalf::message(&terms, &txn));
+    let half = SwapHalf {
+        terms: terms.clone(),
+        submission: SubmitBody {
+            transaction: txn,
+            memos: info.memos.into_iter().flatten().collect(),
+            signature: info.sig,
+        },
+        output,
+        proposer: key_pair.pub_key(),
+        signature,
+    };
+    Ok(SwapProposal {
+        terms,
+        proposal: base64::encode(bincode::serialize(&half)?),
+        hash,
+    })
+}
+
+/// Complete a swap proposed to one of this wallet's addresses.
+///
+/// The proposer's transfer is checked against the terms of the swap before anything is built. The
+/// transfer of the requested asset back to the proposer is then submitted in the same block as the
+/// proposer's transfer, so that either both are committed or neither is.
+async fn swapaccept(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    min_anonymity: &Mutex<MinAnonymity>,
+    proof_threads: &Mutex<ProofThreads>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TransactionReceipt<CapeLedger>, tide::Error> {
+    require_wallet(wallet)?;
+    let proposal = request_body::<SwapProposal, _>(req).await?;
+    let bytes = base64::decode(&proposal.proposal).map_err(|err| {
+        server_error(CapeAPIError::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+    let half = bincode::deserialize::<SwapHalf>(&bytes).map_err(|err| {
+        server_error(CapeAPIError::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+    let invalid = |msg: &str| {
+        server_error(CapeAPIError::InvalidSwap {
+            msg: msg.to_string(),
+        })
+    };
+    let terms = half.terms;
+    if terms != proposal.terms {
+        return Err(invalid(
+            "the proposal does not match the terms it was sent with",
+        ));
+    }
+
+    // The proposer must vouch for the transfer, so that a proposal cannot be made out of a
+    // transfer someone else sent to one of our addresses.
+    if half.proposer.address() != terms.proposer.0
+        || half
+            .proposer
+            .verify_sig(
+                &SwapHalf::message(&terms, &half.submission.transaction),
+                &half.signature,
+            )
+            .is_err()
+    {
+        return Err(invalid(
+            "the proposer's transfer is not signed by the proposer",
+        ));
+    }
+
+    // The proposer's transfer must create the offered record, owned by one of our keys, and the
+    // memo for it must be signed, so that we can find the record once the swap is committed.
+    let note = match &half.submission.transaction {
+        CapeModelTxn::CAP(note @ TransactionNote::Transfer(_)) => note,
+        _ => return Err(invalid("the proposer's half is not a transfer")),
+    };
+    if note
+        .verify_receiver_memos_signature(&half.submission.memos, &half.submission.signature)
+        .is_err()
+    {
+        return Err(invalid(
+            "the proposer's memos are not signed by their transfer",
+        ));
+    }
+    let output = &half.output;
+    if !note
+        .output_commitments()
+        .contains(&RecordCommitment::from(output))
+    {
+        return Err(invalid(
+            "the proposer's transfer does not create the offered record",
+        ));
+    }
+    if output.asset_def.code != terms.offered_asset
+        || U256::from(output.amount.generic_into::<u128>()) != terms.offered_amount
+        || UserAddress::from(output.pub_key.address()) != terms.counterparty
+    {
+        return Err(invalid("the offered record does not match the terms"));
+    }
+    if !require_wallet(wallet)?
+        .pub_keys()
+        .await
+        .iter()
+        .any(|key| UserAddress::from(key.address()) == terms.counterparty)
+    {
+        return Err(invalid("the counterparty is not an address of this wallet"));
+    }
+    if terms.requested_amount > U256::from(u128::MAX) {
+        return Err(invalid("the requested amount is too large"));
+    }
+
+    let sender = match bindings.get(":sender") {
+        Some(param) => Some(param.value.to::<UserAddress>()?.0),
+        None => None,
+    };
+    let fee = bindings[":fee"].value.as_u128()?;
+    let threshold = min_anonymity
+        .lock()
+        .await
+        .get(&require_wallet_path(options).await?);
+    let requested_asset = terms.requested_asset;
+    let requested_amount = terms.requested_amount.as_u128();
+    check_predicted_min_anonymity(
+        require_wallet(wallet)?,
+        sender.as_ref(),
+        requested_asset,
+        requested_amount,
+        fee,
+        threshold,
+    )
+    .await?;
+    let proposer = terms.proposer.0.clone();
+    limited_spend(
+        options,
+        spend_limits,
+        &requested_asset,
+        requested_amount,
+        async {
+            let (txn, info) = proof_threads::build(proof_threads, wallet, move |wallet| {
+                async move {
+                    wallet
+                        .export_transfer(
+                            sender.as_ref(),
+                            &requested_asset,
+                            &[(proposer, requested_amount.into())],
+                            fee,
+                        )
+                        .await
+                        .map_err(wallet_error)
+                }
+                .boxed()
+            })
+            .await?;
+            let wallet = require_wallet(wallet)?;
+            check_min_anonymity(wallet, &txn, threshold).await?;
+            wallet
+                .submit_bundle(txn, info, half.submission)
+                .await
+                .map_err(wallet_error)
+        },
+    )
+    .await
+}
+
+async fn getasset(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    verifiers: &Mutex<Verifiers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<AssetInfo, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let info = wallet
+        .asset(code)
+        .await
+        .ok_or_else(|| wallet_error(CapeWalletError::UndefinedAsset { asset: code }))?;
+    let mut info = [AssetInfo::from_info(wallet, info)
+        .await
+        .with_metadata(asset_metadata.lock().await.get(&code))];
+    apply_verifiers(options, verifiers, &mut info).await?;
+    let [info] = info;
+    Ok(info)
+}
+
+async fn erc20byasset(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<String, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let erc20_code = wallet.wrapped_erc20(code).await.ok_or_else(|| {
+        server_error(CapeAPIError::NotFound {
+            msg: format!("{} is not a wrapped asset", code),
+        })
+    })?;
+    Ok(format!("{:#x}", Address::from(erc20_code)))
+}
+
+async fn assetbyerc20(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<AssetCode>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let erc20_code: Address = bindings[":eth_address"].value.as_string()?.parse()?;
+    // Several assets, with different policies, may wrap the same token, so collect all of them.
+    let mut assets = vec![];
+    for asset in wallet.assets().await {
+        let code = asset.definition.code;
+        if wallet.wrapped_erc20(code).await.map(Address::from) == Some(erc20_code) {
+            assets.push(code);
+        }
+    }
+    if assets.is_empty() {
+        return Err(server_error(CapeAPIError::NotFound {
+            msg: format!("no known asset wraps {:#x}", erc20_code),
+        }));
+    }
+    assets.sort_by_key(|asset| asset.to_string());
+    Ok(assets)
+}
+
+/// Mark the assets signed by one of the open wallet's trusted verifiers as verified.
+async fn apply_verifiers(
+    options: &NodeOpt,
+    verifiers: &Mutex<Verifiers>,
+    assets: &mut [AssetInfo],
+) -> Result<(), tide::Error> {
+    let wallet_path = require_wallet_path(options).await?;
+    let verifiers = verifiers.lock().await;
+    for asset in assets {
+        if verifiers.is_verified(&wallet_path, &asset.definition.code) {
+            asset.verified = true;
+        }
+    }
+    Ok(())
+}
+
+async fn verifiers(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    verifiers: &Mutex<Verifiers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<TrustedVerifier>, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut verifiers = verifiers.lock().await;
+    if let Some(name) = bindings.get(":name") {
+        let name = name.value.as_string()?;
+        match req.method() {
+            Method::Post => {
+                let key = request_body::<String, _>(req).await?;
+                let key = key.parse::<VerKey>().map_err(|_| {
+                    server_error(CapeAPIError::Param {
+                        expected: String::from("an asset library verification key"),
+                        actual: key.clone(),
+                    })
+                })?;
+                verifiers.insert(&wallet_path, name, key);
+            }
+            Method::Delete => {
+                if !verifiers.remove(&wallet_path, &name) {
+                    return Err(server_error(CapeAPIError::Param {
+                        expected: String::from("the name of a trusted verifier"),
+                        actual: name,
+                    }));
+                }
+            }
+            method => {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from("POST or DELETE request to change a verifier"),
+                    actual: method.to_string(),
+                }))
+            }
+        }
+        verifiers.save(&options.verifiers_path())?;
+    }
+    Ok(verifiers.list(&wallet_path))
+}
+
+async fn alerts(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    alerts: &Mutex<Alerts>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<AlertThreshold>, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut alerts = alerts.lock().await;
+    if let Some(asset) = bindings.get(":asset") {
+        let asset = asset.value.to::<AssetCode>()?;
+        match (method, bindings.get(":amount")) {
+            (Method::Post, Some(amount)) => {
+                alerts.set(&wallet_path, asset, amount.value.as_u128()?);
+            }
+            (Method::Delete, None) => {
+                if !alerts.remove(&wallet_path, &asset) {
+                    return Err(server_error(CapeAPIError::Param {
+                        expected: String::from("an asset with an alert threshold"),
+                        actual: asset.to_string(),
+                    }));
+                }
+            }
+            (method, _) => {
+                return Err(server_error(CapeAPIError::Param {
+                    expected: String::from(
+                        "POST request to set a threshold or DELETE request to remove one",
+                    ),
+                    actual: method.to_string(),
+                }))
+            }
+        }
+        alerts.save(&options.alerts_path())?;
+    }
+    Ok(alerts.list(&wallet_path))
+}
+
+async fn verifyasset(
+    req: &mut Request<WebState>,
+    options: &NodeOpt,
+    verifiers: &Mutex<Verifiers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<AssetInfo>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let bytes = base64::decode(&request_body::<String, _>(req).await?).map_err(|err| {
+        server_error(CapeAPIError::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+    let library = bincode::deserialize::<VerifiedAssetLibrary>(&bytes).map_err(|err| {
+        server_error(CapeAPIError::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+
+    let mut verifiers = verifiers.lock().await;
+    let (name, assets) = verifiers
+        .keys(&wallet_path)
+        .into_iter()
+        .find_map(|(name, key)| Some((name, library.open(&key)?)))
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("an asset library signed by a trusted verifier"),
+                actual: String::from("an asset library signed by an unknown key"),
+            })
+        })?;
+    let codes = assets
+        .iter()
+        .map(|asset| asset.definition.code)
+        .collect::<Vec<_>>();
+    for mut asset in assets {
+        // Only the official asset library sets the wallet's own verified flag, so that the assets
+        // of a verifier stop being verified when it is removed.
+        asset.verified = false;
+        wallet.import_asset(asset).await.map_err(wallet_error)?;
+    }
+    verifiers.add_assets(&wallet_path, &name, codes.iter().copied());
+    verifiers.save(&options.verifiers_path())?;
+
+    let mut assets = vec![];
+    for code in codes {
+        if let Some(info) = wallet.asset(code).await {
+            let mut info = AssetInfo::from_info(wallet, info).await;
+            info.verified = true;
+            assets.push(info);
+        }
+    }
+    Ok(assets)
+}
+
+async fn assetmetadata(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    verifiers: &Mutex<Verifiers>,
+    wallet: &mut Option<Wallet>,
+) -> Result<AssetInfo, tide::Error> {
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let metadata = request_body::<AssetMetadata, _>(req).await?;
+    metadata.validate().map_err(|msg| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("valid asset metadata"),
+            actual: msg,
+        })
+    })?;
+
+    // Only record metadata for assets the wallet knows about.
+    if require_wallet(wallet)?.asset(code).await.is_none() {
+        return Err(wallet_error(CapeWalletError::UndefinedAsset {
+            asset: code,
+        }));
+    }
+    {
+        let mut asset_metadata = asset_metadata.lock().await;
+        asset_metadata.set(code, metadata);
+        asset_metadata.save(&options.asset_metadata_path())?;
+    }
+    getasset(bindings, options, asset_metadata, verifiers, wallet).await
+}
+
+async fn exportasset(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<String, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let code = bindings[":asset"].value.to::<AssetCode>()?;
+    let mut asset = wallet
+        .asset(code)
+        .await
+        .ok_or(CapeWalletError::UndefinedAsset { asset: code })?;
+
+    // Don't export mint info, we don't want other users to be able to mint this asset just because
+    // we've published it on a registry.
+    asset.mint_info = None;
+
+    // The `AssetInfo` structure serializes as a JSON blob, but for exporting and transmitting, a
+    // compact, URL-safe string is more convenient. Therefore, we serialize to bytes and then encode
+    // in base64.
+    let bytes = bincode::serialize(&asset).expect("Failed to serialize asset");
+    Ok(TaggedBase64::new("CAPE-ASSET", &bytes)
+        .expect("Failed to encode serialized asset")
+        .to_string())
+}
+
+pub async fn importasset(
+    request: &mut Request<WebState>,
+    wallet: &mut Option<Wallet>,
+) -> Result<AssetInfo, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let tb64 =
+        TaggedBase64::parse(&request_body::<String, _>(request).await?).map_err(|source| {
+            server_error(CapeAPIError::Deserialize {
+                msg: source.to_string(),
+            })
+        })?;
+    if tb64.tag() != "CAPE-ASSET" {
+        return Err(server_error(CapeAPIError::Tag {
+            expected: "CAPE-ASSET".into(),
+            actual: tb64.tag(),
+        }));
+    }
+    let bytes = tb64.value();
+    let asset = bincode::deserialize::<seahorse::AssetInfo>(&bytes).map_err(|err| {
+        server_error(CapeAPIError::Deserialize {
+            msg: err.to_string(),
+        })
+    })?;
+    let code = asset.definition.code;
+    wallet.import_asset(asset).await.map_err(wallet_error)?;
+
+    // Get the asset info from the wallet, which may be different from what we imported if the
+    // wallet already had this asset and merely updated part of it.
+    let info = wallet
+        .asset(code)
+        .await
+        .expect("Imported asset not in the wallet's asset storage");
+    Ok(AssetInfo::from_info(wallet, info).await)
+}
+
+async fn recordopening(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<sol::RecordOpening, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+
+    let address = bindings[":address"].value.to::<UserAddress>()?;
+    let asset_code = bindings[":asset"].value.to::<AssetCode>()?;
+    let asset_definition = wallet
+        .asset(asset_code)
+        .await
+        .expect("Asset code not in the wallet's asset storage")
+        .definition;
+    let amount = bindings[":amount"].value.as_u128()?;
+    let freeze = match bindings.get(":freeze") {
+        Some(flag) => {
+            if flag.value.as_boolean()? {
+                FreezeFlag::Frozen
+            } else {
+                FreezeFlag::Unfrozen
+            }
+        }
+        None => FreezeFlag::Unfrozen,
+    };
+    let ro: sol::RecordOpening = wallet
+        .record_opening(asset_definition, address.into(), amount, freeze)
+        .await?
+        .into();
+    Ok(ro)
+}
+
+async fn proofofreserves(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ReservesAttestation, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let (sync_time, _) = wallet.scan_status().await.map_err(wallet_error)?;
+    let block_height = sync_time.index(EventSource::QueryService);
+
+    let mut balances = HashMap::new();
+    for pub_key in wallet.pub_keys().await {
+        let balance = wallet.balance_breakdown(&pub_key.address(), &asset).await;
+        balances.insert(pub_key.address().into(), balance);
+    }
+    attest_reserves(wallet, asset, block_height, &balances).await
+}
+
+async fn balanceproof(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ReservesAttestation, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let block_height = bindings[":block"].value.as_u64()? as usize;
+    let state = historical_state(wallet, block_height).await?;
+
+    let mut balances = HashMap::<UserAddress, U256>::new();
+    for record in state.records {
+        if record.ro.asset_def.code == asset {
+            *balances
+                .entry(record.ro.pub_key.address().into())
+                .or_default() += U256::from(record.ro.amount.generic_into::<u128>());
+        }
+    }
+    attest_reserves(wallet, asset, block_height, &balances).await
+}
+
+/// Sign the balance of `asset` held by each of the wallet's sending accounts at `block_height`.
+///
+/// Accounts missing from `balances` are attested to hold none of `asset`.
+async fn attest_reserves(
+    wallet: &Wallet,
+    asset: AssetCode,
+    block_height: usize,
+    balances: &HashMap<UserAddress, U256>,
+) -> Result<ReservesAttestation, tide::Error> {
+    let mut total = U256::zero();
+    let mut accounts = Vec::new();
+    for pub_key in wallet.pub_keys().await {
+        let balance = balances
+            .get(&UserAddress::from(pub_key.address()))
+            .copied()
+            .unwrap_or_default();
+        let key_pair = wallet
+            .get_user_private_key(&pub_key.address())
+            .await
+            .map_err(wallet_error)?;
+        let signature = key_pair.sign(&ReservesAttestation::message(
+            &asset,
+            block_height,
+            &pub_key,
+            &balance,
+        ));
+        total += balance;
+        accounts.push(AccountAttestation {
+            pub_key,
+            balance,
+            signature,
+        });
+    }
+
+    Ok(ReservesAttestation {
+        asset,
+        total,
+        block_height,
+        accounts,
+    })
+}
+
+async fn recordmemo(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<sol::RecordOpening, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let uid = bindings[":uid"].value.as_u64()?;
+
+    // The plaintext of a CAPE receiver memo is the opening of the record it was attached to. The
+    // wallet decrypts memos as it scans the ledger, so for any record we own, the decrypted memo is
+    // just the opening we already have.
+    let record = wallet
+        .records()
+        .await
+        .find(|record| record.uid == uid)
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("uid of a record owned by this wallet"),
+                actual: uid.to_string(),
+            })
+        })?;
+    Ok(record.ro.into())
+}
+
+async fn cache(
+    route_params: &[&str],
+    cache: &Mutex<ResponseCache>,
+) -> Result<CacheStats, tide::Error> {
+    let mut cache = cache.lock().await;
+    if route_params[0] == "clear" {
+        cache.clear();
+    }
+    Ok(cache.stats())
+}
+
+async fn subscriptions(
+    bindings: &HashMap<String, RouteBinding>,
+    subscriptions: &Mutex<Subscriptions>,
+) -> Result<Vec<SubscriptionInfo>, tide::Error> {
+    let mut subscriptions = subscriptions.lock().await;
+    if let Some(id) = bindings.get(":id") {
+        let id = id.value.as_u64()?;
+        if !subscriptions.close(id) {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("id of an open subscription"),
+                actual: id.to_string(),
+            }));
+        }
+    }
+    Ok(subscriptions.list())
+}
+
+async fn transactionhistory(
+    bindings: &HashMap<String, RouteBinding>,
+    asof: Option<usize>,
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
+    pruned: &Mutex<PrunedTransactions>,
+    wallet: &mut Option<Wallet>,
+) -> Result<
+    (
+        Vec<TransactionHistoryEntry>,
+        HashMap<AssetCode, Option<AssetInfo>>,
+    ),
+    tide::Error,
+> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = read_last_path(options).await?;
+    let mut history = wallet.transaction_history().await.map_err(wallet_error)?;
+    if let Some(wallet_path) = &wallet_path {
+        let pruned = pruned.lock().await;
+        history.retain(|entry| !is_pruned(&pruned, wallet_path, entry));
+    }
+    if let Some(asof) = asof {
+        // Only include transactions which had been committed as of `asof`.
+        let committed = historical_state(wallet, asof).await?.transactions;
+        history.retain(|entry| match &entry.hash {
+            Some(hash) => transaction_hash_string(hash)
+                .map(|hash| committed.contains(&hash))
+                .unwrap_or(false),
+            None => false,
+        });
+    }
+    let assets = known_assets(wallet).await;
+    let from = match bindings.get(":from") {
+        Some(param) => history.len().saturating_sub(param.value.as_usize()?),
+        None => 0,
+    };
+    let to = match bindings.get(":count") {
+        Some(param) => from + param.value.as_usize()?,
+        None => history.len(),
+    };
+    let mut selected = iter(history.into_iter().skip(from).take(to - from))
+        .then(|entry| TransactionHistoryEntry::from_wallet(wallet, entry))
+        .collect::<Vec<_>>()
+        .await;
+    if let Some(wallet_path) = wallet_path {
+        confirmations::apply(options, confirmations, wallet, &wallet_path, &mut selected).await?;
+    }
+    let asset_map = selected
+        .iter()
+        .map(|entry| (entry.asset, assets.get(&entry.asset).cloned()))
+        .collect::<HashMap<_, _>>();
+    Ok((selected, asset_map))
+}
+
+// Whether `entry` was pruned from the history of the wallet at `wallet_path`.
+fn is_pruned(
+    pruned: &PrunedTransactions,
+    wallet_path: &Path,
+    entry: &seahorse::txn_builder::TransactionHistoryEntry<CapeLedger>,
+) -> bool {
+    entry
+        .hash
+        .as_ref()
+        .and_then(transaction_hash_string)
+        .map(|hash| pruned.contains(wallet_path, &hash))
+        .unwrap_or(false)
+}
+
+async fn transactioncount(
+    options: &NodeOpt,
+    pruned: &Mutex<PrunedTransactions>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TransactionCount, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let history = wallet.transaction_history().await.map_err(wallet_error)?;
+    let pruned = pruned.lock().await;
+    let num_pruned = history
+        .iter()
+        .filter(|entry| is_pruned(&pruned, &wallet_path, entry))
+        .count();
+    Ok(TransactionCount {
+        transactions: history.len() - num_pruned,
+        pruned: num_pruned,
+    })
+}
+
+async fn prunetransactions(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
+    categories: &Mutex<Categories>,
+    pruned: &Mutex<PrunedTransactions>,
+    wallet: &mut Option<Wallet>,
+) -> Result<PrunedHistory, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let cutoff = bindings[":block"].value.as_u64()?;
+    let blocks = wallet.transaction_blocks().await.map_err(wallet_error)?;
+
+    let mut pruned = pruned.lock().await;
+    let history = wallet
+        .transaction_history()
+        .await
+        .map_err(wallet_error)?
+        .into_iter()
+        .filter(|entry| !is_pruned(&pruned, &wallet_path, entry))
+        .collect::<Vec<_>>();
+    let mut entries = iter(history)
+        .then(|entry| TransactionHistoryEntry::from_wallet(wallet, entry))
+        .collect::<Vec<_>>()
+        .await;
+    confirmations::apply(options, confirmations, wallet, &wallet_path, &mut entries).await?;
+
+    let categories = categories.lock().await;
+    let mut removed = vec![];
+    for entry in &entries {
+        // Only transactions which were committed before the cutoff and have all their
+        // confirmations are pruned. Transactions with a category are kept, since they still
+        // contribute to reports.
+        let hash = match &entry.hash {
+            Some(hash) => hash,
+            None => continue,
+        };
+        let committed = matches!(blocks.get(hash), Some(block) if *block < cutoff);
+        if committed && entry.status == "accepted" && categories.get(&wallet_path, hash).is_none() {
+            removed.push(hash.clone());
+        }
+    }
+    if !removed.is_empty() {
+        for hash in &removed {
+            pruned.insert(&wallet_path, hash.clone());
+        }
+        pruned.save(&options.pruned_path())?;
+    }
+    Ok(PrunedHistory {
+        remaining: entries.len() - removed.len(),
+        pruned: removed,
+    })
+}
+
+async fn receipt(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
+    sent: &Mutex<SentTransfers>,
+    asset_metadata: &Mutex<AssetMetadataStore>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Receipt, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let hash = bindings[":receipt"].value.as_identifier()?;
+    if hash.tag() != "HASH" {
+        return Err(server_error(CapeAPIError::Tag {
+            expected: String::from("HASH"),
+            actual: hash.tag(),
+        }));
+    }
+    let hash = hash.to_string();
+    let entry = wallet
+        .transaction_history()
+        .await
+        .map_err(wallet_error)?
+        .into_iter()
+        .find(|entry| {
+            entry
+                .hash
+                .as_ref()
+                .and_then(transaction_hash_string)
+                .as_ref()
+                == Some(&hash)
+        })
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("a transaction in the wallet's history"),
+                actual: hash.clone(),
+            })
+        })?;
+    let amount = entry
+        .receivers
+        .iter()
+        .fold(U256::zero(), |total, (_, amount)| {
+            total + U256::from((*amount).generic_into::<u128>())
+        });
+    let mut entries = [TransactionHistoryEntry::from_wallet(wallet, entry).await];
+    confirmations::apply(options, confirmations, wallet, &wallet_path, &mut entries).await?;
+    let [entry] = entries;
+
+    let decimals = asset_metadata
+        .lock()
+        .await
+        .get(&entry.asset)
+        .and_then(|metadata| metadata.decimals)
+        .unwrap_or(0);
+    // The transaction has one confirmation as of the event which committed it.
+    let now = wallet.now().await.index(EventSource::QueryService);
+    let depth = wallet
+        .transaction_event(&hash)
+        .await
+        .map_err(wallet_error)?
+        .map(|index| now - index);
+    Ok(Receipt {
+        time: entry.time,
+        kind: entry.kind,
+        from: entry.senders,
+        to: entry
+            .receivers
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect(),
+        asset: entry.asset,
+        symbol: wallet.asset(entry.asset).await.and_then(|info| info.name),
+        amount: amount.to_string(),
+        display_amount: Decimal::from_units(amount, decimals as u32).to_string(),
+        fee: sent
+            .lock()
+            .await
+            .get(&wallet_path, &hash)
+            .map(|params| params.fee.to_string()),
+        status: entry.status,
+        confirmations: depth,
+        required_confirmations: confirmations.lock().await.depth(&wallet_path),
+        hash,
+    })
+}
+
+async fn categorize(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    categories: &Mutex<Categories>,
+    wallet: &mut Option<Wallet>,
+) -> Result<(), tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    if method != Method::Post {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("POST request to categorize a transaction"),
+            actual: method.to_string(),
+        }));
+    }
+    let wallet_path = require_wallet_path(options).await?;
+    let hash = bindings[":receipt"].value.as_identifier()?;
+    if hash.tag() != "HASH" {
+        return Err(server_error(CapeAPIError::Tag {
+            expected: String::from("HASH"),
+            actual: hash.tag(),
+        }));
+    }
+    let hash = hash.to_string();
+    let history = wallet.transaction_history().await.map_err(wallet_error)?;
+    if !history.iter().any(|entry| {
+        entry
+            .hash
+            .as_ref()
+            .and_then(transaction_hash_string)
+            .as_ref()
+            == Some(&hash)
+    }) {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a transaction in the wallet's history"),
+            actual: hash,
+        }));
+    }
+    let category = bindings[":category"].value.as_string()?;
+    let mut categories = categories.lock().await;
+    categories.set(&wallet_path, hash, category);
+    categories.save(&options.categories_path())?;
+    Ok(())
+}
+
+async fn memstats(
+    cache: &Mutex<ResponseCache>,
+    balance_index: &Mutex<BalanceIndex>,
+    wallet: &mut Option<Wallet>,
+) -> Result<MemStats, tide::Error> {
+    // Sizes are estimated from the size of each entry's type, plus the heap data which dominates
+    // the size of some entries, without walking every allocation.
+    let (records, transactions) = match wallet {
+        Some(wallet) => {
+            let records = wallet.records().await.count();
+            let history = wallet.transaction_history().await.map_err(wallet_error)?;
+            let bytes = history
+                .iter()
+                .map(|entry| {
+                    size_of_val(entry) + slice_bytes(&entry.receivers) + slice_bytes(&entry.senders)
+                })
+                .sum();
+            (
+                MemUsage {
+                    count: records,
+                    bytes: records * size_of::<RecordInfo>(),
+                },
+                MemUsage {
+                    count: history.len(),
+                    bytes,
+                },
+            )
+        }
+        None => Default::default(),
+    };
+    let cache = cache.lock().await;
+    let cache_stats = cache.stats();
+    Ok(MemStats {
+        records,
+        transactions,
+        response_cache: MemUsage {
+            count: cache_stats.size,
+            bytes: cache_stats.bytes,
+        },
+        balance_index: balance_index.lock().await.memory(),
+        cache_budget: cache.budget(),
+    })
+}
+
+async fn nullifierstats(wallet: &mut Option<Wallet>) -> Result<NullifierStats, tide::Error> {
+    let counts = require_wallet(wallet)?.nullifier_counts().await;
+    Ok(NullifierStats {
+        usage: MemUsage {
+            count: counts.total,
+            bytes: counts.total * size_of::<(Nullifier, bool)>(),
+        },
+        spent: counts.spent,
+        prunable: counts.prunable,
+    })
+}
+
+/// The size of the elements of `slice`, not counting any heap data they own.
+fn slice_bytes<T>(slice: &[T]) -> usize {
+    slice.len() * size_of::<T>()
+}
+
+async fn assetactivity(wallet: &mut Option<Wallet>) -> Result<Vec<AssetActivity>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let mut last_activity = HashMap::<AssetCode, i64>::new();
+    for entry in wallet.transaction_history().await.map_err(wallet_error)? {
+        let time = last_activity.entry(entry.asset).or_insert(i64::MIN);
+        *time = (*time).max(entry.time.timestamp());
+    }
+    let held = wallet
+        .records()
+        .await
+        .filter(|record| {
+            record.ro.freeze_flag == FreezeFlag::Unfrozen
+                && record.ro.amount.generic_into::<u128>() > 0
+        })
+        .map(|record| record.ro.asset_def.code)
+        .collect::<HashSet<_>>();
+
+    let mut assets = wallet
+        .assets()
+        .await
+        .into_iter()
+        .map(|asset| asset.definition.code)
+        .chain(last_activity.keys().copied())
+        .chain(held.iter().copied())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|asset| AssetActivity {
+            asset,
+            last_activity: last_activity.get(&asset).copied(),
+            has_balance: held.contains(&asset),
+        })
+        .collect::<Vec<_>>();
+    // Most recently active first, then assets with no activity. Break ties by asset code so the
+    // order is deterministic.
+    assets.sort_by(|a, b| {
+        b.last_activity
+            .cmp(&a.last_activity)
+            .then_with(|| a.asset.to_string().cmp(&b.asset.to_string()))
+    });
+    Ok(assets)
+}
+
+async fn assetsbyissuer(
+    sponsors: &Mutex<Sponsors>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<IssuerAssets>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let sponsors = sponsors.lock().await;
+    let known_sponsors = sponsors.addresses().collect::<HashSet<_>>();
+    let mut groups = BTreeMap::<String, Vec<AssetCode>>::new();
+    for asset in wallet.assets().await {
+        let definition = &asset.definition;
+        let issuer = if definition.code == AssetCode::native() {
+            String::from(ISSUER_NATIVE)
+        } else if let Some(erc20_code) = wallet.wrapped_erc20(definition.code).await {
+            // The code of a wrapped asset commits to its sponsor, so a sponsor we know of can be
+            // checked against the definition even if the asset was sponsored elsewhere.
+            sponsors
+                .get(&definition.code)
+                .into_iter()
+                .chain(known_sponsors.iter().copied())
+                .find(|sponsor| {
+                    is_erc20_asset_def_valid(definition, &erc20_code, &(*sponsor).into())
+                })
+                .map(|sponsor| format!("{:#x}", sponsor))
+                .unwrap_or_else(|| String::from(ISSUER_UNKNOWN))
+        } else if asset.mint_info.is_some() {
+            // Only the wallet which defined a domestic asset knows its mint info.
+            String::from(ISSUER_WALLET)
+        } else {
+            String::from(ISSUER_UNKNOWN)
+        };
+        groups.entry(issuer).or_default().push(definition.code);
+    }
+
+    // Known issuers in order, then the assets whose issuer is unknown.
+    let unknown = groups.remove(ISSUER_UNKNOWN);
+    Ok(groups
+        .into_iter()
+        .chain(unknown.map(|assets| (String::from(ISSUER_UNKNOWN), assets)))
+        .map(|(issuer, mut assets)| {
+            assets.sort_by_key(|asset| asset.to_string());
+            IssuerAssets { issuer, assets }
+        })
+        .collect())
+}
+
+async fn report(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    categories: &Mutex<Categories>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<CategoryReport>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let from = match bindings.get(":from") {
+        Some(param) => param.value.as_u64()? as i64,
+        None => i64::MIN,
+    };
+    let to = match bindings.get(":to") {
+        Some(param) => param.value.as_u64()? as i64,
+        None => i64::MAX,
+    };
+    let categories = categories.lock().await;
+    let mut reports: Vec<CategoryReport> = vec![];
+    for entry in wallet.transaction_history().await.map_err(wallet_error)? {
+        let time = entry.time.timestamp();
+        if time < from || time >= to {
+            continue;
         }
-        "FREEZEPUBKEY" => {
-            Ok(Account::from_info(wallet, wallet.freezing_account(&address.to()?).await?).await)
+        let category = match entry
+            .hash
+            .as_ref()
+            .and_then(transaction_hash_string)
+            .and_then(|hash| categories.get(&wallet_path, &hash).map(String::from))
+        {
+            Some(category) => category,
+            None => continue,
+        };
+        let report = match reports
+            .iter()
+            .position(|report| report.category == category)
+        {
+            Some(i) => &mut reports[i],
+            None => {
+                reports.push(CategoryReport {
+                    category,
+                    transactions: 0,
+                    totals: HashMap::new(),
+                });
+                reports.last_mut().unwrap()
+            }
+        };
+        report.transactions += 1;
+        let total = report.totals.entry(entry.asset).or_default();
+        for (_, amount) in entry.receivers {
+            *total += U256::from(amount.generic_into::<u128>());
         }
-        tag => Err(server_error(CapeAPIError::Tag {
-            expected: String::from("ADDR | USERPUBKEY | AUDPUBKEY | FREEZEPUBKEY"),
-            actual: String::from(tag),
-        })),
     }
+    reports.sort_by(|a, b| a.category.cmp(&b.category));
+    Ok(reports)
 }
 
-async fn getaccounts(
-    route_params: &[&str],
+async fn feestotal(
+    bindings: &HashMap<String, RouteBinding>,
     wallet: &mut Option<Wallet>,
-) -> Result<Vec<Account>, tide::Error> {
+) -> Result<FeeTotal, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let selection = route_params[0];
-    let mut accounts = Vec::new();
-
-    if selection == "sending" || selection == "all" {
-        for key in wallet.pub_keys().await {
-            accounts.push(
-                Account::from_info(
-                    wallet,
-                    wallet
-                        .sending_account(&key.address())
-                        .await
-                        .map_err(wallet_error)?,
-                )
-                .await,
-            );
+    let from = match bindings.get(":from") {
+        Some(param) => param.value.as_u64()? as i64,
+        None => i64::MIN,
+    };
+    let to = match bindings.get(":to") {
+        Some(param) => param.value.as_u64()? as i64,
+        None => i64::MAX,
+    };
+    let fees = wallet.transaction_fees().await.map_err(wallet_error)?;
+    let mut counted = HashSet::new();
+    let mut total = FeeTotal {
+        transactions: 0,
+        totals: HashMap::new(),
+    };
+    for entry in wallet.transaction_history().await.map_err(wallet_error)? {
+        let time = entry.time.timestamp();
+        if time < from || time >= to {
+            continue;
         }
-    }
-    if selection == "viewing" || selection == "all" {
-        for key in wallet.auditor_pub_keys().await {
-            accounts.push(
-                Account::from_info(
-                    wallet,
-                    wallet.viewing_account(&key).await.map_err(wallet_error)?,
-                )
-                .await,
-            );
+        // Only transactions the wallet built pay a fee from its balance.
+        let paid = matches!(
+            entry.kind,
+            CapeTransactionKind::CAP(
+                cap::TransactionKind::Send
+                    | cap::TransactionKind::Mint
+                    | cap::TransactionKind::Freeze
+                    | cap::TransactionKind::Unfreeze
+            ) | CapeTransactionKind::Burn
+        );
+        if !paid {
+            continue;
         }
-    }
-    if selection == "freezing" || selection == "all" {
-        for key in wallet.freezer_pub_keys().await {
-            accounts.push(
-                Account::from_info(
-                    wallet,
-                    wallet.freezing_account(&key).await.map_err(wallet_error)?,
-                )
-                .await,
-            );
+        // Transactions which have not been committed have not paid their fee yet.
+        let (hash, fee) = match entry
+            .hash
+            .as_ref()
+            .and_then(transaction_hash_string)
+            .and_then(|hash| fees.get(&hash).map(|fee| (hash, *fee)))
+        {
+            Some(paid) => paid,
+            None => continue,
+        };
+        if counted.insert(hash) {
+            total.transactions += 1;
+            // CAPE fees are always paid in the native asset.
+            *total.totals.entry(AssetCode::native()).or_default() += U256::from(fee);
         }
     }
+    Ok(total)
+}
 
-    Ok(accounts)
+async fn params() -> Result<ProofParams, tide::Error> {
+    Ok(ProofParams::cape())
 }
 
-async fn updateasset(
-    req: &mut Request<WebState>,
-    bindings: &HashMap<String, RouteBinding>,
-    wallet: &mut Option<Wallet>,
-) -> Result<AssetInfo, tide::Error> {
+async fn pendingspends(wallet: &mut Option<Wallet>) -> Result<Vec<Record>, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let code = bindings[":asset"].value.to::<AssetCode>()?;
-
-    // Get the existing asset information.
-    let mut asset = wallet
-        .asset(code)
+    Ok(wallet
+        .records()
         .await
-        .ok_or_else(|| wallet_error(CapeWalletError::UndefinedAsset { asset: code }))?;
+        .filter(|record| record.hold_until.is_some())
+        .map(Record::from)
+        .collect())
+}
 
-    // Update based on request parameters.
-    let params: UpdateAsset = request_body(req).await?;
-    if let Some(symbol) = params.symbol {
-        asset = asset.with_name(symbol);
-    }
-    if let Some(description) = params.description {
-        asset = asset.with_description(description);
-    }
-    if let Some(icon) = params.icon {
-        let bytes = base64::decode(&icon)?;
-        let icon = Icon::load_png(Cursor::new(bytes))?;
-        asset = asset.with_icon(icon);
+async fn pendingmemos(
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<TransactionHistoryEntry>, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let history = wallet.transaction_history().await.map_err(wallet_error)?;
+    let mut pending = vec![];
+    for entry in history {
+        // Only transactions we submitted have a receipt, and only those have memos for us to post.
+        let status = match &entry.receipt {
+            Some(receipt) => wallet.transaction_status(receipt).await,
+            None => continue,
+        };
+        if matches!(
+            status,
+            Ok(TransactionStatus::Pending | TransactionStatus::AwaitingMemos)
+        ) {
+            pending.push(TransactionHistoryEntry::from_wallet(wallet, entry).await);
+        }
     }
+    Ok(pending)
+}
 
-    // Update the asset info in the wallet.
-    wallet.import_asset(asset).await.map_err(wallet_error)?;
+/// How long a snapshot taken by `snapshot` remains available to `diff`.
+const SNAPSHOT_TTL: Duration = Duration::from_secs(60 * 60);
 
-    // Get the final asset info, which may be different than what we imported if, say, the asset is
-    // a verified asset that cannot be overridden.
-    Ok(AssetInfo::from_info(
+fn prune_snapshots(snapshots: &mut HashMap<u64, (Instant, WalletSnapshot)>) {
+    snapshots.retain(|_, (time, _)| time.elapsed() < SNAPSHOT_TTL);
+}
+
+async fn simulate(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    cache: &Mutex<ResponseCache>,
+    balance_index: &Mutex<BalanceIndex>,
+    quarantine: &Mutex<Quarantine>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TransferSimulation, tide::Error> {
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let amount = U256::from(bindings[":amount"].value.as_u128()?);
+    let recipient = bindings[":recipient"].value.to::<UserAddress>()?;
+    let fee = match bindings.get(":fee") {
+        Some(fee) => U256::from(fee.value.as_u128()?),
+        None => U256::zero(),
+    };
+
+    // Notified records cannot be spent until the wallet receives their memos, so they are left out.
+    let BalanceInfo { balances, assets } = getbalance(
+        &HashMap::new(),
+        None,
+        options,
+        cache,
+        balance_index,
+        quarantine,
+        None,
         wallet,
-        wallet
-            .asset(code)
-            .await
-            .expect("Updated asset not in the wallet's asset storage"),
     )
-    .await)
+    .await?;
+    let by_account = match balances {
+        Balances::All { by_account, .. } => by_account,
+        // With no address or asset bindings, `getbalance` always returns all balances.
+        _ => unreachable!(),
+    };
+    Ok(TransferSimulation::new(
+        by_account, assets, asset, amount, fee, &recipient,
+    ))
 }
 
-pub async fn exportasset(
+async fn cansend(
     bindings: &HashMap<String, RouteBinding>,
     wallet: &mut Option<Wallet>,
-) -> Result<String, tide::Error> {
+) -> Result<CanSend, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let code = bindings[":asset"].value.to::<AssetCode>()?;
-    let mut asset = wallet
-        .asset(code)
-        .await
-        .ok_or(CapeWalletError::UndefinedAsset { asset: code })?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let amount = U256::from(bindings[":amount"].value.as_u128()?);
+    // The recipient does not affect whether we can build the transfer, but we still require it to
+    // be a valid address.
+    bindings[":recipient"].value.to::<UserAddress>()?;
+    let fee = match bindings.get(":fee") {
+        Some(fee) => U256::from(fee.value.as_u128()?),
+        None => U256::zero(),
+    };
 
-    // Don't export mint info, we don't want other users to be able to mint this asset just because
-    // we've published it on a registry.
-    asset.mint_info = None;
+    Ok(CanSend::check(
+        wallet.records().await,
+        asset,
+        amount,
+        fee,
+        ProofParams::cape().max_transfer_inputs,
+    ))
+}
 
-    // The `AssetInfo` structure serializes as a JSON blob, but for exporting and transmitting, a
-    // compact, URL-safe string is more convenient. Therefore, we serialize to bytes and then encode
-    // in base64.
-    let bytes = bincode::serialize(&asset).expect("Failed to serialize asset");
-    Ok(TaggedBase64::new("CAPE-ASSET", &bytes)
-        .expect("Failed to encode serialized asset")
-        .to_string())
+async fn changepreview(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    coin_selection: &Mutex<CoinSelection>,
+    wallet: &mut Option<Wallet>,
+) -> Result<ChangePreview, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let strategy = coin_selection.lock().await.get(&wallet_path);
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let amount = U256::from(bindings[":amount"].value.as_u128()?);
+    let fee = match bindings.get(":fee") {
+        Some(fee) => U256::from(fee.value.as_u128()?),
+        None => U256::zero(),
+    };
+
+    ChangePreview::select_with(
+        wallet.records().await,
+        asset,
+        amount,
+        fee,
+        ProofParams::cape().max_transfer_inputs,
+        strategy,
+    )
+    .map_err(|blocker| {
+        server_error(CapeAPIError::Param {
+            expected: String::from("a transfer the wallet can build"),
+            actual: format!("{} ({})", blocker, blocker.code()),
+        })
+    })
 }
 
-pub async fn importasset(
-    request: &mut Request<WebState>,
+async fn anomalies(
+    options: &NodeOpt,
+    cache: &Mutex<ResponseCache>,
+    balance_index: &Mutex<BalanceIndex>,
     wallet: &mut Option<Wallet>,
-) -> Result<AssetInfo, tide::Error> {
+) -> Result<Vec<ReplayedRecord>, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let tb64 =
-        TaggedBase64::parse(&request_body::<String, _>(request).await?).map_err(|source| {
-            server_error(CapeAPIError::Deserialize {
-                msg: source.to_string(),
-            })
-        })?;
-    if tb64.tag() != "CAPE-ASSET" {
-        return Err(server_error(CapeAPIError::Tag {
-            expected: "CAPE-ASSET".into(),
-            actual: tb64.tag(),
+    let wallet_path = read_last_path(options).await?.unwrap_or_default();
+    let generation = cache.lock().await.generation();
+    let mut balance_index = balance_index.lock().await;
+    balance_index.update(wallet, &wallet_path, generation).await;
+    Ok(balance_index.replayed())
+}
+
+async fn totalcost(
+    req: &mut Request<WebState>,
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<TotalCost, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let fee = match bindings.get(":fee") {
+        Some(fee) => U256::from(fee.value.as_u128()?),
+        // Without a fee, estimate that each transaction pays the smallest fee the relayer accepts.
+        None => U256::from(wallet.min_fee().await.map_err(wallet_error)?),
+    };
+    let transfers = request_body::<Vec<PlannedTransfer>, _>(req).await?;
+    Ok(TotalCost::new(
+        wallet.records().await,
+        &transfers,
+        fee,
+        ProofParams::cape().max_transfer_outputs,
+    ))
+}
+
+async fn recordages(
+    bindings: &HashMap<String, RouteBinding>,
+    wallet: &mut Option<Wallet>,
+) -> Result<RecordAges, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let asset = bindings[":asset"].value.to::<AssetCode>()?;
+    let width = match bindings.get(":blocks") {
+        Some(blocks) => blocks.value.as_u64()?,
+        None => 100,
+    };
+    if width == 0 {
+        return Err(server_error(CapeAPIError::Param {
+            expected: String::from("a positive bucket width"),
+            actual: width.to_string(),
         }));
     }
-    let bytes = tb64.value();
-    let asset = bincode::deserialize::<seahorse::AssetInfo>(&bytes).map_err(|err| {
-        server_error(CapeAPIError::Deserialize {
-            msg: err.to_string(),
+
+    let amounts = wallet
+        .records()
+        .await
+        .filter(|record| record.ro.asset_def.code == asset)
+        .map(|record| {
+            (
+                record.uid,
+                U256::from(record.ro.amount.generic_into::<u128>()),
+            )
         })
-    })?;
-    let code = asset.definition.code;
-    wallet.import_asset(asset).await.map_err(wallet_error)?;
+        .collect::<HashMap<_, _>>();
+    let (height, blocks) = wallet
+        .record_blocks(&amounts.keys().copied().collect())
+        .await
+        .map_err(wallet_error)?;
+    Ok(RecordAges::new(
+        asset,
+        height,
+        blocks
+            .into_iter()
+            .map(|(uid, block)| (amounts[&uid], block)),
+        width,
+    ))
+}
 
-    // Get the asset info from the wallet, which may be different from what we imported if the
-    // wallet already had this asset and merely updated part of it.
-    let info = wallet
-        .asset(code)
+async fn linkability(
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    coin_selection: &Mutex<CoinSelection>,
+    wallet: &mut Option<Wallet>,
+) -> Result<Linkability, tide::Error> {
+    let preview = changepreview(bindings, options, coin_selection, wallet).await?;
+    let wallet = require_wallet(wallet)?;
+    let records = wallet.records().await.collect::<Vec<_>>();
+    let used_addresses = wallet
+        .transaction_history()
         .await
-        .expect("Imported asset not in the wallet's asset storage");
-    Ok(AssetInfo::from_info(wallet, info).await)
+        .map_err(wallet_error)?
+        .into_iter()
+        .flat_map(|entry| {
+            entry
+                .senders
+                .into_iter()
+                .chain(entry.receivers.into_iter().map(|(address, _)| address))
+        })
+        .map(UserAddress::from)
+        .collect::<HashSet<_>>();
+    Ok(Linkability::analyze(&preview, &records, &used_addresses))
 }
 
-async fn recordopening(
+async fn coinselection(
+    method: Method,
     bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    coin_selection: &Mutex<CoinSelection>,
     wallet: &mut Option<Wallet>,
-) -> Result<sol::RecordOpening, tide::Error> {
+) -> Result<SelectionStrategy, tide::Error> {
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut coin_selection = coin_selection.lock().await;
+    if let Some(strategy) = bindings.get(":strategy") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to set a selection strategy"),
+                actual: method.to_string(),
+            }));
+        }
+        let strategy = strategy.value.as_string()?;
+        let strategy = strategy.parse().map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("selection strategy (largest, smallest or privacy)"),
+                actual: strategy,
+            })
+        })?;
+        coin_selection.set(&wallet_path, strategy);
+        coin_selection.save(&options.coin_selection_path())?;
+    }
+    Ok(coin_selection.get(&wallet_path))
+}
+
+async fn minanonymity(
+    method: Method,
+    bindings: &HashMap<String, RouteBinding>,
+    options: &NodeOpt,
+    min_anonymity: &Mutex<MinAnonymity>,
+    wallet: &mut Option<Wallet>,
+) -> Result<MinAnonymityStatus, tide::Error> {
     let wallet = require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(options).await?;
+    let mut min_anonymity = min_anonymity.lock().await;
+    if let Some(threshold) = bindings.get(":n") {
+        if method != Method::Post {
+            return Err(server_error(CapeAPIError::Param {
+                expected: String::from("POST request to set a minimum anonymity set"),
+                actual: method.to_string(),
+            }));
+        }
+        min_anonymity.set(&wallet_path, threshold.value.as_u64()?);
+        min_anonymity.save(&options.min_anonymity_path())?;
+    }
 
-    let address = bindings[":address"].value.to::<UserAddress>()?;
-    let asset_code = bindings[":asset"].value.to::<AssetCode>()?;
-    let asset_definition = wallet
-        .asset(asset_code)
+    let threshold = min_anonymity.get(&wallet_path);
+    let (frontier, _, _) = wallet.record_merkle_frontier().await;
+    let mut restricted = wallet
+        .records()
         .await
-        .expect("Asset code not in the wallet's asset storage")
-        .definition;
-    let amount = bindings[":amount"].value.as_u128()?;
-    let freeze = match bindings.get(":freeze") {
-        Some(flag) => {
-            if flag.value.as_boolean()? {
-                FreezeFlag::Frozen
-            } else {
-                FreezeFlag::Unfrozen
-            }
-        }
-        None => FreezeFlag::Unfrozen,
-    };
-    let ro: sol::RecordOpening = wallet
-        .record_opening(asset_definition, address.into(), amount, freeze)
-        .await?
-        .into();
-    Ok(ro)
+        .map(|record| record.uid)
+        .filter(|uid| anonymity_set(*uid, frontier.num_leaves) < threshold)
+        .collect::<Vec<_>>();
+    restricted.sort_unstable();
+    Ok(MinAnonymityStatus {
+        threshold,
+        ledger_size: frontier.num_leaves,
+        restricted,
+    })
+}
+
+async fn snapshot(
+    wallet: &mut Option<Wallet>,
+    rng: &mut ChaChaRng,
+    snapshots: &Mutex<HashMap<u64, (Instant, WalletSnapshot)>>,
+) -> Result<String, tide::Error> {
+    let wallet = require_wallet(wallet)?;
+    let snapshot = WalletSnapshot::from_wallet(wallet).await;
+    let mut snapshots = snapshots.lock().await;
+    prune_snapshots(&mut snapshots);
+    let id = rng.next_u64();
+    snapshots.insert(id, (Instant::now(), snapshot));
+    Ok(TaggedBase64::new("SNAPSHOT", &id.to_le_bytes())
+        .map_err(|err| {
+            server_error(CapeAPIError::Internal {
+                msg: err.to_string(),
+            })
+        })?
+        .to_string())
 }
 
-async fn transactionhistory(
+async fn diff(
     bindings: &HashMap<String, RouteBinding>,
     wallet: &mut Option<Wallet>,
-) -> Result<
-    (
-        Vec<TransactionHistoryEntry>,
-        HashMap<AssetCode, Option<AssetInfo>>,
-    ),
-    tide::Error,
-> {
+    snapshots: &Mutex<HashMap<u64, (Instant, WalletSnapshot)>>,
+) -> Result<WalletDiff, tide::Error> {
     let wallet = require_wallet(wallet)?;
-    let history = wallet.transaction_history().await.map_err(wallet_error)?;
-    let assets = known_assets(wallet).await;
-    let from = match bindings.get(":from") {
-        Some(param) => history.len().saturating_sub(param.value.as_usize()?),
-        None => 0,
-    };
-    let to = match bindings.get(":count") {
-        Some(param) => from + param.value.as_usize()?,
-        None => history.len(),
+    let token = bindings[":snapshot"].value.as_identifier()?;
+    if token.tag() != "SNAPSHOT" {
+        return Err(server_error(CapeAPIError::Tag {
+            expected: String::from("SNAPSHOT"),
+            actual: token.tag(),
+        }));
+    }
+    let unknown_snapshot = || {
+        server_error(CapeAPIError::Param {
+            expected: String::from("token of an unexpired snapshot"),
+            actual: token.to_string(),
+        })
     };
-    let selected = iter(history.into_iter().skip(from).take(to - from))
-        .then(|entry| TransactionHistoryEntry::from_wallet(wallet, entry))
-        .collect::<Vec<_>>()
-        .await;
-    let asset_map = selected
-        .iter()
-        .map(|entry| (entry.asset, assets.get(&entry.asset).cloned()))
-        .collect::<HashMap<_, _>>();
-    Ok((selected, asset_map))
+    let id = u64::from_le_bytes(token.value().try_into().map_err(|_| unknown_snapshot())?);
+
+    let mut snapshots = snapshots.lock().await;
+    prune_snapshots(&mut snapshots);
+    let (_, before) = snapshots.get(&id).ok_or_else(unknown_snapshot)?;
+    Ok(WalletDiff::new(
+        before,
+        &WalletSnapshot::from_wallet(wallet).await,
+    ))
 }
 
 async fn getprivatekey(
@@ -1355,6 +6905,120 @@ async fn getprivatekey(
     }
 }
 
+/// Routes whose reads can be combined in a `batch` request.
+const BATCH_ROUTES: &[&str] = &[
+    "getaccount",
+    "getaccounts",
+    "getaddress",
+    "getbalance",
+    "getinfo",
+    "getrecords",
+    "syncstatus",
+    "transactionhistory",
+];
+
+/// Answer a list of read-only requests, all with the same view of the wallet.
+///
+/// Every request is checked before any is answered, so a batch containing a request which is not a
+/// batchable read is rejected as a whole. Once the batch is accepted, each request succeeds or fails
+/// on its own.
+async fn batch(
+    req: &mut Request<WebState>,
+    state: &WebState,
+    wallet: &mut Option<Wallet>,
+) -> Result<Vec<BatchResult>, tide::Error> {
+    let paths = request_body::<Vec<String>, _>(req).await?;
+    let mut reads = vec![];
+    for path in &paths {
+        let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        let (pattern, bindings) = match_route(&state.api, &segments).map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("route path"),
+                actual: path.clone(),
+            })
+        })?;
+        let route = pattern.split('/').next().unwrap_or(&pattern);
+        if !BATCH_ROUTES.contains(&route) || modifies_wallet(Method::Get, &pattern) {
+            return Err(server_error(CapeAPIError::Param {
+                expected: format!("read-only route ({})", BATCH_ROUTES.join(", ")),
+                actual: path.clone(),
+            }));
+        }
+        reads.push((path.clone(), pattern, bindings));
+    }
+
+    let mut results = vec![];
+    for (path, pattern, bindings) in reads {
+        results.push(match batch_read(&pattern, &bindings, state, wallet).await {
+            Ok(result) => BatchResult {
+                path,
+                status: StatusCode::Ok as u16,
+                result: Some(result),
+                error: None,
+            },
+            Err(err) => BatchResult {
+                path,
+                status: err.status() as u16,
+                result: None,
+                error: Some(err.to_string()),
+            },
+        });
+    }
+    Ok(results)
+}
+
+async fn batch_read(
+    route_pattern: &str,
+    bindings: &HashMap<String, RouteBinding>,
+    state: &WebState,
+    wallet: &mut Option<Wallet>,
+) -> Result<serde_json::Value, tide::Error> {
+    let segments = route_pattern.split_once('/').unwrap_or((route_pattern, ""));
+    let route_params = segments.1.split('/').collect::<Vec<_>>();
+    let options = &state.options;
+    Ok(
+        match ApiRouteKey::from_str(segments.0).expect("Unknown route") {
+            ApiRouteKey::getaccount => serde_json::to_value(getaccount(bindings, wallet).await?)?,
+            ApiRouteKey::getaccounts => {
+                serde_json::to_value(getaccounts(&route_params, wallet).await?)?
+            }
+            ApiRouteKey::getaddress => serde_json::to_value(getaddress(wallet).await?)?,
+            ApiRouteKey::getbalance => serde_json::to_value(
+                getbalance(
+                    bindings,
+                    None,
+                    options,
+                    &state.cache,
+                    &state.balance_index,
+                    &state.quarantine,
+                    Some(&state.notified),
+                    wallet,
+                )
+                .await?,
+            )?,
+            ApiRouteKey::getinfo => {
+                serde_json::to_value(getinfo(options, &state.verifiers, wallet).await?)?
+            }
+            ApiRouteKey::getrecords => serde_json::to_value(get_records(None, wallet).await?)?,
+            ApiRouteKey::syncstatus => {
+                serde_json::to_value(syncstatus(&state.scan_interval, wallet).await?)?
+            }
+            ApiRouteKey::transactionhistory => serde_json::to_value(
+                transactionhistory(
+                    bindings,
+                    None,
+                    options,
+                    &state.confirmations,
+                    &state.pruned,
+                    wallet,
+                )
+                .await?,
+            )?,
+            _ => unreachable!(),
+        },
+    )
+}
+
 pub async fn dispatch_url(
     mut req: Request<WebState>,
     route_pattern: &str,
@@ -1363,75 +7027,772 @@ pub async fn dispatch_url(
     let segments = route_pattern.split_once('/').unwrap_or((route_pattern, ""));
     let route_params = segments.1.split('/').collect::<Vec<_>>();
     let state = req.state().clone();
+    let key = ApiRouteKey::from_str(segments.0).expect("Unknown route");
+    if state.options.route_disabled(&key) {
+        return Err(server_error(CapeAPIError::RouteDisabled {
+            route: key.to_string(),
+        }));
+    }
+    if let ApiRouteKey::closewallet = key {
+        // `closewallet` may have to wait for pending transactions to settle, so rather than holding
+        // the wallet lock for the whole request, it takes the lock itself whenever it needs it.
+        return response(&req, closewallet(&state.lock, &state.wallet).await?);
+    }
     let options = &state.options;
-    let rng = &mut *state.rng.lock().await;
+    // Traced operations release these locks before waiting for their transactions to be confirmed.
+    let mut rng_guard = state.rng.lock().await;
+    let rng = &mut *rng_guard;
     let faucet_key_pair = &state.faucet_key_pair;
-    let wallet = &mut *state.wallet.lock().await;
-    let key = ApiRouteKey::from_str(segments.0).expect("Unknown route");
+    let mut wallet_guard = state.wallet.lock().await;
+    let wallet = &mut *wallet_guard;
+    if submits_transaction(&key, &route_params) && *state.submission_paused.lock().await {
+        return Err(server_error(CapeAPIError::SubmissionPaused));
+    }
+    {
+        let mut lock = state.lock.lock().await;
+        if wallet.is_none() {
+            // The lock belongs to the open wallet, so it is released when the wallet is closed, for
+            // example by the idle timeout.
+            *lock = None;
+        } else if lock.is_some()
+            && !matches!(key, ApiRouteKey::unlock | ApiRouteKey::verifymnemonic)
+            && (modifies_wallet(req.method(), route_pattern)
+                || matches!(key, ApiRouteKey::getprivatekey))
+        {
+            return Err(server_error(CapeAPIError::WalletLocked));
+        }
+    }
     match key {
+        ApiRouteKey::auth => match route_params[0] {
+            "status" => response(&req, authstatus(&req, &state.admin_token).await?),
+            "rotate" => response(
+                &req,
+                authrotate(&req, rng, options, &state.admin_token).await?,
+            ),
+            "peer" => response(&req, req.ext::<PeerCertificate>().cloned()),
+            _ => unreachable!(),
+        },
+        ApiRouteKey::accounts => response(&req, accounts(wallet).await?),
+        ApiRouteKey::alerts => response(
+            &req,
+            alerts(req.method(), bindings, options, &state.alerts, wallet).await?,
+        ),
+        ApiRouteKey::anomalies => response(
+            &req,
+            anomalies(options, &state.cache, &state.balance_index, wallet).await?,
+        ),
+        ApiRouteKey::apiconfig => response(&req, state.api_integrity.clone()),
+        ApiRouteKey::asset => response(&req, assetbyerc20(bindings, wallet).await?),
+        ApiRouteKey::assetmetadata => {
+            let res = assetmetadata(
+                &mut req,
+                bindings,
+                options,
+                &state.asset_metadata,
+                &state.verifiers,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
+        ApiRouteKey::assets => match route_params[0] {
+            "activity" => response(&req, assetactivity(wallet).await?),
+            "byissuer" => response(&req, assetsbyissuer(&state.sponsors, wallet).await?),
+            _ => unreachable!(),
+        },
+        ApiRouteKey::audit => response(
+            &req,
+            audit(bindings, options, &state.viewing_capabilities, wallet).await?,
+        ),
+        ApiRouteKey::auditable => response(&req, auditable(wallet).await?),
+        ApiRouteKey::autoconsolidate => response(
+            &req,
+            autoconsolidate(
+                req.method(),
+                bindings,
+                options,
+                &state.autoconsolidate,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::backup => match route_params[0] {
+            "schedule" => response(
+                &req,
+                backupschedule(req.method(), bindings, options, &state.backups, wallet).await?,
+            ),
+            "now" => response(
+                &req,
+                backupnow(
+                    options,
+                    &state.backups,
+                    &state.contacts,
+                    &state.asset_metadata,
+                    wallet,
+                )
+                .await?,
+            ),
+            "restore" => response(
+                &req,
+                backuprestore(
+                    bindings,
+                    options,
+                    &state.backups,
+                    &state.contacts,
+                    &state.asset_metadata,
+                    wallet,
+                )
+                .await?,
+            ),
+            _ => unreachable!(),
+        },
+        ApiRouteKey::backups => response(&req, listbackups(options, &state.backups, wallet).await?),
+        ApiRouteKey::balanceindex => response(&req, state.balance_index.lock().await.stats()),
+        ApiRouteKey::balanceproof => response(&req, balanceproof(bindings, wallet).await?),
+        ApiRouteKey::batch => {
+            let res = batch(&mut req, &state, wallet).await?;
+            response(&req, res)
+        }
         ApiRouteKey::buildsponsor => response(&req, buildsponsor(bindings, wallet).await?),
         ApiRouteKey::buildwrap => response(&req, buildwrap(bindings, wallet).await?),
-        ApiRouteKey::closewallet => response(&req, closewallet(wallet).await?),
+        ApiRouteKey::cache => response(&req, cache(&route_params, &state.cache).await?),
+        ApiRouteKey::cansend => response(&req, cansend(bindings, wallet).await?),
+        ApiRouteKey::changepreview => response(
+            &req,
+            changepreview(bindings, options, &state.coin_selection, wallet).await?,
+        ),
+        ApiRouteKey::checkrecipient => response(&req, checkrecipient(bindings)?),
+        ApiRouteKey::checkwallet => response(&req, checkwallet(bindings, options, wallet).await?),
+        ApiRouteKey::clock => response(&req, clock(options, &state.clock, wallet).await?),
+        ApiRouteKey::closewallet => unreachable!(),
+        ApiRouteKey::coinselection => response(
+            &req,
+            coinselection(
+                req.method(),
+                bindings,
+                options,
+                &state.coin_selection,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::comparepolicy => {
+            let res = comparepolicy(&mut req, bindings, wallet).await?;
+            response(&req, res)
+        }
+        ApiRouteKey::confirmations => response(
+            &req,
+            confirmations(
+                req.method(),
+                bindings,
+                options,
+                &state.confirmations,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::congestion => response(&req, congestion(&state.congestion, wallet).await?),
+        ApiRouteKey::contacts => match route_params[0] {
+            "" => response(&req, listcontacts(&state.contacts).await?),
+            "import" => {
+                let res = importcontacts(&mut req, bindings, options, &state.contacts).await?;
+                response(&req, res)
+            }
+            _ => unreachable!(),
+        },
+        ApiRouteKey::conversions => response(
+            &req,
+            conversions(options, &state.conversions, wallet).await?,
+        ),
+        ApiRouteKey::erc20 => response(&req, erc20byasset(bindings, wallet).await?),
+        ApiRouteKey::errordetail => response(
+            &req,
+            errordetail(req.method(), bindings, &state.error_detail).await?,
+        ),
+        ApiRouteKey::export => response(&req, exportrecords(bindings, wallet).await?),
         ApiRouteKey::exportasset => response(&req, exportasset(bindings, wallet).await?),
-        ApiRouteKey::freeze => dummy_url_eval(route_pattern, bindings),
+        ApiRouteKey::exportmetadata => response(
+            &req,
+            exportmetadata(&state.contacts, &state.asset_metadata, wallet).await?,
+        ),
+        ApiRouteKey::exporttransaction => {
+            let (exported, trace) = exporttransaction(
+                bindings,
+                options,
+                &state.spend_limits,
+                &state.min_anonymity,
+                &state.proof_threads,
+                trace(&req)?,
+                wallet,
+            )
+            .await?;
+            traced_response(&req, exported, trace)
+        }
+        ApiRouteKey::failed => {
+            if route_params[0].is_empty() {
+                response(&req, failed(options, &state.sent, wallet).await?)
+            } else {
+                response(
+                    &req,
+                    resend(
+                        bindings,
+                        options,
+                        &state.sent,
+                        &state.min_anonymity,
+                        &state.proof_threads,
+                        wallet,
+                    )
+                    .await?,
+                )
+            }
+        }
+        ApiRouteKey::features => response(&req, features(options).await?),
+        ApiRouteKey::feerate => response(&req, feerate(&state.fee_rate, wallet).await?),
+        ApiRouteKey::fees => response(&req, feestotal(bindings, wallet).await?),
+        ApiRouteKey::feetopup => response(
+            &req,
+            feetopup(req.method(), bindings, options, &state.feetopup, wallet).await?,
+        ),
+        ApiRouteKey::freezable => response(&req, freezable(wallet).await?),
+        ApiRouteKey::freeze => response(
+            &req,
+            freeze(bindings, true, &state.proof_threads, wallet).await?,
+        ),
+        ApiRouteKey::frontier => response(&req, frontier(wallet).await?),
+        ApiRouteKey::fsck => response(
+            &req,
+            fsck(
+                &route_params,
+                options,
+                &state.cache,
+                &state.balance_index,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::gaplimit => response(
+            &req,
+            gaplimit(req.method(), bindings, &state.gap_limit).await?,
+        ),
+        ApiRouteKey::gasestimate => {
+            response(&req, gasestimate(&route_params, bindings, wallet).await?)
+        }
+        ApiRouteKey::diff => response(&req, diff(bindings, wallet, &state.snapshots).await?),
         ApiRouteKey::getaddress => response(&req, getaddress(wallet).await?),
         ApiRouteKey::getaccount => response(&req, getaccount(bindings, wallet).await?),
         ApiRouteKey::getaccounts => response(&req, getaccounts(&route_params, wallet).await?),
-        ApiRouteKey::getbalance => response(&req, getbalance(bindings, wallet).await?),
-        ApiRouteKey::getinfo => response(&req, getinfo(wallet).await?),
+        ApiRouteKey::getasset => response(
+            &req,
+            getasset(
+                bindings,
+                options,
+                &state.asset_metadata,
+                &state.verifiers,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::getbalance => response(
+            &req,
+            getbalance(
+                bindings,
+                asof(&req)?,
+                options,
+                &state.cache,
+                &state.balance_index,
+                &state.quarantine,
+                Some(&state.notified),
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::getinfo => {
+            let info = getinfo(options, &state.verifiers, wallet).await?;
+            match fields(&req) {
+                Some(fields) => response(&req, select_fields(&info, &fields)?),
+                None => response(&req, info),
+            }
+        }
         ApiRouteKey::getmnemonic => response(&req, getmnemonic(rng).await?),
+        ApiRouteKey::import => {
+            let res = importrecords(&mut req, rng, wallet).await?;
+            response(&req, res)
+        }
         ApiRouteKey::importasset => {
             let res = importasset(&mut req, wallet).await?;
             response(&req, res)
         }
         ApiRouteKey::getprivatekey => response(&req, getprivatekey(bindings, wallet).await?),
-        ApiRouteKey::getrecords => response(&req, get_records(wallet).await?),
+        ApiRouteKey::getrecords => response(&req, get_records(asof(&req)?, wallet).await?),
         ApiRouteKey::healthcheck => healthcheck().await,
+        ApiRouteKey::importmetadata => {
+            let res = importmetadata(
+                &mut req,
+                bindings,
+                options,
+                &state.contacts,
+                &state.asset_metadata,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
         ApiRouteKey::importkey => dummy_url_eval(route_pattern, bindings),
+        ApiRouteKey::keys => {
+            let res = keycoverage(&mut req, wallet).await?;
+            response(&req, res)
+        }
         ApiRouteKey::lastusedkeystore => response(&req, get_last_keystore(options).await?),
+        ApiRouteKey::linkability => response(
+            &req,
+            linkability(bindings, options, &state.coin_selection, wallet).await?,
+        ),
+        ApiRouteKey::linketh => match route_params.get(2) {
+            None => response(&req, listethlinks(&state.eth_links).await?),
+            Some(&"challenge") => {
+                response(&req, ethchallenge(bindings, rng, &state.eth_links).await?)
+            }
+            Some(&"signature") => response(
+                &req,
+                linketh(req.method(), bindings, options, &state.eth_links).await?,
+            ),
+            _ => unreachable!(),
+        },
         ApiRouteKey::listkeystores => response(&req, listkeystores(options).await?),
-        ApiRouteKey::mint => response(&req, mint(bindings, wallet).await?),
+        ApiRouteKey::lock => response(
+            &req,
+            lock(bindings, &route_params, &state.lock, rng, wallet).await?,
+        ),
+        ApiRouteKey::memstats => response(
+            &req,
+            memstats(&state.cache, &state.balance_index, wallet).await?,
+        ),
+        ApiRouteKey::mergewallet => response(
+            &req,
+            mergewallet(
+                options,
+                &state.scan_interval,
+                bindings,
+                &state.contacts,
+                rng,
+                faucet_key_pair,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::minanonymity => response(
+            &req,
+            minanonymity(
+                req.method(),
+                bindings,
+                options,
+                &state.min_anonymity,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::mint => {
+            let (receipt, mut trace) = mint(
+                bindings,
+                options,
+                &state.spend_limits,
+                &state.proof_threads,
+                trace(&req)?,
+                wallet,
+            )
+            .await?;
+            if let Some(trace) = &mut trace {
+                drop((rng_guard, wallet_guard));
+                confirm(&state.wallet, &receipt, trace).await?;
+            }
+            traced_response(&req, receipt, trace)
+        }
         ApiRouteKey::newasset => response(&req, newasset(bindings, wallet).await?),
+        ApiRouteKey::newassets => {
+            let res = newassets(&mut req, wallet).await?;
+            response(&req, res)
+        }
         ApiRouteKey::newkey => response(&req, newkey(&route_params, bindings, wallet).await?),
         ApiRouteKey::newwallet => response(
             &req,
-            newwallet(options, bindings, rng, faucet_key_pair, wallet).await?,
+            newwallet(
+                options,
+                &state.scan_interval,
+                bindings,
+                rng,
+                faucet_key_pair,
+                *state.gap_limit.lock().await,
+                wallet,
+            )
+            .await?,
         ),
+        ApiRouteKey::notifyrecord => {
+            let res = notifyrecord(
+                &mut req,
+                options,
+                &state.admin_token,
+                &state.notified,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
+        ApiRouteKey::nullifiers => response(&req, nullifierstats(wallet).await?),
         ApiRouteKey::openwallet => response(
             &req,
-            openwallet(options, bindings, rng, faucet_key_pair, wallet).await?,
+            openwallet(
+                options,
+                &state.scan_interval,
+                bindings,
+                rng,
+                faucet_key_pair,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::params => response(&req, params().await?),
+        ApiRouteKey::paymentrequest => response(
+            &req,
+            paymentrequest(
+                req.method(),
+                &route_params,
+                bindings,
+                rng,
+                options,
+                &state.payment_requests,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::pendingmemos => response(&req, pendingmemos(wallet).await?),
+        ApiRouteKey::pendingspends => response(&req, pendingspends(wallet).await?),
+        ApiRouteKey::portfolio => {
+            let res = portfoliovalue(&mut req, wallet).await?;
+            response(&req, res)
+        }
+        ApiRouteKey::previewimportkey => response(&req, previewimportkey(bindings, wallet).await?),
+        ApiRouteKey::proofconfig => response(
+            &req,
+            proofconfig(req.method(), bindings, &state.proof_threads).await?,
+        ),
+        ApiRouteKey::proofofreserves => response(&req, proofofreserves(bindings, wallet).await?),
+        ApiRouteKey::quarantine => response(
+            &req,
+            quarantine(
+                req.method(),
+                &route_params,
+                bindings,
+                options,
+                &state.quarantine,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::receipt => response(
+            &req,
+            receipt(
+                bindings,
+                options,
+                &state.confirmations,
+                &state.sent,
+                &state.asset_metadata,
+                wallet,
+            )
+            .await?,
         ),
+        ApiRouteKey::reconcile => match route_params[0] {
+            "onchain" => response(&req, reconcileonchain(bindings, wallet).await?),
+            _ => {
+                let res = reconcile(&mut req, wallet).await?;
+                response(&req, res)
+            }
+        },
+        ApiRouteKey::reconnect => response(&req, reconnect(&state.scan_interval, wallet).await?),
+        ApiRouteKey::record => match route_params[1] {
+            "provenance" => response(&req, record(bindings, wallet).await?),
+            "spendable" => response(
+                &req,
+                recordspendable(bindings, options, &state.min_anonymity, wallet).await?,
+            ),
+            _ => dummy_url_eval(route_pattern, bindings),
+        },
+        ApiRouteKey::recordages => response(&req, recordages(bindings, wallet).await?),
+        ApiRouteKey::recordmemo => response(&req, recordmemo(bindings, wallet).await?),
         ApiRouteKey::recordopening => response(&req, recordopening(bindings, wallet).await?),
         ApiRouteKey::recoverkey => {
-            response(&req, recoverkey(&route_params, bindings, wallet).await?)
+            let gap_limit = *state.gap_limit.lock().await;
+            response(
+                &req,
+                recoverkey(&route_params, bindings, gap_limit, wallet).await?,
+            )
         }
+        ApiRouteKey::recoveryestimate => response(
+            &req,
+            recoveryestimate(bindings, options, &state.gap_limit, wallet).await?,
+        ),
+        ApiRouteKey::report => response(
+            &req,
+            report(bindings, options, &state.categories, wallet).await?,
+        ),
         ApiRouteKey::resetpassword => response(
             &req,
-            resetpassword(options, bindings, rng, faucet_key_pair, wallet).await?,
+            resetpassword(
+                options,
+                &state.scan_interval,
+                bindings,
+                rng,
+                faucet_key_pair,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::routes => response(&req, state.route_stats.lock().await.summary()),
+        ApiRouteKey::scan => response(
+            &req,
+            scan(&route_params, bindings, &state.scan_interval, wallet).await?,
+        ),
+        ApiRouteKey::scanconfig => response(
+            &req,
+            scanconfig(req.method(), &route_params, bindings, &state.scan_interval).await?,
+        ),
+        ApiRouteKey::scangaps => response(
+            &req,
+            scangaps(req.method(), bindings, options, &state.autoretry, wallet).await?,
+        ),
+        ApiRouteKey::schedule => response(
+            &req,
+            schedule(
+                req.method(),
+                &route_params,
+                bindings,
+                options,
+                &state.schedules,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::schedules => response(&req, listschedules(&state.schedules).await?),
+        ApiRouteKey::seedfingerprint => response(&req, seedfingerprint(wallet).await?),
+        ApiRouteKey::send => {
+            let (receipt, mut trace) = send(
+                bindings,
+                options,
+                &state.spend_limits,
+                &state.sent,
+                &state.min_anonymity,
+                &state.proof_threads,
+                trace(&req)?,
+                wallet,
+            )
+            .await?;
+            if let Some(trace) = &mut trace {
+                drop((rng_guard, wallet_guard));
+                confirm(&state.wallet, &receipt, trace).await?;
+            }
+            traced_response(&req, receipt, trace)
+        }
+        ApiRouteKey::simulate => response(
+            &req,
+            simulate(
+                bindings,
+                options,
+                &state.cache,
+                &state.balance_index,
+                &state.quarantine,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::skippedevents => response(&req, skippedevents(route_params, wallet).await?),
+        ApiRouteKey::snapshot => response(&req, snapshot(wallet, rng, &state.snapshots).await?),
+        ApiRouteKey::spendlimit => response(
+            &req,
+            spendlimit(req.method(), bindings, options, &state.spend_limits).await?,
+        ),
+        ApiRouteKey::statedigest => response(&req, statedigest(wallet).await?),
+        ApiRouteKey::stealthaddress => response(&req, stealthaddress(wallet).await?),
+        ApiRouteKey::subaccount => response(&req, subaccount_address(bindings, wallet).await?),
+        ApiRouteKey::subaccounts => response(&req, subaccounts(bindings, wallet).await?),
+        ApiRouteKey::submission => response(
+            &req,
+            submission(
+                &req,
+                &route_params,
+                &state.admin_token,
+                &state.submission_paused,
+            )
+            .await?,
         ),
-        ApiRouteKey::send => response(&req, send(bindings, wallet).await?),
         ApiRouteKey::submitsponsor => {
-            let res = submitsponsor(&mut req, bindings, wallet).await?;
+            let res = submitsponsor(&mut req, bindings, options, &state.sponsors, wallet).await?;
             response(&req, res)
         }
         ApiRouteKey::submitwrap => {
-            let res = submitwrap(&mut req, bindings, wallet).await?;
+            let res = submitwrap(
+                &mut req,
+                bindings,
+                options,
+                &state.conversions,
+                &state.eth_links,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
+        ApiRouteKey::subscriptions => {
+            response(&req, subscriptions(bindings, &state.subscriptions).await?)
+        }
+        ApiRouteKey::swap => match route_params[0] {
+            "propose" => response(
+                &req,
+                swappropose(
+                    bindings,
+                    options,
+                    &state.spend_limits,
+                    &state.min_anonymity,
+                    &state.proof_threads,
+                    wallet,
+                )
+                .await?,
+            ),
+            "accept" => {
+                let res = swapaccept(
+                    &mut req,
+                    bindings,
+                    options,
+                    &state.spend_limits,
+                    &state.min_anonymity,
+                    &state.proof_threads,
+                    wallet,
+                )
+                .await?;
+                response(&req, res)
+            }
+            _ => unreachable!(),
+        },
+        ApiRouteKey::sweep => response(
+            &req,
+            sweep(
+                bindings,
+                options,
+                &state.scan_interval,
+                rng,
+                faucet_key_pair,
+                &state.proof_threads,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::syncstatus => response(&req, syncstatus(&state.scan_interval, wallet).await?),
+        ApiRouteKey::testvectors => response(&req, testvectors(bindings, options)?),
+        ApiRouteKey::totalcost => {
+            let res = totalcost(&mut req, bindings, wallet).await?;
             response(&req, res)
         }
-        ApiRouteKey::transaction => dummy_url_eval(route_pattern, bindings),
-        ApiRouteKey::transactionhistory => {
-            response(&req, transactionhistory(bindings, wallet).await?)
+        ApiRouteKey::transaction => match route_params[0] {
+            "category" => response(
+                &req,
+                categorize(req.method(), bindings, options, &state.categories, wallet).await?,
+            ),
+            _ => dummy_url_eval(route_pattern, bindings),
+        },
+        ApiRouteKey::transactionhistory => response(
+            &req,
+            transactionhistory(
+                bindings,
+                asof(&req)?,
+                options,
+                &state.confirmations,
+                &state.pruned,
+                wallet,
+            )
+            .await?,
+        ),
+        ApiRouteKey::transactions => match route_params[0] {
+            "count" => response(
+                &req,
+                transactioncount(options, &state.pruned, wallet).await?,
+            ),
+            "prune" => response(
+                &req,
+                prunetransactions(
+                    bindings,
+                    options,
+                    &state.confirmations,
+                    &state.categories,
+                    &state.pruned,
+                    wallet,
+                )
+                .await?,
+            ),
+            _ => unreachable!(),
+        },
+        ApiRouteKey::unfreeze => response(
+            &req,
+            freeze(bindings, false, &state.proof_threads, wallet).await?,
+        ),
+        ApiRouteKey::unlock => response(&req, unlock(bindings, &state.lock, wallet).await?),
+        ApiRouteKey::unwrap => {
+            let (receipt, mut trace) = unwrap(
+                bindings,
+                options,
+                &state.spend_limits,
+                &state.conversions,
+                &state.proof_threads,
+                trace(&req)?,
+                wallet,
+            )
+            .await?;
+            if let Some(trace) = &mut trace {
+                drop((rng_guard, wallet_guard));
+                confirm(&state.wallet, &receipt, trace).await?;
+            }
+            traced_response(&req, receipt, trace)
         }
-        ApiRouteKey::unfreeze => dummy_url_eval(route_pattern, bindings),
-        ApiRouteKey::unwrap => response(&req, unwrap(bindings, wallet).await?),
         ApiRouteKey::updateasset => {
             let res = updateasset(&mut req, bindings, wallet).await?;
             response(&req, res)
         }
+        ApiRouteKey::validatemnemonic => response(&req, validatemnemonic(bindings).await?),
+        ApiRouteKey::verifiers => {
+            let res = verifiers(&mut req, bindings, options, &state.verifiers, wallet).await?;
+            response(&req, res)
+        }
+        ApiRouteKey::verifyasset => {
+            let res = verifyasset(&mut req, options, &state.verifiers, wallet).await?;
+            response(&req, res)
+        }
+        ApiRouteKey::verifymnemonic => {
+            let res = verifymnemonic(&mut req, wallet).await?;
+            response(&req, res)
+        }
         ApiRouteKey::view => dummy_url_eval(route_pattern, bindings),
+        ApiRouteKey::viewingcapability => {
+            let res = viewingcapability(
+                &mut req,
+                bindings,
+                options,
+                rng,
+                &state.viewing_capabilities,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
         ApiRouteKey::waitforsponsor => {
             let res = waitforsponsor(&mut req, bindings, wallet).await?;
             response(&req, res)
         }
+        ApiRouteKey::webhook => {
+            let res = webhook(
+                &mut req,
+                options,
+                &state.webhooks,
+                &state.confirmations,
+                wallet,
+            )
+            .await?;
+            response(&req, res)
+        }
     }
 }
@@ -0,0 +1,290 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recurring transfers.
+//!
+//! Scheduled transfers are stored in the `schedules` file in the storage directory. A background
+//! task fires each one from the open wallet every time its interval elapses. If a transfer cannot be
+//! made when it fires, for example because the balance is insufficient, that firing is skipped and
+//! the reason is recorded with the schedule, rather than failing silently.
+
+use crate::cache::ResponseCache;
+use crate::lock::WalletLock;
+use crate::min_anonymity::{too_small_input, too_small_predicted_input, MinAnonymity};
+use crate::persisted::Persisted;
+use crate::routes::{read_last_path, Wallet};
+use crate::spend_limits::{limited_spend, SpendError, SpendLimits};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cap_rust_sandbox::ledger::CapeTransition;
+use cape_wallet::{
+    ui::{
+        CanSend, ProofParams, ScheduledTransfer, ScheduledTransferFiring, ScheduledTransferOutcome,
+    },
+    wallet::CapeWalletExt,
+};
+use jf_cap::structs::AssetCode;
+use net::UserAddress;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{event, Level};
+
+/// How often the background task checks for transfers which are due.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How many firings of each schedule to remember.
+const MAX_FIRINGS: usize = 100;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Schedules {
+    next_id: u64,
+    schedules: Vec<ScheduledTransfer>,
+}
+
+impl Persisted for Schedules {}
+
+impl Schedules {
+    pub fn list(&self) -> Vec<ScheduledTransfer> {
+        self.schedules.clone()
+    }
+
+    /// Schedule a transfer which first fires one `interval` from now.
+    pub fn add(
+        &mut self,
+        asset: AssetCode,
+        recipient: UserAddress,
+        amount: u128,
+        fee: u128,
+        interval: Duration,
+    ) -> ScheduledTransfer {
+        let schedule = ScheduledTransfer {
+            id: self.next_id,
+            asset,
+            recipient,
+            amount: amount.into(),
+            fee: fee.into(),
+            interval_secs: interval.as_secs(),
+            next_firing_ms: Some(now() + interval.as_millis() as u64),
+            firings: vec![],
+        };
+        self.next_id += 1;
+        self.schedules.push(schedule.clone());
+        schedule
+    }
+
+    /// Stop firing schedule `id`.
+    ///
+    /// Returns the cancelled schedule, or `None` if there is no schedule `id`.
+    pub fn cancel(&mut self, id: u64) -> Option<ScheduledTransfer> {
+        let schedule = self
+            .schedules
+            .iter_mut()
+            .find(|schedule| schedule.id == id)?;
+        schedule.next_firing_ms = None;
+        Some(schedule.clone())
+    }
+
+    /// Schedules which are due to fire at `now`.
+    fn due(&self, now: u64) -> Vec<ScheduledTransfer> {
+        self.schedules
+            .iter()
+            .filter(|schedule| matches!(schedule.next_firing_ms, Some(time) if time <= now))
+            .cloned()
+            .collect()
+    }
+
+    /// Record that schedule `id` fired at `now`, and schedule its next firing.
+    ///
+    /// If firings were missed, for example because the server was not running, they are not made
+    /// up: the next firing is the first one after `now`.
+    fn fired(&mut self, id: u64, now: u64, outcome: ScheduledTransferOutcome) {
+        if let Some(schedule) = self.schedules.iter_mut().find(|schedule| schedule.id == id) {
+            schedule.firings.push(ScheduledTransferFiring {
+                time_ms: now,
+                outcome,
+            });
+            if schedule.firings.len() > MAX_FIRINGS {
+                schedule.firings.remove(0);
+            }
+            if let Some(next) = &mut schedule.next_firing_ms {
+                let interval = std::cmp::max(schedule.interval_secs * 1000, 1);
+                while *next <= now {
+                    *next += interval;
+                }
+            }
+        }
+    }
+}
+
+/// Fire scheduled transfers from the open wallet as they become due.
+///
+/// The task exits once the server which owns `schedules` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    schedules: Arc<Mutex<Schedules>>,
+    spend_limits: Arc<Mutex<SpendLimits>>,
+    min_anonymity: Arc<Mutex<MinAnonymity>>,
+    submission_paused: Arc<Mutex<bool>>,
+    lock: Arc<Mutex<Option<WalletLock>>>,
+    cache: Arc<Mutex<ResponseCache>>,
+) {
+    while Arc::strong_count(&schedules) > 1 {
+        sleep(TICK).await;
+
+        let mut wallet = wallet.lock().await;
+        let mut schedules = schedules.lock().await;
+        let now = now();
+        let due = schedules.due(now);
+        if due.is_empty() {
+            continue;
+        }
+        let paused = *submission_paused.lock().await;
+        let locked = lock.lock().await.is_some();
+        for schedule in due {
+            let outcome = match wallet.as_mut() {
+                Some(_) if paused => ScheduledTransferOutcome::Skipped {
+                    reason: String::from("transaction submission is paused"),
+                    code: Some(String::from("submission_paused")),
+                },
+                Some(_) if locked => ScheduledTransferOutcome::Skipped {
+                    reason: String::from("the wallet is locked"),
+                    code: Some(String::from("wallet_locked")),
+                },
+                Some(wallet) => {
+                    fire(&options, wallet, &schedule, &spend_limits, &min_anonymity).await
+                }
+                None => ScheduledTransferOutcome::Skipped {
+                    reason: String::from("no wallet is open"),
+                    code: None,
+                },
+            };
+            schedules.fired(schedule.id, now, outcome);
+        }
+        // Firing a transfer changes the wallet behind the back of the request handlers.
+        cache.lock().await.invalidate();
+        if let Err(err) = schedules.save(&options.schedules_path()) {
+            event!(Level::ERROR, "failed to save scheduled transfers: {}", err);
+        }
+    }
+}
+
+async fn fire(
+    options: &NodeOpt,
+    wallet: &mut Wallet,
+    schedule: &ScheduledTransfer,
+    spend_limits: &Mutex<SpendLimits>,
+    min_anonymity: &Mutex<MinAnonymity>,
+) -> ScheduledTransferOutcome {
+    let check = CanSend::check(
+        wallet.records().await,
+        schedule.asset,
+        schedule.amount,
+        schedule.fee,
+        ProofParams::cape().max_transfer_inputs,
+    );
+    if !check.ok {
+        return ScheduledTransferOutcome::Skipped {
+            reason: check.reason.unwrap_or_default(),
+            code: check.code,
+        };
+    }
+
+    let wallet_path = match read_last_path(options).await {
+        Ok(Some(wallet_path)) => wallet_path,
+        Ok(None) => {
+            return ScheduledTransferOutcome::Skipped {
+                reason: String::from("no wallet is open"),
+                code: None,
+            }
+        }
+        Err(err) => {
+            return ScheduledTransferOutcome::Failed {
+                msg: err.to_string(),
+            }
+        }
+    };
+    let amount = schedule.amount.as_u128();
+    // Like `send`, refuse to spend records whose anonymity sets are below the wallet's minimum.
+    let threshold = min_anonymity.lock().await.get(&wallet_path);
+    let too_small = |(uid, size): (u64, u64)| ScheduledTransferOutcome::Skipped {
+        reason: format!(
+            "record {} has an anonymity set of {}, below the minimum of {}",
+            uid, size, threshold
+        ),
+        code: Some(String::from("anonymity_set_too_small")),
+    };
+    if let Some(input) = too_small_predicted_input(
+        wallet,
+        None,
+        schedule.asset,
+        amount,
+        schedule.fee.as_u128(),
+        threshold,
+    )
+    .await
+    {
+        return too_small(input);
+    }
+    let spend = async {
+        let (txn, info) = match wallet
+            .export_transfer(
+                None,
+                &schedule.asset,
+                &[(schedule.recipient.clone().into(), amount.into())],
+                schedule.fee.as_u128(),
+            )
+            .await
+        {
+            Ok(built) => built,
+            Err(err) => {
+                return Err(ScheduledTransferOutcome::Failed {
+                    msg: err.to_string(),
+                })
+            }
+        };
+        if let Some(input) = too_small_input(wallet, &txn, threshold).await {
+            return Err(too_small(input));
+        }
+        wallet
+            .submit(CapeTransition::Transaction(txn), info)
+            .await
+            .map_err(|err| ScheduledTransferOutcome::Failed {
+                msg: err.to_string(),
+            })
+    };
+    match limited_spend(
+        options,
+        spend_limits,
+        &wallet_path,
+        &schedule.asset,
+        amount,
+        spend,
+    )
+    .await
+    {
+        Ok(receipt) => ScheduledTransferOutcome::Sent { receipt },
+        Err(SpendError::LimitExceeded { remaining, .. }) => ScheduledTransferOutcome::Skipped {
+            reason: format!(
+                "spending limit exceeded: only {} can be spent until the window rolls",
+                remaining
+            ),
+            code: Some(String::from("spend_limit_exceeded")),
+        },
+        Err(SpendError::Failed(outcome)) => outcome,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
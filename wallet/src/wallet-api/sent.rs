@@ -0,0 +1,88 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The parameters of transfers sent through this server, so they can be resent if they fail.
+//!
+//! The wallet's transaction history records the receivers of each transfer, but not the fee or
+//! whether the sender was chosen by the caller, so a failed transfer cannot be rebuilt from history
+//! alone. Parameters are stored in the `sent` file in the storage directory, keyed by wallet path
+//! and then by transaction hash.
+
+use crate::persisted::Persisted;
+use jf_cap::structs::AssetCode;
+use net::UserAddress;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The parameters of a `send` request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferParams {
+    pub sender: Option<UserAddress>,
+    pub asset: AssetCode,
+    pub recipient: UserAddress,
+    pub amount: u128,
+    pub fee: u128,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SentTransfer {
+    params: TransferParams,
+    /// The hash of the transaction this one was resent as, if it has been resent.
+    resent_as: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SentTransfers {
+    transfers: HashMap<PathBuf, HashMap<String, SentTransfer>>,
+}
+
+impl Persisted for SentTransfers {}
+
+impl SentTransfers {
+    /// Record that the wallet at `wallet_path` sent the transaction with hash `hash`.
+    pub fn insert(&mut self, wallet_path: &Path, hash: String, params: TransferParams) {
+        self.transfers
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .insert(
+                hash,
+                SentTransfer {
+                    params,
+                    resent_as: None,
+                },
+            );
+    }
+
+    /// The parameters of the transaction with hash `hash`, if it was sent by the wallet at
+    /// `wallet_path` and has not been resent.
+    pub fn get(&self, wallet_path: &Path, hash: &str) -> Option<&TransferParams> {
+        self.transfers
+            .get(wallet_path)
+            .and_then(|transfers| transfers.get(hash))
+            .filter(|transfer| transfer.resent_as.is_none())
+            .map(|transfer| &transfer.params)
+    }
+
+    /// Record that the transaction with hash `hash` was resent as the transaction with hash
+    /// `resent_as`, which has the same parameters.
+    pub fn resend(&mut self, wallet_path: &Path, hash: &str, resent_as: String) {
+        if let Some(transfers) = self.transfers.get_mut(wallet_path) {
+            if let Some(transfer) = transfers.get_mut(hash) {
+                transfer.resent_as = Some(resent_as.clone());
+                let params = transfer.params.clone();
+                transfers.insert(
+                    resent_as,
+                    SentTransfer {
+                        params,
+                        resent_as: None,
+                    },
+                );
+            }
+        }
+    }
+}
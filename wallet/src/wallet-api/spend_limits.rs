@@ -0,0 +1,195 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-wallet, per-asset spending limits.
+//!
+//! A spending limit caps the total amount of an asset which a wallet can spend through this server
+//! within a rolling window, whether by minting, transferring, unwrapping, or exporting a transaction
+//! for someone else to submit. Every such spend goes through [limited_spend]. Limits and the
+//! spending they count are stored in the `spend_limits` file in the storage directory, keyed by
+//! wallet path, so restarting the server does not reset them.
+
+use crate::persisted::Persisted;
+use crate::web::NodeOpt;
+use async_std::sync::Mutex;
+use cape_wallet::ui::SpendLimitInfo;
+use futures::Future;
+use jf_cap::structs::AssetCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{event, Level};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SpendLimit {
+    asset: AssetCode,
+    limit: u128,
+    period_ms: u64,
+    /// Amounts spent within the current window, with the time each was spent, in milliseconds
+    /// since the Unix epoch, oldest first.
+    spent: Vec<(u64, u128)>,
+}
+
+impl SpendLimit {
+    /// Forget spending which has rolled out of the window ending at `now`.
+    fn prune(&mut self, now: u64) {
+        let start = now.saturating_sub(self.period_ms);
+        self.spent.retain(|(time, _)| *time > start);
+    }
+
+    fn spent(&self) -> u128 {
+        self.spent
+            .iter()
+            .fold(0u128, |total, (_, amount)| total.saturating_add(*amount))
+    }
+
+    fn remaining(&self) -> u128 {
+        self.limit.saturating_sub(self.spent())
+    }
+
+    fn info(&self) -> SpendLimitInfo {
+        SpendLimitInfo {
+            asset: self.asset,
+            limit: self.limit.into(),
+            period_secs: self.period_ms / 1000,
+            spent: self.spent().into(),
+            remaining: self.remaining().into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SpendLimits {
+    limits: HashMap<PathBuf, Vec<SpendLimit>>,
+}
+
+impl Persisted for SpendLimits {}
+
+impl SpendLimits {
+    /// All limits of the wallet at `wallet_path`, with the spending counted against each at the
+    /// current time.
+    pub fn list(&mut self, wallet_path: &Path) -> Vec<SpendLimitInfo> {
+        let now = now();
+        self.limits
+            .get_mut(wallet_path)
+            .into_iter()
+            .flatten()
+            .map(|limit| {
+                limit.prune(now);
+                limit.info()
+            })
+            .collect()
+    }
+
+    /// Limit spending of `asset` by the wallet at `wallet_path` to `limit` in any window of length
+    /// `period`.
+    ///
+    /// Replaces any existing limit for `asset`. Spending already counted against the old limit is
+    /// counted against the new one, so lowering a limit takes effect immediately.
+    pub fn set(&mut self, wallet_path: &Path, asset: AssetCode, limit: u128, period: Duration) {
+        let period_ms = period.as_millis() as u64;
+        let limits = self.limits.entry(wallet_path.to_path_buf()).or_default();
+        match limits.iter_mut().find(|limit| limit.asset == asset) {
+            Some(existing) => {
+                existing.limit = limit;
+                existing.period_ms = period_ms;
+            }
+            None => limits.push(SpendLimit {
+                asset,
+                limit,
+                period_ms,
+                spent: vec![],
+            }),
+        }
+    }
+
+    /// Check that the wallet at `wallet_path` can spend `amount` of `asset` now.
+    ///
+    /// On failure, returns the amount which can still be spent in the current window.
+    fn check(&mut self, wallet_path: &Path, asset: &AssetCode, amount: u128) -> Result<(), u128> {
+        let now = now();
+        match self.find(wallet_path, asset) {
+            Some(limit) => {
+                limit.prune(now);
+                if amount > limit.remaining() {
+                    Err(limit.remaining())
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count `amount` of `asset` as spent by the wallet at `wallet_path` now.
+    ///
+    /// Returns whether `asset` has a limit, in which case the limits have changed and should be
+    /// saved.
+    fn record(&mut self, wallet_path: &Path, asset: &AssetCode, amount: u128) -> bool {
+        match self.find(wallet_path, asset) {
+            Some(limit) => {
+                limit.spent.push((now(), amount));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find(&mut self, wallet_path: &Path, asset: &AssetCode) -> Option<&mut SpendLimit> {
+        self.limits
+            .get_mut(wallet_path)?
+            .iter_mut()
+            .find(|limit| limit.asset == *asset)
+    }
+}
+
+/// Why a spend subject to a spending limit did not happen.
+pub enum SpendError<E> {
+    /// The spend would exceed the limit, of which `remaining` can still be spent in the current
+    /// window.
+    LimitExceeded { asset: AssetCode, remaining: u128 },
+    /// The spend itself failed.
+    Failed(E),
+}
+
+/// Run `spend`, which spends `amount` of `asset` from the wallet at `wallet_path`, if the wallet's
+/// spending limit for `asset` allows it, and count the spending if it succeeds.
+///
+/// The limits stay locked from the check until the spending is counted, so concurrent spends cannot
+/// together exceed a limit.
+pub async fn limited_spend<T, E>(
+    options: &NodeOpt,
+    spend_limits: &Mutex<SpendLimits>,
+    wallet_path: &Path,
+    asset: &AssetCode,
+    amount: u128,
+    spend: impl Future<Output = Result<T, E>>,
+) -> Result<T, SpendError<E>> {
+    let mut spend_limits = spend_limits.lock().await;
+    spend_limits
+        .check(wallet_path, asset, amount)
+        .map_err(|remaining| SpendError::LimitExceeded {
+            asset: *asset,
+            remaining,
+        })?;
+    let res = spend.await.map_err(SpendError::Failed)?;
+    if spend_limits.record(wallet_path, asset, amount) {
+        // The spend has already happened, so it is not failed if it cannot be saved.
+        if let Err(err) = spend_limits.save(&options.spend_limits_path()) {
+            event!(Level::ERROR, "failed to save spending limits: {}", err);
+        }
+    }
+    Ok(res)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
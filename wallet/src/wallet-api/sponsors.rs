@@ -0,0 +1,42 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! The sponsors of the assets sponsored through this server.
+//!
+//! The CAPE contract does not record who sponsored an asset, so the server remembers the sponsor of
+//! each asset it submits a sponsorship for. Sponsors are stored in the `sponsors` file in the
+//! storage directory, separately from any wallet, since sponsorship is a property of the asset.
+
+use crate::persisted::Persisted;
+use ethers::prelude::Address;
+use jf_cap::structs::AssetCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sponsors {
+    sponsors: HashMap<AssetCode, Address>,
+}
+
+impl Persisted for Sponsors {}
+
+impl Sponsors {
+    /// The recorded sponsor of `asset`, if any.
+    pub fn get(&self, asset: &AssetCode) -> Option<Address> {
+        self.sponsors.get(asset).copied()
+    }
+
+    /// Record that `asset` was sponsored by `sponsor`.
+    pub fn set(&mut self, asset: AssetCode, sponsor: Address) {
+        self.sponsors.insert(asset, sponsor);
+    }
+
+    /// Every address which has sponsored an asset through this server.
+    pub fn addresses(&self) -> impl Iterator<Item = Address> + '_ {
+        self.sponsors.values().copied()
+    }
+}
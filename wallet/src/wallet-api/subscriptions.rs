@@ -0,0 +1,759 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Streaming subscriptions.
+//!
+//! Unlike the request/response routes in `api.toml`, a subscription holds a connection open and
+//! pushes server-sent events to the client until either side closes it. Every open subscription is
+//! registered in [Subscriptions], so that operators can list open connections and forcibly close
+//! leaked ones with the `subscriptions` route.
+//!
+//! Events are not written to the connection directly. Each subscription queues its events in a
+//! bounded [Outbox], which is drained into the connection in the background, so a slow subscriber
+//! cannot make the server buffer events without limit. What happens when the outbox is full is
+//! configured per subscription with the `buffer` and `overflow` query parameters.
+
+use crate::routes::{
+    require_wallet, require_wallet_path, server_error, wallet_error, CapeAPIError, Wallet,
+};
+use crate::web::WebState;
+use async_std::{
+    channel::{bounded, Receiver, Sender, TrySendError},
+    future::FutureExt as _,
+    sync::{Arc, MutexGuard},
+    task::{sleep, spawn},
+};
+use cap_rust_sandbox::ledger::CapeLedger;
+use cape_wallet::{
+    ui::{
+        AssetInfo, BalanceDelta, EventBatch, LargeTransaction, OverflowPolicy, SubscriptionInfo,
+        TransactionHistoryEntry, WalletSnapshot,
+    },
+    wallet::transaction_hash_string,
+};
+use ethers::prelude::U256;
+use futures::prelude::*;
+use jf_cap::structs::AssetCode;
+use seahorse::events::{EventSource, LedgerEvent};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tide::{Endpoint, Request};
+
+struct Subscription {
+    route: String,
+    opened: Instant,
+    close: Sender<()>,
+    policy: BufferPolicy,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Registry of open subscriptions.
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    open: HashMap<u64, Subscription>,
+}
+
+impl Subscriptions {
+    /// Register a new subscription to `route`, which sends its events through `outbox`.
+    ///
+    /// Returns the id of the subscription and a channel which receives a message if the
+    /// subscription is closed with [Subscriptions::close]. The subscription handler must call
+    /// [Subscriptions::remove] when it exits.
+    pub fn open(&mut self, route: &str, outbox: &Outbox) -> (u64, Receiver<()>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (close, closed) = bounded(1);
+        self.open.insert(
+            id,
+            Subscription {
+                route: route.to_string(),
+                opened: Instant::now(),
+                close,
+                policy: outbox.policy,
+                dropped: outbox.dropped.clone(),
+            },
+        );
+        (id, closed)
+    }
+
+    /// Remove a subscription whose handler has exited.
+    pub fn remove(&mut self, id: u64) {
+        self.open.remove(&id);
+    }
+
+    /// Forcibly terminate a subscription.
+    ///
+    /// Returns `false` if there is no open subscription with this id.
+    pub fn close(&mut self, id: u64) -> bool {
+        match self.open.remove(&id) {
+            Some(subscription) => {
+                // The handler may already be exiting, in which case nobody is listening for the
+                // close signal, and that's fine.
+                subscription.close.try_send(()).ok();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SubscriptionInfo> {
+        let mut subscriptions = self
+            .open
+            .iter()
+            .map(|(id, subscription)| SubscriptionInfo {
+                id: *id,
+                route: subscription.route.clone(),
+                open_secs: subscription.opened.elapsed().as_secs(),
+                buffer: subscription.policy.capacity,
+                overflow: subscription.policy.overflow,
+                dropped: subscription.dropped.load(Ordering::SeqCst),
+            })
+            .collect::<Vec<_>>();
+        subscriptions.sort_by_key(|subscription| subscription.id);
+        subscriptions
+    }
+}
+
+enum Next {
+    Event(Option<LedgerEvent<CapeLedger>>),
+    Closed,
+    Elapsed,
+}
+
+/// The kinds of event a subscription can be restricted to with the `events` query parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EventKind {
+    /// Committed or rejected transactions: `commit` and `reject` events.
+    Transaction,
+    /// Owner memos posted for committed records: `memos` events.
+    Memos,
+    /// New assets: `assets` and `asset` events.
+    Asset,
+    /// Large transaction alerts: `large_transaction` events.
+    Alert,
+}
+
+/// Parse the optional `events` query parameter accepted by every streaming route.
+///
+/// The parameter is a comma-separated list of event kinds. Returns `None` if it is absent, in which
+/// case every kind of event is forwarded.
+fn event_filter(req: &Request<WebState>) -> Result<Option<HashSet<EventKind>>, tide::Error> {
+    let value = match req.url().query_pairs().find(|(key, _)| key == "events") {
+        Some((_, value)) => value,
+        None => return Ok(None),
+    };
+    value
+        .split(',')
+        .map(|kind| match kind {
+            "transaction" => Ok(EventKind::Transaction),
+            "memos" => Ok(EventKind::Memos),
+            "asset" => Ok(EventKind::Asset),
+            "alert" => Ok(EventKind::Alert),
+            _ => Err(server_error(CapeAPIError::Param {
+                expected: String::from("event kind (transaction, memos, asset, or alert)"),
+                actual: kind.to_string(),
+            })),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Whether a subscription with `filter` forwards events of `kind`.
+fn selected(filter: &Option<HashSet<EventKind>>, kind: EventKind) -> bool {
+    match filter {
+        Some(kinds) => kinds.contains(&kind),
+        None => true,
+    }
+}
+
+/// The number of events a subscription buffers if the `buffer` query parameter is not given.
+pub const DEFAULT_BUFFER: usize = 1024;
+/// The largest buffer a subscription can ask for.
+pub const MAX_BUFFER: usize = 65536;
+
+/// How many events a subscription buffers, and what it does when the buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferPolicy {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_BUFFER,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Parse the optional `buffer` and `overflow` query parameters accepted by every streaming route.
+fn buffer_policy(req: &Request<WebState>) -> Result<BufferPolicy, tide::Error> {
+    let mut policy = BufferPolicy::default();
+    for (key, value) in req.url().query_pairs() {
+        match key.as_ref() {
+            "buffer" => {
+                policy.capacity = value
+                    .parse()
+                    .ok()
+                    .filter(|capacity| (1..=MAX_BUFFER).contains(capacity))
+                    .ok_or_else(|| {
+                        server_error(CapeAPIError::Param {
+                            expected: format!("buffer size between 1 and {}", MAX_BUFFER),
+                            actual: value.to_string(),
+                        })
+                    })?;
+            }
+            "overflow" => {
+                policy.overflow = match value.as_ref() {
+                    "drop-oldest" => OverflowPolicy::DropOldest,
+                    "drop-newest" => OverflowPolicy::DropNewest,
+                    "disconnect" => OverflowPolicy::Disconnect,
+                    _ => {
+                        return Err(server_error(CapeAPIError::Param {
+                            expected: String::from(
+                                "overflow policy (drop-oldest, drop-newest, or disconnect)",
+                            ),
+                            actual: value.to_string(),
+                        }))
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+    Ok(policy)
+}
+
+/// A server-sent event waiting in an [Outbox].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamEvent {
+    pub name: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// The bounded buffer of events waiting to be sent to a subscriber.
+///
+/// Handlers queue events with [Outbox::send], which never waits for the subscriber. If the buffer
+/// is full, the event is handled according to the subscription's [OverflowPolicy], and the number
+/// of dropped events is reported to the subscriber in an event named `dropped`, whose data is the
+/// total number of events dropped so far, so that it knows to resync.
+pub struct Outbox {
+    policy: BufferPolicy,
+    events: Sender<StreamEvent>,
+    // A handle on the receiving end of `events`, so that the oldest event can be dropped.
+    buffered: Receiver<StreamEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Outbox {
+    pub fn new(policy: BufferPolicy) -> Self {
+        let (events, buffered) = bounded(policy.capacity);
+        Self {
+            policy,
+            events,
+            buffered,
+            dropped: Default::default(),
+        }
+    }
+
+    /// Queue an event for the subscriber.
+    ///
+    /// Returns `false` if the stream is over, either because the subscriber disconnected or because
+    /// the buffer overflowed with the [OverflowPolicy::Disconnect] policy.
+    pub fn send(&self, name: &str, data: impl Into<String>, id: Option<&str>) -> bool {
+        let mut event = StreamEvent {
+            name: name.to_string(),
+            data: data.into(),
+            id: id.map(String::from),
+        };
+        loop {
+            event = match self.events.try_send(event) {
+                Ok(()) => return true,
+                Err(TrySendError::Closed(_)) => return false,
+                Err(TrySendError::Full(event)) => event,
+            };
+            match self.policy.overflow {
+                OverflowPolicy::DropOldest => {
+                    // The subscriber may take the oldest event itself before we get to it, in
+                    // which case nothing needs to be dropped.
+                    if self.buffered.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                    return true;
+                }
+                OverflowPolicy::Disconnect => {
+                    let mut discarded = 1;
+                    while self.buffered.try_recv().is_ok() {
+                        discarded += 1;
+                    }
+                    self.dropped.fetch_add(discarded, Ordering::SeqCst);
+                    self.events.close();
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// The events to send to the subscriber, in order.
+    ///
+    /// Before the next queued event, and before the stream ends, an event named `dropped` is
+    /// inserted if events have been dropped since the last one. The stream ends once the outbox is
+    /// dropped or closed and every queued event has been taken.
+    pub fn stream(&self) -> impl Stream<Item = StreamEvent> + Send + 'static {
+        let dropped_event = |total: u64| StreamEvent {
+            name: String::from("dropped"),
+            data: total.to_string(),
+            id: None,
+        };
+        stream::unfold(
+            (self.buffered.clone(), self.dropped.clone(), 0, false),
+            move |(buffered, dropped, reported, done)| async move {
+                if done {
+                    return None;
+                }
+                let total = dropped.load(Ordering::SeqCst);
+                if total > reported {
+                    return Some((dropped_event(total), (buffered, dropped, total, false)));
+                }
+                match buffered.recv().await {
+                    Ok(event) => Some((event, (buffered, dropped, reported, false))),
+                    Err(_) => {
+                        // Report anything dropped while the last events were being sent.
+                        let total = dropped.load(Ordering::SeqCst);
+                        if total > reported {
+                            Some((dropped_event(total), (buffered, dropped, total, true)))
+                        } else {
+                            None
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// Send queued events to `sender` in the background, as fast as the subscriber takes them.
+    ///
+    /// If the subscriber disconnects, the outbox is closed, so the next [Outbox::send] fails.
+    pub fn forward(&self, sender: tide::sse::Sender) {
+        let events = self.stream();
+        let buffered = self.buffered.clone();
+        spawn(async move {
+            futures::pin_mut!(events);
+            while let Some(event) = events.next().await {
+                if sender
+                    .send(&event.name, &event.data, event.id.as_deref())
+                    .await
+                    .is_err()
+                {
+                    buffered.close();
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Set up the outbox of a subscription requested by `req`, forwarding its events to `sender`.
+fn outbox(req: &Request<WebState>, sender: tide::sse::Sender) -> Result<Outbox, tide::Error> {
+    let outbox = Outbox::new(buffer_policy(req)?);
+    outbox.forward(sender);
+    Ok(outbox)
+}
+
+/// A streaming route which checks its query parameters before it starts streaming.
+///
+/// Once a stream has started, the response status has already been sent, so errors in the handler
+/// only end the stream. Checking the `events` filter and the buffer policy first means bad
+/// parameters are reported to the client with an error status instead.
+pub fn endpoint<F, Fut>(handler: F) -> impl Endpoint<WebState>
+where
+    F: Fn(Request<WebState>, tide::sse::Sender) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = tide::Result<()>> + Send + Sync + 'static,
+{
+    let stream = Arc::new(tide::sse::endpoint(handler));
+    move |req: Request<WebState>| {
+        let stream = stream.clone();
+        async move {
+            event_filter(&req)?;
+            buffer_policy(&req)?;
+            stream.call(req).await
+        }
+    }
+}
+
+/// Parse the optional `debounce_ms` query parameter accepted by `events`.
+fn debounce(req: &Request<WebState>) -> Result<Option<Duration>, tide::Error> {
+    match req
+        .url()
+        .query_pairs()
+        .find(|(key, _)| key == "debounce_ms")
+    {
+        Some((_, value)) => value
+            .parse()
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .map_err(|_| {
+                server_error(CapeAPIError::Param {
+                    expected: String::from("debounce window in milliseconds"),
+                    actual: value.to_string(),
+                })
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Stream ledger events to the client as they are processed by the open wallet.
+///
+/// Each server-sent event is named after the kind of ledger event (`commit`, `memos`, or `reject`),
+/// and its id and data are the index of the event in the query service event stream. If the request
+/// has an `events` query parameter, only events of the listed kinds are sent.
+///
+/// If the request has a `debounce_ms` query parameter, events are instead coalesced into batches;
+/// see [events_debounced].
+pub async fn events(req: Request<WebState>, sender: tide::sse::Sender) -> tide::Result<()> {
+    if let Some(window) = debounce(&req)? {
+        return events_debounced(req, sender, window).await;
+    }
+    let filter = event_filter(&req)?;
+    let outbox = outbox(&req, sender)?;
+    let state = req.state();
+    let (mut events, mut index) = {
+        let wallet = &mut *state.wallet.lock().await;
+        let wallet = require_wallet(wallet)?;
+        let now = wallet.now().await;
+        let events = wallet.lock().await.backend().subscribe(now, None).await;
+        (events, now.index(EventSource::QueryService))
+    };
+    let (id, closed) = state.subscriptions.lock().await.open("events", &outbox);
+
+    loop {
+        let next = async { Next::Event(events.next().await.map(|(event, _)| event)) }
+            .race(async {
+                closed.recv().await.ok();
+                Next::Closed
+            })
+            .await;
+        let (name, kind) = match next {
+            Next::Event(Some(LedgerEvent::Commit { .. })) => ("commit", EventKind::Transaction),
+            Next::Event(Some(LedgerEvent::Memos { .. })) => ("memos", EventKind::Memos),
+            Next::Event(Some(LedgerEvent::Reject { .. })) => ("reject", EventKind::Transaction),
+            Next::Event(None) | Next::Closed | Next::Elapsed => break,
+        };
+        let data = index.to_string();
+        index += 1;
+        if !selected(&filter, kind) {
+            continue;
+        }
+        if !outbox.send(name, data.clone(), Some(&data)) {
+            break;
+        }
+    }
+
+    state.subscriptions.lock().await.remove(id);
+    Ok(())
+}
+
+/// Stream batches of ledger events to the client, at most one batch per `window`.
+///
+/// A batch starts with the first event after the previous batch, and collects every event which
+/// arrives within `window` of it. Each batch is sent as a server-sent event named `batch`, whose id
+/// is the index of the last event in the batch, and whose data is a JSON [EventBatch] summarizing
+/// the events and the net change in the wallet's balances. Events filtered out by the `events` query
+/// parameter are left out of the batches, and do not open a window.
+async fn events_debounced(
+    req: Request<WebState>,
+    sender: tide::sse::Sender,
+    window: Duration,
+) -> tide::Result<()> {
+    let filter = event_filter(&req)?;
+    let outbox = outbox(&req, sender)?;
+    let state = req.state();
+    let (mut events, mut index, mut balances) = {
+        let wallet = &mut *state.wallet.lock().await;
+        let wallet = require_wallet(wallet)?;
+        let now = wallet.now().await;
+        let events = wallet.lock().await.backend().subscribe(now, None).await;
+        let balances = WalletSnapshot::from_wallet(wallet).await.balances();
+        (events, now.index(EventSource::QueryService), balances)
+    };
+    let (id, closed) = state.subscriptions.lock().await.open("events", &outbox);
+
+    'stream: loop {
+        let mut batch = EventBatch {
+            first: index,
+            events: 0,
+            commits: 0,
+            memos: 0,
+            rejects: 0,
+            balances: HashMap::new(),
+        };
+        let mut deadline = None;
+        // Events which are filtered out still count towards the index of the next event.
+        let mut received = 0;
+        loop {
+            let next = async { Next::Event(events.next().await.map(|(event, _)| event)) }
+                .race(async {
+                    closed.recv().await.ok();
+                    Next::Closed
+                })
+                .race(async {
+                    match deadline {
+                        Some(deadline) => {
+                            sleep(deadline.saturating_duration_since(Instant::now())).await
+                        }
+                        None => future::pending().await,
+                    }
+                    Next::Elapsed
+                })
+                .await;
+            let (kind, count) = match next {
+                Next::Event(Some(LedgerEvent::Commit { .. })) => {
+                    (EventKind::Transaction, &mut batch.commits)
+                }
+                Next::Event(Some(LedgerEvent::Memos { .. })) => {
+                    (EventKind::Memos, &mut batch.memos)
+                }
+                Next::Event(Some(LedgerEvent::Reject { .. })) => {
+                    (EventKind::Transaction, &mut batch.rejects)
+                }
+                Next::Elapsed => break,
+                Next::Event(None) | Next::Closed => break 'stream,
+            };
+            let event_index = index + received;
+            received += 1;
+            if !selected(&filter, kind) {
+                continue;
+            }
+            *count += 1;
+            if batch.events == 0 {
+                batch.first = event_index;
+            }
+            batch.events += 1;
+            // The window opens with the first event in the batch.
+            deadline.get_or_insert_with(|| Instant::now() + window);
+        }
+        index += received;
+
+        // Report balances once the wallet has processed every event in the batch.
+        let new_balances = match wallet_after(state, index - 1).await {
+            Some(wallet) => match wallet.as_ref() {
+                Some(wallet) => WalletSnapshot::from_wallet(wallet).await.balances(),
+                None => break,
+            },
+            None => break,
+        };
+        for asset in balances.keys().chain(new_balances.keys()) {
+            let before = balances.get(asset).copied().unwrap_or_else(U256::zero);
+            let after = new_balances.get(asset).copied().unwrap_or_else(U256::zero);
+            if before != after {
+                batch
+                    .balances
+                    .insert(*asset, BalanceDelta { before, after });
+            }
+        }
+        balances = new_balances;
+
+        let last = (index - 1).to_string();
+        if !outbox.send("batch", serde_json::to_string(&batch)?, Some(&last)) {
+            break;
+        }
+    }
+
+    state.subscriptions.lock().await.remove(id);
+    Ok(())
+}
+
+/// Lock the open wallet once it has processed the event at `index`.
+///
+/// Returns `None` if the wallet is closed.
+async fn wallet_after(state: &WebState, index: usize) -> Option<MutexGuard<'_, Option<Wallet>>> {
+    loop {
+        {
+            let wallet = state.wallet.lock().await;
+            let caught_up = wallet
+                .as_ref()?
+                .now()
+                .await
+                .index(EventSource::QueryService)
+                > index;
+            if caught_up {
+                return Some(wallet);
+            }
+        }
+        // Release the lock while the wallet catches up.
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Get the assets known to the open wallet, once it has processed the event at `index`.
+///
+/// Returns `None` if the wallet is closed.
+async fn assets_after(state: &WebState, index: usize) -> Option<Vec<AssetInfo>> {
+    let wallet = wallet_after(state, index).await?;
+    let wallet = wallet.as_ref()?;
+    let mut assets = vec![];
+    for asset in wallet.assets().await {
+        assets.push(AssetInfo::from_info(wallet, asset).await);
+    }
+    Some(assets)
+}
+
+/// Stream the asset library of the open wallet to the client as it grows.
+///
+/// The first server-sent event is named `assets`, and its data is a JSON list of the [AssetInfo]
+/// of every asset the wallet knows. After that, whenever processing a ledger event adds new assets
+/// to the wallet, an event named `asset` is sent for each one, whose data is its [AssetInfo].
+pub async fn assets(req: Request<WebState>, sender: tide::sse::Sender) -> tide::Result<()> {
+    let forward = selected(&event_filter(&req)?, EventKind::Asset);
+    let outbox = outbox(&req, sender)?;
+    let state = req.state();
+    let (mut events, mut index, initial) = {
+        let wallet = &mut *state.wallet.lock().await;
+        let wallet = require_wallet(wallet)?;
+        let now = wallet.now().await;
+        let events = wallet.lock().await.backend().subscribe(now, None).await;
+        let mut assets = vec![];
+        for asset in wallet.assets().await {
+            assets.push(AssetInfo::from_info(wallet, asset).await);
+        }
+        (events, now.index(EventSource::QueryService), assets)
+    };
+    let mut known = initial
+        .iter()
+        .map(|asset| asset.definition.code)
+        .collect::<HashSet<AssetCode>>();
+    let (id, closed) = state
+        .subscriptions
+        .lock()
+        .await
+        .open("subscribe/assets", &outbox);
+
+    if !forward || outbox.send("assets", serde_json::to_string(&initial)?, None) {
+        'stream: loop {
+            let next = async { Next::Event(events.next().await.map(|(event, _)| event)) }
+                .race(async {
+                    closed.recv().await.ok();
+                    Next::Closed
+                })
+                .await;
+            if let Next::Event(None) | Next::Closed = next {
+                break;
+            }
+
+            // New assets are discovered when the wallet processes this event, which it does
+            // independently of this stream.
+            let assets = match assets_after(state, index).await {
+                Some(assets) => assets,
+                None => break,
+            };
+            index += 1;
+            for asset in assets {
+                if known.insert(asset.definition.code)
+                    && forward
+                    && !outbox.send("asset", serde_json::to_string(&asset)?, None)
+                {
+                    break 'stream;
+                }
+            }
+        }
+    }
+
+    state.subscriptions.lock().await.remove(id);
+    Ok(())
+}
+
+/// Stream large transaction alerts for the open wallet to the client.
+///
+/// Whenever a transaction which exceeds one of the wallet's alert thresholds (see the `alerts`
+/// module) is added to the wallet's history, an event named `large_transaction` is sent. Its id is
+/// the hash of the transaction, and its data is a JSON [LargeTransaction]. Transactions already in
+/// the history when the stream starts do not raise alerts.
+pub async fn alerts(req: Request<WebState>, sender: tide::sse::Sender) -> tide::Result<()> {
+    let forward = selected(&event_filter(&req)?, EventKind::Alert);
+    let outbox = outbox(&req, sender)?;
+    let state = req.state();
+    let wallet_path = require_wallet_path(&state.options).await?;
+    let (mut events, mut index, mut seen) = {
+        let wallet = &mut *state.wallet.lock().await;
+        let wallet = require_wallet(wallet)?;
+        let now = wallet.now().await;
+        let events = wallet.lock().await.backend().subscribe(now, None).await;
+        let seen = wallet
+            .transaction_history()
+            .await
+            .map_err(wallet_error)?
+            .into_iter()
+            .filter_map(|entry| entry.hash.as_ref().and_then(transaction_hash_string))
+            .collect::<HashSet<_>>();
+        (events, now.index(EventSource::QueryService), seen)
+    };
+    let (id, closed) = state
+        .subscriptions
+        .lock()
+        .await
+        .open("subscribe/alerts", &outbox);
+
+    'stream: loop {
+        let next = async { Next::Event(events.next().await.map(|(event, _)| event)) }
+            .race(async {
+                closed.recv().await.ok();
+                Next::Closed
+            })
+            .await;
+        if let Next::Event(None) | Next::Closed = next {
+            break;
+        }
+
+        // Find the transactions the wallet added to its history while processing this event.
+        let alerts = {
+            let wallet = match wallet_after(state, index).await {
+                Some(wallet) => wallet,
+                None => break,
+            };
+            let wallet = match wallet.as_ref() {
+                Some(wallet) => wallet,
+                None => break,
+            };
+            let thresholds = state.alerts.lock().await;
+            let mut alerts: Vec<LargeTransaction> = vec![];
+            for entry in wallet.transaction_history().await.map_err(wallet_error)? {
+                let hash = match entry.hash.as_ref().and_then(transaction_hash_string) {
+                    Some(hash) => hash,
+                    None => continue,
+                };
+                if !seen.insert(hash.clone()) {
+                    continue;
+                }
+                let entry = TransactionHistoryEntry::from_wallet(wallet, entry).await;
+                alerts.extend(thresholds.check(&wallet_path, &hash, &entry));
+            }
+            alerts
+        };
+        index += 1;
+        if !forward {
+            continue;
+        }
+        for alert in alerts {
+            if !outbox.send(
+                "large_transaction",
+                serde_json::to_string(&alert)?,
+                Some(&alert.hash),
+            ) {
+                break 'stream;
+            }
+        }
+    }
+
+    state.subscriptions.lock().await.remove(id);
+    Ok(())
+}
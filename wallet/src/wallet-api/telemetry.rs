@@ -0,0 +1,57 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tracing configuration for the wallet server.
+//!
+//! Spans and events are always logged locally. If an OTLP endpoint is configured, spans are also
+//! exported to an OpenTelemetry collector, so that requests can be followed across a distributed
+//! deployment.
+
+use crate::web::NodeOpt;
+use opentelemetry::{
+    sdk::{trace, Resource},
+    trace::TraceError,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Install the global tracing subscriber.
+///
+/// If `options` specifies an OTLP endpoint, an exporter is layered on top of the local logger.
+/// Otherwise, this is equivalent to a plain pretty-printing `tracing_subscriber::fmt` subscriber.
+pub fn init_tracing(options: &NodeOpt) -> Result<(), TraceError> {
+    let otlp = match options.otlp_endpoint() {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .http()
+                        .with_endpoint(endpoint.as_str()),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", "cape-wallet-api"),
+                ])))
+                .install_batch(opentelemetry::runtime::AsyncStd)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer().pretty())
+        .with(otlp)
+        .init();
+    Ok(())
+}
+
+/// Flush any spans which have not yet been exported.
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Testing routes which save and restore the state of the open wallet.
+//!
+//! A test suite can set up a complex scenario once, save it with `GET teststate/snapshot`, and
+//! restore it with `POST teststate/restore/:password` at the start of each test. A [TestState]
+//! contains every file of the wallet's keystore, which is everything the wallet loads when it is
+//! opened: its keys, assets, records and transaction history. Restoring closes the open wallet,
+//! replaces its keystore with the one in the snapshot and reopens it.
+//!
+//! The ledger is not part of the snapshot. The restored wallet is reopened like any other, against
+//! the ledger the server is using at the time, so the changes a restore reliably undoes are those
+//! local to the wallet, like generating keys or defining assets.
+//!
+//! These routes are only built in tests and with the non-default `test-state` feature, never in
+//! production builds.
+
+use crate::routes::{
+    init_wallet, latest_contract, require_wallet, require_wallet_path, server_error, CapeAPIError,
+};
+use crate::web::WebState;
+use cape_wallet::loader::CapeLoader;
+use net::server::{self, request_body};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The saved state of a wallet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestState {
+    /// The keystore the state was saved from, which it is restored to.
+    pub wallet_path: PathBuf,
+    /// The base64-encoded contents of each file in the keystore, by path relative to the keystore.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+/// Route handler for `GET teststate/snapshot`.
+pub async fn snapshot(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let state = req.state();
+
+    // Hold the wallet lock while reading the keystore, so it is not modified underneath us.
+    let wallet = &mut *state.wallet.lock().await;
+    require_wallet(wallet)?;
+    let wallet_path = require_wallet_path(&state.options).await?;
+    let mut files = BTreeMap::new();
+    read_files(&wallet_path, Path::new(""), &mut files)?;
+    server::response(&req, TestState { wallet_path, files })
+}
+
+/// Route handler for `POST teststate/restore/:password`.
+///
+/// The body is a [TestState] returned by `teststate/snapshot`. `:password` is the base64-encoded
+/// password of the wallet the state was saved from.
+pub async fn restore(mut req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let password =
+        base64::decode_config(req.param("password")?, base64::URL_SAFE_NO_PAD).map_err(|_| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("base64-encoded password"),
+                actual: req.param("password").unwrap_or_default().to_string(),
+            })
+        })?;
+    let password = String::from_utf8(password)?;
+    let snapshot: TestState = request_body(&mut req).await?;
+    let state = req.state();
+
+    let wallet = &mut *state.wallet.lock().await;
+    // Close the open wallet before replacing its files.
+    *wallet = None;
+    if let Err(err) = fs::remove_dir_all(&snapshot.wallet_path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            return Err(err.into());
+        }
+    }
+    for (path, contents) in &snapshot.files {
+        let path = snapshot.wallet_path.join(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, base64::decode(contents)?)?;
+    }
+
+    let loader = CapeLoader::from_literal(
+        None,
+        password,
+        snapshot.wallet_path.clone(),
+        latest_contract(&state.options).await?,
+    );
+    *wallet = Some(
+        init_wallet(
+            &state.options,
+            &state.scan_interval,
+            &mut *state.rng.lock().await,
+            state.faucet_key_pair.pub_key(),
+            loader,
+            true,
+        )
+        .await?,
+    );
+
+    // These routes are not dispatched through the response cache, but they change the wallet.
+    state.cache.lock().await.invalidate();
+    state.balance_index.lock().await.reset();
+    server::response(&req, ())
+}
+
+/// Add the contents of each file under `root.join(dir)` to `files`, by path relative to `root`.
+fn read_files(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, String>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            read_files(root, &path, files)?;
+        } else {
+            files.insert(path, base64::encode(fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Serving the API over TLS, optionally authenticating clients by certificate.
+//!
+//! tide's listeners do not give request handlers access to the certificate a client presented, so
+//! when `--tls-cert` is given the server runs its own accept loop: it completes the TLS handshake on
+//! each connection, then serves HTTP on the encrypted stream with the same HTTP implementation tide
+//! uses, attaching the [PeerCertificate] of the client, if it presented one, to every request on
+//! the connection. With `--client-ca`, the handshake fails unless the client presents a certificate
+//! issued by one of the given CAs, so unauthenticated clients never reach the request handlers.
+
+use crate::web::{NodeOpt, WebState};
+use async_std::{
+    net::{TcpListener, TcpStream},
+    task::spawn,
+};
+use cape_wallet::ui::PeerCertificate;
+use futures::prelude::*;
+use futures_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{event, Level};
+
+/// The TLS configuration given by `options`, or `None` if the server should not use TLS.
+pub fn server_config(options: &NodeOpt) -> io::Result<Option<ServerConfig>> {
+    let (cert, key) = match (&options.tls_cert, &options.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if options.client_ca.is_none() => return Ok(None),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--client-ca requires --tls-cert, which requires --tls-key",
+            ))
+        }
+    };
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &options.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots.add(&cert).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "invalid CA certificate in {}: {:?}",
+                            client_ca.display(),
+                            err
+                        ),
+                    )
+                })?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    builder
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates in {}", path.display()),
+        ));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => {
+                return Ok(PrivateKey(key))
+            }
+            Some(_) => continue,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no private key in {}", path.display()),
+                ))
+            }
+        }
+    }
+}
+
+/// Serve `server` over TLS on `addr`.
+pub async fn listen(
+    server: tide::Server<WebState>,
+    addr: String,
+    config: ServerConfig,
+) -> io::Result<()> {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind(addr).await?;
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                spawn(serve(server.clone(), acceptor.clone(), stream));
+            }
+            Err(err) => event!(Level::WARN, "failed to accept connection: {}", err),
+        }
+    }
+    Ok(())
+}
+
+async fn serve(server: tide::Server<WebState>, acceptor: TlsAcceptor, stream: TcpStream) {
+    let peer_addr = stream.peer_addr().ok();
+    let stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            event!(
+                Level::INFO,
+                "TLS handshake with {:?} failed: {}",
+                peer_addr,
+                err
+            );
+            return;
+        }
+    };
+    let peer = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| peer_certificate(&cert.0));
+
+    // The HTTP implementation needs a stream it can clone.
+    let mut stream = async_dup::Arc::new(async_dup::Mutex::new(stream));
+    let res = async_h1::accept(stream.clone(), |mut req| {
+        let server = server.clone();
+        let peer = peer.clone();
+        async move {
+            req.set_peer_addr(peer_addr);
+            if let Some(peer) = peer {
+                req.ext_mut().insert(peer);
+            }
+            server.respond::<_, tide::http::Response>(req).await
+        }
+    })
+    .await;
+    if let Err(err) = res {
+        event!(
+            Level::INFO,
+            "connection with {:?} failed: {}",
+            peer_addr,
+            err
+        );
+    }
+    // Let the client know the connection was closed on purpose.
+    stream.close().await.ok();
+}
+
+fn peer_certificate(der: &[u8]) -> Option<PeerCertificate> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(PeerCertificate {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+    })
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-wallet trusted asset verification authorities.
+//!
+//! Besides the official CAPE asset signing key, a wallet may trust named keys to sign asset
+//! libraries. The server remembers which assets each verifier signed, so that the assets stop being
+//! verified when the verifier is removed. Verifiers are stored in the `verifiers` file in the
+//! storage directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::TrustedVerifier;
+use jf_cap::{structs::AssetCode, VerKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Verifier {
+    key: VerKey,
+    /// The assets in libraries signed by `key`.
+    assets: HashSet<AssetCode>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Verifiers {
+    verifiers: HashMap<PathBuf, BTreeMap<String, Verifier>>,
+}
+
+impl Persisted for Verifiers {}
+
+impl Verifiers {
+    /// The verifiers trusted by the wallet at `wallet_path`, in order of name.
+    pub fn list(&self, wallet_path: &Path) -> Vec<TrustedVerifier> {
+        self.verifiers
+            .get(wallet_path)
+            .into_iter()
+            .flatten()
+            .map(|(name, verifier)| {
+                let mut assets = verifier.assets.iter().copied().collect::<Vec<_>>();
+                assets.sort_by_key(|asset| asset.to_string());
+                TrustedVerifier {
+                    name: name.clone(),
+                    key: verifier.key.to_string(),
+                    assets,
+                }
+            })
+            .collect()
+    }
+
+    /// The keys of the verifiers trusted by the wallet at `wallet_path`, by name.
+    pub fn keys(&self, wallet_path: &Path) -> Vec<(String, VerKey)> {
+        self.verifiers
+            .get(wallet_path)
+            .into_iter()
+            .flatten()
+            .map(|(name, verifier)| (name.clone(), verifier.key.clone()))
+            .collect()
+    }
+
+    /// Trust `key` under `name`.
+    ///
+    /// If `name` was already trusted with a different key, the assets signed by the old key are no
+    /// longer verified.
+    pub fn insert(&mut self, wallet_path: &Path, name: String, key: VerKey) {
+        let verifiers = self.verifiers.entry(wallet_path.to_path_buf()).or_default();
+        match verifiers.get_mut(&name) {
+            Some(verifier) if verifier.key == key => {}
+            _ => {
+                verifiers.insert(
+                    name,
+                    Verifier {
+                        key,
+                        assets: Default::default(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Stop trusting the verifier `name`. Returns whether it was trusted.
+    pub fn remove(&mut self, wallet_path: &Path, name: &str) -> bool {
+        self.verifiers
+            .get_mut(wallet_path)
+            .map(|verifiers| verifiers.remove(name).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Record that the verifier `name` signed `assets`.
+    pub fn add_assets(
+        &mut self,
+        wallet_path: &Path,
+        name: &str,
+        assets: impl IntoIterator<Item = AssetCode>,
+    ) {
+        if let Some(verifier) = self
+            .verifiers
+            .get_mut(wallet_path)
+            .and_then(|verifiers| verifiers.get_mut(name))
+        {
+            verifier.assets.extend(assets);
+        }
+    }
+
+    /// Whether one of the verifiers trusted by the wallet at `wallet_path` signed `asset`.
+    pub fn is_verified(&self, wallet_path: &Path, asset: &AssetCode) -> bool {
+        self.verifiers
+            .get(wallet_path)
+            .into_iter()
+            .flat_map(|verifiers| verifiers.values())
+            .any(|verifier| verifier.assets.contains(asset))
+    }
+}
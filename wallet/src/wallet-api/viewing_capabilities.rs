@@ -0,0 +1,148 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scoped, revocable grants to view an asset.
+//!
+//! Rather than sharing a viewing key, a wallet which owns one can grant a capability to view part of
+//! the asset's transaction graph, such as only the amounts, or only the transactions in a range of
+//! blocks. The token of a capability encodes its scope and expiry along with a random secret, but
+//! the server only honors tokens which match a capability it issued and has not revoked, so a
+//! token cannot be widened by editing it. Capabilities are stored in the `viewing_capabilities`
+//! file in the storage directory, keyed by wallet path.
+
+use crate::persisted::Persisted;
+use cape_wallet::ui::{ViewingCapability, ViewingScope};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tagged_base64::TaggedBase64;
+
+/// The tag of capability tokens.
+pub const TOKEN_TAG: &str = "VIEWCAP";
+
+/// The contents of a capability token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Grant {
+    id: String,
+    secret: [u8; 16],
+    scope: ViewingScope,
+    expires: Option<u64>,
+}
+
+impl Grant {
+    fn token(&self) -> String {
+        let bytes = bincode::serialize(self).unwrap();
+        TaggedBase64::new(TOKEN_TAG, &bytes).unwrap().to_string()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Capability {
+    grant: Grant,
+    created: u64,
+    revoked: bool,
+}
+
+impl From<&Capability> for ViewingCapability {
+    fn from(capability: &Capability) -> Self {
+        Self {
+            id: capability.grant.id.clone(),
+            token: capability.grant.token(),
+            scope: capability.grant.scope.clone(),
+            created: capability.created,
+            expires: capability.grant.expires,
+            revoked: capability.revoked,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ViewingCapabilities {
+    capabilities: HashMap<PathBuf, BTreeMap<String, Capability>>,
+}
+
+impl Persisted for ViewingCapabilities {}
+
+impl ViewingCapabilities {
+    /// Grant a capability to view `scope` of an asset viewable by the wallet at `wallet_path`.
+    pub fn create(
+        &mut self,
+        wallet_path: &Path,
+        scope: ViewingScope,
+        expires_in_secs: Option<u64>,
+        rng: &mut impl RngCore,
+    ) -> ViewingCapability {
+        let mut id = [0u8; 8];
+        rng.fill_bytes(&mut id);
+        let mut secret = [0u8; 16];
+        rng.fill_bytes(&mut secret);
+        let created = now();
+        let capability = Capability {
+            grant: Grant {
+                id: hex::encode(id),
+                secret,
+                scope,
+                expires: expires_in_secs
+                    .map(|secs| created.saturating_add(secs.saturating_mul(1000))),
+            },
+            created,
+            revoked: false,
+        };
+        let info = ViewingCapability::from(&capability);
+        self.capabilities
+            .entry(wallet_path.to_path_buf())
+            .or_default()
+            .insert(capability.grant.id.clone(), capability);
+        info
+    }
+
+    /// Revoke the capability `id`, returning it, or `None` if the wallet at `wallet_path` never
+    /// granted it.
+    pub fn revoke(&mut self, wallet_path: &Path, id: &str) -> Option<ViewingCapability> {
+        let capability = self.capabilities.get_mut(wallet_path)?.get_mut(id)?;
+        capability.revoked = true;
+        Some(ViewingCapability::from(&*capability))
+    }
+
+    /// The scope of the capability whose token is `token`.
+    ///
+    /// Fails with a description of the problem if `token` is not the token of an unexpired
+    /// capability granted by the wallet at `wallet_path`, or if the capability has been revoked.
+    pub fn check(&self, wallet_path: &Path, token: &TaggedBase64) -> Result<ViewingScope, String> {
+        if token.tag() != TOKEN_TAG {
+            return Err(format!(
+                "expected a {} token, got {}",
+                TOKEN_TAG,
+                token.tag()
+            ));
+        }
+        let grant = bincode::deserialize::<Grant>(&token.value())
+            .map_err(|_| String::from("malformed viewing capability"))?;
+        let capability = self
+            .capabilities
+            .get(wallet_path)
+            .and_then(|capabilities| capabilities.get(&grant.id))
+            .filter(|capability| capability.grant == grant)
+            .ok_or_else(|| String::from("unknown viewing capability"))?;
+        if capability.revoked {
+            return Err(format!("viewing capability {} has been revoked", grant.id));
+        }
+        if matches!(grant.expires, Some(expires) if now() >= expires) {
+            return Err(format!("viewing capability {} has expired", grant.id));
+        }
+        Ok(grant.scope)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -12,28 +12,79 @@
 //! of the actual routes is defined in [crate::routes].
 
 #![allow(clippy::format_push_string)]
-use crate::routes::{dispatch_url, CapeAPIError, RouteBinding, UrlSegmentValue, Wallet};
+use crate::alerts::Alerts;
+use crate::asset_metadata::AssetMetadataStore;
+use crate::auth::AdminToken;
+use crate::autoconsolidate::{self, AutoConsolidate};
+use crate::autoretry::{self, AutoRetry};
+use crate::backups::{self, Backups};
+use crate::balance_index::BalanceIndex;
+use crate::cache::{self, CacheKey, CachedResponse, ResponseCache};
+use crate::categories::Categories;
+use crate::clock::Clock;
+use crate::coin_selection::CoinSelection;
+use crate::confirmations::Confirmations;
+use crate::contacts::Contacts;
+use crate::conversions::Conversions;
+use crate::error_detail;
+use crate::eth_links::EthLinks;
+use crate::feetopup::{self, FeeTopUp};
+use crate::idle::{self, Activity};
+use crate::lock::WalletLock;
+use crate::min_anonymity::MinAnonymity;
+use crate::notified::NotifiedRecords;
+use crate::payment_requests::PaymentRequests;
+use crate::persisted::Persisted;
+use crate::proof_threads::ProofThreads;
+use crate::pruned::PrunedTransactions;
+use crate::quarantine::Quarantine;
+use crate::ranges;
+use crate::route_stats::RouteStatistics;
+use crate::routes::{
+    dispatch_url, server_error, CapeAPIError, RouteBinding, UrlSegmentValue, Wallet,
+};
+use crate::schedules::{self, Schedules};
+use crate::sent::SentTransfers;
+use crate::spend_limits::SpendLimits;
+use crate::sponsors::Sponsors;
+use crate::subscriptions::{self, Subscriptions};
+#[cfg(any(test, feature = "test-state"))]
+use crate::teststate;
+use crate::tls;
+use crate::verifiers::Verifiers;
+use crate::viewing_capabilities::ViewingCapabilities;
+use crate::webhook::{self, Webhooks};
 use async_std::{
     sync::{Arc, Mutex},
     task::{spawn, JoinHandle},
 };
 use cap_rust_sandbox::model::EthereumAddr;
-use cape_wallet::disco::{self, default_api_path, default_web_path, UrlSegmentType};
+use cape_wallet::{
+    backend::ScanInterval,
+    disco::{self, default_api_path, default_web_path, ApiRouteKey, UrlSegmentType},
+    ui::{ApiIntegrity, ErrorDetail, WalletSnapshot},
+};
 use ethers::prelude::{Address, H160};
-use jf_cap::{keys::UserKeyPair, structs::AssetCode};
+use jf_cap::{
+    keys::{UserKeyPair, UserPubKey},
+    structs::AssetCode,
+};
 use net::server;
 use rand_chacha::ChaChaRng;
-use std::collections::hash_map::HashMap;
+use seahorse::events::EventSource;
+use std::collections::{hash_map::HashMap, HashSet};
 use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use tagged_base64::TaggedBase64;
 use tide::{
     http::{headers::HeaderValue, Method, Url},
     security::{CorsMiddleware, Origin},
+    StatusCode,
 };
+use tracing::Instrument;
 
 pub const DEFAULT_ETH_ADDR: Address = H160([2; 20]);
 pub const DEFAULT_WRAPPED_AMT: u128 = 1000;
@@ -55,6 +106,13 @@ pub struct NodeOpt {
     #[structopt(long = "api")]
     pub api_path: Option<PathBuf>,
 
+    /// Public key which must have signed the API specification.
+    ///
+    /// If given, the server refuses to start unless a signature of the API specification by this
+    /// key is stored next to it, in a file with `.sig` appended to its name.
+    #[structopt(long, env = "CAPE_WALLET_API_SIGNER")]
+    pub api_signer: Option<UserPubKey>,
+
     /// Path to store keystores and location of most recent wallet
     #[structopt(
         long,
@@ -97,6 +155,107 @@ pub struct NodeOpt {
     /// Minimum amount of time to wait between polling requests to EQS.
     #[structopt(long, env = "CAPE_WALLET_MIN_POLLING_DELAY", default_value = "500")]
     pub min_polling_delay_ms: u64,
+
+    /// Interval, in milliseconds, at which to poll the EQS for new records.
+    ///
+    /// If 0, the EQS is not polled at all. If not specified, the EQS is polled every
+    /// `min-polling-delay-ms`. This can be changed at runtime with `scanconfig`.
+    #[structopt(long = "scan-interval", env = "CAPE_WALLET_SCAN_INTERVAL")]
+    pub scan_interval_ms: Option<u64>,
+
+    /// Number of threads to generate transaction proofs with.
+    ///
+    /// If not specified, one thread is used per CPU. This can be changed at runtime with
+    /// `proofconfig`.
+    #[structopt(long, env = "CAPE_WALLET_PROOF_THREADS")]
+    pub proof_threads: Option<usize>,
+
+    /// URL of an OpenTelemetry collector to export request spans to.
+    ///
+    /// If not specified, spans and events are only logged locally.
+    #[structopt(long, env = "CAPE_WALLET_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<Url>,
+
+    /// Number of consecutive unused sending keys to scan before giving up when recovering a wallet.
+    ///
+    /// If 0, no keys are scanned automatically. This can be changed at runtime with `gaplimit`.
+    #[structopt(long, env = "CAPE_WALLET_GAP_LIMIT", default_value = "0")]
+    pub gap_limit: usize,
+
+    /// Allow `fsck/repair` to modify the wallet store.
+    ///
+    /// Without this flag, `fsck` only reports inconsistencies.
+    #[structopt(long)]
+    pub enable_fsck_repair: bool,
+
+    /// Only allow wrapping from Ethereum addresses whose ownership has been proven with `linketh`.
+    #[structopt(long)]
+    pub require_eth_link: bool,
+
+    /// Enable routes which help with development but should not be exposed in production, such as
+    /// `testvectors`.
+    #[structopt(long)]
+    pub dev_mode: bool,
+
+    /// Close the open wallet after this many seconds without a request.
+    ///
+    /// Requests which need a wallet then fail with `WalletTimedOut` until a wallet is opened again.
+    /// If not specified, wallets stay open until they are closed explicitly.
+    #[structopt(long = "idle-timeout", env = "CAPE_WALLET_IDLE_TIMEOUT")]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Pretty-print JSON responses by default.
+    ///
+    /// Clients can override this for a single request with the `pretty` query parameter.
+    #[structopt(long)]
+    pub pretty_json: bool,
+
+    /// How much diagnostic detail to include in error responses (terse or verbose).
+    ///
+    /// Terse errors contain just the error and its message. Verbose errors also explain how the
+    /// request was matched against the API, and the chain of errors which caused the failure. Clients
+    /// can override this for a single request with the `verbose` query parameter, and the level can
+    /// be changed at run time with `errordetail`.
+    #[structopt(long, env = "CAPE_WALLET_ERROR_DETAIL", default_value = "terse")]
+    pub error_detail: ErrorDetail,
+
+    /// The most memory, in bytes, to use for cached responses to read-only routes.
+    ///
+    /// When the cache grows past this size, the least recently used responses are evicted. If not
+    /// specified, the cache is not limited.
+    #[structopt(long, env = "CAPE_WALLET_CACHE_BUDGET")]
+    pub cache_budget: Option<usize>,
+
+    /// Admin token to enable authentication with, if no admin token has been stored yet.
+    ///
+    /// Once the server has stored an admin token, this is ignored, and the stored token can only be
+    /// changed with `auth/rotate`.
+    #[structopt(long, env = "CAPE_WALLET_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// PEM file with the certificate chain to serve the API over TLS with.
+    ///
+    /// If specified, the server only accepts TLS connections, and `--tls-key` is required.
+    #[structopt(long, env = "CAPE_WALLET_TLS_CERT", requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of `--tls-cert`.
+    #[structopt(long, env = "CAPE_WALLET_TLS_KEY", requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM file with the certificates of the CAs which issue client certificates.
+    ///
+    /// If specified, clients must present a certificate issued by one of these CAs, and connections
+    /// from clients which do not are rejected during the TLS handshake. Requires `--tls-cert`.
+    #[structopt(long, env = "CAPE_WALLET_CLIENT_CA", requires = "tls-cert")]
+    pub client_ca: Option<PathBuf>,
+
+    /// Disable a route, so that requests for it fail with `RouteDisabled`.
+    ///
+    /// May be given more than once. Disabled routes are still described by the API schema, and are
+    /// listed by `features`.
+    #[structopt(long = "disable-route", number_of_values = 1)]
+    pub disabled_routes: Vec<ApiRouteKey>,
 }
 
 impl Default for NodeOpt {
@@ -104,6 +263,7 @@ impl Default for NodeOpt {
         Self {
             web_path: None,
             api_path: None,
+            api_signer: None,
             storage: None,
             port: 60000,
             eqs_url: "http://localhost:50087"
@@ -118,6 +278,22 @@ impl Default for NodeOpt {
             rpc_url: None,
             eth_mnemonic: None,
             min_polling_delay_ms: 500,
+            scan_interval_ms: None,
+            proof_threads: None,
+            otlp_endpoint: None,
+            gap_limit: 0,
+            enable_fsck_repair: false,
+            require_eth_link: false,
+            dev_mode: false,
+            idle_timeout_secs: None,
+            pretty_json: false,
+            error_detail: ErrorDetail::Terse,
+            cache_budget: None,
+            admin_token: None,
+            tls_cert: None,
+            tls_key: None,
+            client_ca: None,
+            disabled_routes: vec![],
         }
     }
 }
@@ -135,6 +311,13 @@ impl NodeOpt {
         }
     }
 
+    /// Whether `route` was disabled with `--disable-route`.
+    pub fn route_disabled(&self, route: &ApiRouteKey) -> bool {
+        self.disabled_routes
+            .iter()
+            .any(|disabled| disabled.as_ref() == route.as_ref())
+    }
+
     pub fn web_path(&self) -> PathBuf {
         self.web_path
             .as_ref()
@@ -176,12 +359,128 @@ impl NodeOpt {
             .collect()
     }
 
+    pub fn admin_token_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("admin_token")].iter().collect()
+    }
+
+    pub fn asset_metadata_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("asset_metadata")]
+            .iter()
+            .collect()
+    }
+
+    pub fn contacts_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("contacts")].iter().collect()
+    }
+
+    pub fn conversions_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("conversions")].iter().collect()
+    }
+
+    pub fn spend_limits_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("spend_limits")]
+            .iter()
+            .collect()
+    }
+
+    pub fn schedules_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("schedules")].iter().collect()
+    }
+
+    pub fn eth_links_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("eth_links")].iter().collect()
+    }
+
+    pub fn autoconsolidate_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("autoconsolidate")]
+            .iter()
+            .collect()
+    }
+
+    pub fn feetopup_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("feetopup")].iter().collect()
+    }
+
+    pub fn categories_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("categories")].iter().collect()
+    }
+
+    pub fn sent_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("sent")].iter().collect()
+    }
+
+    pub fn coin_selection_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("coin_selection")]
+            .iter()
+            .collect()
+    }
+
+    pub fn quarantine_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("quarantine")].iter().collect()
+    }
+
+    pub fn notified_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("notified")].iter().collect()
+    }
+
+    pub fn payment_requests_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("payment_requests")]
+            .iter()
+            .collect()
+    }
+
+    pub fn webhooks_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("webhooks")].iter().collect()
+    }
+
     pub fn assets_path(&self) -> PathBuf {
         [&self.storage(), Path::new("verified_assets")]
             .iter()
             .collect()
     }
 
+    pub fn confirmations_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("confirmations")]
+            .iter()
+            .collect()
+    }
+
+    pub fn sponsors_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("sponsors")].iter().collect()
+    }
+
+    pub fn autoretry_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("autoretry")].iter().collect()
+    }
+
+    pub fn verifiers_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("verifiers")].iter().collect()
+    }
+
+    pub fn viewing_capabilities_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("viewing_capabilities")]
+            .iter()
+            .collect()
+    }
+
+    pub fn pruned_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("pruned")].iter().collect()
+    }
+
+    pub fn alerts_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("alerts")].iter().collect()
+    }
+
+    pub fn min_anonymity_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("min_anonymity")]
+            .iter()
+            .collect()
+    }
+
+    pub fn backups_path(&self) -> PathBuf {
+        [&self.storage(), Path::new("backups")].iter().collect()
+    }
+
     pub fn web3_provider(&self) -> Option<Url> {
         self.rpc_url.clone()
     }
@@ -205,6 +504,22 @@ impl NodeOpt {
     pub fn min_polling_delay(&self) -> Duration {
         Duration::from_millis(self.min_polling_delay_ms)
     }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn scan_interval(&self) -> Option<Duration> {
+        match self.scan_interval_ms {
+            Some(0) => None,
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => Some(self.min_polling_delay()),
+        }
+    }
+
+    pub fn otlp_endpoint(&self) -> Option<Url> {
+        self.otlp_endpoint.clone()
+    }
 }
 
 /// Returns the default path to store generated files.
@@ -218,10 +533,92 @@ fn default_storage_path() -> PathBuf {
 #[derive(Clone)]
 pub struct WebState {
     pub(crate) api: toml::Value,
+    /// The result of checking the signature of `api` at startup.
+    pub(crate) api_integrity: ApiIntegrity,
     pub(crate) wallet: Arc<Mutex<Option<Wallet>>>,
     pub(crate) rng: Arc<Mutex<ChaChaRng>>,
     pub(crate) faucet_key_pair: UserKeyPair,
     pub(crate) options: NodeOpt,
+    /// Wallet snapshots taken by `snapshot`, indexed by token, with the time each was taken.
+    pub(crate) snapshots: Arc<Mutex<HashMap<u64, (Instant, WalletSnapshot)>>>,
+    /// The minimum fee last fetched from the relayer by `feerate`, with the time it was fetched.
+    pub(crate) fee_rate: Arc<Mutex<Option<(Instant, u64)>>>,
+    /// The backlog last fetched from the relayer by `congestion`, with the time it was fetched.
+    pub(crate) congestion: Arc<Mutex<Option<(Instant, relayer::Congestion)>>>,
+    /// Gap limit used when recovering sending keys; see [NodeOpt::gap_limit].
+    pub(crate) gap_limit: Arc<Mutex<usize>>,
+    /// Interval at which open wallets poll for new records; see [NodeOpt::scan_interval_ms].
+    pub(crate) scan_interval: ScanInterval,
+    /// Thread pool for generating proofs; see [NodeOpt::proof_threads].
+    pub(crate) proof_threads: Arc<Mutex<ProofThreads>>,
+    /// Open streaming subscriptions.
+    pub(crate) subscriptions: Arc<Mutex<Subscriptions>>,
+    /// The current admin token, if authentication is enabled.
+    pub(crate) admin_token: Arc<Mutex<Option<AdminToken>>>,
+    /// Whether submission of transactions has been paused by an operator.
+    pub(crate) submission_paused: Arc<Mutex<bool>>,
+    /// The read-only lock on the open wallet, if it is locked.
+    pub(crate) lock: Arc<Mutex<Option<WalletLock>>>,
+    /// The address book.
+    pub(crate) contacts: Arc<Mutex<Contacts>>,
+    /// Local off-chain asset metadata.
+    pub(crate) asset_metadata: Arc<Mutex<AssetMetadataStore>>,
+    /// Wraps and unwraps submitted through this server.
+    pub(crate) conversions: Arc<Mutex<Conversions>>,
+    /// Per-asset spending limits enforced by `send` and `mint`.
+    pub(crate) spend_limits: Arc<Mutex<SpendLimits>>,
+    /// Call statistics of each route since the server started.
+    pub(crate) route_stats: Arc<Mutex<RouteStatistics>>,
+    /// Request activity, for closing idle wallets; see [NodeOpt::idle_timeout_secs].
+    pub(crate) activity: Arc<Mutex<Activity>>,
+    /// Recurring transfers.
+    pub(crate) schedules: Arc<Mutex<Schedules>>,
+    /// Responses to read-only routes.
+    pub(crate) cache: Arc<Mutex<ResponseCache>>,
+    /// Balances of the open wallet, kept up to date with its records.
+    pub(crate) balance_index: Arc<Mutex<BalanceIndex>>,
+    /// Ethereum addresses whose ownership has been proven.
+    pub(crate) eth_links: Arc<Mutex<EthLinks>>,
+    /// Automatic consolidation policies, by wallet.
+    pub(crate) autoconsolidate: Arc<Mutex<AutoConsolidate>>,
+    /// Automatic fee top-up policies, by wallet.
+    pub(crate) feetopup: Arc<Mutex<FeeTopUp>>,
+    /// Transaction categories, by wallet.
+    pub(crate) categories: Arc<Mutex<Categories>>,
+    /// Parameters of transfers sent through this server, by wallet.
+    pub(crate) sent: Arc<Mutex<SentTransfers>>,
+    /// Record selection strategies, by wallet.
+    pub(crate) coin_selection: Arc<Mutex<CoinSelection>>,
+    /// Quarantine policies and quarantined records, by wallet.
+    pub(crate) quarantine: Arc<Mutex<Quarantine>>,
+    /// Records notified by an external indexer, by wallet.
+    pub(crate) notified: Arc<Mutex<NotifiedRecords>>,
+    /// Payment requests, by wallet.
+    pub(crate) payment_requests: Arc<Mutex<PaymentRequests>>,
+    /// Settlement webhooks, by wallet.
+    pub(crate) webhooks: Arc<Mutex<Webhooks>>,
+    /// Confirmation depths, by wallet.
+    pub(crate) confirmations: Arc<Mutex<Confirmations>>,
+    /// Sponsors of the assets sponsored through this server.
+    pub(crate) sponsors: Arc<Mutex<Sponsors>>,
+    /// Automatic retry policies for skipped events, by wallet.
+    pub(crate) autoretry: Arc<Mutex<AutoRetry>>,
+    /// Trusted asset verification authorities, by wallet.
+    pub(crate) verifiers: Arc<Mutex<Verifiers>>,
+    /// Transactions pruned from reported history, by wallet.
+    pub(crate) pruned: Arc<Mutex<PrunedTransactions>>,
+    /// Large transaction alert thresholds, by wallet.
+    pub(crate) alerts: Arc<Mutex<Alerts>>,
+    /// Observations of the ledger's height over time, for `clock`.
+    pub(crate) clock: Arc<Mutex<Clock>>,
+    /// Minimum anonymity sets of records spent by `send`, by wallet.
+    pub(crate) min_anonymity: Arc<Mutex<MinAnonymity>>,
+    /// Automatic backup policies, by wallet.
+    pub(crate) backups: Arc<Mutex<Backups>>,
+    /// Viewing capabilities granted, by wallet.
+    pub(crate) viewing_capabilities: Arc<Mutex<ViewingCapabilities>>,
+    /// The default level of detail in error responses, as configured by `errordetail`.
+    pub(crate) error_detail: Arc<Mutex<ErrorDetail>>,
 }
 
 // Get the route pattern that matches the URL of a request, and the bindings for parameters in the
@@ -229,16 +626,62 @@ pub struct WebState {
 fn parse_route(
     req: &tide::Request<WebState>,
 ) -> Result<(String, HashMap<String, RouteBinding>), String> {
-    let first_segment = &req
+    let segments = req
         .url()
         .path_segments()
         .ok_or_else(|| String::from("No path segments"))?
-        .next()
-        .ok_or_else(|| String::from("Empty path"))?;
-    let api = &req.state().api["route"][first_segment];
+        .collect::<Vec<_>>();
+    match_route(&req.state().api, &segments)
+}
+
+// Get the route pattern that matches the path `segments`, and the bindings for parameters in the
+// pattern, as for `parse_route`.
+pub(crate) fn match_route(
+    api: &toml::Value,
+    segments: &[&str],
+) -> Result<(String, HashMap<String, RouteBinding>), String> {
+    let (route, arg_doc) = explain_route(api, segments)?;
+    route.ok_or(arg_doc)
+}
+
+// Route parameters whose values are secret, and must never be echoed back in diagnostics.
+const SECRET_PARAMS: [&str; 4] = [":key", ":mnemonic", ":password", ":pin"];
+
+// Match the path `segments` against the route patterns, as for `match_route`, and explain how each
+// pattern did or did not match. The route is `None` if no single pattern matches.
+//
+// Any request segment in a position where some pattern of the route expects a secret parameter is
+// redacted from the explanation.
+pub(crate) fn explain_route(
+    api: &toml::Value,
+    segments: &[&str],
+) -> Result<(Option<(String, HashMap<String, RouteBinding>)>, String), String> {
+    let first_segment = segments.first().ok_or_else(|| String::from("Empty path"))?;
+    let api = api["route"]
+        .get(first_segment)
+        .ok_or_else(|| format!("No route {}", first_segment))?;
     let route_patterns = api["PATH"]
         .as_array()
         .expect("Invalid PATH type. Expecting array.");
+    let secret_positions = route_patterns
+        .iter()
+        .flat_map(|route_pattern| {
+            route_pattern
+                .as_str()
+                .expect("PATH must be an array of strings")
+                .split('/')
+                .enumerate()
+                .filter(|(_, pat_segment)| SECRET_PARAMS.contains(pat_segment))
+                .map(|(position, _)| position)
+        })
+        .collect::<HashSet<_>>();
+    let redact = |position: usize, req_segment: &str| {
+        if secret_positions.contains(&position) {
+            String::from("<redacted>")
+        } else {
+            req_segment.to_string()
+        }
+    };
     let mut arg_doc: String = api["DOC"].as_str().expect("Missing DOC").to_string();
     let mut matching_route_count = 0u64;
     let mut matching_route = String::new();
@@ -252,12 +695,12 @@ fn parse_route(
                 .as_str()
                 .expect("PATH must be an array of strings")
         ));
-        // The `path_segments()` succeeded above, so `unwrap()` is safe.
-        let mut req_segments = req.url().path_segments().unwrap();
-        for pat_segment in route_pattern
+        let mut req_segments = segments.iter().copied();
+        for (position, pat_segment) in route_pattern
             .as_str()
             .expect("PATH must be an array of strings")
             .split('/')
+            .enumerate()
         {
             // Each route parameter has an associated type. The lookup
             // will only succeed if the current segment is a parameter
@@ -270,7 +713,9 @@ fn parse_route(
                 let req_segment = req_segments.next().unwrap_or("");
                 arg_doc.push_str(&format!(
                     "  Argument: {} as type {} and value: {} ",
-                    pat_segment, segment_type, req_segment
+                    pat_segment,
+                    segment_type,
+                    redact(position, req_segment)
                 ));
                 let ptype =
                     UrlSegmentType::from_str(segment_type).map_err(|err| err.to_string())?;
@@ -300,7 +745,8 @@ fn parse_route(
                     found_literal_mismatch = true;
                     arg_doc.push_str(&format!(
                         "Request segment {} does not match route segment {}.\n",
-                        req_segment, pat_segment
+                        redact(position, req_segment),
+                        pat_segment
                     ));
                 }
             }
@@ -342,15 +788,15 @@ fn parse_route(
     match matching_route_count {
         0 => {
             arg_doc.push_str("\nNeed documentation");
-            Err(arg_doc)
+            Ok((None, arg_doc))
         }
         1 => {
             let route_bindings = bindings.remove(&matching_route).unwrap_or_default();
-            Ok((matching_route, route_bindings))
+            Ok((Some((matching_route, route_bindings)), arg_doc))
         }
         _ => {
             arg_doc.push_str("\nAmbiguity in api.toml");
-            Err(arg_doc)
+            Ok((None, arg_doc))
         }
     }
 }
@@ -361,11 +807,114 @@ fn parse_route(
 /// is an unfortunate side-effect of defining the routes in an external file.
 async fn entry_page(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
     match parse_route(&req) {
-        Ok((pattern, bindings)) => dispatch_url(req, pattern.as_str(), &bindings).await,
+        Ok((pattern, bindings)) => {
+            // Handle each request in a span tagged with the matched route, so that exported traces
+            // can be grouped by endpoint.
+            let span = tracing::info_span!("request", method = %req.method(), route = %pattern);
+            let state = req.state().clone();
+            state.activity.lock().await.touch();
+            let start = Instant::now();
+            let res = dispatch_cached(req, pattern.as_str(), &bindings)
+                .instrument(span)
+                .await;
+            let failed = match &res {
+                Ok(res) => !res.status().is_success(),
+                Err(_) => true,
+            };
+            state
+                .route_stats
+                .lock()
+                .await
+                .record(&pattern, start.elapsed(), failed);
+
+            // Count the end of a long request as activity too, so the wallet isn't closed as soon
+            // as it finishes.
+            let wallet_open = state.wallet.lock().await.is_some();
+            let mut activity = state.activity.lock().await;
+            activity.touch();
+            if wallet_open {
+                activity.reopened();
+            }
+            match res {
+                Err(err)
+                    if activity.timed_out()
+                        && matches!(
+                            err.downcast_ref::<CapeAPIError>(),
+                            Some(CapeAPIError::MissingWallet)
+                        ) =>
+                {
+                    Err(server_error(CapeAPIError::WalletTimedOut))
+                }
+                res => res,
+            }
+        }
         Err(arg_doc) => Ok(tide::Response::builder(200).body(arg_doc).build()),
     }
 }
 
+/// Dispatch a request, answering it from the response cache if possible.
+async fn dispatch_cached(
+    req: tide::Request<WebState>,
+    pattern: &str,
+    bindings: &HashMap<String, RouteBinding>,
+) -> Result<tide::Response, tide::Error> {
+    let state = req.state().clone();
+    let method = req.method();
+    if !cache::cacheable(method, pattern) {
+        let res = dispatch_url(req, pattern, bindings).await;
+        if cache::modifies_wallet(method, pattern) {
+            state.cache.lock().await.invalidate();
+        }
+        return res;
+    }
+
+    // Responses are only cached while a wallet is open, since they depend on its state.
+    let event_index = match &*state.wallet.lock().await {
+        Some(wallet) => Some(wallet.now().await.index(EventSource::QueryService)),
+        None => None,
+    };
+    let event_index = match event_index {
+        Some(event_index) => event_index,
+        None => return dispatch_url(req, pattern, bindings).await,
+    };
+    let url = req.url();
+    let key = CacheKey {
+        url: match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        accept: req
+            .header("Accept")
+            .map(|accept| accept.as_str().to_string()),
+        event_index,
+    };
+    // Read the generation before computing the response, so that if the wallet is modified while
+    // we are computing it, the response is not cached.
+    let generation = {
+        let mut cache = state.cache.lock().await;
+        if let Some(cached) = cache.get(&key) {
+            let mut res = tide::Response::new(StatusCode::Ok);
+            if let Some(content_type) = cached.content_type {
+                res.set_content_type(content_type);
+            }
+            res.set_body(cached.body);
+            return Ok(res);
+        }
+        cache.generation()
+    };
+
+    let mut res = dispatch_url(req, pattern, bindings).await?;
+    if res.status() == StatusCode::Ok {
+        let cached = CachedResponse {
+            content_type: res.content_type(),
+            body: res.take_body().into_bytes().await?,
+        };
+        res.set_body(cached.body.clone());
+        state.cache.lock().await.insert(key, generation, cached);
+    }
+    Ok(res)
+}
+
 /// Testing route handler which populates a wallet with dummy data.
 ///
 /// This route will modify the wallet by generating 2 of each kind of key (viewing, freezing, and
@@ -482,46 +1031,364 @@ async fn populatefortest(req: tide::Request<WebState>) -> Result<tide::Response,
     })
     .await;
 
+    // This route is not dispatched through the response cache, but it changes the wallet.
+    req.state().cache.lock().await.invalidate();
     server::response(&req, receipt)
 }
 
-/// Start the CAPE wallet server.
-///
-/// The server runs on `localhost` at the specified port. A new task is spawned to run the server,
-/// and a handle to the task is returned. Waiting on the handle will join the task; dropping the
-/// handle will detach the task.
+/// Testing route handler which makes the mock relayer report a backlog of `:pending` transactions,
+/// with `:accepted` accepted in the last minute.
+#[cfg(test)]
+async fn congestionfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+
+    let congestion = relayer::Congestion {
+        pending: req.param("pending")?.parse()?,
+        accepted: req.param("accepted")?.parse()?,
+        window_secs: relayer::CONGESTION_WINDOW.as_secs(),
+    };
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .set_congestion(Some(congestion));
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the mock relayer unreachable for `congestion`.
+#[cfg(test)]
+async fn relayerdownfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .set_congestion(None);
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the mock network reject the next block submitted to it.
+#[cfg(test)]
+async fn rejectnextfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .reject_next_block();
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the mock network commit the open wallet's record with uid
+/// `:uid` again, at a new uid.
+#[cfg(test)]
+async fn replayrecordfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::{require_wallet, server_error, CapeAPIError};
+
+    let uid = req.param("uid")?.parse::<u64>()?;
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    let record = wallet
+        .records()
+        .await
+        .find(|record| record.uid == uid)
+        .ok_or_else(|| {
+            server_error(CapeAPIError::Param {
+                expected: String::from("uid of a record in the wallet"),
+                actual: uid.to_string(),
+            })
+        })?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .replay_record(record.ro);
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the next wallet opened use the same mock ledger as the open
+/// wallet, instead of a new one, so that the two wallets can exchange records.
+#[cfg(test)]
+async fn shareledgerfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::{require_wallet, share_ledger};
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    let ledger = wallet.lock().await.backend().ledger().clone();
+    share_ledger(&req.state().options, ledger);
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the mock network withhold the memos for the next block
+/// committed.
+#[cfg(test)]
+async fn withholdmemosfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .withhold_next_memos();
+    server::response(&req, ())
+}
+
+/// Testing route handler which makes the wallet skip the next memos event, as if it had failed to
+/// process it.
+#[cfg(test)]
+async fn skipmemosfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .skip_next_memos();
+    server::response(&req, ())
+}
+
+/// Testing route handler which returns the memos the mock network has withheld, as notifications
+/// an indexer could send to `notifyrecord`.
+#[cfg(test)]
+async fn withheldmemosfortest(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    use crate::routes::require_wallet;
+    use cape_wallet::ui::RecordNotification;
+
+    let wallet = &mut *req.state().wallet.lock().await;
+    let wallet = require_wallet(wallet)?;
+    let notifications = wallet
+        .lock()
+        .await
+        .backend()
+        .ledger()
+        .lock()
+        .await
+        .network()
+        .withheld_memos()
+        .into_iter()
+        .map(|(memo, commitment, uid, merkle_path)| RecordNotification {
+            memo,
+            commitment,
+            uid,
+            merkle_path,
+        })
+        .collect::<Vec<_>>();
+    server::response(&req, notifications)
+}
+
+/// Build the CAPE wallet server, with all middleware and routes installed.
 ///
-/// Note that there is currently no way to stop the server task once started, other than killing the
-/// entire process. This is a limitation of the Tide server framework.
-pub fn init_server(
+/// The server is not bound to a port. It can be started with [tide::Server::listen], or requests
+/// can be handled directly with [tide::Server::respond]. Most callers should use [init_server].
+pub fn build_server(
     mut rng: ChaChaRng,
     options: &NodeOpt,
-) -> std::io::Result<JoinHandle<std::io::Result<()>>> {
+) -> std::io::Result<tide::Server<WebState>> {
     // Make sure relevant sub-directories of `storage` exist.
     create_dir_all(options.keystores_dir())?;
 
-    let api = disco::load_messages(&options.api_path());
+    // Authentication is enabled if an admin token has been stored, or if one was given on the
+    // command line, in which case we store it now.
+    let admin_token = match AdminToken::load(&options.admin_token_path())? {
+        Some(admin_token) => Some(admin_token),
+        None => match &options.admin_token {
+            Some(token) => {
+                let admin_token = AdminToken::new(token);
+                admin_token.save(&options.admin_token_path())?;
+                Some(admin_token)
+            }
+            None => None,
+        },
+    };
+
+    let proof_threads = ProofThreads::new(options.proof_threads)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    let contacts = Contacts::load(&options.contacts_path())?;
+    let asset_metadata = AssetMetadataStore::load(&options.asset_metadata_path())?;
+    let conversions = Conversions::load(&options.conversions_path())?;
+    let spend_limits = SpendLimits::load(&options.spend_limits_path())?;
+    let schedules = Schedules::load(&options.schedules_path())?;
+    let eth_links = EthLinks::load(&options.eth_links_path())?;
+    let autoconsolidate = AutoConsolidate::load(&options.autoconsolidate_path())?;
+    let feetopup = FeeTopUp::load(&options.feetopup_path())?;
+    let categories = Categories::load(&options.categories_path())?;
+    let sent = SentTransfers::load(&options.sent_path())?;
+    let coin_selection = CoinSelection::load(&options.coin_selection_path())?;
+    let quarantine = Quarantine::load(&options.quarantine_path())?;
+    let notified = NotifiedRecords::load(&options.notified_path())?;
+    let payment_requests = PaymentRequests::load(&options.payment_requests_path())?;
+    let webhooks = Webhooks::load(&options.webhooks_path())?;
+    let confirmations = Confirmations::load(&options.confirmations_path())?;
+    let sponsors = Sponsors::load(&options.sponsors_path())?;
+    let autoretry = AutoRetry::load(&options.autoretry_path())?;
+    let verifiers = Verifiers::load(&options.verifiers_path())?;
+    let pruned = PrunedTransactions::load(&options.pruned_path())?;
+    let alerts = Alerts::load(&options.alerts_path())?;
+    let min_anonymity = MinAnonymity::load(&options.min_anonymity_path())?;
+    let backups = Backups::load(&options.backups_path())?;
+    let viewing_capabilities = ViewingCapabilities::load(&options.viewing_capabilities_path())?;
+
+    let (api, api_integrity) =
+        disco::load_messages(&options.api_path(), options.api_signer.as_ref())?;
     let faucet_key_pair = UserKeyPair::generate(&mut rng);
-    let mut web_server = tide::with_state(WebState {
+    let state = WebState {
         api: api.clone(),
+        api_integrity,
         wallet: Arc::new(Mutex::new(None)),
         rng: Arc::new(Mutex::new(rng)),
         faucet_key_pair,
         options: options.clone(),
-    });
+        snapshots: Default::default(),
+        fee_rate: Default::default(),
+        congestion: Default::default(),
+        gap_limit: Arc::new(Mutex::new(options.gap_limit)),
+        scan_interval: ScanInterval::new(options.scan_interval()),
+        proof_threads: Arc::new(Mutex::new(proof_threads)),
+        subscriptions: Default::default(),
+        admin_token: Arc::new(Mutex::new(admin_token)),
+        submission_paused: Default::default(),
+        lock: Default::default(),
+        contacts: Arc::new(Mutex::new(contacts)),
+        asset_metadata: Arc::new(Mutex::new(asset_metadata)),
+        conversions: Arc::new(Mutex::new(conversions)),
+        spend_limits: Arc::new(Mutex::new(spend_limits)),
+        activity: Default::default(),
+        route_stats: Default::default(),
+        clock: Default::default(),
+        schedules: Arc::new(Mutex::new(schedules)),
+        cache: Arc::new(Mutex::new(ResponseCache::with_budget(options.cache_budget))),
+        balance_index: Default::default(),
+        eth_links: Arc::new(Mutex::new(eth_links)),
+        autoconsolidate: Arc::new(Mutex::new(autoconsolidate)),
+        feetopup: Arc::new(Mutex::new(feetopup)),
+        categories: Arc::new(Mutex::new(categories)),
+        sent: Arc::new(Mutex::new(sent)),
+        coin_selection: Arc::new(Mutex::new(coin_selection)),
+        quarantine: Arc::new(Mutex::new(quarantine)),
+        notified: Arc::new(Mutex::new(notified)),
+        payment_requests: Arc::new(Mutex::new(payment_requests)),
+        webhooks: Arc::new(Mutex::new(webhooks)),
+        confirmations: Arc::new(Mutex::new(confirmations)),
+        sponsors: Arc::new(Mutex::new(sponsors)),
+        autoretry: Arc::new(Mutex::new(autoretry)),
+        verifiers: Arc::new(Mutex::new(verifiers)),
+        pruned: Arc::new(Mutex::new(pruned)),
+        alerts: Arc::new(Mutex::new(alerts)),
+        min_anonymity: Arc::new(Mutex::new(min_anonymity)),
+        backups: Arc::new(Mutex::new(backups)),
+        viewing_capabilities: Arc::new(Mutex::new(viewing_capabilities)),
+        error_detail: Arc::new(Mutex::new(options.error_detail)),
+    };
+    spawn(schedules::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.schedules.clone(),
+        state.spend_limits.clone(),
+        state.min_anonymity.clone(),
+        state.submission_paused.clone(),
+        state.lock.clone(),
+        state.cache.clone(),
+    ));
+    spawn(autoconsolidate::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.autoconsolidate.clone(),
+        state.activity.clone(),
+        state.submission_paused.clone(),
+        state.lock.clone(),
+        state.cache.clone(),
+    ));
+    spawn(feetopup::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.feetopup.clone(),
+        state.activity.clone(),
+        state.submission_paused.clone(),
+        state.lock.clone(),
+        state.cache.clone(),
+    ));
+    spawn(webhook::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.webhooks.clone(),
+        state.confirmations.clone(),
+        state.alerts.clone(),
+    ));
+    spawn(autoretry::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.autoretry.clone(),
+        state.cache.clone(),
+    ));
+    spawn(backups::run(
+        options.clone(),
+        state.wallet.clone(),
+        state.backups.clone(),
+        state.contacts.clone(),
+        state.asset_metadata.clone(),
+    ));
+    if let Some(timeout) = options.idle_timeout() {
+        spawn(idle::watchdog(
+            timeout,
+            state.wallet.clone(),
+            state.activity.clone(),
+            state.cache.clone(),
+        ));
+    }
+    let mut web_server = tide::with_state(state);
     web_server
         .with(
             CorsMiddleware::new()
-                .allow_methods("GET, POST".parse::<HeaderValue>().unwrap())
+                .allow_methods("GET, POST, DELETE".parse::<HeaderValue>().unwrap())
                 .allow_headers("*".parse::<HeaderValue>().unwrap())
                 .allow_origin(Origin::from("*"))
                 .allow_credentials(true),
         )
         .with(server::trace)
-        .with(server::add_error_body::<_, CapeAPIError>);
+        .with(server::add_error_body::<_, CapeAPIError>)
+        .with(error_detail::error_detail);
 
     // Define the routes handled by the web server.
-    web_server.at("/public").serve_dir(options.web_path())?;
+    web_server
+        .at("/public")
+        .with(ranges::ranges)
+        .serve_dir(options.web_path())?;
     web_server
         .at("/")
         .get(|req: tide::Request<WebState>| async move {
@@ -551,23 +1418,89 @@ pub fn init_server(
                     .collect(),
                 _ => panic!("Expecting a toml::String or toml::Array, but got: {:?}", &v),
             };
-            let method = match v.get("METHOD") {
-                Some(m) => m
-                    .as_str()
-                    .expect("PATH must be string")
+            let parse_method = |m: &toml::Value| -> Method {
+                m.as_str()
+                    .expect("METHOD must be string")
                     .parse()
-                    .expect("METHOD must be GET or POST"),
-                None => Method::Get,
+                    .expect("METHOD must be GET, POST or DELETE")
+            };
+            // A route may accept more than one method, in which case METHOD is an array.
+            let methods = match v.get("METHOD") {
+                Some(toml::Value::Array(a)) => a.iter().map(parse_method).collect(),
+                Some(m) => vec![parse_method(m)],
+                None => vec![Method::Get],
             };
             for path in routes {
-                web_server.at(&path).method(method, entry_page);
+                for method in &methods {
+                    web_server.at(&path).method(*method, entry_page);
+                }
             }
         });
     }
 
+    // Streaming routes don't fit the request/response model of the routes in the configuration file,
+    // so they are registered separately.
+    web_server
+        .at("events")
+        .get(subscriptions::endpoint(subscriptions::events));
+    web_server
+        .at("subscribe/assets")
+        .get(subscriptions::endpoint(subscriptions::assets));
+    web_server
+        .at("subscribe/alerts")
+        .get(subscriptions::endpoint(subscriptions::alerts));
+
+    #[cfg(test)]
+    web_server
+        .at("congestionfortest/:pending/:accepted")
+        .post(congestionfortest);
     #[cfg(any(test, feature = "testing"))]
     web_server.at("populatefortest").get(populatefortest);
+    #[cfg(test)]
+    web_server.at("rejectnextfortest").post(rejectnextfortest);
+    #[cfg(test)]
+    web_server.at("relayerdownfortest").post(relayerdownfortest);
+    #[cfg(test)]
+    web_server
+        .at("replayrecordfortest/:uid")
+        .post(replayrecordfortest);
+    #[cfg(test)]
+    web_server.at("shareledgerfortest").post(shareledgerfortest);
+    #[cfg(test)]
+    web_server.at("skipmemosfortest").post(skipmemosfortest);
+    #[cfg(test)]
+    web_server
+        .at("withholdmemosfortest")
+        .get(withheldmemosfortest)
+        .post(withholdmemosfortest);
+    #[cfg(any(test, feature = "test-state"))]
+    {
+        web_server.at("teststate/snapshot").get(teststate::snapshot);
+        web_server
+            .at("teststate/restore/:password")
+            .post(teststate::restore);
+    }
 
+    Ok(web_server)
+}
+
+/// Start the CAPE wallet server.
+///
+/// The server runs on `localhost` at the specified port. A new task is spawned to run the server,
+/// and a handle to the task is returned. Waiting on the handle will join the task; dropping the
+/// handle will detach the task.
+///
+/// Note that there is currently no way to stop the server task once started, other than killing the
+/// entire process. This is a limitation of the Tide server framework.
+pub fn init_server(
+    rng: ChaChaRng,
+    options: &NodeOpt,
+) -> std::io::Result<JoinHandle<std::io::Result<()>>> {
+    let tls_config = tls::server_config(options)?;
+    let web_server = build_server(rng, options)?;
     let addr = format!("0.0.0.0:{}", options.port);
-    Ok(spawn(web_server.listen(addr)))
+    match tls_config {
+        Some(config) => Ok(spawn(tls::listen(web_server, addr, config))),
+        None => Ok(spawn(web_server.listen(addr))),
+    }
 }
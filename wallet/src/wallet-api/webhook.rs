@@ -0,0 +1,312 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+//
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Callbacks notifying an integration when transactions settle.
+//!
+//! A wallet can have one webhook: a URL which the server POSTs a [SettlementEvent] to whenever a
+//! transaction in the wallet's history settles, so integrations do not have to hold a streaming
+//! subscription open. If the webhook was registered with a secret, each request carries an
+//! HMAC-SHA3-256 of its body, keyed by the secret, in the [SIGNATURE_HEADER] header. Webhooks are
+//! stored in the `webhooks` file in the storage directory, keyed by wallet path, along with the
+//! transactions each has been notified of, so a restart does not repeat notifications.
+//!
+//! A transaction the wallet submitted is not considered settled until it has the wallet's required
+//! number of confirmations (see the `confirmations` module).
+//!
+//! If a settled transaction exceeds one of the wallet's alert thresholds (see the `alerts` module),
+//! the webhook also receives a [LargeTransaction] for it. The [EVENT_HEADER] header of each request
+//! tells the two kinds of event apart.
+//!
+//! A background task checks the history of the open wallet periodically. A failed delivery is
+//! retried with exponential backoff, and after the last attempt the event is dropped and the error
+//! is recorded with the webhook.
+
+use crate::alerts::Alerts;
+use crate::confirmations::{self, Confirmations};
+use crate::persisted::Persisted;
+use crate::routes::{read_last_path, Wallet};
+use crate::web::NodeOpt;
+use async_std::{
+    sync::{Arc, Mutex},
+    task::sleep,
+};
+use cape_wallet::{
+    ui::{LargeTransaction, SettlementEvent, TransactionHistoryEntry, WebhookInfo},
+    wallet::{transaction_hash_string, CapeWalletError},
+};
+use hmac::{Hmac, Mac, NewMac};
+use seahorse::txn_builder::TransactionStatus;
+use serde::{Deserialize, Serialize};
+use sha3::Sha3_256;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// The header carrying the signature of a webhook request.
+pub const SIGNATURE_HEADER: &str = "X-Cape-Signature";
+
+/// The header naming the kind of event a webhook request carries: `settlement` for a
+/// [SettlementEvent], or `large_transaction` for a [LargeTransaction].
+pub const EVENT_HEADER: &str = "X-Cape-Event";
+
+/// How often the background task checks for newly settled transactions.
+const TICK: Duration = Duration::from_millis(500);
+
+/// How many times delivery of an event is attempted before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before retrying a failed delivery. The wait doubles after each attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Webhook {
+    url: String,
+    secret: Option<String>,
+    delivered: u64,
+    failed: u64,
+    last_error: Option<String>,
+    /// Hashes of the settled transactions the webhook has been notified of, including those which
+    /// had already settled when it was registered.
+    notified: HashSet<String>,
+}
+
+impl From<&Webhook> for WebhookInfo {
+    fn from(webhook: &Webhook) -> Self {
+        Self {
+            url: webhook.url.clone(),
+            signed: webhook.secret.is_some(),
+            delivered: webhook.delivered,
+            failed: webhook.failed,
+            last_error: webhook.last_error.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Webhooks {
+    webhooks: HashMap<PathBuf, Webhook>,
+}
+
+impl Persisted for Webhooks {}
+
+impl Webhooks {
+    /// The webhook of the wallet at `wallet_path`, if it has one.
+    pub fn get(&self, wallet_path: &Path) -> Option<WebhookInfo> {
+        self.webhooks.get(wallet_path).map(WebhookInfo::from)
+    }
+
+    /// Notify `url` of transactions which settle in the wallet at `wallet_path` from now on.
+    ///
+    /// `settled` are the hashes of the transactions which have already settled, which are not
+    /// notified. Replaces any existing webhook of the wallet.
+    pub fn set(
+        &mut self,
+        wallet_path: &Path,
+        url: String,
+        secret: Option<String>,
+        settled: impl IntoIterator<Item = String>,
+    ) -> WebhookInfo {
+        let webhook = Webhook {
+            url,
+            secret,
+            delivered: 0,
+            failed: 0,
+            last_error: None,
+            notified: settled.into_iter().collect(),
+        };
+        let info = WebhookInfo::from(&webhook);
+        self.webhooks.insert(wallet_path.to_path_buf(), webhook);
+        info
+    }
+
+    /// Remove the webhook of the wallet at `wallet_path`, returning whether it had one.
+    pub fn remove(&mut self, wallet_path: &Path) -> bool {
+        self.webhooks.remove(wallet_path).is_some()
+    }
+}
+
+/// The hex-encoded HMAC-SHA3-256 of `body`, keyed by `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha3_256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The transactions in the history of `wallet`, opened from `wallet_path`, which have settled.
+///
+/// A transaction the wallet submitted settles when it is rejected, or when it is retired and has
+/// the confirmations required by `confirmations`. Transactions the wallet did not submit are only
+/// added to its history once they are committed, so they settle once they have the required
+/// confirmations.
+pub async fn settled_transactions(
+    options: &NodeOpt,
+    confirmations: &Mutex<Confirmations>,
+    wallet: &Wallet,
+    wallet_path: &Path,
+) -> Result<Vec<SettlementEvent>, CapeWalletError> {
+    let mut settled = vec![];
+    for entry in wallet.transaction_history().await? {
+        let hash = match entry.hash.as_ref().and_then(transaction_hash_string) {
+            Some(hash) => hash,
+            None => continue,
+        };
+        if let Some(receipt) = &entry.receipt {
+            if !matches!(
+                wallet.transaction_status(receipt).await,
+                Ok(TransactionStatus::Retired | TransactionStatus::Rejected)
+            ) {
+                continue;
+            }
+        }
+        settled.push(SettlementEvent {
+            hash,
+            transaction: TransactionHistoryEntry::from_wallet(wallet, entry).await,
+        });
+    }
+    let mut history = settled
+        .iter()
+        .map(|settlement| settlement.transaction.clone())
+        .collect::<Vec<_>>();
+    confirmations::apply(options, confirmations, wallet, wallet_path, &mut history)
+        .await
+        .map_err(|err| CapeWalletError::Failed {
+            msg: format!("failed to save confirmations: {}", err),
+        })?;
+    Ok(settled
+        .into_iter()
+        .zip(history)
+        .filter(|(_, entry)| entry.status != confirmations::PENDING)
+        .map(|(settlement, entry)| SettlementEvent {
+            transaction: entry,
+            ..settlement
+        })
+        .collect())
+}
+
+/// Notify the webhook of the open wallet of transactions as they settle.
+///
+/// The task exits once the server which owns `webhooks` has been dropped.
+pub async fn run(
+    options: NodeOpt,
+    wallet: Arc<Mutex<Option<Wallet>>>,
+    webhooks: Arc<Mutex<Webhooks>>,
+    confirmations: Arc<Mutex<Confirmations>>,
+    alerts: Arc<Mutex<Alerts>>,
+) {
+    while Arc::strong_count(&webhooks) > 1 {
+        sleep(TICK).await;
+
+        // Find the new settlements with the wallet locked, but deliver them without holding any
+        // locks, since delivery may take several attempts.
+        let (wallet_path, url, secret, events) = {
+            let wallet = wallet.lock().await;
+            let wallet = match wallet.as_ref() {
+                Some(wallet) => wallet,
+                None => continue,
+            };
+            let wallet_path = match read_last_path(&options).await {
+                Ok(Some(path)) => path,
+                _ => continue,
+            };
+            let (url, secret, notified) = match webhooks.lock().await.webhooks.get(&wallet_path) {
+                Some(webhook) => (
+                    webhook.url.clone(),
+                    webhook.secret.clone(),
+                    webhook.notified.clone(),
+                ),
+                None => continue,
+            };
+            let events =
+                match settled_transactions(&options, &confirmations, wallet, &wallet_path).await {
+                    Ok(events) => events
+                        .into_iter()
+                        .filter(|event| !notified.contains(&event.hash))
+                        .collect::<Vec<_>>(),
+                    Err(err) => {
+                        event!(Level::WARN, "failed to check for settlements: {}", err);
+                        continue;
+                    }
+                };
+            let alerts = alerts.lock().await;
+            let events = events
+                .into_iter()
+                .map(|settlement| {
+                    let alert =
+                        alerts.check(&wallet_path, &settlement.hash, &settlement.transaction);
+                    (settlement, alert)
+                })
+                .collect::<Vec<_>>();
+            (wallet_path, url, secret, events)
+        };
+
+        for (settlement, alert) in events {
+            let mut res = deliver(&url, secret.as_deref(), "settlement", &settlement).await;
+            if let Some(alert) = alert {
+                let alert_res = deliver(&url, secret.as_deref(), "large_transaction", &alert).await;
+                res = res.and(alert_res);
+            }
+            let mut webhooks = webhooks.lock().await;
+            let webhook = match webhooks.webhooks.get_mut(&wallet_path) {
+                // Drop the result if the webhook was replaced or removed while we were delivering.
+                Some(webhook) if webhook.url == url => webhook,
+                _ => break,
+            };
+            match res {
+                Ok(()) => webhook.delivered += 1,
+                Err(err) => {
+                    event!(
+                        Level::WARN,
+                        "failed to deliver settlement of {} to {}: {}",
+                        settlement.hash,
+                        url,
+                        err
+                    );
+                    webhook.failed += 1;
+                    webhook.last_error = Some(err);
+                }
+            }
+            webhook.notified.insert(settlement.hash);
+            if let Err(err) = webhooks.save(&options.webhooks_path()) {
+                event!(Level::ERROR, "failed to save webhooks: {}", err);
+            }
+        }
+    }
+}
+
+/// POST the `kind` event `body` to `url`, retrying with backoff until it succeeds or runs out of
+/// attempts.
+async fn deliver(
+    url: &str,
+    secret: Option<&str>,
+    kind: &str,
+    body: &impl Serialize,
+) -> Result<(), String> {
+    let body = serde_json::to_vec(body).map_err(|err| err.to_string())?;
+    let mut backoff = INITIAL_BACKOFF;
+    let mut last_error = String::new();
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            sleep(backoff).await;
+            backoff *= 2;
+        }
+        let mut req = surf::post(url)
+            .body(body.clone())
+            .content_type("application/json")
+            .header(EVENT_HEADER, kind);
+        if let Some(secret) = secret {
+            req = req.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+        match req.await {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => last_error = format!("{} responded with {}", url, res.status()),
+            Err(err) => last_error = err.to_string(),
+        }
+    }
+    Err(last_error)
+}
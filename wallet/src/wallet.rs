@@ -7,24 +7,83 @@
 
 //! Instantiation of [seahorse::Wallet] for CAPE.
 
-use async_std::{fs, sync::Arc};
+use async_std::{
+    fs,
+    sync::{Arc, Mutex, MutexGuard},
+};
 use async_trait::async_trait;
-use cap_rust_sandbox::{deploy::EthMiddleware, ledger::*, model::*};
+use cap_rust_sandbox::{deploy::EthMiddleware, ledger::*, model::*, types::GenericInto};
+use ethers::prelude::{TxHash, U256};
+use futures::stream::StreamExt;
 use jf_cap::{
-    keys::UserAddress,
-    structs::{AssetCode, AssetDefinition, AssetPolicy, FreezeFlag, RecordOpening},
-    VerKey,
+    keys::{AuditorKeyPair, AuditorPubKey, UserAddress, UserKeyPair},
+    structs::{
+        AssetCode, AssetDefinition, AssetPolicy, AuditData, FreezeFlag, ReceiverMemo,
+        RecordCommitment, RecordOpening,
+    },
+    MerkleCommitment, MerkleFrontier, MerklePath, TransactionNote, VerKey,
 };
+use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+use reef::traits::{Block as _, Transaction as _};
 use seahorse::{
-    events::EventIndex,
-    txn_builder::{TransactionError, TransactionReceipt},
-    AssetInfo, RecordAmount, Wallet, WalletBackend, WalletError,
+    encryption::{Cipher, CipherText},
+    events::{EventIndex, EventSource, LedgerEvent},
+    hd::{KeyTree, Mnemonic},
+    txn_builder::{
+        RecordInfo, TransactionError, TransactionHistoryEntry, TransactionInfo, TransactionReceipt,
+        TransactionUID,
+    },
+    AssetInfo, RecordAmount, Wallet, WalletBackend, WalletError, WalletStorage,
 };
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter::once;
 use std::path::Path;
 use std::time::Duration;
 
 pub type CapeWalletError = WalletError<CapeLedger>;
 
+/// The key tree of a wallet created from `mnemonic`.
+///
+/// Every key the wallet generates is derived from this tree.
+pub fn wallet_key_tree(mnemonic: &Mnemonic) -> KeyTree {
+    KeyTree::from_mnemonic(mnemonic).derive_sub_tree("wallet".as_bytes())
+}
+
+/// Derive the sending key of sub-account `index` from a wallet's key tree.
+///
+/// See [CapeWalletExt::subaccount_key].
+pub fn derive_subaccount_key(key_tree: &KeyTree, index: u64) -> UserKeyPair {
+    key_tree
+        .derive_sub_tree("user".as_bytes())
+        .derive_user_key_pair(&index.to_le_bytes())
+}
+
+/// Compute the fingerprint of a wallet's key tree.
+///
+/// See [CapeWalletExt::seed_fingerprint].
+pub fn derive_seed_fingerprint(key_tree: &KeyTree) -> String {
+    // The fingerprint key is derived from its own sub-tree, so it is never used for anything else,
+    // and only a hash of its public key is revealed.
+    let pub_key = key_tree
+        .derive_sub_tree("fingerprint".as_bytes())
+        .derive_user_key_pair(&[])
+        .pub_key();
+    hex::encode(Sha3_256::digest(&bincode::serialize(&pub_key).unwrap()))
+}
+
+/// The cipher which encrypts backups of the metadata of the wallet with key tree `key_tree`.
+///
+/// See [CapeWalletExt::encrypt_backup].
+fn backup_cipher(key_tree: &KeyTree) -> Cipher<ChaChaRng> {
+    // The backup key is derived from its own sub-tree, so it is never used for anything else.
+    Cipher::new(
+        key_tree.derive_sub_tree("backup".as_bytes()),
+        ChaChaRng::from_entropy(),
+    )
+}
+
 /// Extension of the [WalletBackend] trait with CAPE-specific functionality.
 #[async_trait]
 pub trait CapeWalletBackend<'a>: WalletBackend<'a, CapeLedger> {
@@ -65,12 +124,63 @@ pub trait CapeWalletBackend<'a>: WalletBackend<'a, CapeLedger> {
     /// block is validated by the contract, but once this function succeeds the ERC20 balance will
     /// be deducted from the linked Ethereum account and the CAPE assets will be guaranteed at the
     /// next block.
+    ///
+    /// Returns the hash of the Ethereum transaction which deposited the ERC20 tokens.
     async fn wrap_erc20(
         &mut self,
         erc20_code: Erc20Code,
         src_addr: EthereumAddr,
         ro: RecordOpening,
-    ) -> Result<(), CapeWalletError>;
+    ) -> Result<TxHash, CapeWalletError>;
+
+    /// Estimate the gas used by the Ethereum transactions needed to wrap `amount` of `erc20_code`.
+    ///
+    /// This function must not submit any transactions.
+    async fn estimate_wrap_gas(
+        &self,
+        erc20_code: Erc20Code,
+        amount: U256,
+    ) -> Result<U256, CapeWalletError>;
+
+    /// Estimate the gas used by the contract to release `amount` of `erc20_code` to `dst` when a
+    /// burn is processed.
+    ///
+    /// If `dst` is `None`, the tokens are assumed to be released to this backend's Ethereum
+    /// account. The estimate is a lower bound: it does not include the gas the relayer spends
+    /// submitting the block containing the burn, which depends on the rest of the block.
+    ///
+    /// This function must not submit any transactions.
+    async fn estimate_unwrap_gas(
+        &self,
+        erc20_code: Erc20Code,
+        dst: Option<EthereumAddr>,
+        amount: U256,
+    ) -> Result<U256, CapeWalletError>;
+
+    /// The current Ethereum gas price, in wei.
+    async fn gas_price(&self) -> Result<U256, CapeWalletError>;
+
+    /// The smallest fee, in native asset units, the relayer currently accepts.
+    async fn min_fee(&self) -> Result<u64, CapeWalletError>;
+
+    /// The relayer's current backlog of transactions.
+    async fn congestion(&self) -> Result<relayer::Congestion, CapeWalletError>;
+
+    /// The amount of `erc20_code` held by the CAPE contract.
+    ///
+    /// This backs every record of the CAPE asset wrapping `erc20_code`, including the records of
+    /// deposits which have not yet been finalized.
+    async fn erc20_deposited(&self, erc20_code: Erc20Code) -> Result<U256, CapeWalletError>;
+
+    /// Submit `partner`, a transaction built by another wallet, in the same block as the
+    /// transaction with `uid`, when that transaction is submitted.
+    ///
+    /// The contract validates a block as a whole, so either both transactions are committed or
+    /// neither is.
+    fn bundle(&mut self, uid: TransactionUID<CapeLedger>, partner: relayer::SubmitBody);
+
+    /// Forget the partner bundled with the transaction with `uid`, if it has not been submitted.
+    fn unbundle(&mut self, uid: &TransactionUID<CapeLedger>);
 
     /// Get the underlying Ethereum connection.
     fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError>;
@@ -89,10 +199,157 @@ pub trait CapeWalletBackend<'a>: WalletBackend<'a, CapeLedger> {
 
     /// The latest contract, in use by the EQS.
     async fn latest_contract_address(&self) -> Result<Erc20Code, CapeWalletError>;
+
+    /// The events this backend has already replayed for queries which reconstruct information
+    /// from the event log; see [CapeWalletExt::replay].
+    fn event_log(&self) -> EventLog;
 }
 
 pub type CapeWallet<'a, Backend> = Wallet<'a, Backend, CapeLedger>;
 
+/// A prefix of the EQS event log, cached by a backend.
+///
+/// Queries which reconstruct information from the event log fetch only the events which are not
+/// yet cached, rather than replaying the whole ledger on every call.
+#[derive(Clone, Default)]
+pub struct EventLog(Arc<Mutex<Vec<LedgerEvent<CapeLedger>>>>);
+
+impl EventLog {
+    pub async fn events(&self) -> MutexGuard<'_, Vec<LedgerEvent<CapeLedger>>> {
+        self.0.lock().await
+    }
+
+    /// Forget the cached events, if the ledger they were fetched from has been reorganized.
+    pub async fn clear(&self) {
+        self.0.lock().await.clear();
+    }
+}
+
+/// The state of a wallet at some point in the past, reconstructed from the event log.
+#[derive(Clone, Debug, Default)]
+pub struct HistoricalState {
+    /// Unspent records owned by the wallet's sending keys.
+    pub records: Vec<RecordInfo>,
+    /// Hashes of the transactions committed so far, formatted as by [transaction_hash_string].
+    pub transactions: HashSet<String>,
+}
+
+/// How a record was created, reconstructed from the event log.
+#[derive(Clone, Debug)]
+pub struct RecordOrigin {
+    /// Index of the block which created the record.
+    pub block_id: u64,
+    /// Index of the creating transaction within its block.
+    pub txn_id: u64,
+    pub transaction: CapeTransition,
+    /// The owner memo of the record, if one has been posted.
+    pub memo: Option<ReceiverMemo>,
+}
+
+/// The nullifiers a wallet has cached.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullifierCounts {
+    pub total: usize,
+    /// Cached nullifiers which are known to be spent.
+    pub spent: usize,
+    /// Cached nullifiers which do not belong to a record the wallet owns, and so will not be looked
+    /// up again when the wallet builds a transaction.
+    pub prunable: usize,
+}
+
+/// A record revealed to a viewing key, reconstructed from the event log.
+#[derive(Clone, Debug)]
+pub struct ViewedRecord {
+    pub asset: AssetCode,
+    /// The amount of the record, or `None` if the asset's policy does not reveal amounts.
+    pub amount: Option<u128>,
+}
+
+/// A record of a transaction traced with a viewing key, as revealed to the key.
+#[derive(Clone, Debug)]
+pub struct TracedRecord {
+    /// The owner of the record, or `None` if the asset's policy does not reveal addresses.
+    pub owner: Option<UserAddress>,
+    /// The amount of the record, or `None` if the asset's policy does not reveal amounts.
+    pub amount: Option<u128>,
+}
+
+impl From<AuditData> for TracedRecord {
+    fn from(data: AuditData) -> Self {
+        Self {
+            owner: data.user_address,
+            amount: data.amount.map(|amount| amount.generic_into::<u128>()),
+        }
+    }
+}
+
+/// A transaction of an asset traced with a viewing key, reconstructed from the event log.
+#[derive(Clone, Debug)]
+pub struct TracedTransaction {
+    /// Index of the block which committed the transaction.
+    pub block_id: u64,
+    /// Index of the transaction within its block.
+    pub txn_id: u64,
+    pub transaction: CapeTransition,
+    /// The records of the asset the transaction spent.
+    pub inputs: Vec<TracedRecord>,
+    /// The records of the asset the transaction created.
+    pub outputs: Vec<TracedRecord>,
+}
+
+/// A record the wallet owns but does not have, because it skipped the event which posted its memo.
+#[derive(Clone, Debug)]
+pub struct SkippedRecord {
+    pub info: RecordInfo,
+    pub memo: ReceiverMemo,
+    pub comm: RecordCommitment,
+    /// The Merkle path of the record as of the event which posted its memo.
+    pub merkle_path: MerklePath,
+}
+
+/// An event the wallet skipped, reconstructed from the event log.
+#[derive(Clone, Debug)]
+pub struct SkippedEvent {
+    /// Index of the event in the query service event stream.
+    pub index: usize,
+    pub records: Vec<SkippedRecord>,
+}
+
+/// Format a transaction hash as a `HASH~...` TaggedBase64 string.
+pub fn transaction_hash_string(hash: &impl Serialize) -> Option<String> {
+    tagged_bincode_string("HASH", hash)
+}
+
+/// The fee paid by a transaction, in the native asset.
+///
+/// Wraps and faucet transactions are not CAP transactions, and do not pay a fee.
+pub fn transaction_fee(txn: &CapeTransition) -> u128 {
+    match txn {
+        CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Transfer(note))) => {
+            note.aux_info.fee.generic_into::<u128>()
+        }
+        CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Mint(note))) => {
+            note.aux_info.fee.generic_into::<u128>()
+        }
+        CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Freeze(note))) => {
+            note.aux_info.fee.generic_into::<u128>()
+        }
+        CapeTransition::Transaction(CapeModelTxn::Burn { xfr, .. }) => {
+            xfr.aux_info.fee.generic_into::<u128>()
+        }
+        CapeTransition::Wrap { .. } | CapeTransition::Faucet { .. } => 0,
+    }
+}
+
+/// Format the bincode serialization of `value` as a TaggedBase64 string with the given tag.
+pub fn tagged_bincode_string(tag: &str, value: &impl Serialize) -> Option<String> {
+    bincode::serialize(value).ok().and_then(|bytes| {
+        tagged_base64::TaggedBase64::new(tag, &bytes)
+            .ok()
+            .map(|tb| tb.to_string())
+    })
+}
+
 /// Extension methods for CAPE wallets.
 ///
 /// This trait adds to [Wallet] some methods that implement CAPE-specific functionality. It is
@@ -162,7 +419,8 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
         dst_addr: UserAddress,
         amount: impl Into<RecordAmount> + Send + 'static,
         // We may return a `WrapReceipt`, i.e., a record commitment to track wraps, once it's defined.
-    ) -> Result<(), CapeWalletError>;
+        // For now, we return the hash of the Ethereum deposit transaction.
+    ) -> Result<TxHash, CapeWalletError>;
 
     /// Construct the information required to wrap an asset, but do not submit a transaction.
     ///
@@ -177,12 +435,13 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
 
     /// Submit a wrap transaction to the CAPE contract.
     ///
-    /// `ro` should be the record opening returned by `build_wrap`.
+    /// `ro` should be the record opening returned by `build_wrap`. Returns the hash of the Ethereum
+    /// transaction which deposited the ERC20 tokens.
     async fn submit_wrap(
         &mut self,
         src_addr: EthereumAddr,
         ro: RecordOpening,
-    ) -> Result<(), CapeWalletError>;
+    ) -> Result<TxHash, CapeWalletError>;
 
     /// Burn some wrapped tokens, unlocking the corresponding ERC-20 tokens into the account
     /// `dst_addr`.
@@ -201,6 +460,42 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
         fee: impl Into<RecordAmount> + Send + 'static,
     ) -> Result<TransactionReceipt<CapeLedger>, CapeWalletError>;
 
+    /// Build a burn like [CapeWalletExt::burn] without submitting it.
+    async fn export_burn(
+        &mut self,
+        account: Option<&UserAddress>,
+        dst_addr: EthereumAddr,
+        cap_asset: &AssetCode,
+        amount: impl Into<RecordAmount> + Send + 'static,
+        fee: impl Into<RecordAmount> + Send + 'static,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError>;
+
+    /// Build a mint of `amount` of `asset` to `owner` without submitting it.
+    ///
+    /// The fee is paid by `minter`, or by any of this wallet's addresses if `minter` is not given.
+    async fn export_mint(
+        &mut self,
+        minter: Option<&UserAddress>,
+        fee: impl Into<RecordAmount> + Send + 'static,
+        asset: &AssetCode,
+        amount: impl Into<RecordAmount> + Send + 'static,
+        owner: UserAddress,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError>;
+
+    /// Build a transfer without submitting it.
+    ///
+    /// This is for deployments which submit transactions through an external service instead of
+    /// the configured relayer. The input records are put on hold as for any other transaction the
+    /// wallet builds, so they will not be spent again locally while the exported transaction is
+    /// pending.
+    async fn export_transfer(
+        &mut self,
+        account: Option<&UserAddress>,
+        asset: &AssetCode,
+        receivers: &[(UserAddress, RecordAmount)],
+        fee: impl Into<RecordAmount> + Send + 'static,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError>;
+
     /// Construct the record opening of an asset for the given address.
     async fn record_opening(
         &mut self,
@@ -216,6 +511,49 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
     /// Determine if an asset is a wrapped ERC-20 asset (as opposed to a domestic CAPE asset).
     async fn is_wrapped_asset(&self, asset: AssetCode) -> bool;
 
+    /// Estimate the Ethereum gas cost of wrapping `amount` of the ERC-20 token underlying
+    /// `cap_asset`.
+    ///
+    /// Returns `(gas, gas_price)`, where `gas_price` is the current gas price in wei. No
+    /// transactions are submitted.
+    async fn estimate_wrap_gas(
+        &self,
+        cap_asset: &AssetCode,
+        amount: U256,
+    ) -> Result<(U256, U256), CapeWalletError>;
+
+    /// Estimate the Ethereum gas cost of unwrapping `amount` of `cap_asset` to `dst`.
+    ///
+    /// Returns `(gas, gas_price)`, where `gas_price` is the current gas price in wei. No
+    /// transactions are submitted. The gas is a lower bound, as for
+    /// [CapeWalletBackend::estimate_unwrap_gas].
+    async fn estimate_unwrap_gas(
+        &self,
+        cap_asset: &AssetCode,
+        dst: Option<EthereumAddr>,
+        amount: U256,
+    ) -> Result<(U256, U256), CapeWalletError>;
+
+    /// The smallest fee, in native asset units, the relayer currently accepts.
+    async fn min_fee(&self) -> Result<u64, CapeWalletError>;
+
+    /// The relayer's current backlog of transactions.
+    async fn congestion(&self) -> Result<relayer::Congestion, CapeWalletError>;
+
+    /// The amount of the ERC-20 token wrapped by `asset` which is held by the CAPE contract.
+    ///
+    /// Returns `None` if `asset` is not a wrapped asset.
+    async fn erc20_deposited(&self, asset: AssetCode) -> Result<Option<U256>, CapeWalletError>;
+
+    /// Submit a transaction built by this wallet in the same block as `partner`, a transaction
+    /// built by another wallet, so that either both are committed or neither is.
+    async fn submit_bundle(
+        &mut self,
+        txn: CapeModelTxn,
+        info: TransactionInfo<CapeLedger>,
+        partner: relayer::SubmitBody,
+    ) -> Result<TransactionReceipt<CapeLedger>, CapeWalletError>;
+
     /// Get the underlying Ethereum connection.
     async fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError>;
 
@@ -235,11 +573,186 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
     /// guaranteed that `sync_time <= eqs_time`.
     async fn scan_status(&self) -> Result<(EventIndex, EventIndex), CapeWalletError>;
 
+    /// Re-establish the connection to the EQS after an outage.
+    ///
+    /// Waits until the EQS is reachable, then checks that the ledger it serves still extends the
+    /// history this wallet has scanned. Returns why the wallet must be rescanned from the start, if
+    /// it must, or `None` if the wallet can catch up from its sync time.
+    async fn reconnect(&self) -> Result<Option<String>, CapeWalletError>;
+
+    /// The first `to` events of the event log.
+    ///
+    /// Events are cached in the backend's [EventLog], so only the events which have not been
+    /// replayed before are fetched from the EQS. The returned log may hold more than `to` events.
+    async fn replay(&self, to: EventIndex) -> Result<EventLog, CapeWalletError>;
+
+    /// Reconstruct the state of the wallet's sending accounts as of event `time`.
+    ///
+    /// The state is computed by replaying the event log from the beginning up to (not including)
+    /// `time`, using the wallet's current sending keys. `time` should not be later than the
+    /// wallet's current sync time.
+    async fn state_at(&self, time: EventIndex) -> Result<HistoricalState, CapeWalletError>;
+
     /// The contract for which this wallet was created.
     async fn contract_address(&self) -> Result<Erc20Code, CapeWalletError>;
 
     /// The latest contract, in use by the EQS.
     async fn latest_contract_address(&self) -> Result<Erc20Code, CapeWalletError>;
+
+    /// Derive the sending key of sub-account `index` from the wallet's key tree.
+    ///
+    /// Sub-account keys are derived from the same sub-tree as the sending keys the wallet generates
+    /// itself, so derivation is deterministic: the same wallet always derives the same key for the
+    /// same index. Deriving a key does not add it to the wallet.
+    async fn subaccount_key(&self, index: u64) -> UserKeyPair;
+
+    /// Add `entry` to the wallet's transaction history.
+    ///
+    /// This is for history which the wallet cannot discover from the ledger itself, such as
+    /// transactions submitted by another wallet with the same keys. The entry is not checked
+    /// against the ledger, and it is added after the existing history.
+    async fn import_transaction(
+        &mut self,
+        entry: TransactionHistoryEntry<CapeLedger>,
+    ) -> Result<(), CapeWalletError>;
+
+    /// A fingerprint of the wallet's key tree, as a hex string.
+    ///
+    /// Wallets created from the same mnemonic have the same fingerprint, and wallets created from
+    /// different mnemonics have different fingerprints. The fingerprint is a hash of a public key
+    /// which the wallet never uses, so it reveals nothing about the mnemonic or any of the wallet's
+    /// keys.
+    async fn seed_fingerprint(&self) -> String;
+
+    /// Encrypt a backup of the wallet's metadata.
+    ///
+    /// The backup is encrypted with a key derived from the wallet's key tree, so it can only be
+    /// decrypted, with [decrypt_backup](Self::decrypt_backup), by a wallet created from the same
+    /// mnemonic.
+    async fn encrypt_backup(&self, plaintext: &[u8]) -> Result<CipherText, CapeWalletError>;
+
+    /// Decrypt a backup encrypted by [encrypt_backup](Self::encrypt_backup).
+    ///
+    /// Fails if the backup was encrypted by a wallet with a different mnemonic, or has been
+    /// tampered with.
+    async fn decrypt_backup(&self, ciphertext: &CipherText) -> Result<Vec<u8>, CapeWalletError>;
+
+    /// The record Merkle tree as of the wallet's current sync time.
+    ///
+    /// Returns the commitment to the tree the wallet has scanned, its frontier, and the sync time,
+    /// all read atomically, so that they are consistent with each other and with the Merkle paths
+    /// the wallet uses to build transactions.
+    async fn record_merkle_frontier(&self) -> (MerkleCommitment, MerkleFrontier, EventIndex);
+
+    /// The Merkle paths of the records with the given uids.
+    ///
+    /// Returns the commitment to the tree the wallet has scanned, read atomically with the paths,
+    /// so that every path is valid against it. Records the wallet does not have a path for are left
+    /// out.
+    async fn record_merkle_paths(
+        &self,
+        uids: &[u64],
+    ) -> (MerkleCommitment, HashMap<u64, MerklePath>);
+
+    /// Count the nullifiers in the wallet's local nullifier cache.
+    async fn nullifier_counts(&self) -> NullifierCounts;
+
+    /// Find the transaction which created record `uid`.
+    ///
+    /// The event log is replayed up to the wallet's current sync time. Returns `None` if no
+    /// transaction in that range created a record with this uid.
+    async fn record_origin(&self, uid: u64) -> Result<Option<RecordOrigin>, CapeWalletError>;
+
+    /// Find the event which committed the transaction with hash `hash`, formatted as by
+    /// [transaction_hash_string].
+    ///
+    /// The event log is replayed up to the wallet's current sync time. Returns the index of the
+    /// event, or `None` if no event in that range committed the transaction.
+    async fn transaction_event(&self, hash: &str) -> Result<Option<usize>, CapeWalletError>;
+
+    /// Find the blocks which committed each transaction.
+    ///
+    /// The event log is replayed up to the wallet's current sync time. The result maps the hash of
+    /// each transaction committed in that range, formatted by [transaction_hash_string], to the
+    /// index of its block.
+    async fn transaction_blocks(&self) -> Result<HashMap<String, u64>, CapeWalletError>;
+
+    /// Find the fee paid by each transaction.
+    ///
+    /// The event log is replayed up to the wallet's current sync time. The result maps the hash of
+    /// each transaction committed in that range, formatted by [transaction_hash_string], to the fee
+    /// it paid, as by [transaction_fee].
+    async fn transaction_fees(&self) -> Result<HashMap<String, u128>, CapeWalletError>;
+
+    /// Find the blocks which created the records with uids in `uids`.
+    ///
+    /// The event log is replayed up to the wallet's current sync time. Returns the number of blocks
+    /// committed in that range, and the index of the block which created each record in `uids`
+    /// which was created in that range.
+    async fn record_blocks(
+        &self,
+        uids: &HashSet<u64>,
+    ) -> Result<(u64, HashMap<u64, u64>), CapeWalletError>;
+
+    /// Count the blocks committed by the events from `from` up to, but not including, `to`.
+    async fn blocks_committed(
+        &self,
+        from: EventIndex,
+        to: EventIndex,
+    ) -> Result<u64, CapeWalletError>;
+
+    /// Find the records `key` can view.
+    ///
+    /// The event log is replayed up to the wallet's current sync time, and the viewing memo of each
+    /// committed transaction is opened with `key`. Only assets known to the wallet are considered,
+    /// since a viewing memo cannot be opened without the definition of its asset. Nothing is
+    /// added to the wallet.
+    async fn viewed_records(
+        &self,
+        key: &AuditorKeyPair,
+    ) -> Result<Vec<ViewedRecord>, CapeWalletError>;
+
+    /// Find the records each of `keys` can view, as for [viewed_records](Self::viewed_records).
+    ///
+    /// The event log is replayed once for all of the keys. The result maps the public key of each
+    /// of `keys` to the records it can view.
+    async fn viewed_records_by_key(
+        &self,
+        keys: &[AuditorKeyPair],
+    ) -> Result<HashMap<AuditorPubKey, Vec<ViewedRecord>>, CapeWalletError>;
+
+    /// Trace the transactions of `asset` with `key`, the viewing key named by its policy.
+    ///
+    /// The event log is replayed up to the wallet's current sync time, and the viewing memo of each
+    /// committed transaction is opened with `key`. Wraps of `asset` are traced too, since their
+    /// records are public. Transactions are returned in the order they were committed.
+    async fn trace_asset(
+        &self,
+        asset: &AssetDefinition,
+        key: &AuditorKeyPair,
+    ) -> Result<Vec<TracedTransaction>, CapeWalletError>;
+
+    /// Find the reasons transactions were rejected by the network.
+    ///
+    /// The event log is replayed up to the wallet's current sync time. The result maps the hash of
+    /// each rejected transaction, formatted by [transaction_hash_string], to the validation error
+    /// which caused it to be rejected.
+    async fn rejections(&self) -> Result<HashMap<String, String>, CapeWalletError>;
+
+    /// Find the events the wallet skipped.
+    ///
+    /// The event log is replayed up to the wallet's current sync time, and each memo is opened with
+    /// the wallet's sending keys. An event is skipped if it posted the memo of an unspent record
+    /// owned by the wallet which the wallet does not have, for instance because processing the
+    /// event failed. Events are returned in order of index.
+    async fn skipped_events(&self) -> Result<Vec<SkippedEvent>, CapeWalletError>;
+
+    /// Reprocess the events the wallet skipped, adding their records to the wallet.
+    ///
+    /// Returns the events which were reprocessed, as found by
+    /// [skipped_events](CapeWalletExt::skipped_events). Fails if a record cannot be added, which
+    /// happens if its Merkle path is no longer valid against the wallet's record Merkle tree.
+    async fn replay_skipped_events(&mut self) -> Result<Vec<SkippedEvent>, CapeWalletError>;
 }
 
 #[async_trait]
@@ -333,7 +846,7 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
         &mut self,
         src_addr: EthereumAddr,
         ro: RecordOpening,
-    ) -> Result<(), CapeWalletError> {
+    ) -> Result<TxHash, CapeWalletError> {
         let mut state = self.lock().await;
 
         let cap_asset = ro.asset_def.clone();
@@ -362,7 +875,7 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
         cap_asset: AssetDefinition,
         dst_addr: UserAddress,
         amount: impl Into<RecordAmount> + Send + 'static,
-    ) -> Result<(), CapeWalletError> {
+    ) -> Result<TxHash, CapeWalletError> {
         let ro = self.build_wrap(cap_asset, dst_addr, amount.into()).await?;
 
         self.submit_wrap(src_addr, ro).await
@@ -376,6 +889,20 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
         amount: impl Into<RecordAmount> + Send + 'static,
         fee: impl Into<RecordAmount> + Send + 'static,
     ) -> Result<TransactionReceipt<CapeLedger>, CapeWalletError> {
+        let (txn, info) = self
+            .export_burn(account, dst_addr, cap_asset, amount, fee)
+            .await?;
+        self.submit(CapeTransition::Transaction(txn), info).await
+    }
+
+    async fn export_burn(
+        &mut self,
+        account: Option<&UserAddress>,
+        dst_addr: EthereumAddr,
+        cap_asset: &AssetCode,
+        amount: impl Into<RecordAmount> + Send + 'static,
+        fee: impl Into<RecordAmount> + Send + 'static,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError> {
         let amount = amount.into();
         let fee = fee.into();
 
@@ -420,11 +947,48 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
             history.kind = CapeTransactionKind::Burn;
         }
 
-        let txn = CapeTransition::Transaction(CapeModelTxn::Burn {
+        let txn = CapeModelTxn::Burn {
             xfr: Box::new(note),
             ro: Box::new(info.outputs[1].clone()),
-        });
-        self.submit(txn, info).await
+        };
+        Ok((txn, info))
+    }
+
+    async fn export_mint(
+        &mut self,
+        minter: Option<&UserAddress>,
+        fee: impl Into<RecordAmount> + Send + 'static,
+        asset: &AssetCode,
+        amount: impl Into<RecordAmount> + Send + 'static,
+        owner: UserAddress,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError> {
+        let (note, info) = self
+            .build_mint(minter, fee.into(), asset, amount.into(), owner)
+            .await?;
+        Ok((
+            CapeModelTxn::CAP(TransactionNote::Mint(Box::new(note))),
+            info,
+        ))
+    }
+
+    async fn export_transfer(
+        &mut self,
+        account: Option<&UserAddress>,
+        asset: &AssetCode,
+        receivers: &[(UserAddress, RecordAmount)],
+        fee: impl Into<RecordAmount> + Send + 'static,
+    ) -> Result<(CapeModelTxn, TransactionInfo<CapeLedger>), CapeWalletError> {
+        let receivers = receivers
+            .iter()
+            .map(|(address, amount)| (address.clone(), *amount, false))
+            .collect::<Vec<_>>();
+        let (note, info) = self
+            .build_transfer(account, asset, &receivers, fee.into(), vec![], None)
+            .await?;
+        Ok((
+            CapeModelTxn::CAP(TransactionNote::Transfer(Box::new(note))),
+            info,
+        ))
     }
 
     async fn record_opening(
@@ -460,6 +1024,80 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
         self.wrapped_erc20(asset).await.is_some()
     }
 
+    async fn estimate_wrap_gas(
+        &self,
+        cap_asset: &AssetCode,
+        amount: U256,
+    ) -> Result<(U256, U256), CapeWalletError> {
+        let state = self.lock().await;
+        let erc20_code = state
+            .backend()
+            .get_wrapped_erc20_code(cap_asset)
+            .await?
+            .ok_or(WalletError::<CapeLedger>::UndefinedAsset { asset: *cap_asset })?;
+        let gas = state
+            .backend()
+            .estimate_wrap_gas(erc20_code, amount)
+            .await?;
+        let gas_price = state.backend().gas_price().await?;
+        Ok((gas, gas_price))
+    }
+
+    async fn estimate_unwrap_gas(
+        &self,
+        cap_asset: &AssetCode,
+        dst: Option<EthereumAddr>,
+        amount: U256,
+    ) -> Result<(U256, U256), CapeWalletError> {
+        let state = self.lock().await;
+        let erc20_code = state
+            .backend()
+            .get_wrapped_erc20_code(cap_asset)
+            .await?
+            .ok_or(WalletError::<CapeLedger>::UndefinedAsset { asset: *cap_asset })?;
+        let gas = state
+            .backend()
+            .estimate_unwrap_gas(erc20_code, dst, amount)
+            .await?;
+        let gas_price = state.backend().gas_price().await?;
+        Ok((gas, gas_price))
+    }
+
+    async fn min_fee(&self) -> Result<u64, CapeWalletError> {
+        self.lock().await.backend().min_fee().await
+    }
+
+    async fn congestion(&self) -> Result<relayer::Congestion, CapeWalletError> {
+        self.lock().await.backend().congestion().await
+    }
+
+    async fn erc20_deposited(&self, asset: AssetCode) -> Result<Option<U256>, CapeWalletError> {
+        let erc20_code = match self.wrapped_erc20(asset).await {
+            Some(erc20_code) => erc20_code,
+            None => return Ok(None),
+        };
+        let state = self.lock().await;
+        Ok(Some(state.backend().erc20_deposited(erc20_code).await?))
+    }
+
+    async fn submit_bundle(
+        &mut self,
+        txn: CapeModelTxn,
+        info: TransactionInfo<CapeLedger>,
+        partner: relayer::SubmitBody,
+    ) -> Result<TransactionReceipt<CapeLedger>, CapeWalletError> {
+        let txn = CapeTransition::Transaction(txn);
+        let uid = TransactionUID(txn.hash());
+        self.lock().await.backend_mut().bundle(uid.clone(), partner);
+        let receipt = self.submit(txn, info).await;
+        if receipt.is_err() {
+            // The wallet may reject the transaction before the backend takes its partner, which
+            // would otherwise stay bundled with a transaction that was never submitted.
+            self.lock().await.backend_mut().unbundle(&uid);
+        }
+        receipt
+    }
+
     async fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError> {
         self.lock().await.backend().eth_client()
     }
@@ -490,6 +1128,125 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
         Ok((sync_time, eqs_time))
     }
 
+    async fn reconnect(&self) -> Result<Option<String>, CapeWalletError> {
+        self.lock().await.backend().wait_for_eqs().await?;
+        if self.contract_address().await? != self.latest_contract_address().await? {
+            let log = self.lock().await.backend().event_log();
+            log.clear().await;
+            return Ok(Some(String::from(
+                "the EQS is serving a different CAPE contract than the one this wallet scanned",
+            )));
+        }
+        let eqs_time = self.lock().await.backend().eqs_time().await?;
+        let sync_time = self.now().await;
+        let (eqs_time, sync_time) = (
+            eqs_time.index(EventSource::QueryService),
+            sync_time.index(EventSource::QueryService),
+        );
+        if eqs_time < sync_time {
+            // The events this wallet has scanned beyond the ledger's end were reorganized away, and
+            // the wallet's state may depend on them, as may the events we have cached.
+            let log = self.lock().await.backend().event_log();
+            log.clear().await;
+            return Ok(Some(format!(
+                "the ledger has {} events, but this wallet has scanned {}",
+                eqs_time, sync_time
+            )));
+        }
+        Ok(None)
+    }
+
+    async fn replay(&self, to: EventIndex) -> Result<EventLog, CapeWalletError> {
+        let log = self.lock().await.backend().event_log();
+        {
+            // Hold the log while fetching, so that concurrent queries do not fetch the same events
+            // twice.
+            let mut events = log.events().await;
+            let cached = events.len();
+            let num_events = to.index(EventSource::QueryService);
+            if num_events > cached {
+                let mut new_events = self
+                    .lock()
+                    .await
+                    .backend()
+                    .subscribe(
+                        EventIndex::from_source(EventSource::QueryService, cached),
+                        Some(to),
+                    )
+                    .await
+                    .take(num_events - cached);
+                while let Some((event, _)) = new_events.next().await {
+                    events.push(event);
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    async fn state_at(&self, time: EventIndex) -> Result<HistoricalState, CapeWalletError> {
+        let mut key_pairs = vec![];
+        for pub_key in self.pub_keys().await {
+            key_pairs.push(self.get_user_private_key(&pub_key.address()).await?);
+        }
+
+        let num_events = time.index(EventSource::QueryService);
+        let log = self.replay(time).await?;
+        let events = log.events().await;
+
+        let mut records = HashMap::new();
+        let mut nullifiers = HashMap::new();
+        let mut transactions = HashSet::new();
+        let mut num_records = 0;
+        for event in events.iter().take(num_events) {
+            match event {
+                LedgerEvent::Commit { block, .. } => {
+                    for txn in block.txns() {
+                        for nullifier in txn.input_nullifiers() {
+                            if let Some(uid) = nullifiers.remove(&nullifier) {
+                                records.remove(&uid);
+                            }
+                        }
+                        // Wrapped records are created in the clear, so we learn about them from the
+                        // block rather than from memos.
+                        if let CapeTransition::Wrap { ro, .. } = &txn {
+                            if let Some(key_pair) =
+                                key_pairs.iter().find(|key| key.pub_key() == ro.pub_key)
+                            {
+                                let info = RecordInfo::new((**ro).clone(), num_records, key_pair);
+                                nullifiers.insert(info.nullifier, info.uid);
+                                records.insert(info.uid, info);
+                            }
+                        }
+                        num_records += txn.output_commitments().len() as u64;
+                        if let Some(hash) = transaction_hash_string(&txn.hash()) {
+                            transactions.insert(hash);
+                        }
+                    }
+                }
+                LedgerEvent::Memos { outputs, .. } => {
+                    for (memo, comm, uid, _) in outputs {
+                        for key_pair in &key_pairs {
+                            if let Ok(ro) = memo.decrypt(key_pair, &comm, &[]) {
+                                if RecordCommitment::from(&ro) == comm {
+                                    let info = RecordInfo::new(ro, uid, key_pair);
+                                    nullifiers.insert(info.nullifier, info.uid);
+                                    records.insert(info.uid, info);
+                                }
+                                break;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(HistoricalState {
+            records: records.into_values().collect(),
+            transactions,
+        })
+    }
+
     async fn contract_address(&self) -> Result<Erc20Code, CapeWalletError> {
         self.lock().await.backend().contract_address().await
     }
@@ -497,4 +1254,464 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
     async fn latest_contract_address(&self) -> Result<Erc20Code, CapeWalletError> {
         self.lock().await.backend().latest_contract_address().await
     }
+
+    async fn subaccount_key(&self, index: u64) -> UserKeyPair {
+        derive_subaccount_key(&self.lock().await.backend().key_stream(), index)
+    }
+
+    async fn import_transaction(
+        &mut self,
+        entry: TransactionHistoryEntry<CapeLedger>,
+    ) -> Result<(), CapeWalletError> {
+        let mut state = self.lock().await;
+        let mut storage = state.backend_mut().storage().await;
+        storage.store_transaction(entry).await?;
+        storage.commit().await;
+        Ok(())
+    }
+
+    async fn seed_fingerprint(&self) -> String {
+        derive_seed_fingerprint(&self.lock().await.backend().key_stream())
+    }
+
+    async fn encrypt_backup(&self, plaintext: &[u8]) -> Result<CipherText, CapeWalletError> {
+        backup_cipher(&self.lock().await.backend().key_stream())
+            .encrypt(plaintext)
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("failed to encrypt backup: {}", err),
+            })
+    }
+
+    async fn decrypt_backup(&self, ciphertext: &CipherText) -> Result<Vec<u8>, CapeWalletError> {
+        backup_cipher(&self.lock().await.backend().key_stream())
+            .decrypt(ciphertext)
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("failed to decrypt backup: {}", err),
+            })
+    }
+
+    async fn record_merkle_frontier(&self) -> (MerkleCommitment, MerkleFrontier, EventIndex) {
+        let state = self.lock().await;
+        let txn_state = &state.state().txn_state;
+        (
+            txn_state.record_mt.commitment(),
+            txn_state.record_mt.frontier(),
+            txn_state.now,
+        )
+    }
+
+    async fn record_merkle_paths(
+        &self,
+        uids: &[u64],
+    ) -> (MerkleCommitment, HashMap<u64, MerklePath>) {
+        let state = self.lock().await;
+        let record_mt = &state.state().txn_state.record_mt;
+        let paths = uids
+            .iter()
+            .filter_map(|uid| Some((*uid, record_mt.get_leaf(*uid).expect_ok().ok()?.1.path)))
+            .collect();
+        (record_mt.commitment(), paths)
+    }
+
+    async fn nullifier_counts(&self) -> NullifierCounts {
+        let owned = self
+            .records()
+            .await
+            .map(|record| record.nullifier)
+            .collect::<HashSet<_>>();
+        let state = self.lock().await;
+        let nullifiers = &state.state().txn_state.nullifiers;
+        NullifierCounts {
+            total: nullifiers.len(),
+            spent: nullifiers.iter().filter(|(_, spent)| *spent).count(),
+            prunable: nullifiers
+                .iter()
+                .filter(|(nullifier, _)| !owned.contains(nullifier))
+                .count(),
+        }
+    }
+
+    async fn record_origin(&self, uid: u64) -> Result<Option<RecordOrigin>, CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut origin: Option<RecordOrigin> = None;
+        let mut num_records = 0;
+        for event in events.iter().take(num_events) {
+            match event {
+                LedgerEvent::Commit {
+                    block, block_id, ..
+                } if origin.is_none() => {
+                    for (txn_id, txn) in block.txns().into_iter().enumerate() {
+                        let num_outputs = txn.output_commitments().len() as u64;
+                        if (num_records..num_records + num_outputs).contains(&uid) {
+                            origin = Some(RecordOrigin {
+                                block_id: *block_id,
+                                txn_id: txn_id as u64,
+                                transaction: txn,
+                                memo: None,
+                            });
+                            break;
+                        }
+                        num_records += num_outputs;
+                    }
+                }
+                LedgerEvent::Memos { outputs, .. } => {
+                    if let Some(origin) = &mut origin {
+                        if let Some((memo, ..)) =
+                            outputs.iter().find(|(_, _, memo_uid, _)| *memo_uid == uid)
+                        {
+                            origin.memo = Some(memo.clone());
+                            // Memos are posted after the transaction is committed, so there is
+                            // nothing more to learn about this record.
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(origin)
+    }
+
+    async fn transaction_event(&self, hash: &str) -> Result<Option<usize>, CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        for (index, event) in events.iter().take(num_events).enumerate() {
+            if let LedgerEvent::Commit { block, .. } = event {
+                if block
+                    .txns()
+                    .iter()
+                    .any(|txn| transaction_hash_string(&txn.hash()).as_deref() == Some(hash))
+                {
+                    return Ok(Some(index));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn transaction_blocks(&self) -> Result<HashMap<String, u64>, CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut blocks = HashMap::new();
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Commit {
+                block, block_id, ..
+            } = event
+            {
+                blocks.extend(
+                    block
+                        .txns()
+                        .iter()
+                        .filter_map(|txn| transaction_hash_string(&txn.hash()))
+                        .map(|hash| (hash, *block_id)),
+                );
+            }
+        }
+        Ok(blocks)
+    }
+
+    async fn transaction_fees(&self) -> Result<HashMap<String, u128>, CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut fees = HashMap::new();
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Commit { block, .. } = event {
+                fees.extend(block.txns().iter().filter_map(|txn| {
+                    transaction_hash_string(&txn.hash()).map(|hash| (hash, transaction_fee(txn)))
+                }));
+            }
+        }
+        Ok(fees)
+    }
+
+    async fn record_blocks(
+        &self,
+        uids: &HashSet<u64>,
+    ) -> Result<(u64, HashMap<u64, u64>), CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut num_blocks = 0;
+        let mut num_records = 0;
+        let mut blocks = HashMap::new();
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Commit {
+                block, block_id, ..
+            } = event
+            {
+                let num_outputs = block
+                    .txns()
+                    .iter()
+                    .map(|txn| txn.output_commitments().len() as u64)
+                    .sum::<u64>();
+                blocks.extend(
+                    (num_records..num_records + num_outputs)
+                        .filter(|uid| uids.contains(uid))
+                        .map(|uid| (uid, *block_id)),
+                );
+                num_records += num_outputs;
+                num_blocks = block_id + 1;
+            }
+        }
+        Ok((num_blocks, blocks))
+    }
+
+    async fn blocks_committed(
+        &self,
+        from: EventIndex,
+        to: EventIndex,
+    ) -> Result<u64, CapeWalletError> {
+        let log = self.replay(to).await?;
+        let num_blocks = log
+            .events()
+            .await
+            .iter()
+            .take(to.index(EventSource::QueryService))
+            .skip(from.index(EventSource::QueryService))
+            .filter(|event| matches!(event, LedgerEvent::Commit { .. }))
+            .count();
+        Ok(num_blocks as u64)
+    }
+
+    async fn viewed_records(
+        &self,
+        key: &AuditorKeyPair,
+    ) -> Result<Vec<ViewedRecord>, CapeWalletError> {
+        Ok(self
+            .viewed_records_by_key(std::slice::from_ref(key))
+            .await?
+            .remove(&key.pub_key())
+            .unwrap_or_default())
+    }
+
+    async fn viewed_records_by_key(
+        &self,
+        keys: &[AuditorKeyPair],
+    ) -> Result<HashMap<AuditorPubKey, Vec<ViewedRecord>>, CapeWalletError> {
+        let keys = keys
+            .iter()
+            .map(|key| (key.pub_key(), key.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut records = keys
+            .keys()
+            .map(|key| (key.clone(), vec![]))
+            .collect::<HashMap<_, _>>();
+        let assets = self
+            .assets()
+            .await
+            .into_iter()
+            .filter(|asset| {
+                let policy = asset.definition.policy_ref();
+                policy.is_auditor_pub_key_set() && keys.contains_key(policy.auditor_pub_key())
+            })
+            .map(|asset| (asset.definition.code, asset.definition))
+            .collect::<HashMap<_, _>>();
+        if assets.is_empty() {
+            return Ok(records);
+        }
+
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Commit { block, .. } = event {
+                for txn in block.txns() {
+                    // Transactions without a viewing memo for one of `keys`, including wraps and
+                    // faucet transactions, whose records are public anyway, fail to open.
+                    if let Ok(opening) = txn.open_audit_memo(&assets, &keys) {
+                        let key = opening.asset.policy_ref().auditor_pub_key();
+                        if let Some(records) = records.get_mut(key) {
+                            records.extend(opening.outputs.into_iter().map(|output| {
+                                ViewedRecord {
+                                    asset: opening.asset.code,
+                                    amount: output
+                                        .amount
+                                        .map(|amount| amount.generic_into::<u128>()),
+                                }
+                            }));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    async fn trace_asset(
+        &self,
+        asset: &AssetDefinition,
+        key: &AuditorKeyPair,
+    ) -> Result<Vec<TracedTransaction>, CapeWalletError> {
+        let assets = once((asset.code, asset.clone())).collect::<HashMap<_, _>>();
+        let keys = once((key.pub_key(), key.clone())).collect::<HashMap<_, _>>();
+
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut traced = vec![];
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Commit {
+                block, block_id, ..
+            } = event
+            {
+                for (txn_id, txn) in block.txns().into_iter().enumerate() {
+                    let (inputs, outputs) = match &txn {
+                        CapeTransition::Wrap { ro, .. } if ro.asset_def.code == asset.code => (
+                            vec![],
+                            vec![TracedRecord {
+                                owner: Some(ro.pub_key.address()),
+                                amount: Some(ro.amount.generic_into::<u128>()),
+                            }],
+                        ),
+                        // Transactions of other assets fail to open.
+                        _ => match txn.open_audit_memo(&assets, &keys) {
+                            Ok(opening) => (
+                                opening.inputs.into_iter().map(TracedRecord::from).collect(),
+                                opening
+                                    .outputs
+                                    .into_iter()
+                                    .map(TracedRecord::from)
+                                    .collect(),
+                            ),
+                            Err(_) => continue,
+                        },
+                    };
+                    traced.push(TracedTransaction {
+                        block_id: *block_id,
+                        txn_id: txn_id as u64,
+                        transaction: txn,
+                        inputs,
+                        outputs,
+                    });
+                }
+            }
+        }
+        Ok(traced)
+    }
+
+    async fn rejections(&self) -> Result<HashMap<String, String>, CapeWalletError> {
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        let mut rejections = HashMap::new();
+        for event in events.iter().take(num_events) {
+            if let LedgerEvent::Reject { block, error } = event {
+                for txn in block.txns() {
+                    if let Some(hash) = transaction_hash_string(&txn.hash()) {
+                        rejections.insert(hash, error.to_string());
+                    }
+                }
+            }
+        }
+        Ok(rejections)
+    }
+
+    async fn skipped_events(&self) -> Result<Vec<SkippedEvent>, CapeWalletError> {
+        let mut key_pairs = vec![];
+        for pub_key in self.pub_keys().await {
+            key_pairs.push(self.get_user_private_key(&pub_key.address()).await?);
+        }
+
+        let now = self.now().await;
+        let num_events = now.index(EventSource::QueryService);
+        let log = self.replay(now).await?;
+        let events = log.events().await;
+
+        // Owned records by uid, with the index of the event which posted the memo of each.
+        let mut records = HashMap::new();
+        let mut nullifiers = HashMap::new();
+        for (index, event) in events.iter().take(num_events).enumerate() {
+            match event {
+                LedgerEvent::Commit { block, .. } => {
+                    for txn in block.txns() {
+                        for nullifier in txn.input_nullifiers() {
+                            if let Some(uid) = nullifiers.remove(&nullifier) {
+                                records.remove(&uid);
+                            }
+                        }
+                    }
+                }
+                LedgerEvent::Memos { outputs, .. } => {
+                    for (memo, comm, uid, merkle_path) in outputs {
+                        for key_pair in &key_pairs {
+                            if let Ok(ro) = memo.decrypt(key_pair, comm, &[]) {
+                                if RecordCommitment::from(&ro) == *comm {
+                                    let info = RecordInfo::new(ro, *uid, key_pair);
+                                    nullifiers.insert(info.nullifier, *uid);
+                                    records.insert(
+                                        *uid,
+                                        (
+                                            index,
+                                            SkippedRecord {
+                                                info,
+                                                memo: memo.clone(),
+                                                comm: *comm,
+                                                merkle_path: merkle_path.clone(),
+                                            },
+                                        ),
+                                    );
+                                }
+                                break;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        drop(events);
+
+        for record in self.records().await {
+            records.remove(&record.uid);
+        }
+        let mut skipped = BTreeMap::<usize, Vec<SkippedRecord>>::new();
+        for (index, record) in records.into_values() {
+            skipped.entry(index).or_default().push(record);
+        }
+        Ok(skipped
+            .into_iter()
+            .map(|(index, mut records)| {
+                records.sort_by_key(|record| record.info.uid);
+                SkippedEvent { index, records }
+            })
+            .collect())
+    }
+
+    async fn replay_skipped_events(&mut self) -> Result<Vec<SkippedEvent>, CapeWalletError> {
+        let skipped = self.skipped_events().await?;
+        for event in &skipped {
+            for record in &event.records {
+                self.import_memo(
+                    record.memo.clone(),
+                    record.comm,
+                    record.info.uid,
+                    record.merkle_path.clone(),
+                )
+                .await?;
+            }
+        }
+        Ok(skipped)
+    }
 }